@@ -0,0 +1,38 @@
+use std::env;
+use std::process::Command;
+
+fn main() {
+    emit_build_info();
+}
+
+/// Pass the git commit and the build time to the crate as `GIT_SHA` and
+/// `BUILD_TIMESTAMP` (Unix seconds, `SOURCE_DATE_EPOCH` if set)
+fn emit_build_info() {
+    let git = |args: &[&str]| {
+        let output = Command::new("git").args(args).output().ok()?;
+        let stdout = String::from_utf8(output.stdout).ok()?;
+        output.status.success().then(|| stdout.trim().to_string())
+    };
+    if let Some(sha) = git(&["rev-parse", "--short=12", "HEAD"]) {
+        println!("cargo:rustc-env=GIT_SHA={}", sha);
+        // Rebuild when another commit is checked out or the branch moves
+        let branch = git(&["symbolic-ref", "-q", "HEAD"]);
+        for name in std::iter::once("HEAD").chain(branch.as_deref()) {
+            if let Some(path) = git(&["rev-parse", "--git-path", name]) {
+                println!("cargo:rerun-if-changed={}", path);
+            }
+        }
+    }
+
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    let timestamp = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+        });
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", timestamp);
+}
@@ -0,0 +1,186 @@
+//! Content-addressed cache of image blobs
+//!
+//! The host pulls images once and sends the guest only the layers it does not
+//! already have, so an image is never downloaded twice. Blobs are kept on the
+//! guest's disk under their digest, which makes the cache shared by every
+//! image that uses a layer and lets the host ask what is missing by digest
+//! alone.
+
+use libcrun_shim_proto::BlobChunk;
+use sha2::{Digest, Sha256};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Where blobs are kept in the guest
+pub const BLOBS_DIR: &str = "/var/lib/libcrun-shim/blobs";
+
+/// Blobs stored as `<root>/sha256/<hex>`
+pub struct BlobStore {
+    root: PathBuf,
+}
+
+/// Whether a digest names a blob the store can hold
+pub fn is_valid_digest(digest: &str) -> bool {
+    sha256_hex(digest).is_some()
+}
+
+/// Hex part of a `sha256:<hex>` digest, if it is well formed
+fn sha256_hex(digest: &str) -> Option<&str> {
+    let hex = digest.strip_prefix("sha256:")?;
+    (hex.len() == 64 && hex.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))).then_some(hex)
+}
+
+impl BlobStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path(&self, hex: &str) -> PathBuf {
+        self.root.join("sha256").join(hex)
+    }
+
+    fn partial_path(&self, hex: &str) -> PathBuf {
+        self.root.join("sha256").join(format!("{}.partial", hex))
+    }
+
+    /// Path of a cached blob
+    pub fn get(&self, digest: &str) -> Option<PathBuf> {
+        let path = self.path(sha256_hex(digest)?);
+        path.is_file().then_some(path)
+    }
+
+    /// The digests that are not cached, in the given order
+    pub fn missing(&self, digests: &[String]) -> Vec<String> {
+        digests
+            .iter()
+            .filter(|d| self.get(d).is_none())
+            .cloned()
+            .collect()
+    }
+
+    /// Write a chunk, verifying and caching the blob with the last one
+    pub fn put(&self, chunk: &BlobChunk) -> Result<(), String> {
+        let hex = sha256_hex(&chunk.digest)
+            .ok_or_else(|| format!("Invalid blob digest '{}'", chunk.digest))?;
+        let path = self.path(hex);
+        // A retried last chunk finds the blob already in place
+        if path.is_file() {
+            return Ok(());
+        }
+        let partial = self.partial_path(hex);
+        std::fs::create_dir_all(path.parent().unwrap_or(Path::new("/")))
+            .map_err(|e| format!("Failed to create blob directory: {}", e))?;
+
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(chunk.offset == 0)
+            .open(&partial)
+            .map_err(|e| format!("Failed to open {}: {}", partial.display(), e))?;
+        let len = file
+            .metadata()
+            .map_err(|e| format!("Failed to stat {}: {}", partial.display(), e))?
+            .len();
+        if chunk.offset > len {
+            return Err(format!(
+                "Chunk of {} at offset {} leaves a gap, {} bytes received so far",
+                chunk.digest, chunk.offset, len
+            ));
+        }
+        file.set_len(chunk.offset)
+            .and_then(|_| file.seek(SeekFrom::Start(chunk.offset)))
+            .and_then(|_| file.write_all(&chunk.data))
+            .map_err(|e| format!("Failed to write {}: {}", partial.display(), e))?;
+        if !chunk.last {
+            return Ok(());
+        }
+
+        file.sync_all()
+            .map_err(|e| format!("Failed to write {}: {}", partial.display(), e))?;
+        drop(file);
+        let actual = std::fs::File::open(&partial)
+            .and_then(|mut f| {
+                let mut hasher = Sha256::new();
+                std::io::copy(&mut f, &mut hasher)?;
+                Ok(format!("{:x}", hasher.finalize()))
+            })
+            .map_err(|e| format!("Failed to read {}: {}", partial.display(), e))?;
+        if actual != hex {
+            let _ = std::fs::remove_file(&partial);
+            return Err(format!(
+                "Digest mismatch: expected {}, got sha256:{}",
+                chunk.digest, actual
+            ));
+        }
+        std::fs::rename(&partial, &path)
+            .map_err(|e| format!("Failed to store {}: {}", chunk.digest, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digest(data: &[u8]) -> String {
+        format!("sha256:{:x}", Sha256::digest(data))
+    }
+
+    fn chunk(digest: &str, offset: u64, data: &[u8], last: bool) -> BlobChunk {
+        BlobChunk {
+            digest: digest.to_string(),
+            offset,
+            data: data.to_vec(),
+            last,
+        }
+    }
+
+    fn store(name: &str) -> BlobStore {
+        let root =
+            std::env::temp_dir().join(format!("agent-blobs-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        BlobStore::new(root)
+    }
+
+    #[test]
+    fn test_put_in_chunks() {
+        let store = store("chunks");
+        let data = b"layer contents";
+        let d = digest(data);
+        assert_eq!(
+            store.missing(std::slice::from_ref(&d)),
+            std::slice::from_ref(&d)
+        );
+
+        store.put(&chunk(&d, 0, &data[..5], false)).unwrap();
+        // A retried chunk replaces what it covered
+        store.put(&chunk(&d, 0, &data[..5], false)).unwrap();
+        assert!(store.get(&d).is_none());
+        store.put(&chunk(&d, 5, &data[5..], true)).unwrap();
+
+        assert_eq!(std::fs::read(store.get(&d).unwrap()).unwrap(), data);
+        assert!(store.missing(std::slice::from_ref(&d)).is_empty());
+        // The last chunk again, after the blob was stored
+        store.put(&chunk(&d, 5, &data[5..], true)).unwrap();
+        let _ = std::fs::remove_dir_all(&store.root);
+    }
+
+    #[test]
+    fn test_put_rejects_bad_blobs() {
+        let store = store("bad");
+        let d = digest(b"expected");
+        let err = store
+            .put(&chunk(&d, 0, b"something else", true))
+            .unwrap_err();
+        assert!(err.contains("Digest mismatch"), "{}", err);
+        assert!(store.get(&d).is_none());
+
+        store.put(&chunk(&d, 0, b"exp", false)).unwrap();
+        assert!(store.put(&chunk(&d, 10, b"ected", true)).is_err());
+
+        assert!(store.put(&chunk("sha256:../../etc", 0, b"", true)).is_err());
+        assert!(!is_valid_digest("md5:abc"));
+        assert!(!is_valid_digest(&d.to_uppercase()));
+        assert!(is_valid_digest(&d));
+        let _ = std::fs::remove_dir_all(&store.root);
+    }
+}
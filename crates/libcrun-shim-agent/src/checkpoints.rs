@@ -0,0 +1,404 @@
+//! Named checkpoints of containers
+//!
+//! A checkpoint lives in `<root>/<container>/<name>/`: the CRIU image under
+//! `images/`, the container's OCI configuration as `config.json`, which makes
+//! it restorable after the container itself is gone, and its metadata as
+//! `checkpoint.json`. The metadata is written last, so a directory without it
+//! is a checkpoint that failed half way and is not listed.
+
+use libcrun_shim_proto::{CheckpointFileProto, CheckpointProto, BLOB_CHUNK_SIZE};
+use sha2::{Digest, Sha256};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Where checkpoints are kept in the guest
+///
+/// On disk rather than in the tmpfs state directory: an image holds all of
+/// the container's memory, and it should survive a reboot of the VM.
+pub const CHECKPOINTS_DIR: &str = "/var/lib/libcrun-shim/checkpoints";
+
+/// Longest checkpoint name accepted
+const MAX_NAME_LEN: usize = 128;
+
+const METADATA_FILE: &str = "checkpoint.json";
+const CONFIG_FILE: &str = "config.json";
+
+pub struct CheckpointStore {
+    root: PathBuf,
+}
+
+/// Why a checkpoint name is not accepted, if it is not
+pub fn invalid_name(name: &str) -> Option<&'static str> {
+    if name.is_empty() {
+        Some("Checkpoint name must not be empty")
+    } else if name.len() > MAX_NAME_LEN {
+        Some("Checkpoint name is too long")
+    } else if !name.starts_with(|c: char| c.is_ascii_alphanumeric())
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+    {
+        Some("Checkpoint name may only contain letters, digits, '_', '.' and '-', and must start with a letter or digit")
+    } else {
+        None
+    }
+}
+
+impl CheckpointStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Directory of a checkpoint, whether or not it exists
+    pub fn dir(&self, container: &str, name: &str) -> PathBuf {
+        self.root.join(container).join(name)
+    }
+
+    /// Directory CRIU writes the checkpoint image to
+    pub fn images_dir(&self, container: &str, name: &str) -> PathBuf {
+        self.dir(container, name).join("images")
+    }
+
+    /// Metadata of a checkpoint, if it was completed
+    pub fn get(&self, container: &str, name: &str) -> Option<CheckpointProto> {
+        let content = std::fs::read(self.dir(container, name).join(METADATA_FILE)).ok()?;
+        serde_json::from_slice(&content).ok()
+    }
+
+    /// Create the directory for a new checkpoint, clearing a failed one
+    pub fn prepare(&self, container: &str, name: &str) -> Result<PathBuf, String> {
+        let dir = self.dir(container, name);
+        let _ = std::fs::remove_dir_all(&dir);
+        let images = self.images_dir(container, name);
+        std::fs::create_dir_all(&images)
+            .map_err(|e| format!("Failed to create {}: {}", images.display(), e))?;
+        Ok(images)
+    }
+
+    /// Record a checkpoint once CRIU has written its image
+    pub fn finish(
+        &self,
+        container: &str,
+        name: &str,
+        config_json: &str,
+        created_at: u64,
+    ) -> Result<CheckpointProto, String> {
+        let dir = self.dir(container, name);
+        std::fs::write(dir.join(CONFIG_FILE), config_json)
+            .map_err(|e| format!("Failed to write checkpoint config: {}", e))?;
+        let images = self.images_dir(container, name);
+        let checkpoint = CheckpointProto {
+            container: container.to_string(),
+            name: name.to_string(),
+            created_at,
+            size_bytes: dir_size(&dir),
+            image_digest: image_digest(&images)
+                .map_err(|e| format!("Failed to read checkpoint image: {}", e))?,
+        };
+        let metadata = serde_json::to_vec_pretty(&checkpoint).map_err(|e| e.to_string())?;
+        std::fs::write(dir.join(METADATA_FILE), metadata)
+            .map_err(|e| format!("Failed to write checkpoint metadata: {}", e))?;
+        Ok(checkpoint)
+    }
+
+    /// Completed checkpoints of a container, or of all containers, oldest first
+    pub fn list(&self, container: Option<&str>) -> Vec<CheckpointProto> {
+        let containers: Vec<String> = match container {
+            Some(container) => vec![container.to_string()],
+            None => subdirs(&self.root),
+        };
+        let mut checkpoints: Vec<CheckpointProto> = containers
+            .iter()
+            .flat_map(|container| {
+                subdirs(&self.root.join(container))
+                    .into_iter()
+                    .filter_map(move |name| self.get(container, &name))
+            })
+            .collect();
+        checkpoints.sort_by(|a, b| {
+            (a.created_at, &a.container, &a.name).cmp(&(b.created_at, &b.container, &b.name))
+        });
+        checkpoints
+    }
+
+    /// Delete a checkpoint, and the container's directory with its last one
+    pub fn remove(&self, container: &str, name: &str) -> Result<(), String> {
+        let dir = self.dir(container, name);
+        std::fs::remove_dir_all(&dir)
+            .map_err(|e| format!("Failed to remove {}: {}", dir.display(), e))?;
+        // Fails while other checkpoints of the container remain
+        let _ = std::fs::remove_dir(self.root.join(container));
+        Ok(())
+    }
+
+    /// The OCI configuration of a checkpoint, after checking its image is intact
+    pub fn verify(&self, checkpoint: &CheckpointProto) -> Result<String, String> {
+        let images = self.images_dir(&checkpoint.container, &checkpoint.name);
+        let digest =
+            image_digest(&images).map_err(|e| format!("Failed to read checkpoint image: {}", e))?;
+        if digest != checkpoint.image_digest {
+            return Err(format!(
+                "Checkpoint '{}' of '{}' is corrupt: image digest is {}, expected {}",
+                checkpoint.name, checkpoint.container, digest, checkpoint.image_digest
+            ));
+        }
+        let config = self
+            .dir(&checkpoint.container, &checkpoint.name)
+            .join(CONFIG_FILE);
+        std::fs::read_to_string(&config)
+            .map_err(|e| format!("Failed to read {}: {}", config.display(), e))
+    }
+
+    /// Files of a checkpoint besides its metadata, to copy it elsewhere
+    pub fn files(&self, container: &str, name: &str) -> std::io::Result<Vec<CheckpointFileProto>> {
+        let dir = self.dir(container, name);
+        let mut files = vec![CheckpointFileProto {
+            path: CONFIG_FILE.to_string(),
+            size: std::fs::metadata(dir.join(CONFIG_FILE))?.len(),
+        }];
+        let mut images: Vec<CheckpointFileProto> = std::fs::read_dir(dir.join("images"))?
+            .flatten()
+            .filter(|e| e.file_type().is_ok_and(|t| t.is_file()))
+            .filter_map(|e| {
+                Some(CheckpointFileProto {
+                    path: format!("images/{}", e.file_name().into_string().ok()?),
+                    size: e.metadata().ok()?.len(),
+                })
+            })
+            .collect();
+        images.sort_by(|a, b| a.path.cmp(&b.path));
+        files.extend(images);
+        Ok(files)
+    }
+
+    /// Where a file of a checkpoint is kept, for a path as
+    /// [`CheckpointStore::files`] lists it; `None` for any other path
+    pub fn file(&self, container: &str, name: &str, path: &str) -> Option<PathBuf> {
+        let valid = path == CONFIG_FILE
+            || path.strip_prefix("images/").is_some_and(|file| {
+                !file.is_empty() && file != "." && file != ".." && !file.contains(['/', '\0'])
+            });
+        valid.then(|| self.dir(container, name).join(path))
+    }
+
+    /// Complete a checkpoint whose files were copied into its directory,
+    /// once its image matches the digest of `checkpoint`
+    ///
+    /// A copy that does not match is removed, so the import can be retried.
+    pub fn import(&self, checkpoint: &CheckpointProto) -> Result<CheckpointProto, String> {
+        let dir = self.dir(&checkpoint.container, &checkpoint.name);
+        let imported = self.verify(checkpoint).and_then(|_| {
+            let imported = CheckpointProto {
+                size_bytes: dir_size(&dir),
+                ..checkpoint.clone()
+            };
+            let metadata = serde_json::to_vec_pretty(&imported).map_err(|e| e.to_string())?;
+            std::fs::write(dir.join(METADATA_FILE), metadata)
+                .map_err(|e| format!("Failed to write checkpoint metadata: {}", e))?;
+            Ok(imported)
+        });
+        if imported.is_err() {
+            let _ = self.remove(&checkpoint.container, &checkpoint.name);
+        }
+        imported
+    }
+}
+
+/// Up to [`BLOB_CHUNK_SIZE`] bytes of a file from `offset` on
+pub fn read_chunk(path: &Path, offset: u64) -> Result<Vec<u8>, String> {
+    let mut file = std::fs::File::open(path)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let mut data = Vec::new();
+    file.seek(SeekFrom::Start(offset))
+        .and_then(|_| file.take(BLOB_CHUNK_SIZE as u64).read_to_end(&mut data))
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    Ok(data)
+}
+
+/// Write `data` at `offset` of a file, dropping whatever followed it
+pub fn write_chunk(path: &Path, offset: u64, data: &[u8]) -> Result<(), String> {
+    std::fs::create_dir_all(path.parent().unwrap_or(Path::new("/")))
+        .map_err(|e| format!("Failed to create checkpoint directory: {}", e))?;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(offset == 0)
+        .open(path)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let len = file
+        .metadata()
+        .map_err(|e| format!("Failed to stat {}: {}", path.display(), e))?
+        .len();
+    if offset > len {
+        return Err(format!(
+            "Chunk of {} at offset {} leaves a gap, {} bytes received so far",
+            path.display(),
+            offset,
+            len
+        ));
+    }
+    file.set_len(offset)
+        .and_then(|_| file.seek(SeekFrom::Start(offset)))
+        .and_then(|_| file.write_all(data))
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+/// Names of the directories in `dir`
+fn subdirs(dir: &Path) -> Vec<String> {
+    std::fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+                .filter_map(|e| e.file_name().into_string().ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Total size of the files under `dir`
+fn dir_size(dir: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|e| match e.file_type() {
+            Ok(t) if t.is_dir() => dir_size(&e.path()),
+            Ok(t) if t.is_file() => e.metadata().map(|m| m.len()).unwrap_or(0),
+            _ => 0,
+        })
+        .sum()
+}
+
+/// Digest over the names and contents of the files in a checkpoint image
+///
+/// CRIU writes a flat directory, so subdirectories are not descended into.
+fn image_digest(images: &Path) -> std::io::Result<String> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(images)?
+        .flatten()
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_file()))
+        .map(|e| e.path())
+        .collect();
+    files.sort();
+    let mut hasher = Sha256::new();
+    for file in files {
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        hasher.update((name.len() as u64).to_le_bytes());
+        hasher.update(name.as_bytes());
+        let mut reader = std::fs::File::open(&file)?;
+        hasher.update(std::fs::metadata(&file)?.len().to_le_bytes());
+        std::io::copy(&mut reader, &mut hasher)?;
+    }
+    Ok(format!("sha256:{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalid_name() {
+        assert_eq!(invalid_name("before-upgrade"), None);
+        assert_eq!(invalid_name("v1.2_rc"), None);
+        assert!(invalid_name("").is_some());
+        assert!(invalid_name("-x").is_some());
+        assert!(invalid_name("../etc").is_some());
+        assert!(invalid_name("a/b").is_some());
+        assert!(invalid_name(&"a".repeat(MAX_NAME_LEN + 1)).is_some());
+    }
+
+    #[test]
+    fn test_checkpoint_store() {
+        let root = std::env::temp_dir().join(format!("agent-checkpoints-{}", std::process::id()));
+        let store = CheckpointStore::new(&root);
+
+        let images = store.prepare("c1", "first").unwrap();
+        std::fs::write(images.join("pages-1.img"), b"pages").unwrap();
+        std::fs::write(images.join("core-1.img"), b"core").unwrap();
+        let first = store.finish("c1", "first", "{}", 100).unwrap();
+        assert!(first.image_digest.starts_with("sha256:"));
+        assert_eq!(first.size_bytes, 11);
+        assert_eq!(store.get("c1", "first"), Some(first.clone()));
+        assert_eq!(store.verify(&first).unwrap(), "{}");
+
+        // Half-written checkpoints are not listed
+        store.prepare("c1", "broken").unwrap();
+        let images = store.prepare("c2", "second").unwrap();
+        std::fs::write(images.join("core-1.img"), b"core").unwrap();
+        store.finish("c2", "second", "{}", 50).unwrap();
+        let names = |list: Vec<CheckpointProto>| -> Vec<String> {
+            list.into_iter().map(|c| c.name).collect()
+        };
+        assert_eq!(names(store.list(Some("c1"))), ["first"]);
+        assert_eq!(names(store.list(None)), ["second", "first"]);
+        assert!(store.list(Some("c3")).is_empty());
+
+        // A changed image is caught before restoring it
+        std::fs::write(
+            store.images_dir("c1", "first").join("pages-1.img"),
+            b"other",
+        )
+        .unwrap();
+        assert!(store.verify(&first).unwrap_err().contains("corrupt"));
+
+        store.remove("c2", "second").unwrap();
+        assert!(!root.join("c2").exists());
+        store.remove("c1", "first").unwrap();
+        assert!(root.join("c1").exists());
+        assert!(store.remove("c1", "first").is_err());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_copy_checkpoint() {
+        let root = std::env::temp_dir().join(format!("agent-copy-{}", std::process::id()));
+        let store = CheckpointStore::new(&root);
+        let images = store.prepare("c1", "first").unwrap();
+        std::fs::write(images.join("pages-1.img"), b"pages").unwrap();
+        let first = store.finish("c1", "first", "{}", 100).unwrap();
+
+        let files = store.files("c1", "first").unwrap();
+        let paths: Vec<&str> = files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, ["config.json", "images/pages-1.img"]);
+        assert!(store.file("c1", "first", "checkpoint.json").is_none());
+        assert!(store.file("c1", "first", "images/../../x").is_none());
+        assert!(store.file("c1", "first", "images/").is_none());
+
+        // Copy it as another checkpoint, resending a chunk on the way
+        let copy = CheckpointProto {
+            container: "c2".to_string(),
+            ..first.clone()
+        };
+        for file in &files {
+            let from = store.file("c1", "first", &file.path).unwrap();
+            let to = store.file("c2", "first", &file.path).unwrap();
+            let data = read_chunk(&from, 0).unwrap();
+            assert_eq!(data.len() as u64, file.size);
+            write_chunk(&to, 0, &data[..1]).unwrap();
+            write_chunk(&to, 1, b"xx").unwrap();
+            write_chunk(&to, 1, &data[1..]).unwrap();
+            assert!(read_chunk(&from, file.size).unwrap().is_empty());
+        }
+        assert!(write_chunk(&root.join("c2/first/config.json"), 10, b"x").is_err());
+        let imported = store.import(&copy).unwrap();
+        assert_eq!(imported.size_bytes, first.size_bytes);
+        assert_eq!(store.get("c2", "first"), Some(imported));
+
+        // A copy that does not match is dropped
+        let wrong = CheckpointProto {
+            name: "second".to_string(),
+            ..first
+        };
+        write_chunk(
+            &store.images_dir("c1", "second").join("pages-1.img"),
+            0,
+            b"other",
+        )
+        .unwrap();
+        std::fs::write(store.dir("c1", "second").join(CONFIG_FILE), "{}").unwrap();
+        assert!(store.import(&wrong).unwrap_err().contains("corrupt"));
+        assert!(!store.dir("c1", "second").exists());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,119 @@
+//! Guest clock synchronization with the host
+//!
+//! The VM's clock stops while the host sleeps, so after a wake the guest
+//! can be minutes or hours behind, which breaks TLS certificate checks and
+//! log timestamps. The host sends its own time periodically; like chrony's
+//! `makestep`, large offsets are stepped at once and small ones slewed so
+//! that time in the guest never jumps for running programs.
+
+use libcrun_shim_proto::{TimeSyncProto, TimeSyncRequest};
+
+/// How the clock is brought in line with the host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Correction {
+    /// Already in line
+    None,
+    /// Gradually speed up or slow down the clock by the offset
+    Slew,
+    /// Set the clock to the host time
+    Step,
+}
+
+/// The correction for a clock `offset_ns` behind the host (negative when
+/// ahead), stepping at `step_threshold_ms` or more
+pub fn correction(offset_ns: i64, step_threshold_ms: u64) -> Correction {
+    let offset = offset_ns.unsigned_abs();
+    if offset == 0 {
+        Correction::None
+    } else if offset >= step_threshold_ms.saturating_mul(1_000_000) {
+        Correction::Step
+    } else {
+        Correction::Slew
+    }
+}
+
+/// Measure the guest clock against `request.host_time_ns` and correct it
+///
+/// The transit time of the request is not accounted for; over vsock it is
+/// far below what matters here.
+pub fn sync(request: &TimeSyncRequest) -> std::io::Result<TimeSyncProto> {
+    let offset_ns = request.host_time_ns.saturating_sub(now_ns());
+    let correction = correction(offset_ns, request.step_threshold_ms);
+    match correction {
+        Correction::None => {}
+        Correction::Slew => slew(offset_ns)?,
+        Correction::Step => {
+            step(request.host_time_ns)?;
+            log::info!(
+                "Stepped the clock by {}ms to the host time",
+                offset_ns / 1_000_000
+            );
+        }
+    }
+    Ok(TimeSyncProto {
+        offset_ns,
+        stepped: correction == Correction::Step,
+    })
+}
+
+/// Current wall clock in nanoseconds since the Unix epoch
+fn now_ns() -> i64 {
+    match std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+        Ok(since) => since.as_nanos() as i64,
+        Err(e) => -(e.duration().as_nanos() as i64),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn step(time_ns: i64) -> std::io::Result<()> {
+    let time = libc::timespec {
+        tv_sec: time_ns.div_euclid(1_000_000_000) as libc::time_t,
+        tv_nsec: time_ns.rem_euclid(1_000_000_000) as _,
+    };
+    if unsafe { libc::clock_settime(libc::CLOCK_REALTIME, &time) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Have the kernel skew the clock until it has gained `offset_ns`,
+/// replacing any adjustment still in progress
+#[cfg(target_os = "linux")]
+fn slew(offset_ns: i64) -> std::io::Result<()> {
+    let offset_us = offset_ns / 1000;
+    let delta = libc::timeval {
+        tv_sec: offset_us.div_euclid(1_000_000) as libc::time_t,
+        tv_usec: offset_us.rem_euclid(1_000_000) as libc::suseconds_t,
+    };
+    if unsafe { libc::adjtime(&delta, std::ptr::null_mut()) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn step(_time_ns: i64) -> std::io::Result<()> {
+    Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn slew(_offset_ns: i64) -> std::io::Result<()> {
+    Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_correction() {
+        assert_eq!(correction(0, 1000), Correction::None);
+        assert_eq!(correction(5_000_000, 1000), Correction::Slew);
+        assert_eq!(correction(-999_999_999, 1000), Correction::Slew);
+        assert_eq!(correction(1_000_000_000, 1000), Correction::Step);
+        assert_eq!(correction(-3_600_000_000_000, 1000), Correction::Step);
+        assert_eq!(correction(i64::MIN, 1000), Correction::Step);
+        assert_eq!(correction(1, 0), Correction::Step);
+        assert_eq!(correction(i64::MAX, u64::MAX), Correction::Slew);
+    }
+}
@@ -0,0 +1,182 @@
+//! Diagnostics for support bundles
+//!
+//! The agent logs to stderr, which only reaches the VM console, so it keeps
+//! its last lines in memory for the host to collect along with the guest
+//! kernel log and the agent's state files. The host redacts them, so only
+//! admin connections may collect them.
+
+use libcrun_shim_proto::{DiagnosticsProto, StateFileProto};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::io::AsFd;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Lines of the agent's log kept for [`recent_log`]
+const LOG_LINES: usize = 2000;
+
+/// Longest part of a state file reported
+const MAX_STATE_FILE_BYTES: usize = 1024 * 1024;
+
+static LOG: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// Log target writing to stderr, and keeping the last lines written
+///
+/// It writes to a copy of stderr taken when it is created, so log lines do
+/// not end up in a container's output while fd 2 is redirected to capture it.
+pub struct LogTee(File);
+
+impl LogTee {
+    pub fn new() -> Self {
+        let stderr = std::io::stderr()
+            .as_fd()
+            .try_clone_to_owned()
+            .expect("Failed to duplicate stderr");
+        LogTee(File::from(stderr))
+    }
+}
+
+impl Write for LogTee {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        {
+            let mut log = LOG.lock().unwrap_or_else(|e| e.into_inner());
+            for line in String::from_utf8_lossy(buf).lines() {
+                if log.len() == LOG_LINES {
+                    log.pop_front();
+                }
+                log.push_back(line.to_string());
+            }
+        }
+        self.0.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Everything the agent reports for a support bundle
+pub fn collect(state_dir: &Path) -> DiagnosticsProto {
+    DiagnosticsProto {
+        agent_log: recent_log(),
+        kernel_log: kernel_log(),
+        state_files: state_files(state_dir),
+    }
+}
+
+/// The lines the agent logged last, oldest first
+pub fn recent_log() -> Vec<String> {
+    let log = LOG.lock().unwrap_or_else(|e| e.into_inner());
+    log.iter().cloned().collect()
+}
+
+/// The kernel's message buffer, read from /dev/kmsg
+fn kernel_log() -> String {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let kmsg = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open("/dev/kmsg");
+    let Ok(mut kmsg) = kmsg else {
+        return String::new();
+    };
+    let mut log = String::new();
+    // Each read returns one record
+    let mut record = [0u8; 8192];
+    loop {
+        match kmsg.read(&mut record) {
+            Ok(0) => break,
+            Ok(n) => {
+                if let Some(line) = format_record(&String::from_utf8_lossy(&record[..n])) {
+                    log.push_str(&line);
+                    log.push('\n');
+                }
+            }
+            // The record was overwritten before it was read
+            Err(e) if e.raw_os_error() == Some(libc::EPIPE) => continue,
+            // EAGAIN once all records were read
+            Err(_) => break,
+        }
+    }
+    log
+}
+
+/// A /dev/kmsg record ("priority,sequence,microseconds,flags;message") as
+/// `dmesg` prints it
+fn format_record(record: &str) -> Option<String> {
+    let (header, message) = record.split_once(';')?;
+    let micros: u64 = header.split(',').nth(2)?.parse().ok()?;
+    // Continuation lines carry the record's key=value dictionary
+    let message = message.lines().next().unwrap_or_default();
+    Some(format!(
+        "[{:5}.{:06}] {}",
+        micros / 1_000_000,
+        micros % 1_000_000,
+        message
+    ))
+}
+
+/// The agent's state file and the configs of its containers
+fn state_files(dir: &Path) -> Vec<StateFileProto> {
+    let mut paths = vec![dir.join("state.json")];
+    if let Ok(entries) = std::fs::read_dir(dir) {
+        let mut configs: Vec<PathBuf> = entries
+            .flatten()
+            .map(|entry| entry.path().join("config.json"))
+            .filter(|path| path.is_file())
+            .collect();
+        configs.sort();
+        paths.extend(configs);
+    }
+    paths
+        .into_iter()
+        .filter_map(|path| {
+            let mut contents = std::fs::read(&path).ok()?;
+            contents.truncate(MAX_STATE_FILE_BYTES);
+            Some(StateFileProto {
+                path: path.display().to_string(),
+                contents,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_record() {
+        assert_eq!(
+            format_record("6,339,5140900,-;virtio_net virtio0 eth0: renamed\n SUBSYSTEM=net\n")
+                .unwrap(),
+            "[    5.140900] virtio_net virtio0 eth0: renamed"
+        );
+        assert_eq!(format_record("no header"), None);
+    }
+
+    #[test]
+    fn test_state_files() {
+        let dir = std::env::temp_dir().join(format!("agent-diagnostics-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("web")).unwrap();
+        std::fs::create_dir_all(dir.join("empty")).unwrap();
+        std::fs::write(dir.join("state.json"), "{}").unwrap();
+        std::fs::write(dir.join("web").join("config.json"), "{\"env\":[]}").unwrap();
+
+        let files = state_files(&dir);
+        let paths: Vec<&str> = files.iter().map(|file| file.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                dir.join("state.json").display().to_string(),
+                dir.join("web/config.json").display().to_string()
+            ]
+        );
+        assert_eq!(files[1].contents, b"{\"env\":[]}");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
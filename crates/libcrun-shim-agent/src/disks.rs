@@ -0,0 +1,245 @@
+//! Guest filesystem usage and trimming
+//!
+//! Deleted image layers and container files leave blocks allocated in the
+//! host's disk image until the guest discards them. The agent trims its
+//! disk-backed filesystems periodically and on request so the host gets
+//! that space back.
+
+use libcrun_shim_proto::{FilesystemUsageProto, TrimResultProto};
+use std::path::Path;
+
+/// Default time between scheduled trims
+pub const DEFAULT_TRIM_INTERVAL: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+
+/// Filesystems that hold no data, left out of usage reports
+const PSEUDO_FILESYSTEMS: &[&str] = &[
+    "proc",
+    "sysfs",
+    "devtmpfs",
+    "devpts",
+    "cgroup",
+    "cgroup2",
+    "securityfs",
+    "debugfs",
+    "tracefs",
+    "mqueue",
+    "bpf",
+    "pstore",
+    "configfs",
+    "fusectl",
+    "hugetlbfs",
+    "binfmt_misc",
+    "nsfs",
+    "autofs",
+];
+
+/// One entry of /proc/mounts
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mount {
+    pub device: String,
+    pub mount_point: String,
+    pub fs_type: String,
+}
+
+impl Mount {
+    /// Whether the filesystem sits on a block device and can be trimmed
+    pub fn is_block_backed(&self) -> bool {
+        self.device.starts_with("/dev/")
+    }
+}
+
+/// Parse /proc/mounts, skipping pseudo filesystems and repeated mount points
+pub fn parse_mounts(mounts: &str) -> Vec<Mount> {
+    let mut parsed: Vec<Mount> = Vec::new();
+    for line in mounts.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [device, mount_point, fs_type, ..] = fields.as_slice() else {
+            continue;
+        };
+        if PSEUDO_FILESYSTEMS.contains(fs_type) {
+            continue;
+        }
+        let mount = Mount {
+            device: unescape(device),
+            mount_point: unescape(mount_point),
+            fs_type: fs_type.to_string(),
+        };
+        // A later mount on the same point hides the earlier one
+        parsed.retain(|m| m.mount_point != mount.mount_point);
+        parsed.push(mount);
+    }
+    parsed
+}
+
+/// Undo the octal escapes /proc/mounts uses for spaces and tabs
+fn unescape(field: &str) -> String {
+    let mut out = Vec::with_capacity(field.len());
+    let bytes = field.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' {
+            let digits = field.get(i + 1..i + 4).unwrap_or_default();
+            if let Ok(value) = u8::from_str_radix(digits, 8) {
+                out.push(value);
+                i += 4;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn mounts() -> Vec<Mount> {
+    parse_mounts(&std::fs::read_to_string("/proc/self/mounts").unwrap_or_default())
+}
+
+fn statvfs(path: &str) -> std::io::Result<libc::statvfs> {
+    let c_path = std::ffi::CString::new(path)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(stat)
+}
+
+/// Usage of every mounted filesystem that can hold data
+pub fn usage() -> Vec<FilesystemUsageProto> {
+    mounts()
+        .into_iter()
+        .filter_map(|mount| {
+            let stat = statvfs(&mount.mount_point).ok()?;
+            let block = stat.f_frsize as u64;
+            let total_bytes = stat.f_blocks as u64 * block;
+            if total_bytes == 0 {
+                return None;
+            }
+            Some(FilesystemUsageProto {
+                total_bytes,
+                used_bytes: (stat.f_blocks as u64).saturating_sub(stat.f_bfree as u64) * block,
+                available_bytes: stat.f_bavail as u64 * block,
+                mount_point: mount.mount_point,
+                device: mount.device,
+                fs_type: mount.fs_type,
+            })
+        })
+        .collect()
+}
+
+/// `struct fstrim_range` from <linux/fs.h>
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct FstrimRange {
+    start: u64,
+    len: u64,
+    minlen: u64,
+}
+
+/// `_IOWR('X', 121, struct fstrim_range)`
+#[cfg(target_os = "linux")]
+const FITRIM: u64 = 0xC018_5879;
+
+/// Discard unused blocks of the filesystem mounted at `mount_point`,
+/// returning the number of bytes trimmed
+#[cfg(target_os = "linux")]
+fn fitrim(mount_point: &Path) -> std::io::Result<u64> {
+    use std::os::unix::io::AsRawFd;
+
+    let dir = std::fs::File::open(mount_point)?;
+    let mut range = FstrimRange {
+        start: 0,
+        len: u64::MAX,
+        minlen: 0,
+    };
+    if unsafe { libc::ioctl(dir.as_raw_fd(), FITRIM as _, &mut range) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // The kernel replaces `len` with the number of bytes trimmed
+    Ok(range.len)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn fitrim(_mount_point: &Path) -> std::io::Result<u64> {
+    Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+}
+
+/// Trim every disk-backed filesystem
+pub fn trim_all() -> Vec<TrimResultProto> {
+    mounts()
+        .into_iter()
+        .filter(Mount::is_block_backed)
+        .map(|mount| {
+            let result = fitrim(Path::new(&mount.mount_point));
+            TrimResultProto {
+                mount_point: mount.mount_point,
+                trimmed_bytes: *result.as_ref().unwrap_or(&0),
+                error: result.err().map(|e| e.to_string()),
+            }
+        })
+        .collect()
+}
+
+/// Trim all disk-backed filesystems every `interval`, until `stop` returns true
+pub fn spawn_trim_scheduler(
+    interval: std::time::Duration,
+    stop: impl Fn() -> bool + Send + 'static,
+) {
+    std::thread::spawn(move || {
+        log::info!("Trimming filesystems every {}s", interval.as_secs());
+        loop {
+            std::thread::sleep(interval);
+            if stop() {
+                break;
+            }
+            for result in trim_all() {
+                match result.error {
+                    Some(e) => log::debug!("Cannot trim {}: {}", result.mount_point, e),
+                    None => log::info!(
+                        "Trimmed {} bytes on {}",
+                        result.trimmed_bytes,
+                        result.mount_point
+                    ),
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mounts() {
+        let mounts = parse_mounts(
+            "rootfs / rootfs rw 0 0\n\
+             proc /proc proc rw,nosuid 0 0\n\
+             tmpfs /run tmpfs rw 0 0\n\
+             cgroup2 /sys/fs/cgroup cgroup2 rw 0 0\n\
+             /dev/vda /var/lib/containers ext4 rw,relatime 0 0\n\
+             /dev/vdb /mnt/my\\040data ext4 rw 0 0\n\
+             tmpfs /run tmpfs rw,size=64m 0 0\n\
+             truncated\n",
+        );
+        let points: Vec<&str> = mounts.iter().map(|m| m.mount_point.as_str()).collect();
+        assert_eq!(points, ["/", "/var/lib/containers", "/mnt/my data", "/run"]);
+
+        let block: Vec<&str> = mounts
+            .iter()
+            .filter(|m| m.is_block_backed())
+            .map(|m| m.device.as_str())
+            .collect();
+        assert_eq!(block, ["/dev/vda", "/dev/vdb"]);
+    }
+
+    #[test]
+    fn test_usage() {
+        // Whatever the host has mounted, the root filesystem is reported sanely
+        for fs in usage() {
+            assert!(fs.used_bytes <= fs.total_bytes, "{:?}", fs);
+            assert!(fs.available_bytes <= fs.total_bytes, "{:?}", fs);
+        }
+    }
+}
@@ -0,0 +1,116 @@
+//! Container events raised by the agent on its own
+//!
+//! The host sees what it asks the agent to do, but not what the agent does
+//! behind its back: exits, OOM kills, restarts, deadline and idle stops, and
+//! health changes. Those are sent to every connection subscribed with
+//! `Request::SubscribeEvents`.
+
+use libcrun_shim_proto::{EventKind, EventProto};
+use std::collections::BTreeMap;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::Mutex;
+
+/// Events a subscriber may fall behind by before it misses some
+const SUBSCRIBER_BACKLOG: usize = 1024;
+
+/// The subscribers to the agent's events
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: Mutex<Vec<SyncSender<EventProto>>>,
+}
+
+impl EventBus {
+    /// Receive the events published from now on
+    pub fn subscribe(&self) -> Receiver<EventProto> {
+        let (sender, receiver) = sync_channel(SUBSCRIBER_BACKLOG);
+        self.lock().push(sender);
+        receiver
+    }
+
+    /// Send an event to every subscriber, forgetting the ones that are gone
+    pub fn publish(&self, event: EventProto) {
+        self.lock()
+            .retain(|subscriber| match subscriber.try_send(event.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    log::warn!(
+                        "Event subscriber is {} events behind, dropping {:?} for {}",
+                        SUBSCRIBER_BACKLOG,
+                        event.kind,
+                        event.container_id
+                    );
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            });
+    }
+
+    /// Publish an event of `kind` for the container `id`, raised now
+    pub fn emit(&self, kind: EventKind, id: &str) {
+        self.publish(event(kind, id));
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<SyncSender<EventProto>>> {
+        self.subscribers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// An event of `kind` for the container `id`, raised now
+pub fn event(kind: EventKind, id: &str) -> EventProto {
+    EventProto {
+        kind,
+        container_id: id.to_string(),
+        timestamp: crate::current_timestamp(),
+        exit_code: None,
+        signal: None,
+        attributes: BTreeMap::new(),
+    }
+}
+
+/// An event with a `reason` attribute
+pub fn with_reason(mut event: EventProto, reason: &str) -> EventProto {
+    event
+        .attributes
+        .insert("reason".to_string(), reason.to_string());
+    event
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_bus() {
+        let bus = EventBus::default();
+        // Nobody listens yet, so this one is not kept
+        bus.emit(EventKind::Start, "c0");
+
+        let first = bus.subscribe();
+        let second = bus.subscribe();
+        bus.publish(EventProto {
+            exit_code: Some(137),
+            ..event(EventKind::Die, "c1")
+        });
+        assert_eq!(first.try_recv().unwrap().exit_code, Some(137));
+        assert_eq!(second.try_recv().unwrap().container_id, "c1");
+        assert!(first.try_recv().is_err());
+
+        drop(second);
+        bus.publish(with_reason(event(EventKind::Stop, "c2"), "idle"));
+        assert_eq!(bus.lock().len(), 1);
+        assert_eq!(first.try_recv().unwrap().attributes["reason"], "idle");
+    }
+
+    #[test]
+    fn test_slow_subscriber() {
+        let bus = EventBus::default();
+        let slow = bus.subscribe();
+        for _ in 0..SUBSCRIBER_BACKLOG + 10 {
+            bus.emit(EventKind::HealthFail, "c1");
+        }
+        // The newest events are dropped, the subscriber is kept
+        assert_eq!(slow.try_iter().count(), SUBSCRIBER_BACKLOG);
+        bus.emit(EventKind::HealthOk, "c1");
+        assert_eq!(slow.try_recv().unwrap().kind, EventKind::HealthOk);
+    }
+}
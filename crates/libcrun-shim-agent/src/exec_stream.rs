@@ -0,0 +1,517 @@
+//! Interactive exec sessions
+//!
+//! Once the agent answers `ExecStream` with `ExecStarted`, the connection
+//! belongs to the session: a thread writes the host's input frames to the
+//! command, the command's output goes back as frames, and the exit code is
+//! the last frame before the connection is closed.
+//!
+//! A detachable session is registered under an ID sent to the host first.
+//! When the host detaches or its connection drops, the command keeps running
+//! and its output is kept, up to a limit, until a host attaches again.
+
+use libcrun_shim_proto::*;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex};
+
+#[cfg(target_os = "linux")]
+use libcrun_sys::safe as crun;
+
+/// Size of the output chunks sent to the host
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// A connection from the host that a session can read and write at once
+pub trait Connection: Read + Write + Send + Sized + 'static {
+    fn try_clone(&self) -> std::io::Result<Self>;
+    /// Close both directions, waking up a reader blocked on the connection
+    fn shutdown(&self);
+}
+
+impl Connection for UnixStream {
+    fn try_clone(&self) -> std::io::Result<Self> {
+        UnixStream::try_clone(self)
+    }
+
+    fn shutdown(&self) {
+        let _ = UnixStream::shutdown(self, std::net::Shutdown::Both);
+    }
+}
+
+impl Connection for std::net::TcpStream {
+    fn try_clone(&self) -> std::io::Result<Self> {
+        std::net::TcpStream::try_clone(self)
+    }
+
+    fn shutdown(&self) {
+        let _ = std::net::TcpStream::shutdown(self, std::net::Shutdown::Both);
+    }
+}
+
+/// Output kept for a detached session, beyond which the oldest is dropped
+const BACKLOG_SIZE: usize = 256 * 1024;
+
+/// Detachable sessions by ID, while their command runs
+static SESSIONS: Mutex<BTreeMap<String, Arc<Session>>> = Mutex::new(BTreeMap::new());
+
+/// The writing half of the connection of an attached host
+trait Host: Write + Send {
+    fn hang_up(&self);
+}
+
+impl<S: Connection> Host for S {
+    fn hang_up(&self) {
+        Connection::shutdown(self)
+    }
+}
+
+/// Send one frame; fails once the host is gone
+fn send<S: Write>(conn: &mut S, frame: &ExecFrame) -> std::io::Result<()> {
+    let data = serialize_exec_frame(frame)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    write_frame(conn, &data)
+}
+
+/// Send everything read from `output` as frames made by `frame`
+///
+/// Stops at the end of the output, or at the first error, which is how a
+/// terminal reports that the command closed it; stops reading when `send`
+/// reports that nobody will see the output, so the command sees it closed.
+fn pump(
+    mut output: impl Read,
+    mut send: impl FnMut(ExecFrame) -> bool,
+    frame: fn(Vec<u8>) -> ExecFrame,
+) {
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        match output.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                if !send(frame(buf[..n].to_vec())) {
+                    break;
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(_) => break,
+        }
+    }
+}
+
+/// A running command and the host its I/O is relayed to, if any
+pub struct Session {
+    /// Set for a detachable session, which outlives its host
+    id: Option<String>,
+    pid: i32,
+    tty: bool,
+    /// End the command's input with that of its first host
+    stdin_once: bool,
+    stdin: Mutex<Input>,
+    output: Mutex<Output>,
+}
+
+/// The command's input, until it is closed
+struct Input {
+    file: Option<File>,
+    /// The input so far ends in the middle of a line
+    mid_line: bool,
+}
+
+#[derive(Default)]
+struct Output {
+    host: Option<Box<dyn Host>>,
+    /// Hosts attached so far, so that a reader of one that left cannot
+    /// detach the next
+    attached: u64,
+    /// Output written while detached, for the next host
+    backlog: VecDeque<ExecFrame>,
+    backlog_size: usize,
+    finished: bool,
+}
+
+impl Output {
+    fn keep(&mut self, frame: ExecFrame) {
+        self.backlog_size += frame_size(&frame);
+        self.backlog.push_back(frame);
+        while self.backlog_size > BACKLOG_SIZE {
+            match self.backlog.pop_front() {
+                Some(dropped) => self.backlog_size -= frame_size(&dropped),
+                None => break,
+            }
+        }
+    }
+}
+
+fn frame_size(frame: &ExecFrame) -> usize {
+    match frame {
+        ExecFrame::Stdout(data) | ExecFrame::Stderr(data) => data.len(),
+        _ => 0,
+    }
+}
+
+impl Session {
+    fn new(
+        id: Option<String>,
+        pid: i32,
+        tty: bool,
+        stdin_once: bool,
+        stdin: Option<File>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            id,
+            pid,
+            tty,
+            stdin_once,
+            stdin: Mutex::new(Input {
+                file: stdin,
+                mid_line: false,
+            }),
+            output: Mutex::new(Output::default()),
+        })
+    }
+
+    /// Make `conn` the session's host, replacing the one attached before
+    ///
+    /// `ExecStarted` is written first when `started` is given. Returns the
+    /// number of the attachment, for [`Session::read_input`].
+    fn attach<S: Connection>(&self, conn: &S, started: Option<&[u8]>) -> std::io::Result<u64> {
+        let mut writer = conn.try_clone()?;
+        let mut output = self.output.lock().unwrap();
+        if output.finished {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "exec session has ended",
+            ));
+        }
+        if let Some(previous) = output.host.take() {
+            log::info!("Exec session attached elsewhere, detaching the previous host");
+            previous.hang_up();
+        }
+        if let Some(started) = started {
+            write_frame(&mut writer, started)?;
+        }
+        if let Some(id) = &self.id {
+            send(&mut writer, &ExecFrame::Session(id.clone()))?;
+        }
+        while let Some(frame) = output.backlog.front() {
+            send(&mut writer, frame)?;
+            let frame = output.backlog.pop_front();
+            output.backlog_size -= frame.as_ref().map_or(0, frame_size);
+        }
+        output.host = Some(Box::new(writer));
+        output.attached += 1;
+        Ok(output.attached)
+    }
+
+    /// Send output to the host, or keep it while detached
+    ///
+    /// Returns false once nobody will see it: the host of a session that is
+    /// not detachable went away.
+    fn send(&self, frame: ExecFrame) -> bool {
+        let mut output = self.output.lock().unwrap();
+        if let Some(host) = output.host.as_mut() {
+            if send(host, &frame).is_ok() {
+                return true;
+            }
+            output.host = None;
+        }
+        if self.id.is_none() {
+            return false;
+        }
+        output.keep(frame);
+        true
+    }
+
+    /// Stop sending output to attachment `attached`, if it is still the host
+    fn detach(&self, attached: u64) {
+        let mut output = self.output.lock().unwrap();
+        if output.attached == attached {
+            if let Some(host) = output.host.take() {
+                log::info!(
+                    "Detached from exec session {}",
+                    self.id.as_deref().unwrap_or("")
+                );
+                host.hang_up();
+            }
+        }
+    }
+
+    /// End the command's input: close it, or send end-of-file to its
+    /// terminal, after which it gets no more
+    fn end_input(&self) {
+        let mut stdin = self.stdin.lock().unwrap();
+        if let Some(file) = stdin.file.take() {
+            #[cfg(target_os = "linux")]
+            if self.tty {
+                let _ = crun::end_terminal_input(&file, stdin.mid_line);
+            }
+            drop(file);
+        }
+    }
+
+    /// Write the host's input frames to the command until it detaches or
+    /// the connection ends
+    ///
+    /// When the host of a session that is not detachable goes away, the
+    /// command's input is closed and libcrun is sent `SIGHUP`; under a
+    /// terminal that hangs up the container's terminal too, as closing a
+    /// terminal window does.
+    fn read_input<S: Connection>(&self, mut conn: S, attached: u64) {
+        while let Ok(Some(data)) = read_frame(&mut conn) {
+            match deserialize_exec_frame(&data).ok() {
+                Some(ExecFrame::Stdin(data)) => {
+                    let mut stdin = self.stdin.lock().unwrap();
+                    if let Some(input) = stdin.file.as_mut() {
+                        if input.write_all(&data).is_err() {
+                            stdin.file = None;
+                        } else if let Some(&last) = data.last() {
+                            stdin.mid_line = last != b'\n';
+                        }
+                    }
+                }
+                // A terminal only ends input with ^D, as for a local one,
+                // unless the host's input is all there is
+                Some(ExecFrame::CloseStdin) if !self.tty || self.stdin_once => self.end_input(),
+                #[cfg(target_os = "linux")]
+                Some(ExecFrame::Resize(rows, cols)) if self.tty => {
+                    if let Some(terminal) = &self.stdin.lock().unwrap().file {
+                        let _ = crun::resize_terminal(terminal, rows, cols);
+                    }
+                }
+                Some(ExecFrame::Detach) if self.id.is_some() => break,
+                Some(frame) => log::debug!("Ignoring exec frame {:?}", frame),
+                None => log::warn!("Ignoring malformed exec frame"),
+            }
+        }
+        if self.id.is_some() {
+            if self.stdin_once {
+                self.end_input();
+            }
+            self.detach(attached);
+            return;
+        }
+        self.stdin.lock().unwrap().file.take();
+        if !self.output.lock().unwrap().finished {
+            log::info!("Exec client went away, hanging up");
+            unsafe { libc::kill(self.pid, libc::SIGHUP) };
+        }
+    }
+
+    /// Send the last frame and hang up on the host
+    fn finish(&self, last: ExecFrame) {
+        if let Some(id) = &self.id {
+            SESSIONS.lock().unwrap().remove(id);
+        }
+        let mut output = self.output.lock().unwrap();
+        output.finished = true;
+        if let Some(mut host) = output.host.take() {
+            let _ = send(&mut host, &last);
+            host.hang_up();
+        }
+    }
+}
+
+/// Relay a started command's I/O over the connection until it exits
+///
+/// Returns the exit code, after sending it as the last frame and closing
+/// the connection. A session given an ID is detachable: it keeps running
+/// when its host detaches or goes away, and [`attach`] hands it to another.
+#[cfg(target_os = "linux")]
+pub fn relay<S: Connection>(
+    conn: S,
+    mut child: crun::ExecChild,
+    tty: bool,
+    id: Option<String>,
+    stdin_once: bool,
+) -> Option<i32> {
+    let session = Session::new(id.clone(), child.pid(), tty, stdin_once, child.stdin.take());
+    let attached = match session.attach(&conn, None) {
+        Ok(attached) => attached,
+        Err(e) => {
+            log::error!("Failed to share exec connection: {}", e);
+            session.stdin.lock().unwrap().file.take();
+            let _ = child.wait();
+            return None;
+        }
+    };
+    if let Some(id) = id {
+        SESSIONS.lock().unwrap().insert(id, Arc::clone(&session));
+    }
+
+    let input = {
+        let session = Arc::clone(&session);
+        std::thread::spawn(move || session.read_input(conn, attached))
+    };
+    let stdout = {
+        let output = child.stdout.try_clone();
+        let session = Arc::clone(&session);
+        output.map(|output| {
+            std::thread::spawn(move || pump(output, |f| session.send(f), ExecFrame::Stdout))
+        })
+    };
+    let stderr = child.stderr.take().map(|output| {
+        let session = Arc::clone(&session);
+        std::thread::spawn(move || pump(output, |f| session.send(f), ExecFrame::Stderr))
+    });
+    if let Ok(stdout) = stdout {
+        let _ = stdout.join();
+    }
+    if let Some(stderr) = stderr {
+        let _ = stderr.join();
+    }
+
+    let exit_code = child.wait();
+    let last = match &exit_code {
+        Ok(code) => ExecFrame::Exit(*code),
+        Err(e) => ExecFrame::Failed(format!("Failed to execute command: {}", e.message)),
+    };
+    session.finish(last);
+    let _ = input.join();
+    exit_code.ok()
+}
+
+/// Attach the connection to a detached session, answering `AttachExec`
+///
+/// Returns the connection and the response to send when there is no such
+/// session, and `None` once the host detached again or the command exited.
+pub fn attach<S: Connection>(conn: S, id: &str) -> Option<(S, Response)> {
+    let session = SESSIONS.lock().unwrap().get(id).cloned();
+    let Some(session) = session else {
+        let response = super::failed(
+            ErrorCode::NotFound,
+            format!("No detached exec session: {}", id),
+        );
+        return Some((conn, response));
+    };
+    let started = super::encode_response(&Response::ExecStarted);
+    match session.attach(&conn, Some(&started)) {
+        Ok(attached) => session.read_input(conn, attached),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let response = super::failed(
+                ErrorCode::NotFound,
+                format!("Exec session has ended: {}", id),
+            );
+            return Some((conn, response));
+        }
+        Err(e) => log::error!("Failed to attach to exec session {}: {}", id, e),
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pump() {
+        let (mut host, agent) = UnixStream::pair().unwrap();
+        let mut conn = agent;
+        let output: &[u8] = &[7u8; CHUNK_SIZE + 1];
+        pump(output, |f| send(&mut conn, &f).is_ok(), ExecFrame::Stdout);
+        drop(conn);
+
+        let mut received = Vec::new();
+        while let Some(data) = read_frame(&mut host).unwrap() {
+            match deserialize_exec_frame(&data).unwrap() {
+                ExecFrame::Stdout(chunk) => received.push(chunk.len()),
+                frame => panic!("unexpected frame {:?}", frame),
+            }
+        }
+        assert_eq!(received, [CHUNK_SIZE, 1]);
+    }
+
+    fn frames(host: &mut UnixStream) -> Vec<ExecFrame> {
+        let mut frames = Vec::new();
+        while let Some(data) = read_frame(host).unwrap() {
+            frames.push(deserialize_exec_frame(&data).unwrap());
+        }
+        frames
+    }
+
+    #[test]
+    fn test_detach_and_attach() {
+        let session = Session::new(Some("s1".to_string()), 0, false, false, None);
+        let (mut first, agent) = UnixStream::pair().unwrap();
+        let attached = session.attach(&agent, None).unwrap();
+        assert!(session.send(ExecFrame::Stdout(b"a".to_vec())));
+        session.detach(attached);
+        drop(agent);
+        assert_eq!(
+            frames(&mut first),
+            [
+                ExecFrame::Session("s1".to_string()),
+                ExecFrame::Stdout(b"a".to_vec())
+            ]
+        );
+
+        // Output while detached goes to the next host, up to the limit
+        assert!(session.send(ExecFrame::Stdout(b"b".to_vec())));
+        assert!(session.send(ExecFrame::Stderr(vec![0; BACKLOG_SIZE - 1])));
+        assert!(session.send(ExecFrame::Stdout(b"c".to_vec())));
+        let (mut second, agent) = UnixStream::pair().unwrap();
+        let reader = std::thread::spawn(move || frames(&mut second));
+        session.attach(&agent, None).unwrap();
+        // A stale attachment cannot detach the new one
+        session.detach(attached);
+        session.finish(ExecFrame::Exit(3));
+        drop(agent);
+        assert_eq!(
+            reader.join().unwrap(),
+            [
+                ExecFrame::Session("s1".to_string()),
+                ExecFrame::Stderr(vec![0; BACKLOG_SIZE - 1]),
+                ExecFrame::Stdout(b"c".to_vec()),
+                ExecFrame::Exit(3)
+            ]
+        );
+
+        let (_third, agent) = UnixStream::pair().unwrap();
+        assert!(session.attach(&agent, None).is_err());
+    }
+
+    #[test]
+    fn test_output_of_attached_session() {
+        // Without an ID, output stops once the host is gone
+        let session = Session::new(None, 0, false, false, None);
+        let (host, agent) = UnixStream::pair().unwrap();
+        session.attach(&agent, None).unwrap();
+        drop(host);
+        drop(agent);
+        assert!(!session.send(ExecFrame::Stdout(b"a".to_vec())));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_stdin_once_ends_terminal_input() {
+        use std::os::unix::io::FromRawFd;
+
+        let (mut master, mut slave) = (0, 0);
+        let ret = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                std::ptr::null(),
+            )
+        };
+        assert_eq!(ret, 0);
+        let (master, mut slave) = unsafe { (File::from_raw_fd(master), File::from_raw_fd(slave)) };
+        // The command's output keeps the terminal open
+        let _output = master.try_clone().unwrap();
+        // Detachable, so that the host leaving does not hang up
+        let session = Session::new(Some("s2".to_string()), 0, true, true, Some(master));
+        let (mut host, agent) = UnixStream::pair().unwrap();
+        let attached = session.attach(&agent, None).unwrap();
+        send(&mut host, &ExecFrame::Stdin(b"abc".to_vec())).unwrap();
+        send(&mut host, &ExecFrame::CloseStdin).unwrap();
+        drop(host);
+        session.read_input(agent, attached);
+
+        // The unfinished line, then the end of the input
+        let mut buf = [0u8; 16];
+        assert_eq!(slave.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"abc");
+        assert_eq!(slave.read(&mut buf).unwrap(), 0);
+    }
+}
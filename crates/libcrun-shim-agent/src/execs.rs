@@ -0,0 +1,34 @@
+//! Exec session history kept per container
+
+use libcrun_shim_proto::ExecSessionProto;
+
+/// Exec sessions kept per container; older ones are dropped first
+pub const MAX_EXEC_SESSIONS: usize = 100;
+
+/// Append a session to a container's history, dropping the oldest past the limit
+pub fn record(history: &mut Vec<ExecSessionProto>, session: ExecSessionProto) {
+    history.push(session);
+    if history.len() > MAX_EXEC_SESSIONS {
+        let excess = history.len() - MAX_EXEC_SESSIONS;
+        history.drain(..excess);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exec_history() {
+        let mut history = Vec::new();
+        for i in 0..(MAX_EXEC_SESSIONS + 5) {
+            let session = ExecSessionProto {
+                started_at: i as u64,
+                ..Default::default()
+            };
+            record(&mut history, session);
+        }
+        assert_eq!(history.len(), MAX_EXEC_SESSIONS);
+        assert_eq!(history[0].started_at, 5);
+    }
+}
@@ -0,0 +1,86 @@
+//! Health check bookkeeping
+//!
+//! Follows Docker's semantics: failures during the start period don't count,
+//! a passing probe makes the container healthy and resets the failure streak,
+//! and `retries` consecutive failures make it unhealthy.
+
+/// Probe interval when the health check doesn't set one
+pub const DEFAULT_INTERVAL_SECS: u64 = 30;
+
+/// Consecutive failures before a container is unhealthy, if not configured
+pub const DEFAULT_RETRIES: u32 = 3;
+
+/// Probe output kept for the host, from the end of the output
+const MAX_OUTPUT_BYTES: usize = 4096;
+
+/// Health status and failure streak after a probe
+///
+/// `status` is the current status ("starting", "healthy" or "unhealthy").
+pub fn next_status(
+    status: &str,
+    failures: u32,
+    passed: bool,
+    retries: u32,
+    in_start_period: bool,
+) -> (&'static str, u32) {
+    if passed {
+        return ("healthy", 0);
+    }
+    // Slow starters aren't penalized during the start period
+    let failures = if in_start_period {
+        failures
+    } else {
+        failures + 1
+    };
+    let status = if failures >= retries.max(1) {
+        "unhealthy"
+    } else if status == "healthy" {
+        "healthy"
+    } else {
+        "starting"
+    };
+    (status, failures)
+}
+
+/// Keep the tail of a probe's output
+pub fn truncate_output(mut output: String) -> String {
+    if output.len() > MAX_OUTPUT_BYTES {
+        let mut start = output.len() - MAX_OUTPUT_BYTES;
+        while !output.is_char_boundary(start) {
+            start += 1;
+        }
+        output.drain(..start);
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_status() {
+        // Failures during the start period don't count
+        assert_eq!(next_status("starting", 0, false, 3, true), ("starting", 0));
+        assert_eq!(next_status("starting", 0, true, 3, true), ("healthy", 0));
+
+        // A healthy container turns unhealthy only after `retries` failures
+        assert_eq!(next_status("healthy", 0, false, 3, false), ("healthy", 1));
+        assert_eq!(next_status("healthy", 1, false, 3, false), ("healthy", 2));
+        assert_eq!(next_status("healthy", 2, false, 3, false), ("unhealthy", 3));
+        assert_eq!(
+            next_status("unhealthy", 3, false, 3, false),
+            ("unhealthy", 4)
+        );
+        assert_eq!(next_status("unhealthy", 4, true, 3, false), ("healthy", 0));
+
+        assert_eq!(
+            next_status("starting", 0, false, 0, false),
+            ("unhealthy", 1)
+        );
+
+        let output = truncate_output("é".repeat(MAX_OUTPUT_BYTES));
+        assert!(output.len() <= MAX_OUTPUT_BYTES);
+        assert!(output.chars().all(|c| c == 'é'));
+    }
+}
@@ -0,0 +1,110 @@
+//! Metrics history for usage reports
+//!
+//! The agent samples the counters of every running container at a fixed
+//! interval and keeps the samples in memory for a day, so the host can report
+//! usage over a window without having polled the whole time. Samples of
+//! deleted containers stay until they age out, so short-lived containers
+//! still show up in reports.
+
+use libcrun_shim_proto::{ContainerMetricsProto, MetricsSampleProto};
+use std::collections::VecDeque;
+
+/// Default time between samples
+pub const DEFAULT_SAMPLE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15);
+
+/// How long samples are kept
+const RETENTION_SECS: u64 = 24 * 60 * 60;
+
+/// Upper bound on samples kept across all containers, oldest dropped first
+const MAX_SAMPLES: usize = 200_000;
+
+/// Samples of all containers, oldest first
+#[derive(Debug, Default)]
+pub struct History {
+    samples: VecDeque<MetricsSampleProto>,
+}
+
+impl History {
+    /// Add a sample, dropping those past retention
+    pub fn record(&mut self, sample: MetricsSampleProto) {
+        let cutoff = sample.timestamp.saturating_sub(RETENTION_SECS);
+        self.samples.push_back(sample);
+        while self
+            .samples
+            .front()
+            .is_some_and(|s| s.timestamp < cutoff || self.samples.len() > MAX_SAMPLES)
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Samples taken at or after `timestamp`, oldest first
+    pub fn since(&self, timestamp: u64) -> Vec<MetricsSampleProto> {
+        let start = self.samples.partition_point(|s| s.timestamp < timestamp);
+        self.samples.range(start..).cloned().collect()
+    }
+}
+
+/// The counters a report needs, taken from a full metrics snapshot
+pub fn sample(metrics: &ContainerMetricsProto, timestamp: u64) -> MetricsSampleProto {
+    MetricsSampleProto {
+        id: metrics.id.clone(),
+        timestamp,
+        cpu_usage_ns: metrics.cpu.usage_total,
+        memory_bytes: metrics.memory.usage,
+        net_rx_bytes: metrics.network.rx_bytes,
+        net_tx_bytes: metrics.network.tx_bytes,
+        blkio_read_bytes: metrics.blkio.read_bytes,
+        blkio_write_bytes: metrics.blkio.write_bytes,
+        pids: metrics.pids.current,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(id: &str, timestamp: u64) -> MetricsSampleProto {
+        MetricsSampleProto {
+            id: id.to_string(),
+            timestamp,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_since_and_retention() {
+        let mut history = History::default();
+        for t in [100, 115, 130] {
+            history.record(at("a", t));
+            history.record(at("b", t));
+        }
+        assert_eq!(history.since(0).len(), 6);
+        let recent = history.since(115);
+        assert_eq!(recent.len(), 4);
+        assert!(recent.iter().all(|s| s.timestamp >= 115));
+        assert!(history.since(131).is_empty());
+
+        // A day later the old samples are gone
+        history.record(at("a", 115 + RETENTION_SECS));
+        let kept: Vec<u64> = history.since(0).iter().map(|s| s.timestamp).collect();
+        assert_eq!(kept, [115, 115, 130, 130, 115 + RETENTION_SECS]);
+    }
+
+    #[test]
+    fn test_sample() {
+        let mut metrics = ContainerMetricsProto {
+            id: "c1".to_string(),
+            ..Default::default()
+        };
+        metrics.cpu.usage_total = 5_000;
+        metrics.memory.usage = 1 << 20;
+        metrics.network.tx_bytes = 42;
+        let sample = sample(&metrics, 7);
+        assert_eq!(sample.id, "c1");
+        assert_eq!(sample.timestamp, 7);
+        assert_eq!(sample.cpu_usage_ns, 5_000);
+        assert_eq!(sample.memory_bytes, 1 << 20);
+        assert_eq!(sample.net_tx_bytes, 42);
+    }
+}
@@ -0,0 +1,183 @@
+//! One agent per VM
+//!
+//! Two agents in the same VM, e.g. when an init script raced, would both
+//! listen on vsock and write the same state file. An agent holds an
+//! exclusive lock on its pid file while it runs, and exits when another one
+//! holds it unless it was started with `--replace` to take over.
+
+use libcrun_shim_proto::*;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Default pid file, next to the state it guards
+pub const DEFAULT_PID_FILE: &str = "/var/run/libcrun-shim/agent.pid";
+
+/// How long the running agent may take to drain, a little over its own
+/// wait for requests in flight
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(35);
+
+/// How long a replaced agent may take to exit and release the lock
+pub const RELEASE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The agent holding a pid file, as it recorded itself
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Owner {
+    pub pid: u32,
+    /// Unix socket it listens on
+    pub socket: String,
+}
+
+impl Owner {
+    /// Parse a pid file: the pid, then the socket on the next line
+    fn parse(content: &str) -> Option<Self> {
+        let mut lines = content.lines();
+        let pid = lines.next()?.trim().parse().ok()?;
+        let socket = lines.next().unwrap_or_default().trim().to_string();
+        Some(Owner { pid, socket })
+    }
+}
+
+/// Why the agent could not lock its pid file
+#[derive(Debug)]
+pub enum LockError {
+    /// Another agent holds it, `None` if it has not recorded itself yet
+    Held(Option<Owner>),
+    Io(io::Error),
+}
+
+impl From<io::Error> for LockError {
+    fn from(e: io::Error) -> Self {
+        LockError::Io(e)
+    }
+}
+
+/// Exclusive lock on the pid file, released when the agent exits
+///
+/// The file is opened close-on-exec, so neither container processes nor an
+/// upgraded agent binary inherit the lock; the latter takes it again.
+#[derive(Debug)]
+pub struct InstanceLock {
+    _file: File,
+}
+
+impl InstanceLock {
+    /// Lock `path` and record this agent in it, listening on `socket`
+    pub fn acquire(path: &Path, socket: &str) -> Result<Self, LockError> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::WouldBlock {
+                return Err(err.into());
+            }
+            let mut content = String::new();
+            let _ = file.read_to_string(&mut content);
+            return Err(LockError::Held(Owner::parse(&content)));
+        }
+        file.set_len(0)?;
+        file.write_all(format!("{}\n{}\n", std::process::id(), socket).as_bytes())?;
+        Ok(Self { _file: file })
+    }
+
+    /// [`InstanceLock::acquire`], waiting up to `timeout` for the agent
+    /// holding the lock to exit
+    pub fn acquire_within(path: &Path, socket: &str, timeout: Duration) -> Result<Self, LockError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match Self::acquire(path, socket) {
+                Err(LockError::Held(_)) if Instant::now() < deadline => {
+                    std::thread::sleep(Duration::from_millis(50));
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Take over from the agent `owner`
+///
+/// The drain lets it finish the requests in flight and persist its state,
+/// then it is killed rather than terminated: on SIGTERM it would stop its
+/// containers, which keep running for this agent to recover from the state
+/// file instead.
+pub fn replace(owner: &Owner) -> io::Result<()> {
+    let mut stream = UnixStream::connect(&owner.socket)?;
+    stream.set_read_timeout(Some(DRAIN_TIMEOUT))?;
+    let payload = serialize_request(&Request::Drain).map_err(io::Error::other)?;
+    write_frame(&mut stream, &payload)?;
+    let frame = read_frame(&mut stream)?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "it closed the connection"))?;
+    match deserialize_response(&frame).map_err(io::Error::other)? {
+        Response::Drained => {}
+        Response::Failed(e) => return Err(io::Error::other(e.message)),
+        other => return Err(io::Error::other(format!("unexpected response {:?}", other))),
+    }
+    log::info!("Agent {} drained, killing it", owner.pid);
+    if unsafe { libc::kill(owner.pid as libc::pid_t, libc::SIGKILL) } != 0 {
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::ESRCH) {
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_owner_parse() {
+        assert_eq!(
+            Owner::parse("42\n/tmp/agent.sock\n"),
+            Some(Owner {
+                pid: 42,
+                socket: "/tmp/agent.sock".to_string()
+            })
+        );
+        assert_eq!(Owner::parse("42\n").map(|o| o.socket), Some(String::new()));
+        assert_eq!(Owner::parse(""), None);
+        assert_eq!(Owner::parse("agent\n"), None);
+    }
+
+    #[test]
+    fn test_instance_lock() {
+        let dir = std::env::temp_dir().join(format!("agent-instance-{}", std::process::id()));
+        let path = dir.join("agent.pid");
+
+        let lock = InstanceLock::acquire(&path, "/tmp/first.sock").unwrap();
+        // The lock is per open file, so it excludes this process as well
+        match InstanceLock::acquire(&path, "/tmp/second.sock") {
+            Err(LockError::Held(Some(owner))) => {
+                assert_eq!(owner.pid, std::process::id());
+                assert_eq!(owner.socket, "/tmp/first.sock");
+            }
+            other => panic!("expected the lock to be held, got {:?}", other),
+        }
+        assert!(matches!(
+            InstanceLock::acquire_within(&path, "/tmp/second.sock", Duration::from_millis(100)),
+            Err(LockError::Held(_))
+        ));
+
+        // A pid file left behind does not keep the next agent out
+        drop(lock);
+        let _lock = InstanceLock::acquire(&path, "/tmp/second.sock").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!("{}\n/tmp/second.sock\n", std::process::id())
+        );
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
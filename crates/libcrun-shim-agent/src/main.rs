@@ -1,15 +1,38 @@
+mod blobs;
+mod checkpoints;
+mod clock;
+mod diagnostics;
+mod disks;
+mod events;
+mod exec_stream;
+mod execs;
+mod health;
+mod history;
+mod instance;
+mod meminfo;
+mod metrics_cache;
+mod mounts;
+mod network;
+mod policy;
+mod probe;
+mod progress;
+mod records;
+
+use exec_stream::Connection;
 use libcrun_shim_proto::*;
 use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
 use signal_hook::iterator::Signals;
-use std::collections::HashMap;
-use std::io::{Read, Write};
+use std::collections::{BTreeMap, HashMap};
 use std::os::unix::net::{UnixListener, UnixStream};
-use std::path::PathBuf;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, RwLock};
 
 #[cfg(target_os = "linux")]
-use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use libcrun_shim_proto::cgroup::{self, oom_kill_count, unapplied_limits, CgroupPaths};
+#[cfg(target_os = "linux")]
+use std::os::unix::io::RawFd;
+use std::os::unix::io::{AsRawFd, FromRawFd};
 
 // Vsock constants
 #[cfg(target_os = "linux")]
@@ -63,7 +86,7 @@ struct PersistedContainerState {
     command: Vec<String>,
     env: Vec<String>,
     working_dir: String,
-    status: String,
+    status: ContainerStatus,
     pid: Option<u32>,
     created_at: u64,
     #[serde(default)]
@@ -74,23 +97,110 @@ struct PersistedContainerState {
     health_status: String,
     #[serde(default)]
     consecutive_failures: u32,
+    #[serde(default)]
+    last_output: String,
+    #[serde(default)]
+    max_runtime_secs: Option<u64>,
+    #[serde(default)]
+    stop_timeout: Option<u64>,
+    #[serde(default)]
+    started_at: Option<u64>,
+    #[serde(default)]
+    exit_reason: Option<String>,
+    #[serde(default)]
+    auto_stop: Option<AutoStopConfig>,
+    #[serde(default)]
+    execs: Vec<ExecSessionProto>,
+    #[serde(default)]
+    restart_count: u32,
+    #[serde(default)]
+    last_exit_code: Option<i32>,
+    #[serde(default)]
+    last_exit_reason: Option<ExitReason>,
+    #[serde(default)]
+    oom_kills: u32,
+    #[serde(default)]
+    restart_policy: RestartPolicy,
+    #[serde(default)]
+    restart_retries: u32,
+    #[serde(default)]
+    next_restart_at: Option<u64>,
+    #[serde(default)]
+    ip_address: Option<std::net::Ipv4Addr>,
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+    /// Empty in the state of agents that predate names, which use the ID
+    #[serde(default)]
+    name: String,
+}
+
+/// Idle-based auto-stop policy for a container
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+struct AutoStopConfig {
+    idle_secs: u64,
+    cpu_percent: f64,
+}
+
+/// Last resource sample of a container, used to detect idleness
+#[derive(Clone, Copy, Debug)]
+struct IdleSample {
+    at: std::time::Instant,
+    cpu_usage_ns: u64,
+    net_bytes: u64,
+    /// Start of the current idle period (Unix seconds)
+    idle_since: Option<u64>,
 }
 
 // Container state in the agent
 
 struct ContainerState {
     id: String,
+    /// Unique among the containers, the ID unless the host gave one
+    name: String,
     rootfs: String,
     command: Vec<String>,
     env: Vec<String>,
     working_dir: String,
-    status: String,
+    status: ContainerStatus,
     pid: Option<u32>,
     created_at: u64,
     health_check: Option<HealthCheckConfig>,
     last_health_check: Option<u64>,
     health_status: String,
     consecutive_failures: u32,
+    /// Output of the last health probe
+    last_output: String,
+    /// Kill the container after it has run this long
+    max_runtime_secs: Option<u64>,
+    /// Seconds between SIGTERM and SIGKILL on stop, the default if unset
+    stop_timeout: Option<u64>,
+    started_at: Option<u64>,
+    /// Why the agent stopped the container, e.g. "timeout"
+    exit_reason: Option<String>,
+    /// Times the container was started again after it exited
+    restart_count: u32,
+    /// Exit code of the last run, 128 + signal when it was killed
+    last_exit_code: Option<i32>,
+    last_exit_reason: Option<ExitReason>,
+    /// Processes of the current or last run killed by the OOM killer
+    oom_kills: u32,
+    restart_policy: RestartPolicy,
+    /// Restarts in a row, reset once a run lasts `RESTART_RESET_AFTER`
+    restart_retries: u32,
+    /// When the watchdog runs the exited container again (Unix seconds)
+    next_restart_at: Option<u64>,
+    /// Address on the bridge network, kept until the container is deleted
+    ip_address: Option<std::net::Ipv4Addr>,
+    /// Key-value metadata from the host, matched by prune filters
+    labels: BTreeMap<String, String>,
+    auto_stop: Option<AutoStopConfig>,
+    idle_sample: Option<IdleSample>,
+    /// Finished exec sessions, oldest first
+    execs: Vec<ExecSessionProto>,
+    /// Exec sessions running in the container, see [`ExecGuard`]
+    execs_in_flight: usize,
+    /// Its process was gone when the agent recovered its state
+    orphaned: bool,
     #[cfg(target_os = "linux")]
     libcrun_container: Option<LibcrunContainer>,
 }
@@ -103,18 +213,40 @@ impl ContainerState {
             command: self.command.clone(),
             env: self.env.clone(),
             working_dir: self.working_dir.clone(),
-            status: self.status.clone(),
+            status: self.status,
             pid: self.pid,
             created_at: self.created_at,
             health_check: self.health_check.clone(),
             last_health_check: self.last_health_check,
             health_status: self.health_status.clone(),
             consecutive_failures: self.consecutive_failures,
+            last_output: self.last_output.clone(),
+            max_runtime_secs: self.max_runtime_secs,
+            stop_timeout: self.stop_timeout,
+            started_at: self.started_at,
+            exit_reason: self.exit_reason.clone(),
+            auto_stop: self.auto_stop.clone(),
+            execs: self.execs.clone(),
+            restart_count: self.restart_count,
+            last_exit_code: self.last_exit_code,
+            last_exit_reason: self.last_exit_reason,
+            oom_kills: self.oom_kills,
+            restart_policy: self.restart_policy,
+            restart_retries: self.restart_retries,
+            next_restart_at: self.next_restart_at,
+            ip_address: self.ip_address,
+            labels: self.labels.clone(),
+            name: self.name.clone(),
         }
     }
 
     fn from_persisted(p: PersistedContainerState) -> Self {
         Self {
+            name: if p.name.is_empty() {
+                p.id.clone()
+            } else {
+                p.name
+            },
             id: p.id,
             rootfs: p.rootfs,
             command: p.command,
@@ -125,22 +257,164 @@ impl ContainerState {
             created_at: p.created_at,
             health_check: p.health_check,
             last_health_check: p.last_health_check,
-            health_status: if p.health_status.is_empty() {
-                "unknown".to_string()
+            health_status: if p.health_status.is_empty() || p.health_status == "unknown" {
+                "starting".to_string()
             } else {
                 p.health_status
             },
             consecutive_failures: p.consecutive_failures,
+            last_output: p.last_output,
+            max_runtime_secs: p.max_runtime_secs,
+            stop_timeout: p.stop_timeout,
+            started_at: p.started_at,
+            exit_reason: p.exit_reason,
+            restart_count: p.restart_count,
+            last_exit_code: p.last_exit_code,
+            last_exit_reason: p.last_exit_reason,
+            oom_kills: p.oom_kills,
+            restart_policy: p.restart_policy,
+            restart_retries: p.restart_retries,
+            next_restart_at: p.next_restart_at,
+            ip_address: p.ip_address,
+            labels: p.labels,
+            auto_stop: p.auto_stop,
+            idle_sample: None,
+            execs: p.execs,
+            execs_in_flight: 0,
+            orphaned: false,
             #[cfg(target_os = "linux")]
             libcrun_container: None,
         }
     }
+
+    fn to_info(&self) -> ContainerInfoProto {
+        ContainerInfoProto {
+            id: self.id.clone(),
+            status: self.status,
+            pid: self.pid,
+            exit_reason: self.exit_reason.clone(),
+            restart_count: self.restart_count,
+            last_exit_code: self.last_exit_code,
+            last_exit_reason: self.last_exit_reason,
+            restart_policy: self.restart_policy,
+            restart_retries: self.restart_retries,
+            ip_address: self.ip_address.map(|address| address.to_string()),
+            oom_kills: self.oom_kills,
+            labels: self.labels.clone(),
+            name: self.name.clone(),
+        }
+    }
+
+    /// Mark the container stopped after the agent sent its process `signal`
+    fn killed(&mut self, lifecycle: &StateMachine, signal: libc::c_int) {
+        if let Err(e) = lifecycle.apply(&self.id, &mut self.status, Transition::Stop) {
+            log::warn!("{}", e);
+            return;
+        }
+        self.pid = None;
+        self.last_exit_code = Some(128 + signal);
+        self.last_exit_reason = Some(ExitReason::Signal);
+    }
+
+    /// Plan the next run after an exit the watchdog noticed, per the restart policy
+    fn schedule_restart(&mut self, now: u64) {
+        self.next_restart_at = self
+            .restart_policy
+            .restarts(self.last_exit_code, self.restart_retries)
+            .then(|| now + restart_delay(self.restart_retries).as_secs());
+    }
+}
+
+/// How long the watchdog waits before restarting a container that has
+/// already been restarted `retries` times in a row
+fn restart_delay(retries: u32) -> std::time::Duration {
+    RESTART_BACKOFF
+        .saturating_mul(1 << retries.min(16))
+        .min(MAX_RESTART_BACKOFF)
 }
 
 /// Agent state directory for persistence
 const STATE_DIR: &str = "/var/run/libcrun-shim";
 const STATE_FILE: &str = "/var/run/libcrun-shim/state.json";
 
+/// Per-container log directories
+const CONTAINER_LOG_DIR: &str = "/var/log/containers";
+
+/// Writable layers of containers created with `rootfs_snapshot`
+#[cfg(target_os = "linux")]
+const SNAPSHOTS_DIR: &str = "/var/lib/libcrun-shim/snapshots";
+
+/// How often followed logs are checked for new output
+const LOG_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+
+/// Longest a log follower goes without a message while the container is quiet
+const LOG_HEARTBEAT: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Longest a waiter sleeps before looking at its container again, e.g. in
+/// case it was stopped or deleted meanwhile
+const WAIT_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Delay before restarting an exited container, doubled for each restart in a
+/// row; restarts happen on the first watchdog pass after it
+const RESTART_BACKOFF: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Longest delay between two restarts of a container
+const MAX_RESTART_BACKOFF: std::time::Duration = std::time::Duration::from_secs(300);
+
+/// A run at least this long ends a series of restarts in a row
+const RESTART_RESET_AFTER: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Longest container ID accepted on create
+const MAX_CONTAINER_ID_LEN: usize = 256;
+
+/// Location where a replacement agent binary is staged before an upgrade
+const STAGED_AGENT_PATH: &str = "/var/run/libcrun-shim/agent.new";
+
+/// Default lifetime of cached container metrics
+const DEFAULT_METRICS_TTL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Optional features advertised to the host during the handshake
+const AGENT_FEATURES: &[&str] = &[
+    features::EXEC,
+    features::HEALTH,
+    features::METRICS,
+    features::AGENT_UPGRADE,
+    features::KERNEL_FEATURES,
+    features::EXEC_AUDIT,
+    features::DISKS,
+    features::HOT_MOUNT,
+    features::METRICS_HISTORY,
+    features::BLOB_CACHE,
+    features::READ_ONLY,
+    features::CHECKPOINTS,
+    features::MEMORY_INFO,
+    features::EXEC_STREAM,
+    features::LOG_STREAMING,
+    features::VERSION,
+    features::PAUSE,
+    features::RESTART_POLICY,
+    features::WAIT,
+    features::EXEC_DETACH,
+    features::LISTENING_PORTS,
+    features::TIME_SYNC,
+    features::ROOTFS_SNAPSHOT,
+    features::READ_ONLY_ROOTFS,
+    features::CHECKPOINT_TRANSFER,
+    features::LABELS,
+    features::PRUNE,
+    features::RESOLVE,
+    features::DIAGNOSTICS,
+    features::EVENTS,
+    features::PROGRESS,
+    features::LIST_FILTER,
+    features::STALE_RESOURCES,
+    features::RENAME,
+    features::STOP_TIMEOUT,
+    features::MOUNT_OPTIONS,
+    features::UNDRAIN,
+    features::COPY,
+];
+
 /// Get current Unix timestamp in seconds
 fn current_timestamp() -> u64 {
     std::time::SystemTime::now()
@@ -158,6 +432,33 @@ struct AgentState {
     libcrun_context: Option<LibcrunContext>,
     #[cfg(target_os = "linux")]
     libcrun_available: bool,
+    /// Admission policy checked before create and start
+    policy: policy::Policy,
+    /// Kernel features probed at startup, reported to the host
+    kernel: KernelFeaturesProto,
+    /// Recently collected metrics, so concurrent readers share cgroup reads
+    metrics_cache: metrics_cache::MetricsCache,
+    /// Periodic metrics samples for usage reports
+    history: std::sync::Mutex<history::History>,
+    /// Bridge network of containers in `bridge` mode
+    bridge: network::Bridge,
+    /// Subscribers to the events the agent raises on its own
+    events: events::EventBus,
+    /// IDs of the containers being created, see [`CreateGuard`]
+    #[cfg(target_os = "linux")]
+    creating: std::sync::Mutex<std::collections::HashSet<String>>,
+    /// Validates the status changes of containers
+    lifecycle: StateMachine,
+}
+
+/// Status changes of the agent's containers
+///
+/// The agent persists its state and publishes the events of a change itself,
+/// as only it knows whether the containers lock is held.
+fn lifecycle() -> StateMachine {
+    StateMachine::new().on_transition(|id, transition, from, to| {
+        log::debug!("Container {}: {} ({} -> {})", id, transition, from, to);
+    })
 }
 
 impl AgentState {
@@ -190,6 +491,14 @@ impl AgentState {
                 state_dir,
                 libcrun_context: context,
                 libcrun_available: available,
+                policy: policy::Policy::default(),
+                kernel: KernelFeaturesProto::default(),
+                metrics_cache: metrics_cache::MetricsCache::new(DEFAULT_METRICS_TTL),
+                history: Default::default(),
+                bridge: network::Bridge::new(network::Subnet::default()),
+                events: Default::default(),
+                creating: Default::default(),
+                lifecycle: lifecycle(),
             };
 
             // Recover any persisted state
@@ -202,6 +511,13 @@ impl AgentState {
             let state = Self {
                 containers: RwLock::new(HashMap::new()),
                 state_dir,
+                policy: policy::Policy::default(),
+                kernel: KernelFeaturesProto::default(),
+                metrics_cache: metrics_cache::MetricsCache::new(DEFAULT_METRICS_TTL),
+                history: Default::default(),
+                bridge: network::Bridge::new(network::Subnet::default()),
+                events: Default::default(),
+                lifecycle: lifecycle(),
             };
 
             // Recover any persisted state
@@ -261,15 +577,42 @@ impl AgentState {
                                     p.pid.unwrap_or(0)
                                 );
                                 let mut state = ContainerState::from_persisted(p);
-                                state.status = "running".to_string();
+                                // A frozen container stays frozen across an agent restart
+                                if state.status != ContainerStatus::Paused {
+                                    state.status = ContainerStatus::Running;
+                                }
                                 containers.insert(state.id.clone(), state);
                             } else {
-                                // Container process not running - mark as orphaned
-                                log::warn!("Container {} was orphaned (pid {} not running), marking for cleanup", 
-                                    p.id, p.pid.unwrap_or(0));
+                                let pid = p.pid.unwrap_or(0);
+                                let was_running = matches!(
+                                    p.status,
+                                    ContainerStatus::Running | ContainerStatus::Paused
+                                );
                                 let mut state = ContainerState::from_persisted(p);
-                                state.status = "orphaned".to_string();
+                                state.status = ContainerStatus::Stopped;
                                 state.pid = None;
+                                // Containers that went down with the agent come back
+                                // under their restart policy, `always` ones even if
+                                // they had been stopped
+                                let resume = state.started_at.is_some()
+                                    && (was_running
+                                        || state.restart_policy == RestartPolicy::Always)
+                                    && state.restart_policy.restarts(None, state.restart_retries);
+                                if resume && state.next_restart_at.is_none() {
+                                    state.next_restart_at = Some(current_timestamp());
+                                }
+                                if state.next_restart_at.is_some() {
+                                    log::info!(
+                                        "Container {} is not running, restarting it ({} policy)",
+                                        state.id,
+                                        state.restart_policy
+                                    );
+                                } else {
+                                    // Container process not running - mark as orphaned
+                                    log::warn!("Container {} was orphaned (pid {} not running), marking for cleanup", 
+                                        state.id, pid);
+                                    state.orphaned = true;
+                                }
                                 containers.insert(state.id.clone(), state);
                             }
                         }
@@ -296,7 +639,7 @@ impl AgentState {
         let mut containers = self.containers.write().unwrap();
         let orphans: Vec<String> = containers
             .iter()
-            .filter(|(_, c)| c.status == "orphaned")
+            .filter(|(_, c)| c.orphaned)
             .map(|(id, _)| id.clone())
             .collect();
 
@@ -322,15 +665,23 @@ impl AgentState {
             let containers = self.containers.read().unwrap();
             containers
                 .iter()
-                .filter(|(_, c)| c.status == "running" || c.status == "Running")
+                .filter(|(_, c)| {
+                    matches!(c.status, ContainerStatus::Running | ContainerStatus::Paused)
+                })
                 .map(|(id, _)| id.clone())
                 .collect()
         };
 
         for id in container_ids {
             log::info!("Stopping container {} during shutdown", id);
-            if let Err(e) = self.stop_container(&id) {
-                log::error!("Failed to stop container {}: {}", id, e);
+            if let Err(e) = self.stop_container(&id, None) {
+                log::error!("Failed to stop container {}: {}", id, e.message);
+            }
+            // Not stopped on purpose; `always` containers come back anyway
+            if let Some(c) = self.containers.write().unwrap().get_mut(&id) {
+                if c.restart_policy == RestartPolicy::UnlessStopped {
+                    c.next_restart_at = Some(current_timestamp());
+                }
             }
         }
 
@@ -339,262 +690,1191 @@ impl AgentState {
         log::info!("Graceful shutdown complete");
     }
 
-    /// Run health checks for all containers that have them configured
-    fn run_health_checks(&self) {
-        let containers = self.containers.read().unwrap();
+    /// Kill running containers that have exceeded their max runtime
+    fn enforce_deadlines(&self) {
+        let now = current_timestamp();
+        let mut containers = self.containers.write().unwrap();
+        let mut expired = false;
 
-        for (id, container) in containers.iter() {
-            if container.status != "Running" && container.status != "running" {
+        for c in containers.values_mut() {
+            let (Some(max_runtime), Some(started_at)) = (c.max_runtime_secs, c.started_at) else {
+                continue;
+            };
+            if c.status != ContainerStatus::Running || now.saturating_sub(started_at) < max_runtime
+            {
                 continue;
             }
 
-            // Check if container has health check configured
-            if let Some(health_check) = &container.health_check {
-                if health_check.command.is_empty() {
-                    continue;
-                }
-
-                // Check if enough time has passed since last check
-                let now = std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap_or_default()
-                    .as_secs();
-
-                let last_check = container.last_health_check.unwrap_or(0);
-                let interval = health_check.interval_secs.unwrap_or(30);
-
-                if now - last_check < interval {
-                    continue;
-                }
-
-                // Run health check
-                log::debug!("Running health check for container {}", id);
-                let result = self.execute_health_check(id, &health_check.command);
+            log::warn!(
+                "Container {} exceeded its max runtime of {}s, killing it",
+                c.id,
+                max_runtime
+            );
 
-                match result {
-                    Ok(healthy) => {
-                        if healthy {
-                            log::debug!("Container {} health check passed", id);
-                        } else {
-                            log::warn!("Container {} health check failed", id);
-                        }
-                    }
-                    Err(e) => {
-                        log::warn!("Container {} health check error: {}", id, e);
-                    }
-                }
-            }
+            self.signal_container(c, libc::SIGKILL);
+            c.killed(&self.lifecycle, libc::SIGKILL);
+            c.exit_reason = Some("timeout".to_string());
+            self.events.publish(events::with_reason(
+                EventProto {
+                    signal: Some(libc::SIGKILL),
+                    ..events::event(EventKind::Kill, &c.id)
+                },
+                "timeout",
+            ));
+            expired = true;
         }
-    }
 
-    /// Execute a health check command for a container
-    fn execute_health_check(
-        &self,
-        _container_id: &str,
-        command: &[String],
-    ) -> Result<bool, String> {
-        if command.is_empty() {
-            return Err("Empty health check command".to_string());
+        drop(containers);
+        if expired {
+            self.persist_state();
         }
+    }
 
-        let output = std::process::Command::new(&command[0])
-            .args(&command[1..])
-            .output()
-            .map_err(|e| format!("Failed to execute health check: {}", e))?;
+    /// Metrics for a container, served from the cache while they are fresh
+    fn container_metrics(&self, id: &str, pid: Option<u32>) -> ContainerMetricsProto {
+        self.metrics_cache
+            .get_or_collect(id, pid, || collect_container_metrics(id, pid))
+    }
 
-        Ok(output.status.success())
+    /// Record a metrics sample for every running container
+    fn sample_metrics(&self) {
+        let running: Vec<(String, u32)> = self
+            .containers
+            .read()
+            .unwrap()
+            .values()
+            .filter(|c| c.status == ContainerStatus::Running)
+            // Skip the fallback mode placeholder PID
+            .filter_map(|c| {
+                c.pid
+                    .filter(|&pid| pid != std::process::id())
+                    .map(|pid| (c.id.clone(), pid))
+            })
+            .collect();
+        let now = current_timestamp();
+        for (id, pid) in running {
+            let sample = history::sample(&self.container_metrics(&id, Some(pid)), now);
+            self.history.lock().unwrap().record(sample);
+        }
     }
 
-    /// Stop a container by ID
-    fn stop_container(&self, id: &str) -> Result<(), String> {
+    /// Sample CPU and network usage of containers with an auto-stop policy
+    /// and stop the ones that have been idle for long enough
+    ///
+    /// They are stopped like on request, each on its own thread so their
+    /// grace periods do not hold up the watchdog.
+    fn stop_idle_containers(self: &Arc<Self>) {
+        let now = current_timestamp();
         let mut containers = self.containers.write().unwrap();
-        if let Some(container) = containers.get_mut(id) {
-            if let Some(pid) = container.pid {
-                // Send SIGTERM first
-                unsafe {
-                    libc::kill(pid as libc::pid_t, libc::SIGTERM);
+        let mut idle = Vec::new();
+
+        for c in containers.values_mut() {
+            let Some(policy) = c.auto_stop.clone() else {
+                continue;
+            };
+            // Skip containers without a real PID (fallback mode placeholder)
+            let pid = match c.pid {
+                Some(pid) if c.status == ContainerStatus::Running && pid != std::process::id() => {
+                    pid
                 }
+                _ => continue,
+            };
 
-                // Wait briefly for graceful shutdown
-                std::thread::sleep(std::time::Duration::from_secs(2));
+            let metrics = collect_container_metrics(&c.id, Some(pid));
+            let sample = IdleSample {
+                at: std::time::Instant::now(),
+                cpu_usage_ns: metrics.cpu.usage_total,
+                net_bytes: metrics.network.rx_bytes + metrics.network.tx_bytes,
+                idle_since: None,
+            };
 
-                // Check if still running, send SIGKILL
-                if Self::is_process_running(pid) {
-                    log::warn!("Container {} did not stop gracefully, sending SIGKILL", id);
-                    unsafe {
-                        libc::kill(pid as libc::pid_t, libc::SIGKILL);
+            let idle_since = match c.idle_sample {
+                Some(prev) => {
+                    let elapsed_ns = sample.at.duration_since(prev.at).as_nanos().max(1) as f64;
+                    let cpu_delta = sample.cpu_usage_ns.saturating_sub(prev.cpu_usage_ns) as f64;
+                    let cpu_percent = cpu_delta / elapsed_ns * 100.0;
+                    if cpu_percent < policy.cpu_percent && sample.net_bytes == prev.net_bytes {
+                        Some(prev.idle_since.unwrap_or(now))
+                    } else {
+                        None
                     }
                 }
+                None => None,
+            };
+            c.idle_sample = Some(IdleSample {
+                idle_since,
+                ..sample
+            });
+
+            if idle_since.is_some_and(|since| now.saturating_sub(since) >= policy.idle_secs) {
+                log::info!(
+                    "Container {} idle for {}s, stopping it",
+                    c.id,
+                    policy.idle_secs
+                );
+                c.idle_sample = None;
+                idle.push(c.id.clone());
             }
-            container.status = "stopped".to_string();
-            container.pid = None;
-            Ok(())
-        } else {
-            Err(format!("Container {} not found", id))
         }
-    }
+        drop(containers);
 
-    #[cfg(target_os = "linux")]
-    fn build_oci_config_json(
-        rootfs: &str,
-        command: &[String],
-        env: &[String],
-        working_dir: &str,
-        container_id: &str,
-        stdio: &libcrun_shim_proto::StdioConfigProto,
-        network: &libcrun_shim_proto::NetworkConfigProto,
-        volumes: &[libcrun_shim_proto::VolumeMountProto],
-        resources: &libcrun_shim_proto::ResourceLimitsProto,
-    ) -> Result<String, String> {
-        // Ensure PATH is in env if not provided
-        let mut env_vec = env.to_vec();
-        let has_path = env_vec.iter().any(|e| e.starts_with("PATH="));
-        if !has_path {
-            env_vec.push(
-                "PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin".to_string(),
-            );
+        for id in idle {
+            let state = Arc::clone(self);
+            std::thread::spawn(move || {
+                if let Err(e) = state.stop_container(&id, None) {
+                    log::warn!("Failed to stop idle container {}: {}", id, e.message);
+                    return;
+                }
+                if let Some(c) = state.containers.write().unwrap().get_mut(&id) {
+                    c.exit_reason = Some("idle".to_string());
+                }
+                state.persist_state();
+                state.events.publish(events::with_reason(
+                    EventProto {
+                        signal: Some(libc::SIGTERM),
+                        ..events::event(EventKind::Stop, &id)
+                    },
+                    "idle",
+                ));
+            });
         }
+    }
 
-        // Build mounts array with default mounts + user volumes
-        let mut mounts = vec![
-            serde_json::json!({
-                "destination": "/proc",
-                "type": "proc",
-                "source": "proc"
-            }),
-            serde_json::json!({
-                "destination": "/dev",
-                "type": "tmpfs",
-                "source": "tmpfs",
-                "options": ["nosuid", "strictatime", "mode=755", "size=65536k"]
-            }),
-            serde_json::json!({
-                "destination": "/dev/pts",
-                "type": "devpts",
-                "source": "devpts",
-                "options": ["nosuid", "noexec", "newinstance", "ptmxmode=0666", "mode=0620"]
-            }),
-            serde_json::json!({
-                "destination": "/dev/shm",
-                "type": "tmpfs",
-                "source": "shm",
-                "options": ["nosuid", "noexec", "nodev", "mode=1777", "size=65536k"]
-            }),
-            serde_json::json!({
-                "destination": "/sys",
-                "type": "sysfs",
-                "source": "sysfs",
-                "options": ["nosuid", "noexec", "nodev", "ro"]
-            }),
-        ];
-
-        // Add user-defined volume mounts
-        for volume in volumes {
-            let mut mount = serde_json::json!({
-                "destination": volume.destination,
-                "type": "bind",
-                "source": volume.source,
-            });
+    /// Resolve the namespaces a container joins to `/proc/<pid>/ns` paths
+    fn shared_namespace_paths(
+        &self,
+        req: &CreateRequest,
+    ) -> Result<Vec<(&'static str, String)>, String> {
+        let Some(ref join) = req.join_namespaces else {
+            return Ok(vec![]);
+        };
 
-            if !volume.options.is_empty() {
-                mount["options"] = serde_json::json!(volume.options);
+        let containers = self.containers.read().unwrap();
+        let owner = containers
+            .get(&join.container)
+            .ok_or_else(|| format!("Container '{}' not found", join.container))?;
+        let pid = match owner.pid {
+            Some(pid) if owner.status == ContainerStatus::Running => pid,
+            _ => {
+                return Err(format!(
+                    "Container '{}' must be running to share its namespaces",
+                    join.container
+                ))
             }
+        };
 
-            mounts.push(mount);
+        let mut paths = Vec::new();
+        if join.network {
+            paths.push(("network", format!("/proc/{}/ns/net", pid)));
         }
-
-        // Build rlimits array with defaults + resource limits
-        let mut rlimits = vec![serde_json::json!({
-            "type": "RLIMIT_NOFILE",
-            "hard": 1024,
-            "soft": 1024
-        })];
-
-        // Add resource limits
-        if let Some(memory) = resources.memory {
-            if memory > 0 {
-                rlimits.push(serde_json::json!({
-                    "type": "RLIMIT_AS",
-                    "hard": memory,
-                    "soft": memory
-                }));
-            }
+        if join.ipc {
+            paths.push(("ipc", format!("/proc/{}/ns/ipc", pid)));
         }
+        Ok(paths)
+    }
 
-        if let Some(pids) = resources.pids {
-            if pids > 0 {
-                rlimits.push(serde_json::json!({
-                    "type": "RLIMIT_NPROC",
-                    "hard": pids,
-                    "soft": pids
-                }));
-            }
-        }
+    /// What containers left behind without a container being tracked for
+    /// them, removed with `remove`, in which case only those that were
+    /// removed are returned
+    ///
+    /// Containers whose process still runs are left out, as are those of
+    /// creates in progress.
+    fn stale_resources(&self, remove: bool) -> Vec<StaleResourceProto> {
+        #[cfg(target_os = "linux")]
+        {
+            let mut tracked: std::collections::HashSet<String> =
+                self.containers.read().unwrap().keys().cloned().collect();
+            tracked.extend(self.creating.lock().unwrap().iter().cloned());
+            let stale = |id: &String| {
+                !tracked.contains(id) && !crun::read_state(id).is_some_and(|state| state.running)
+            };
 
-        // Build resources object
-        let mut resources_obj = serde_json::json!({
-            "devices": [
-                {
-                    "allow": false,
-                    "access": "rwm"
+            let mut resources = Vec::new();
+            let mut found = |kind, id: &str, path: String| {
+                resources.push(StaleResourceProto {
+                    kind,
+                    id: id.to_string(),
+                    path,
+                })
+            };
+            for id in crun::state_ids().into_iter().filter(stale) {
+                // The cgroup goes first, the state directory is how it is found
+                for dir in crun::cgroup_dirs(&id) {
+                    found(StaleResourceKind::Cgroup, &id, dir.display().to_string());
                 }
-            ]
-        });
-
-        // Add CPU and memory limits
-        if resources.cpu.is_some() || resources.memory.is_some() {
-            let mut cpu_obj = serde_json::json!({});
-            if let Some(cpu) = resources.cpu {
-                if cpu > 0.0 {
-                    cpu_obj["shares"] = serde_json::json!((cpu * 1024.0) as u64);
-                    cpu_obj["quota"] = serde_json::json!((cpu * 100000.0) as i64);
-                    cpu_obj["period"] = serde_json::json!(100000);
+                if let Some(dir) = crun::state_dir(&id) {
+                    found(StaleResourceKind::State, &id, dir.display().to_string());
                 }
             }
-
-            let mut memory_obj = serde_json::json!({});
-            if let Some(memory) = resources.memory {
-                if memory > 0 {
-                    memory_obj["limit"] = serde_json::json!(memory);
+            for entry in std::fs::read_dir(SNAPSHOTS_DIR)
+                .into_iter()
+                .flatten()
+                .flatten()
+            {
+                if let Ok(id) = entry.file_name().into_string() {
+                    if stale(&id) {
+                        found(
+                            StaleResourceKind::Snapshot,
+                            &id,
+                            entry.path().display().to_string(),
+                        );
+                    }
                 }
             }
-            if let Some(memory_swap) = resources.memory_swap {
-                if memory_swap > 0 {
-                    memory_obj["swap"] = serde_json::json!(memory_swap);
-                }
+            // Named after an address, which tells nothing of the container
+            // once the address is free
+            for interface in self.bridge.stray_interfaces() {
+                found(StaleResourceKind::Interface, "", interface);
             }
 
-            if !cpu_obj.as_object().unwrap().is_empty() {
-                resources_obj["cpu"] = cpu_obj;
-            }
-            if !memory_obj.as_object().unwrap().is_empty() {
-                resources_obj["memory"] = memory_obj;
+            if remove {
+                resources.retain(|resource| {
+                    let removed = match resource.kind {
+                        StaleResourceKind::State => {
+                            crun::remove_state(&resource.id).map_err(|e| e.to_string())
+                        }
+                        StaleResourceKind::Cgroup => {
+                            std::fs::remove_dir(&resource.path).map_err(|e| e.to_string())
+                        }
+                        StaleResourceKind::Snapshot => {
+                            crun::remove_snapshot(Path::new(&resource.path))
+                                .map_err(|e| e.to_string())
+                        }
+                        StaleResourceKind::Interface => network::remove_interface(&resource.path),
+                    };
+                    match removed {
+                        Ok(()) => {
+                            log::info!("Removed stale {} {}", resource.kind, resource.path);
+                            true
+                        }
+                        Err(e) => {
+                            log::warn!(
+                                "Failed to remove {} {}: {}",
+                                resource.kind,
+                                resource.path,
+                                e
+                            );
+                            false
+                        }
+                    }
+                });
             }
+            resources
         }
 
-        // Determine network namespace based on network mode
-        let network_namespace = match network.mode.as_str() {
-            "host" => None, // No network namespace for host mode
-            "none" => Some(serde_json::json!({
-                "type": "network"
-            })),
-            _ => Some(serde_json::json!({
-                "type": "network"
-            })),
-        };
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = remove;
+            Vec::new()
+        }
+    }
 
-        let mut namespaces = vec![
-            serde_json::json!({"type": "pid"}),
-            serde_json::json!({"type": "ipc"}),
-            serde_json::json!({"type": "uts"}),
-            serde_json::json!({"type": "mount"}),
-        ];
+    /// Send a signal to a container's init process
+    fn signal_container(&self, c: &ContainerState, signal: i32) {
+        #[cfg(target_os = "linux")]
+        if let (true, Some(LibcrunContainer(container)), Some(LibcrunContext(ctx))) = (
+            self.libcrun_available,
+            &c.libcrun_container,
+            &self.libcrun_context,
+        ) {
+            if let Err(e) = crun::container_kill(*ctx, *container, &c.id, signal) {
+                log::warn!("Failed to signal container {}: {}", c.id, e.message);
+            }
+            return;
+        }
 
-        if let Some(ns) = network_namespace {
-            namespaces.push(ns);
+        if let Some(pid) = c.pid.filter(|pid| *pid != std::process::id()) {
+            unsafe {
+                libc::kill(pid as libc::pid_t, signal);
+            }
         }
+    }
 
-        let oci_config = serde_json::json!({
+    /// Run health checks that are due and record their outcome
+    fn run_health_checks(&self) {
+        let now = current_timestamp();
+        let due: Vec<(String, Vec<String>)> = self
+            .containers
+            .read()
+            .unwrap()
+            .values()
+            .filter(|c| c.status == ContainerStatus::Running)
+            .filter_map(|c| {
+                let health_check = c.health_check.as_ref()?;
+                let interval = health_check
+                    .interval_secs
+                    .unwrap_or(health::DEFAULT_INTERVAL_SECS);
+                let due = now.saturating_sub(c.last_health_check.unwrap_or(0)) >= interval;
+                (due && !health_check.command.is_empty())
+                    .then(|| (c.id.clone(), health_check.command.clone()))
+            })
+            .collect();
+        if due.is_empty() {
+            return;
+        }
+
+        // Probes can take a while, so run them without holding the lock
+        for (id, command) in due {
+            log::debug!("Running health check for container {}", id);
+            let (passed, output) = match self.execute_health_check(&id, &command) {
+                Ok(result) => result,
+                Err(e) => {
+                    log::warn!("Container {} health check error: {}", id, e);
+                    (false, e)
+                }
+            };
+
+            let mut containers = self.containers.write().unwrap();
+            let Some(container) = containers.get_mut(&id) else {
+                continue;
+            };
+            let Some(health_check) = &container.health_check else {
+                continue;
+            };
+            let in_start_period = match (health_check.start_period_secs, container.started_at) {
+                (Some(period), Some(started_at)) => now.saturating_sub(started_at) < period,
+                _ => false,
+            };
+            let (status, failures) = health::next_status(
+                &container.health_status,
+                container.consecutive_failures,
+                passed,
+                health_check.retries.unwrap_or(health::DEFAULT_RETRIES),
+                in_start_period,
+            );
+            if status != container.health_status {
+                log::info!(
+                    "Container {} is now {} ({} consecutive failures)",
+                    id,
+                    status,
+                    failures
+                );
+                match status {
+                    "healthy" => self.events.emit(EventKind::HealthOk, &id),
+                    "unhealthy" => self.events.emit(EventKind::HealthFail, &id),
+                    _ => {}
+                }
+            }
+            container.health_status = status.to_string();
+            container.consecutive_failures = failures;
+            container.last_health_check = Some(now);
+            container.last_output = health::truncate_output(output);
+        }
+        self.persist_state();
+    }
+
+    /// Run a health check command in a container
+    ///
+    /// Returns whether it passed and its combined output.
+    fn execute_health_check(
+        &self,
+        container_id: &str,
+        command: &[String],
+    ) -> Result<(bool, String), String> {
+        if command.is_empty() {
+            return Err("Empty health check command".to_string());
+        }
+
+        #[cfg(target_os = "linux")]
+        if let Some(LibcrunContext(ctx)) = &self.libcrun_context {
+            let process = crun::ExecProcess {
+                args: command.to_vec(),
+                ..Default::default()
+            };
+            let output = crun::container_exec(*ctx, container_id, &process)
+                .map_err(|e| format!("Failed to execute health check: {}", e.message))?;
+            let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+            text.push_str(&String::from_utf8_lossy(&output.stderr));
+            return Ok((output.exit_code == 0, text));
+        }
+
+        let output = std::process::Command::new(&command[0])
+            .args(&command[1..])
+            .output()
+            .map_err(|e| format!("Failed to execute health check: {}", e))?;
+        let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+        text.push_str(&String::from_utf8_lossy(&output.stderr));
+        Ok((output.status.success(), text))
+    }
+
+    /// Stop container `id` with SIGTERM, and SIGKILL if its process has not
+    /// exited after `timeout` seconds, or the container's own stop timeout
+    ///
+    /// The container is stopped at once, so the watchdog neither records the
+    /// exit nor restarts it; the containers are not locked while its process
+    /// has time to exit.
+    fn stop_container(&self, id: &str, timeout: Option<u64>) -> Result<(), ErrorProto> {
+        let mut containers = self.containers.write().unwrap();
+        let Some(c) = containers.get_mut(id) else {
+            return Err(ErrorProto {
+                code: ErrorCode::NotFound,
+                message: format!("Container '{}' not found", id),
+            });
+        };
+        if c.status == ContainerStatus::Stopped && c.next_restart_at.is_some() {
+            // Stopping a container waiting for its restart keeps it stopped
+            log::info!("Cancelling the restart of container {}", id);
+            c.next_restart_at = None;
+            drop(containers);
+            self.persist_state();
+            return Ok(());
+        }
+        if let Err(e) = self.lifecycle.check(id, c.status, Transition::Stop) {
+            return Err(ErrorProto {
+                code: ErrorCode::Conflict,
+                message: e.to_string(),
+            });
+        }
+
+        #[cfg(target_os = "linux")]
+        if let (true, Some(LibcrunContainer(container)), Some(LibcrunContext(ctx))) = (
+            self.libcrun_available,
+            &c.libcrun_container,
+            &self.libcrun_context,
+        ) {
+            // Frozen processes only see the signal once thawed
+            if c.status == ContainerStatus::Paused {
+                if let Err(e) = crun::container_resume(*ctx, id) {
+                    log::warn!(
+                        "Failed to unpause '{}' before stopping it: {}",
+                        id,
+                        e.message
+                    );
+                }
+            }
+            if let Err(e) = crun::container_kill(*ctx, *container, id, libc::SIGTERM) {
+                return Err(ErrorProto {
+                    code: ErrorCode::Internal,
+                    message: format!("libcrun failed to stop container: {}", e.message),
+                });
+            }
+        }
+        #[cfg(target_os = "linux")]
+        let signaled = self.libcrun_available
+            && c.libcrun_container.is_some()
+            && self.libcrun_context.is_some();
+        #[cfg(not(target_os = "linux"))]
+        let signaled = false;
+
+        // The placeholder PID of containers without libcrun is the agent's
+        let pid = c.pid.filter(|pid| *pid != std::process::id());
+        if let (Some(pid), false) = (pid, signaled) {
+            unsafe {
+                libc::kill(pid as libc::pid_t, libc::SIGTERM);
+            }
+        }
+        let timeout = std::time::Duration::from_secs(
+            timeout
+                .or(c.stop_timeout)
+                .unwrap_or(DEFAULT_STOP_TIMEOUT_SECS),
+        );
+        log::info!("Stopping container {} (timeout {:?})", id, timeout);
+        c.killed(&self.lifecycle, libc::SIGTERM);
+        drop(containers);
+        self.persist_state();
+
+        let Some(pid) = pid else {
+            return Ok(());
+        };
+        let exited = await_exit(pid, timeout);
+        let outcome = match exited {
+            Some(status) => status.map(|status| exit_outcome(status, false)),
+            None => {
+                log::warn!(
+                    "Container {} did not stop within {:?}, sending SIGKILL",
+                    id,
+                    timeout
+                );
+                unsafe {
+                    libc::kill(pid as libc::pid_t, libc::SIGKILL);
+                }
+                // Reaped, so it does not linger as a zombie
+                let _ = await_exit(pid, std::time::Duration::from_secs(1));
+                Some((128 + libc::SIGKILL, ExitReason::Signal))
+            }
+        };
+        if let Some((code, reason)) = outcome {
+            if let Some(c) = self.containers.write().unwrap().get_mut(id) {
+                c.last_exit_code = Some(code);
+                c.last_exit_reason = Some(reason);
+            }
+            self.persist_state();
+        }
+        Ok(())
+    }
+
+    /// Mark containers whose process is gone as stopped, with how it exited,
+    /// and schedule their restarts
+    ///
+    /// Processes the OOM killer killed in running containers are counted,
+    /// and published as `Oom` events, whether the container exits or not.
+    fn record_exits(&self) {
+        let mut containers = self.containers.write().unwrap();
+        for (id, container) in containers.iter_mut() {
+            let Some(pid) = container.pid else {
+                continue;
+            };
+            // A paused container can still be killed, e.g. by the OOM killer
+            if !matches!(
+                container.status,
+                ContainerStatus::Running | ContainerStatus::Paused
+            ) {
+                continue;
+            }
+            // Looked up first, the process is gone once it is reaped
+            #[cfg(target_os = "linux")]
+            let oom_kills = CgroupPaths::find(pid).map_or(0, |cgroup| oom_kill_count(&cgroup));
+            #[cfg(not(target_os = "linux"))]
+            let oom_kills = 0;
+            if oom_kills > container.oom_kills {
+                log::warn!(
+                    "The OOM killer killed {} process(es) of container {}",
+                    oom_kills - container.oom_kills,
+                    id
+                );
+                container.oom_kills = oom_kills;
+                let mut event = events::event(EventKind::Oom, id);
+                event
+                    .attributes
+                    .insert("oom_kills".to_string(), oom_kills.to_string());
+                self.events.publish(event);
+            }
+            let Some(status) = process_exit(pid) else {
+                continue;
+            };
+
+            let outcome = status.map(|status| exit_outcome(status, oom_kills > 0));
+            log::warn!(
+                "Container {} (PID {}) is no longer running - marking as stopped ({:?})",
+                id,
+                pid,
+                outcome
+            );
+            // Their process is gone, so they are stopped. Only running and
+            // paused containers get here, which can always exit
+            let _ = self
+                .lifecycle
+                .apply(id, &mut container.status, Transition::Exit);
+            container.pid = None;
+            container.last_exit_code = outcome.map(|(code, _)| code);
+            container.last_exit_reason = outcome.map(|(_, reason)| reason);
+            self.events.publish(EventProto {
+                exit_code: container.last_exit_code,
+                ..events::event(EventKind::Die, id)
+            });
+
+            let now = current_timestamp();
+            // A run that lasted a while starts a new series of restarts
+            if container
+                .started_at
+                .is_some_and(|at| now.saturating_sub(at) >= RESTART_RESET_AFTER.as_secs())
+            {
+                container.restart_retries = 0;
+            }
+            container.schedule_restart(now);
+            if let Some(at) = container.next_restart_at {
+                log::info!(
+                    "Restarting container {} in {}s ({} policy)",
+                    id,
+                    at - now,
+                    container.restart_policy
+                );
+            }
+        }
+    }
+
+    /// Run the exited containers whose restart delay is over
+    fn restart_exited(&self) {
+        let now = current_timestamp();
+        let mut containers = self.containers.write().unwrap();
+        let mut restarted = false;
+
+        for c in containers.values_mut() {
+            if c.status != ContainerStatus::Stopped || c.next_restart_at.is_none_or(|at| at > now) {
+                continue;
+            }
+            restarted = true;
+            c.next_restart_at = None;
+            c.restart_retries += 1;
+            match self.rerun_container(c) {
+                Ok(pid) => {
+                    log::info!(
+                        "Restarted container {} ({} in a row)",
+                        c.id,
+                        c.restart_retries
+                    );
+                    let _ = self
+                        .lifecycle
+                        .apply(&c.id, &mut c.status, Transition::Restart);
+                    c.pid = Some(pid);
+                    c.restart_count += 1;
+                    c.started_at = Some(now);
+                    c.oom_kills = 0;
+                    self.events.publish(events::with_reason(
+                        events::event(EventKind::Start, &c.id),
+                        "restart",
+                    ));
+                }
+                Err(e) => {
+                    log::warn!("Failed to restart container {}: {}", c.id, e);
+                    c.schedule_restart(now);
+                }
+            }
+        }
+
+        drop(containers);
+        if restarted {
+            self.persist_state();
+        }
+    }
+
+    /// Run an exited container again from the OCI config it was created
+    /// with, returning its PID
+    #[cfg(target_os = "linux")]
+    fn rerun_container(&self, c: &mut ContainerState) -> Result<u32, String> {
+        let Some(LibcrunContext(ctx)) = &self.libcrun_context else {
+            return Err("restarts need libcrun".to_string());
+        };
+        let config = std::fs::read_to_string(oci_config_path(&c.id))
+            .map_err(|e| format!("failed to read its OCI config: {}", e))?;
+        let container = match c.libcrun_container {
+            Some(LibcrunContainer(container)) => container,
+            // Recovered containers are loaded again
+            None => crun::container_load_from_memory(&config).map_err(|e| e.message)?,
+        };
+        c.libcrun_container = Some(LibcrunContainer(container));
+
+        // The exited instance still holds the ID
+        if let Err(e) = crun::container_delete(*ctx, container, &c.id) {
+            log::debug!("Failed to delete exited container {}: {}", c.id, e.message);
+        }
+        let tty = serde_json::from_str::<serde_json::Value>(&config)
+            .is_ok_and(|oci| oci["process"]["terminal"] == true);
+        let capture = if tty {
+            None
+        } else {
+            records::StdioCapture::start(&PathBuf::from(CONTAINER_LOG_DIR).join(&c.id)).ok()
+        };
+        let created = crun::container_create(*ctx, container, &c.id);
+        drop(capture);
+        created.map_err(|e| e.message)?;
+        crun::container_start(*ctx, container, &c.id).map_err(|e| e.message)?;
+
+        Ok(crun::get_container_pid(&c.id).unwrap_or_else(|| {
+            log::warn!(
+                "Could not retrieve PID for container '{}' from libcrun state, using placeholder",
+                c.id
+            );
+            std::process::id()
+        }))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn rerun_container(&self, _c: &mut ContainerState) -> Result<u32, String> {
+        Err("restarts need libcrun".to_string())
+    }
+
+    /// Freeze the processes of a running container, or thaw a paused one
+    fn set_paused(&self, id: &str, pause: bool) -> Response {
+        let (verb, transition) = if pause {
+            ("pause", Transition::Pause)
+        } else {
+            ("unpause", Transition::Unpause)
+        };
+        let mut containers = self.containers.write().unwrap();
+        match containers.get(id) {
+            None => return failed(ErrorCode::NotFound, format!("Container '{}' not found", id)),
+            Some(c) => {
+                if let Err(e) = self.lifecycle.check(id, c.status, transition) {
+                    return failed(ErrorCode::Conflict, e.to_string());
+                }
+            }
+        }
+
+        #[cfg(target_os = "linux")]
+        {
+            let Some(LibcrunContext(ctx)) = &self.libcrun_context else {
+                return failed(ErrorCode::Unavailable, "Pausing containers needs libcrun");
+            };
+            let result = if pause {
+                crun::container_pause(*ctx, id)
+            } else {
+                crun::container_resume(*ctx, id)
+            };
+            if let Err(e) = result {
+                return failed(
+                    ErrorCode::Internal,
+                    format!("Failed to {} container '{}': {}", verb, id, e.message),
+                );
+            }
+            let Some(c) = containers.get_mut(id) else {
+                return failed(ErrorCode::NotFound, format!("Container '{}' not found", id));
+            };
+            let _ = self.lifecycle.apply(id, &mut c.status, transition);
+            let to = c.status;
+            drop(containers);
+            log::info!("Container '{}' is now {}", id, to);
+            self.persist_state();
+            if pause {
+                Response::Paused
+            } else {
+                Response::Unpaused
+            }
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = (verb, containers);
+            failed(ErrorCode::Unavailable, "Pausing containers needs libcrun")
+        }
+    }
+
+    /// Checkpoint a running container under a name
+    fn create_checkpoint(&self, req: CheckpointRequest) -> Response {
+        if let Some(reason) = invalid_container_id(&req.id) {
+            return failed(ErrorCode::InvalidArgument, reason);
+        }
+        if let Some(reason) = checkpoints::invalid_name(&req.name) {
+            return failed(ErrorCode::InvalidArgument, reason);
+        }
+        match self.containers.read().unwrap().get(&req.id) {
+            None => {
+                return failed(
+                    ErrorCode::NotFound,
+                    format!("Container '{}' not found", req.id),
+                )
+            }
+            Some(c) if c.status != ContainerStatus::Running => {
+                return failed(
+                    ErrorCode::Conflict,
+                    format!("Container '{}' is not running", req.id),
+                )
+            }
+            Some(_) => {}
+        }
+        let store = checkpoints::CheckpointStore::new(checkpoints::CHECKPOINTS_DIR);
+        if store.get(&req.id, &req.name).is_some() {
+            return failed(
+                ErrorCode::Conflict,
+                format!("Checkpoint '{}' of '{}' already exists", req.name, req.id),
+            );
+        }
+        let Ok(config) = std::fs::read_to_string(oci_config_path(&req.id)) else {
+            return failed(
+                ErrorCode::Unavailable,
+                format!("Container '{}' was not created through libcrun", req.id),
+            );
+        };
+
+        #[cfg(target_os = "linux")]
+        {
+            let Some(LibcrunContext(ctx)) = &self.libcrun_context else {
+                return failed(ErrorCode::Unavailable, "Checkpoints need libcrun");
+            };
+            let images = match store.prepare(&req.id, &req.name) {
+                Ok(images) => images,
+                Err(e) => return failed(ErrorCode::Internal, e),
+            };
+            let options = crun::CheckpointOptions {
+                image_path: images,
+                leave_running: req.leave_running,
+                ..Default::default()
+            };
+            progress::report("dump", None, "Dumping the container's processes");
+            let checkpoint = crun::container_checkpoint(*ctx, &req.id, &options)
+                .map_err(|e| e.message)
+                .and_then(|_| {
+                    progress::report("record", None, "Recording the checkpoint image");
+                    store.finish(&req.id, &req.name, &config, current_timestamp())
+                });
+            let checkpoint = match checkpoint {
+                Ok(checkpoint) => checkpoint,
+                Err(e) => {
+                    let _ = store.remove(&req.id, &req.name);
+                    return failed(
+                        ErrorCode::Internal,
+                        format!("Failed to checkpoint container '{}': {}", req.id, e),
+                    );
+                }
+            };
+            log::info!("Checkpointed container '{}' as '{}'", req.id, req.name);
+
+            if !req.leave_running {
+                if let Some(c) = self.containers.write().unwrap().get_mut(&req.id) {
+                    // It may have exited while it was being dumped
+                    if self
+                        .lifecycle
+                        .apply(&req.id, &mut c.status, Transition::Stop)
+                        .is_ok()
+                    {
+                        c.pid = None;
+                        c.exit_reason = Some("checkpoint".to_string());
+                    }
+                }
+                self.persist_state();
+            }
+            Response::Checkpoint(checkpoint)
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = config;
+            failed(ErrorCode::Unavailable, "Checkpoints need libcrun")
+        }
+    }
+
+    /// Restore a checkpoint as a new, running container
+    fn restore_checkpoint(&self, req: RestoreRequest) -> Response {
+        if let Some(reason) = invalid_container_id(&req.id) {
+            return failed(ErrorCode::InvalidArgument, reason);
+        }
+        {
+            let containers = self.containers.read().unwrap();
+            if containers.contains_key(&req.id) || name_taken(&containers, &req.id, &req.id) {
+                return failed(
+                    ErrorCode::Conflict,
+                    format!("Container '{}' already exists", req.id),
+                );
+            }
+        }
+        let CheckpointRef { container, name } = &req.checkpoint;
+        if let Some(reason) = invalid_checkpoint_ref(container, name) {
+            return failed(ErrorCode::InvalidArgument, reason);
+        }
+        let store = checkpoints::CheckpointStore::new(checkpoints::CHECKPOINTS_DIR);
+        let Some(checkpoint) = store.get(container, name) else {
+            return failed(
+                ErrorCode::NotFound,
+                format!("Checkpoint '{}' of '{}' not found", name, container),
+            );
+        };
+        progress::report("verify", None, "Verifying the checkpoint image");
+        let config = match store.verify(&checkpoint) {
+            Ok(config) => config,
+            Err(e) => return failed(ErrorCode::Internal, e),
+        };
+        let oci: serde_json::Value = match serde_json::from_str(&config) {
+            Ok(oci) => oci,
+            Err(e) => {
+                return failed(
+                    ErrorCode::Internal,
+                    format!("Invalid checkpoint config: {}", e),
+                )
+            }
+        };
+
+        #[cfg(target_os = "linux")]
+        {
+            let Some(LibcrunContext(ctx)) = &self.libcrun_context else {
+                return failed(ErrorCode::Unavailable, "Checkpoints need libcrun");
+            };
+            let container_ptr = match crun::container_load_from_memory(&config) {
+                Ok(container) => container,
+                Err(e) => return failed(ErrorCode::Internal, e.message),
+            };
+            let options = crun::CheckpointOptions {
+                image_path: store.images_dir(container, name),
+                ..Default::default()
+            };
+            progress::report("restore", None, "Restoring the container's processes");
+            if let Err(e) =
+                crun::container_restore(*ctx, &req.id, &store.dir(container, name), &options)
+            {
+                crun::container_free(container_ptr);
+                return failed(
+                    ErrorCode::Internal,
+                    format!("Failed to restore container '{}': {}", req.id, e.message),
+                );
+            }
+            save_oci_config(&req.id, &config);
+            log::info!(
+                "Restored checkpoint '{}' of '{}' as container '{}'",
+                name,
+                container,
+                req.id
+            );
+
+            let strings = |value: &serde_json::Value| -> Vec<String> {
+                serde_json::from_value(value.clone()).unwrap_or_default()
+            };
+            let now = current_timestamp();
+            let restored = ContainerState {
+                id: req.id.clone(),
+                name: req.id.clone(),
+                rootfs: oci["root"]["path"].as_str().unwrap_or_default().to_string(),
+                command: strings(&oci["process"]["args"]),
+                env: strings(&oci["process"]["env"]),
+                working_dir: oci["process"]["cwd"].as_str().unwrap_or("/").to_string(),
+                status: ContainerStatus::Running,
+                pid: crun::get_container_pid(&req.id),
+                created_at: now,
+                health_check: None,
+                last_health_check: None,
+                health_status: "starting".to_string(),
+                consecutive_failures: 0,
+                last_output: String::new(),
+                max_runtime_secs: None,
+                stop_timeout: None,
+                started_at: Some(now),
+                exit_reason: None,
+                restart_count: 0,
+                last_exit_code: None,
+                last_exit_reason: None,
+                oom_kills: 0,
+                restart_policy: RestartPolicy::No,
+                restart_retries: 0,
+                next_restart_at: None,
+                ip_address: None,
+                labels: BTreeMap::new(),
+                auto_stop: None,
+                idle_sample: None,
+                execs: Vec::new(),
+                execs_in_flight: 0,
+                orphaned: false,
+                libcrun_container: Some(LibcrunContainer(container_ptr)),
+            };
+            self.containers
+                .write()
+                .unwrap()
+                .insert(req.id.clone(), restored);
+            self.persist_state();
+            Response::Created(req.id)
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = oci;
+            failed(ErrorCode::Unavailable, "Checkpoints need libcrun")
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn build_oci_config_json(
+        rootfs: &str,
+        command: &[String],
+        env: &[String],
+        working_dir: &str,
+        container_id: &str,
+        stdio: &libcrun_shim_proto::StdioConfigProto,
+        network: &libcrun_shim_proto::NetworkConfigProto,
+        volumes: &[libcrun_shim_proto::VolumeMountProto],
+        resources: &libcrun_shim_proto::ResourceLimitsProto,
+        security: &libcrun_shim_proto::SecurityProto,
+        shared_namespaces: &[(&str, String)],
+        read_only_rootfs: bool,
+    ) -> Result<String, String> {
+        // Ensure PATH is in env if not provided
+        let mut env_vec = env.to_vec();
+        let has_path = env_vec.iter().any(|e| e.starts_with("PATH="));
+        if !has_path {
+            env_vec.push(
+                "PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin".to_string(),
+            );
+        }
+
+        // Build mounts array with default mounts + user volumes
+        let mut mounts = vec![
+            serde_json::json!({
+                "destination": "/proc",
+                "type": "proc",
+                "source": "proc"
+            }),
+            serde_json::json!({
+                "destination": "/dev",
+                "type": "tmpfs",
+                "source": "tmpfs",
+                "options": ["nosuid", "strictatime", "mode=755", "size=65536k"]
+            }),
+            serde_json::json!({
+                "destination": "/dev/pts",
+                "type": "devpts",
+                "source": "devpts",
+                "options": ["nosuid", "noexec", "newinstance", "ptmxmode=0666", "mode=0620"]
+            }),
+            serde_json::json!({
+                "destination": "/dev/shm",
+                "type": "tmpfs",
+                "source": "shm",
+                "options": ["nosuid", "noexec", "nodev", "mode=1777", "size=65536k"]
+            }),
+            serde_json::json!({
+                "destination": "/sys",
+                "type": "sysfs",
+                "source": "sysfs",
+                "options": ["nosuid", "noexec", "nodev", "ro"]
+            }),
+        ];
+
+        // Add user-defined volume mounts
+        for volume in volumes {
+            let mut mount = serde_json::json!({
+                "destination": volume.destination,
+                "type": "bind",
+                "source": volume.source,
+            });
+
+            let options = libcrun_shim_proto::volume_mount_options(
+                &volume.options,
+                volume.propagation,
+                volume.recursive_readonly,
+            );
+            if !options.is_empty() {
+                mount["options"] = serde_json::json!(options);
+            }
+
+            mounts.push(mount);
+        }
+
+        // Build rlimits array with defaults. Resource limits go to the
+        // cgroup below, rlimits count per process (or per user for
+        // RLIMIT_NPROC) rather than for the container as a whole.
+        let mut rlimits = vec![serde_json::json!({
+            "type": "RLIMIT_NOFILE",
+            "hard": 1024,
+            "soft": 1024
+        })];
+
+        // Profile or container rlimits replace the defaults of the same type
+        for rlimit in &security.rlimits {
+            rlimits.retain(|r| r["type"] != rlimit.rlimit_type.as_str());
+            rlimits.push(serde_json::json!({
+                "type": rlimit.rlimit_type,
+                "hard": rlimit.hard,
+                "soft": rlimit.soft
+            }));
+        }
+
+        let capabilities: Vec<String> = match &security.capabilities {
+            Some(caps) => caps.iter().map(|c| normalize_capability(c)).collect(),
+            None => DEFAULT_CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+        };
+
+        // Build resources object
+        let mut resources_obj = serde_json::json!({
+            "devices": [
+                {
+                    "allow": false,
+                    "access": "rwm"
+                }
+            ]
+        });
+
+        // Add CPU and memory limits
+        if resources.cpu.is_some()
+            || resources.memory.is_some()
+            || resources.memory_swap.is_some()
+            || resources.memory_swappiness.is_some()
+            || resources.cpuset_cpus.is_some()
+            || resources.cpuset_mems.is_some()
+        {
+            let mut cpu_obj = serde_json::json!({});
+            if let Some(cpu) = resources.cpu {
+                if cpu > 0.0 {
+                    cpu_obj["shares"] = serde_json::json!((cpu * 1024.0) as u64);
+                    cpu_obj["quota"] = serde_json::json!((cpu * CPU_PERIOD as f64) as i64);
+                    cpu_obj["period"] = serde_json::json!(CPU_PERIOD);
+                }
+            }
+            if let Some(ref cpus) = resources.cpuset_cpus {
+                cpu_obj["cpus"] = serde_json::json!(cpus);
+            }
+            if let Some(ref mems) = resources.cpuset_mems {
+                cpu_obj["mems"] = serde_json::json!(mems);
+            }
+
+            let mut memory_obj = serde_json::json!({});
+            if let Some(memory) = resources.memory {
+                if memory > 0 {
+                    memory_obj["limit"] = serde_json::json!(memory);
+                }
+            }
+
+            // The OCI `swap` key is memory+swap, which only matches cgroup v1.
+            // On v2 the swap limit is its own knob, so set it directly.
+            let cgroup_v2 = is_cgroup_v2();
+            match swap_limit(resources.memory, resources.memory_swap) {
+                Some(-1) if cgroup_v2 => {
+                    resources_obj["unified"] = serde_json::json!({ "memory.swap.max": "max" });
+                }
+                Some(total) if cgroup_v2 => {
+                    let swap_only = total - resources.memory.unwrap_or(0) as i64;
+                    resources_obj["unified"] =
+                        serde_json::json!({ "memory.swap.max": swap_only.max(0).to_string() });
+                }
+                Some(total) => {
+                    memory_obj["swap"] = serde_json::json!(total);
+                }
+                None => {}
+            }
+            if let Some(swappiness) = resources.memory_swappiness {
+                if cgroup_v2 {
+                    log::warn!("memory swappiness is not supported on cgroup v2, ignoring");
+                } else {
+                    memory_obj["swappiness"] = serde_json::json!(swappiness);
+                }
+            }
+
+            if !cpu_obj.as_object().unwrap().is_empty() {
+                resources_obj["cpu"] = cpu_obj;
+            }
+            if !memory_obj.as_object().unwrap().is_empty() {
+                resources_obj["memory"] = memory_obj;
+            }
+        }
+
+        if !resources.hugepage_limits.is_empty() {
+            let limits: Vec<_> = resources
+                .hugepage_limits
+                .iter()
+                .map(|h| serde_json::json!({ "pageSize": h.page_size, "limit": h.limit }))
+                .collect();
+            resources_obj["hugepageLimits"] = serde_json::json!(limits);
+        }
+
+        if let Some(pids) = resources.pids.filter(|p| *p > 0) {
+            resources_obj["pids"] = serde_json::json!({ "limit": pids });
+        }
+        if let Some(weight) = resources.blkio_weight {
+            // On v2 crun writes blockIO.weight to io.bfq.weight when the BFQ
+            // scheduler has it, set io.weight itself so the weight holds
+            // whatever the scheduler
+            if is_cgroup_v2() {
+                resources_obj["unified"]["io.weight"] =
+                    serde_json::json!(io_weight(weight).to_string());
+            } else {
+                resources_obj["blockIO"] = serde_json::json!({ "weight": weight });
+            }
+        }
+
+        // Determine network namespace based on network mode
+        let network_namespace = match network.mode.as_str() {
+            "host" => None, // No network namespace for host mode
+            "none" => Some(serde_json::json!({
+                "type": "network"
+            })),
+            _ => Some(serde_json::json!({
+                "type": "network"
+            })),
+        };
+
+        let mut namespaces = vec![
+            serde_json::json!({"type": "pid"}),
+            serde_json::json!({"type": "ipc"}),
+            serde_json::json!({"type": "uts"}),
+            serde_json::json!({"type": "mount"}),
+        ];
+
+        if let Some(ns) = network_namespace {
+            namespaces.push(ns);
+        }
+
+        // Namespaces joined from another container (pods)
+        for (ns_type, path) in shared_namespaces {
+            namespaces.retain(|ns| ns["type"] != *ns_type);
+            namespaces.push(serde_json::json!({ "type": ns_type, "path": path }));
+        }
+
+        let mut oci_config = serde_json::json!({
             "ociVersion": "1.0.0",
             "process": {
                 "terminal": stdio.tty,
@@ -606,38 +1886,18 @@ impl AgentState {
                 "env": env_vec,
                 "cwd": working_dir,
                 "capabilities": {
-                    "bounding": [
-                        "CAP_AUDIT_WRITE",
-                        "CAP_KILL",
-                        "CAP_NET_BIND_SERVICE"
-                    ],
-                    "effective": [
-                        "CAP_AUDIT_WRITE",
-                        "CAP_KILL",
-                        "CAP_NET_BIND_SERVICE"
-                    ],
-                    "inheritable": [
-                        "CAP_AUDIT_WRITE",
-                        "CAP_KILL",
-                        "CAP_NET_BIND_SERVICE"
-                    ],
-                    "permitted": [
-                        "CAP_AUDIT_WRITE",
-                        "CAP_KILL",
-                        "CAP_NET_BIND_SERVICE"
-                    ],
-                    "ambient": [
-                        "CAP_AUDIT_WRITE",
-                        "CAP_KILL",
-                        "CAP_NET_BIND_SERVICE"
-                    ]
+                    "bounding": capabilities,
+                    "effective": capabilities,
+                    "inheritable": capabilities,
+                    "permitted": capabilities,
+                    "ambient": capabilities
                 },
                 "rlimits": rlimits,
                 "noNewPrivileges": true
             },
             "root": {
                 "path": rootfs,
-                "readonly": false
+                "readonly": read_only_rootfs
             },
             "hostname": container_id,
             "mounts": mounts,
@@ -664,489 +1924,1759 @@ impl AgentState {
             }
         });
 
-        serde_json::to_string_pretty(&oci_config).map_err(|e| e.to_string())
+        if let Some(ref seccomp) = security.seccomp {
+            oci_config["linux"]["seccomp"] = serde_json::from_str(seccomp)
+                .map_err(|e| format!("Invalid seccomp profile: {}", e))?;
+        }
+
+        let mut hooks = Vec::new();
+        let mut container_hooks = Vec::new();
+        for interface in &network.interfaces {
+            match network::veth_hooks(interface) {
+                Some((create, configure)) if interface.interface_type == "bridge" => {
+                    hooks.push(create);
+                    container_hooks.push(configure);
+                }
+                _ => log::warn!(
+                    "Ignoring {} interface {}",
+                    interface.interface_type,
+                    interface.name
+                ),
+            }
+        }
+        if !hooks.is_empty() {
+            oci_config["hooks"]["createRuntime"] = serde_json::json!(hooks);
+        }
+        if !network.allow_egress.is_empty() || !network.deny_egress.is_empty() {
+            container_hooks.push(serde_json::json!(egress::hook(
+                &network.allow_egress,
+                &network.deny_egress
+            )));
+        }
+        if !container_hooks.is_empty() {
+            oci_config["hooks"]["createContainer"] = serde_json::json!(container_hooks);
+        }
+
+        serde_json::to_string_pretty(&oci_config).map_err(|e| e.to_string())
+    }
+}
+
+impl Drop for AgentState {
+    fn drop(&mut self) {
+        #[cfg(target_os = "linux")]
+        {
+            // Clean up libcrun context
+            if let Some(LibcrunContext(ctx)) = self.libcrun_context.take() {
+                crun::context_free(ctx);
+            }
+
+            // Clean up containers
+            let containers = self.containers.write().unwrap();
+            for (_, state) in containers.iter() {
+                #[cfg(target_os = "linux")]
+                if let Some(LibcrunContainer(container)) = state.libcrun_container {
+                    crun::container_free(container);
+                }
+            }
+        }
+    }
+}
+
+/// Create a vsock listener socket
+#[cfg(target_os = "linux")]
+fn create_vsock_listener(port: u32) -> std::io::Result<RawFd> {
+    use std::mem;
+
+    eprintln!("[AGENT] Creating vsock socket (AF_VSOCK={})...", AF_VSOCK);
+
+    // Create vsock socket
+    let fd = unsafe { libc::socket(AF_VSOCK, libc::SOCK_STREAM, 0) };
+    if fd < 0 {
+        let err = std::io::Error::last_os_error();
+        eprintln!("[AGENT] ERROR: socket() failed: {}", err);
+        return Err(err);
+    }
+    eprintln!("[AGENT] Socket created, fd={}", fd);
+
+    // Bind to the port
+    #[repr(C)]
+    struct SockaddrVm {
+        svm_family: libc::sa_family_t,
+        svm_reserved1: u16,
+        svm_port: u32,
+        svm_cid: u32,
+        svm_zero: [u8; 4],
+    }
+
+    // For listening, we bind to VMADDR_CID_ANY (any CID can connect)
+    // But for Apple Virtualization Framework, connections come from CID 2 (host)
+    let addr = SockaddrVm {
+        svm_family: AF_VSOCK as libc::sa_family_t,
+        svm_reserved1: 0,
+        svm_port: port,
+        svm_cid: VMADDR_CID_ANY,  // Listen on any CID (host connects from CID 2)
+        svm_zero: [0; 4],
+    };
+    eprintln!("[AGENT] Binding to CID={} (VMADDR_CID_ANY), port={}", VMADDR_CID_ANY, port);
+
+    let ret = unsafe {
+        libc::bind(
+            fd,
+            &addr as *const SockaddrVm as *const libc::sockaddr,
+            mem::size_of::<SockaddrVm>() as libc::socklen_t,
+        )
+    };
+
+    eprintln!("[AGENT] Binding to port {}...", port);
+    if ret < 0 {
+        let err = std::io::Error::last_os_error();
+        eprintln!("[AGENT] ERROR: bind() failed: {}", err);
+        unsafe { libc::close(fd) };
+        return Err(err);
+    }
+    eprintln!("[AGENT] Bind successful");
+
+    // Listen for connections
+    eprintln!("[AGENT] Calling listen()...");
+    let ret = unsafe { libc::listen(fd, 5) };
+    if ret < 0 {
+        let err = std::io::Error::last_os_error();
+        eprintln!("[AGENT] ERROR: listen() failed: {}", err);
+        unsafe { libc::close(fd) };
+        return Err(err);
+    }
+    eprintln!("[AGENT] Listen successful");
+
+    // Set non-blocking
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 {
+        unsafe { libc::close(fd) };
+        return Err(std::io::Error::last_os_error());
+    }
+    let ret = unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) };
+    if ret < 0 {
+        unsafe { libc::close(fd) };
+        return Err(std::io::Error::last_os_error());
+    }
+
+    eprintln!("[AGENT] Vsock listener fully initialized, fd={}, port={}", fd, port);
+    Ok(fd)
+}
+
+/// Accept a connection from vsock
+#[cfg(target_os = "linux")]
+fn accept_vsock(fd: RawFd) -> Option<std::net::TcpStream> {
+    let client_fd = unsafe { libc::accept(fd, std::ptr::null_mut(), std::ptr::null_mut()) };
+    if client_fd < 0 {
+        let err = std::io::Error::last_os_error();
+        if err.kind() != std::io::ErrorKind::WouldBlock {
+            log::debug!("Vsock accept error: {}", err);
+        }
+        return None;
+    }
+
+    // Wrap the fd in a TcpStream for convenience (it's not actually TCP, but it has the same interface)
+    // This is a bit of a hack, but it works for our purposes
+    Some(unsafe { std::net::TcpStream::from_raw_fd(client_fd) })
+}
+
+/// Agent configuration
+struct AgentConfig {
+    socket_path: String,
+    vsock_port: u32,
+    vsock_enabled: bool,
+    /// Already-listening Unix socket inherited from a previous agent (upgrade handoff)
+    inherited_unix_fd: Option<i32>,
+    /// Already-listening vsock socket inherited from a previous agent (upgrade handoff)
+    inherited_vsock_fd: Option<i32>,
+    /// Admission policy file (JSON)
+    policy_path: Option<String>,
+    /// How long container metrics are cached
+    metrics_ttl: std::time::Duration,
+    /// Time between scheduled filesystem trims (zero disables them)
+    trim_interval: std::time::Duration,
+    /// Time between metrics history samples (zero disables them)
+    sample_interval: std::time::Duration,
+    /// Subnet of the bridge network
+    bridge_subnet: network::Subnet,
+    /// Locked while the agent runs, so a second agent exits
+    pid_file: String,
+    /// Take over from the agent holding the pid file
+    replace: bool,
+}
+
+impl Default for AgentConfig {
+    fn default() -> Self {
+        Self {
+            socket_path: "/tmp/libcrun-shim.sock".to_string(),
+            vsock_port: 1234,
+            vsock_enabled: false,
+            inherited_unix_fd: None,
+            inherited_vsock_fd: None,
+            policy_path: None,
+            metrics_ttl: DEFAULT_METRICS_TTL,
+            trim_interval: disks::DEFAULT_TRIM_INTERVAL,
+            sample_interval: history::DEFAULT_SAMPLE_INTERVAL,
+            bridge_subnet: network::Subnet::default(),
+            pid_file: instance::DEFAULT_PID_FILE.to_string(),
+            replace: false,
+        }
+    }
+}
+
+fn parse_args() -> AgentConfig {
+    let args: Vec<String> = std::env::args().collect();
+    let mut config = AgentConfig::default();
+    let mut i = 1;
+
+    while i < args.len() {
+        match args[i].as_str() {
+            "--version" | "-V" => {
+                println!("libcrun-shim-agent {}", env!("CARGO_PKG_VERSION"));
+                std::process::exit(0);
+            }
+            // Internal: started by the agent for each container it captures
+            #[cfg(target_os = "linux")]
+            "--container-logger" => {
+                let Some(dir) = args.get(i + 1) else {
+                    std::process::exit(2);
+                };
+                if let Err(e) = records::run_logger(Path::new(dir)) {
+                    eprintln!("Container logger for {} failed: {}", dir, e);
+                    std::process::exit(1);
+                }
+                std::process::exit(0);
+            }
+            "--help" | "-h" => {
+                println!("libcrun-shim-agent - Container runtime agent");
+                println!();
+                println!("Usage: libcrun-shim-agent [OPTIONS]");
+                println!();
+                println!("Options:");
+                println!("  --socket PATH     Unix socket path (default: /tmp/libcrun-shim.sock)");
+                println!("  --vsock-port PORT Vsock port for VM communication");
+                println!("  --listen-fd FD    Inherited Unix listener (used by agent upgrade)");
+                println!("  --vsock-fd FD     Inherited vsock listener (used by agent upgrade)");
+                println!("  --policy FILE     Admission policy applied before create and start");
+                println!("  --metrics-ttl-ms MS  Cache container metrics this long (default: 1000, 0 disables)");
+                println!("  --fstrim-interval SECS  Trim disk-backed filesystems this often (default: 86400, 0 disables)");
+                println!("  --metrics-interval SECS  Record metrics history this often (default: 15, 0 disables)");
+                println!(
+                    "  --bridge-subnet CIDR  Addresses of bridge-mode containers (default: {})",
+                    network::DEFAULT_SUBNET
+                );
+                println!(
+                    "  --pid-file PATH   Lock held while the agent runs (default: {})",
+                    instance::DEFAULT_PID_FILE
+                );
+                println!("  --replace         Drain and replace the agent already running");
+                println!("  --version         Print version");
+                println!("  --help            Print help");
+                std::process::exit(0);
+            }
+            "--socket" => {
+                i += 1;
+                if i < args.len() {
+                    config.socket_path = args[i].clone();
+                }
+            }
+            "--vsock-port" => {
+                i += 1;
+                if i < args.len() {
+                    config.vsock_port = args[i].parse().unwrap_or(1234);
+                    config.vsock_enabled = true;
+                }
+            }
+            "--listen-fd" => {
+                i += 1;
+                if i < args.len() {
+                    config.inherited_unix_fd = args[i].parse().ok();
+                }
+            }
+            "--vsock-fd" => {
+                i += 1;
+                if i < args.len() {
+                    config.inherited_vsock_fd = args[i].parse().ok();
+                }
+            }
+            "--policy" => {
+                i += 1;
+                if i < args.len() {
+                    config.policy_path = Some(args[i].clone());
+                }
+            }
+            "--metrics-ttl-ms" => {
+                i += 1;
+                if i < args.len() {
+                    match args[i].parse() {
+                        Ok(ms) => config.metrics_ttl = std::time::Duration::from_millis(ms),
+                        Err(_) => eprintln!("Invalid --metrics-ttl-ms: {}", args[i]),
+                    }
+                }
+            }
+            "--fstrim-interval" => {
+                i += 1;
+                if i < args.len() {
+                    match args[i].parse() {
+                        Ok(secs) => config.trim_interval = std::time::Duration::from_secs(secs),
+                        Err(_) => eprintln!("Invalid --fstrim-interval: {}", args[i]),
+                    }
+                }
+            }
+            "--metrics-interval" => {
+                i += 1;
+                if i < args.len() {
+                    match args[i].parse() {
+                        Ok(secs) => config.sample_interval = std::time::Duration::from_secs(secs),
+                        Err(_) => eprintln!("Invalid --metrics-interval: {}", args[i]),
+                    }
+                }
+            }
+            "--bridge-subnet" => {
+                i += 1;
+                if i < args.len() {
+                    match args[i].parse() {
+                        Ok(subnet) => config.bridge_subnet = subnet,
+                        Err(e) => eprintln!("Invalid --bridge-subnet: {}", e),
+                    }
+                }
+            }
+            "--pid-file" => {
+                i += 1;
+                if i < args.len() {
+                    config.pid_file = args[i].clone();
+                }
+            }
+            "--replace" => config.replace = true,
+            _ => {
+                eprintln!("Unknown argument: {}", args[i]);
+            }
+        }
+        i += 1;
+    }
+
+    config
+}
+
+/// Make this the only agent using the pid file, exiting if another one runs
+///
+/// With `--replace` the other agent is drained and killed first. Without a
+/// usable pid file, e.g. when not running as root, the agent runs unguarded.
+fn lock_instance(config: &AgentConfig) -> Option<instance::InstanceLock> {
+    use instance::{InstanceLock, LockError};
+
+    let path = Path::new(&config.pid_file);
+    let owner = match InstanceLock::acquire(path, &config.socket_path) {
+        Ok(lock) => return Some(lock),
+        Err(LockError::Io(e)) => {
+            log::warn!(
+                "Could not lock {}: {}, not guarding against a second agent",
+                config.pid_file,
+                e
+            );
+            return None;
+        }
+        Err(LockError::Held(owner)) => owner,
+    };
+    let running = match &owner {
+        Some(owner) => format!(
+            "Another agent (pid {}) is already running, listening on {}",
+            owner.pid, owner.socket
+        ),
+        None => format!("Another agent is already running with {}", config.pid_file),
+    };
+    let owner = match owner {
+        Some(owner) if config.replace => owner,
+        _ => {
+            let hint = if config.replace {
+                "it has not recorded where it listens yet, try again"
+            } else {
+                "stop it first or start with --replace to take over"
+            };
+            eprintln!("[AGENT] {}; {}", running, hint);
+            std::process::exit(1);
+        }
+    };
+
+    log::info!("{}, replacing it", running);
+    if let Err(e) = instance::replace(&owner) {
+        eprintln!(
+            "[AGENT] Failed to drain the agent with pid {}: {}",
+            owner.pid, e
+        );
+        std::process::exit(1);
+    }
+    match InstanceLock::acquire_within(path, &config.socket_path, instance::RELEASE_TIMEOUT) {
+        Ok(lock) => Some(lock),
+        Err(LockError::Held(_)) => {
+            eprintln!(
+                "[AGENT] The agent with pid {} did not exit after it was drained",
+                owner.pid
+            );
+            std::process::exit(1);
+        }
+        Err(LockError::Io(e)) => {
+            eprintln!("[AGENT] Could not lock {}: {}", config.pid_file, e);
+            std::process::exit(1);
+        }
     }
 }
 
-impl Drop for AgentState {
+/// Global shutdown flag
+static SHUTDOWN_FLAG: AtomicBool = AtomicBool::new(false);
+
+/// Set once a drain has been requested; mutating requests are rejected afterwards
+static DRAINING: AtomicBool = AtomicBool::new(false);
+
+/// Set when the main loop should exec the staged agent binary
+static UPGRADE_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Number of requests currently being handled
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts a request in [`IN_FLIGHT`] until dropped
+struct InFlight;
+
+impl InFlight {
+    fn enter() -> Self {
+        IN_FLIGHT.fetch_add(1, Ordering::SeqCst);
+        InFlight
+    }
+}
+
+impl Drop for InFlight {
     fn drop(&mut self) {
+        IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn main() {
+    // Parse command line arguments
+    let config = parse_args();
+
+    // Initialize logging - to stderr for VM visibility, keeping the last
+    // lines for support bundles
+    env_logger::Builder::from_default_env()
+        .filter_level(log::LevelFilter::Info)
+        .target(env_logger::Target::Pipe(Box::new(
+            diagnostics::LogTee::new(),
+        )))
+        .init();
+
+    log::info!("libcrun-shim-agent v{}", env!("CARGO_PKG_VERSION"));
+    eprintln!("[AGENT] libcrun-shim-agent v{} starting...", env!("CARGO_PKG_VERSION"));
+    eprintln!("[AGENT] Config: socket={}, vsock_port={}, vsock_enabled={}", 
+              config.socket_path, config.vsock_port, config.vsock_enabled);
+
+    // Taken before the state is loaded, a replaced agent persists it first
+    let _instance = lock_instance(&config);
+
+    // Create shared state
+    let mut state = AgentState::new();
+    if let Some(path) = &config.policy_path {
+        match policy::Policy::load(path) {
+            Ok(policy) => {
+                log::info!("Loaded admission policy from {}", path);
+                state.policy = policy;
+            }
+            Err(e) => {
+                // Refuse to run unguarded rather than silently dropping the policy
+                eprintln!("[AGENT] {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    state.metrics_cache = metrics_cache::MetricsCache::new(config.metrics_ttl);
+    state.bridge = network::Bridge::new(config.bridge_subnet);
+    for c in state.containers.read().unwrap().values() {
+        if let Some(address) = c.ip_address {
+            state.bridge.restore(&c.id, address);
+        }
+    }
+    state.kernel = probe::probe_kernel(std::path::Path::new("/"));
+    if !state.kernel.cgroup_v2 {
+        log::info!("cgroup v1 hierarchy detected, reading metrics per controller");
+    }
+    let missing = probe::missing_features(&state.kernel);
+    if !missing.is_empty() {
+        log::warn!("Guest kernel is missing: {}", missing.join(", "));
+    }
+    let missing = probe::missing_tools(std::path::Path::new("/"), probe::TOOL_PATH);
+    if !missing.is_empty() {
+        log::warn!(
+            "Bridge networking and egress rules need {}, which the guest lacks",
+            missing.join(" and ")
+        );
+    }
+    let state = Arc::new(state);
+
+    // Clean up any orphaned containers from previous runs
+    state.cleanup_orphans();
+
+    // Setup signal handlers
+    let state_for_signals = Arc::clone(&state);
+    let mut signals =
+        Signals::new([SIGTERM, SIGINT, SIGHUP]).expect("Failed to register signal handlers");
+
+    std::thread::spawn(move || {
+        for sig in signals.forever() {
+            match sig {
+                SIGTERM => {
+                    log::info!("Received SIGTERM, initiating graceful shutdown");
+                    SHUTDOWN_FLAG.store(true, Ordering::SeqCst);
+                    state_for_signals.graceful_shutdown();
+                    std::process::exit(0);
+                }
+                SIGINT => {
+                    log::info!("Received SIGINT, initiating graceful shutdown");
+                    SHUTDOWN_FLAG.store(true, Ordering::SeqCst);
+                    state_for_signals.graceful_shutdown();
+                    std::process::exit(0);
+                }
+                SIGHUP => {
+                    log::info!("Received SIGHUP, reloading configuration");
+                    // Could reload config here if needed
+                    state_for_signals.persist_state();
+                }
+                _ => {}
+            }
+        }
+    });
+
+    // Setup vsock listener if enabled (Linux only)
+    #[cfg(target_os = "linux")]
+    let vsock_fd: Option<RawFd> = if let Some(fd) = config.inherited_vsock_fd {
+        log::info!("Reusing inherited vsock listener fd {}", fd);
+        Some(fd)
+    } else if config.vsock_enabled {
+        eprintln!("[AGENT] Setting up vsock listener on port {}...", config.vsock_port);
+        match create_vsock_listener(config.vsock_port) {
+            Ok(fd) => {
+                log::info!("Vsock listener started on port {}", config.vsock_port);
+                eprintln!("[AGENT] Vsock listener ready on port {}, fd={}", config.vsock_port, fd);
+                Some(fd)
+            }
+            Err(e) => {
+                log::warn!("Failed to create vsock listener: {}, falling back to Unix socket only", e);
+                eprintln!("[AGENT] ERROR: Failed to create vsock listener: {}", e);
+                None
+            }
+        }
+    } else {
+        eprintln!("[AGENT] Vsock disabled");
+        None
+    };
+
+    #[cfg(not(target_os = "linux"))]
+    let vsock_fd: Option<i32> = None;
+    #[cfg(not(target_os = "linux"))]
+    let _ = &config; // silence unused warning
+
+    // Listen on a Unix socket for RPC requests, reusing the previous agent's
+    // listener after an in-place upgrade so no connection is refused
+    let listener = if let Some(fd) = config.inherited_unix_fd {
+        log::info!("Reusing inherited Unix listener fd {}", fd);
+        unsafe { UnixListener::from_raw_fd(fd) }
+    } else {
+        // Remove old socket if it exists
+        let _ = std::fs::remove_file(&config.socket_path);
+        UnixListener::bind(&config.socket_path).expect("Failed to bind to socket")
+    };
+
+    // Set non-blocking so we can check shutdown flag
+    listener
+        .set_nonblocking(true)
+        .expect("Failed to set non-blocking");
+
+    log::info!("Agent listening on {}", config.socket_path);
+    eprintln!("[AGENT] Agent listening on Unix socket: {}", config.socket_path);
+    
+    // Write status to /tmp for debugging (accessible in VM)
+    let _ = std::fs::write("/tmp/agent-status.txt", format!(
+        "Agent started\nSocket: {}\nVsock port: {}\nVsock enabled: {}\nVsock fd: {:?}\n",
+        config.socket_path, config.vsock_port, config.vsock_enabled, vsock_fd
+    ));
+    
+    if vsock_fd.is_some() {
+        log::info!("Also listening on vsock port {}", config.vsock_port);
+        eprintln!("[AGENT] Also listening on vsock port {}", config.vsock_port);
+        let _ = std::fs::write("/tmp/agent-vsock-ready.txt", format!("Vsock ready on port {}", config.vsock_port));
+    } else {
+        eprintln!("[AGENT] WARNING: Vsock listener not available!");
+        let _ = std::fs::write("/tmp/agent-vsock-failed.txt", "Vsock listener creation failed");
+    }
+
+    // Ensure socket is cleaned up on drop
+    struct SocketGuard(String);
+    impl Drop for SocketGuard {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+    let _guard = SocketGuard(config.socket_path.clone());
+
+    // Persist state periodically
+    let state_for_persist = Arc::clone(&state);
+    std::thread::spawn(move || loop {
+        std::thread::sleep(std::time::Duration::from_secs(30));
+        if SHUTDOWN_FLAG.load(Ordering::SeqCst) {
+            break;
+        }
+        state_for_persist.persist_state();
+    });
+
+    // Give space freed in the guest back to the host's disk image
+    if !config.trim_interval.is_zero() {
+        disks::spawn_trim_scheduler(config.trim_interval, || {
+            SHUTDOWN_FLAG.load(Ordering::SeqCst)
+        });
+    }
+
+    // Record metrics history for usage reports
+    if !config.sample_interval.is_zero() {
+        let state_for_history = Arc::clone(&state);
+        let interval = config.sample_interval;
+        std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+            if SHUTDOWN_FLAG.load(Ordering::SeqCst) {
+                break;
+            }
+            state_for_history.sample_metrics();
+        });
+    }
+
+    // Container watchdog - monitors container health and detects orphans
+    let state_for_watchdog = Arc::clone(&state);
+    std::thread::spawn(move || {
+        log::info!("Container watchdog started");
+        loop {
+            std::thread::sleep(std::time::Duration::from_secs(10));
+            if SHUTDOWN_FLAG.load(Ordering::SeqCst) {
+                break;
+            }
+
+            state_for_watchdog.record_exits();
+            state_for_watchdog.restart_exited();
+            state_for_watchdog.enforce_deadlines();
+            state_for_watchdog.stop_idle_containers();
+
+            // Check health for containers with health checks
+            state_for_watchdog.run_health_checks();
+        }
+        log::info!("Container watchdog stopped");
+    });
+
+    // Main accept loop with shutdown check
+    loop {
+        if SHUTDOWN_FLAG.load(Ordering::SeqCst) {
+            log::info!("Shutdown flag set, exiting main loop");
+            break;
+        }
+
+        if UPGRADE_REQUESTED.load(Ordering::SeqCst) {
+            #[cfg(target_os = "linux")]
+            let vsock_listener = vsock_fd;
+            #[cfg(not(target_os = "linux"))]
+            let vsock_listener: Option<i32> = None;
+
+            let err = exec_staged_agent(&state, &config, listener.as_raw_fd(), vsock_listener);
+            log::error!(
+                "Agent upgrade failed, continuing with current binary: {}",
+                err
+            );
+            UPGRADE_REQUESTED.store(false, Ordering::SeqCst);
+            DRAINING.store(false, Ordering::SeqCst);
+        }
+
+        // Check for Unix socket connections
+        match listener.accept() {
+            Ok((stream, _)) => {
+                log::debug!("Accepted Unix socket connection");
+                let state_clone = Arc::clone(&state);
+                std::thread::spawn(move || handle_unix_client(stream, state_clone));
+            }
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                // No connection ready, continue to check vsock
+            }
+            Err(e) => {
+                log::error!("Unix socket connection error: {}", e);
+            }
+        }
+
+        // Check for vsock connections (Linux only)
         #[cfg(target_os = "linux")]
-        {
-            // Clean up libcrun context
-            if let Some(LibcrunContext(ctx)) = self.libcrun_context.take() {
-                crun::context_free(ctx);
+        if let Some(fd) = vsock_fd {
+            if let Some(stream) = accept_vsock(fd) {
+                log::info!("Accepted vsock connection");
+                let state_clone = Arc::clone(&state);
+                std::thread::spawn(move || handle_tcp_client(stream, state_clone));
             }
+        }
+
+        // Brief sleep to avoid busy-waiting
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+
+    // Clean up vsock listener
+    #[cfg(target_os = "linux")]
+    if let Some(fd) = vsock_fd {
+        unsafe { libc::close(fd) };
+    }
+
+    // Final cleanup
+    state.graceful_shutdown();
+}
+
+fn handle_unix_client(stream: UnixStream, state: Arc<AgentState>) {
+    handle_client_generic(stream, state);
+}
+
+#[cfg(target_os = "linux")]
+fn handle_tcp_client(stream: std::net::TcpStream, state: Arc<AgentState>) {
+    handle_client_generic(stream, state);
+}
+
+fn handle_client_generic<S: Connection>(mut stream: S, state: Arc<AgentState>) {
+    let mut role = Role::Admin;
+    loop {
+        match read_frame(&mut stream) {
+            Ok(None) => break, // Connection closed
+            Ok(Some(frame)) => {
+                let request = match deserialize_request(&frame) {
+                    Ok(req) => req,
+                    Err(e) => {
+                        log::warn!("Failed to parse request: {}", e);
+                        let response = Response::Error(format!("Parse error: {}", e));
+                        let _ = write_frame(&mut stream, &encode_response(&response));
+                        continue;
+                    }
+                };
 
-            // Clean up containers
-            let containers = self.containers.write().unwrap();
-            for (_, state) in containers.iter() {
-                #[cfg(target_os = "linux")]
-                if let Some(LibcrunContainer(container)) = state.libcrun_container {
-                    crun::container_free(container);
+                // Held until the response is written, so neither a drain nor
+                // an upgrade exec finish before the client has its answer
+                let mut in_flight = None;
+                let response = if let Request::SetRole(requested) = request {
+                    set_role(&mut role, requested)
+                } else if !role.permits(&request) {
+                    failed(
+                        ErrorCode::PermissionDenied,
+                        "This connection is read-only and cannot change containers or the agent",
+                    )
+                } else if let Request::Copy(req) = request {
+                    let not_started = {
+                        let _in_flight = InFlight::enter();
+                        copy(stream, req, &state)
+                    };
+                    match not_started {
+                        Some((returned, response)) => {
+                            stream = returned;
+                            response
+                        }
+                        None => return,
+                    }
+                } else if let Request::ExecStream(req) = request {
+                    let not_started = {
+                        let _in_flight = InFlight::enter();
+                        exec_stream(stream, req, &state)
+                    };
+                    match not_started {
+                        Some((returned, response)) => {
+                            stream = returned;
+                            response
+                        }
+                        // The session ran and closed the connection
+                        None => return,
+                    }
+                } else if let Request::AttachExec(session) = request {
+                    // Not counted as in flight: the session's own relay is
+                    match exec_stream::attach(stream, &session) {
+                        Some((returned, response)) => {
+                            stream = returned;
+                            response
+                        }
+                        None => return,
+                    }
+                } else if let Request::Wait(id) = request {
+                    // Not counted as in flight: it lasts as long as the container runs
+                    wait_for_exit(&id, &state)
+                } else if let Request::SubscribeEvents = request {
+                    // Not counted as in flight: it lasts as long as the connection
+                    stream_events(stream, &state);
+                    return;
+                } else if let Request::LogsStream(req) = request {
+                    // Not counted as in flight: following never ends on its own
+                    // while the container runs, and would hold up a drain
+                    match follow_logs(stream, req, &state) {
+                        Some((returned, response)) => {
+                            stream = returned;
+                            response
+                        }
+                        None => return,
+                    }
+                } else if let Request::WithProgress(request_id, request) = request {
+                    in_flight = Some(InFlight::enter());
+                    match stream.try_clone() {
+                        Ok(mut progress) => progress::reporting(
+                            request_id,
+                            move |phase| {
+                                let _ = write_frame(
+                                    &mut progress,
+                                    &encode_response(&Response::Progress(phase)),
+                                );
+                            },
+                            || serve_request(request.into(), &state),
+                        ),
+                        Err(e) => failed(
+                            ErrorCode::Internal,
+                            format!("Failed to report progress: {}", e),
+                        ),
+                    }
+                } else {
+                    in_flight = Some(InFlight::enter());
+                    serve_request(request, &state)
+                };
+
+                let written = write_frame(&mut stream, &encode_response(&response));
+                drop(in_flight);
+                if let Err(e) = written {
+                    log::error!("Write error: {}", e);
+                    break;
                 }
             }
+            Err(e) => {
+                log::error!("Read error: {}", e);
+                break;
+            }
         }
     }
 }
 
-/// Create a vsock listener socket
-#[cfg(target_os = "linux")]
-fn create_vsock_listener(port: u32) -> std::io::Result<RawFd> {
-    use std::mem;
-
-    eprintln!("[AGENT] Creating vsock socket (AF_VSOCK={})...", AF_VSOCK);
+/// Handle a request answered with a single response
+fn serve_request(request: Request, state: &AgentState) -> Response {
+    // A bug in one handler fails that request, not the connection
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        handle_request(request, state)
+    }))
+    .unwrap_or_else(|_| {
+        log::error!("Request handler panicked");
+        failed(ErrorCode::Internal, "Agent failed to handle the request")
+    })
+}
 
-    // Create vsock socket
-    let fd = unsafe { libc::socket(AF_VSOCK, libc::SOCK_STREAM, 0) };
-    if fd < 0 {
-        let err = std::io::Error::last_os_error();
-        eprintln!("[AGENT] ERROR: socket() failed: {}", err);
-        return Err(err);
+/// Run an interactive exec session, which takes over the connection
+///
+/// Returns the connection and the response to send when the session could
+/// not be started, and `None` once it ran and closed the connection.
+fn exec_stream<S: Connection>(
+    stream: S,
+    req: ExecStreamRequest,
+    state: &AgentState,
+) -> Option<(S, Response)> {
+    if DRAINING.load(Ordering::SeqCst) {
+        let response = failed(
+            ErrorCode::Unavailable,
+            "Agent is draining for an upgrade, retry shortly",
+        );
+        return Some((stream, response));
     }
-    eprintln!("[AGENT] Socket created, fd={}", fd);
+    let _exec = match ExecGuard::new(state, &req.exec.id) {
+        Ok(guard) => guard,
+        Err(e) => return Some((stream, Response::Failed(e))),
+    };
 
-    // Bind to the port
-    #[repr(C)]
-    struct SockaddrVm {
-        svm_family: libc::sa_family_t,
-        svm_reserved1: u16,
-        svm_port: u32,
-        svm_cid: u32,
-        svm_zero: [u8; 4],
+    #[cfg(target_os = "linux")]
+    if let Some(LibcrunContext(ctx)) = &state.libcrun_context {
+        let mut stream = stream;
+        let process = exec_process(&req.exec);
+        // Without the host's size, a terminal gets the traditional 24x80
+        let terminal_size = req.terminal_size.or(Some((24, 80)));
+        let child = match crun::container_exec_spawn(*ctx, &req.exec.id, &process, terminal_size) {
+            Ok(child) => child,
+            Err(e) => {
+                let response = Response::Error(format!("Failed to execute command: {}", e.message));
+                return Some((stream, response));
+            }
+        };
+        let started_at = current_timestamp();
+        let started = std::time::Instant::now();
+        if let Err(e) = write_frame(&mut stream, &encode_response(&Response::ExecStarted)) {
+            // The session notices the host is gone and hangs up
+            log::error!("Write error: {}", e);
+        }
+        let session = req
+            .detachable
+            .then(|| format!("{}-{}", req.exec.id, child.pid()));
+        let exit_code =
+            exec_stream::relay(stream, child, req.exec.tty, session, req.stdin_once).unwrap_or(-1);
+        record_exec(state, req.exec, started_at, started.elapsed(), exit_code);
+        return None;
     }
 
-    // For listening, we bind to VMADDR_CID_ANY (any CID can connect)
-    // But for Apple Virtualization Framework, connections come from CID 2 (host)
-    let addr = SockaddrVm {
-        svm_family: AF_VSOCK as libc::sa_family_t,
-        svm_reserved1: 0,
-        svm_port: port,
-        svm_cid: VMADDR_CID_ANY,  // Listen on any CID (host connects from CID 2)
-        svm_zero: [0; 4],
-    };
-    eprintln!("[AGENT] Binding to CID={} (VMADDR_CID_ANY), port={}", VMADDR_CID_ANY, port);
-
-    let ret = unsafe {
-        libc::bind(
-            fd,
-            &addr as *const SockaddrVm as *const libc::sockaddr,
-            mem::size_of::<SockaddrVm>() as libc::socklen_t,
-        )
-    };
+    let response =
+        Response::Error("Exec requires libcrun, which is not available in the agent".to_string());
+    Some((stream, response))
+}
 
-    eprintln!("[AGENT] Binding to port {}...", port);
-    if ret < 0 {
-        let err = std::io::Error::last_os_error();
-        eprintln!("[AGENT] ERROR: bind() failed: {}", err);
-        unsafe { libc::close(fd) };
-        return Err(err);
+/// Block until a container has stopped, then report how its process exited
+///
+/// The waiter sleeps on a pidfd of the container's process where the kernel
+/// has them, so it answers as soon as the process exits rather than on the
+/// watchdog's next pass.
+fn wait_for_exit(id: &str, state: &AgentState) -> Response {
+    loop {
+        let pid = match state.containers.read().unwrap().get(id) {
+            None => return failed(ErrorCode::NotFound, format!("Container not found: {}", id)),
+            Some(c) if c.status == ContainerStatus::Stopped => {
+                return Response::Exited(ExitStatusProto {
+                    exit_code: c.last_exit_code,
+                    exit_reason: c.last_exit_reason,
+                })
+            }
+            Some(c) => c.pid,
+        };
+        match pid {
+            Some(pid) => wait_for_process(pid, WAIT_CHECK_INTERVAL),
+            // Not started yet
+            None => std::thread::sleep(WAIT_CHECK_INTERVAL),
+        }
+        state.record_exits();
     }
-    eprintln!("[AGENT] Bind successful");
+}
 
-    // Listen for connections
-    eprintln!("[AGENT] Calling listen()...");
-    let ret = unsafe { libc::listen(fd, 5) };
-    if ret < 0 {
-        let err = std::io::Error::last_os_error();
-        eprintln!("[AGENT] ERROR: listen() failed: {}", err);
-        unsafe { libc::close(fd) };
-        return Err(err);
+/// Sleep until process `pid` exits, or at most `timeout`
+fn wait_for_process(pid: u32, timeout: std::time::Duration) {
+    #[cfg(target_os = "linux")]
+    if let Ok(pidfd) = crun::pidfd_open(pid) {
+        let mut fd = libc::pollfd {
+            fd: pidfd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        unsafe { libc::poll(&mut fd, 1, timeout.as_millis() as libc::c_int) };
+        return;
     }
-    eprintln!("[AGENT] Listen successful");
+    // Without pidfds, exits are noticed at the next check
+    let _ = pid;
+    std::thread::sleep(LOG_POLL_INTERVAL.min(timeout));
+}
 
-    // Set non-blocking
-    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
-    if flags < 0 {
-        unsafe { libc::close(fd) };
-        return Err(std::io::Error::last_os_error());
-    }
-    let ret = unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) };
-    if ret < 0 {
-        unsafe { libc::close(fd) };
-        return Err(std::io::Error::last_os_error());
+/// Send a container's logs and then its new output, until it has stopped
+///
+/// Returns the connection and the response to send when the container does
+/// not exist, and `None` once the logs were followed and the connection
+/// closed, which is also how the host going away ends it.
+fn follow_logs<S: Connection>(
+    mut stream: S,
+    req: LogsRequest,
+    state: &AgentState,
+) -> Option<(S, Response)> {
+    let id = req.id.clone();
+    if !state.containers.read().unwrap().contains_key(&id) {
+        let response = failed(ErrorCode::NotFound, format!("Container not found: {}", id));
+        return Some((stream, response));
     }
 
-    eprintln!("[AGENT] Vsock listener fully initialized, fd={}, port={}", fd, port);
-    Ok(fd)
-}
+    let mut next = req;
+    let mut last_sent: Option<std::time::Instant> = None;
+    loop {
+        // Checked before reading, so output written just before the container
+        // stopped is still sent
+        let running = state
+            .containers
+            .read()
+            .unwrap()
+            .get(&id)
+            .is_some_and(|c| c.status == ContainerStatus::Running);
+        let logs = read_container_logs(next.clone());
+        let quiet = logs.stdout.is_empty() && logs.stderr.is_empty() && logs.entries.is_empty();
+        let more = logs.truncated && next.stdout_offset.is_some();
+        next = LogsRequest {
+            tail: 0,
+            since: 0,
+            stdout_offset: Some(logs.stdout_offset),
+            stderr_offset: Some(logs.stderr_offset),
+            ..next
+        };
 
-/// Accept a connection from vsock
-#[cfg(target_os = "linux")]
-fn accept_vsock(fd: RawFd) -> Option<std::net::TcpStream> {
-    let client_fd = unsafe { libc::accept(fd, std::ptr::null_mut(), std::ptr::null_mut()) };
-    if client_fd < 0 {
-        let err = std::io::Error::last_os_error();
-        if err.kind() != std::io::ErrorKind::WouldBlock {
-            log::debug!("Vsock accept error: {}", err);
+        if !quiet || last_sent.is_none_or(|sent| sent.elapsed() >= LOG_HEARTBEAT) {
+            if let Err(e) = write_frame(&mut stream, &encode_response(&Response::Logs(logs))) {
+                log::debug!("Log follower for {} went away: {}", id, e);
+                break;
+            }
+            last_sent = Some(std::time::Instant::now());
+        }
+        if quiet && !running {
+            break;
+        }
+        // A page cut short by the byte limit is followed by the rest right away
+        if !more {
+            std::thread::sleep(LOG_POLL_INTERVAL);
         }
-        return None;
     }
-
-    // Wrap the fd in a TcpStream for convenience (it's not actually TCP, but it has the same interface)
-    // This is a bit of a hack, but it works for our purposes
-    Some(unsafe { std::net::TcpStream::from_raw_fd(client_fd) })
+    stream.shutdown();
+    None
 }
 
-/// Agent configuration
-struct AgentConfig {
-    socket_path: String,
-    vsock_port: u32,
-    vsock_enabled: bool,
+/// Send the agent's events over the connection until the subscriber goes
+/// away, then close it
+fn stream_events<S: Connection>(mut stream: S, state: &AgentState) {
+    let heartbeat = std::time::Duration::from_secs(EVENT_HEARTBEAT_SECS);
+    let events = state.events.subscribe();
+    // Subscribed before this first answer, so nothing raised after it is missed
+    let mut batch = vec![];
+    loop {
+        if let Err(e) = write_frame(&mut stream, &encode_response(&Response::Events(batch))) {
+            log::debug!("Event subscriber went away: {}", e);
+            break;
+        }
+        batch = match events.recv_timeout(heartbeat) {
+            Ok(event) => std::iter::once(event).chain(events.try_iter()).collect(),
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => vec![],
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+    }
+    stream.shutdown();
 }
 
-impl Default for AgentConfig {
-    fn default() -> Self {
-        Self {
-            socket_path: "/tmp/libcrun-shim.sock".to_string(),
-            vsock_port: 1234,
-            vsock_enabled: false,
-        }
+/// Why a command can't be run in a container, if it can't
+fn exec_target_error(state: &AgentState, id: &str) -> Option<Response> {
+    let containers = state.containers.read().unwrap();
+    match containers.get(id) {
+        None => Some(failed(
+            ErrorCode::NotFound,
+            format!("Container not found: {}", id),
+        )),
+        Some(c) if c.status != ContainerStatus::Running => Some(failed(
+            ErrorCode::Conflict,
+            format!("Container '{}' is not running", id),
+        )),
+        Some(_) => None,
     }
 }
 
-fn parse_args() -> AgentConfig {
-    let args: Vec<String> = std::env::args().collect();
-    let mut config = AgentConfig::default();
-    let mut i = 1;
+/// Counts an exec session running in a container, which cannot be deleted
+/// until the guard is dropped
+struct ExecGuard<'a> {
+    state: &'a AgentState,
+    id: String,
+}
 
-    while i < args.len() {
-        match args[i].as_str() {
-            "--version" | "-V" => {
-                println!("libcrun-shim-agent {}", env!("CARGO_PKG_VERSION"));
-                std::process::exit(0);
-            }
-            "--help" | "-h" => {
-                println!("libcrun-shim-agent - Container runtime agent");
-                println!();
-                println!("Usage: libcrun-shim-agent [OPTIONS]");
-                println!();
-                println!("Options:");
-                println!("  --socket PATH     Unix socket path (default: /tmp/libcrun-shim.sock)");
-                println!("  --vsock-port PORT Vsock port for VM communication");
-                println!("  --version         Print version");
-                println!("  --help            Print help");
-                std::process::exit(0);
-            }
-            "--socket" => {
-                i += 1;
-                if i < args.len() {
-                    config.socket_path = args[i].clone();
-                }
-            }
-            "--vsock-port" => {
-                i += 1;
-                if i < args.len() {
-                    config.vsock_port = args[i].parse().unwrap_or(1234);
-                    config.vsock_enabled = true;
-                }
-            }
-            _ => {
-                eprintln!("Unknown argument: {}", args[i]);
+impl<'a> ExecGuard<'a> {
+    /// The guard, or why the container cannot run commands
+    fn new(state: &'a AgentState, id: &str) -> Result<Self, ErrorProto> {
+        let mut containers = state.containers.write().unwrap();
+        match containers.get_mut(id) {
+            None => Err(ErrorProto {
+                code: ErrorCode::NotFound,
+                message: format!("Container not found: {}", id),
+            }),
+            Some(c) if c.status != ContainerStatus::Running => Err(ErrorProto {
+                code: ErrorCode::Conflict,
+                message: format!("Container '{}' is not running", id),
+            }),
+            Some(c) => {
+                c.execs_in_flight += 1;
+                Ok(Self {
+                    state,
+                    id: id.to_string(),
+                })
             }
         }
-        i += 1;
     }
-
-    config
 }
 
-/// Global shutdown flag
-static SHUTDOWN_FLAG: AtomicBool = AtomicBool::new(false);
-
-fn main() {
-    // Parse command line arguments
-    let config = parse_args();
-
-    // Initialize logging - also log to stderr for VM visibility
-    env_logger::Builder::from_default_env()
-        .filter_level(log::LevelFilter::Info)
-        .target(env_logger::Target::Stderr)
-        .init();
-
-    log::info!("libcrun-shim-agent v{}", env!("CARGO_PKG_VERSION"));
-    eprintln!("[AGENT] libcrun-shim-agent v{} starting...", env!("CARGO_PKG_VERSION"));
-    eprintln!("[AGENT] Config: socket={}, vsock_port={}, vsock_enabled={}", 
-              config.socket_path, config.vsock_port, config.vsock_enabled);
-
-    // Create shared state
-    let state = Arc::new(AgentState::new());
+impl Drop for ExecGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(c) = self.state.containers.write().unwrap().get_mut(&self.id) {
+            c.execs_in_flight -= 1;
+        }
+    }
+}
 
-    // Clean up any orphaned containers from previous runs
-    state.cleanup_orphans();
+#[cfg(target_os = "linux")]
+fn exec_process(req: &ExecRequest) -> crun::ExecProcess {
+    crun::ExecProcess {
+        args: req.command.clone(),
+        env: req.env.clone(),
+        cwd: req.working_dir.clone(),
+        uid: req.uid,
+        gid: req.gid,
+        tty: req.tty,
+    }
+}
 
-    // Setup signal handlers
-    let state_for_signals = Arc::clone(&state);
-    let mut signals =
-        Signals::new([SIGTERM, SIGINT, SIGHUP]).expect("Failed to register signal handlers");
+/// Write an exec session to the audit log and the container's exec history
+#[cfg(target_os = "linux")]
+fn record_exec(
+    state: &AgentState,
+    req: ExecRequest,
+    started_at: u64,
+    duration: std::time::Duration,
+    exit_code: i32,
+) {
+    let duration_ms = duration.as_millis() as u64;
+    log::info!(
+        target: "audit",
+        "exec container={} user={} command={:?} duration_ms={} exit_code={}",
+        req.id,
+        req.user,
+        req.command,
+        duration_ms,
+        exit_code
+    );
+    if let Some(container) = state.containers.write().unwrap().get_mut(&req.id) {
+        let session = ExecSessionProto {
+            command: req.command,
+            user: req.user,
+            started_at,
+            duration_ms,
+            exit_code,
+        };
+        execs::record(&mut container.execs, session);
+    }
+    state.persist_state();
+}
 
-    std::thread::spawn(move || {
-        for sig in signals.forever() {
-            match sig {
-                SIGTERM => {
-                    log::info!("Received SIGTERM, initiating graceful shutdown");
-                    SHUTDOWN_FLAG.store(true, Ordering::SeqCst);
-                    state_for_signals.graceful_shutdown();
-                    std::process::exit(0);
-                }
-                SIGINT => {
-                    log::info!("Received SIGINT, initiating graceful shutdown");
-                    SHUTDOWN_FLAG.store(true, Ordering::SeqCst);
-                    state_for_signals.graceful_shutdown();
-                    std::process::exit(0);
-                }
-                SIGHUP => {
-                    log::info!("Received SIGHUP, reloading configuration");
-                    // Could reload config here if needed
-                    state_for_signals.persist_state();
-                }
-                _ => {}
+/// Copy files in or out of a container's rootfs, which takes over the
+/// connection
+///
+/// Returns the connection and the response to send when the copy could not
+/// be started, and `None` once it ran and closed the connection. Volumes are
+/// mounted in the container's own mount namespace, so a path under one is
+/// copied from or to the rootfs beneath it.
+fn copy<S: Connection>(
+    mut stream: S,
+    req: CopyRequest,
+    state: &AgentState,
+) -> Option<(S, Response)> {
+    if req.direction == CopyDirection::In && DRAINING.load(Ordering::SeqCst) {
+        let response = failed(
+            ErrorCode::Unavailable,
+            "Agent is draining for an upgrade, retry shortly",
+        );
+        return Some((stream, response));
+    }
+    let Some((root, read_only)) = container_root(&req.id, state) else {
+        let response = failed(
+            ErrorCode::NotFound,
+            format!("Container not found: {}", req.id),
+        );
+        return Some((stream, response));
+    };
+    let path = Path::new(&req.path);
+    let started = |stream: &mut S| write_frame(stream, &encode_response(&Response::CopyStarted));
+
+    let copied = match req.direction {
+        CopyDirection::Out => {
+            let total = match archive::measure(&root, path, &req.options) {
+                Ok(total) => total,
+                Err(e) => return Some((stream, copy_failed(&req, e))),
+            };
+            if let Err(e) = started(&mut stream)
+                .and_then(|_| archive::send_frame(&mut stream, &CopyFrame::Total(total)))
+            {
+                log::error!("Write error: {}", e);
+                return None;
             }
+            let mut writer = archive::FrameWriter::new(&mut stream);
+            archive::pack(&root, path, &mut writer, &req.options, |_| {})
+                .and_then(|stats| writer.finish().map(|_| stats))
         }
-    });
-
-    // Setup vsock listener if enabled (Linux only)
-    #[cfg(target_os = "linux")]
-    let vsock_fd: Option<RawFd> = if config.vsock_enabled {
-        eprintln!("[AGENT] Setting up vsock listener on port {}...", config.vsock_port);
-        match create_vsock_listener(config.vsock_port) {
-            Ok(fd) => {
-                log::info!("Vsock listener started on port {}", config.vsock_port);
-                eprintln!("[AGENT] Vsock listener ready on port {}, fd={}", config.vsock_port, fd);
-                Some(fd)
+        CopyDirection::In => {
+            if read_only {
+                let response = failed(
+                    ErrorCode::Conflict,
+                    format!("Container '{}' has a read-only rootfs", req.id),
+                );
+                return Some((stream, response));
             }
-            Err(e) => {
-                log::warn!("Failed to create vsock listener: {}, falling back to Unix socket only", e);
-                eprintln!("[AGENT] ERROR: Failed to create vsock listener: {}", e);
-                None
+            let target = match archive::Target::resolve(&root, path) {
+                Ok(target) => target,
+                Err(e) => return Some((stream, copy_failed(&req, e))),
+            };
+            if let Err(e) = started(&mut stream) {
+                log::error!("Write error: {}", e);
+                return None;
             }
+            let mut reader = archive::FrameReader::new(&mut stream);
+            archive::unpack(&mut reader, &target, &req.options, |_| {})
+                .and_then(|stats| reader.finish().map(|_| stats))
         }
-    } else {
-        eprintln!("[AGENT] Vsock disabled");
-        None
     };
 
-    #[cfg(not(target_os = "linux"))]
-    let vsock_fd: Option<i32> = None;
-    #[cfg(not(target_os = "linux"))]
-    let _ = &config; // silence unused warning
+    let last = match copied {
+        Ok(stats) => {
+            log::info!(
+                "Copied {} entries ({} bytes) {} '{}' of container '{}'",
+                stats.entries,
+                stats.bytes,
+                if req.direction == CopyDirection::In {
+                    "to"
+                } else {
+                    "from"
+                },
+                req.path,
+                req.id
+            );
+            CopyFrame::Done(stats)
+        }
+        Err(e) => {
+            log::warn!(
+                "Failed to copy '{}' of container '{}': {}",
+                req.path,
+                req.id,
+                e
+            );
+            CopyFrame::Failed(e.to_string())
+        }
+    };
+    // The host may be gone already, e.g. after sending a failure of its own
+    let _ = archive::send_frame(&mut stream, &last);
+    stream.shutdown();
+    None
+}
+
+fn copy_failed(req: &CopyRequest, e: std::io::Error) -> Response {
+    let code = match e.kind() {
+        std::io::ErrorKind::NotFound => ErrorCode::NotFound,
+        std::io::ErrorKind::InvalidInput => ErrorCode::InvalidArgument,
+        _ => ErrorCode::Internal,
+    };
+    failed(
+        code,
+        format!(
+            "Cannot copy '{}' of container '{}': {}",
+            req.path, req.id, e
+        ),
+    )
+}
 
-    // Remove old socket if it exists
-    let _ = std::fs::remove_file(&config.socket_path);
+/// A container's rootfs as its processes see it, and whether it is read-only
+///
+/// That of a container created with `rootfs_snapshot` is the snapshot, as
+/// recorded in its OCI configuration.
+fn container_root(id: &str, state: &AgentState) -> Option<(PathBuf, bool)> {
+    let rootfs = state.containers.read().unwrap().get(id)?.rootfs.clone();
+    let oci = std::fs::read_to_string(oci_config_path(id))
+        .ok()
+        .and_then(|config| serde_json::from_str::<serde_json::Value>(&config).ok());
+    Some(match oci {
+        Some(oci) => (
+            PathBuf::from(oci["root"]["path"].as_str().unwrap_or(&rootfs)),
+            oci["root"]["readonly"].as_bool().unwrap_or(false),
+        ),
+        None => (PathBuf::from(rootfs), false),
+    })
+}
 
-    // Listen on a Unix socket for RPC requests
-    let listener = UnixListener::bind(&config.socket_path).expect("Failed to bind to socket");
+/// Change a connection's role; a read-only connection cannot become admin again
+fn set_role(role: &mut Role, requested: Role) -> Response {
+    if *role == Role::ReadOnly && requested != Role::ReadOnly {
+        return failed(
+            ErrorCode::PermissionDenied,
+            "A read-only connection cannot raise its role, open a new connection",
+        );
+    }
+    if requested == Role::ReadOnly {
+        log::debug!("Connection switched to read-only");
+    }
+    *role = requested;
+    Response::RoleSet(requested)
+}
 
-    // Set non-blocking so we can check shutdown flag
-    listener
-        .set_nonblocking(true)
-        .expect("Failed to set non-blocking");
+/// Encode a response, or an error the host can read if it can't be encoded
+/// (e.g. it is larger than a frame)
+fn encode_response(response: &Response) -> Vec<u8> {
+    serialize_response(response).unwrap_or_else(|e| {
+        log::error!("Failed to encode response: {}", e);
+        let error = failed(
+            ErrorCode::Internal,
+            format!("Failed to encode response: {}", e),
+        );
+        serialize_response(&error).unwrap_or_default()
+    })
+}
 
-    log::info!("Agent listening on {}", config.socket_path);
-    eprintln!("[AGENT] Agent listening on Unix socket: {}", config.socket_path);
-    
-    // Write status to /tmp for debugging (accessible in VM)
-    let _ = std::fs::write("/tmp/agent-status.txt", format!(
-        "Agent started\nSocket: {}\nVsock port: {}\nVsock enabled: {}\nVsock fd: {:?}\n",
-        config.socket_path, config.vsock_port, config.vsock_enabled, vsock_fd
-    ));
-    
-    if vsock_fd.is_some() {
-        log::info!("Also listening on vsock port {}", config.vsock_port);
-        eprintln!("[AGENT] Also listening on vsock port {}", config.vsock_port);
-        let _ = std::fs::write("/tmp/agent-vsock-ready.txt", format!("Vsock ready on port {}", config.vsock_port));
+/// Why a container ID can't be used, if it can't
+///
+/// IDs name directories under the agent's state and log directories, so
+/// they follow runc's rules: letters, digits, `_`, `+`, `-` and `.`.
+fn invalid_container_id(id: &str) -> Option<&'static str> {
+    if id.is_empty() {
+        Some("Container ID cannot be empty")
+    } else if id.len() > MAX_CONTAINER_ID_LEN {
+        Some("Container ID is too long")
+    } else if id == "." || id == ".." {
+        Some("Container ID cannot be '.' or '..'")
+    } else if !id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '+' | '-' | '.'))
+    {
+        Some("Container ID may only contain letters, digits, '_', '+', '-' and '.'")
     } else {
-        eprintln!("[AGENT] WARNING: Vsock listener not available!");
-        let _ = std::fs::write("/tmp/agent-vsock-failed.txt", "Vsock listener creation failed");
+        None
     }
+}
 
-    // Ensure socket is cleaned up on drop
-    struct SocketGuard(String);
-    impl Drop for SocketGuard {
-        fn drop(&mut self) {
-            let _ = std::fs::remove_file(&self.0);
-        }
+/// Why a container name is not accepted, if it is not
+fn invalid_container_name(name: &str) -> Option<&'static str> {
+    if name.is_empty() {
+        Some("Container name cannot be empty")
+    } else if name.len() > MAX_CONTAINER_ID_LEN {
+        Some("Container name is too long")
+    } else if !name.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+        Some("Container name must start with a letter or digit")
+    } else if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+    {
+        Some("Container name may only contain letters, digits, '_', '-' and '.'")
+    } else {
+        None
     }
-    let _guard = SocketGuard(config.socket_path.clone());
+}
 
-    // Persist state periodically
-    let state_for_persist = Arc::clone(&state);
-    std::thread::spawn(move || loop {
-        std::thread::sleep(std::time::Duration::from_secs(30));
-        if SHUTDOWN_FLAG.load(Ordering::SeqCst) {
-            break;
-        }
-        state_for_persist.persist_state();
-    });
+/// Whether a container other than `id` is named `name`
+fn name_taken(containers: &HashMap<String, ContainerState>, name: &str, id: &str) -> bool {
+    containers.values().any(|c| c.name == name && c.id != id)
+}
 
-    // Container watchdog - monitors container health and detects orphans
-    let state_for_watchdog = Arc::clone(&state);
-    std::thread::spawn(move || {
-        log::info!("Container watchdog started");
-        loop {
-            std::thread::sleep(std::time::Duration::from_secs(10));
-            if SHUTDOWN_FLAG.load(Ordering::SeqCst) {
-                break;
-            }
+/// Why a checkpoint reference is not accepted, if it is not; both parts
+/// become path components
+fn invalid_checkpoint_ref(container: &str, name: &str) -> Option<&'static str> {
+    invalid_container_id(container).or_else(|| checkpoints::invalid_name(name))
+}
 
-            // Check all running containers
-            let mut containers = state_for_watchdog.containers.write().unwrap();
-            let mut orphaned = Vec::new();
+/// Whether a stopped container is one `filter` prunes
+fn prune_matches(filter: &PruneFilterProto, container: &ContainerState) -> bool {
+    (filter.until == 0 || container.created_at < filter.until)
+        && labels_match(&filter.labels, &container.labels)
+}
 
-            for (id, container) in containers.iter() {
-                if container.status == "Running" {
-                    if let Some(pid) = container.pid {
-                        // Check if process is still alive
-                        let alive = unsafe { libc::kill(pid as libc::pid_t, 0) == 0 };
-                        if !alive {
-                            log::warn!(
-                                "Container {} (PID {}) is no longer running - marking as orphaned",
-                                id,
-                                pid
-                            );
-                            orphaned.push(id.clone());
-                        }
-                    }
-                }
-            }
+/// Whether a container is one [`Request::ListFiltered`] lists
+fn list_matches(filter: &ListFilterProto, container: &ContainerState) -> bool {
+    (filter.statuses.is_empty() || filter.statuses.contains(&container.status))
+        && container.name.starts_with(&filter.name_prefix)
+        && labels_match(&filter.labels, &container.labels)
+}
 
-            // Mark orphaned containers
-            for id in orphaned {
-                if let Some(container) = containers.get_mut(&id) {
-                    container.status = "orphaned".to_string();
-                    container.pid = None;
-                }
-            }
+/// Whether all the conditions on labels hold for `labels`
+fn labels_match(filters: &[LabelFilterProto], labels: &BTreeMap<String, String>) -> bool {
+    filters.iter().all(|filter| {
+        let set = match &filter.value {
+            Some(value) => labels.get(&filter.key) == Some(value),
+            None => labels.contains_key(&filter.key),
+        };
+        set != filter.negate
+    })
+}
 
-            drop(containers);
+/// The containers `filter` matches
+fn list_containers(state: &AgentState, filter: &ListFilterProto) -> Response {
+    let containers = state.containers.read().unwrap();
+    Response::List(
+        containers
+            .values()
+            .filter(|c| list_matches(filter, c))
+            .map(ContainerState::to_info)
+            .collect(),
+    )
+}
 
-            // Check health for containers with health checks
-            state_for_watchdog.run_health_checks();
-        }
-        log::info!("Container watchdog stopped");
-    });
+/// Where the OCI configuration of a container created through libcrun is
+/// kept, for checkpoints of it
+fn oci_config_path(id: &str) -> PathBuf {
+    PathBuf::from(STATE_DIR).join(id).join("config.json")
+}
 
-    // Main accept loop with shutdown check
-    loop {
-        if SHUTDOWN_FLAG.load(Ordering::SeqCst) {
-            log::info!("Shutdown flag set, exiting main loop");
-            break;
+fn save_oci_config(id: &str, config: &str) {
+    let path = oci_config_path(id);
+    let saved = std::fs::create_dir_all(path.parent().unwrap_or(Path::new("/")))
+        .and_then(|_| std::fs::write(&path, config));
+    if let Err(e) = saved {
+        log::warn!("Failed to save OCI config of '{}': {}", id, e);
+    }
+}
+
+/// Undoes a create that does not finish
+///
+/// Whatever the create made for the container, its rootfs snapshot, bridge
+/// address and interface, libcrun state and cgroup, is removed when the guard
+/// is dropped before [`CreateGuard::commit`], whichever way the create
+/// failed. While the guard lives no other create of the ID can start, and
+/// [`AgentState::stale_resources`] leaves the ID alone.
+#[cfg(target_os = "linux")]
+struct CreateGuard<'a> {
+    state: &'a AgentState,
+    id: String,
+    /// Where the rootfs snapshot is being made
+    snapshot: Option<PathBuf>,
+    /// The container got an address on the bridge
+    attached: bool,
+    /// libcrun had no state for the ID before the create
+    owns_state: bool,
+    /// The container libcrun created
+    container: Option<*mut libcrun_sys::libcrun_container_t>,
+    committed: bool,
+}
+
+#[cfg(target_os = "linux")]
+impl<'a> CreateGuard<'a> {
+    /// None while another create of `id` is in progress
+    fn new(state: &'a AgentState, id: &str) -> Option<Self> {
+        if !state.creating.lock().unwrap().insert(id.to_string()) {
+            return None;
         }
+        Some(Self {
+            state,
+            id: id.to_string(),
+            snapshot: None,
+            attached: false,
+            owns_state: false,
+            container: None,
+            committed: false,
+        })
+    }
 
-        // Check for Unix socket connections
-        match listener.accept() {
-            Ok((stream, _)) => {
-                log::debug!("Accepted Unix socket connection");
-                let state_clone = Arc::clone(&state);
-                std::thread::spawn(move || handle_unix_client(stream, state_clone));
+    /// The container is tracked, and keeps what was made for it
+    fn commit(mut self) {
+        self.committed = true;
+    }
+
+    fn undo(&mut self) {
+        log::info!("Removing what the failed create of '{}' made", self.id);
+        if let Some(container) = self.container.take() {
+            if let Some(LibcrunContext(ctx)) = &self.state.libcrun_context {
+                if let Err(e) = crun::container_delete(*ctx, container, &self.id) {
+                    log::warn!("libcrun delete of '{}' failed: {}", self.id, e);
+                }
             }
-            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                // No connection ready, continue to check vsock
+            crun::container_free(container);
+            let _ = std::fs::remove_dir_all(PathBuf::from(STATE_DIR).join(&self.id));
+        }
+        if self.owns_state {
+            if let Err(e) = crun::remove_state(&self.id) {
+                log::warn!("Failed to remove the libcrun state of '{}': {}", self.id, e);
             }
-            Err(e) => {
-                log::error!("Unix socket connection error: {}", e);
+            if let Err(e) = crun::remove_cgroup(&self.id) {
+                log::warn!("Failed to remove the cgroup of '{}': {}", self.id, e);
             }
         }
-
-        // Check for vsock connections (Linux only)
-        #[cfg(target_os = "linux")]
-        if let Some(fd) = vsock_fd {
-            if let Some(stream) = accept_vsock(fd) {
-                eprintln!("[AGENT] Accepted vsock connection!");
-                log::info!("Accepted vsock connection");
-                let state_clone = Arc::clone(&state);
-                std::thread::spawn(move || handle_tcp_client(stream, state_clone));
+        if self.attached {
+            self.state.bridge.detach(&self.id);
+        }
+        if let Some(dir) = &self.snapshot {
+            if let Err(e) = crun::remove_snapshot(dir) {
+                log::warn!(
+                    "Failed to remove the rootfs snapshot of '{}': {}",
+                    self.id,
+                    e
+                );
             }
         }
-
-        // Brief sleep to avoid busy-waiting
-        std::thread::sleep(std::time::Duration::from_millis(50));
     }
+}
 
-    // Clean up vsock listener
-    #[cfg(target_os = "linux")]
-    if let Some(fd) = vsock_fd {
-        unsafe { libc::close(fd) };
+#[cfg(target_os = "linux")]
+impl Drop for CreateGuard<'_> {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.undo();
+        }
+        self.state.creating.lock().unwrap().remove(&self.id);
     }
+}
 
-    // Final cleanup
-    state.graceful_shutdown();
+/// Whether a request changes container state and must be refused while draining
+fn is_mutating(request: &Request) -> bool {
+    matches!(
+        request,
+        Request::Create(_)
+            | Request::Start(_)
+            | Request::Stop(_)
+            | Request::Delete(_)
+            | Request::Exec(_)
+            | Request::Mount(_)
+            | Request::CreateCheckpoint(_)
+            | Request::RemoveCheckpoint(_)
+            | Request::RestoreCheckpoint(_)
+            | Request::WriteCheckpointFile(_)
+            | Request::ImportCheckpoint(_)
+            | Request::Prune(_)
+            | Request::RemoveStaleResources
+            | Request::ExecStream(_)
+            | Request::Pause(_)
+            | Request::Unpause(_)
+            | Request::Rename(_)
+            | Request::StopWithTimeout(_)
+            | Request::Copy(CopyRequest {
+                direction: CopyDirection::In,
+                ..
+            })
+    )
 }
 
-fn handle_unix_client(stream: UnixStream, state: Arc<AgentState>) {
-    handle_client_generic(stream, state);
+/// Compute the hex encoded SHA-256 of a file
+fn sha256_file(path: &str) -> std::io::Result<String> {
+    use sha2::{Digest, Sha256};
+
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
 }
 
-#[cfg(target_os = "linux")]
-fn handle_tcp_client(stream: std::net::TcpStream, state: Arc<AgentState>) {
-    handle_client_generic(stream, state);
+/// Write a new agent binary to the staging path and verify its checksum
+fn stage_agent_binary(req: UploadAgentRequest) -> Result<String, String> {
+    let tmp_path = format!("{}.partial", STAGED_AGENT_PATH);
+
+    match req.source {
+        AgentBinarySource::Inline(data) => std::fs::write(&tmp_path, data),
+        AgentBinarySource::Path(path) => std::fs::copy(&path, &tmp_path).map(|_| ()),
+    }
+    .map_err(|e| format!("Failed to write staged agent binary: {}", e))?;
+
+    let actual = sha256_file(&tmp_path).map_err(|e| format!("Failed to hash binary: {}", e))?;
+    if !actual.eq_ignore_ascii_case(&req.sha256) {
+        let _ = std::fs::remove_file(&tmp_path);
+        return Err(format!(
+            "Checksum mismatch for uploaded agent: expected {}, got {}",
+            req.sha256, actual
+        ));
+    }
+
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&tmp_path, std::fs::Permissions::from_mode(0o755))
+            .map_err(|e| format!("Failed to mark agent binary executable: {}", e))?;
+    }
+    std::fs::rename(&tmp_path, STAGED_AGENT_PATH)
+        .map_err(|e| format!("Failed to stage agent binary: {}", e))?;
+
+    log::info!(
+        "Staged new agent binary at {} ({})",
+        STAGED_AGENT_PATH,
+        actual
+    );
+    Ok(STAGED_AGENT_PATH.to_string())
 }
 
-fn handle_client_generic<S: Read + Write>(mut stream: S, state: Arc<AgentState>) {
-    let mut buffer = vec![0u8; 4096];
+/// Replace the current process with the staged agent, handing over the listening sockets.
+///
+/// Only returns if the exec failed.
+fn exec_staged_agent(
+    state: &AgentState,
+    config: &AgentConfig,
+    unix_fd: i32,
+    vsock_fd: Option<i32>,
+) -> std::io::Error {
+    use std::os::unix::process::CommandExt;
+
+    // Requests stay in flight until their response is written, the one that
+    // requested the upgrade included
+    while IN_FLIGHT.load(Ordering::SeqCst) > 0 {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
 
-    loop {
-        match stream.read(&mut buffer) {
-            Ok(0) => break, // Connection closed
-            Ok(n) => {
-                let request = match deserialize_request(&buffer[..n]) {
-                    Ok(req) => req,
-                    Err(e) => {
-                        log::warn!("Failed to parse request: {}", e);
-                        let response = Response::Error(format!("Parse error: {}", e));
-                        let _ = stream.write_all(&serialize_response(&response));
-                        continue;
-                    }
-                };
+    // The new agent recovers containers from the persisted state file
+    state.persist_state();
 
-                let response = handle_request(request, &state);
-                if let Err(e) = stream.write_all(&serialize_response(&response)) {
-                    log::error!("Write error: {}", e);
-                    break;
-                }
-            }
-            Err(e) => {
-                log::error!("Read error: {}", e);
-                break;
-            }
+    let mut fds = vec![unix_fd];
+    fds.extend(vsock_fd);
+    for fd in &fds {
+        let flags = unsafe { libc::fcntl(*fd, libc::F_GETFD) };
+        if flags < 0 || unsafe { libc::fcntl(*fd, libc::F_SETFD, flags & !libc::FD_CLOEXEC) } < 0 {
+            return std::io::Error::last_os_error();
         }
     }
+
+    let mut cmd = std::process::Command::new(STAGED_AGENT_PATH);
+    cmd.arg("--socket")
+        .arg(&config.socket_path)
+        .arg("--listen-fd")
+        .arg(unix_fd.to_string());
+    if let Some(fd) = vsock_fd {
+        cmd.arg("--vsock-port")
+            .arg(config.vsock_port.to_string())
+            .arg("--vsock-fd")
+            .arg(fd.to_string());
+    }
+    if let Some(path) = &config.policy_path {
+        cmd.arg("--policy").arg(path);
+    }
+    cmd.arg("--metrics-ttl-ms")
+        .arg(config.metrics_ttl.as_millis().to_string());
+    cmd.arg("--fstrim-interval")
+        .arg(config.trim_interval.as_secs().to_string());
+    cmd.arg("--metrics-interval")
+        .arg(config.sample_interval.as_secs().to_string());
+    cmd.arg("--pid-file").arg(&config.pid_file);
+
+    log::info!("Executing staged agent binary {}", STAGED_AGENT_PATH);
+    cmd.exec()
+}
+
+/// An error response the host maps onto its own error kinds
+fn failed(code: ErrorCode, message: impl Into<String>) -> Response {
+    Response::Failed(ErrorProto {
+        code,
+        message: message.into(),
+    })
 }
 
 fn handle_request(request: Request, state: &AgentState) -> Response {
+    if DRAINING.load(Ordering::SeqCst) && is_mutating(&request) {
+        return failed(
+            ErrorCode::Unavailable,
+            "Agent is draining for an upgrade, retry shortly",
+        );
+    }
+
     match request {
         Request::Create(req) => {
             // Validate request
-            if req.id.is_empty() {
-                return Response::Error("Container ID cannot be empty".to_string());
+            if let Some(reason) = invalid_container_id(&req.id) {
+                return failed(ErrorCode::InvalidArgument, reason);
             }
             if req.command.is_empty() {
-                return Response::Error("Command cannot be empty".to_string());
+                return failed(ErrorCode::InvalidArgument, "Command cannot be empty");
+            }
+            let name = if req.name.is_empty() {
+                req.id.clone()
+            } else if let Some(reason) = invalid_container_name(&req.name) {
+                return failed(ErrorCode::InvalidArgument, reason);
+            } else {
+                req.name.clone()
+            };
+
+            let violations = state.policy.evaluate_create(&req);
+            if !violations.is_empty() {
+                log::warn!("Admission policy denied create of '{}'", req.id);
+                return Response::Denied(violations);
             }
 
+            // Held until the container is tracked, so a concurrent create of
+            // the same ID cannot undo what this one makes
+            #[cfg(target_os = "linux")]
+            let mut guard = match CreateGuard::new(state, &req.id) {
+                Some(guard) => guard,
+                None => {
+                    return failed(
+                        ErrorCode::Conflict,
+                        format!("Container '{}' is being created", req.id),
+                    )
+                }
+            };
+
             // Check if container already exists
             {
                 let containers = state.containers.read().unwrap();
                 if containers.contains_key(&req.id) {
-                    return Response::Error(format!("Container '{}' already exists", req.id));
+                    return failed(
+                        ErrorCode::Conflict,
+                        format!("Container '{}' already exists", req.id),
+                    );
+                }
+                if name_taken(&containers, &name, &req.id) {
+                    return failed(
+                        ErrorCode::Conflict,
+                        format!("The name '{}' is already in use", name),
+                    );
                 }
             }
 
             log::info!("Creating container: id={}, rootfs={}", req.id, req.rootfs);
 
+            let shared_namespaces = match state.shared_namespace_paths(&req) {
+                Ok(paths) => paths,
+                Err(e) => return Response::Error(e),
+            };
+
+            // The container writes to a layer of its own over the image; a
+            // read-only root needs none
+            #[cfg(target_os = "linux")]
+            let snapshot = PathBuf::from(SNAPSHOTS_DIR).join(&req.id);
+            #[cfg(target_os = "linux")]
+            let rootfs = if state.libcrun_available && req.rootfs_snapshot && !req.read_only_rootfs
+            {
+                progress::report("snapshot", None, "Snapshotting the root filesystem");
+                // Made part way before failing, too
+                guard.snapshot = Some(snapshot.clone());
+                match crun::snapshot_rootfs(Path::new(&req.rootfs), &snapshot) {
+                    Ok(path) => path.display().to_string(),
+                    Err(e) => {
+                        return failed(
+                            ErrorCode::Internal,
+                            format!("Failed to snapshot the rootfs of '{}': {}", req.id, e),
+                        )
+                    }
+                }
+            } else {
+                req.rootfs.clone()
+            };
+
+            // libcrun only fails at start on a missing working directory,
+            // and without saying which. A shared image is never written to
+            #[cfg(target_os = "linux")]
+            {
+                let shared = req.rootfs_snapshot && guard.snapshot.is_none();
+                let create = req.create_working_dir && !req.read_only_rootfs && !shared;
+                if let Err(e) =
+                    crun::prepare_working_dir(Path::new(&rootfs), &req.working_dir, create)
+                {
+                    let reason = match e.kind() {
+                        std::io::ErrorKind::NotFound => "does not exist".to_string(),
+                        _ => format!("cannot be used ({})", e),
+                    };
+                    return failed(
+                        ErrorCode::InvalidArgument,
+                        format!(
+                            "Working directory '{}' {} in the root filesystem {}",
+                            req.working_dir, reason, req.rootfs
+                        ),
+                    );
+                }
+            }
+
+            // Containers on the bridge get an address there, unless they
+            // join the network namespace of another container
+            #[cfg(target_os = "linux")]
+            let mut network = req.network.clone();
+            #[cfg(target_os = "linux")]
+            if state.libcrun_available
+                && network.mode == "bridge"
+                && !shared_namespaces
+                    .iter()
+                    .any(|(ns_type, _)| *ns_type == "network")
+            {
+                progress::report("network", None, "Attaching to the bridge network");
+                match state.bridge.attach(&req.id) {
+                    Ok(Some(interface)) => {
+                        guard.attached = true;
+                        network.interfaces.push(interface)
+                    }
+                    Ok(None) => {}
+                    Err(e) => return failed(ErrorCode::Unavailable, e),
+                }
+            }
+
             // Try to use libcrun if available
             #[cfg(target_os = "linux")]
             let libcrun_container = if state.libcrun_available {
                 // Build OCI config JSON
                 let oci_json = match AgentState::build_oci_config_json(
-                    &req.rootfs,
+                    &rootfs,
                     &req.command,
                     &req.env,
                     &req.working_dir,
                     &req.id,
                     &req.stdio,
-                    &req.network,
+                    &network,
                     &req.volumes,
                     &req.resources,
+                    &req.security,
+                    &shared_namespaces,
+                    req.read_only_rootfs,
                 ) {
                     Ok(json) => json,
                     Err(e) => {
@@ -1159,12 +3689,37 @@ fn handle_request(request: Request, state: &AgentState) -> Response {
                     Ok(container) => {
                         // Create the container using libcrun
                         if let Some(LibcrunContext(ctx)) = &state.libcrun_context {
-                            match crun::container_create(*ctx, container, &req.id) {
+                            // The container inherits the agent's stdio; capture it
+                            // into the structured log unless it runs on a terminal
+                            let capture = if req.stdio.tty {
+                                None
+                            } else {
+                                let log_dir = PathBuf::from(CONTAINER_LOG_DIR).join(&req.id);
+                                records::StdioCapture::start(&log_dir)
+                                    .map_err(|e| {
+                                        log::warn!(
+                                            "Failed to capture output of '{}': {}",
+                                            req.id,
+                                            e
+                                        )
+                                    })
+                                    .ok()
+                            };
+                            progress::report("create", None, "Creating the container");
+                            // What libcrun leaves of a failed create is ours
+                            // to remove, unless the ID had state before
+                            guard.owns_state = crun::state_dir(&req.id).is_none();
+                            let created = crun::container_create(*ctx, container, &req.id);
+                            drop(capture);
+                            match created {
                                 Ok(_) => {
                                     log::info!(
                                         "Container '{}' created successfully via libcrun",
                                         req.id
                                     );
+                                    save_oci_config(&req.id, &oci_json);
+                                    warn_unapplied_limits(&req.id, &req.resources);
+                                    guard.container = Some(container);
                                     Some(LibcrunContainer(container))
                                 }
                                 Err(e) => {
@@ -1192,6 +3747,21 @@ fn handle_request(request: Request, state: &AgentState) -> Response {
                 None
             };
 
+            // Without libcrun the container runs in the VM's network
+            #[cfg(target_os = "linux")]
+            let ip_address = if libcrun_container.is_some() {
+                state.bridge.address(&req.id)
+            } else {
+                state.bridge.release(&req.id);
+                None
+            };
+
+            #[cfg(not(target_os = "linux"))]
+            let _ = &shared_namespaces;
+
+            #[cfg(not(target_os = "linux"))]
+            let ip_address = None;
+
             #[cfg(not(target_os = "linux"))]
             let _libcrun_container: Option<*mut libcrun_sys::libcrun_container_t> = None;
 
@@ -1222,47 +3792,91 @@ fn handle_request(request: Request, state: &AgentState) -> Response {
 
             let container_state = ContainerState {
                 id: req.id.clone(),
+                name,
                 rootfs: req.rootfs,
                 command: req.command,
                 env: req.env,
                 working_dir: req.working_dir,
-                status: "Created".to_string(),
+                status: ContainerStatus::Created,
                 pid: None,
                 created_at: current_timestamp(),
                 health_check,
                 last_health_check: None,
-                health_status: "unknown".to_string(),
+                health_status: "starting".to_string(),
                 consecutive_failures: 0,
+                last_output: String::new(),
+                max_runtime_secs: req.max_runtime_secs,
+                stop_timeout: req.stop_timeout,
+                started_at: None,
+                exit_reason: None,
+                restart_count: 0,
+                last_exit_code: None,
+                last_exit_reason: None,
+                oom_kills: 0,
+                restart_policy: req.restart_policy,
+                restart_retries: 0,
+                next_restart_at: None,
+                ip_address,
+                labels: req.labels,
+                auto_stop: req.auto_stop.map(|p| AutoStopConfig {
+                    idle_secs: p.idle_secs,
+                    cpu_percent: p.cpu_percent,
+                }),
+                idle_sample: None,
+                execs: Vec::new(),
+                execs_in_flight: 0,
+                orphaned: false,
                 #[cfg(target_os = "linux")]
-                libcrun_container,
-            };
-
-            state
-                .containers
-                .write()
-                .unwrap()
-                .insert(req.id.clone(), container_state);
+                libcrun_container,
+            };
+
+            {
+                let mut containers = state.containers.write().unwrap();
+                // Checked again, another create may have taken the name since
+                if name_taken(&containers, &container_state.name, &req.id) {
+                    return failed(
+                        ErrorCode::Conflict,
+                        format!("The name '{}' is already in use", container_state.name),
+                    );
+                }
+                containers.insert(req.id.clone(), container_state);
+            }
+            #[cfg(target_os = "linux")]
+            guard.commit();
             state.persist_state();
             Response::Created(req.id)
         }
         Request::Start(id) => {
+            // Evaluated before taking the lock, the webhook may be slow
+            let violations = state.policy.evaluate_start(&id);
+            if !violations.is_empty() {
+                log::warn!("Admission policy denied start of '{}'", id);
+                return Response::Denied(violations);
+            }
+
             let mut containers = state.containers.write().unwrap();
             let container = containers.get_mut(&id);
 
             match container {
-                None => Response::Error(format!("Container '{}' not found", id)),
+                None => failed(ErrorCode::NotFound, format!("Container '{}' not found", id)),
                 Some(c) => {
-                    if c.status == "Running" {
-                        Response::Error(format!("Container '{}' is already running", id))
-                    } else if c.status == "Stopped" {
-                        Response::Error(format!(
-                            "Container '{}' is stopped and cannot be restarted",
-                            id
-                        ))
+                    if let Err(e) = state.lifecycle.check(&id, c.status, Transition::Start) {
+                        failed(ErrorCode::Conflict, e.to_string())
                     } else {
-                        // Try to start container via libcrun if available
+                        // A stopped container runs again from the OCI config
+                        // it was created with, a scheduled restart included
                         #[cfg(target_os = "linux")]
-                        if state.libcrun_available {
+                        if state.libcrun_available && c.status == ContainerStatus::Stopped {
+                            match state.rerun_container(c) {
+                                Ok(pid) => c.pid = Some(pid),
+                                Err(e) => {
+                                    return failed(
+                                        ErrorCode::Internal,
+                                        format!("Failed to start container '{}' again: {}", id, e),
+                                    )
+                                }
+                            }
+                        } else if state.libcrun_available {
                             if let Some(LibcrunContainer(container)) = c.libcrun_container {
                                 if let Some(LibcrunContext(ctx)) = &state.libcrun_context {
                                     match crun::container_start(*ctx, container, &id) {
@@ -1297,11 +3911,19 @@ fn handle_request(request: Request, state: &AgentState) -> Response {
                             }
                         }
 
-                        if c.status != "Running" {
+                        if c.pid.is_none() {
                             log::info!("Starting container: {} (fallback mode)", id);
-                            c.status = "Running".to_string();
                             c.pid = Some(std::process::id()); // Placeholder
                         }
+                        let _ = state.lifecycle.apply(&id, &mut c.status, Transition::Start);
+                        // Started by hand, the restart policy starts over
+                        c.next_restart_at = None;
+                        c.restart_retries = 0;
+                        if c.started_at.is_some() {
+                            c.restart_count += 1;
+                        }
+                        c.started_at = Some(current_timestamp());
+                        c.oom_kills = 0;
 
                         drop(containers);
                         state.persist_state();
@@ -1310,48 +3932,14 @@ fn handle_request(request: Request, state: &AgentState) -> Response {
                 }
             }
         }
-        Request::Stop(id) => {
-            let mut containers = state.containers.write().unwrap();
-            let container = containers.get_mut(&id);
-
-            match container {
-                None => Response::Error(format!("Container '{}' not found", id)),
-                Some(c) => {
-                    if c.status != "Running" {
-                        Response::Error(format!("Container '{}' is not running", id))
-                    } else {
-                        // Try to stop container via libcrun if available
-                        #[cfg(target_os = "linux")]
-                        if state.libcrun_available {
-                            if let Some(LibcrunContainer(container)) = c.libcrun_container {
-                                if let Some(LibcrunContext(ctx)) = &state.libcrun_context {
-                                    // Use SIGTERM to stop gracefully
-                                    match crun::container_kill(*ctx, container, &id, libc::SIGTERM)
-                                    {
-                                        Ok(_) => {
-                                            log::info!("Container '{}' stopped successfully via libcrun (SIGTERM)", id);
-                                        }
-                                        Err(e) => {
-                                            return Response::Error(format!(
-                                                "libcrun failed to stop container: {}",
-                                                e.message
-                                            ));
-                                        }
-                                    }
-                                    // Put container back
-                                    c.libcrun_container = Some(LibcrunContainer(container));
-                                }
-                            }
-                        }
-
-                        log::info!("Stopping container: {}", id);
-                        c.status = "Stopped".to_string();
-                        c.pid = None;
-                        drop(containers);
-                        state.persist_state();
-                        Response::Stopped
-                    }
-                }
+        Request::Stop(id) => match state.stop_container(&id, None) {
+            Ok(()) => Response::Stopped,
+            Err(e) => Response::Failed(e),
+        },
+        Request::StopWithTimeout(req) => {
+            match state.stop_container(&req.id, Some(req.timeout_secs)) {
+                Ok(()) => Response::Stopped,
+                Err(e) => Response::Failed(e),
             }
         }
         Request::Delete(id) => {
@@ -1359,13 +3947,13 @@ fn handle_request(request: Request, state: &AgentState) -> Response {
             let container = containers.get(&id);
 
             match container {
-                None => Response::Error(format!("Container '{}' not found", id)),
+                None => failed(ErrorCode::NotFound, format!("Container '{}' not found", id)),
                 Some(c) => {
-                    if c.status == "Running" {
-                        Response::Error(format!(
-                            "Cannot delete running container '{}'. Stop it first.",
-                            id
-                        ))
+                    if let Err(e) = state
+                        .lifecycle
+                        .check_delete(&id, c.status, c.execs_in_flight)
+                    {
+                        failed(ErrorCode::Conflict, e.to_string())
                     } else {
                         // Try to delete container via libcrun if available
                         #[cfg(target_os = "linux")]
@@ -1393,9 +3981,20 @@ fn handle_request(request: Request, state: &AgentState) -> Response {
                         // Clean up any container-specific state files
                         let container_state_dir = format!("{}/{}", STATE_DIR, id);
                         let _ = std::fs::remove_dir_all(&container_state_dir);
+                        #[cfg(target_os = "linux")]
+                        if let Err(e) =
+                            crun::remove_snapshot(&PathBuf::from(SNAPSHOTS_DIR).join(&id))
+                        {
+                            log::warn!("Failed to remove the rootfs snapshot of '{}': {}", id, e);
+                        }
 
                         log::info!("Deleting container: {}", id);
-                        containers.remove(&id);
+                        if let Some(mut c) = containers.remove(&id) {
+                            let _ = state
+                                .lifecycle
+                                .apply(&id, &mut c.status, Transition::Delete);
+                        }
+                        state.bridge.release(&id);
                         drop(containers);
                         state.persist_state();
                         Response::Deleted
@@ -1403,135 +4002,580 @@ fn handle_request(request: Request, state: &AgentState) -> Response {
                 }
             }
         }
-        Request::List => {
-            let containers = state.containers.read().unwrap();
-            let list: Vec<ContainerInfoProto> = containers
-                .values()
-                .map(|c| ContainerInfoProto {
-                    id: c.id.clone(),
-                    status: c.status.clone(),
-                    pid: c.pid,
-                })
-                .collect();
-
-            Response::List(list)
-        }
+        Request::List => list_containers(state, &ListFilterProto::default()),
+        Request::ListFiltered(filter) => list_containers(state, &filter),
+        Request::StaleResources => Response::StaleResources(state.stale_resources(false)),
+        Request::RemoveStaleResources => Response::StaleResources(state.stale_resources(true)),
         Request::Metrics(id) => {
             let containers = state.containers.read().unwrap();
             match containers.get(&id) {
                 Some(container) => {
-                    let metrics = collect_container_metrics(&id, container.pid);
+                    let metrics = state.container_metrics(&id, container.pid);
                     Response::Metrics(metrics)
                 }
-                None => Response::Error(format!("Container not found: {}", id)),
+                None => failed(ErrorCode::NotFound, format!("Container not found: {}", id)),
             }
         }
         Request::AllMetrics => {
             let containers = state.containers.read().unwrap();
             let metrics: Vec<ContainerMetricsProto> = containers
                 .iter()
-                .map(|(id, c)| collect_container_metrics(id, c.pid))
+                .map(|(id, c)| state.container_metrics(id, c.pid))
                 .collect();
             Response::AllMetrics(metrics)
         }
         Request::Logs(req) => {
             let containers = state.containers.read().unwrap();
             if !containers.contains_key(&req.id) {
-                return Response::Error(format!("Container not found: {}", req.id));
+                return failed(
+                    ErrorCode::NotFound,
+                    format!("Container not found: {}", req.id),
+                );
             }
 
-            // Read logs from container log directory
-            let log_dir = format!("/var/log/containers/{}", req.id);
-            let stdout = read_log_file(&format!("{}/stdout.log", log_dir), req.tail);
-            let stderr = read_log_file(&format!("{}/stderr.log", log_dir), req.tail);
-
-            let timestamp = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .map(|d| d.as_secs())
-                .unwrap_or(0);
-
-            Response::Logs(libcrun_shim_proto::LogsProto {
-                id: req.id,
-                stdout,
-                stderr,
-                timestamp,
-            })
+            drop(containers);
+            Response::Logs(read_container_logs(req))
         }
         Request::Health(id) => {
             let containers = state.containers.read().unwrap();
             match containers.get(&id) {
                 Some(container) => {
-                    // Basic health check based on container state
-                    let status = if container.status == "running" {
-                        "healthy"
-                    } else if container.status == "created" {
-                        "starting"
-                    } else {
-                        "none"
+                    let status = match (&container.health_check, container.health_status.as_str()) {
+                        (None, _) => "none",
+                        (Some(_), status @ ("healthy" | "unhealthy")) => status,
+                        (Some(_), _) => "starting",
                     };
-
                     Response::Health(libcrun_shim_proto::HealthStatusProto {
                         id: id.clone(),
                         status: status.to_string(),
-                        failing_streak: 0,
-                        last_output: String::new(),
-                        last_check: std::time::SystemTime::now()
-                            .duration_since(std::time::UNIX_EPOCH)
-                            .map(|d| d.as_secs())
-                            .unwrap_or(0),
+                        failing_streak: container.consecutive_failures,
+                        last_output: container.last_output.clone(),
+                        last_check: container.last_health_check.unwrap_or(0),
                     })
                 }
-                None => Response::Error(format!("Container not found: {}", id)),
+                None => failed(ErrorCode::NotFound, format!("Container not found: {}", id)),
             }
         }
         Request::Exec(req) => {
-            let containers = state.containers.read().unwrap();
-            let container = match containers.get(&req.id) {
-                Some(c) => c,
-                None => return Response::Error(format!("Container not found: {}", req.id)),
+            let _exec = match ExecGuard::new(state, &req.id) {
+                Ok(guard) => guard,
+                Err(e) => return Response::Failed(e),
             };
 
-            if container.status != "running" {
-                return Response::Error(format!("Container '{}' is not running", req.id));
+            // libcrun joins the container's namespaces and cgroup itself
+            #[cfg(target_os = "linux")]
+            if let Some(LibcrunContext(ctx)) = &state.libcrun_context {
+                let process = exec_process(&req);
+                let started_at = current_timestamp();
+                let started = std::time::Instant::now();
+                let output = crun::container_exec(*ctx, &req.id, &process);
+                let exit_code = output.as_ref().map(|o| o.exit_code).unwrap_or(-1);
+                record_exec(state, req, started_at, started.elapsed(), exit_code);
+
+                return match output {
+                    Ok(output) => Response::Exec(libcrun_shim_proto::ExecResultProto {
+                        exit_code,
+                        stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+                        stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+                    }),
+                    Err(e) => Response::Error(format!("Failed to execute command: {}", e.message)),
+                };
             }
 
-            // Execute command using nsenter
+            Response::Error(
+                "Exec requires libcrun, which is not available in the agent".to_string(),
+            )
+        }
+        Request::ExecSessions(id) => match state.containers.read().unwrap().get(&id) {
+            Some(container) => Response::ExecSessions(container.execs.clone()),
+            None => failed(ErrorCode::NotFound, format!("Container not found: {}", id)),
+        },
+        Request::Handshake => Response::Handshake(HandshakeProto {
+            agent_version: env!("CARGO_PKG_VERSION").to_string(),
+            protocol_version: PROTOCOL_VERSION,
+        }),
+        Request::Features => {
+            Response::Features(AGENT_FEATURES.iter().map(|f| f.to_string()).collect())
+        }
+        Request::KernelFeatures => Response::KernelFeatures(state.kernel.clone()),
+        Request::DiskUsage => Response::DiskUsage(disks::usage()),
+        Request::MemoryInfo => Response::MemoryInfo(meminfo::read()),
+        Request::ListeningPorts(id) => {
+            if let Some(response) = exec_target_error(state, &id) {
+                return response;
+            }
+            let pid = state
+                .containers
+                .read()
+                .unwrap()
+                .get(&id)
+                .and_then(|c| c.pid);
+            let Some(pid) = pid else {
+                return failed(
+                    ErrorCode::Conflict,
+                    format!("Container '{}' has no process", id),
+                );
+            };
             #[cfg(target_os = "linux")]
-            if let Some(pid) = container.pid {
-                let mut cmd = std::process::Command::new("nsenter");
-                cmd.args(&["-t", &pid.to_string(), "-m", "-u", "-i", "-n", "-p", "--"]);
-                cmd.args(&req.command);
-
-                match cmd.output() {
-                    Ok(output) => {
-                        return Response::Exec(libcrun_shim_proto::ExecResultProto {
-                            exit_code: output.status.code().unwrap_or(-1),
-                            stdout: String::from_utf8_lossy(&output.stdout).to_string(),
-                            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
-                        });
+            let ports = crun::listening_ports(pid);
+            #[cfg(not(target_os = "linux"))]
+            let ports: std::io::Result<Vec<u16>> = Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("no socket tables of process {}", pid),
+            ));
+            match ports {
+                Ok(ports) => Response::ListeningPorts(ports),
+                Err(e) => failed(
+                    ErrorCode::Internal,
+                    format!("Failed to read sockets of '{}': {}", id, e),
+                ),
+            }
+        }
+        Request::SyncTime(req) => match clock::sync(&req) {
+            Ok(sync) => Response::TimeSynced(sync),
+            Err(e) => failed(
+                ErrorCode::Internal,
+                format!("Failed to set the clock: {}", e),
+            ),
+        },
+        Request::Pause(id) => state.set_paused(&id, true),
+        Request::Unpause(id) => state.set_paused(&id, false),
+        Request::Rename(req) => {
+            if let Some(reason) = invalid_container_name(&req.name) {
+                return failed(ErrorCode::InvalidArgument, reason);
+            }
+            let mut containers = state.containers.write().unwrap();
+            if name_taken(&containers, &req.name, &req.id) {
+                return failed(
+                    ErrorCode::Conflict,
+                    format!("The name '{}' is already in use", req.name),
+                );
+            }
+            let Some(c) = containers.get_mut(&req.id) else {
+                return failed(
+                    ErrorCode::NotFound,
+                    format!("Container '{}' not found", req.id),
+                );
+            };
+            log::info!(
+                "Renaming container {} from '{}' to '{}'",
+                req.id,
+                c.name,
+                req.name
+            );
+            c.name = req.name;
+            drop(containers);
+            state.persist_state();
+            Response::Renamed
+        }
+        Request::Version => Response::Version(VersionProto {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            git_sha: option_env!("GIT_SHA").map(str::to_string),
+            build_timestamp: env!("BUILD_TIMESTAMP").parse().unwrap_or(0),
+            libcrun_version: libcrun_sys::LIBCRUN_VERSION.map(str::to_string),
+            min_protocol_version: MIN_PROTOCOL_VERSION,
+            max_protocol_version: PROTOCOL_VERSION,
+        }),
+        // Takes over the connection, so the connection loop runs it
+        Request::ExecStream(_) => failed(
+            ErrorCode::Internal,
+            "Interactive exec is only served on a client connection",
+        ),
+        Request::AttachExec(_) => failed(
+            ErrorCode::Internal,
+            "Attaching to exec sessions is only served on a client connection",
+        ),
+        Request::LogsStream(_) => failed(
+            ErrorCode::Internal,
+            "Following logs is only served on a client connection",
+        ),
+        Request::Wait(_) => failed(
+            ErrorCode::Internal,
+            "Waiting is only served on a client connection",
+        ),
+        Request::SubscribeEvents => failed(
+            ErrorCode::Internal,
+            "Events are only served on a client connection",
+        ),
+        Request::WithProgress(..) => failed(
+            ErrorCode::Internal,
+            "Progress is only reported on a client connection",
+        ),
+        Request::Trim => Response::Trimmed(disks::trim_all()),
+        Request::MetricsHistory(since) => {
+            Response::MetricsHistory(state.history.lock().unwrap().since(since))
+        }
+        Request::MissingBlobs(digests) => {
+            if let Some(bad) = digests.iter().find(|d| !blobs::is_valid_digest(d)) {
+                return failed(
+                    ErrorCode::InvalidArgument,
+                    format!("Invalid blob digest '{}'", bad),
+                );
+            }
+            Response::MissingBlobs(blobs::BlobStore::new(blobs::BLOBS_DIR).missing(&digests))
+        }
+        Request::PutBlob(chunk) => {
+            if !blobs::is_valid_digest(&chunk.digest) {
+                return failed(
+                    ErrorCode::InvalidArgument,
+                    format!("Invalid blob digest '{}'", chunk.digest),
+                );
+            }
+            match blobs::BlobStore::new(blobs::BLOBS_DIR).put(&chunk) {
+                Ok(()) => {
+                    if chunk.last {
+                        log::info!("Cached blob {}", chunk.digest);
                     }
-                    Err(e) => {
-                        return Response::Error(format!("Failed to execute command: {}", e));
+                    Response::BlobStored
+                }
+                Err(e) => failed(ErrorCode::Internal, e),
+            }
+        }
+        // Handled by the connection, which owns the role
+        Request::SetRole(role) => Response::RoleSet(role),
+        Request::Copy(_) => failed(
+            ErrorCode::Internal,
+            "Copying is only served on a client connection",
+        ),
+        Request::CreateCheckpoint(req) => state.create_checkpoint(req),
+        Request::ListCheckpoints(container) => {
+            match container.as_deref().and_then(invalid_container_id) {
+                Some(reason) => failed(ErrorCode::InvalidArgument, reason),
+                None => Response::Checkpoints(
+                    checkpoints::CheckpointStore::new(checkpoints::CHECKPOINTS_DIR)
+                        .list(container.as_deref()),
+                ),
+            }
+        }
+        Request::RemoveCheckpoint(CheckpointRef { container, name }) => {
+            let store = checkpoints::CheckpointStore::new(checkpoints::CHECKPOINTS_DIR);
+            if let Some(reason) = invalid_checkpoint_ref(&container, &name) {
+                failed(ErrorCode::InvalidArgument, reason)
+            } else if store.get(&container, &name).is_none() {
+                failed(
+                    ErrorCode::NotFound,
+                    format!("Checkpoint '{}' of '{}' not found", name, container),
+                )
+            } else {
+                match store.remove(&container, &name) {
+                    Ok(()) => Response::CheckpointRemoved,
+                    Err(e) => failed(ErrorCode::Internal, e),
+                }
+            }
+        }
+        Request::RestoreCheckpoint(req) => state.restore_checkpoint(req),
+        Request::CheckpointFiles(CheckpointRef { container, name }) => {
+            let store = checkpoints::CheckpointStore::new(checkpoints::CHECKPOINTS_DIR);
+            if let Some(reason) = invalid_checkpoint_ref(&container, &name) {
+                return failed(ErrorCode::InvalidArgument, reason);
+            }
+            let Some(checkpoint) = store.get(&container, &name) else {
+                return failed(
+                    ErrorCode::NotFound,
+                    format!("Checkpoint '{}' of '{}' not found", name, container),
+                );
+            };
+            match store.files(&container, &name) {
+                Ok(files) => Response::CheckpointFiles(CheckpointFilesProto { checkpoint, files }),
+                Err(e) => failed(
+                    ErrorCode::Internal,
+                    format!("Failed to list checkpoint files: {}", e),
+                ),
+            }
+        }
+        Request::ReadCheckpointFile(req) => {
+            let store = checkpoints::CheckpointStore::new(checkpoints::CHECKPOINTS_DIR);
+            let CheckpointRef { container, name } = &req.checkpoint;
+            if let Some(reason) = invalid_checkpoint_ref(container, name) {
+                return failed(ErrorCode::InvalidArgument, reason);
+            }
+            let Some(path) = store.file(container, name, &req.path) else {
+                return failed(
+                    ErrorCode::InvalidArgument,
+                    format!("Invalid checkpoint file '{}'", req.path),
+                );
+            };
+            if store.get(container, name).is_none() {
+                return failed(
+                    ErrorCode::NotFound,
+                    format!("Checkpoint '{}' of '{}' not found", name, container),
+                );
+            }
+            match checkpoints::read_chunk(&path, req.offset) {
+                Ok(data) => Response::CheckpointData(data),
+                Err(e) => failed(ErrorCode::Internal, e),
+            }
+        }
+        Request::WriteCheckpointFile(chunk) => {
+            let store = checkpoints::CheckpointStore::new(checkpoints::CHECKPOINTS_DIR);
+            let CheckpointRef { container, name } = &chunk.checkpoint;
+            if let Some(reason) = invalid_checkpoint_ref(container, name) {
+                return failed(ErrorCode::InvalidArgument, reason);
+            }
+            let Some(path) = store.file(container, name, &chunk.path) else {
+                return failed(
+                    ErrorCode::InvalidArgument,
+                    format!("Invalid checkpoint file '{}'", chunk.path),
+                );
+            };
+            if store.get(container, name).is_some() {
+                return failed(
+                    ErrorCode::Conflict,
+                    format!("Checkpoint '{}' of '{}' already exists", name, container),
+                );
+            }
+            match checkpoints::write_chunk(&path, chunk.offset, &chunk.data) {
+                Ok(()) => Response::CheckpointFileWritten,
+                Err(e) => failed(ErrorCode::Internal, e),
+            }
+        }
+        Request::Prune(filter) => {
+            let matching: Vec<String> = state
+                .containers
+                .read()
+                .unwrap()
+                .values()
+                .filter(|c| c.status == ContainerStatus::Stopped && prune_matches(&filter, c))
+                .map(|c| c.id.clone())
+                .collect();
+            // Deleted one at a time like any other, skipping those that
+            // were started again in the meantime
+            let pruned: Vec<String> = matching
+                .into_iter()
+                .filter(|id| {
+                    matches!(
+                        handle_request(Request::Delete(id.clone()), state),
+                        Response::Deleted
+                    )
+                })
+                .collect();
+            log::info!("Pruned {} stopped container(s)", pruned.len());
+            Response::Pruned(pruned)
+        }
+        Request::Resolve(host) => {
+            use std::net::ToSocketAddrs;
+            match (host.as_str(), 0).to_socket_addrs() {
+                Ok(resolved) => {
+                    // One entry per socket type, so the same address repeats
+                    let mut addresses: Vec<String> = Vec::new();
+                    for address in resolved.map(|a| a.ip().to_string()) {
+                        if !addresses.contains(&address) {
+                            addresses.push(address);
+                        }
+                    }
+                    Response::Resolved(addresses)
+                }
+                Err(e) => failed(
+                    ErrorCode::NotFound,
+                    format!("Failed to resolve {}: {}", host, e),
+                ),
+            }
+        }
+        Request::Diagnostics => Response::Diagnostics(diagnostics::collect(Path::new(STATE_DIR))),
+        Request::ImportCheckpoint(checkpoint) => {
+            let store = checkpoints::CheckpointStore::new(checkpoints::CHECKPOINTS_DIR);
+            if let Some(reason) = invalid_checkpoint_ref(&checkpoint.container, &checkpoint.name) {
+                return failed(ErrorCode::InvalidArgument, reason);
+            }
+            if store.get(&checkpoint.container, &checkpoint.name).is_some() {
+                return failed(
+                    ErrorCode::Conflict,
+                    format!(
+                        "Checkpoint '{}' of '{}' already exists",
+                        checkpoint.name, checkpoint.container
+                    ),
+                );
+            }
+            progress::report("verify", None, "Verifying the checkpoint image");
+            match store.import(&checkpoint) {
+                Ok(checkpoint) => {
+                    log::info!(
+                        "Imported checkpoint '{}' of '{}'",
+                        checkpoint.name,
+                        checkpoint.container
+                    );
+                    Response::Checkpoint(checkpoint)
+                }
+                Err(e) => failed(ErrorCode::InvalidArgument, e),
+            }
+        }
+        Request::Mount(req) => {
+            if let Err(reason) = mounts::validate(&req) {
+                return failed(ErrorCode::InvalidArgument, reason);
+            }
+            let pid = match state.containers.read().unwrap().get(&req.id) {
+                Some(c) => match c.pid {
+                    Some(pid) if c.status == ContainerStatus::Running => pid,
+                    _ => {
+                        return failed(
+                            ErrorCode::Conflict,
+                            format!("Container '{}' is not running", req.id),
+                        )
                     }
+                },
+                None => {
+                    return failed(
+                        ErrorCode::NotFound,
+                        format!("Container not found: {}", req.id),
+                    )
+                }
+            };
+            if let Err(e) = mounts::mount_shares() {
+                return failed(
+                    ErrorCode::Internal,
+                    format!("Failed to mount shared directories: {}", e),
+                );
+            }
+            let source = std::path::Path::new(mounts::SHARES_DIR).join(&req.share);
+            if !source.exists() {
+                return failed(
+                    ErrorCode::NotFound,
+                    format!("Shared directory '{}' not found", req.share),
+                );
+            }
+            match mounts::bind_into(pid, &source, &req.destination, req.read_only) {
+                Ok(()) => {
+                    log::info!(
+                        "Mounted share {} at {} in container {}",
+                        req.share,
+                        req.destination,
+                        req.id
+                    );
+                    Response::Mounted
+                }
+                Err(e) => failed(
+                    ErrorCode::Internal,
+                    format!("Failed to mount at {}: {}", req.destination, e),
+                ),
+            }
+        }
+        Request::UploadAgent(req) => match stage_agent_binary(req) {
+            Ok(path) => Response::AgentUploaded(path),
+            Err(e) => Response::Error(e),
+        },
+        Request::Drain => {
+            DRAINING.store(true, Ordering::SeqCst);
+            log::info!("Draining agent: waiting for in-flight requests");
+
+            // This request itself is counted as in flight
+            let deadline = std::time::Instant::now() + std::time::Duration::from_secs(30);
+            while IN_FLIGHT.load(Ordering::SeqCst) > 1 {
+                if std::time::Instant::now() > deadline {
+                    DRAINING.store(false, Ordering::SeqCst);
+                    return failed(
+                        ErrorCode::Unavailable,
+                        "Timed out waiting for in-flight requests",
+                    );
                 }
+                std::thread::sleep(std::time::Duration::from_millis(50));
             }
 
-            Response::Error("Container PID not available".to_string())
+            state.persist_state();
+            Response::Drained
+        }
+        Request::UpgradeAgent(req) => {
+            if !DRAINING.load(Ordering::SeqCst) {
+                return failed(
+                    ErrorCode::Conflict,
+                    "Agent must be drained before upgrading",
+                );
+            }
+            match sha256_file(STAGED_AGENT_PATH) {
+                Ok(actual) if actual.eq_ignore_ascii_case(&req.sha256) => {
+                    UPGRADE_REQUESTED.store(true, Ordering::SeqCst);
+                    Response::Upgrading
+                }
+                // Not upgrading after all, the host may retry or undrain
+                Ok(actual) => {
+                    DRAINING.store(false, Ordering::SeqCst);
+                    Response::Error(format!(
+                        "Staged agent checksum {} does not match requested {}",
+                        actual, req.sha256
+                    ))
+                }
+                Err(e) => {
+                    DRAINING.store(false, Ordering::SeqCst);
+                    Response::Error(format!("No staged agent binary: {}", e))
+                }
+            }
+        }
+        Request::Undrain => {
+            if UPGRADE_REQUESTED.load(Ordering::SeqCst) {
+                return failed(ErrorCode::Conflict, "Agent is upgrading");
+            }
+            DRAINING.store(false, Ordering::SeqCst);
+            log::info!("Agent undrained, accepting changes again");
+            Response::Undrained
         }
     }
 }
 
-fn read_log_file(path: &str, tail: u32) -> String {
-    if let Ok(content) = std::fs::read_to_string(path) {
-        if tail > 0 {
-            let lines: Vec<&str> = content.lines().collect();
-            let start = lines.len().saturating_sub(tail as usize);
-            lines[start..].join("\n")
-        } else {
-            content
+/// Read a container's logs, preferring the structured log over raw stream files
+fn read_container_logs(req: LogsRequest) -> LogsProto {
+    let log_dir = PathBuf::from(CONTAINER_LOG_DIR).join(&req.id);
+    let timestamp = current_timestamp();
+
+    let structured = log_dir.join(records::STRUCTURED_LOG);
+    if structured.exists() {
+        let query = records::RecordQuery {
+            tail: req.tail,
+            since_ns: req.since.saturating_mul(1_000_000_000),
+            until_ns: req.until.saturating_mul(1_000_000_000),
+            // Both streams live in one file, so either offset continues it
+            offset: req.stdout_offset.or(req.stderr_offset),
+            max_bytes: req.max_bytes,
+        };
+        match records::read_records(&structured, &query) {
+            Ok(chunk) => {
+                let mut logs = LogsProto {
+                    id: req.id,
+                    timestamp,
+                    stdout_offset: chunk.next_offset,
+                    stderr_offset: chunk.next_offset,
+                    truncated: chunk.truncated,
+                    ..Default::default()
+                };
+                // Only as entries: the host puts the streams' text together
+                // from them, so the response stays within the byte cap
+                for record in chunk.records {
+                    let stream = match record.stream {
+                        records::Stream::Stdout => "stdout",
+                        records::Stream::Stderr => "stderr",
+                    };
+                    logs.entries.push(LogEntryProto {
+                        stream: stream.to_string(),
+                        timestamp_ns: record.timestamp_ns,
+                        seq: record.seq,
+                        payload: record.payload,
+                    });
+                }
+                return logs;
+            }
+            Err(e) => log::warn!("Failed to read {}: {}", structured.display(), e),
         }
-    } else {
-        String::new()
+    }
+
+    let stdout = logs::read_log(
+        &log_dir.join("stdout.log").to_string_lossy(),
+        req.tail,
+        req.stdout_offset,
+        req.max_bytes,
+    );
+    let stderr = logs::read_log(
+        &log_dir.join("stderr.log").to_string_lossy(),
+        req.tail,
+        req.stderr_offset,
+        req.max_bytes,
+    );
+    LogsProto {
+        id: req.id,
+        stdout: stdout.data,
+        stderr: stderr.data,
+        timestamp,
+        stdout_offset: stdout.next_offset,
+        stderr_offset: stderr.next_offset,
+        truncated: stdout.truncated || stderr.truncated,
+        entries: vec![],
     }
 }
 
@@ -1553,12 +4597,11 @@ fn collect_container_metrics(id: &str, pid: Option<u32>) -> ContainerMetricsProt
     #[cfg(target_os = "linux")]
     {
         if let Some(pid) = pid {
-            // Try cgroup v2 first, then v1
-            if let Some(cgroup_path) = find_cgroup_path(pid) {
-                metrics.cpu = read_cpu_metrics(&cgroup_path);
-                metrics.memory = read_memory_metrics(&cgroup_path);
-                metrics.blkio = read_blkio_metrics(&cgroup_path);
-                metrics.pids = read_pids_metrics(&cgroup_path);
+            if let Some(cgroup) = CgroupPaths::find(pid) {
+                metrics.cpu = cgroup::read_cpu_metrics(&cgroup);
+                metrics.memory = cgroup::read_memory_metrics(&cgroup);
+                metrics.blkio = cgroup::read_blkio_metrics(&cgroup);
+                metrics.pids = cgroup::read_pids_metrics(&cgroup);
             }
             // Network metrics from /proc/net
             metrics.network = read_network_metrics(pid);
@@ -1568,183 +4611,68 @@ fn collect_container_metrics(id: &str, pid: Option<u32>) -> ContainerMetricsProt
     metrics
 }
 
+/// Capabilities granted when the host doesn't send any
 #[cfg(target_os = "linux")]
-fn find_cgroup_path(pid: u32) -> Option<String> {
-    // Try to find cgroup path from /proc/[pid]/cgroup
-    let cgroup_file = format!("/proc/{}/cgroup", pid);
-    if let Ok(content) = std::fs::read_to_string(&cgroup_file) {
-        // cgroup v2: single line "0::/path"
-        // cgroup v1: multiple lines "hierarchy:controllers:path"
-        for line in content.lines() {
-            let parts: Vec<&str> = line.split(':').collect();
-            if parts.len() >= 3 {
-                let path = parts[2];
-                // cgroup v2
-                if parts[0] == "0" && parts[1].is_empty() {
-                    let cgroup_base = "/sys/fs/cgroup";
-                    return Some(format!("{}{}", cgroup_base, path));
-                }
-            }
-        }
-        // Fallback for cgroup v1 - use first memory controller path
-        for line in content.lines() {
-            let parts: Vec<&str> = line.split(':').collect();
-            if parts.len() >= 3 && parts[1].contains("memory") {
-                return Some(format!("/sys/fs/cgroup/memory{}", parts[2]));
-            }
-        }
-    }
-    None
-}
+const DEFAULT_CAPABILITIES: &[&str] = &["CAP_AUDIT_WRITE", "CAP_KILL", "CAP_NET_BIND_SERVICE"];
 
+/// Accept capability names with or without the CAP_ prefix, in any case
 #[cfg(target_os = "linux")]
-fn read_cpu_metrics(cgroup_path: &str) -> CpuMetricsProto {
-    let mut cpu = CpuMetricsProto::default();
-
-    // cgroup v2: cpu.stat
-    let cpu_stat = format!("{}/cpu.stat", cgroup_path);
-    if let Ok(content) = std::fs::read_to_string(&cpu_stat) {
-        for line in content.lines() {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() >= 2 {
-                let value = parts[1].parse().unwrap_or(0);
-                match parts[0] {
-                    "usage_usec" => cpu.usage_total = value * 1000, // Convert to ns
-                    "user_usec" => cpu.usage_user = value * 1000,
-                    "system_usec" => cpu.usage_system = value * 1000,
-                    "nr_throttled" => cpu.throttled_periods = value,
-                    "throttled_usec" => cpu.throttled_time = value * 1000,
-                    _ => {}
-                }
-            }
-        }
-    }
-
-    // cgroup v1 fallback: cpuacct.usage
-    let cpuacct_usage = format!("{}/cpuacct.usage", cgroup_path);
-    if cpu.usage_total == 0 {
-        if let Ok(content) = std::fs::read_to_string(&cpuacct_usage) {
-            cpu.usage_total = content.trim().parse().unwrap_or(0);
-        }
+fn normalize_capability(name: &str) -> String {
+    let name = name.to_uppercase();
+    if name.starts_with("CAP_") {
+        name
+    } else {
+        format!("CAP_{}", name)
     }
-
-    cpu
 }
 
-#[cfg(target_os = "linux")]
-fn read_memory_metrics(cgroup_path: &str) -> MemoryMetricsProto {
-    let mut mem = MemoryMetricsProto::default();
-
-    // cgroup v2: memory.current, memory.max, memory.stat
-    if let Ok(content) = std::fs::read_to_string(format!("{}/memory.current", cgroup_path)) {
-        mem.usage = content.trim().parse().unwrap_or(0);
-    }
-    if let Ok(content) = std::fs::read_to_string(format!("{}/memory.max", cgroup_path)) {
-        mem.limit = content.trim().parse().unwrap_or(u64::MAX);
-    }
-    if let Ok(content) = std::fs::read_to_string(format!("{}/memory.peak", cgroup_path)) {
-        mem.max_usage = content.trim().parse().unwrap_or(0);
-    }
-    if let Ok(content) = std::fs::read_to_string(format!("{}/memory.swap.current", cgroup_path)) {
-        mem.swap = content.trim().parse().unwrap_or(0);
-    }
-    if let Ok(content) = std::fs::read_to_string(format!("{}/memory.stat", cgroup_path)) {
-        for line in content.lines() {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() >= 2 {
-                match parts[0] {
-                    "file" | "cache" => mem.cache = parts[1].parse().unwrap_or(0),
-                    "anon" => mem.rss = parts[1].parse().unwrap_or(0),
-                    _ => {}
-                }
-            }
-        }
-    }
-
-    // cgroup v1 fallback
-    if mem.usage == 0 {
-        if let Ok(content) =
-            std::fs::read_to_string(format!("{}/memory.usage_in_bytes", cgroup_path))
-        {
-            mem.usage = content.trim().parse().unwrap_or(0);
-        }
-        if let Ok(content) =
-            std::fs::read_to_string(format!("{}/memory.limit_in_bytes", cgroup_path))
-        {
-            mem.limit = content.trim().parse().unwrap_or(u64::MAX);
-        }
-        if let Ok(content) =
-            std::fs::read_to_string(format!("{}/memory.max_usage_in_bytes", cgroup_path))
-        {
-            mem.max_usage = content.trim().parse().unwrap_or(0);
+/// The wait status of a process that has ended, `Some(None)` if it ended
+/// but is not a child of the agent, so its status is unknown
+fn process_exit(pid: u32) -> Option<Option<libc::c_int>> {
+    let mut status = 0;
+    match unsafe { libc::waitpid(pid as libc::pid_t, &mut status, libc::WNOHANG) } {
+        0 => None,
+        reaped if reaped > 0 => Some(Some(status)),
+        _ => {
+            let alive = unsafe { libc::kill(pid as libc::pid_t, 0) == 0 };
+            (!alive).then_some(None)
         }
     }
-
-    // Calculate percentage
-    if mem.limit > 0 && mem.limit != u64::MAX {
-        mem.usage_percent = (mem.usage as f64 / mem.limit as f64) * 100.0;
-    }
-
-    mem
 }
 
-#[cfg(target_os = "linux")]
-fn read_blkio_metrics(cgroup_path: &str) -> BlkioMetricsProto {
-    let mut blkio = BlkioMetricsProto::default();
-
-    // cgroup v2: io.stat
-    if let Ok(content) = std::fs::read_to_string(format!("{}/io.stat", cgroup_path)) {
-        for line in content.lines() {
-            // Format: "major:minor rbytes=X wbytes=Y rios=Z wios=W"
-            for part in line.split_whitespace() {
-                if let Some(value) = part.strip_prefix("rbytes=") {
-                    blkio.read_bytes += value.parse::<u64>().unwrap_or(0);
-                } else if let Some(value) = part.strip_prefix("wbytes=") {
-                    blkio.write_bytes += value.parse::<u64>().unwrap_or(0);
-                } else if let Some(value) = part.strip_prefix("rios=") {
-                    blkio.read_ops += value.parse::<u64>().unwrap_or(0);
-                } else if let Some(value) = part.strip_prefix("wios=") {
-                    blkio.write_ops += value.parse::<u64>().unwrap_or(0);
-                }
-            }
+/// Wait up to `timeout` for process `pid` to exit, returning its wait status
+/// as [`process_exit`] does, or `None` if it is still running
+fn await_exit(pid: u32, timeout: std::time::Duration) -> Option<Option<libc::c_int>> {
+    let deadline = std::time::Instant::now() + timeout;
+    loop {
+        if let Some(status) = process_exit(pid) {
+            return Some(status);
         }
-    }
-
-    // cgroup v1 fallback
-    if blkio.read_bytes == 0 {
-        if let Ok(content) =
-            std::fs::read_to_string(format!("{}/blkio.throttle.io_service_bytes", cgroup_path))
-        {
-            for line in content.lines() {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 3 {
-                    let value: u64 = parts[2].parse().unwrap_or(0);
-                    match parts[1] {
-                        "Read" => blkio.read_bytes += value,
-                        "Write" => blkio.write_bytes += value,
-                        _ => {}
-                    }
-                }
-            }
+        let left = deadline.saturating_duration_since(std::time::Instant::now());
+        if left.is_zero() {
+            return None;
         }
+        wait_for_process(pid, left);
     }
-
-    blkio
 }
 
+/// Check the limits of a created container against its cgroup
 #[cfg(target_os = "linux")]
-fn read_pids_metrics(cgroup_path: &str) -> PidsMetricsProto {
-    let mut pids = PidsMetricsProto::default();
-
-    // cgroup v2
-    if let Ok(content) = std::fs::read_to_string(format!("{}/pids.current", cgroup_path)) {
-        pids.current = content.trim().parse().unwrap_or(0);
-    }
-    if let Ok(content) = std::fs::read_to_string(format!("{}/pids.max", cgroup_path)) {
-        pids.limit = content.trim().parse().unwrap_or(0);
+fn warn_unapplied_limits(id: &str, resources: &ResourceLimitsProto) {
+    let Some(cgroup) = crun::get_container_pid(id).and_then(CgroupPaths::find) else {
+        return;
+    };
+    let limits = cgroup_limits(
+        matches!(cgroup, CgroupPaths::Unified(_)),
+        unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64,
+        resources.memory,
+        resources.pids,
+        resources.cpu,
+        resources.blkio_weight,
+    );
+    for problem in unapplied_limits(&cgroup, &limits) {
+        log::warn!("Limit of container '{}' is not applied: {}", id, problem);
     }
-
-    pids
 }
 
 #[cfg(target_os = "linux")]
@@ -1777,3 +4705,351 @@ fn read_network_metrics(pid: u32) -> NetworkMetricsProto {
 
     net
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_untrusted_input() {
+        assert_eq!(invalid_container_id("web-1.2_a+b"), None);
+        for id in [
+            "",
+            ".",
+            "..",
+            "../etc",
+            "/etc",
+            "a/b",
+            "bad\0id",
+            "caf\u{e9}",
+        ] {
+            assert!(invalid_container_id(id).is_some(), "{:?}", id);
+        }
+        assert!(invalid_container_id(&"a".repeat(MAX_CONTAINER_ID_LEN + 1)).is_some());
+        assert_eq!(invalid_container_name("brave_hopper-2.web"), None);
+        for name in ["", ".hidden", "-x", "a/b", "a+b", "caf\u{e9}"] {
+            assert!(invalid_container_name(name).is_some(), "{:?}", name);
+        }
+
+        // A response too large for a frame reaches the host as an error
+        let payload = encode_response(&Response::Error("x".repeat(MAX_FRAME_SIZE)));
+        assert!(payload.len() <= MAX_FRAME_SIZE);
+        assert!(matches!(
+            deserialize_response(&payload),
+            Ok(Response::Failed(ErrorProto {
+                code: ErrorCode::Internal,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_set_role() {
+        let mut role = Role::Admin;
+        assert!(matches!(
+            set_role(&mut role, Role::ReadOnly),
+            Response::RoleSet(Role::ReadOnly)
+        ));
+        assert_eq!(role, Role::ReadOnly);
+        assert!(matches!(
+            set_role(&mut role, Role::Admin),
+            Response::Failed(ErrorProto {
+                code: ErrorCode::PermissionDenied,
+                ..
+            })
+        ));
+        assert_eq!(role, Role::ReadOnly);
+        assert!(matches!(
+            set_role(&mut role, Role::ReadOnly),
+            Response::RoleSet(Role::ReadOnly)
+        ));
+    }
+
+    #[test]
+    fn test_restart_backoff() {
+        assert_eq!(restart_delay(0), RESTART_BACKOFF);
+        assert_eq!(restart_delay(3), RESTART_BACKOFF * 8);
+        assert_eq!(restart_delay(u32::MAX), MAX_RESTART_BACKOFF);
+
+        let persisted: PersistedContainerState = serde_json::from_value(serde_json::json!({
+            "id": "c1",
+            "rootfs": "/rootfs",
+            "command": ["false"],
+            "env": [],
+            "working_dir": "/",
+            "status": "Stopped",
+            "pid": null,
+            "created_at": 0,
+            "restart_policy": {"on-failure": {"max_retries": 2}},
+        }))
+        .unwrap();
+        let mut c = ContainerState::from_persisted(persisted);
+        c.last_exit_code = Some(1);
+        c.schedule_restart(1000);
+        assert_eq!(c.next_restart_at, Some(1001));
+        c.restart_retries = 1;
+        c.schedule_restart(1000);
+        assert_eq!(c.next_restart_at, Some(1002));
+        // Out of retries
+        c.restart_retries = 2;
+        c.schedule_restart(1000);
+        assert_eq!(c.next_restart_at, None);
+        // A clean exit is not a failure
+        c.restart_retries = 0;
+        c.last_exit_code = Some(0);
+        c.schedule_restart(1000);
+        assert_eq!(c.next_restart_at, None);
+    }
+
+    #[test]
+    fn test_prune_matches() {
+        let persisted: PersistedContainerState = serde_json::from_value(serde_json::json!({
+            "id": "c1",
+            "rootfs": "/rootfs",
+            "command": ["true"],
+            "env": [],
+            "working_dir": "/",
+            "status": "Stopped",
+            "pid": null,
+            "created_at": 1000,
+            "labels": {"temporary": "true", "team": "ci"},
+        }))
+        .unwrap();
+        let c = ContainerState::from_persisted(persisted);
+        let label = |key: &str, value: Option<&str>, negate| LabelFilterProto {
+            key: key.to_string(),
+            value: value.map(str::to_string),
+            negate,
+        };
+        let filter = |until, labels| PruneFilterProto { until, labels };
+
+        assert!(prune_matches(&PruneFilterProto::default(), &c));
+        assert!(prune_matches(&filter(1001, vec![]), &c));
+        assert!(!prune_matches(&filter(1000, vec![]), &c));
+        assert!(prune_matches(
+            &filter(0, vec![label("temporary", Some("true"), false)]),
+            &c
+        ));
+        assert!(!prune_matches(
+            &filter(0, vec![label("temporary", Some("false"), false)]),
+            &c
+        ));
+        assert!(prune_matches(
+            &filter(0, vec![label("team", None, false)]),
+            &c
+        ));
+        assert!(!prune_matches(
+            &filter(0, vec![label("team", None, true)]),
+            &c
+        ));
+        assert!(prune_matches(
+            &filter(0, vec![label("team", Some("web"), true)]),
+            &c
+        ));
+        // All conditions must hold
+        assert!(!prune_matches(
+            &filter(
+                2000,
+                vec![label("team", None, false), label("keep", None, false)]
+            ),
+            &c
+        ));
+    }
+
+    #[test]
+    fn test_list_matches() {
+        let persisted: PersistedContainerState = serde_json::from_value(serde_json::json!({
+            "id": "web-1",
+            "rootfs": "/rootfs",
+            "command": ["true"],
+            "env": [],
+            "working_dir": "/",
+            "status": "Running",
+            "pid": null,
+            "created_at": 1000,
+            "labels": {"team": "web"},
+        }))
+        .unwrap();
+        let c = ContainerState::from_persisted(persisted);
+        let team = |value: &str| LabelFilterProto {
+            key: "team".to_string(),
+            value: Some(value.to_string()),
+            negate: false,
+        };
+
+        assert!(list_matches(&ListFilterProto::default(), &c));
+        assert!(list_matches(
+            &ListFilterProto {
+                labels: vec![team("web")],
+                statuses: vec![ContainerStatus::Paused, ContainerStatus::Running],
+                name_prefix: "web-".to_string(),
+            },
+            &c
+        ));
+        assert!(!list_matches(
+            &ListFilterProto {
+                labels: vec![team("db")],
+                ..Default::default()
+            },
+            &c
+        ));
+        assert!(!list_matches(
+            &ListFilterProto {
+                statuses: vec![ContainerStatus::Stopped],
+                ..Default::default()
+            },
+            &c
+        ));
+        assert!(!list_matches(
+            &ListFilterProto {
+                name_prefix: "db-".to_string(),
+                ..Default::default()
+            },
+            &c
+        ));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_listening_ports() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let ports = crun::listening_ports(std::process::id()).unwrap();
+        assert!(ports.contains(&port), "{} not in {:?}", port, ports);
+        drop(listener);
+        let ports = crun::listening_ports(std::process::id()).unwrap();
+        assert!(!ports.contains(&port));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_prepare_working_dir() {
+        let rootfs = std::env::temp_dir().join(format!("agent-workdir-{}", std::process::id()));
+        std::fs::create_dir_all(rootfs.join("srv")).unwrap();
+        std::fs::write(rootfs.join("file"), "").unwrap();
+        std::os::unix::fs::symlink("/srv", rootfs.join("app")).unwrap();
+
+        crun::prepare_working_dir(&rootfs, "/srv", false).unwrap();
+        let err = crun::prepare_working_dir(&rootfs, "/srv/data", false).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+        let err = crun::prepare_working_dir(&rootfs, "/file/data", true).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotADirectory);
+
+        // An absolute link target is in the rootfs, not on the host
+        crun::prepare_working_dir(&rootfs, "/app/data/../cache", true).unwrap();
+        assert!(rootfs.join("srv/data").is_dir());
+        assert!(rootfs.join("srv/cache").is_dir());
+        std::fs::remove_dir_all(&rootfs).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_snapshot_rootfs() {
+        let root = std::env::temp_dir().join(format!("agent-snapshot-{}", std::process::id()));
+        let image = root.join("image");
+        std::fs::create_dir_all(image.join("etc")).unwrap();
+        std::fs::write(image.join("etc/hostname"), "image\n").unwrap();
+
+        let snapshot = root.join("snapshot");
+        let rootfs = crun::snapshot_rootfs(&image, &snapshot).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(rootfs.join("etc/hostname")).unwrap(),
+            "image\n"
+        );
+        std::fs::write(rootfs.join("etc/hostname"), "container\n").unwrap();
+        std::fs::write(rootfs.join("new"), "").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(image.join("etc/hostname")).unwrap(),
+            "image\n"
+        );
+        assert!(!image.join("new").exists());
+
+        crun::remove_snapshot(&snapshot).unwrap();
+        assert!(!snapshot.exists());
+        // Already gone
+        crun::remove_snapshot(&snapshot).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_wait_for_process() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("0.1")
+            .spawn()
+            .unwrap();
+        let started = std::time::Instant::now();
+        wait_for_process(child.id(), std::time::Duration::from_secs(10));
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+        // Woken by the exit, leaving the process to be reaped
+        assert!(matches!(process_exit(child.id()), Some(Some(0))));
+        // Already reaped above, this only fails
+        let _ = child.wait();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_await_exit() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("10")
+            .spawn()
+            .unwrap();
+        assert_eq!(
+            await_exit(child.id(), std::time::Duration::from_millis(100)),
+            None
+        );
+        unsafe {
+            libc::kill(child.id() as libc::pid_t, libc::SIGTERM);
+        }
+        let status = await_exit(child.id(), std::time::Duration::from_secs(10))
+            .flatten()
+            .unwrap();
+        assert_eq!(
+            exit_outcome(status, false),
+            (128 + libc::SIGTERM, ExitReason::Signal)
+        );
+        let _ = child.wait();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_resource_limits_in_cgroup() {
+        let resources = ResourceLimitsProto {
+            cpu: Some(0.5),
+            memory: Some(64 << 20),
+            pids: Some(32),
+            blkio_weight: Some(500),
+            ..Default::default()
+        };
+        let oci = AgentState::build_oci_config_json(
+            "/rootfs",
+            &["sh".to_string()],
+            &[],
+            "/",
+            "c1",
+            &StdioConfigProto::default(),
+            &NetworkConfigProto::default(),
+            &[],
+            &resources,
+            &SecurityProto::default(),
+            &[],
+            false,
+        )
+        .unwrap();
+        let oci: serde_json::Value = serde_json::from_str(&oci).unwrap();
+        let limits = &oci["linux"]["resources"];
+        assert_eq!(limits["memory"]["limit"], 64 << 20);
+        assert_eq!(limits["pids"]["limit"], 32);
+        assert_eq!(limits["cpu"]["quota"], 50_000);
+        assert_eq!(limits["cpu"]["period"], 100_000);
+        if is_cgroup_v2() {
+            assert_eq!(limits["unified"]["io.weight"], "4950");
+        } else {
+            assert_eq!(limits["blockIO"]["weight"], 500);
+        }
+        // Only the default rlimit, limits are not per process
+        let rlimits = oci["process"]["rlimits"].as_array().unwrap();
+        assert_eq!(rlimits.len(), 1);
+        assert_eq!(rlimits[0]["type"], "RLIMIT_NOFILE");
+    }
+}
@@ -0,0 +1,55 @@
+//! Guest memory totals
+//!
+//! Reported to the host, which checks a container's memory limit against
+//! them before starting it so an oversubscribed VM is caught before it
+//! starts thrashing.
+
+use libcrun_shim_proto::MemoryInfoProto;
+
+/// Read the guest's memory totals from /proc/meminfo
+pub fn read() -> MemoryInfoProto {
+    parse(&std::fs::read_to_string("/proc/meminfo").unwrap_or_default())
+}
+
+/// Parse /proc/meminfo, whose values are in KiB
+pub fn parse(meminfo: &str) -> MemoryInfoProto {
+    let field = |name: &str| {
+        meminfo.lines().find_map(|line| {
+            let value = line.strip_prefix(name)?.strip_prefix(':')?;
+            let kib: u64 = value.trim().trim_end_matches("kB").trim().parse().ok()?;
+            Some(kib * 1024)
+        })
+    };
+    let total_bytes = field("MemTotal").unwrap_or(0);
+    MemoryInfoProto {
+        total_bytes,
+        // Kernels before 3.14 lack MemAvailable
+        available_bytes: field("MemAvailable")
+            .or_else(|| Some(field("MemFree")? + field("Cached").unwrap_or(0)))
+            .unwrap_or(total_bytes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let meminfo = "MemTotal:        2030652 kB\n\
+                       MemFree:          120000 kB\n\
+                       MemAvailable:    1500000 kB\n\
+                       Cached:           800000 kB\n";
+        assert_eq!(
+            parse(meminfo),
+            MemoryInfoProto {
+                total_bytes: 2030652 * 1024,
+                available_bytes: 1500000 * 1024,
+            }
+        );
+
+        let old_kernel = "MemTotal: 1000 kB\nMemFree: 200 kB\nCached: 300 kB\n";
+        assert_eq!(parse(old_kernel).available_bytes, 500 * 1024);
+        assert_eq!(parse(""), MemoryInfoProto::default());
+    }
+}
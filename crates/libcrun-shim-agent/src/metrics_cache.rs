@@ -0,0 +1,166 @@
+//! Recently collected container metrics
+//!
+//! Dashboards and `stats` polling several containers at once would otherwise
+//! read the same cgroup files over and over. Each container has its own slot,
+//! locked while its metrics are collected, so a burst of readers for one
+//! container results in a single set of cgroup reads without holding up the
+//! readers of other containers.
+
+use libcrun_shim_proto::ContainerMetricsProto;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Metrics collected for a container
+struct Cached {
+    at: Instant,
+    pid: Option<u32>,
+    metrics: ContainerMetricsProto,
+}
+
+type Slot = Arc<Mutex<Option<Cached>>>;
+
+/// Metrics served for `ttl` after they were collected, keyed by container ID
+pub struct MetricsCache {
+    /// How long cached metrics are served (zero disables the cache)
+    ttl: Duration,
+    slots: Mutex<HashMap<String, Slot>>,
+}
+
+impl MetricsCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            slots: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Metrics of container `id` with process `pid`, from the cache while
+    /// they are fresh and from `collect` otherwise
+    ///
+    /// Metrics cached for another process of the container, e.g. before a
+    /// restart, are collected again.
+    pub fn get_or_collect(
+        &self,
+        id: &str,
+        pid: Option<u32>,
+        collect: impl FnOnce() -> ContainerMetricsProto,
+    ) -> ContainerMetricsProto {
+        if self.ttl.is_zero() {
+            return collect();
+        }
+
+        let slot = {
+            let mut slots = self.slots.lock().unwrap();
+            // Slots being collected are locked and kept
+            slots.retain(|_, slot| {
+                slot.try_lock().map_or(true, |cached| {
+                    cached.as_ref().is_some_and(|c| c.at.elapsed() < self.ttl)
+                })
+            });
+            Arc::clone(slots.entry(id.to_string()).or_default())
+        };
+
+        let mut cached = slot.lock().unwrap();
+        if let Some(c) = cached.as_ref() {
+            if c.pid == pid && c.at.elapsed() < self.ttl {
+                return c.metrics.clone();
+            }
+        }
+        let metrics = collect();
+        *cached = Some(Cached {
+            at: Instant::now(),
+            pid,
+            metrics: metrics.clone(),
+        });
+        metrics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Collect metrics reporting how many times they were collected
+    fn counting(count: &AtomicUsize) -> impl FnOnce() -> ContainerMetricsProto + '_ {
+        move || {
+            let n = count.fetch_add(1, Ordering::SeqCst) + 1;
+            let mut metrics = ContainerMetricsProto::default();
+            metrics.cpu.usage_total = n as u64;
+            metrics
+        }
+    }
+
+    #[test]
+    fn test_served_until_expired() {
+        let cache = MetricsCache::new(Duration::from_millis(100));
+        let count = AtomicUsize::new(0);
+
+        let first = cache.get_or_collect("web", Some(10), counting(&count));
+        let second = cache.get_or_collect("web", Some(10), counting(&count));
+        assert_eq!(first.cpu.usage_total, 1);
+        assert_eq!(second.cpu.usage_total, 1);
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        std::thread::sleep(Duration::from_millis(150));
+        let expired = cache.get_or_collect("web", Some(10), counting(&count));
+        assert_eq!(expired.cpu.usage_total, 2);
+    }
+
+    #[test]
+    fn test_collected_again_for_new_pid() {
+        let cache = MetricsCache::new(Duration::from_secs(60));
+        let count = AtomicUsize::new(0);
+
+        cache.get_or_collect("web", Some(10), counting(&count));
+        let restarted = cache.get_or_collect("web", Some(11), counting(&count));
+        assert_eq!(restarted.cpu.usage_total, 2);
+        let stopped = cache.get_or_collect("web", None, counting(&count));
+        assert_eq!(stopped.cpu.usage_total, 3);
+
+        // Containers are cached separately
+        let other = cache.get_or_collect("db", Some(11), counting(&count));
+        assert_eq!(other.cpu.usage_total, 4);
+        assert_eq!(count.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn test_zero_ttl_bypasses_cache() {
+        let cache = MetricsCache::new(Duration::ZERO);
+        let count = AtomicUsize::new(0);
+
+        for _ in 0..3 {
+            cache.get_or_collect("web", Some(10), counting(&count));
+        }
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+        assert!(cache.slots.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_collecting_does_not_block_other_containers() {
+        let cache = Arc::new(MetricsCache::new(Duration::from_secs(60)));
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+
+        let slow = {
+            let cache = Arc::clone(&cache);
+            std::thread::spawn(move || {
+                cache.get_or_collect("slow", Some(10), || {
+                    started_tx.send(()).unwrap();
+                    release_rx.recv().unwrap();
+                    ContainerMetricsProto::default()
+                })
+            })
+        };
+        started_rx.recv().unwrap();
+
+        // Answered while "slow" is still being collected
+        let count = AtomicUsize::new(0);
+        cache.get_or_collect("fast", Some(11), counting(&count));
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        release_tx.send(()).unwrap();
+        slow.join().unwrap();
+    }
+}
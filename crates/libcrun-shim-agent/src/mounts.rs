@@ -0,0 +1,276 @@
+//! Mounting host directories into running containers
+//!
+//! The host shares directories through a single virtiofs device
+//! ([`SHARES_TAG`]) whose contents it can change while the VM runs. The
+//! agent mounts that device once at [`SHARES_DIR`], then clones a share with
+//! `open_tree` and attaches it inside the container's mount namespace with
+//! `move_mount`, so the container does not have to be recreated.
+
+use libcrun_shim_proto::{MountRequest, SHARES_TAG};
+use std::ffi::CString;
+use std::io;
+use std::path::{Component, Path};
+
+/// Where the shares device is mounted in the guest
+pub const SHARES_DIR: &str = "/run/libcrun-shares";
+
+/// `MOVE_MOUNT_F_EMPTY_PATH` from <linux/mount.h>, which libc lacks on musl
+#[cfg(target_os = "linux")]
+const MOVE_MOUNT_F_EMPTY_PATH: libc::c_uint = 0x4;
+
+/// Reject share names and destinations that could reach outside what was asked for
+pub fn validate(req: &MountRequest) -> Result<(), String> {
+    if req.share.is_empty() || req.share.contains('/') || req.share == "." || req.share == ".." {
+        return Err(format!("Invalid share name '{}'", req.share));
+    }
+    let destination = Path::new(&req.destination);
+    if !destination.is_absolute() || destination.components().any(|c| c == Component::ParentDir) {
+        return Err(format!(
+            "Mount destination must be an absolute path without '..': {}",
+            req.destination
+        ));
+    }
+    if destination.parent().is_none() {
+        return Err("Cannot mount over the container's root".to_string());
+    }
+    Ok(())
+}
+
+fn cstring(s: &str) -> io::Result<CString> {
+    CString::new(s).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+/// Mount the shares device at [`SHARES_DIR`] unless it already is
+pub fn mount_shares() -> io::Result<()> {
+    let mounts = std::fs::read_to_string("/proc/self/mounts")?;
+    if mounts
+        .lines()
+        .any(|line| line.split_whitespace().nth(1) == Some(SHARES_DIR))
+    {
+        return Ok(());
+    }
+    std::fs::create_dir_all(SHARES_DIR)?;
+    let tag = cstring(SHARES_TAG)?;
+    let target = cstring(SHARES_DIR)?;
+    let fs_type = cstring("virtiofs")?;
+    if unsafe {
+        libc::mount(
+            tag.as_ptr(),
+            target.as_ptr(),
+            fs_type.as_ptr(),
+            0,
+            std::ptr::null(),
+        )
+    } != 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    log::info!("Mounted shared directories at {}", SHARES_DIR);
+    Ok(())
+}
+
+/// Bind mount `source` at `destination` in the mount namespace of `pid`
+///
+/// `destination` is resolved against the process's root and created if
+/// missing. With `read_only` the whole mount tree is made read-only.
+#[cfg(target_os = "linux")]
+pub fn bind_into(pid: u32, source: &Path, destination: &str, read_only: bool) -> io::Result<()> {
+    use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+
+    let source = cstring(&source.to_string_lossy())?;
+    let empty = CString::default();
+    let tree = unsafe {
+        libc::syscall(
+            libc::SYS_open_tree,
+            libc::AT_FDCWD,
+            source.as_ptr(),
+            libc::OPEN_TREE_CLONE | libc::OPEN_TREE_CLOEXEC | libc::AT_RECURSIVE as libc::c_uint,
+        )
+    };
+    if tree < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let tree = unsafe { OwnedFd::from_raw_fd(tree as libc::c_int) };
+
+    if read_only {
+        let attr = libc::mount_attr {
+            attr_set: libc::MOUNT_ATTR_RDONLY,
+            attr_clr: 0,
+            propagation: 0,
+            userns_fd: 0,
+        };
+        if unsafe {
+            libc::syscall(
+                libc::SYS_mount_setattr,
+                tree.as_raw_fd(),
+                empty.as_ptr(),
+                libc::AT_EMPTY_PATH | libc::AT_RECURSIVE,
+                &attr,
+                std::mem::size_of::<libc::mount_attr>(),
+            )
+        } != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    let namespace = std::fs::File::open(format!("/proc/{}/ns/mnt", pid))?;
+    let root = std::fs::File::open(format!("/proc/{}/root", pid))?;
+    // Every ancestor of the destination, then the destination itself
+    let mut dirs = Vec::new();
+    let mut path = std::path::PathBuf::from("/");
+    for component in Path::new(destination).components().skip(1) {
+        path.push(component);
+        dirs.push(cstring(&path.to_string_lossy())?);
+    }
+    let dot = cstring(".")?;
+
+    // setns into a mount namespace is refused to threaded processes, so a
+    // forked child does it. Only async-signal-safe calls are made in the child.
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => {
+            let errno = || {
+                io::Error::last_os_error()
+                    .raw_os_error()
+                    .unwrap_or(libc::EIO)
+            };
+            unsafe {
+                if libc::setns(namespace.as_raw_fd(), libc::CLONE_NEWNS) != 0
+                    || libc::fchdir(root.as_raw_fd()) != 0
+                    || libc::chroot(dot.as_ptr()) != 0
+                {
+                    libc::_exit(errno());
+                }
+                for dir in &dirs {
+                    if libc::mkdir(dir.as_ptr(), 0o755) != 0 && errno() != libc::EEXIST {
+                        libc::_exit(errno());
+                    }
+                }
+                if libc::syscall(
+                    libc::SYS_move_mount,
+                    tree.as_raw_fd(),
+                    empty.as_ptr(),
+                    libc::AT_FDCWD,
+                    dirs[dirs.len() - 1].as_ptr(),
+                    MOVE_MOUNT_F_EMPTY_PATH,
+                ) != 0
+                {
+                    libc::_exit(errno());
+                }
+                libc::_exit(0)
+            }
+        }
+        child => {
+            let mut status = 0;
+            if unsafe { libc::waitpid(child, &mut status, 0) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            match libc::WIFEXITED(status).then(|| libc::WEXITSTATUS(status)) {
+                Some(0) => Ok(()),
+                Some(errno) => Err(io::Error::from_raw_os_error(errno)),
+                None => Err(io::Error::other("Mount helper was killed")),
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn bind_into(
+    _pid: u32,
+    _source: &Path,
+    _destination: &str,
+    _read_only: bool,
+) -> io::Result<()> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(share: &str, destination: &str) -> MountRequest {
+        MountRequest {
+            id: "c1".to_string(),
+            share: share.to_string(),
+            destination: destination.to_string(),
+            read_only: false,
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&request("dir-0123", "/src")).is_ok());
+        assert!(validate(&request("dir-0123", "/home/dev/src")).is_ok());
+        for share in ["", "..", "a/b"] {
+            assert!(validate(&request(share, "/src")).is_err(), "{}", share);
+        }
+        for destination in ["src", "/", "/src/../../etc", ""] {
+            assert!(
+                validate(&request("dir-0123", destination)).is_err(),
+                "{}",
+                destination
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_bind_into() {
+        if unsafe { libc::geteuid() } != 0 {
+            eprintln!("skipping: needs root");
+            return;
+        }
+        // A process in its own mount namespace stands in for a container
+        let Ok(mut holder) = std::process::Command::new("unshare")
+            .args(["--mount", "--propagation", "private", "sleep", "30"])
+            .spawn()
+        else {
+            eprintln!("skipping: unshare not available");
+            return;
+        };
+        let source = std::env::temp_dir().join(format!("agent-mount-{}", std::process::id()));
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("hello"), "hi").unwrap();
+        let destination = format!("{}-dest/nested", source.display());
+
+        // Wait for unshare to exec sleep inside the new namespace
+        let own = std::fs::read_link("/proc/self/ns/mnt").unwrap();
+        let ns = format!("/proc/{}/ns/mnt", holder.id());
+        for _ in 0..100 {
+            if std::fs::read_link(&ns).is_ok_and(|ns| ns != own) {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        let result = bind_into(holder.id(), &source, &destination, true);
+        let mounts = std::fs::read_to_string(format!("/proc/{}/mounts", holder.id()));
+        let _ = holder.kill();
+        let _ = holder.wait();
+        match result {
+            // Sandboxes may filter the new mount syscalls
+            Err(e)
+                if e.raw_os_error() == Some(libc::ENOSYS)
+                    || e.kind() == io::ErrorKind::PermissionDenied =>
+            {
+                eprintln!("skipping: {}", e);
+            }
+            result => {
+                result.unwrap();
+                let mounts = mounts.unwrap();
+                let line = mounts
+                    .lines()
+                    .find(|l| l.split_whitespace().nth(1) == Some(destination.as_str()))
+                    .expect("mounted in the namespace");
+                assert!(line.split_whitespace().nth(3).unwrap().starts_with("ro"));
+                // The agent's own namespace is untouched
+                assert!(!std::fs::read_to_string("/proc/self/mounts")
+                    .unwrap()
+                    .contains(&destination));
+            }
+        }
+        let _ = std::fs::remove_dir_all(&source);
+        let _ = std::fs::remove_dir_all(format!("{}-dest", source.display()));
+    }
+}
@@ -0,0 +1,400 @@
+//! Bridge networking for containers
+//!
+//! A container in `bridge` mode gets one end of a veth pair as its `eth0`,
+//! with an address from the agent's subnet. The other end is attached to a
+//! bridge in the VM that holds the subnet's gateway address, so containers
+//! reach each other directly and everything else through NAT. Addresses are
+//! handed out lowest first and kept until the container is deleted, so a
+//! restarted container keeps its address.
+
+use libcrun_shim_proto::NetworkInterfaceProto;
+use std::collections::BTreeMap;
+use std::net::Ipv4Addr;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+/// Subnet containers get their addresses from, unless configured
+pub const DEFAULT_SUBNET: &str = "10.88.0.0/16";
+
+/// The bridge device in the VM
+const BRIDGE_NAME: &str = "crun0";
+
+/// An IPv4 subnet, such as `10.88.0.0/16`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Subnet {
+    network: u32,
+    prefix: u8,
+}
+
+impl std::str::FromStr for Subnet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid subnet '{}', expected e.g. {}", s, DEFAULT_SUBNET);
+        let (addr, prefix) = s.split_once('/').ok_or_else(invalid)?;
+        let addr: Ipv4Addr = addr.parse().map_err(|_| invalid())?;
+        let prefix: u8 = prefix.parse().map_err(|_| invalid())?;
+        // At least a gateway and two containers
+        if !(8..=29).contains(&prefix) {
+            return Err(format!(
+                "subnet '{}' must have a prefix length from 8 to 29",
+                s
+            ));
+        }
+        let mask = u32::MAX << (32 - prefix);
+        Ok(Self {
+            network: u32::from(addr) & mask,
+            prefix,
+        })
+    }
+}
+
+impl Default for Subnet {
+    fn default() -> Self {
+        DEFAULT_SUBNET.parse().unwrap()
+    }
+}
+
+impl std::fmt::Display for Subnet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", Ipv4Addr::from(self.network), self.prefix)
+    }
+}
+
+impl Subnet {
+    /// The bridge's address, the first in the subnet
+    fn gateway(&self) -> Ipv4Addr {
+        Ipv4Addr::from(self.network + 1)
+    }
+
+    /// Addresses for containers: all but the network, gateway and broadcast
+    /// addresses
+    fn hosts(&self) -> impl Iterator<Item = Ipv4Addr> {
+        let broadcast = self.network | (u32::MAX >> self.prefix);
+        (self.network + 2..broadcast).map(Ipv4Addr::from)
+    }
+}
+
+/// The bridge and the addresses of the containers attached to it
+pub struct Bridge {
+    subnet: Subnet,
+    /// Container addresses by container ID
+    addresses: Mutex<BTreeMap<String, Ipv4Addr>>,
+    /// Whether the bridge device is up, once it was set up
+    up: Mutex<Option<bool>>,
+}
+
+impl Bridge {
+    pub fn new(subnet: Subnet) -> Self {
+        Self {
+            subnet,
+            addresses: Mutex::new(BTreeMap::new()),
+            up: Mutex::new(None),
+        }
+    }
+
+    /// The address of a container, if it has one
+    pub fn address(&self, id: &str) -> Option<Ipv4Addr> {
+        self.addresses.lock().unwrap().get(id).copied()
+    }
+
+    /// Keep the address a container had before the agent restarted
+    pub fn restore(&self, id: &str, address: Ipv4Addr) {
+        self.addresses
+            .lock()
+            .unwrap()
+            .insert(id.to_string(), address);
+    }
+
+    /// Give a container an address, the one it has if any
+    pub fn allocate(&self, id: &str) -> Result<Ipv4Addr, String> {
+        let mut addresses = self.addresses.lock().unwrap();
+        if let Some(&address) = addresses.get(id) {
+            return Ok(address);
+        }
+        let taken: std::collections::BTreeSet<_> = addresses.values().copied().collect();
+        let address = self
+            .subnet
+            .hosts()
+            .find(|address| !taken.contains(address))
+            .ok_or_else(|| format!("No addresses left in subnet {}", self.subnet))?;
+        addresses.insert(id.to_string(), address);
+        Ok(address)
+    }
+
+    /// Free the address of a deleted container
+    pub fn release(&self, id: &str) {
+        self.addresses.lock().unwrap().remove(id);
+    }
+
+    /// Free the address of a container whose create failed, and remove the
+    /// host end of its veth pair if the create got as far as making it
+    pub fn detach(&self, id: &str) {
+        let Some(address) = self.addresses.lock().unwrap().remove(id) else {
+            return;
+        };
+        let interface = host_interface(address);
+        if std::path::Path::new("/sys/class/net")
+            .join(&interface)
+            .exists()
+        {
+            if let Err(e) = run("ip", &["link", "del", &interface], None) {
+                log::warn!(
+                    "Failed to remove interface {} of '{}': {}",
+                    interface,
+                    id,
+                    e
+                );
+            }
+        }
+    }
+
+    /// Host ends of veth pairs on the bridge that belong to no container
+    /// with an address, e.g. left by a create that failed
+    pub fn stray_interfaces(&self) -> Vec<String> {
+        let addresses = self.addresses.lock().unwrap();
+        let owned: std::collections::HashSet<String> = addresses
+            .values()
+            .map(|&address| host_interface(address))
+            .collect();
+        let ports = format!("/sys/class/net/{}/brif", BRIDGE_NAME);
+        let mut stray: Vec<String> = std::fs::read_dir(ports)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|name| name.starts_with("veth") && !owned.contains(name))
+            .collect();
+        stray.sort();
+        stray
+    }
+
+    /// Give a container an address on the bridge, as the interface that
+    /// [`veth_hook`] sets up
+    ///
+    /// `None` when the bridge cannot be set up, e.g. for lack of `ip` in the
+    /// VM; the container then runs in a network namespace of its own, with
+    /// no network.
+    pub fn attach(&self, id: &str) -> Result<Option<NetworkInterfaceProto>, String> {
+        if !self.ensure_up() {
+            return Ok(None);
+        }
+        let address = self.allocate(id)?;
+        let config = BTreeMap::from([
+            ("bridge".to_string(), BRIDGE_NAME.to_string()),
+            (
+                "address".to_string(),
+                format!("{}/{}", address, self.subnet.prefix),
+            ),
+            ("gateway".to_string(), self.subnet.gateway().to_string()),
+            ("host_interface".to_string(), host_interface(address)),
+        ]);
+        Ok(Some(NetworkInterfaceProto {
+            name: "eth0".to_string(),
+            interface_type: "bridge".to_string(),
+            config,
+        }))
+    }
+
+    /// Set up the bridge and NAT on first use; false if the bridge could
+    /// not be set up, which is not tried again
+    fn ensure_up(&self) -> bool {
+        let mut up = self.up.lock().unwrap();
+        if let Some(up) = *up {
+            return up;
+        }
+        let result = self.set_up();
+        if let Err(e) = &result {
+            log::warn!(
+                "Bridge networking is unavailable, containers get no network: {}",
+                e
+            );
+        }
+        *up = Some(result.is_ok());
+        result.is_ok()
+    }
+
+    fn set_up(&self) -> Result<(), String> {
+        let gateway = format!("{}/{}", self.subnet.gateway(), self.subnet.prefix);
+        let exists = run("ip", &["link", "show", BRIDGE_NAME], None).is_ok();
+        if !exists {
+            run("ip", &["link", "add", BRIDGE_NAME, "type", "bridge"], None)?;
+        }
+        run(
+            "ip",
+            &["addr", "replace", &gateway, "dev", BRIDGE_NAME],
+            None,
+        )?;
+        run("ip", &["link", "set", BRIDGE_NAME, "up"], None)?;
+        std::fs::write("/proc/sys/net/ipv4/ip_forward", "1")
+            .map_err(|e| format!("Failed to enable IP forwarding: {}", e))?;
+        // Containers still reach each other without NAT
+        match run("nft", &["-f", "-"], Some(&nat_ruleset(&self.subnet))) {
+            Ok(()) => log::info!("Bridge {} is up for {}", BRIDGE_NAME, self.subnet),
+            Err(e) => log::warn!("Containers cannot reach outside the VM: {}", e),
+        }
+        Ok(())
+    }
+}
+
+/// Remove a host interface [`Bridge::stray_interfaces`] lists
+pub fn remove_interface(interface: &str) -> Result<(), String> {
+    run("ip", &["link", "del", interface], None)
+}
+
+/// The host end of a container's veth pair, named after its address so it
+/// is unique and fits the 15 characters of an interface name
+fn host_interface(address: Ipv4Addr) -> String {
+    format!("veth{:08x}", u32::from(address))
+}
+
+/// nftables ruleset masquerading traffic from the subnet that leaves it.
+/// The table is replaced as a whole, so loading it again changes nothing.
+fn nat_ruleset(subnet: &Subnet) -> String {
+    format!(
+        "add table ip libcrun_shim_nat\ndelete table ip libcrun_shim_nat\ntable ip libcrun_shim_nat {{\n  chain postrouting {{\n    type nat hook postrouting priority 100; policy accept;\n    ip saddr {0} ip daddr != {0} masquerade\n  }}\n}}\n",
+        subnet
+    )
+}
+
+/// Run a command, with `input` on its stdin
+fn run(program: &str, args: &[&str], input: Option<&str>) -> Result<(), String> {
+    use std::io::Write;
+
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run {}: {}", program, e))?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        let _ = stdin.write_all(input.as_bytes());
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("Failed to run {}: {}", program, e))?;
+    if output.status.success() {
+        return Ok(());
+    }
+    Err(format!(
+        "{} {} failed: {}",
+        program,
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr).trim()
+    ))
+}
+
+/// OCI hooks that set up `interface`, a `createRuntime` and a
+/// `createContainer` one; if either fails, the start fails
+///
+/// The first creates a veth pair, attaches one end to the bridge and moves
+/// the other into the container's network namespace as `interface`, by the
+/// PID in the state JSON on stdin. The second runs in that namespace, so it
+/// gives the interface its address and default route without entering it.
+pub fn veth_hooks(
+    interface: &NetworkInterfaceProto,
+) -> Option<(serde_json::Value, serde_json::Value)> {
+    const CREATE: &str = r#"pid=$(sed -n 's/.*"pid":[[:space:]]*\([0-9][0-9]*\).*/\1/p') && [ -n "$pid" ] && { ip link del "$HOST_IF" 2>/dev/null; true; } && ip link add "$HOST_IF" type veth peer name "$IF" netns "$pid" && ip link set "$HOST_IF" master "$BRIDGE" up"#;
+    const CONFIGURE: &str = r#"ip link set lo up && ip addr add "$ADDRESS" dev "$IF" && ip link set "$IF" up && ip route add default via "$GATEWAY""#;
+    let config = &interface.config;
+    let create = serde_json::json!({
+        "path": "/bin/sh",
+        "args": ["sh", "-c", CREATE],
+        "env": [
+            format!("PATH={}", crate::probe::TOOL_PATH),
+            format!("IF={}", interface.name),
+            format!("HOST_IF={}", config.get("host_interface")?),
+            format!("BRIDGE={}", config.get("bridge")?),
+        ]
+    });
+    let configure = serde_json::json!({
+        "path": "/bin/sh",
+        "args": ["sh", "-c", CONFIGURE],
+        "env": [
+            format!("PATH={}", crate::probe::TOOL_PATH),
+            format!("IF={}", interface.name),
+            format!("ADDRESS={}", config.get("address")?),
+            format!("GATEWAY={}", config.get("gateway")?),
+        ]
+    });
+    Some((create, configure))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subnet() {
+        let subnet: Subnet = "10.88.3.7/16".parse().unwrap();
+        assert_eq!(subnet.to_string(), "10.88.0.0/16");
+        assert_eq!(subnet.gateway(), Ipv4Addr::new(10, 88, 0, 1));
+
+        let subnet: Subnet = "192.168.5.0/29".parse().unwrap();
+        let hosts: Vec<_> = subnet.hosts().collect();
+        assert_eq!(hosts.first(), Some(&Ipv4Addr::new(192, 168, 5, 2)));
+        assert_eq!(hosts.last(), Some(&Ipv4Addr::new(192, 168, 5, 6)));
+
+        for invalid in ["10.88.0.0", "10.88.0.0/30", "10.88.0.0/x", "fd00::/64"] {
+            assert!(invalid.parse::<Subnet>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_allocate() {
+        let bridge = Bridge::new("192.168.5.0/29".parse().unwrap());
+        assert_eq!(bridge.allocate("a"), Ok(Ipv4Addr::new(192, 168, 5, 2)));
+        bridge.restore("b", Ipv4Addr::new(192, 168, 5, 3));
+        assert_eq!(bridge.allocate("c"), Ok(Ipv4Addr::new(192, 168, 5, 4)));
+        // A container keeps its address
+        assert_eq!(bridge.allocate("a"), Ok(Ipv4Addr::new(192, 168, 5, 2)));
+
+        // Freed addresses are handed out again, lowest first
+        bridge.release("a");
+        assert_eq!(bridge.allocate("d"), Ok(Ipv4Addr::new(192, 168, 5, 2)));
+        assert!(bridge.allocate("e").is_ok());
+        assert!(bridge.allocate("f").is_ok());
+        assert!(bridge.allocate("g").is_err());
+        assert_eq!(bridge.address("g"), None);
+        assert_eq!(bridge.address("c"), Some(Ipv4Addr::new(192, 168, 5, 4)));
+
+        // A failed create gives its address back, and its interface was
+        // never made
+        bridge.detach("c");
+        assert_eq!(bridge.address("c"), None);
+        assert_eq!(bridge.allocate("g"), Ok(Ipv4Addr::new(192, 168, 5, 4)));
+        bridge.detach("no-such-container");
+    }
+
+    #[test]
+    fn test_veth_hooks() {
+        let interface = NetworkInterfaceProto {
+            name: "eth0".to_string(),
+            interface_type: "bridge".to_string(),
+            config: BTreeMap::from([
+                ("bridge".to_string(), "crun0".to_string()),
+                ("address".to_string(), "10.88.0.2/16".to_string()),
+                ("gateway".to_string(), "10.88.0.1".to_string()),
+                (
+                    "host_interface".to_string(),
+                    host_interface(Ipv4Addr::new(10, 88, 0, 2)),
+                ),
+            ]),
+        };
+        let (create, configure) = veth_hooks(&interface).unwrap();
+        let env = create["env"].as_array().unwrap();
+        assert!(env.contains(&serde_json::json!("HOST_IF=veth0a580002")));
+        let env = configure["env"].as_array().unwrap();
+        assert!(env.contains(&serde_json::json!("ADDRESS=10.88.0.2/16")));
+        // Neither enters the container's namespace by PID
+        for hook in [&create, &configure] {
+            assert!(!hook["args"][2].as_str().unwrap().contains("nsenter"));
+        }
+
+        // An interface without its settings is not set up
+        let mut incomplete = interface;
+        incomplete.config.remove("gateway");
+        assert!(veth_hooks(&incomplete).is_none());
+    }
+}
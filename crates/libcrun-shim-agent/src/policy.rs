@@ -0,0 +1,319 @@
+//! Admission policy evaluated before containers are created or started
+//!
+//! Native rules are checked first; if they pass and a webhook is configured,
+//! the request is forwarded to it for a final decision.
+
+use libcrun_shim_proto::{CreateRequest, PolicyViolationProto};
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// Capabilities that effectively give a container full control of the guest
+const PRIVILEGED_CAPABILITIES: &[&str] = &["ALL", "CAP_ALL", "CAP_SYS_ADMIN", "SYS_ADMIN"];
+
+/// Admission policy, loaded from the file given with `--policy`
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct Policy {
+    /// Reject containers with privileged capabilities or host networking
+    #[serde(default)]
+    pub deny_privileged: bool,
+    /// Reject containers without a memory limit
+    #[serde(default)]
+    pub require_memory_limit: bool,
+    /// Registries images must be pulled from (empty = any)
+    #[serde(default)]
+    pub allowed_registries: Vec<String>,
+    /// External policy endpoint, e.g. http://10.0.2.2:8181/admit
+    #[serde(default)]
+    pub webhook: Option<String>,
+    /// Webhook timeout in seconds
+    #[serde(default = "default_webhook_timeout")]
+    pub webhook_timeout_secs: u64,
+    /// Deny requests when the webhook can't be reached
+    #[serde(default)]
+    pub webhook_fail_closed: bool,
+}
+
+fn default_webhook_timeout() -> u64 {
+    5
+}
+
+/// Decision returned by the policy webhook
+#[derive(Debug, serde::Deserialize)]
+struct WebhookDecision {
+    allowed: bool,
+    #[serde(default)]
+    violations: Vec<WebhookViolation>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct WebhookViolation {
+    rule: String,
+    message: String,
+}
+
+fn violation(rule: &str, message: impl Into<String>) -> PolicyViolationProto {
+    PolicyViolationProto {
+        rule: rule.to_string(),
+        message: message.into(),
+    }
+}
+
+impl Policy {
+    /// Load a policy from a JSON file
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read policy {}: {}", path, e))?;
+        serde_json::from_str(&content).map_err(|e| format!("Invalid policy {}: {}", path, e))
+    }
+
+    /// Check a create request, returning every rule it violates
+    pub fn evaluate_create(&self, req: &CreateRequest) -> Vec<PolicyViolationProto> {
+        let mut violations = Vec::new();
+
+        if self.deny_privileged {
+            let privileged_caps: Vec<&String> = req
+                .security
+                .capabilities
+                .iter()
+                .flatten()
+                .filter(|c| PRIVILEGED_CAPABILITIES.contains(&c.to_uppercase().as_str()))
+                .collect();
+            if !privileged_caps.is_empty() {
+                violations.push(violation(
+                    "deny_privileged",
+                    format!(
+                        "Privileged capabilities are not allowed: {:?}",
+                        privileged_caps
+                    ),
+                ));
+            }
+            if req.network.mode == "host" {
+                violations.push(violation(
+                    "deny_privileged",
+                    "Host networking is not allowed",
+                ));
+            }
+        }
+
+        if self.require_memory_limit && req.resources.memory.unwrap_or(0) == 0 {
+            violations.push(violation(
+                "require_memory_limit",
+                "A memory limit is required",
+            ));
+        }
+
+        if !self.allowed_registries.is_empty() {
+            match req.image.as_deref() {
+                None => violations.push(violation(
+                    "allowed_registries",
+                    "An image reference is required to check its registry",
+                )),
+                Some(image) => {
+                    let registry = image_registry(image);
+                    if !self.allowed_registries.iter().any(|r| r == registry) {
+                        violations.push(violation(
+                            "allowed_registries",
+                            format!("Registry '{}' is not in the allowed list", registry),
+                        ));
+                    }
+                }
+            }
+        }
+
+        if violations.is_empty() {
+            violations.extend(self.ask_webhook(serde_json::json!({
+                "action": "create",
+                "id": req.id,
+                "image": req.image,
+                "command": req.command,
+                "env": req.env,
+                "network_mode": req.network.mode,
+                "memory": req.resources.memory,
+                "capabilities": req.security.capabilities,
+                "volumes": req.volumes.iter().map(|v| &v.source).collect::<Vec<_>>(),
+            })));
+        }
+
+        violations
+    }
+
+    /// Check a start request; only the webhook has a say at this point
+    pub fn evaluate_start(&self, id: &str) -> Vec<PolicyViolationProto> {
+        self.ask_webhook(serde_json::json!({
+            "action": "start",
+            "id": id,
+        }))
+    }
+
+    fn ask_webhook(&self, input: serde_json::Value) -> Vec<PolicyViolationProto> {
+        let Some(url) = &self.webhook else {
+            return vec![];
+        };
+
+        let timeout = Duration::from_secs(self.webhook_timeout_secs);
+        match post_json(url, &input, timeout) {
+            Ok(decision) if decision.allowed => vec![],
+            Ok(decision) if decision.violations.is_empty() => {
+                vec![violation("webhook", "Denied by policy webhook")]
+            }
+            Ok(decision) => decision
+                .violations
+                .into_iter()
+                .map(|v| violation(&v.rule, v.message))
+                .collect(),
+            Err(e) if self.webhook_fail_closed => {
+                vec![violation(
+                    "webhook",
+                    format!("Policy webhook unavailable: {}", e),
+                )]
+            }
+            Err(e) => {
+                log::warn!("Policy webhook unavailable, allowing request: {}", e);
+                vec![]
+            }
+        }
+    }
+}
+
+/// Registry host of an image reference, defaulting to Docker Hub
+fn image_registry(image: &str) -> &str {
+    match image.split_once('/') {
+        Some((first, _)) if first.contains('.') || first.contains(':') || first == "localhost" => {
+            first
+        }
+        _ => "docker.io",
+    }
+}
+
+/// Minimal HTTP/1.1 JSON POST, enough for a policy endpoint inside the VM network
+fn post_json(
+    url: &str,
+    body: &serde_json::Value,
+    timeout: Duration,
+) -> Result<WebhookDecision, String> {
+    let rest = url.strip_prefix("http://").ok_or_else(|| {
+        format!(
+            "Unsupported webhook URL (only http:// is supported): {}",
+            url
+        )
+    })?;
+    let (authority, path) = match rest.find('/') {
+        Some(idx) => (&rest[..idx], &rest[idx..]),
+        None => (rest, "/"),
+    };
+    let addr_str = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{}:80", authority)
+    };
+    let addr = addr_str
+        .to_socket_addrs()
+        .map_err(|e| format!("Failed to resolve {}: {}", authority, e))?
+        .next()
+        .ok_or_else(|| format!("No address for {}", authority))?;
+
+    let mut stream = TcpStream::connect_timeout(&addr, timeout).map_err(|e| e.to_string())?;
+    stream.set_read_timeout(Some(timeout)).ok();
+    stream.set_write_timeout(Some(timeout)).ok();
+
+    let payload = body.to_string();
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        authority,
+        payload.len(),
+        payload
+    );
+    stream
+        .write_all(request.as_bytes())
+        .map_err(|e| e.to_string())?;
+
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .map_err(|e| e.to_string())?;
+
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or("Malformed HTTP response")?;
+    let status = head.split_whitespace().nth(1).unwrap_or("");
+    if status != "200" {
+        return Err(format!("Webhook returned HTTP {}", status));
+    }
+    serde_json::from_str(body).map_err(|e| format!("Invalid webhook response: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libcrun_shim_proto::*;
+
+    fn request(image: Option<&str>) -> CreateRequest {
+        CreateRequest {
+            id: "policy-test".to_string(),
+            name: String::new(),
+            rootfs: "/tmp/rootfs".to_string(),
+            command: vec!["sh".to_string()],
+            env: vec![],
+            working_dir: "/".to_string(),
+            stdio: StdioConfigProto::default(),
+            network: NetworkConfigProto::default(),
+            volumes: vec![],
+            resources: ResourceLimitsProto::default(),
+            health_check: None,
+            security: SecurityProto::default(),
+            image: image.map(str::to_string),
+            max_runtime_secs: None,
+            auto_stop: None,
+            join_namespaces: None,
+            restart_policy: RestartPolicy::No,
+            rootfs_snapshot: false,
+            read_only_rootfs: false,
+            labels: Default::default(),
+            stop_timeout: None,
+            create_working_dir: false,
+        }
+    }
+
+    #[test]
+    fn test_image_registry() {
+        assert_eq!(image_registry("alpine:3.19"), "docker.io");
+        assert_eq!(image_registry("library/alpine"), "docker.io");
+        assert_eq!(image_registry("ghcr.io/org/app:v1"), "ghcr.io");
+        assert_eq!(image_registry("localhost:5000/app"), "localhost:5000");
+        assert_eq!(image_registry("localhost/app"), "localhost");
+    }
+
+    #[test]
+    fn test_native_rules() {
+        let policy = Policy {
+            deny_privileged: true,
+            require_memory_limit: true,
+            allowed_registries: vec!["ghcr.io".to_string()],
+            ..Default::default()
+        };
+
+        let mut req = request(Some("ghcr.io/org/app:v1"));
+        req.resources.memory = Some(64 * 1024 * 1024);
+        assert!(policy.evaluate_create(&req).is_empty());
+
+        let mut req = request(Some("alpine"));
+        req.network.mode = "host".to_string();
+        req.security.capabilities = Some(vec!["CAP_SYS_ADMIN".to_string()]);
+        let rules: Vec<String> = policy
+            .evaluate_create(&req)
+            .into_iter()
+            .map(|v| v.rule)
+            .collect();
+        assert_eq!(
+            rules,
+            [
+                "deny_privileged",
+                "deny_privileged",
+                "require_memory_limit",
+                "allowed_registries"
+            ]
+        );
+    }
+}
@@ -0,0 +1,159 @@
+//! Guest kernel feature detection
+//!
+//! Probed once at startup and reported to the host, which checks container
+//! configs against it before create instead of failing halfway through. The
+//! tools container networking runs are checked for at the same time.
+
+use libcrun_shim_proto::KernelFeaturesProto;
+use std::path::Path;
+
+/// Controllers containers commonly need; missing ones are logged at startup
+const EXPECTED_CONTROLLERS: &[&str] = &["cpu", "cpuset", "memory", "pids", "io"];
+
+/// Programs the bridge network and egress rules run, from their hooks too
+const NETWORK_TOOLS: &[&str] = &["ip", "nft"];
+
+/// Directories the network hooks search for [`NETWORK_TOOLS`]
+pub const TOOL_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// Probe the kernel features visible under `root` ("/" in the guest)
+pub fn probe_kernel(root: &Path) -> KernelFeaturesProto {
+    let read = |path: &str| std::fs::read_to_string(root.join(path)).unwrap_or_default();
+
+    let cgroup_v2 = root.join("sys/fs/cgroup/cgroup.controllers").exists();
+    let cgroup_controllers = if cgroup_v2 {
+        read("sys/fs/cgroup/cgroup.controllers")
+            .split_whitespace()
+            .map(str::to_string)
+            .collect()
+    } else {
+        // cgroup v1: "subsys_name hierarchy num_cgroups enabled"
+        read("proc/cgroups")
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                match fields.as_slice() {
+                    [name, _, _, "1"] => Some(name.to_string()),
+                    _ => None,
+                }
+            })
+            .collect()
+    };
+
+    let overlayfs = read("proc/filesystems")
+        .lines()
+        .any(|line| line.split_whitespace().last() == Some("overlay"));
+    let user_namespaces = root.join("proc/self/ns/user").exists()
+        && read("proc/sys/user/max_user_namespaces").trim() != "0";
+    let seccomp = read("proc/self/status")
+        .lines()
+        .any(|line| line.starts_with("Seccomp:"));
+
+    KernelFeaturesProto {
+        cgroup_v2,
+        cgroup_controllers,
+        overlayfs,
+        vsock: root.join("dev/vsock").exists(),
+        user_namespaces,
+        seccomp,
+    }
+}
+
+/// Human-readable list of features containers may need but the kernel lacks
+pub fn missing_features(kernel: &KernelFeaturesProto) -> Vec<String> {
+    let mut missing: Vec<String> = EXPECTED_CONTROLLERS
+        .iter()
+        // cgroup v1 names the block I/O controller "blkio"
+        .map(|c| match *c {
+            "io" if !kernel.cgroup_v2 => "blkio",
+            c => c,
+        })
+        .filter(|c| !kernel.cgroup_controllers.iter().any(|have| have == c))
+        .map(|c| format!("{} cgroup controller", c))
+        .collect();
+    if !kernel.overlayfs {
+        missing.push("overlayfs".to_string());
+    }
+    if !kernel.vsock {
+        missing.push("vsock".to_string());
+    }
+    if !kernel.user_namespaces {
+        missing.push("user namespaces".to_string());
+    }
+    if !kernel.seccomp {
+        missing.push("seccomp".to_string());
+    }
+    missing
+}
+
+/// [`NETWORK_TOOLS`] found in none of the directories of `path`
+pub fn missing_tools(root: &Path, path: &str) -> Vec<&'static str> {
+    NETWORK_TOOLS
+        .iter()
+        .copied()
+        .filter(|tool| {
+            !path.split(':').any(|dir| {
+                let dir = dir.trim_start_matches('/');
+                root.join(dir).join(tool).is_file()
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe_kernel() {
+        let root = std::env::temp_dir().join(format!("agent-probe-{}", std::process::id()));
+        let write = |path: &str, content: &str| {
+            let path = root.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        };
+        write("sys/fs/cgroup/cgroup.controllers", "cpuset cpu io pids\n");
+        write("proc/filesystems", "nodev\tproc\nnodev\toverlay\n\text4\n");
+        write("proc/self/status", "Name:\tagent\nSeccomp:\t0\n");
+        write("proc/sys/user/max_user_namespaces", "0\n");
+        write("proc/self/ns/user", "");
+
+        let kernel = probe_kernel(&root);
+        assert!(kernel.cgroup_v2);
+        assert_eq!(kernel.cgroup_controllers, ["cpuset", "cpu", "io", "pids"]);
+        assert!(kernel.overlayfs && kernel.seccomp);
+        assert!(!kernel.vsock && !kernel.user_namespaces);
+        assert_eq!(
+            missing_features(&kernel),
+            ["memory cgroup controller", "vsock", "user namespaces"]
+        );
+
+        // cgroup v1 reports controllers through /proc/cgroups
+        std::fs::remove_dir_all(root.join("sys")).unwrap();
+        write(
+            "proc/cgroups",
+            "#subsys_name\thierarchy\tnum_cgroups\tenabled\ncpu\t2\t1\t1\nmemory\t0\t1\t0\n",
+        );
+        let kernel = probe_kernel(&root);
+        assert!(!kernel.cgroup_v2);
+        assert_eq!(kernel.cgroup_controllers, ["cpu"]);
+        assert!(missing_features(&kernel).contains(&"blkio cgroup controller".to_string()));
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_missing_tools() {
+        let root = std::env::temp_dir().join(format!("agent-tools-{}", std::process::id()));
+        assert_eq!(missing_tools(&root, TOOL_PATH), ["ip", "nft"]);
+
+        std::fs::create_dir_all(root.join("sbin")).unwrap();
+        std::fs::write(root.join("sbin/ip"), "").unwrap();
+        assert_eq!(missing_tools(&root, TOOL_PATH), ["nft"]);
+        // Only the directories on the path count
+        assert_eq!(missing_tools(&root, "/usr/bin"), ["ip", "nft"]);
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
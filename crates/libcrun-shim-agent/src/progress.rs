@@ -0,0 +1,92 @@
+//! Progress of long requests, reported while they run
+//!
+//! A request sent with `Request::WithProgress` has the phases it goes
+//! through sent back before its response. Handlers call [`report`] wherever
+//! they are; it does nothing unless the request being served on the thread
+//! asked for progress, each connection being served on a thread of its own.
+
+use libcrun_shim_proto::ProgressProto;
+use std::cell::RefCell;
+
+type Sink = Box<dyn FnMut(ProgressProto)>;
+
+thread_local! {
+    /// ID of the request being served on the thread, and where its progress goes
+    static REPORTER: RefCell<Option<(u64, Sink)>> = const { RefCell::new(None) };
+}
+
+/// Run `serve`, passing the phases it reports for the request `request_id`
+/// to `send`
+pub fn reporting<T>(
+    request_id: u64,
+    send: impl FnMut(ProgressProto) + 'static,
+    serve: impl FnOnce() -> T,
+) -> T {
+    /// Stops reporting when the request is served, or its handler panicked
+    struct Reporting;
+    impl Drop for Reporting {
+        fn drop(&mut self) {
+            REPORTER.with(|reporter| reporter.borrow_mut().take());
+        }
+    }
+
+    REPORTER.with(|reporter| *reporter.borrow_mut() = Some((request_id, Box::new(send))));
+    let _reporting = Reporting;
+    serve()
+}
+
+/// Report that the request being served entered `phase`
+pub fn report(phase: &str, percent: Option<u8>, message: impl Into<String>) {
+    REPORTER.with(|reporter| {
+        if let Some((request_id, send)) = reporter.borrow_mut().as_mut() {
+            send(ProgressProto {
+                request_id: *request_id,
+                phase: phase.to_string(),
+                percent,
+                message: message.into(),
+            });
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn test_reporting() {
+        let (sender, receiver) = channel();
+        // Not asked for, so dropped
+        report("snapshot", None, "Snapshotting");
+
+        let served = reporting(
+            7,
+            move |progress| sender.send(progress).unwrap(),
+            || {
+                report("snapshot", None, "Snapshotting");
+                report("create", Some(50), "Creating");
+                "created"
+            },
+        );
+        assert_eq!(served, "created");
+        let reported: Vec<ProgressProto> = receiver.try_iter().collect();
+        assert_eq!(reported.len(), 2);
+        assert_eq!(reported[1].request_id, 7);
+        assert_eq!(reported[1].phase, "create");
+        assert_eq!(reported[1].percent, Some(50));
+
+        // Reporting stops with the request, even when its handler panics
+        let (sender, receiver) = channel();
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            reporting(
+                8,
+                move |progress| sender.send(progress).unwrap(),
+                || panic!(),
+            )
+        }));
+        assert!(panicked.is_err());
+        report("create", None, "Creating");
+        assert!(receiver.try_recv().is_err());
+    }
+}
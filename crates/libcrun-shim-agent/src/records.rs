@@ -0,0 +1,496 @@
+//! Structured container logs
+//!
+//! Captured output is stored one line per record in `container.log`:
+//!
+//! ```text
+//! len: u32 | stream: u8 | timestamp_ns: u64 | seq: u64 | payload | len: u32
+//! ```
+//!
+//! All integers are little-endian and `len` is the payload length. The
+//! trailing copy of `len` lets readers walk backwards from the end of the
+//! file, so tails and `since` queries never scan the whole log. Sequence
+//! numbers order stdout and stderr relative to each other.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+#[cfg(target_os = "linux")]
+use std::{
+    io::{BufRead, BufReader},
+    os::unix::io::{AsFd, AsRawFd, FromRawFd, OwnedFd, RawFd},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+/// File name of the structured log inside a container's log directory
+pub const STRUCTURED_LOG: &str = "container.log";
+
+/// Fixed bytes around each payload
+const RECORD_OVERHEAD: u64 = 4 + 1 + 8 + 8 + 4;
+
+/// Longest payload stored in one record; longer lines are split
+const MAX_RECORD_PAYLOAD: usize = 16 * 1024;
+
+/// Output stream a record was captured from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout = 1,
+    Stderr = 2,
+}
+
+impl Stream {
+    fn from_byte(b: u8) -> io::Result<Self> {
+        match b {
+            1 => Ok(Stream::Stdout),
+            2 => Ok(Stream::Stderr),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid log stream {}", b),
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogRecord {
+    pub stream: Stream,
+    pub timestamp_ns: u64,
+    pub seq: u64,
+    pub payload: Vec<u8>,
+}
+
+impl LogRecord {
+    fn encode(&self) -> Vec<u8> {
+        let len = (self.payload.len() as u32).to_le_bytes();
+        let mut buf = Vec::with_capacity(self.payload.len() + RECORD_OVERHEAD as usize);
+        buf.extend_from_slice(&len);
+        buf.push(self.stream as u8);
+        buf.extend_from_slice(&self.timestamp_ns.to_le_bytes());
+        buf.extend_from_slice(&self.seq.to_le_bytes());
+        buf.extend_from_slice(&self.payload);
+        buf.extend_from_slice(&len);
+        buf
+    }
+
+    /// Read the record starting at `offset`, returning it and the offset after it
+    fn read_at(file: &mut File, offset: u64) -> io::Result<(LogRecord, u64)> {
+        let mut header = [0u8; 21];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut header)?;
+        let len = u32::from_le_bytes(header[0..4].try_into().unwrap()) as u64;
+        let mut payload = vec![0; len as usize];
+        file.read_exact(&mut payload)?;
+        Ok((
+            LogRecord {
+                stream: Stream::from_byte(header[4])?,
+                timestamp_ns: u64::from_le_bytes(header[5..13].try_into().unwrap()),
+                seq: u64::from_le_bytes(header[13..21].try_into().unwrap()),
+                payload,
+            },
+            offset + RECORD_OVERHEAD + len,
+        ))
+    }
+
+    /// Offset of the record that ends at `end`
+    fn start_before(file: &mut File, end: u64) -> io::Result<u64> {
+        let mut len = [0u8; 4];
+        file.seek(SeekFrom::Start(end - 4))?;
+        file.read_exact(&mut len)?;
+        let size = RECORD_OVERHEAD + u32::from_le_bytes(len) as u64;
+        end.checked_sub(size)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "corrupt log record length"))
+    }
+}
+
+/// Appends records to a structured log
+pub struct LogWriter {
+    file: File,
+    next_seq: u64,
+}
+
+impl LogWriter {
+    /// Open (or create) a structured log, continuing its sequence numbers
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+        let len = file.metadata()?.len();
+        let next_seq = if len >= RECORD_OVERHEAD {
+            let start = LogRecord::start_before(&mut file, len)?;
+            LogRecord::read_at(&mut file, start)?.0.seq + 1
+        } else {
+            0
+        };
+        Ok(Self { file, next_seq })
+    }
+
+    pub fn append(&mut self, stream: Stream, payload: &[u8]) -> io::Result<()> {
+        let record = LogRecord {
+            stream,
+            timestamp_ns: now_ns(),
+            seq: self.next_seq,
+            payload: payload.to_vec(),
+        };
+        self.file.write_all(&record.encode())?;
+        self.next_seq += 1;
+        Ok(())
+    }
+}
+
+fn now_ns() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+/// Which records to return from a structured log
+#[derive(Debug, Default)]
+pub struct RecordQuery {
+    /// Last N records (0 = all)
+    pub tail: u32,
+    /// Only records at or after this time (0 = no bound)
+    pub since_ns: u64,
+    /// Only records before this time (0 = no bound)
+    pub until_ns: u64,
+    /// Read forward from this offset instead of from the end
+    pub offset: Option<u64>,
+    /// Cap on payload bytes returned
+    pub max_bytes: u64,
+}
+
+impl RecordQuery {
+    fn matches(&self, record: &LogRecord) -> bool {
+        record.timestamp_ns >= self.since_ns
+            && (self.until_ns == 0 || record.timestamp_ns < self.until_ns)
+    }
+}
+
+/// Records read from a structured log, in sequence order
+#[derive(Debug, Default)]
+pub struct RecordChunk {
+    pub records: Vec<LogRecord>,
+    /// Offset to pass back to continue after these records
+    pub next_offset: u64,
+    /// Records were left out to stay within the byte limit
+    pub truncated: bool,
+}
+
+/// Read records from a structured log
+pub fn read_records(path: &Path, query: &RecordQuery) -> io::Result<RecordChunk> {
+    let cap = match query.max_bytes {
+        0 => libcrun_shim_proto::logs::MAX_LOG_BYTES,
+        n => n.min(libcrun_shim_proto::logs::MAX_LOG_BYTES),
+    };
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut chunk = RecordChunk::default();
+    let mut bytes = 0;
+
+    match query.offset {
+        Some(offset) => {
+            let mut pos = offset.min(len);
+            while pos < len {
+                let (record, next) = LogRecord::read_at(&mut file, pos)?;
+                if query.until_ns != 0 && record.timestamp_ns >= query.until_ns {
+                    break;
+                }
+                if query.matches(&record) {
+                    if bytes + record.payload.len() as u64 > cap && !chunk.records.is_empty() {
+                        chunk.truncated = true;
+                        break;
+                    }
+                    bytes += record.payload.len() as u64;
+                    chunk.records.push(record);
+                }
+                pos = next;
+            }
+            chunk.next_offset = pos;
+        }
+        None => {
+            // Walk backwards from the end; records are appended in time order
+            let mut end = len;
+            while end > 0 {
+                let start = LogRecord::start_before(&mut file, end)?;
+                let (record, _) = LogRecord::read_at(&mut file, start)?;
+                end = start;
+                if record.timestamp_ns < query.since_ns {
+                    break;
+                }
+                if !query.matches(&record) {
+                    continue;
+                }
+                if bytes + record.payload.len() as u64 > cap {
+                    chunk.truncated = true;
+                    break;
+                }
+                bytes += record.payload.len() as u64;
+                chunk.records.push(record);
+                if query.tail > 0 && chunk.records.len() == query.tail as usize {
+                    break;
+                }
+            }
+            chunk.records.reverse();
+            chunk.next_offset = len;
+        }
+    }
+
+    Ok(chunk)
+}
+
+/// Serializes stdio redirection, since fds 1 and 2 are shared by the whole agent
+#[cfg(target_os = "linux")]
+static STDIO_LOCK: Mutex<()> = Mutex::new(());
+
+/// Descriptor the logger reads stderr from; stdout comes on its stdin
+#[cfg(target_os = "linux")]
+const LOGGER_STDERR_FD: RawFd = 3;
+
+/// While alive, fds 1 and 2 are pipes into a container's structured log
+///
+/// libcrun starts the container process with the caller's stdio, so a
+/// container created while a capture is active writes into the log. The
+/// pipes are read by a logger process of their own, detached from the agent,
+/// so the container keeps its output when the agent is upgraded, replaced or
+/// crashes. The agent logs to a copy of its original stderr (see
+/// [`crate::diagnostics::LogTee`]), so its own lines stay out of the log.
+#[cfg(target_os = "linux")]
+pub struct StdioCapture {
+    saved: [RawFd; 2],
+    _guard: MutexGuard<'static, ()>,
+}
+
+#[cfg(target_os = "linux")]
+impl StdioCapture {
+    pub fn start(log_dir: &Path) -> io::Result<Self> {
+        use std::os::unix::process::CommandExt;
+
+        std::fs::create_dir_all(log_dir)?;
+        let (stdout_read, stdout_write) = pipe()?;
+        let (stderr_read, stderr_write) = pipe()?;
+
+        let stderr_fd = stderr_read.as_raw_fd();
+        let mut logger = std::process::Command::new("/proc/self/exe");
+        logger
+            .arg("--container-logger")
+            .arg(log_dir)
+            .stdin(stdout_read)
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null());
+        // SAFETY: only async-signal-safe calls; the copy dup2 makes is not
+        // close-on-exec, and neither is the pipe once it already is fd 3
+        unsafe {
+            logger.pre_exec(move || {
+                let moved = if stderr_fd == LOGGER_STDERR_FD {
+                    libc::fcntl(stderr_fd, libc::F_SETFD, 0)
+                } else {
+                    libc::dup2(stderr_fd, LOGGER_STDERR_FD)
+                };
+                if moved < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        // The logger forks and returns at once, see `run_logger`
+        let status = logger.status()?;
+        drop(stderr_read);
+        if !status.success() {
+            return Err(io::Error::other(format!(
+                "container logger failed to start: {}",
+                status
+            )));
+        }
+
+        let guard = STDIO_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut saved = [-1; 2];
+        for (i, (fd, pipe)) in [(1, &stdout_write), (2, &stderr_write)]
+            .into_iter()
+            .enumerate()
+        {
+            // SAFETY: plain fd syscalls on descriptors owned here
+            unsafe {
+                saved[i] = libc::dup(fd);
+                libc::dup2(pipe.as_raw_fd(), fd);
+            }
+        }
+
+        Ok(Self {
+            saved,
+            _guard: guard,
+        })
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for StdioCapture {
+    fn drop(&mut self) {
+        for (fd, saved) in [1, 2].into_iter().zip(self.saved) {
+            if saved >= 0 {
+                // SAFETY: restores the fds duplicated in `start`
+                unsafe {
+                    libc::dup2(saved, fd);
+                    libc::close(saved);
+                }
+            }
+        }
+    }
+}
+
+/// A close-on-exec pipe, read end first
+#[cfg(target_os = "linux")]
+fn pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    // SAFETY: on success both fds are new and owned by the returned values
+    unsafe {
+        if libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])))
+    }
+}
+
+/// Run as a container's logger, `--container-logger <log dir>`
+///
+/// Copies stdin (the container's stdout) and [`LOGGER_STDERR_FD`] into the
+/// structured log until the container closes both. It detaches first, so
+/// the agent that started it does not have to reap it.
+#[cfg(target_os = "linux")]
+pub fn run_logger(log_dir: &Path) -> io::Result<()> {
+    // SAFETY: the logger is still single-threaded here
+    match unsafe { libc::fork() } {
+        -1 => return Err(io::Error::last_os_error()),
+        0 => {}
+        _ => return Ok(()),
+    }
+    // SAFETY: leaves the agent's session, so its signals do not reach us
+    unsafe { libc::setsid() };
+
+    let writer = Arc::new(Mutex::new(LogWriter::open(&log_dir.join(STRUCTURED_LOG))?));
+    // SAFETY: the agent passed the stderr pipe on this fd, it is ours alone
+    let stderr = unsafe { File::from_raw_fd(LOGGER_STDERR_FD) };
+    let stderr = {
+        let writer = Arc::clone(&writer);
+        std::thread::spawn(move || pump(stderr, Stream::Stderr, writer))
+    };
+    pump(
+        File::from(io::stdin().as_fd().try_clone_to_owned()?),
+        Stream::Stdout,
+        writer,
+    );
+    let _ = stderr.join();
+    Ok(())
+}
+
+/// Copy one stream into the log, a line per record, until the container closes it
+#[cfg(target_os = "linux")]
+fn pump(pipe: File, stream: Stream, writer: Arc<Mutex<LogWriter>>) {
+    let mut reader = BufReader::with_capacity(MAX_RECORD_PAYLOAD, pipe);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match (&mut reader)
+            .take(MAX_RECORD_PAYLOAD as u64)
+            .read_until(b'\n', &mut line)
+        {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {
+                if writer.lock().unwrap().append(stream, &line).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_structured_log() {
+        let path = std::env::temp_dir().join(format!("agent-records-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut writer = LogWriter::open(&path).unwrap();
+        for i in 0..10 {
+            let stream = if i % 3 == 0 {
+                Stream::Stderr
+            } else {
+                Stream::Stdout
+            };
+            writer
+                .append(stream, format!("line {}\n", i).as_bytes())
+                .unwrap();
+        }
+        drop(writer);
+
+        // Reopening continues the sequence
+        let mut writer = LogWriter::open(&path).unwrap();
+        writer.append(Stream::Stdout, b"line 10\n").unwrap();
+
+        let all = read_records(&path, &RecordQuery::default()).unwrap();
+        let seqs: Vec<u64> = all.records.iter().map(|r| r.seq).collect();
+        assert_eq!(seqs, (0..11).collect::<Vec<_>>());
+        assert_eq!(all.records[3].stream, Stream::Stderr);
+        assert_eq!(all.records[3].payload, b"line 3\n");
+
+        let tail = read_records(
+            &path,
+            &RecordQuery {
+                tail: 2,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(tail.records[0].payload, b"line 9\n");
+        assert_eq!(tail.records.len(), 2);
+
+        // since/until select by record timestamp
+        let cutoff = all.records[5].timestamp_ns;
+        let since = read_records(
+            &path,
+            &RecordQuery {
+                since_ns: cutoff,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(since.records.iter().all(|r| r.timestamp_ns >= cutoff));
+        let until = read_records(
+            &path,
+            &RecordQuery {
+                until_ns: cutoff,
+                offset: Some(0),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(until.records.iter().all(|r| r.timestamp_ns < cutoff));
+        assert_eq!(since.records.len() + until.records.len(), 11);
+
+        // Paging by offset with a byte cap
+        let mut offset = 0;
+        let mut paged = Vec::new();
+        loop {
+            let chunk = read_records(
+                &path,
+                &RecordQuery {
+                    offset: Some(offset),
+                    max_bytes: 20,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            if chunk.records.is_empty() {
+                break;
+            }
+            offset = chunk.next_offset;
+            paged.extend(chunk.records);
+        }
+        assert_eq!(paged, all.records);
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,314 @@
+//! Bootstrap for running a standalone kubelet against the CRI socket
+//!
+//! `crun-shim cri install` writes the crictl and kubelet configurations that
+//! point at the runtime's CRI socket, then walks through the container
+//! lifecycle behind the RPCs kubelet cannot work without. A broken setup
+//! then shows up as a failed check here instead of a node that never becomes
+//! Ready.
+
+use libcrun_shim::{
+    ContainerConfig, ContainerRuntime, ContainerStatus, PodSpec, Result, ShimError,
+};
+use std::path::{Path, PathBuf};
+
+/// Where the CRI server listens unless told otherwise
+pub const DEFAULT_SOCKET: &str = "/run/crun-shim/cri.sock";
+
+/// The RPCs checked, in the order kubelet first needs them
+const RPCS: &[&str] = &[
+    "ImageStatus",
+    "Status",
+    "RunPodSandbox",
+    "CreateContainer",
+    "StartContainer",
+    "ListContainers",
+    "ExecSync",
+    "ContainerStats",
+    "StopContainer",
+    "RemoveContainer",
+    "StopPodSandbox",
+    "RemovePodSandbox",
+];
+
+fn endpoint(socket: &Path) -> String {
+    format!("unix://{}", socket.display())
+}
+
+/// crictl configuration for the socket
+pub fn crictl_config(socket: &Path) -> String {
+    format!(
+        "# Generated by crun-shim cri install\n\
+         runtime-endpoint: {endpoint}\n\
+         image-endpoint: {endpoint}\n\
+         timeout: 10\n",
+        endpoint = endpoint(socket)
+    )
+}
+
+/// Kubelet configuration for a single node running static pods from
+/// `manifests`, without an API server
+pub fn kubelet_config(socket: &Path, manifests: &Path, cgroup_driver: &str) -> String {
+    format!(
+        "# Generated by crun-shim cri install\n\
+         apiVersion: kubelet.config.k8s.io/v1beta1\n\
+         kind: KubeletConfiguration\n\
+         containerRuntimeEndpoint: {endpoint}\n\
+         imageServiceEndpoint: {endpoint}\n\
+         cgroupDriver: {cgroup_driver}\n\
+         staticPodPath: {manifests}\n\
+         failSwapOn: false\n\
+         authentication:\n  \
+           anonymous:\n    \
+             enabled: false\n  \
+           webhook:\n    \
+             enabled: false\n\
+         authorization:\n  \
+           mode: AlwaysAllow\n",
+        endpoint = endpoint(socket),
+        cgroup_driver = cgroup_driver,
+        manifests = manifests.display()
+    )
+}
+
+/// The cgroup driver kubelet must use to agree with the host
+pub fn cgroup_driver() -> &'static str {
+    if Path::new("/run/systemd/system").exists() {
+        "systemd"
+    } else {
+        "cgroupfs"
+    }
+}
+
+/// Write a generated file, keeping a different existing one as `<path>.bak`
+pub fn write_config(path: &Path, contents: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    match std::fs::read_to_string(path) {
+        Ok(existing) if existing == contents => return Ok(()),
+        Ok(_) => {
+            let mut backup = path.as_os_str().to_owned();
+            backup.push(".bak");
+            std::fs::rename(path, &backup)?;
+        }
+        Err(_) => {}
+    }
+    std::fs::write(path, contents)?;
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Passed(String),
+    Failed(String),
+    /// Not run because an earlier check failed
+    Skipped,
+}
+
+#[derive(Debug)]
+pub struct Check {
+    pub rpc: &'static str,
+    pub outcome: Outcome,
+}
+
+/// Record the outcome of the next check, `None` if it failed
+fn record(checks: &mut Vec<Check>, result: Result<String>) -> Option<()> {
+    let rpc = RPCS[checks.len()];
+    let (outcome, passed) = match result {
+        Ok(detail) => (Outcome::Passed(detail), Some(())),
+        Err(e) => (Outcome::Failed(e.to_string()), None),
+    };
+    checks.push(Check { rpc, outcome });
+    passed
+}
+
+/// Run the container lifecycle behind each checked RPC
+///
+/// `image` is the image the test pod runs, with its rootfs if it is in the
+/// store. It needs `sleep` and `echo`, as busybox has. Everything created is
+/// removed again, also when a check fails.
+pub async fn self_check(
+    runtime: &ContainerRuntime,
+    image: &str,
+    found: Option<(PathBuf, String)>,
+) -> Vec<Check> {
+    let pod_id = format!("cri-check-{}", std::process::id());
+    let mut checks = Vec::new();
+    if run_checks(runtime, image, found, &pod_id, &mut checks)
+        .await
+        .is_none()
+        && runtime.pod_members(&pod_id).is_some()
+    {
+        let _ = runtime.delete_pod(&pod_id).await;
+    }
+    for rpc in &RPCS[checks.len()..] {
+        checks.push(Check {
+            rpc,
+            outcome: Outcome::Skipped,
+        });
+    }
+    checks
+}
+
+async fn run_checks(
+    runtime: &ContainerRuntime,
+    image: &str,
+    found: Option<(PathBuf, String)>,
+    pod_id: &str,
+    checks: &mut Vec<Check>,
+) -> Option<()> {
+    let image_status = found
+        .clone()
+        .map(|(_, reference)| reference)
+        .ok_or_else(|| {
+            ShimError::not_found(format!(
+                "Image '{}' (pull it with 'crun-shim pull {}')",
+                image, image
+            ))
+        });
+    record(checks, image_status)?;
+    let (rootfs, reference) = found?;
+    let config = |id: &str| ContainerConfig {
+        id: id.to_string(),
+        rootfs: rootfs.clone(),
+        command: vec!["sleep".to_string(), "3600".to_string()],
+        working_dir: "/".to_string(),
+        image: Some(reference.clone()),
+        ..Default::default()
+    };
+    let id = format!("{}-app", pod_id);
+
+    record(
+        checks,
+        runtime
+            .list()
+            .await
+            .map(|c| format!("{} containers", c.len())),
+    )?;
+    record(
+        checks,
+        runtime
+            .create_pod(PodSpec::new(config(pod_id)))
+            .await
+            .map(|id| format!("sandbox {}", id)),
+    )?;
+    let member = ContainerConfig {
+        pod: Some(pod_id.to_string()),
+        ..config(&id)
+    };
+    record(checks, runtime.create(member).await)?;
+    record(
+        checks,
+        runtime.start(&id).await.map(|_| "running".to_string()),
+    )?;
+    let listed =
+        runtime
+            .list()
+            .await
+            .and_then(|containers| match containers.iter().find(|c| c.id == id) {
+                Some(c) if c.status == ContainerStatus::Running => Ok(format!("{} is running", id)),
+                Some(c) => Err(ShimError::runtime(format!(
+                    "{} is listed as {:?}, expected Running",
+                    id, c.status
+                ))),
+                None => Err(ShimError::runtime(format!(
+                    "{} is missing from the list",
+                    id
+                ))),
+            });
+    record(checks, listed)?;
+    let exec = runtime
+        .exec(&id, vec!["echo".to_string(), "ok".to_string()])
+        .await
+        .and_then(|(code, stdout, stderr)| {
+            if code == 0 && stdout.trim() == "ok" {
+                Ok("exit code 0".to_string())
+            } else {
+                Err(ShimError::runtime(format!(
+                    "echo exited with {}: {}{}",
+                    code,
+                    stdout.trim(),
+                    stderr.trim()
+                )))
+            }
+        });
+    record(checks, exec)?;
+    record(
+        checks,
+        runtime
+            .metrics(&id)
+            .await
+            .map(|m| format!("{} bytes of memory", m.memory.usage)),
+    )?;
+    record(
+        checks,
+        runtime.stop(&id).await.map(|_| "stopped".to_string()),
+    )?;
+    record(
+        checks,
+        runtime.delete(&id).await.map(|_| "removed".to_string()),
+    )?;
+    record(
+        checks,
+        runtime
+            .stop_pod(pod_id)
+            .await
+            .map(|_| "stopped".to_string()),
+    )?;
+    record(
+        checks,
+        runtime
+            .delete_pod(pod_id)
+            .await
+            .map(|_| "removed".to_string()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_configs() {
+        let socket = Path::new(DEFAULT_SOCKET);
+        let crictl = crictl_config(socket);
+        assert!(crictl.contains("runtime-endpoint: unix:///run/crun-shim/cri.sock\n"));
+        assert!(crictl.contains("image-endpoint: unix:///run/crun-shim/cri.sock\n"));
+
+        let kubelet = kubelet_config(socket, Path::new("/etc/kubernetes/manifests"), "systemd");
+        assert!(kubelet.contains("kind: KubeletConfiguration\n"));
+        assert!(kubelet.contains("containerRuntimeEndpoint: unix:///run/crun-shim/cri.sock\n"));
+        assert!(kubelet.contains("cgroupDriver: systemd\n"));
+        assert!(kubelet.contains("staticPodPath: /etc/kubernetes/manifests\n"));
+        assert!(kubelet.contains("authentication:\n  anonymous:\n    enabled: false\n"));
+        assert!(kubelet.ends_with("authorization:\n  mode: AlwaysAllow\n"));
+    }
+
+    #[test]
+    fn test_write_config_keeps_backup() {
+        let dir = std::env::temp_dir().join(format!("cri-install-{}", std::process::id()));
+        let path = dir.join("nested").join("config.yaml");
+        let backup = dir.join("nested").join("config.yaml.bak");
+
+        write_config(&path, "one").unwrap();
+        assert!(!backup.exists());
+        // Writing the same contents again leaves no backup
+        write_config(&path, "one").unwrap();
+        assert!(!backup.exists());
+        write_config(&path, "two").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "two");
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), "one");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn test_self_check_without_image() {
+        let runtime = ContainerRuntime::new().await.unwrap();
+        let checks = self_check(&runtime, "busybox", None).await;
+        assert_eq!(checks.len(), RPCS.len());
+        assert_eq!(checks[0].rpc, "ImageStatus");
+        assert!(matches!(checks[0].outcome, Outcome::Failed(_)));
+        assert!(checks[1..].iter().all(|c| c.outcome == Outcome::Skipped));
+    }
+}
@@ -0,0 +1,408 @@
+//! Live-reload development loop (`crun-shim dev`)
+//!
+//! `dev` runs a container with a host directory mounted in it (a bind mount
+//! on Linux, a virtiofs share on macOS), so saved edits are visible inside
+//! straight away. The directory is watched and, once changes settle, the
+//! container is either recreated or its main process is signalled so the
+//! new code gets picked up.
+
+use colored::Colorize;
+use libcrun_shim::{ContainerConfig, ContainerRuntime, Result, ShimError};
+use notify::{EventKind, RecursiveMode, Watcher as _};
+use std::collections::BTreeSet;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+/// Paths that churn during builds and editing and never warrant a reload
+pub const DEFAULT_IGNORES: &[&str] = &[".git", "target", "node_modules", "*.swp", "*~"];
+
+/// How often a wait for changes checks whether to stop
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Signals that can be sent on change, by name without the SIG prefix
+const SIGNALS: &[(&str, i32)] = &[
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("TERM", libc::SIGTERM),
+    ("USR1", libc::SIGUSR1),
+    ("USR2", libc::SIGUSR2),
+];
+
+/// What to do once watched files change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reload {
+    /// Recreate the container from its configuration
+    Restart,
+    /// Send the named signal (e.g. "HUP") to the container's main process
+    Signal(&'static str),
+}
+
+/// Parse `restart` or a signal name such as HUP or SIGUSR1
+pub fn parse_reload(s: &str) -> std::result::Result<Reload, String> {
+    if s.eq_ignore_ascii_case("restart") {
+        return Ok(Reload::Restart);
+    }
+    let upper = s.to_ascii_uppercase();
+    let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+    SIGNALS
+        .iter()
+        .find(|(signal, _)| *signal == name)
+        .map(|(signal, _)| Reload::Signal(signal))
+        .ok_or_else(|| {
+            let names: Vec<&str> = SIGNALS.iter().map(|(signal, _)| *signal).collect();
+            format!(
+                "invalid action '{}', expected restart or one of {}",
+                s,
+                names.join(", ")
+            )
+        })
+}
+
+/// Whether `path`, relative to the watched directory, matches an ignore pattern
+///
+/// A pattern matches a path component equal to it; a leading or trailing `*`
+/// matches components ending or starting with the rest.
+pub fn is_ignored(path: &Path, ignores: &[String]) -> bool {
+    path.components().any(|component| {
+        let Component::Normal(name) = component else {
+            return false;
+        };
+        let name = name.to_string_lossy();
+        ignores.iter().any(|pattern| {
+            if let Some(suffix) = pattern.strip_prefix('*') {
+                name.ends_with(suffix)
+            } else if let Some(prefix) = pattern.strip_suffix('*') {
+                name.starts_with(prefix)
+            } else {
+                name == pattern.as_str()
+            }
+        })
+    })
+}
+
+/// Recursive watch on a directory that reports changed paths in batches
+pub struct Watcher {
+    root: PathBuf,
+    ignores: Vec<String>,
+    events: tokio::sync::mpsc::UnboundedReceiver<notify::Result<notify::Event>>,
+    _watcher: notify::RecommendedWatcher,
+}
+
+impl Watcher {
+    /// Start watching `root`, which must be canonical for event paths to match it
+    pub fn new(root: &Path, ignores: Vec<String>) -> Result<Self> {
+        let (tx, events) = tokio::sync::mpsc::unbounded_channel();
+        let watcher = notify::recommended_watcher(move |event| {
+            let _ = tx.send(event);
+        })
+        .and_then(|mut watcher| {
+            watcher.watch(root, RecursiveMode::Recursive)?;
+            Ok(watcher)
+        })
+        .map_err(|e| {
+            ShimError::runtime_with_context(
+                format!("Failed to watch {}", root.display()),
+                e.to_string(),
+            )
+        })?;
+        Ok(Self {
+            root: root.to_path_buf(),
+            ignores,
+            events,
+            _watcher: watcher,
+        })
+    }
+
+    /// Wait for changes, then keep collecting until none arrive for `debounce`
+    ///
+    /// Returns the changed paths relative to the watched directory, or `None`
+    /// once `stop` returns true or the watch ends.
+    pub async fn changes(
+        &mut self,
+        debounce: Duration,
+        stop: impl Fn() -> bool,
+    ) -> Option<BTreeSet<PathBuf>> {
+        let mut changed = BTreeSet::new();
+        while changed.is_empty() {
+            if stop() {
+                return None;
+            }
+            match tokio::time::timeout(STOP_POLL_INTERVAL, self.events.recv()).await {
+                Ok(Some(event)) => self.collect(event, &mut changed),
+                Ok(None) => return None,
+                Err(_) => continue,
+            }
+        }
+        while let Ok(Some(event)) = tokio::time::timeout(debounce, self.events.recv()).await {
+            self.collect(event, &mut changed);
+        }
+        Some(changed)
+    }
+
+    fn collect(&self, event: notify::Result<notify::Event>, changed: &mut BTreeSet<PathBuf>) {
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                log::warn!("File watch error: {}", e);
+                return;
+            }
+        };
+        if matches!(event.kind, EventKind::Access(_)) {
+            return;
+        }
+        for path in event.paths {
+            let relative = path.strip_prefix(&self.root).unwrap_or(&path);
+            if !is_ignored(relative, &self.ignores) {
+                changed.insert(relative.to_path_buf());
+            }
+        }
+    }
+}
+
+/// A container run by `dev`, with the host directory mounted in it
+pub struct DevSession {
+    config: ContainerConfig,
+    source: PathBuf,
+    destination: String,
+    reload: Reload,
+}
+
+impl DevSession {
+    /// `source` must be canonical; it is mounted read-write at `destination`
+    pub fn new(
+        config: ContainerConfig,
+        source: PathBuf,
+        destination: String,
+        reload: Reload,
+    ) -> Self {
+        Self {
+            config,
+            source,
+            destination,
+            reload,
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.config.id
+    }
+
+    /// Containers on Linux run on the host, so the directory is bind mounted
+    #[cfg(not(target_os = "macos"))]
+    fn container_config(&self) -> ContainerConfig {
+        let mut config = self.config.clone();
+        config.volumes.push(libcrun_shim::VolumeMount {
+            source: self.source.clone(),
+            destination: PathBuf::from(&self.destination),
+            options: vec!["rbind".to_string(), "rw".to_string()],
+            propagation: None,
+            recursive_readonly: false,
+        });
+        config
+    }
+
+    /// In the VM the directory is shared once the container runs, see `up`
+    #[cfg(target_os = "macos")]
+    fn container_config(&self) -> ContainerConfig {
+        self.config.clone()
+    }
+
+    /// Create and start the container
+    pub async fn up(&self, runtime: &ContainerRuntime) -> Result<()> {
+        runtime.create(self.container_config()).await?;
+        runtime.start(self.id()).await?;
+        // A new container has a new mount namespace, so share the directory again
+        #[cfg(target_os = "macos")]
+        runtime
+            .mount(self.id(), &self.source, &self.destination, false)
+            .await?;
+        Ok(())
+    }
+
+    /// Stop and delete the container
+    pub async fn down(&self, runtime: &ContainerRuntime) -> Result<()> {
+        let _ = runtime.stop(self.id()).await;
+        runtime.delete(self.id()).await
+    }
+
+    /// Apply the configured reload after a change
+    pub async fn reload(&self, runtime: &ContainerRuntime) -> Result<()> {
+        match self.reload {
+            Reload::Restart => {
+                self.down(runtime).await?;
+                self.up(runtime).await
+            }
+            Reload::Signal(signal) => self.signal(runtime, signal).await,
+        }
+    }
+
+    /// Signal the main process from inside the container, where it is PID 1
+    ///
+    /// Signal numbers differ between macOS and the Linux guest, so the
+    /// signal is passed to `kill` by name.
+    #[cfg(target_os = "macos")]
+    async fn signal(&self, runtime: &ContainerRuntime, signal: &str) -> Result<()> {
+        let command = ["kill", "-s", signal, "1"].map(String::from).to_vec();
+        let (exit_code, _, stderr) = runtime.exec(self.id(), command).await?;
+        if exit_code != 0 {
+            return Err(ShimError::runtime_with_context(
+                format!("Failed to send SIG{} to container '{}'", signal, self.id()),
+                stderr.trim().to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    async fn signal(&self, runtime: &ContainerRuntime, signal: &str) -> Result<()> {
+        let pid = runtime
+            .list()
+            .await?
+            .into_iter()
+            .find(|c| c.id == self.id() && c.status == libcrun_shim::ContainerStatus::Running)
+            .and_then(|c| c.pid)
+            .ok_or_else(|| {
+                ShimError::conflict(
+                    format!("Container '{}' is not running", self.id()),
+                    "Use --on-change restart to recreate it on change",
+                )
+            })?;
+        // Without libcrun the runtime reports its own PID as a placeholder
+        if pid == std::process::id() {
+            return Err(ShimError::runtime(format!(
+                "Container '{}' has no process to signal (libcrun is not available)",
+                self.id()
+            )));
+        }
+        let number = SIGNALS
+            .iter()
+            .find(|(name, _)| *name == signal)
+            .map(|(_, number)| *number)
+            .unwrap_or(libc::SIGHUP);
+        if unsafe { libc::kill(pid as libc::pid_t, number) } != 0 {
+            return Err(ShimError::io_with_context(
+                std::io::Error::last_os_error(),
+                format!("Failed to send SIG{} to container '{}'", signal, self.id()),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Run the container and reload it after every batch of changes under
+/// `source`, until `stop` returns true
+///
+/// The container only lives as long as the session: it is deleted on the
+/// way out, including when a reload fails.
+#[allow(clippy::too_many_arguments)]
+pub async fn run(
+    runtime: &ContainerRuntime,
+    config: ContainerConfig,
+    source: &Path,
+    destination: String,
+    reload: Reload,
+    ignores: Vec<String>,
+    debounce: Duration,
+    stop: impl Fn() -> bool,
+) -> Result<()> {
+    let source = source
+        .canonicalize()
+        .map_err(|e| ShimError::io_with_context(e, format!("Cannot watch {}", source.display())))?;
+    if !source.is_dir() {
+        return Err(ShimError::validation(
+            "src",
+            format!("{} is not a directory", source.display()),
+        ));
+    }
+    let mut watcher = Watcher::new(&source, ignores)?;
+    let session = DevSession::new(config, source, destination, reload);
+    session.up(runtime).await?;
+    println!(
+        "{} {} at {}:{} (Ctrl+C to stop)",
+        "Watching".green().bold(),
+        session.source.display(),
+        session.id(),
+        session.destination
+    );
+
+    let mut result = Ok(());
+    while let Some(changed) = watcher.changes(debounce, &stop).await {
+        let action = match reload {
+            Reload::Restart => "restarting".to_string(),
+            Reload::Signal(signal) => format!("sending SIG{}", signal),
+        };
+        println!(
+            "{} {} path(s) changed, {}",
+            "Reloading".cyan().bold(),
+            changed.len(),
+            action
+        );
+        log::debug!("Changed paths: {:?}", changed);
+        if let Err(e) = session.reload(runtime).await {
+            result = Err(e);
+            break;
+        }
+    }
+
+    let removed = session.down(runtime).await;
+    result.and(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_reload() {
+        assert_eq!(parse_reload("restart"), Ok(Reload::Restart));
+        assert_eq!(parse_reload("HUP"), Ok(Reload::Signal("HUP")));
+        assert_eq!(parse_reload("sigusr1"), Ok(Reload::Signal("USR1")));
+        assert!(parse_reload("KILL9").is_err());
+        assert!(parse_reload("").is_err());
+    }
+
+    #[test]
+    fn test_is_ignored() {
+        let ignores: Vec<String> = DEFAULT_IGNORES.iter().map(|p| p.to_string()).collect();
+        assert!(is_ignored(Path::new(".git/index"), &ignores));
+        assert!(is_ignored(Path::new("web/node_modules/x/y.js"), &ignores));
+        assert!(is_ignored(Path::new("src/.main.rs.swp"), &ignores));
+        assert!(is_ignored(Path::new("notes.txt~"), &ignores));
+        assert!(!is_ignored(Path::new("src/main.rs"), &ignores));
+        assert!(!is_ignored(Path::new("targets.txt"), &ignores));
+        assert!(is_ignored(
+            Path::new("build-output/a"),
+            &["build-*".to_string()]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_watcher_batches_changes() {
+        let dir = std::env::temp_dir().join(format!("dev-watch-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join(".git")).unwrap();
+        let dir = dir.canonicalize().unwrap();
+        let mut watcher = Watcher::new(&dir, vec![".git".to_string()]).unwrap();
+
+        std::fs::write(dir.join("a.txt"), "a").unwrap();
+        std::fs::write(dir.join("b.txt"), "b").unwrap();
+        std::fs::write(dir.join(".git/HEAD"), "ref").unwrap();
+        let changed = tokio::time::timeout(
+            Duration::from_secs(10),
+            watcher.changes(Duration::from_millis(200), || false),
+        )
+        .await
+        .expect("changes reported")
+        .unwrap();
+        assert!(changed.contains(Path::new("a.txt")), "{:?}", changed);
+        assert!(changed.contains(Path::new("b.txt")), "{:?}", changed);
+        assert!(!changed.iter().any(|p| p.starts_with(".git")));
+
+        // Nothing pending: stopping ends the wait
+        assert!(watcher
+            .changes(Duration::from_millis(10), || true)
+            .await
+            .is_none());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,102 @@
+//! Process exit codes
+//!
+//! Failures exit with a code for their kind, following docker where it has
+//! one, so scripts can tell a missing container from a name conflict or a
+//! runtime that is down. A command run in a container (`exec`) exits with
+//! the command's own code instead, and so does `run --wait` with the
+//! container's.
+
+use libcrun_shim::{ExitReason, ShimError};
+
+/// Invalid arguments; also what clap exits with for bad command lines
+pub const USAGE: i32 = 2;
+
+/// The container or image already exists, or is in the wrong state
+pub const CONFLICT: i32 = 3;
+
+/// The runtime failed or could not be reached
+pub const RUNTIME: i32 = 125;
+
+/// Permission was denied
+pub const NOT_EXECUTABLE: i32 = 126;
+
+/// The container or image does not exist
+pub const NOT_FOUND: i32 = 127;
+
+/// Cancelled with Ctrl+C, as a shell reports SIGINT
+pub const INTERRUPTED: i32 = 128 + 2;
+
+/// Exit code for a failed command
+pub fn for_error(error: &ShimError) -> i32 {
+    match error {
+        ShimError::Validation { .. } => USAGE,
+        e if e.is_permission_denied() => NOT_EXECUTABLE,
+        e if e.is_not_found() => NOT_FOUND,
+        e if e.is_conflict() => CONFLICT,
+        e if e.is_cancelled() => INTERRUPTED,
+        _ => RUNTIME,
+    }
+}
+
+/// Exit code for a container's run, as `docker run` reports it
+///
+/// OOM kills are always 137, the code of the SIGKILL the kernel sends.
+pub fn for_container_exit(code: Option<i32>, reason: Option<ExitReason>) -> i32 {
+    match (code, reason) {
+        (_, Some(ExitReason::Oom)) => 128 + 9,
+        (Some(code), _) => code,
+        (None, Some(ExitReason::Completed)) => 0,
+        (None, _) => RUNTIME,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_for_error() {
+        assert_eq!(
+            for_error(&ShimError::validation("memory", "bad size")),
+            USAGE
+        );
+        assert_eq!(for_error(&ShimError::not_found("c1")), NOT_FOUND);
+        assert_eq!(
+            for_error(&ShimError::conflict("Container already exists", "c1")),
+            CONFLICT
+        );
+        let denied = std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+        assert_eq!(for_error(&denied.into()), NOT_EXECUTABLE);
+        assert_eq!(
+            for_error(&ShimError::permission_denied(
+                "Runtime is read-only",
+                "stop"
+            )),
+            NOT_EXECUTABLE
+        );
+        assert_eq!(for_error(&ShimError::runtime("libcrun failed")), RUNTIME);
+        assert_eq!(
+            for_error(&ShimError::cancelled("Pull of alpine was cancelled")),
+            INTERRUPTED
+        );
+        assert_eq!(
+            for_error(&ShimError::Unavailable {
+                message: "Agent closed the connection".to_string(),
+                context: None,
+            }),
+            RUNTIME
+        );
+    }
+
+    #[test]
+    fn test_for_container_exit() {
+        assert_eq!(for_container_exit(Some(0), Some(ExitReason::Completed)), 0);
+        assert_eq!(for_container_exit(Some(3), Some(ExitReason::Error)), 3);
+        assert_eq!(for_container_exit(Some(143), Some(ExitReason::Signal)), 143);
+        assert_eq!(for_container_exit(None, Some(ExitReason::Oom)), 137);
+        assert_eq!(for_container_exit(Some(1), Some(ExitReason::Oom)), 137);
+        assert_eq!(for_container_exit(None, Some(ExitReason::Completed)), 0);
+        // The runtime could not tell how the container ended
+        assert_eq!(for_container_exit(None, None), RUNTIME);
+    }
+}
@@ -0,0 +1,261 @@
+//! Reproducible initramfs packaging for the VM agent
+//!
+//! Archives are written in the cpio "newc" format the kernel unpacks at boot.
+//! Entries are sorted and carry fixed owners and timestamps, so the same inputs
+//! always produce the same image. When a base image is given, the new archive
+//! is appended to it: the kernel unpacks concatenated archives in order, so
+//! later entries (e.g. a newer agent) replace those in the base.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Where the agent lives inside the guest
+pub const AGENT_PATH: &str = "bin/libcrun-shim-agent";
+
+/// Directories the agent and its containers expect to exist
+const BASE_DIRS: &[&str] = &[
+    "bin",
+    "sbin",
+    "etc",
+    "proc",
+    "sys",
+    "dev",
+    "tmp",
+    "run",
+    "var/run",
+    "var/log/containers",
+    "usr/bin",
+    "usr/lib",
+    "lib",
+];
+
+/// Init script used when the image is built around busybox
+const INIT_SCRIPT: &str = r#"#!/bin/sh
+/bin/busybox --install -s
+
+mount -t proc proc /proc
+mount -t sysfs sysfs /sys
+mount -t devtmpfs devtmpfs /dev
+mount -t tmpfs tmpfs /run
+mount -t tmpfs tmpfs /tmp
+mkdir -p /dev/pts /var/log/containers
+mount -t devpts devpts /dev/pts
+
+hostname libcrun-vm
+ip link set lo up 2>/dev/null || true
+
+/bin/libcrun-shim-agent --socket /run/shim.sock --vsock-port 1234
+echo "Agent exited with code: $?"
+exec /bin/sh
+"#;
+
+enum Entry {
+    Dir,
+    File { data: Vec<u8>, mode: u32 },
+    Symlink(String),
+}
+
+/// An in-memory cpio archive
+pub struct Initramfs {
+    entries: BTreeMap<String, Entry>,
+    mtime: u32,
+}
+
+impl Initramfs {
+    /// Create an archive whose entries all carry `mtime`
+    pub fn new(mtime: u32) -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            mtime,
+        }
+    }
+
+    /// The standard guest layout: base directories, the agent, and (with
+    /// busybox) a shell and an init script that starts the agent
+    pub fn for_agent(agent: Vec<u8>, busybox: Option<Vec<u8>>, mtime: u32) -> Self {
+        let mut image = Self::new(mtime);
+        for dir in BASE_DIRS {
+            image.dir(dir);
+        }
+        image.file(AGENT_PATH, agent, 0o755);
+        if let Some(busybox) = busybox {
+            image.file("bin/busybox", busybox, 0o755);
+            image.symlink("bin/sh", "busybox");
+            image.file("init", INIT_SCRIPT.as_bytes().to_vec(), 0o755);
+        }
+        image
+    }
+
+    pub fn dir(&mut self, path: &str) {
+        let path = path.trim_matches('/');
+        let mut parent = String::new();
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            if !parent.is_empty() {
+                parent.push('/');
+            }
+            parent.push_str(component);
+            self.entries.entry(parent.clone()).or_insert(Entry::Dir);
+        }
+    }
+
+    pub fn file(&mut self, path: &str, data: Vec<u8>, mode: u32) {
+        let path = path.trim_matches('/');
+        self.add_parents(path);
+        self.entries
+            .insert(path.to_string(), Entry::File { data, mode });
+    }
+
+    pub fn symlink(&mut self, path: &str, target: &str) {
+        let path = path.trim_matches('/');
+        self.add_parents(path);
+        self.entries
+            .insert(path.to_string(), Entry::Symlink(target.to_string()));
+    }
+
+    fn add_parents(&mut self, path: &str) {
+        if let Some((parent, _)) = path.rsplit_once('/') {
+            self.dir(parent);
+        }
+    }
+
+    /// Write the archive in newc format. Paths sort before their children, so
+    /// every directory is created before anything inside it.
+    pub fn write_cpio<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        for (ino, (path, entry)) in (1..).zip(&self.entries) {
+            let (mode, data): (u32, &[u8]) = match entry {
+                Entry::Dir => (0o040755, &[]),
+                Entry::File { data, mode } => (0o100000 | mode, data),
+                Entry::Symlink(target) => (0o120777, target.as_bytes()),
+            };
+            let nlink = if matches!(entry, Entry::Dir) { 2 } else { 1 };
+            write_entry(out, ino, path, mode, nlink, self.mtime, data)?;
+        }
+        write_entry(out, 0, "TRAILER!!!", 0, 1, 0, &[])
+    }
+
+    /// Write the gzip-compressed archive
+    pub fn write_gzip<W: Write>(&self, out: W) -> io::Result<()> {
+        let mut encoder = flate2::GzBuilder::new()
+            .mtime(0)
+            .write(out, flate2::Compression::best());
+        self.write_cpio(&mut encoder)?;
+        encoder.finish()?;
+        Ok(())
+    }
+}
+
+fn write_entry<W: Write>(
+    out: &mut W,
+    ino: u32,
+    name: &str,
+    mode: u32,
+    nlink: u32,
+    mtime: u32,
+    data: &[u8],
+) -> io::Result<()> {
+    let header = format!(
+        "070701{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}",
+        ino,
+        mode,
+        0, // uid
+        0, // gid
+        nlink,
+        mtime,
+        data.len(),
+        0, // devmajor
+        0, // devminor
+        0, // rdevmajor
+        0, // rdevminor
+        name.len() + 1,
+        0, // check
+    );
+    out.write_all(header.as_bytes())?;
+    out.write_all(name.as_bytes())?;
+    out.write_all(&[0])?;
+    pad4(out, header.len() + name.len() + 1)?;
+    out.write_all(data)?;
+    pad4(out, data.len())
+}
+
+fn pad4<W: Write>(out: &mut W, len: usize) -> io::Result<()> {
+    out.write_all(&[0; 3][..(4 - len % 4) % 4])
+}
+
+/// Whether an ELF binary is statically linked (has no program interpreter).
+/// Returns `None` for anything that isn't a 64-bit little-endian ELF file.
+pub fn is_static_elf(data: &[u8]) -> Option<bool> {
+    const PT_INTERP: u32 = 3;
+    if data.len() < 64 || &data[..4] != b"\x7fELF" || data[4] != 2 || data[5] != 1 {
+        return None;
+    }
+    let u16_at = |off: usize| Some(u16::from_le_bytes(data.get(off..off + 2)?.try_into().ok()?));
+    let phoff = u64::from_le_bytes(data[32..40].try_into().ok()?) as usize;
+    let phentsize = u16_at(54)? as usize;
+    let phnum = u16_at(56)? as usize;
+
+    for i in 0..phnum {
+        let off = phoff + i * phentsize;
+        let p_type = u32::from_le_bytes(data.get(off..off + 4)?.try_into().ok()?);
+        if p_type == PT_INTERP {
+            return Some(false);
+        }
+    }
+    Some(true)
+}
+
+/// Timestamp for archive entries: `SOURCE_DATE_EPOCH` if set, otherwise 0
+pub fn source_date_epoch() -> u32 {
+    std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Parse an `--include HOST_PATH:GUEST_PATH` argument
+pub fn parse_include(spec: &str) -> Option<(&Path, &str)> {
+    let (host, guest) = spec.rsplit_once(':')?;
+    if host.is_empty() || guest.is_empty() {
+        return None;
+    }
+    Some((Path::new(host), guest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cpio_layout() {
+        let mut image = Initramfs::new(0);
+        image.file("/bin/agent", b"abc".to_vec(), 0o755);
+        image.symlink("bin/sh", "busybox");
+
+        let mut out = Vec::new();
+        image.write_cpio(&mut out).unwrap();
+        let text = String::from_utf8_lossy(&out);
+
+        // Parent directory first, entries sorted, trailer last
+        let bin = text.find("bin\0").unwrap();
+        let agent = text.find("bin/agent\0").unwrap();
+        let sh = text.find("bin/sh\0").unwrap();
+        assert!(bin < agent && agent < sh);
+        assert!(text.ends_with("TRAILER!!!\0\0\0\0"));
+        assert_eq!(out.len() % 4, 0);
+        assert!(out.starts_with(b"070701"));
+
+        // Same inputs, same bytes
+        let mut again = Vec::new();
+        image.write_cpio(&mut again).unwrap();
+        assert_eq!(out, again);
+    }
+
+    #[test]
+    fn test_is_static_elf() {
+        assert_eq!(is_static_elf(b"#!/bin/sh\n"), None);
+        #[cfg(target_os = "linux")]
+        let exe = std::fs::read(std::env::current_exe().unwrap()).unwrap();
+        #[cfg(target_os = "linux")]
+        assert!(is_static_elf(&exe).is_some());
+    }
+}
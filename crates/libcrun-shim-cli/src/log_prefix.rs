@@ -0,0 +1,89 @@
+//! `name |` prefixes for the merged logs of several containers
+//!
+//! Each container gets a color of its own and the names are padded to the
+//! longest one, so the output lines up as in `docker compose logs`. Output
+//! may stop in the middle of a line; the rest of it is not prefixed again.
+
+use colored::{Color, Colorize};
+use std::collections::{HashMap, HashSet};
+
+/// Prefix colors, assigned to the containers in order
+const COLORS: &[Color] = &[
+    Color::Cyan,
+    Color::Yellow,
+    Color::Green,
+    Color::Magenta,
+    Color::Blue,
+    Color::BrightCyan,
+    Color::BrightYellow,
+    Color::BrightGreen,
+    Color::BrightMagenta,
+    Color::BrightBlue,
+];
+
+pub struct LogPrefixer {
+    prefixes: HashMap<String, String>,
+    /// Containers and streams (true for stderr) whose output so far ended
+    /// in the middle of a line
+    mid_line: HashSet<(String, bool)>,
+}
+
+impl LogPrefixer {
+    pub fn new(names: &[String]) -> Self {
+        let width = names.iter().map(|name| name.len()).max().unwrap_or(0);
+        let prefixes = names
+            .iter()
+            .zip(COLORS.iter().cycle())
+            .map(|(name, &color)| {
+                let prefix = format!("{:width$} |", name, width = width);
+                (name.clone(), format!("{} ", prefix.color(color)))
+            })
+            .collect();
+        Self {
+            prefixes,
+            mid_line: HashSet::new(),
+        }
+    }
+
+    /// `text` written by container `name` to stdout or stderr, with the
+    /// container's prefix at the start of each line
+    pub fn prefix(&mut self, name: &str, stderr: bool, text: &str) -> String {
+        let prefix = self.prefixes.get(name).map_or("", String::as_str);
+        let key = (name.to_string(), stderr);
+        let mut mid_line = self.mid_line.contains(&key);
+        let mut out = String::with_capacity(text.len());
+        for line in text.split_inclusive('\n') {
+            if !mid_line {
+                out.push_str(prefix);
+            }
+            out.push_str(line);
+            mid_line = !line.ends_with('\n');
+        }
+        if mid_line {
+            self.mid_line.insert(key);
+        } else {
+            self.mid_line.remove(&key);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefix() {
+        colored::control::set_override(false);
+        let names = ["web".to_string(), "cache".to_string()];
+        let mut prefixer = LogPrefixer::new(&names);
+        assert_eq!(
+            prefixer.prefix("web", false, "a\nb\n"),
+            "web   | a\nweb   | b\n"
+        );
+        // The rest of a line is not prefixed again, even after other output
+        assert_eq!(prefixer.prefix("cache", false, "start"), "cache | start");
+        assert_eq!(prefixer.prefix("cache", true, "oops\n"), "cache | oops\n");
+        assert_eq!(prefixer.prefix("cache", false, "ed\nok"), "ed\ncache | ok");
+    }
+}
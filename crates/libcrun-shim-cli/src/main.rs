@@ -1,9 +1,23 @@
+mod cri;
+mod dev;
+mod exit_code;
+mod initramfs;
+mod log_prefix;
+mod registry;
+mod spinner;
+mod timestamp;
+
 use clap::{Parser, Subcommand};
 use colored::Colorize;
 use libcrun_shim::{
-    subscribe_events, ContainerConfig, ContainerEventType, ContainerRuntime, ContainerStatus,
-    HealthState, ImageStore, LogOptions, PullProgress, RuntimeConfig,
+    doctor, multiplex, subscribe_events, support, watch_terminal_size, AuthConfig, AutoStopPolicy,
+    BuildInfo, ContainerConfig, ContainerEventType, ContainerInfo, ContainerLogs, ContainerRuntime,
+    ContainerStatus, DetachKeys, DiskImageInfo, DockerConfig, ExecOptions, ExecOutcome, ExecStdio,
+    ExitReason, HealthState, ImageStore, LabelFilter, ListFilter, LogOptions, LogStream,
+    PruneFilter, PullPolicy, PullProgress, RawMode, ReadyCheck, RestartPolicy, RuntimeConfig,
+    VmDiskConfig,
 };
+use std::io::IsTerminal;
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
@@ -24,15 +38,19 @@ struct Cli {
     #[arg(long, global = true)]
     socket: Option<PathBuf>,
 
+    /// Show times in UTC instead of the local timezone
+    #[arg(long, global = true)]
+    utc: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    /// Create a new container
+    /// Create a new container, printing its generated ID
     Create {
-        /// Container name/ID
+        /// Container name
         name: String,
 
         /// Path to container rootfs
@@ -47,6 +65,10 @@ enum Commands {
         #[arg(short, long)]
         env: Vec<String>,
 
+        /// Forward host environment variables, by name or glob (e.g. HOME,AWS_*)
+        #[arg(long, value_delimiter = ',')]
+        env_passthrough: Vec<String>,
+
         /// Working directory
         #[arg(short, long, default_value = "/")]
         workdir: String,
@@ -55,21 +77,109 @@ enum Commands {
         #[arg(long)]
         memory: Option<String>,
 
+        /// Memory plus swap limit (e.g., 1g), -1 for unlimited swap
+        #[arg(long, allow_hyphen_values = true)]
+        memory_swap: Option<String>,
+
+        /// Container swappiness (0-100)
+        #[arg(long, value_parser = clap::value_parser!(u8).range(0..=100))]
+        memory_swappiness: Option<u8>,
+
         /// CPU limit (cores, e.g., 0.5, 2)
         #[arg(long)]
         cpus: Option<f64>,
+
+        /// CPUs the container may run on (e.g., 0-3, 0,2)
+        #[arg(long)]
+        cpuset_cpus: Option<String>,
+
+        /// NUMA memory nodes the container may use (e.g., 0, 0-1)
+        #[arg(long)]
+        cpuset_mems: Option<String>,
+
+        /// Container profile from the runtime config file (e.g., hardened)
+        #[arg(long)]
+        profile: Option<String>,
+
+        /// Only allow egress to this CIDR (repeatable; all other egress is dropped)
+        #[arg(long)]
+        allow_egress: Vec<String>,
+
+        /// Block egress to this CIDR (repeatable)
+        #[arg(long)]
+        deny_egress: Vec<String>,
+
+        /// Kill the container after it has run this many seconds
+        #[arg(long)]
+        max_runtime: Option<u64>,
+
+        /// Seconds the container has to exit when stopped before it is killed
+        #[arg(long)]
+        stop_timeout: Option<u64>,
+
+        /// Stop the container after this many seconds without CPU or network activity
+        #[arg(long)]
+        auto_stop: Option<u64>,
+
+        /// Restart policy (no, always, on-failure[:max-retries], unless-stopped)
+        #[arg(long, default_value = "no")]
+        restart: RestartPolicy,
+
+        /// Label the container (KEY=VALUE, repeatable), e.g. to prune by
+        #[arg(long = "label", value_parser = parse_label)]
+        labels: Vec<(String, String)>,
     },
 
     /// Start a container
     Start {
         /// Container name/ID
         name: String,
+
+        /// Start even if the host (or VM) looks short of memory or disk
+        #[arg(long)]
+        force: bool,
     },
 
     /// Stop a running container
     Stop {
         /// Container name/ID
         name: String,
+
+        /// Seconds to wait after SIGTERM before killing the container,
+        /// instead of its own stop timeout
+        #[arg(short = 't', long)]
+        timeout: Option<u64>,
+    },
+
+    /// Stop a container if it is running and start it again
+    Restart {
+        /// Container name/ID
+        name: String,
+
+        /// Seconds to wait after SIGTERM before killing the container,
+        /// instead of its own stop timeout
+        #[arg(short = 't', long)]
+        timeout: Option<u64>,
+    },
+
+    /// Wait for containers to stop and print their exit codes; exits with
+    /// the code of the last one
+    Wait {
+        /// Container names/IDs
+        #[arg(required = true)]
+        names: Vec<String>,
+    },
+
+    /// Freeze all processes of a running container
+    Pause {
+        /// Container name/ID
+        name: String,
+    },
+
+    /// Thaw a paused container
+    Unpause {
+        /// Container name/ID
+        name: String,
     },
 
     /// Delete a container
@@ -83,6 +193,15 @@ enum Commands {
         force: bool,
     },
 
+    /// Give a container a new name
+    Rename {
+        /// Container name/ID
+        container: String,
+
+        /// Name no other container has
+        new_name: String,
+    },
+
     /// List containers
     #[command(alias = "ps")]
     List {
@@ -93,12 +212,23 @@ enum Commands {
         /// Output format (table, json)
         #[arg(short, long, default_value = "table")]
         format: String,
+
+        /// Only list what matches label=KEY[=VALUE], label!=KEY[=VALUE],
+        /// status=STATUS or name=PREFIX (repeatable, all must match)
+        #[arg(long = "filter", value_parser = parse_list_filter)]
+        filters: Vec<ListCondition>,
+
+        /// Add the CPU and memory usage of running containers, read in one
+        /// request for all of them
+        #[arg(long)]
+        stats: bool,
     },
 
     /// Get container logs
     Logs {
-        /// Container name/ID
-        name: String,
+        /// Container names/IDs; the lines of several are prefixed with `name |`
+        #[arg(required = true)]
+        names: Vec<String>,
 
         /// Number of lines to show
         #[arg(short = 'n', long, default_value = "100")]
@@ -107,9 +237,14 @@ enum Commands {
         /// Follow log output
         #[arg(short, long)]
         follow: bool,
+
+        /// Prefix each line with the time it was written (RFC 3339)
+        #[arg(short, long)]
+        timestamps: bool,
     },
 
     /// Show container metrics
+    #[command(args_conflicts_with_subcommands = true)]
     Stats {
         /// Container name/ID (optional, shows all if not specified)
         name: Option<String>,
@@ -117,6 +252,9 @@ enum Commands {
         /// Output format (table, json)
         #[arg(short, long, default_value = "table")]
         format: String,
+
+        #[command(subcommand)]
+        command: Option<StatsCommands>,
     },
 
     /// Check container health
@@ -125,6 +263,20 @@ enum Commands {
         name: String,
     },
 
+    /// Show details of a container
+    Inspect {
+        /// Container name/ID
+        name: String,
+
+        /// Show the commands that were exec'd in the container
+        #[arg(long)]
+        execs: bool,
+
+        /// Output format (table, json)
+        #[arg(short, long, default_value = "table")]
+        format: String,
+    },
+
     /// Execute a command in a running container
     Exec {
         /// Container name/ID
@@ -138,14 +290,121 @@ enum Commands {
         #[arg(short = 't', long)]
         tty: bool,
 
+        /// Run as UID[:GID] inside the container (default root)
+        #[arg(short, long, value_parser = parse_exec_user)]
+        user: Option<(u32, Option<u32>)>,
+
+        /// Environment variables (KEY=VALUE)
+        #[arg(short, long)]
+        env: Vec<String>,
+
+        /// Working directory inside the container
+        #[arg(short, long)]
+        workdir: Option<String>,
+
+        /// Keys that detach, leaving the command running to `attach` to
+        /// again (e.g. ctrl-p,ctrl-q; macOS only)
+        #[arg(long)]
+        detach_keys: Option<DetachKeys>,
+
+        /// Write stdout and stderr both to stdout, in Docker's multiplexed
+        /// stream format that tags each chunk with its stream
+        #[arg(long, conflicts_with = "tty")]
+        multiplex: bool,
+
         /// Command to execute
         #[arg(num_args = 1..)]
         command: Vec<String>,
     },
 
+    /// Attach again to an exec session that was detached from
+    Attach {
+        /// Session ID, as printed on detaching
+        session: String,
+
+        /// Keys that detach again
+        #[arg(long, default_value = "ctrl-p,ctrl-q")]
+        detach_keys: DetachKeys,
+    },
+
+    /// Mount a host directory into a running container
+    Mount {
+        /// Directory on the host
+        host_dir: PathBuf,
+
+        /// Container and mount point, as NAME:/path
+        #[arg(value_parser = parse_mount_target)]
+        target: (String, String),
+
+        /// Mount read-only
+        #[arg(long)]
+        read_only: bool,
+    },
+
+    /// Copy files between the host and a container
+    ///
+    /// One side is a path on the host, the other NAME:/path in a container.
+    Cp {
+        /// Where to copy from
+        #[arg(value_parser = parse_copy_path)]
+        source: CopyPath,
+
+        /// Where to copy to; an existing directory receives the source under
+        /// its own name
+        #[arg(value_parser = parse_copy_path)]
+        destination: CopyPath,
+
+        /// Skip files matching this glob (repeatable); a pattern without a
+        /// slash matches names at any depth
+        #[arg(long)]
+        exclude: Vec<String>,
+
+        /// Copy what symlinks point to rather than the links
+        #[arg(short = 'L', long)]
+        follow_link: bool,
+
+        /// Keep file owners (uid/gid) as they are in the source
+        #[arg(short, long)]
+        archive: bool,
+    },
+
+    /// Checkpoint containers and restore them from checkpoints
+    Checkpoint {
+        #[command(subcommand)]
+        command: CheckpointCommands,
+    },
+
     /// Show runtime information
     Info,
 
+    /// Show the version; with --verbose, how the library and the VM agent
+    /// were built, for bug reports
+    Version,
+
+    /// Check the setup from the host down to DNS in the VM, with hints for
+    /// fixing what is broken
+    Doctor {
+        /// Output format (table, json)
+        #[arg(short, long, default_value = "table")]
+        format: String,
+    },
+
+    /// Collect versions, logs, state and recent failures into a tarball
+    /// for a bug report, with secrets redacted
+    SupportBundle {
+        /// Where to write the tarball (default: crun-shim-support-<time>.tar.gz)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Number of the most recent failed agent requests to include
+        #[arg(long, default_value = "50")]
+        rpcs: usize,
+
+        /// Number of the most recent container events to include
+        #[arg(long, default_value = "200")]
+        events: usize,
+    },
+
     /// Pull an image from a registry
     Pull {
         /// Image reference (e.g., alpine:latest, ghcr.io/user/repo:v1)
@@ -169,12 +428,60 @@ enum Commands {
         image: String,
     },
 
+    /// Write an image to a tarball that docker load and OCI tools can read
+    Save {
+        /// Image ID or name
+        image: String,
+
+        /// Archive to write
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+
+    /// Add the images in an OCI layout or docker save archive to the store
+    Load {
+        /// Archive (optionally gzipped) or OCI layout directory
+        #[arg(short, long)]
+        input: PathBuf,
+    },
+
+    /// Log in to a registry, storing the credentials in the Docker config
+    Login {
+        /// Registry host (e.g., ghcr.io)
+        #[arg(default_value = "docker.io")]
+        registry: String,
+
+        #[arg(short, long)]
+        username: String,
+
+        /// Password or access token
+        #[arg(short, long, conflicts_with = "password_stdin")]
+        password: Option<String>,
+
+        /// Read the password from stdin
+        #[arg(long)]
+        password_stdin: bool,
+    },
+
+    /// Log out of a registry
+    Logout {
+        /// Registry host (e.g., ghcr.io)
+        #[arg(default_value = "docker.io")]
+        registry: String,
+    },
+
+    /// Share pulled images with other machines over the registry API
+    Registry {
+        #[command(subcommand)]
+        command: RegistryCommands,
+    },
+
     /// Run a container from an image
     Run {
-        /// Image reference
+        /// Image reference, or @NAME for a template from the config file
         image: String,
 
-        /// Container name
+        /// Container name (generated, as in brave_hopper, if not given)
         #[arg(long)]
         name: Option<String>,
 
@@ -182,25 +489,176 @@ enum Commands {
         #[arg(num_args = 0..)]
         command: Vec<String>,
 
-        /// Remove container after exit
+        /// Remove container after exit; waits for it as --wait does
         #[arg(long)]
         rm: bool,
 
+        /// Wait for the container to exit, and exit with its exit code
+        #[arg(long)]
+        wait: bool,
+
+        /// Wait until a process in the container listens on this TCP port
+        #[arg(long)]
+        wait_for_port: Option<u16>,
+
+        /// Wait until the container's health check passes
+        #[arg(long)]
+        wait_healthy: bool,
+
+        /// How long --wait-for-port and --wait-healthy wait (e.g. 30s, 2m)
+        #[arg(long, default_value = "60s", value_parser = parse_window)]
+        wait_timeout: std::time::Duration,
+
+        /// Start even if the host (or VM) looks short of memory or disk
+        #[arg(long)]
+        force: bool,
+
         /// Environment variables (KEY=VALUE)
         #[arg(short, long)]
         env: Vec<String>,
 
+        /// Forward host environment variables, by name or glob (e.g. HOME,AWS_*)
+        #[arg(long, value_delimiter = ',')]
+        env_passthrough: Vec<String>,
+
         /// Working directory
         #[arg(short, long)]
         workdir: Option<String>,
 
+        /// Mount the image read-only rather than giving the container a
+        /// writable layer of its own
+        #[arg(long)]
+        read_only: bool,
+
         /// Memory limit (e.g., 512m, 1g)
         #[arg(long)]
         memory: Option<String>,
 
+        /// Memory plus swap limit (e.g., 1g), -1 for unlimited swap
+        #[arg(long, allow_hyphen_values = true)]
+        memory_swap: Option<String>,
+
+        /// Container swappiness (0-100)
+        #[arg(long, value_parser = clap::value_parser!(u8).range(0..=100))]
+        memory_swappiness: Option<u8>,
+
         /// CPU limit (cores, e.g., 0.5, 2)
         #[arg(long)]
         cpus: Option<f64>,
+
+        /// CPUs the container may run on (e.g., 0-3, 0,2)
+        #[arg(long)]
+        cpuset_cpus: Option<String>,
+
+        /// NUMA memory nodes the container may use (e.g., 0, 0-1)
+        #[arg(long)]
+        cpuset_mems: Option<String>,
+
+        /// Container profile from the runtime config file (e.g., hardened)
+        #[arg(long)]
+        profile: Option<String>,
+
+        /// Only allow egress to this CIDR (repeatable; all other egress is dropped)
+        #[arg(long)]
+        allow_egress: Vec<String>,
+
+        /// Block egress to this CIDR (repeatable)
+        #[arg(long)]
+        deny_egress: Vec<String>,
+
+        /// Kill the container after it has run this many seconds
+        #[arg(long)]
+        max_runtime: Option<u64>,
+
+        /// Seconds the container has to exit when stopped before it is killed
+        #[arg(long)]
+        stop_timeout: Option<u64>,
+
+        /// Stop the container after this many seconds without CPU or network activity
+        #[arg(long)]
+        auto_stop: Option<u64>,
+
+        /// Restart policy (no, always, on-failure[:max-retries], unless-stopped)
+        #[arg(long, default_value = "no")]
+        restart: RestartPolicy,
+
+        /// Image pull policy (local, auto-update); auto-update keeps running and
+        /// recreates the container whenever the registry has a new version of the image
+        #[arg(long, default_value = "local", value_parser = parse_pull_policy)]
+        pull_policy: PullPolicy,
+
+        /// How often auto-update checks the registry (e.g. 30m, 1h)
+        #[arg(long, default_value = "1h", value_parser = parse_window)]
+        update_interval: std::time::Duration,
+
+        /// Label the container (KEY=VALUE, repeatable), e.g. to prune by
+        #[arg(long = "label", value_parser = parse_label)]
+        labels: Vec<(String, String)>,
+    },
+
+    /// Run a container that reloads whenever a host directory changes
+    Dev {
+        /// Image reference
+        image: String,
+
+        /// Host directory to watch and mount into the container
+        #[arg(long, default_value = ".")]
+        src: PathBuf,
+
+        /// Where the directory is mounted; also the working directory
+        #[arg(long, default_value = "/app")]
+        dest: String,
+
+        /// Container name
+        #[arg(long)]
+        name: Option<String>,
+
+        /// What to do on change: restart, or a signal to send (e.g. HUP, USR1)
+        #[arg(long, default_value = "restart", value_parser = dev::parse_reload)]
+        on_change: dev::Reload,
+
+        /// Wait for changes to settle this long before reloading (milliseconds)
+        #[arg(long, default_value_t = 300)]
+        debounce_ms: u64,
+
+        /// Also ignore changes to paths matching this pattern (e.g. dist, *.log)
+        #[arg(long)]
+        ignore: Vec<String>,
+
+        /// Environment variables (KEY=VALUE)
+        #[arg(short, long)]
+        env: Vec<String>,
+
+        /// Command to run
+        #[arg(num_args = 0..)]
+        command: Vec<String>,
+    },
+
+    /// Scale replica sets of identical containers (e.g. web=3)
+    Scale {
+        /// Replica counts as NAME=REPLICAS; replicas are named NAME-1, NAME-2, ...
+        #[arg(required = true)]
+        targets: Vec<String>,
+
+        /// Image to create new replicas from
+        #[arg(long)]
+        image: Option<String>,
+
+        /// Environment variables for new replicas (KEY=VALUE)
+        #[arg(short, long)]
+        env: Vec<String>,
+
+        /// Memory limit for new replicas (e.g., 512m, 1g)
+        #[arg(long)]
+        memory: Option<String>,
+
+        /// CPU limit for new replicas (cores, e.g., 0.5, 2)
+        #[arg(long)]
+        cpus: Option<f64>,
+
+        /// Command new replicas run
+        #[arg(last = true)]
+        command: Vec<String>,
     },
 
     /// Watch container events
@@ -218,11 +676,28 @@ enum Commands {
         since: Option<u64>,
     },
 
-    /// Remove stopped containers
+    /// Remove stopped containers, or images
     Prune {
         /// Force prune without confirmation
         #[arg(short, long)]
         force: bool,
+
+        /// Remove images from the image store instead of containers
+        #[arg(long)]
+        images: bool,
+
+        /// Only remove what was created longer ago than this (e.g. 30m, 72h, 7d)
+        #[arg(long, value_parser = parse_window)]
+        until: Option<std::time::Duration>,
+
+        /// Only remove what matches label=KEY[=VALUE] or label!=KEY[=VALUE]
+        /// (repeatable, all must match)
+        #[arg(long = "filter", value_parser = parse_prune_filter)]
+        filters: Vec<LabelFilter>,
+
+        /// Keep the N newest images of each repository
+        #[arg(long, requires = "images", default_value = "0")]
+        keep_last: usize,
     },
 
     /// Gracefully shutdown all containers and runtime
@@ -242,6 +717,11 @@ enum Commands {
         #[arg(long)]
         stopped: bool,
 
+        /// Remove what failed creates left behind instead: libcrun state,
+        /// cgroups, rootfs snapshots and bridge interfaces no container owns
+        #[arg(long, conflicts_with_all = ["orphaned", "stopped"])]
+        stale_resources: bool,
+
         /// Force cleanup without confirmation
         #[arg(short, long)]
         force: bool,
@@ -257,48 +737,473 @@ enum Commands {
         #[arg(short, long)]
         force: bool,
     },
-}
 
-#[derive(Tabled)]
-struct ContainerRow {
-    #[tabled(rename = "ID")]
-    id: String,
-    #[tabled(rename = "STATUS")]
-    status: String,
-    #[tabled(rename = "PID")]
-    pid: String,
-}
+    /// Manage the VM agent
+    Agent {
+        #[command(subcommand)]
+        command: AgentCommands,
+    },
 
-#[derive(Tabled)]
-struct StatsRow {
-    #[tabled(rename = "ID")]
-    id: String,
-    #[tabled(rename = "CPU %")]
-    cpu: String,
-    #[tabled(rename = "MEM USAGE")]
-    memory: String,
-    #[tabled(rename = "MEM %")]
-    mem_percent: String,
-    #[tabled(rename = "NET I/O")]
-    network: String,
-    #[tabled(rename = "BLOCK I/O")]
-    block: String,
-    #[tabled(rename = "PIDS")]
-    pids: String,
+    /// Set up Kubernetes (kubelet) to use this runtime over CRI
+    Cri {
+        #[command(subcommand)]
+        command: CriCommands,
+    },
+
+    /// Manage the VM
+    Vm {
+        #[command(subcommand)]
+        command: VmCommands,
+    },
+
+    /// Manage runtime resources
+    System {
+        #[command(subcommand)]
+        command: SystemCommands,
+    },
 }
 
-#[derive(Tabled)]
-struct ImageRow {
-    #[tabled(rename = "ID")]
-    id: String,
-    #[tabled(rename = "REPOSITORY")]
-    repository: String,
-    #[tabled(rename = "TAG")]
-    tag: String,
-    #[tabled(rename = "SIZE")]
-    size: String,
-    #[tabled(rename = "CREATED")]
-    created: String,
+impl Commands {
+    /// The containers the command refers to, by name or ID
+    ///
+    /// `logs` resolves its own, to prefix lines with the names given.
+    fn containers_mut(&mut self) -> Vec<&mut String> {
+        match self {
+            Commands::Start { name, .. }
+            | Commands::Stop { name, .. }
+            | Commands::Restart { name, .. }
+            | Commands::Pause { name }
+            | Commands::Unpause { name }
+            | Commands::Delete { name, .. }
+            | Commands::Stats {
+                name: Some(name), ..
+            }
+            | Commands::Health { name }
+            | Commands::Inspect { name, .. }
+            | Commands::Exec { name, .. }
+            | Commands::Mount {
+                target: (name, _), ..
+            }
+            | Commands::Rename {
+                container: name, ..
+            } => vec![name],
+            Commands::Wait { names } => names.iter_mut().collect(),
+            Commands::Cp {
+                source,
+                destination,
+                ..
+            } => [source, destination]
+                .into_iter()
+                .filter_map(|path| match path {
+                    CopyPath::Container(name, _) => Some(name),
+                    CopyPath::Host(_) => None,
+                })
+                .collect(),
+            Commands::Checkpoint {
+                command:
+                    CheckpointCommands::Create { container, .. }
+                    | CheckpointCommands::Ls {
+                        container: Some(container),
+                        ..
+                    }
+                    | CheckpointCommands::Rm { container, .. }
+                    | CheckpointCommands::Restore { container, .. }
+                    | CheckpointCommands::Export { container, .. },
+            } => vec![container],
+            _ => vec![],
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum AgentCommands {
+    /// Replace the agent binary inside the running VM
+    Upgrade {
+        /// Path to the new agent binary on the host
+        binary: PathBuf,
+
+        /// Path where the guest can read the binary directly (e.g. a virtiofs share)
+        #[arg(long)]
+        guest_path: Option<String>,
+
+        /// Fail unless the upgraded agent reports this version
+        #[arg(long)]
+        expect_version: Option<String>,
+    },
+
+    /// Package a (static) agent binary into a reproducible VM initramfs
+    BuildInitramfs {
+        /// Agent binary, e.g. from `make agent-static`
+        #[arg(long)]
+        agent: PathBuf,
+
+        /// Output image (gzip-compressed cpio)
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// Static busybox providing /init's shell and basic tools
+        #[arg(long)]
+        busybox: Option<PathBuf>,
+
+        /// Existing initramfs to extend; the agent replaces the one inside it
+        #[arg(long)]
+        base: Option<PathBuf>,
+
+        /// Extra file to add, as HOST_PATH:GUEST_PATH (repeatable)
+        #[arg(long)]
+        include: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum CheckpointCommands {
+    /// Checkpoint a running container
+    Create {
+        /// Container name/ID
+        container: String,
+
+        /// Checkpoint name, unique per container (default: checkpoint-<unix time>)
+        #[arg(long)]
+        name: Option<String>,
+
+        /// Keep the container running instead of stopping it
+        #[arg(long)]
+        leave_running: bool,
+    },
+
+    /// List checkpoints
+    Ls {
+        /// Only list this container's checkpoints
+        container: Option<String>,
+
+        /// Output format (table, json)
+        #[arg(short, long, default_value = "table")]
+        format: String,
+    },
+
+    /// Delete a checkpoint
+    Rm {
+        /// Container name/ID the checkpoint was taken of
+        container: String,
+
+        /// Checkpoint name
+        #[arg(long)]
+        name: String,
+    },
+
+    /// Restore a checkpoint as a new, running container
+    Restore {
+        /// Container name/ID the checkpoint was taken of
+        container: String,
+
+        /// Checkpoint name
+        #[arg(long)]
+        name: String,
+
+        /// ID of the restored container
+        #[arg(long)]
+        id: String,
+    },
+
+    /// Copy a checkpoint to a new directory, to restore it elsewhere
+    Export {
+        /// Container name/ID the checkpoint was taken of
+        container: String,
+
+        /// Checkpoint name
+        #[arg(long)]
+        name: String,
+
+        /// Directory to create
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+
+    /// Add an exported checkpoint, for `checkpoint restore`
+    Import {
+        /// Directory written by `checkpoint export`
+        input: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum CriCommands {
+    /// Write crictl and kubelet configs for the CRI socket, then self-check
+    Install {
+        /// CRI socket kubelet and crictl connect to
+        #[arg(long, default_value = cri::DEFAULT_SOCKET)]
+        socket: PathBuf,
+
+        /// Where to write the crictl configuration
+        #[arg(long, default_value = "/etc/crictl.yaml")]
+        crictl_config: PathBuf,
+
+        /// Where to write the kubelet configuration
+        #[arg(long, default_value = "/var/lib/kubelet/config.yaml")]
+        kubelet_config: PathBuf,
+
+        /// Directory of static pod manifests kubelet runs
+        #[arg(long, default_value = "/etc/kubernetes/manifests")]
+        manifests: PathBuf,
+
+        /// Image the self-check pod runs (needs sleep and echo)
+        #[arg(long, default_value = "busybox")]
+        image: String,
+
+        /// Only write the configuration files
+        #[arg(long)]
+        skip_check: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum RegistryCommands {
+    /// Serve the local images as a read-only OCI registry
+    Serve {
+        /// Address to listen on (use 0.0.0.0:PORT to share on the LAN)
+        #[arg(long, default_value = "127.0.0.1:5000")]
+        addr: String,
+
+        /// Pull images the store lacks from this registry (e.g., docker.io)
+        #[arg(long)]
+        upstream: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum VmCommands {
+    /// Manage the VM's disk images
+    Disk {
+        #[command(subcommand)]
+        command: DiskCommands,
+    },
+
+    /// Copy a pulled image's layers into the VM, sending only those it lacks
+    Sync {
+        /// Image ID or reference
+        image: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum DiskCommands {
+    /// Grow a raw disk image (stop the VM first; takes effect on the next boot)
+    Resize {
+        /// New size (e.g., 40g, 512m)
+        size: String,
+
+        /// Disk image to resize (default: the only configured VM disk)
+        #[arg(long)]
+        disk: Option<PathBuf>,
+    },
+
+    /// Show the size and host space used by the VM's disk images
+    Inspect {
+        /// Disk image to inspect (default: all configured VM disks)
+        #[arg(long)]
+        disk: Option<PathBuf>,
+
+        /// Output format (table, json)
+        #[arg(short, long, default_value = "table")]
+        format: String,
+    },
+
+    /// Discard unused blocks in the VM so the host can reclaim the space
+    Trim,
+}
+
+#[derive(Subcommand)]
+enum StatsCommands {
+    /// Export per-container usage (min/max/avg/percentiles) over a time window
+    Export {
+        /// Time window to report on (e.g. 30m, 1h, 7d)
+        #[arg(long, default_value = "1h", value_parser = parse_window)]
+        since: std::time::Duration,
+
+        /// Output format (csv, json, table)
+        #[arg(short, long, default_value = "csv")]
+        format: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum SystemCommands {
+    /// Show disk usage of images, containers and the VM
+    Df {
+        /// Output format (table, json)
+        #[arg(short, long, default_value = "table")]
+        format: String,
+    },
+}
+
+#[derive(Tabled)]
+struct UsageReportRow {
+    #[tabled(rename = "ID")]
+    id: String,
+    #[tabled(rename = "SAMPLES")]
+    samples: usize,
+    #[tabled(rename = "CPU AVG %")]
+    cpu_avg: String,
+    #[tabled(rename = "CPU P95 %")]
+    cpu_p95: String,
+    #[tabled(rename = "CPU TIME")]
+    cpu_time: String,
+    #[tabled(rename = "MEM AVG")]
+    memory_avg: String,
+    #[tabled(rename = "MEM MAX")]
+    memory_max: String,
+    #[tabled(rename = "NET I/O")]
+    network: String,
+    #[tabled(rename = "BLOCK I/O")]
+    block: String,
+}
+
+/// The `ps` row of a container
+fn container_row(c: ContainerInfo) -> ContainerRow {
+    ContainerRow {
+        status: match (c.exit_reason, c.oom_kills) {
+            (Some(reason), _) => format!("{} ({})", format_status(c.status), reason),
+            // Only some of its processes, or it would have stopped
+            (None, kills) if kills > 0 && c.status != ContainerStatus::Stopped => {
+                format!("{} ({})", format_status(c.status), format_oom_kills(kills))
+            }
+            (None, _) => format_status(c.status),
+        },
+        id: c.id,
+        name: c.name,
+        pid: c.pid.map(|p| p.to_string()).unwrap_or_default(),
+        exit: format_exit(c.last_exit_code, c.last_exit_reason),
+        restarts: c.restart_count,
+    }
+}
+
+#[derive(Tabled)]
+struct ContainerRow {
+    #[tabled(rename = "ID")]
+    id: String,
+    #[tabled(rename = "NAME")]
+    name: String,
+    #[tabled(rename = "STATUS")]
+    status: String,
+    #[tabled(rename = "PID")]
+    pid: String,
+    #[tabled(rename = "EXIT")]
+    exit: String,
+    #[tabled(rename = "RESTARTS")]
+    restarts: u32,
+}
+
+/// A [`ContainerRow`] with the usage columns of `ps --stats`
+#[derive(Tabled)]
+struct ContainerStatsRow {
+    #[tabled(inline)]
+    container: ContainerRow,
+    #[tabled(rename = "CPU %")]
+    cpu: String,
+    #[tabled(rename = "MEM")]
+    memory: String,
+}
+
+#[derive(Tabled)]
+struct StatsRow {
+    #[tabled(rename = "ID")]
+    id: String,
+    #[tabled(rename = "CPU %")]
+    cpu: String,
+    #[tabled(rename = "MEM USAGE")]
+    memory: String,
+    #[tabled(rename = "MEM %")]
+    mem_percent: String,
+    #[tabled(rename = "NET I/O")]
+    network: String,
+    #[tabled(rename = "BLOCK I/O")]
+    block: String,
+    #[tabled(rename = "PIDS")]
+    pids: String,
+}
+
+#[derive(Tabled)]
+struct ExecRow {
+    #[tabled(rename = "STARTED")]
+    started: String,
+    #[tabled(rename = "USER")]
+    user: String,
+    #[tabled(rename = "COMMAND")]
+    command: String,
+    #[tabled(rename = "DURATION")]
+    duration: String,
+    #[tabled(rename = "EXIT CODE")]
+    exit_code: i32,
+}
+
+#[derive(Tabled)]
+struct CheckpointRow {
+    #[tabled(rename = "CONTAINER")]
+    container: String,
+    #[tabled(rename = "NAME")]
+    name: String,
+    #[tabled(rename = "CREATED")]
+    created: String,
+    #[tabled(rename = "SIZE")]
+    size: String,
+    #[tabled(rename = "DIGEST")]
+    digest: String,
+}
+
+#[derive(Tabled)]
+struct ImageRow {
+    #[tabled(rename = "ID")]
+    id: String,
+    #[tabled(rename = "REPOSITORY")]
+    repository: String,
+    #[tabled(rename = "TAG")]
+    tag: String,
+    #[tabled(rename = "SIZE")]
+    size: String,
+    #[tabled(rename = "CREATED")]
+    created: String,
+}
+
+#[derive(Tabled)]
+struct DiskRow {
+    #[tabled(rename = "DISK")]
+    path: String,
+    #[tabled(rename = "FORMAT")]
+    format: String,
+    #[tabled(rename = "SIZE")]
+    size: String,
+    #[tabled(rename = "ALLOCATED")]
+    allocated: String,
+}
+
+#[derive(Tabled)]
+struct UsageRow {
+    #[tabled(rename = "TYPE")]
+    kind: String,
+    #[tabled(rename = "TOTAL")]
+    total: usize,
+    #[tabled(rename = "ACTIVE")]
+    active: String,
+    #[tabled(rename = "SIZE")]
+    size: String,
+}
+
+#[derive(Tabled)]
+struct FilesystemRow {
+    #[tabled(rename = "MOUNTED ON")]
+    mount_point: String,
+    #[tabled(rename = "DEVICE")]
+    device: String,
+    #[tabled(rename = "TYPE")]
+    fs_type: String,
+    #[tabled(rename = "SIZE")]
+    size: String,
+    #[tabled(rename = "USED")]
+    used: String,
+    #[tabled(rename = "AVAIL")]
+    available: String,
+    #[tabled(rename = "USE%")]
+    use_percent: String,
 }
 
 #[tokio::main]
@@ -306,7 +1211,7 @@ async fn main() {
     // Setup panic handler for graceful cleanup on panics
     setup_panic_handler();
 
-    let cli = Cli::parse();
+    let mut cli = Cli::parse();
 
     // Setup logging
     if cli.verbose {
@@ -340,16 +1245,18 @@ async fn main() {
         }
 
         Commands::Pull { image, quiet } => {
-            let mut store = match ImageStore::new(ImageStore::default_path()) {
-                Ok(s) => s,
+            let config = RuntimeConfig::from_env();
+            let mut store = match ImageStore::with_proxy(ImageStore::default_path(), &config.proxy)
+            {
+                Ok(s) => s.with_limits(config.extract_limits),
                 Err(e) => {
                     eprintln!("{}: {}", "Error".red().bold(), e);
-                    std::process::exit(1);
+                    std::process::exit(exit_code::for_error(&e));
                 }
             };
 
             let quiet = *quiet;
-            let progress_cb: Option<Box<dyn Fn(PullProgress) + Send>> = if quiet {
+            let progress_cb: Option<Box<dyn Fn(PullProgress) + Send + Sync>> = if quiet {
                 None
             } else {
                 Some(Box::new(move |p: PullProgress| {
@@ -372,7 +1279,18 @@ async fn main() {
                 }))
             };
 
-            match store.pull(image, progress_cb).await {
+            // Ctrl+C stops the pull and removes what it downloaded
+            let (handle, pull) = store.pull_cancellable(image, progress_cb);
+            let on_interrupt = tokio::spawn(async move {
+                while !is_shutdown_requested() {
+                    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                }
+                handle.cancel();
+            });
+            let pulled = pull.await;
+            on_interrupt.abort();
+
+            match pulled {
                 Ok(info) => {
                     if !quiet {
                         println!();
@@ -389,7 +1307,7 @@ async fn main() {
                         println!();
                     }
                     eprintln!("{}: {}", "Error".red().bold(), e);
-                    std::process::exit(1);
+                    std::process::exit(exit_code::for_error(&e));
                 }
             }
             return;
@@ -400,7 +1318,7 @@ async fn main() {
                 Ok(s) => s,
                 Err(e) => {
                     eprintln!("{}: {}", "Error".red().bold(), e);
-                    std::process::exit(1);
+                    std::process::exit(exit_code::for_error(&e));
                 }
             };
 
@@ -437,15 +1355,117 @@ async fn main() {
                 Ok(s) => s,
                 Err(e) => {
                     eprintln!("{}: {}", "Error".red().bold(), e);
-                    std::process::exit(1);
+                    std::process::exit(exit_code::for_error(&e));
                 }
             };
 
+            if !store.list().iter().any(|img| img.id == *image) {
+                eprintln!("{}: No such image: {}", "Error".red().bold(), image);
+                std::process::exit(exit_code::NOT_FOUND);
+            }
             match store.remove(image) {
                 Ok(()) => println!("Deleted: {}", image),
                 Err(e) => {
                     eprintln!("{}: {}", "Error".red().bold(), e);
-                    std::process::exit(1);
+                    std::process::exit(exit_code::for_error(&e));
+                }
+            }
+            return;
+        }
+
+        Commands::Save { image, output } => {
+            let saved = ImageStore::new(ImageStore::default_path())
+                .and_then(|store| store.save(image, output));
+            match saved {
+                Ok(()) => println!("Saved {} to {}", image, output.display()),
+                Err(e) => {
+                    eprintln!("{}: {}", "Error".red().bold(), e);
+                    std::process::exit(exit_code::for_error(&e));
+                }
+            }
+            return;
+        }
+
+        Commands::Load { input } => {
+            let config = RuntimeConfig::from_env();
+            let spinner = spinner::Spinner::start();
+            let loaded = ImageStore::new(ImageStore::default_path())
+                .and_then(|store| store.with_limits(config.extract_limits).load(input));
+            drop(spinner);
+            match loaded {
+                Ok(images) => {
+                    for info in images {
+                        println!(
+                            "{}: {} ({})",
+                            "Loaded".green().bold(),
+                            info.reference.full_name(),
+                            info.id
+                        );
+                    }
+                }
+                Err(e) => {
+                    eprintln!("{}: {}", "Error".red().bold(), e);
+                    std::process::exit(exit_code::for_error(&e));
+                }
+            }
+            return;
+        }
+
+        Commands::Login {
+            registry,
+            username,
+            password,
+            password_stdin,
+        } => {
+            let password = match password {
+                Some(password) => password.clone(),
+                None if *password_stdin => {
+                    let mut password = String::new();
+                    if let Err(e) = std::io::stdin().read_line(&mut password) {
+                        eprintln!(
+                            "{}: Failed to read the password: {}",
+                            "Error".red().bold(),
+                            e
+                        );
+                        std::process::exit(exit_code::RUNTIME);
+                    }
+                    password.trim_end_matches(['\r', '\n']).to_string()
+                }
+                None => {
+                    eprintln!(
+                        "{}: Pass --password or --password-stdin",
+                        "Error".red().bold()
+                    );
+                    std::process::exit(exit_code::USAGE);
+                }
+            };
+            let auth = AuthConfig::basic(username.clone(), password);
+
+            let proxy = RuntimeConfig::from_env().proxy;
+            let logged_in = match ImageStore::with_proxy(ImageStore::default_path(), &proxy) {
+                Ok(store) => store.authenticate(registry, &auth).await,
+                Err(e) => Err(e),
+            };
+            let stored = logged_in
+                .and_then(|()| DockerConfig::load())
+                .and_then(|mut config| config.store(registry, &auth));
+            match stored {
+                Ok(()) => println!("{}: {}", "Login Succeeded".green().bold(), registry),
+                Err(e) => {
+                    eprintln!("{}: {}", "Error".red().bold(), e);
+                    std::process::exit(exit_code::for_error(&e));
+                }
+            }
+            return;
+        }
+
+        Commands::Logout { registry } => {
+            match DockerConfig::load().and_then(|mut config| config.remove(registry)) {
+                Ok(true) => println!("Removed login credentials for {}", registry),
+                Ok(false) => println!("Not logged in to {}", registry),
+                Err(e) => {
+                    eprintln!("{}: {}", "Error".red().bold(), e);
+                    std::process::exit(exit_code::for_error(&e));
                 }
             }
             return;
@@ -478,7 +1498,7 @@ async fn main() {
                         let event_str = format_event_type(&event.event_type);
                         print!(
                             "{} {} {}",
-                            format_timestamp(event.timestamp).dimmed(),
+                            timestamp::rfc3339(event.timestamp, 0, cli.utc).dimmed(),
                             event.container_id.cyan(),
                             event_str
                         );
@@ -495,24 +1515,191 @@ async fn main() {
             }
         }
 
-        _ => {} // Continue to runtime-dependent commands
-    }
+        Commands::Agent {
+            command:
+                AgentCommands::BuildInitramfs {
+                    agent,
+                    output,
+                    busybox,
+                    base,
+                    include,
+                },
+        } => {
+            if let Err(e) =
+                build_initramfs(agent, output, busybox.as_deref(), base.as_deref(), include)
+            {
+                eprintln!("{}: {}", "Error".red().bold(), e);
+                std::process::exit(exit_code::RUNTIME);
+            }
+            return;
+        }
 
-    // Build runtime config
-    let mut config = RuntimeConfig::from_env();
-    if let Some(socket) = cli.socket {
+        Commands::Cri {
+            command:
+                CriCommands::Install {
+                    socket,
+                    crictl_config,
+                    kubelet_config,
+                    manifests,
+                    ..
+                },
+        } => {
+            let files = [
+                (crictl_config, cri::crictl_config(socket)),
+                (
+                    kubelet_config,
+                    cri::kubelet_config(socket, manifests, cri::cgroup_driver()),
+                ),
+            ];
+            for (path, contents) in files {
+                if let Err(e) = cri::write_config(path, &contents) {
+                    eprintln!("{}: {}: {}", "Error".red().bold(), path.display(), e);
+                    std::process::exit(exit_code::for_error(&e));
+                }
+                println!("{}: {}", "Wrote".green().bold(), path.display());
+            }
+            if let Err(e) = std::fs::create_dir_all(manifests) {
+                eprintln!("{}: {}: {}", "Error".red().bold(), manifests.display(), e);
+                std::process::exit(exit_code::RUNTIME);
+            }
+            if matches!(
+                cli.command,
+                Commands::Cri {
+                    command: CriCommands::Install {
+                        skip_check: true,
+                        ..
+                    }
+                }
+            ) {
+                return;
+            }
+        }
+
+        Commands::Registry {
+            command: RegistryCommands::Serve { addr, upstream },
+        } => {
+            if let Err(e) = registry::serve(
+                ImageStore::default_path(),
+                addr,
+                upstream.clone(),
+                is_shutdown_requested,
+            )
+            .await
+            {
+                eprintln!("{}: {}", "Error".red().bold(), e);
+                std::process::exit(exit_code::for_error(&e));
+            }
+            return;
+        }
+
+        // Runs without a runtime so that it can report why there is none
+        Commands::Doctor { format } => {
+            let mut config = RuntimeConfig::from_env();
+            if let Some(ref socket) = cli.socket {
+                config.socket_path = socket.clone();
+            }
+            let report = doctor::diagnose(config).await;
+            if format == "json" {
+                println!("{}", serde_json::to_string_pretty(&report).unwrap());
+            } else {
+                print_doctor_report(&report);
+            }
+            if !report.healthy() {
+                std::process::exit(exit_code::RUNTIME);
+            }
+            return;
+        }
+        Commands::SupportBundle {
+            output,
+            rpcs,
+            events,
+        } => {
+            let mut config = RuntimeConfig::from_env();
+            if let Some(ref socket) = cli.socket {
+                config.socket_path = socket.clone();
+            }
+            let options = support::BundleOptions {
+                failed_rpcs: *rpcs,
+                events: *events,
+            };
+            let bundle = support::collect(config, &options).await;
+
+            let name = format!(
+                "crun-shim-support-{}",
+                std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs()
+            );
+            let path = output
+                .clone()
+                .unwrap_or_else(|| PathBuf::from(format!("{}.tar.gz", name)));
+            let written = std::fs::File::create(&path)
+                .map_err(libcrun_shim::ShimError::from)
+                .and_then(|file| bundle.write_tar_gz(std::io::BufWriter::new(file), &name));
+            if let Err(e) = written {
+                eprintln!("{}: {}", "Error".red().bold(), e);
+                std::process::exit(exit_code::for_error(&e));
+            }
+            println!("Wrote {}", path.display());
+            println!("Secrets are redacted, but look the files over before sharing them.");
+            return;
+        }
+
+        // Only the agent's build information needs the runtime
+        Commands::Version if !(cli.verbose && cfg!(target_os = "macos")) => {
+            println!("crun-shim {}", env!("CARGO_PKG_VERSION"));
+            if cli.verbose {
+                print_build_info("Library", &libcrun_shim::build_info(), cli.utc);
+            }
+            return;
+        }
+
+        Commands::Vm {
+            command: VmCommands::Disk { command },
+        } if !matches!(command, DiskCommands::Trim) => {
+            let config = RuntimeConfig::from_env();
+            let result = match command {
+                DiskCommands::Resize { size, disk } => resize_disk(&config, size, disk.as_deref()),
+                DiskCommands::Inspect { disk, format } => {
+                    inspect_disks(&config, disk.as_deref(), format)
+                }
+                DiskCommands::Trim => unreachable!("needs the runtime"),
+            };
+            if let Err(e) = result {
+                eprintln!("{}: {}", "Error".red().bold(), e);
+                std::process::exit(exit_code::for_error(&e));
+            }
+            return;
+        }
+
+        _ => {} // Continue to runtime-dependent commands
+    }
+
+    // Build runtime config
+    let mut config = RuntimeConfig::from_env();
+    if let Some(socket) = cli.socket {
         config.socket_path = socket;
     }
+    let templates = config.templates.clone();
 
     // Create runtime
     let runtime = match ContainerRuntime::new_with_config(config).await {
         Ok(r) => r,
         Err(e) => {
             eprintln!("{}: {}", "Error".red().bold(), e);
-            std::process::exit(1);
+            std::process::exit(exit_code::for_error(&e));
         }
     };
 
+    // Users may refer to containers by name, the runtime takes IDs. What is
+    // not found is left for the command to report
+    for reference in cli.command.containers_mut() {
+        if let Ok(id) = runtime.lookup(reference).await {
+            *reference = id;
+        }
+    }
+
     // Execute command
     let result = match cli.command {
         Commands::Create {
@@ -520,12 +1707,25 @@ async fn main() {
             rootfs,
             cmd,
             env,
+            env_passthrough,
             workdir,
             memory,
+            memory_swap,
+            memory_swappiness,
             cpus,
+            cpuset_cpus,
+            cpuset_mems,
+            profile,
+            allow_egress,
+            deny_egress,
+            max_runtime,
+            stop_timeout,
+            auto_stop,
+            restart,
+            labels,
         } => {
             let mut container_config = ContainerConfig {
-                id: name.clone(),
+                name: Some(name),
                 rootfs,
                 command: if cmd.is_empty() {
                     vec!["/bin/sh".to_string()]
@@ -534,6 +1734,8 @@ async fn main() {
                 },
                 env,
                 working_dir: workdir,
+                env_passthrough,
+                labels: labels.into_iter().collect(),
                 ..Default::default()
             };
 
@@ -546,8 +1748,22 @@ async fn main() {
             if let Some(cpu) = cpus {
                 container_config.resources.cpu = Some(cpu);
             }
-
-            match runtime.create(container_config).await {
+            container_config.resources.memory_swap = memory_swap.as_deref().map(parse_memory_swap);
+            container_config.resources.memory_swappiness = memory_swappiness;
+            container_config.resources.cpuset_cpus = cpuset_cpus;
+            container_config.resources.cpuset_mems = cpuset_mems;
+            container_config.profile = profile;
+            container_config.network.allow_egress = allow_egress;
+            container_config.network.deny_egress = deny_egress;
+            container_config.max_runtime_secs = max_runtime;
+            container_config.stop_timeout = stop_timeout;
+            container_config.auto_stop = auto_stop.map(AutoStopPolicy::after);
+            container_config.restart_policy = restart;
+
+            let spinner = spinner::Spinner::start();
+            let created = runtime.create(container_config).await;
+            drop(spinner);
+            match created {
                 Ok(id) => {
                     println!("{}", id);
                     Ok(())
@@ -556,11 +1772,57 @@ async fn main() {
             }
         }
 
-        Commands::Start { name } => runtime.start(&name).await.map(|_| {
+        Commands::Start { name, force } => {
+            start_container(&runtime, &name, force).await.map(|_| {
+                println!("{}", name);
+            })
+        }
+
+        Commands::Stop { name, timeout } => {
+            let stopped = match timeout {
+                Some(secs) => {
+                    runtime
+                        .stop_with_timeout(&name, std::time::Duration::from_secs(secs))
+                        .await
+                }
+                None => runtime.stop(&name).await,
+            };
+            stopped.map(|_| {
+                println!("{}", name);
+            })
+        }
+
+        Commands::Restart { name, timeout } => runtime
+            .restart(&name, timeout.map(std::time::Duration::from_secs))
+            .await
+            .map(|_| {
+                println!("{}", name);
+            }),
+
+        Commands::Wait { names } => {
+            let mut code = 0;
+            for name in &names {
+                let status = match runtime.wait(name).await {
+                    Ok(status) => status,
+                    Err(e) => {
+                        eprintln!("{}: {}", "Error".red().bold(), e);
+                        std::process::exit(exit_code::for_error(&e));
+                    }
+                };
+                match status.code {
+                    Some(code) => println!("{}", code),
+                    None => println!("unknown"),
+                }
+                code = exit_code::for_container_exit(status.code, status.reason);
+            }
+            std::process::exit(code);
+        }
+
+        Commands::Pause { name } => runtime.pause(&name).await.map(|_| {
             println!("{}", name);
         }),
 
-        Commands::Stop { name } => runtime.stop(&name).await.map(|_| {
+        Commands::Unpause { name } => runtime.unpause(&name).await.map(|_| {
             println!("{}", name);
         }),
 
@@ -573,61 +1835,153 @@ async fn main() {
             })
         }
 
-        Commands::List { all, format } => match runtime.list().await {
-            Ok(containers) => {
-                let filtered: Vec<_> = if all {
-                    containers
-                } else {
-                    containers
-                        .into_iter()
-                        .filter(|c| c.status == ContainerStatus::Running)
-                        .collect()
-                };
+        Commands::Rename {
+            container,
+            new_name,
+        } => runtime.rename(&container, &new_name).await.map(|_| {
+            println!("{}", new_name);
+        }),
 
+        Commands::List {
+            all,
+            format,
+            filters,
+            stats,
+        } => match runtime.list_filtered(&list_filter(all, filters)).await {
+            Ok(filtered) => {
+                // Stopped containers have none
+                let mut metrics = std::collections::HashMap::new();
+                if stats {
+                    match runtime.all_metrics().await {
+                        Ok(all) => metrics.extend(all.into_iter().map(|m| (m.id.clone(), m))),
+                        Err(e) => eprintln!("{}: Could not read usage: {}", "Warning".yellow(), e),
+                    }
+                }
                 if format == "json" {
-                    println!("{}", serde_json::to_string_pretty(&filtered).unwrap());
-                } else {
-                    let rows: Vec<ContainerRow> = filtered
+                    let output = if stats {
+                        let with_metrics: Vec<_> = filtered
+                            .iter()
+                            .map(|c| {
+                                let mut value = serde_json::to_value(c).unwrap();
+                                value["metrics"] = serde_json::json!(metrics.get(&c.id));
+                                value
+                            })
+                            .collect();
+                        serde_json::to_string_pretty(&with_metrics)
+                    } else {
+                        serde_json::to_string_pretty(&filtered)
+                    };
+                    println!("{}", output.unwrap());
+                } else if filtered.is_empty() {
+                    println!("No containers found");
+                } else if stats {
+                    let rows: Vec<ContainerStatsRow> = filtered
                         .into_iter()
-                        .map(|c| ContainerRow {
-                            id: c.id,
-                            status: format_status(c.status),
-                            pid: c.pid.map(|p| p.to_string()).unwrap_or_default(),
+                        .map(|c| {
+                            let usage = metrics.get(&c.id);
+                            ContainerStatsRow {
+                                cpu: usage
+                                    .map(|m| format!("{:.2}%", m.cpu.usage_percent))
+                                    .unwrap_or_default(),
+                                memory: usage
+                                    .map(|m| format_bytes(m.memory.usage))
+                                    .unwrap_or_default(),
+                                container: container_row(c),
+                            }
                         })
                         .collect();
-
-                    if rows.is_empty() {
-                        println!("No containers found");
-                    } else {
-                        println!("{}", Table::new(rows));
-                    }
+                    println!("{}", Table::new(rows));
+                } else {
+                    let rows: Vec<ContainerRow> = filtered.into_iter().map(container_row).collect();
+                    println!("{}", Table::new(rows));
                 }
                 Ok(())
             }
             Err(e) => Err(e),
         },
 
-        Commands::Logs { name, tail, follow } => {
+        Commands::Logs {
+            names,
+            tail,
+            follow,
+            timestamps,
+        } => {
             let options = LogOptions {
                 tail,
                 follow,
                 ..Default::default()
             };
-            match runtime.logs(&name, options).await {
-                Ok(logs) => {
-                    if !logs.stdout.is_empty() {
-                        print!("{}", logs.stdout);
-                    }
-                    if !logs.stderr.is_empty() {
-                        eprint!("{}", logs.stderr);
+            // Lines are prefixed with the containers as they were given
+            let mut ids = Vec::with_capacity(names.len());
+            for name in &names {
+                ids.push(runtime.lookup(name).await.unwrap_or_else(|_| name.clone()));
+            }
+            if names.len() > 1 {
+                let mut prefixer = log_prefix::LogPrefixer::new(&names);
+                let mut print = |id: &str, logs: &ContainerLogs| {
+                    let name = &names[ids.iter().position(|i| i == id).unwrap_or(0)];
+                    write_logs(logs, timestamps, cli.utc, |stream, text| {
+                        prefixer.prefix(name, stream == LogStream::Stderr, &text)
+                    })
+                };
+                if follow {
+                    runtime
+                        .follow_merged_logs(&ids, options, |id, logs| {
+                            print(id, &logs);
+                            !is_shutdown_requested()
+                        })
+                        .await
+                } else {
+                    let mut result = Ok(());
+                    for id in &ids {
+                        match runtime.logs(id, options.clone()).await {
+                            Ok(logs) => print(id, &logs),
+                            Err(e) => {
+                                result = Err(e);
+                                break;
+                            }
+                        }
                     }
-                    Ok(())
+                    result
                 }
-                Err(e) => Err(e),
+            } else if follow {
+                runtime
+                    .follow_logs(&ids[0], options, |logs| {
+                        print_logs(&logs, timestamps, cli.utc);
+                        !is_shutdown_requested()
+                    })
+                    .await
+            } else {
+                runtime
+                    .logs(&ids[0], options)
+                    .await
+                    .map(|logs| print_logs(&logs, timestamps, cli.utc))
             }
         }
 
-        Commands::Stats { name, format } => {
+        Commands::Version => {
+            println!("crun-shim {}", env!("CARGO_PKG_VERSION"));
+            print_build_info("Library", &libcrun_shim::build_info(), cli.utc);
+            #[cfg(target_os = "macos")]
+            let agent = runtime
+                .agent_build_info()
+                .await
+                .map(|info| print_build_info("Agent", &info, cli.utc));
+            #[cfg(not(target_os = "macos"))]
+            let agent = Ok(());
+            agent
+        }
+
+        Commands::Stats {
+            command: Some(StatsCommands::Export { since, format }),
+            ..
+        } => export_stats(&runtime, since, &format).await,
+
+        Commands::Stats {
+            name,
+            format,
+            command: None,
+        } => {
             let metrics_result = if let Some(id) = name {
                 runtime.metrics(&id).await.map(|m| vec![m])
             } else {
@@ -689,59 +2043,130 @@ async fn main() {
             Err(e) => Err(e),
         },
 
+        Commands::Inspect {
+            name,
+            execs,
+            format,
+        } => match runtime.list().await {
+            Ok(containers) => match containers.into_iter().find(|c| c.id == name) {
+                None => Err(libcrun_shim::ShimError::not_found(format!(
+                    "Container '{}' not found",
+                    name
+                ))),
+                Some(container) if !execs => {
+                    if format == "json" {
+                        println!("{}", serde_json::to_string_pretty(&container).unwrap());
+                    } else {
+                        println!("ID:      {}", container.id);
+                        println!("Status:  {}", format_status(container.status));
+                        if let Some(pid) = container.pid {
+                            println!("PID:     {}", pid);
+                        }
+                        if let Some(ref address) = container.ip_address {
+                            println!("IP:      {}", address);
+                        }
+                        if let Some(reason) = container.exit_reason {
+                            println!("Reason:  {}", reason);
+                        }
+                        if container.last_exit_reason.is_some() {
+                            println!(
+                                "Exit:    {}",
+                                format_exit(container.last_exit_code, container.last_exit_reason)
+                            );
+                        }
+                        if container.oom_kills > 0 {
+                            println!("OOM:     {}", format_oom_kills(container.oom_kills));
+                        }
+                        println!("Restarts: {}", container.restart_count);
+                        if container.restart_policy != RestartPolicy::No {
+                            println!(
+                                "Restart: {} ({} in a row)",
+                                container.restart_policy, container.restart_retries
+                            );
+                        }
+                    }
+                    Ok(())
+                }
+                Some(_) => match runtime.exec_sessions(&name).await {
+                    Ok(sessions) if format == "json" => {
+                        println!("{}", serde_json::to_string_pretty(&sessions).unwrap());
+                        Ok(())
+                    }
+                    Ok(sessions) if sessions.is_empty() => {
+                        println!("No exec sessions for {}", name);
+                        Ok(())
+                    }
+                    Ok(sessions) => {
+                        let rows: Vec<ExecRow> = sessions
+                            .into_iter()
+                            .map(|s| ExecRow {
+                                started: timestamp::rfc3339(s.started_at, 0, cli.utc),
+                                user: s.user,
+                                command: s.command.join(" "),
+                                duration: format!("{:.1}s", s.duration_ms as f64 / 1000.0),
+                                exit_code: s.exit_code,
+                            })
+                            .collect();
+                        println!("{}", Table::new(rows));
+                        Ok(())
+                    }
+                    Err(e) => Err(e),
+                },
+            },
+            Err(e) => Err(e),
+        },
+
         Commands::Exec {
             name,
             interactive,
             tty,
+            user,
+            env,
+            workdir,
+            detach_keys,
+            multiplex,
             command,
         } => {
             if command.is_empty() {
                 eprintln!("{}: No command specified", "Error".red().bold());
-                std::process::exit(1);
+                std::process::exit(exit_code::USAGE);
             }
 
-            // Interactive/TTY mode
-            if interactive || tty {
-                #[cfg(unix)]
-                {
-                    use libcrun_shim::get_terminal_size;
-
-                    if let Some((rows, cols)) = get_terminal_size() {
-                        log::debug!("Terminal size: {}x{}", cols, rows);
-                    }
-
-                    eprintln!(
-                        "{}: Interactive exec with TTY is available (basic implementation)",
-                        "Note".yellow()
-                    );
-                    // For full interactive support, we'd need to:
-                    // 1. Create PTY pair
-                    // 2. Pass slave FD to container
-                    // 3. Forward master I/O to stdin/stdout
-
-                    // Fall through to regular exec for now
-                }
-
-                #[cfg(not(unix))]
-                {
-                    eprintln!(
-                        "{}: Interactive mode not supported on this platform",
-                        "Warning".yellow()
-                    );
+            let options = ExecOptions {
+                command,
+                env,
+                working_dir: workdir,
+                uid: user.map(|(uid, _)| uid),
+                gid: user.and_then(|(_, gid)| gid),
+                tty,
+            };
+            // Multiplexed output is streamed, to keep the order it was written in
+            if interactive || tty || multiplex {
+                let attached = exec_interactive(
+                    &runtime,
+                    &name,
+                    options,
+                    interactive,
+                    detach_keys,
+                    multiplex,
+                );
+                match attached.await {
+                    Ok(exit_code) => std::process::exit(exit_code),
+                    Err(e) => Err(e),
                 }
-            }
-
-            match runtime.exec(&name, command).await {
-                Ok((exit_code, stdout, stderr)) => {
-                    print!("{}", stdout);
-                    eprint!("{}", stderr);
-                    std::process::exit(exit_code);
+            } else {
+                match runtime.exec_with_options(&name, options).await {
+                    Ok((exit_code, stdout, stderr)) => {
+                        print!("{}", stdout);
+                        eprint!("{}", stderr);
+                        std::process::exit(exit_code);
+                    }
+                    Err(e) => Err(e),
                 }
-                Err(e) => Err(e),
             }
         }
 
-        Commands::Info => {
+        Commands::Info | Commands::Doctor { .. } | Commands::SupportBundle { .. } => {
             // Handled above
             unreachable!()
         }
@@ -749,6 +2174,11 @@ async fn main() {
         Commands::Pull { .. }
         | Commands::Images { .. }
         | Commands::Rmi { .. }
+        | Commands::Save { .. }
+        | Commands::Load { .. }
+        | Commands::Login { .. }
+        | Commands::Logout { .. }
+        | Commands::Registry { .. }
         | Commands::Events { .. } => {
             // Handled above
             unreachable!()
@@ -759,17 +2189,54 @@ async fn main() {
             name,
             command,
             rm,
+            wait,
+            wait_for_port,
+            wait_healthy,
+            wait_timeout,
+            force,
             env,
+            env_passthrough,
             workdir,
+            read_only,
             memory,
+            memory_swap,
+            memory_swappiness,
             cpus,
+            cpuset_cpus,
+            cpuset_mems,
+            profile,
+            allow_egress,
+            deny_egress,
+            max_runtime,
+            stop_timeout,
+            auto_stop,
+            restart,
+            pull_policy,
+            update_interval,
+            labels,
         } => {
+            let template = match image.strip_prefix('@') {
+                Some(name) => match templates.get(name) {
+                    Some(template) => Some(template.clone()),
+                    None => {
+                        eprintln!(
+                            "{}: Unknown template '{}'. Define it under \"templates\" in the config file.",
+                            "Error".red().bold(),
+                            name
+                        );
+                        std::process::exit(exit_code::NOT_FOUND);
+                    }
+                },
+                None => None,
+            };
+            let image = template.as_ref().map(|t| t.image.clone()).unwrap_or(image);
+
             // First, ensure image is available
-            let store = match ImageStore::new(ImageStore::default_path()) {
+            let mut store = match ImageStore::new(ImageStore::default_path()) {
                 Ok(s) => s,
                 Err(e) => {
                     eprintln!("{}: Image store error: {}", "Error".red().bold(), e);
-                    std::process::exit(1);
+                    std::process::exit(exit_code::for_error(&e));
                 }
             };
 
@@ -793,7 +2260,7 @@ async fn main() {
                                     "Error".red().bold(),
                                     image
                                 );
-                                std::process::exit(1);
+                                std::process::exit(exit_code::NOT_FOUND);
                             }
                         },
                         None => {
@@ -803,32 +2270,25 @@ async fn main() {
                                 image,
                                 image
                             );
-                            std::process::exit(1);
+                            std::process::exit(exit_code::NOT_FOUND);
                         }
                     }
                 }
             };
 
-            let container_name = name.unwrap_or_else(|| {
-                format!(
-                    "run-{}",
-                    std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .map(|d| d.as_secs())
-                        .unwrap_or(0)
-                )
-            });
-
+            // The runtime generates the ID, and a name if none is given.
+            // Other containers may run the same image, so this one never
+            // writes to it
             let mut container_config = ContainerConfig {
-                id: container_name.clone(),
+                name,
                 rootfs,
-                command: if command.is_empty() {
-                    vec!["/bin/sh".to_string()]
-                } else {
-                    command
-                },
+                command,
                 env,
-                working_dir: workdir.unwrap_or_else(|| "/".to_string()),
+                working_dir: workdir.unwrap_or_default(),
+                env_passthrough,
+                rootfs_snapshot: !read_only,
+                read_only_rootfs: read_only,
+                labels: labels.into_iter().collect(),
                 ..Default::default()
             };
 
@@ -838,95 +2298,366 @@ async fn main() {
             if let Some(cpu) = cpus {
                 container_config.resources.cpu = Some(cpu);
             }
+            container_config.resources.memory_swap = memory_swap.as_deref().map(parse_memory_swap);
+            container_config.resources.memory_swappiness = memory_swappiness;
+            container_config.resources.cpuset_cpus = cpuset_cpus;
+            container_config.resources.cpuset_mems = cpuset_mems;
+            container_config.profile = profile;
+            container_config.network.allow_egress = allow_egress;
+            container_config.network.deny_egress = deny_egress;
+            container_config.max_runtime_secs = max_runtime;
+            container_config.stop_timeout = stop_timeout;
+            container_config.auto_stop = auto_stop.map(AutoStopPolicy::after);
+            container_config.restart_policy = restart;
+            container_config.pull_policy = pull_policy;
+            // Record the fully qualified reference so admission policy can check the registry
+            container_config.image = Some(
+                store
+                    .list()
+                    .into_iter()
+                    .find(|img| {
+                        img.id == image
+                            || img.reference.full_name().contains(&image)
+                            || img.reference.reference == image
+                    })
+                    .map(|img| img.reference.full_name())
+                    .unwrap_or(image),
+            );
+            if let Some(template) = &template {
+                template.apply_to(&mut container_config);
+            }
+            if container_config.command.is_empty() {
+                container_config.command = vec!["/bin/sh".to_string()];
+            }
+            if container_config.working_dir.is_empty() {
+                container_config.working_dir = "/".to_string();
+            }
 
             // Create container
-            let id = match runtime.create(container_config).await {
+            let spinner = spinner::Spinner::start();
+            let created = runtime.create(container_config).await;
+            drop(spinner);
+            let id = match created {
                 Ok(id) => {
                     println!("{}", id);
                     id
                 }
                 Err(e) => {
                     eprintln!("{}: {}", "Error".red().bold(), e);
-                    std::process::exit(1);
+                    std::process::exit(exit_code::for_error(&e));
                 }
             };
 
             // Start container
-            if let Err(e) = runtime.start(&id).await {
+            if let Err(e) = start_container(&runtime, &id, force).await {
                 eprintln!("{}: {}", "Error".red().bold(), e);
-                std::process::exit(1);
+                std::process::exit(exit_code::for_error(&e));
             }
 
-            // If --rm, delete after (in a real impl, we'd wait for exit)
-            if rm {
-                // For now, just note that cleanup would happen
-                log::info!("Container {} will be removed after exit", id);
-            }
-
-            Ok(())
-        }
-
-        Commands::Prune { force } => {
-            if !force {
-                println!(
-                    "{}",
-                    "This will remove all stopped containers. Continue? [y/N] ".yellow()
-                );
-                let mut input = String::new();
-                std::io::stdin().read_line(&mut input).ok();
-                if !input.trim().to_lowercase().starts_with('y') {
-                    println!("Aborted.");
-                    return;
+            let checks = wait_for_port
+                .map(ReadyCheck::Port)
+                .into_iter()
+                .chain(wait_healthy.then_some(ReadyCheck::Healthy));
+            for check in checks {
+                if let Err(e) = runtime.wait_ready(&id, check, wait_timeout).await {
+                    eprintln!("{}: {}", "Error".red().bold(), e);
+                    std::process::exit(exit_code::for_error(&e));
                 }
             }
 
-            match runtime.cleanup_stopped().await {
-                Ok(count) => {
-                    println!(
-                        "{}: Removed {} stopped container(s)",
-                        "Prune".green().bold(),
-                        count
+            if wait || rm {
+                let status = match runtime.wait(&id).await {
+                    Ok(status) => status,
+                    Err(e) => {
+                        eprintln!("{}: {}", "Error".red().bold(), e);
+                        std::process::exit(exit_code::for_error(&e));
+                    }
+                };
+                if status.reason == Some(ExitReason::Oom) {
+                    eprintln!(
+                        "{}: Container {} was killed for running out of memory (OOM)",
+                        "Error".red().bold(),
+                        id
                     );
-                    Ok(())
                 }
-                Err(e) => Err(e),
+                if rm {
+                    if let Err(e) = runtime.delete(&id).await {
+                        eprintln!("{}: Failed to remove {}: {}", "Warning".yellow(), id, e);
+                    }
+                }
+                std::process::exit(exit_code::for_container_exit(status.code, status.reason));
             }
-        }
-
-        Commands::Shutdown { timeout } => {
-            println!("{}", "Initiating graceful shutdown...".yellow());
-            println!("Timeout: {} seconds", timeout);
 
-            // Setup a timeout for the shutdown
-            let shutdown_future = runtime.shutdown();
-            let timeout_duration = std::time::Duration::from_secs(timeout);
-
-            match tokio::time::timeout(timeout_duration, shutdown_future).await {
-                Ok(Ok(())) => {
-                    println!("{}", "All containers stopped successfully".green());
-                    Ok(())
-                }
-                Ok(Err(e)) => {
-                    eprintln!("{}: Shutdown error: {}", "Error".red().bold(), e);
-                    Err(e)
-                }
-                Err(_) => {
-                    eprintln!(
-                        "{}: Shutdown timed out after {} seconds",
-                        "Warning".yellow(),
-                        timeout
-                    );
-                    eprintln!("Some containers may still be running");
-                    Ok(())
+            if pull_policy == PullPolicy::AutoUpdate {
+                println!(
+                    "Checking for new versions of the image every {}s (Ctrl+C to stop)",
+                    update_interval.as_secs()
+                );
+                let mut next_check = std::time::Instant::now() + update_interval;
+                while !is_shutdown_requested() {
+                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                    if std::time::Instant::now() < next_check {
+                        continue;
+                    }
+                    next_check += update_interval;
+                    match runtime.update_images(&mut store).await {
+                        Ok(updates) => {
+                            for update in updates {
+                                println!(
+                                    "{} {} to {} ({})",
+                                    "Updated".green(),
+                                    update.container,
+                                    update.image,
+                                    update.image_id
+                                );
+                            }
+                        }
+                        Err(e) => {
+                            eprintln!("{}: Update check failed: {}", "Warning".yellow(), e)
+                        }
+                    }
                 }
             }
+
+            Ok(())
         }
 
-        Commands::Cleanup {
+        Commands::Dev {
+            image,
+            src,
+            dest,
+            name,
+            on_change,
+            debounce_ms,
+            ignore,
+            env,
+            command,
+        } => {
+            let store = match ImageStore::new(ImageStore::default_path()) {
+                Ok(s) => s,
+                Err(e) => {
+                    eprintln!("{}: Image store error: {}", "Error".red().bold(), e);
+                    std::process::exit(exit_code::for_error(&e));
+                }
+            };
+            let Some((rootfs, reference)) = find_image(&store, &image) else {
+                eprintln!(
+                    "{}: Image not found: {}. Use 'crun-shim pull {}' first.",
+                    "Error".red().bold(),
+                    image,
+                    image
+                );
+                std::process::exit(exit_code::NOT_FOUND);
+            };
+
+            // Reloads recreate the container under the same ID and name
+            let id = format!("dev-{}", std::process::id());
+            let config = ContainerConfig {
+                name: Some(name.unwrap_or_else(|| id.clone())),
+                id,
+                rootfs,
+                command: if command.is_empty() {
+                    vec!["/bin/sh".to_string()]
+                } else {
+                    command
+                },
+                env,
+                working_dir: dest.clone(),
+                image: Some(reference),
+                rootfs_snapshot: true,
+                ..Default::default()
+            };
+            let mut ignores: Vec<String> =
+                dev::DEFAULT_IGNORES.iter().map(|p| p.to_string()).collect();
+            ignores.extend(ignore);
+            dev::run(
+                &runtime,
+                config,
+                &src,
+                dest,
+                on_change,
+                ignores,
+                std::time::Duration::from_millis(debounce_ms),
+                is_shutdown_requested,
+            )
+            .await
+        }
+
+        Commands::Scale {
+            targets,
+            image,
+            env,
+            memory,
+            cpus,
+            command,
+        } => {
+            let mut template = ContainerConfig {
+                command: if command.is_empty() {
+                    vec!["/bin/sh".to_string()]
+                } else {
+                    command
+                },
+                env,
+                ..Default::default()
+            };
+            template.resources.memory = memory.as_deref().map(parse_memory);
+            template.resources.cpu = cpus;
+            if let Some(image) = image {
+                let store = match ImageStore::new(ImageStore::default_path()) {
+                    Ok(s) => s,
+                    Err(e) => {
+                        eprintln!("{}: Image store error: {}", "Error".red().bold(), e);
+                        std::process::exit(exit_code::for_error(&e));
+                    }
+                };
+                match find_image(&store, &image) {
+                    Some((rootfs, reference)) => {
+                        template.rootfs = rootfs;
+                        template.image = Some(reference);
+                        template.rootfs_snapshot = true;
+                    }
+                    None => {
+                        eprintln!(
+                            "{}: Image not found: {}. Use 'crun-shim pull {}' first.",
+                            "Error".red().bold(),
+                            image,
+                            image
+                        );
+                        std::process::exit(exit_code::NOT_FOUND);
+                    }
+                }
+            }
+
+            let mut result = Ok(());
+            for target in targets {
+                let Some((name, replicas)) = target
+                    .split_once('=')
+                    .and_then(|(name, n)| Some((name, n.parse::<u32>().ok()?)))
+                else {
+                    eprintln!(
+                        "{}: Invalid scale target '{}', expected NAME=REPLICAS",
+                        "Error".red().bold(),
+                        target
+                    );
+                    std::process::exit(exit_code::USAGE);
+                };
+
+                template.id = name.to_string();
+                match runtime.scale(&template, replicas).await {
+                    Ok(ids) => println!("{}: {} replica(s) {}", name, ids.len(), ids.join(" ")),
+                    Err(e) => {
+                        result = Err(e);
+                        break;
+                    }
+                }
+            }
+            result
+        }
+
+        Commands::Prune {
+            force,
+            images,
+            until,
+            filters,
+            keep_last,
+        } => {
+            let mut filter = PruneFilter::default().keep_last(keep_last);
+            if let Some(age) = until {
+                filter = filter.older_than(age);
+            }
+            filter.labels = filters;
+            if !force {
+                let what = if images {
+                    "images"
+                } else {
+                    "stopped containers"
+                };
+                let which = if filter == PruneFilter::default() {
+                    "all"
+                } else {
+                    "the matching"
+                };
+                println!(
+                    "{}",
+                    format!("This will remove {} {}. Continue? [y/N] ", which, what).yellow()
+                );
+                let mut input = String::new();
+                std::io::stdin().read_line(&mut input).ok();
+                if !input.trim().to_lowercase().starts_with('y') {
+                    println!("Aborted.");
+                    return;
+                }
+            }
+
+            if images {
+                ImageStore::new(ImageStore::default_path())
+                    .and_then(|mut store| store.prune(&filter))
+                    .map(|pruned| {
+                        for image in &pruned {
+                            println!("Deleted: {}", image.id);
+                        }
+                        println!(
+                            "{}: Removed {} image(s)",
+                            "Prune".green().bold(),
+                            pruned.len()
+                        );
+                    })
+            } else {
+                runtime.prune(&filter).await.map(|pruned| {
+                    for id in &pruned {
+                        println!("{}", id);
+                    }
+                    println!(
+                        "{}: Removed {} stopped container(s)",
+                        "Prune".green().bold(),
+                        pruned.len()
+                    );
+                })
+            }
+        }
+
+        Commands::Shutdown { timeout } => {
+            println!("{}", "Initiating graceful shutdown...".yellow());
+            println!("Timeout: {} seconds", timeout);
+
+            // Setup a timeout for the shutdown
+            let shutdown_future = runtime.shutdown();
+            let timeout_duration = std::time::Duration::from_secs(timeout);
+
+            match tokio::time::timeout(timeout_duration, shutdown_future).await {
+                Ok(Ok(())) => {
+                    println!("{}", "All containers stopped successfully".green());
+                    Ok(())
+                }
+                Ok(Err(e)) => {
+                    eprintln!("{}: Shutdown error: {}", "Error".red().bold(), e);
+                    Err(e)
+                }
+                Err(_) => {
+                    eprintln!(
+                        "{}: Shutdown timed out after {} seconds",
+                        "Warning".yellow(),
+                        timeout
+                    );
+                    eprintln!("Some containers may still be running");
+                    Ok(())
+                }
+            }
+        }
+
+        Commands::Cleanup {
+            stale_resources: true,
+            force,
+            dry_run,
+            ..
+        } => cleanup_stale_resources(&runtime, force, dry_run).await,
+
+        Commands::Cleanup {
             orphaned,
             stopped,
             force,
             dry_run,
+            ..
         } => {
             println!("{}", "Container Cleanup".bold());
 
@@ -1151,12 +2882,774 @@ async fn main() {
 
             Ok(())
         }
+
+        Commands::Agent { command } => match command {
+            AgentCommands::Upgrade {
+                binary,
+                guest_path,
+                expect_version,
+            } => {
+                #[cfg(target_os = "macos")]
+                {
+                    println!("Upgrading agent with {}...", binary.display());
+                    match runtime.upgrade_agent(&binary, guest_path.as_deref()).await {
+                        Ok(upgrade) => {
+                            println!(
+                                "{}: {} -> {} (sha256 {})",
+                                "Upgraded".green().bold(),
+                                upgrade.previous.version,
+                                upgrade.current.version,
+                                upgrade.sha256
+                            );
+                            match expect_version {
+                                Some(expected) if expected != upgrade.current.version => {
+                                    Err(libcrun_shim::ShimError::runtime(format!(
+                                        "Upgraded agent reports version {}, expected {}",
+                                        upgrade.current.version, expected
+                                    )))
+                                }
+                                _ => Ok(()),
+                            }
+                        }
+                        Err(e) => Err(e),
+                    }
+                }
+
+                #[cfg(not(target_os = "macos"))]
+                {
+                    let _ = (binary, guest_path, expect_version);
+                    Err(libcrun_shim::ShimError::runtime(
+                        "Agent upgrade is only supported on macOS (no VM agent on Linux)",
+                    ))
+                }
+            }
+            AgentCommands::BuildInitramfs { .. } => unreachable!("handled before runtime setup"),
+        },
+
+        Commands::Attach {
+            session,
+            detach_keys,
+        } => {
+            #[cfg(target_os = "macos")]
+            {
+                let (stdio, raw_mode) = terminal_stdio(true, true, Some(detach_keys));
+                let result = runtime.attach_exec(&session, stdio).await;
+                drop(raw_mode);
+                match result {
+                    Ok(outcome) => std::process::exit(exit_or_detached(outcome)),
+                    Err(e) => Err(e),
+                }
+            }
+
+            #[cfg(not(target_os = "macos"))]
+            {
+                let _ = (session, detach_keys);
+                Err(libcrun_shim::ShimError::runtime(
+                    "Detaching from and attaching to exec sessions is only supported on macOS",
+                ))
+            }
+        }
+
+        Commands::Mount {
+            host_dir,
+            target: (name, destination),
+            read_only,
+        } => {
+            #[cfg(target_os = "macos")]
+            {
+                runtime
+                    .mount(&name, &host_dir, &destination, read_only)
+                    .await
+                    .map(|()| {
+                        println!(
+                            "{}: {} at {}:{}",
+                            "Mounted".green().bold(),
+                            host_dir.display(),
+                            name,
+                            destination
+                        )
+                    })
+            }
+
+            #[cfg(not(target_os = "macos"))]
+            {
+                let _ = (host_dir, name, destination, read_only);
+                Err(libcrun_shim::ShimError::runtime(
+                    "Mounting into running containers is only supported on macOS (use a bind mount at create time on Linux)",
+                ))
+            }
+        }
+
+        Commands::Cp {
+            source,
+            destination,
+            exclude,
+            follow_link,
+            archive,
+        } => {
+            let options = libcrun_shim::CopyOptions {
+                exclude,
+                follow_links: follow_link,
+                preserve_owner: archive,
+            };
+            let spinner = spinner::Spinner::start();
+            let copied = match (&source, &destination) {
+                (CopyPath::Host(from), CopyPath::Container(name, to)) => {
+                    runtime.copy_to(name, from, to, &options).await
+                }
+                (CopyPath::Container(name, from), CopyPath::Host(to)) => {
+                    runtime.copy_from(name, from, to, &options).await
+                }
+                _ => Err(libcrun_shim::ShimError::validation(
+                    "source",
+                    "Exactly one of the source and destination must be NAME:/path in a container",
+                )),
+            };
+            drop(spinner);
+            copied.map(|stats| {
+                println!(
+                    "{}: {} to {} ({} files, {})",
+                    "Copied".green().bold(),
+                    source,
+                    destination,
+                    stats.entries,
+                    format_bytes(stats.bytes)
+                )
+            })
+        }
+
+        Commands::Checkpoint { command } => match command {
+            CheckpointCommands::Create {
+                container,
+                name,
+                leave_running,
+            } => {
+                let name = name.unwrap_or_else(|| {
+                    let now = std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .map(|d| d.as_secs())
+                        .unwrap_or(0);
+                    format!("checkpoint-{}", now)
+                });
+                let spinner = spinner::Spinner::start();
+                let checkpointed = runtime.checkpoint(&container, &name, leave_running).await;
+                drop(spinner);
+                checkpointed.map(|c| {
+                    println!(
+                        "{}: {} of {} ({}, {})",
+                        "Checkpointed".green().bold(),
+                        c.name,
+                        c.container,
+                        format_bytes(c.size_bytes),
+                        c.image_digest
+                    )
+                })
+            }
+            CheckpointCommands::Ls { container, format } => {
+                match runtime.checkpoints(container.as_deref()).await {
+                    Ok(checkpoints) if format == "json" => {
+                        println!("{}", serde_json::to_string_pretty(&checkpoints).unwrap());
+                        Ok(())
+                    }
+                    Ok(checkpoints) if checkpoints.is_empty() => {
+                        println!("No checkpoints found");
+                        Ok(())
+                    }
+                    Ok(checkpoints) => {
+                        let rows: Vec<CheckpointRow> = checkpoints
+                            .into_iter()
+                            .map(|c| CheckpointRow {
+                                created: format_timestamp(c.created_at),
+                                size: format_bytes(c.size_bytes),
+                                digest: short_digest(&c.image_digest).to_string(),
+                                container: c.container,
+                                name: c.name,
+                            })
+                            .collect();
+                        println!("{}", Table::new(rows));
+                        Ok(())
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            CheckpointCommands::Rm { container, name } => runtime
+                .remove_checkpoint(&container, &name)
+                .await
+                .map(|()| println!("{}", name)),
+            CheckpointCommands::Restore {
+                container,
+                name,
+                id,
+            } => {
+                let spinner = spinner::Spinner::start();
+                let restored = runtime.restore(&container, &name, &id).await;
+                drop(spinner);
+                restored.map(|id| println!("{}", id))
+            }
+            CheckpointCommands::Export {
+                container,
+                name,
+                output,
+            } => {
+                let spinner = spinner::Spinner::start();
+                let exported = runtime.export_checkpoint(&container, &name, &output).await;
+                drop(spinner);
+                exported.map(|c| {
+                    println!(
+                        "{}: {} of {} to {}",
+                        "Exported".green().bold(),
+                        c.name,
+                        c.container,
+                        output.display()
+                    )
+                })
+            }
+            CheckpointCommands::Import { input } => {
+                let spinner = spinner::Spinner::start();
+                let imported = runtime.import_checkpoint(&input).await;
+                drop(spinner);
+                imported.map(|c| {
+                    println!(
+                        "{}: {} of {} ({}, {})",
+                        "Imported".green().bold(),
+                        c.name,
+                        c.container,
+                        format_bytes(c.size_bytes),
+                        c.image_digest
+                    )
+                })
+            }
+        },
+
+        Commands::Vm {
+            command: VmCommands::Disk { command },
+        } => match command {
+            DiskCommands::Trim => {
+                #[cfg(target_os = "macos")]
+                {
+                    runtime.trim_guest_disks().await.map(|results| {
+                        if results.is_empty() {
+                            println!("No disk-backed filesystems in the VM");
+                        }
+                        for result in results {
+                            match result.error {
+                                Some(e) => println!(
+                                    "{}: {}: {}",
+                                    "Skipped".yellow().bold(),
+                                    result.mount_point,
+                                    e
+                                ),
+                                None => println!(
+                                    "{}: {} ({} discarded)",
+                                    "Trimmed".green().bold(),
+                                    result.mount_point,
+                                    format_bytes(result.trimmed_bytes)
+                                ),
+                            }
+                        }
+                    })
+                }
+
+                #[cfg(not(target_os = "macos"))]
+                {
+                    Err(libcrun_shim::ShimError::runtime(
+                        "Trimming VM disks is only supported on macOS (no VM on Linux)",
+                    ))
+                }
+            }
+            _ => unreachable!("handled before runtime setup"),
+        },
+
+        Commands::Vm {
+            command: VmCommands::Sync { image },
+        } => {
+            #[cfg(target_os = "macos")]
+            {
+                match ImageStore::new(ImageStore::default_path()) {
+                    Ok(store) => match lookup_image(&store, &image) {
+                        Some(info) => runtime.sync_image(&store, &info.id).await.map(|report| {
+                            println!(
+                                "{}: {} ({} layers sent, {}; {} already in the VM)",
+                                "Synced".green().bold(),
+                                info.reference.full_name(),
+                                report.sent,
+                                format_bytes(report.sent_bytes),
+                                report.cached
+                            )
+                        }),
+                        None => Err(libcrun_shim::ShimError::not_found(format!(
+                            "Image '{}'",
+                            image
+                        ))),
+                    },
+                    Err(e) => Err(e),
+                }
+            }
+
+            #[cfg(not(target_os = "macos"))]
+            {
+                let _ = image;
+                Err(libcrun_shim::ShimError::runtime(
+                    "Syncing images into the VM is only supported on macOS (no VM on Linux)",
+                ))
+            }
+        }
+
+        Commands::System {
+            command: SystemCommands::Df { format },
+        } => system_df(&runtime, &format).await,
+
+        Commands::Cri {
+            command: CriCommands::Install { image, .. },
+        } => {
+            let found = ImageStore::new(ImageStore::default_path())
+                .ok()
+                .and_then(|store| find_image(&store, &image));
+            let checks = cri::self_check(&runtime, &image, found).await;
+            let mut failed = 0;
+            for check in &checks {
+                match &check.outcome {
+                    cri::Outcome::Passed(detail) => {
+                        println!("{} {:<18} {}", "PASS".green().bold(), check.rpc, detail)
+                    }
+                    cri::Outcome::Failed(e) => {
+                        failed += 1;
+                        println!("{} {:<18} {}", "FAIL".red().bold(), check.rpc, e)
+                    }
+                    cri::Outcome::Skipped => println!("{} {}", "SKIP".dimmed(), check.rpc),
+                }
+            }
+            if failed > 0 {
+                Err(libcrun_shim::ShimError::runtime(format!(
+                    "{} of {} CRI checks failed",
+                    failed,
+                    checks.len()
+                )))
+            } else {
+                println!("All {} CRI checks passed", checks.len());
+                Ok(())
+            }
+        }
     };
 
     if let Err(e) = result {
         eprintln!("{}: {}", "Error".red().bold(), e);
-        std::process::exit(1);
+        std::process::exit(exit_code::for_error(&e));
+    }
+}
+
+/// Print each check with its outcome, and the hint under those that need one
+fn print_doctor_report(report: &doctor::Report) {
+    for check in &report.checks {
+        let status = match check.status {
+            doctor::Status::Pass => "PASS".green().bold(),
+            doctor::Status::Warn => "WARN".yellow().bold(),
+            doctor::Status::Fail => "FAIL".red().bold(),
+            doctor::Status::Skip => "SKIP".dimmed(),
+        };
+        println!("{} {:<14} {}", status, check.name, check.detail);
+        if let Some(ref hint) = check.hint {
+            println!("{:<20}{}", "", hint.dimmed());
+        }
+    }
+    println!(
+        "{} passed, {} warnings, {} failed, {} skipped",
+        report.count(doctor::Status::Pass),
+        report.count(doctor::Status::Warn),
+        report.count(doctor::Status::Fail),
+        report.count(doctor::Status::Skip)
+    );
+}
+
+/// Write an initramfs containing the agent (and optionally busybox and extra files)
+fn build_initramfs(
+    agent: &std::path::Path,
+    output: &std::path::Path,
+    busybox: Option<&std::path::Path>,
+    base: Option<&std::path::Path>,
+    include: &[String],
+) -> Result<(), String> {
+    let read = |path: &std::path::Path| {
+        std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))
+    };
+
+    if busybox.is_none() && base.is_none() {
+        return Err("Either --busybox or --base is needed to provide /init".to_string());
+    }
+
+    let agent_binary = read(agent)?;
+    match initramfs::is_static_elf(&agent_binary) {
+        Some(true) => {}
+        Some(false) => println!(
+            "{}: {} is dynamically linked; the guest must provide its libraries (see `make agent-static`)",
+            "Warning".yellow().bold(),
+            agent.display()
+        ),
+        None => return Err(format!("{} is not a 64-bit Linux binary", agent.display())),
+    }
+
+    let mut image = initramfs::Initramfs::for_agent(
+        agent_binary,
+        busybox.map(read).transpose()?,
+        initramfs::source_date_epoch(),
+    );
+    for spec in include {
+        let (host, guest) = initramfs::parse_include(spec).ok_or_else(|| {
+            format!(
+                "Invalid --include '{}', expected HOST_PATH:GUEST_PATH",
+                spec
+            )
+        })?;
+        let mode = {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::metadata(host)
+                .map_err(|e| format!("Failed to read {}: {}", host.display(), e))?
+                .permissions()
+                .mode()
+                & 0o7777
+        };
+        image.file(guest, read(host)?, mode);
+    }
+
+    let mut bytes = match base {
+        Some(base) => read(base)?,
+        None => Vec::new(),
+    };
+    image
+        .write_gzip(&mut bytes)
+        .map_err(|e| format!("Failed to write initramfs: {}", e))?;
+    std::fs::write(output, &bytes)
+        .map_err(|e| format!("Failed to write {}: {}", output.display(), e))?;
+
+    println!(
+        "{}: {} ({})",
+        "Built".green().bold(),
+        output.display(),
+        format_bytes(bytes.len() as u64)
+    );
+    Ok(())
+}
+
+/// The VM disk at `path`, or the only configured one
+fn select_disk(
+    config: &RuntimeConfig,
+    path: Option<&std::path::Path>,
+) -> libcrun_shim::Result<VmDiskConfig> {
+    match path {
+        Some(path) => Ok(config
+            .vm_disks
+            .iter()
+            .find(|d| d.path == path)
+            .cloned()
+            .unwrap_or_else(|| VmDiskConfig {
+                path: path.to_path_buf(),
+                ..Default::default()
+            })),
+        None => match config.vm_disks.as_slice() {
+            [disk] => Ok(disk.clone()),
+            [] => Err(libcrun_shim::ShimError::validation(
+                "disk",
+                "No VM disks are configured (vm_disks); pass --disk",
+            )),
+            _ => Err(libcrun_shim::ShimError::validation(
+                "disk",
+                "Several VM disks are configured; choose one with --disk",
+            )),
+        },
+    }
+}
+
+fn resize_disk(
+    config: &RuntimeConfig,
+    size: &str,
+    path: Option<&std::path::Path>,
+) -> libcrun_shim::Result<()> {
+    let bytes = parse_memory(size);
+    if bytes == 0 {
+        return Err(libcrun_shim::ShimError::validation(
+            "size",
+            format!("Invalid size '{}', expected e.g. 40g or 512m", size),
+        ));
+    }
+    let disk = select_disk(config, path)?;
+    let info = libcrun_shim::disk::resize(&disk, bytes)?;
+    println!(
+        "{}: {} is now {}",
+        "Resized".green().bold(),
+        info.path.display(),
+        format_bytes(info.size_bytes)
+    );
+    println!(
+        "{}",
+        "The VM sees the new size after a restart; grow the filesystem inside it (e.g. resize2fs)"
+            .dimmed()
+    );
+    Ok(())
+}
+
+fn disk_row(info: &DiskImageInfo) -> DiskRow {
+    DiskRow {
+        path: info.path.display().to_string(),
+        format: info.format.clone(),
+        size: format_bytes(info.size_bytes),
+        allocated: format_bytes(info.allocated_bytes),
+    }
+}
+
+fn inspect_disks(
+    config: &RuntimeConfig,
+    path: Option<&std::path::Path>,
+    format: &str,
+) -> libcrun_shim::Result<()> {
+    let disks = match path {
+        Some(path) => vec![select_disk(config, Some(path))?],
+        None => config.vm_disks.clone(),
+    };
+    let infos = disks
+        .iter()
+        .map(libcrun_shim::disk::inspect)
+        .collect::<libcrun_shim::Result<Vec<_>>>()?;
+
+    if format == "json" {
+        println!("{}", serde_json::to_string_pretty(&infos).unwrap());
+    } else if infos.is_empty() {
+        println!("No VM disks configured");
+    } else {
+        println!("{}", Table::new(infos.iter().map(disk_row)));
+    }
+    Ok(())
+}
+
+/// Disk usage of images, containers, VM disk images and the guest filesystems
+/// Print a usage report over the last `window` as CSV, JSON or a table
+async fn export_stats(
+    runtime: &ContainerRuntime,
+    window: std::time::Duration,
+    format: &str,
+) -> libcrun_shim::Result<()> {
+    let reports = runtime.metrics_report(window).await?;
+    match format {
+        "json" => println!("{}", serde_json::to_string_pretty(&reports).unwrap()),
+        "csv" => {
+            let mut header: Vec<String> =
+                ["id", "from", "to", "samples"].map(String::from).to_vec();
+            for metric in ["cpu_percent", "memory_bytes", "pids"] {
+                for stat in ["min", "max", "avg", "p50", "p95", "p99"] {
+                    header.push(format!("{}_{}", metric, stat));
+                }
+            }
+            header.extend(
+                [
+                    "cpu_seconds",
+                    "net_rx_bytes",
+                    "net_tx_bytes",
+                    "blkio_read_bytes",
+                    "blkio_write_bytes",
+                ]
+                .map(String::from),
+            );
+            println!("{}", header.join(","));
+            for r in &reports {
+                let mut fields = vec![
+                    csv_field(&r.id),
+                    r.from.to_string(),
+                    r.to.to_string(),
+                    r.samples.to_string(),
+                ];
+                for stats in [&r.cpu_percent, &r.memory_bytes, &r.pids] {
+                    for value in [
+                        stats.min, stats.max, stats.avg, stats.p50, stats.p95, stats.p99,
+                    ] {
+                        fields.push(format!("{:.2}", value));
+                    }
+                }
+                fields.extend([
+                    format!("{:.3}", r.cpu_seconds),
+                    r.net_rx_bytes.to_string(),
+                    r.net_tx_bytes.to_string(),
+                    r.blkio_read_bytes.to_string(),
+                    r.blkio_write_bytes.to_string(),
+                ]);
+                println!("{}", fields.join(","));
+            }
+        }
+        _ if reports.is_empty() => println!("No metrics recorded in this window"),
+        _ => {
+            let rows = reports.iter().map(|r| UsageReportRow {
+                id: r.id.clone(),
+                samples: r.samples,
+                cpu_avg: format!("{:.2}", r.cpu_percent.avg),
+                cpu_p95: format!("{:.2}", r.cpu_percent.p95),
+                cpu_time: format!("{:.1}s", r.cpu_seconds),
+                memory_avg: format_bytes(r.memory_bytes.avg as u64),
+                memory_max: format_bytes(r.memory_bytes.max as u64),
+                network: format!(
+                    "{} / {}",
+                    format_bytes(r.net_rx_bytes),
+                    format_bytes(r.net_tx_bytes)
+                ),
+                block: format!(
+                    "{} / {}",
+                    format_bytes(r.blkio_read_bytes),
+                    format_bytes(r.blkio_write_bytes)
+                ),
+            });
+            println!("{}", Table::new(rows));
+        }
+    }
+    Ok(())
+}
+
+/// Quote a CSV field if it contains a separator, quote or newline
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// `cleanup --stale-resources`: list what no container owns, and remove it
+/// with `--force`
+async fn cleanup_stale_resources(
+    runtime: &ContainerRuntime,
+    force: bool,
+    dry_run: bool,
+) -> libcrun_shim::Result<()> {
+    println!("{}", "Stale Resource Cleanup".bold());
+    let stale = runtime.stale_resources().await?;
+    if stale.is_empty() {
+        println!("{}", "No stale resources found".green());
+        return Ok(());
+    }
+
+    println!("Found {} stale resource(s):", stale.len());
+    for resource in &stale {
+        println!("  {:<10} {}", resource.kind, resource.path);
+    }
+    if dry_run {
+        println!();
+        println!("{}", "(dry run - no changes made)".dimmed());
+        return Ok(());
+    }
+    if !force {
+        println!();
+        println!("Run with --force to proceed with cleanup");
+        return Ok(());
+    }
+
+    // Scanned again, for what changed in the meantime
+    let removed = runtime.remove_stale_resources().await?;
+    let failed = stale.len().saturating_sub(removed.len());
+    println!();
+    println!(
+        "Removed: {}, Failed: {}",
+        removed.len().to_string().green(),
+        if failed > 0 {
+            failed.to_string().red()
+        } else {
+            failed.to_string().normal()
+        }
+    );
+    if failed > 0 {
+        return Err(libcrun_shim::ShimError::runtime(format!(
+            "{} stale resources could not be removed",
+            failed
+        )));
+    }
+    Ok(())
+}
+
+async fn system_df(runtime: &ContainerRuntime, format: &str) -> libcrun_shim::Result<()> {
+    let images = ImageStore::new(ImageStore::default_path())?.list();
+    let image_bytes: u64 = images.iter().map(|img| img.size).sum();
+    let containers = runtime.list().await?;
+    let running = containers
+        .iter()
+        .filter(|c| c.status == ContainerStatus::Running)
+        .count();
+    // Images are created when the VM first boots, so missing ones are skipped
+    let disks: Vec<DiskImageInfo> = RuntimeConfig::from_env()
+        .vm_disks
+        .iter()
+        .filter_map(|disk| libcrun_shim::disk::inspect(disk).ok())
+        .collect();
+
+    #[cfg(target_os = "macos")]
+    let filesystems = runtime.guest_disk_usage().await.unwrap_or_else(|e| {
+        log::warn!("Cannot read guest disk usage: {}", e);
+        Vec::new()
+    });
+    #[cfg(not(target_os = "macos"))]
+    let filesystems: Vec<libcrun_shim::FilesystemUsage> = Vec::new();
+
+    if format == "json" {
+        let report = serde_json::json!({
+            "images": { "total": images.len(), "size_bytes": image_bytes },
+            "containers": { "total": containers.len(), "running": running },
+            "vm_disks": disks,
+            "guest_filesystems": filesystems,
+        });
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+        return Ok(());
+    }
+
+    let usage = vec![
+        UsageRow {
+            kind: "Images".to_string(),
+            total: images.len(),
+            active: "-".to_string(),
+            size: format_bytes(image_bytes),
+        },
+        UsageRow {
+            kind: "Containers".to_string(),
+            total: containers.len(),
+            active: running.to_string(),
+            size: "-".to_string(),
+        },
+    ];
+    println!("{}", Table::new(usage));
+
+    if !disks.is_empty() {
+        println!();
+        println!("{}", "VM disks".bold());
+        println!("{}", Table::new(disks.iter().map(disk_row)));
+    }
+
+    if !filesystems.is_empty() {
+        println!();
+        println!("{}", "Guest filesystems".bold());
+        let rows = filesystems.iter().map(|fs| FilesystemRow {
+            mount_point: fs.mount_point.clone(),
+            device: fs.device.clone(),
+            fs_type: fs.fs_type.clone(),
+            size: format_bytes(fs.total_bytes),
+            used: format_bytes(fs.used_bytes),
+            available: format_bytes(fs.available_bytes),
+            use_percent: format!(
+                "{:.0}%",
+                fs.used_bytes as f64 / fs.total_bytes.max(1) as f64 * 100.0
+            ),
+        });
+        println!("{}", Table::new(rows));
     }
+    Ok(())
+}
+
+/// Look up an image by ID or reference
+fn lookup_image(store: &ImageStore, image: &str) -> Option<libcrun_shim::ImageInfo> {
+    store.list().into_iter().find(|img| {
+        img.id == image
+            || img.reference.full_name().contains(image)
+            || img.reference.reference == image
+    })
+}
+
+/// Resolve an image by ID or reference to its rootfs and full reference
+fn find_image(store: &ImageStore, image: &str) -> Option<(PathBuf, String)> {
+    let img = lookup_image(store, image)?;
+    let rootfs = store.get_rootfs(&img.id)?;
+    Some((rootfs, img.reference.full_name()))
 }
 
 fn format_status(status: ContainerStatus) -> String {
@@ -1164,6 +3657,31 @@ fn format_status(status: ContainerStatus) -> String {
         ContainerStatus::Running => "Running".green().to_string(),
         ContainerStatus::Created => "Created".yellow().to_string(),
         ContainerStatus::Stopped => "Stopped".dimmed().to_string(),
+        ContainerStatus::Paused => "Paused".cyan().to_string(),
+    }
+}
+
+/// First 12 hex digits of a `sha256:<hex>` digest
+fn short_digest(digest: &str) -> &str {
+    let hex = digest.strip_prefix("sha256:").unwrap_or(digest);
+    hex.get(..12).unwrap_or(hex)
+}
+
+/// How a container's last run ended, e.g. "137 (oom)"
+fn format_exit(code: Option<i32>, reason: Option<ExitReason>) -> String {
+    match (code, reason) {
+        (Some(code), Some(reason)) => format!("{} ({})", code, reason),
+        (None, Some(reason)) => reason.to_string(),
+        (Some(code), None) => code.to_string(),
+        (None, None) => String::new(),
+    }
+}
+
+/// Processes the OOM killer killed, e.g. "2 OOM kills"
+fn format_oom_kills(kills: u32) -> String {
+    match kills {
+        1 => "1 OOM kill".to_string(),
+        kills => format!("{} OOM kills", kills),
     }
 }
 
@@ -1201,6 +3719,332 @@ fn parse_memory(s: &str) -> u64 {
     num_str.parse::<u64>().unwrap_or(0) * multiplier
 }
 
+/// Parse an exec `--user` value: UID or UID:GID
+fn parse_exec_user(s: &str) -> Result<(u32, Option<u32>), String> {
+    let invalid = || format!("invalid user '{}', expected UID[:GID]", s);
+    match s.split_once(':') {
+        Some((uid, gid)) => Ok((
+            uid.parse().map_err(|_| invalid())?,
+            Some(gid.parse().map_err(|_| invalid())?),
+        )),
+        None => Ok((s.parse().map_err(|_| invalid())?, None)),
+    }
+}
+
+/// Parse a time window such as 90s, 30m, 1h or 7d (plain numbers are seconds)
+fn parse_window(s: &str) -> Result<std::time::Duration, String> {
+    let s = s.trim();
+    let (number, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let multiplier = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "invalid window '{}', expected e.g. 30m, 1h or 7d",
+                s
+            ))
+        }
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .filter(|&n| n > 0)
+        .map(|n| std::time::Duration::from_secs(n * multiplier))
+        .ok_or_else(|| format!("invalid window '{}', expected e.g. 30m, 1h or 7d", s))
+}
+
+/// Start a container; with `force`, missing memory or disk is logged as a
+/// warning instead of refusing the start
+/// Print a component's build information for `version --verbose`
+fn print_build_info(component: &str, info: &BuildInfo, utc: bool) {
+    println!();
+    println!("{}:", component.bold());
+    println!("  Version:   {}", info.version);
+    println!(
+        "  Commit:    {}",
+        info.git_sha.as_deref().unwrap_or("unknown")
+    );
+    println!(
+        "  Built:     {}",
+        timestamp::rfc3339(info.build_timestamp, 0, utc)
+    );
+    println!(
+        "  libcrun:   {}",
+        info.libcrun_version.as_deref().unwrap_or("not linked")
+    );
+    if info.min_protocol_version == info.max_protocol_version {
+        println!("  Protocol:  {}", info.max_protocol_version);
+    } else {
+        println!(
+            "  Protocol:  {}-{}",
+            info.min_protocol_version, info.max_protocol_version
+        );
+    }
+}
+
+/// Print container output, stdout and stderr to the CLI's own
+fn print_logs(logs: &ContainerLogs, timestamps: bool, utc: bool) {
+    write_logs(logs, timestamps, utc, |_, text| text);
+}
+
+/// Print logs, passing each piece of output through `decorate` first
+fn write_logs(
+    logs: &ContainerLogs,
+    timestamps: bool,
+    utc: bool,
+    mut decorate: impl FnMut(LogStream, String) -> String,
+) {
+    let mut print = |stream: LogStream, text: String| match stream {
+        LogStream::Stdout => print!("{}", decorate(stream, text)),
+        LogStream::Stderr => eprint!("{}", decorate(stream, text)),
+    };
+    if !logs.entries.is_empty() {
+        // Structured logs keep stdout and stderr interleaved as written
+        for entry in &logs.entries {
+            let mut text = String::from_utf8_lossy(&entry.payload).into_owned();
+            if timestamps {
+                text.insert_str(
+                    0,
+                    &format!("{} ", timestamp::rfc3339_ns(entry.timestamp_ns, utc)),
+                );
+            }
+            print(entry.stream, text);
+        }
+    } else {
+        if !logs.stdout.is_empty() {
+            print(LogStream::Stdout, logs.stdout.clone());
+        }
+        if !logs.stderr.is_empty() {
+            print(LogStream::Stderr, logs.stderr.clone());
+        }
+    }
+    std::io::Write::flush(&mut std::io::stdout()).ok();
+}
+
+async fn start_container(
+    runtime: &ContainerRuntime,
+    id: &str,
+    force: bool,
+) -> libcrun_shim::Result<()> {
+    if force {
+        runtime.force_start(id).await
+    } else {
+        runtime.start(id).await
+    }
+}
+
+/// Run an exec attached to this terminal: with `-i` it gets our input, and
+/// with `-t` a terminal that follows the size of ours, which is switched to
+/// raw mode so keys like Ctrl-C reach the command; without one its output
+/// can be `multiplexed` on our stdout
+async fn exec_interactive(
+    runtime: &ContainerRuntime,
+    name: &str,
+    options: ExecOptions,
+    attach_stdin: bool,
+    detach_keys: Option<DetachKeys>,
+    multiplexed: bool,
+) -> libcrun_shim::Result<i32> {
+    let (mut stdio, raw_mode) = terminal_stdio(options.tty, attach_stdin, detach_keys);
+    if multiplexed {
+        (stdio.stdout, stdio.stderr) = multiplex(std::io::stdout());
+    }
+    let result = runtime.exec_interactive(name, options, stdio).await;
+    drop(raw_mode);
+    result.map(exit_or_detached)
+}
+
+/// This terminal as the input and output of an exec, and raw mode while
+/// the exec has a terminal and our input
+fn terminal_stdio(
+    tty: bool,
+    attach_stdin: bool,
+    detach_keys: Option<DetachKeys>,
+) -> (ExecStdio, Option<RawMode>) {
+    let mut stdio = ExecStdio::inherit();
+    if !attach_stdin {
+        stdio.stdin = Box::new(std::io::empty());
+    }
+    stdio.detach_keys = detach_keys;
+    // Piped input is all there is, so its end ends the command's input
+    // even under a terminal, as in `cat data | crun-shim exec -it ...`
+    stdio.stdin_once = attach_stdin && !std::io::stdin().is_terminal();
+    // Fails when input is piped in, which then passes through unchanged
+    let raw_mode = if tty && attach_stdin {
+        RawMode::enable().ok()
+    } else {
+        None
+    };
+    if tty {
+        match watch_terminal_size() {
+            Ok(resizes) => stdio.resize = Some(resizes),
+            Err(e) => log::warn!("The exec's terminal will keep its size: {}", e),
+        }
+    } else {
+        stdio.terminal_size = None;
+    }
+    (stdio, raw_mode)
+}
+
+/// The exit code of an exec, or 0 after telling how to attach again to one
+/// that was detached from
+fn exit_or_detached(outcome: ExecOutcome) -> i32 {
+    match outcome {
+        ExecOutcome::Exited(exit_code) => exit_code,
+        ExecOutcome::Detached(session) => {
+            eprintln!(
+                "\n{} from exec session {}, attach again with: crun-shim attach {}",
+                "Detached".yellow().bold(),
+                session,
+                session
+            );
+            0
+        }
+    }
+}
+
+/// Parse a `--label` value: KEY=VALUE, or KEY for an empty value
+fn parse_label(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s.split_once('=').unwrap_or((s, ""));
+    if key.is_empty() {
+        return Err(format!("invalid label '{}', expected KEY=VALUE", s));
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Parse a prune `--filter`: label=KEY[=VALUE] or label!=KEY[=VALUE]
+fn parse_prune_filter(s: &str) -> Result<LabelFilter, String> {
+    let parsed = if let Some(label) = s.strip_prefix("label!=") {
+        LabelFilter::parse(label).map(LabelFilter::negated)
+    } else if let Some(label) = s.strip_prefix("label=") {
+        LabelFilter::parse(label)
+    } else {
+        return Err(format!(
+            "invalid filter '{}', expected label=KEY[=VALUE] or label!=KEY[=VALUE]",
+            s
+        ));
+    };
+    parsed.map_err(|e| e.to_string())
+}
+
+/// A `ps --filter` condition
+#[derive(Debug, Clone)]
+enum ListCondition {
+    Label(LabelFilter),
+    Status(ContainerStatus),
+    Name(String),
+}
+
+/// What `ps` lists: running and paused containers, or all of them with
+/// `--all`, narrowed by its `--filter` conditions
+fn list_filter(all: bool, conditions: Vec<ListCondition>) -> ListFilter {
+    let mut filter = ListFilter::default();
+    for condition in conditions {
+        filter = match condition {
+            ListCondition::Label(label) => filter.label(label),
+            ListCondition::Status(status) => filter.status(status),
+            ListCondition::Name(prefix) => filter.name_prefix(prefix),
+        };
+    }
+    // A status filter says which containers to show, like --all
+    if !all && filter.statuses.is_empty() {
+        filter = filter
+            .status(ContainerStatus::Running)
+            .status(ContainerStatus::Paused);
+    }
+    filter
+}
+
+/// Parse a `ps --filter`: label=KEY[=VALUE], label!=KEY[=VALUE],
+/// status=STATUS or name=PREFIX
+fn parse_list_filter(s: &str) -> Result<ListCondition, String> {
+    if let Some(status) = s.strip_prefix("status=") {
+        let status = match status.to_ascii_lowercase().as_str() {
+            "created" => ContainerStatus::Created,
+            "running" => ContainerStatus::Running,
+            "paused" => ContainerStatus::Paused,
+            "stopped" | "exited" => ContainerStatus::Stopped,
+            _ => {
+                return Err(format!(
+                    "invalid status '{}', expected created, running, paused or stopped",
+                    status
+                ))
+            }
+        };
+        return Ok(ListCondition::Status(status));
+    }
+    if let Some(prefix) = s.strip_prefix("name=") {
+        return Ok(ListCondition::Name(prefix.to_string()));
+    }
+    if !s.starts_with("label=") && !s.starts_with("label!=") {
+        return Err(format!(
+            "invalid filter '{}', expected label=KEY[=VALUE], label!=KEY[=VALUE], status=STATUS or name=PREFIX",
+            s
+        ));
+    }
+    parse_prune_filter(s).map(ListCondition::Label)
+}
+
+/// Parse a `--pull-policy` value
+fn parse_pull_policy(s: &str) -> Result<PullPolicy, String> {
+    match s {
+        "local" => Ok(PullPolicy::Local),
+        "auto-update" => Ok(PullPolicy::AutoUpdate),
+        _ => Err(format!(
+            "invalid pull policy '{}', expected local or auto-update",
+            s
+        )),
+    }
+}
+
+/// Parse a `mount` target of the form NAME:/path
+fn parse_mount_target(s: &str) -> Result<(String, String), String> {
+    match s.split_once(':') {
+        Some((name, path)) if !name.is_empty() && path.starts_with('/') => {
+            Ok((name.to_string(), path.to_string()))
+        }
+        _ => Err(format!("invalid target '{}', expected NAME:/path", s)),
+    }
+}
+
+/// One side of a `cp`
+#[derive(Clone, Debug)]
+enum CopyPath {
+    Host(PathBuf),
+    Container(String, String),
+}
+
+impl std::fmt::Display for CopyPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CopyPath::Host(path) => write!(f, "{}", path.display()),
+            CopyPath::Container(name, path) => write!(f, "{}:{}", name, path),
+        }
+    }
+}
+
+/// Parse a `cp` path: NAME:/path in a container, or a path on the host
+///
+/// A path starting with `/` or `.` is always on the host, so host paths
+/// with a colon can be given as e.g. `./a:b`.
+fn parse_copy_path(s: &str) -> Result<CopyPath, String> {
+    if s.starts_with('/') || s.starts_with('.') || !s.contains(':') {
+        return Ok(CopyPath::Host(PathBuf::from(s)));
+    }
+    parse_mount_target(s).map(|(name, path)| CopyPath::Container(name, path))
+}
+
+/// Parse a `--memory-swap` value, where -1 means unlimited swap
+fn parse_memory_swap(s: &str) -> i64 {
+    if s.trim() == "-1" {
+        -1
+    } else {
+        parse_memory(s) as i64
+    }
+}
+
 fn format_timestamp(ts: u64) -> String {
     if ts == 0 {
         return "N/A".to_string();
@@ -1243,6 +4087,7 @@ fn format_event_type(event_type: &ContainerEventType) -> colored::ColoredString
         ContainerEventType::Oom => "oom".red().bold(),
         ContainerEventType::ExecStart => "exec_start".blue(),
         ContainerEventType::ExecDie => "exec_die".blue(),
+        ContainerEventType::Updated => "updated".cyan(),
     }
 }
 
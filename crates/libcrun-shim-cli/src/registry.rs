@@ -0,0 +1,502 @@
+//! Read-only OCI registry over the local image store
+//!
+//! `crun-shim registry serve` lets VMs and other machines pull the images this
+//! host already has, using the pull side of the OCI distribution API. With an
+//! upstream registry, images that are not in the store are pulled into it
+//! first, which makes the server a pull-through cache for the whole LAN.
+
+use libcrun_shim::{ImageInfo, ImageStore, Result, ShimError};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+/// How often the accept loop checks whether to stop
+const STOP_POLL: std::time::Duration = std::time::Duration::from_millis(250);
+
+/// Largest request line and headers accepted
+const MAX_REQUEST_HEAD: u64 = 64 * 1024;
+
+/// A registry API endpoint
+#[derive(Debug, PartialEq)]
+enum Route<'a> {
+    /// `/v2/`, which clients probe for API support
+    Base,
+    Catalog,
+    Tags(&'a str),
+    Manifest(&'a str, &'a str),
+    Blob(&'a str),
+}
+
+fn route(target: &str) -> Option<Route<'_>> {
+    let path = target.split('?').next()?;
+    let rest = path.strip_prefix("/v2")?;
+    if rest.is_empty() || rest == "/" {
+        return Some(Route::Base);
+    }
+    let rest = rest.strip_prefix('/')?;
+    if rest == "_catalog" {
+        return Some(Route::Catalog);
+    }
+    let route = if let Some(name) = rest.strip_suffix("/tags/list") {
+        Route::Tags(name)
+    } else if let Some((name, reference)) = rest.rsplit_once("/manifests/") {
+        if reference.is_empty() {
+            return None;
+        }
+        Route::Manifest(name, reference)
+    } else {
+        let (_, digest) = rest.rsplit_once("/blobs/")?;
+        Route::Blob(digest)
+    };
+    match route {
+        Route::Tags(name) | Route::Manifest(name, _) if name.is_empty() => None,
+        route => Some(route),
+    }
+}
+
+/// Whether a repository name in a request refers to `info`
+///
+/// Docker Hub images answer to their short name too, e.g. `alpine` for
+/// `library/alpine`.
+fn is_named(info: &ImageInfo, name: &str) -> bool {
+    let reference = &info.reference;
+    name == reference.repository
+        || name == format!("{}/{}", reference.registry, reference.repository)
+        || (reference.registry == "docker.io"
+            && reference.repository.strip_prefix("library/") == Some(name))
+}
+
+/// Image to pull from `upstream` for a request by name and tag
+fn upstream_reference(upstream: &str, name: &str, tag: &str) -> String {
+    // Official Docker Hub images live under library/
+    if upstream == "docker.io" && !name.contains('/') {
+        format!("{}/library/{}:{}", upstream, name, tag)
+    } else {
+        format!("{}/{}:{}", upstream, name, tag)
+    }
+}
+
+struct Reply {
+    status: u16,
+    content_type: &'static str,
+    digest: Option<String>,
+    body: Body,
+}
+
+enum Body {
+    Bytes(Vec<u8>),
+    File(PathBuf, u64),
+}
+
+impl Reply {
+    fn json(status: u16, value: serde_json::Value) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            digest: None,
+            body: Body::Bytes(value.to_string().into_bytes()),
+        }
+    }
+
+    /// An error in the format of the distribution spec
+    fn error(status: u16, code: &str, message: impl Into<String>) -> Self {
+        Self::json(
+            status,
+            serde_json::json!({ "errors": [{ "code": code, "message": message.into() }] }),
+        )
+    }
+
+    fn len(&self) -> u64 {
+        match &self.body {
+            Body::Bytes(bytes) => bytes.len() as u64,
+            Body::File(_, len) => *len,
+        }
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    }
+}
+
+struct Registry {
+    root: PathBuf,
+    upstream: Option<String>,
+    /// Held while pulling from upstream, so one image is not pulled twice at once
+    pulls: tokio::sync::Mutex<()>,
+}
+
+impl Registry {
+    /// The store as it is now, including images pulled since the server started
+    fn store(&self) -> Result<ImageStore> {
+        ImageStore::new(&self.root)
+    }
+
+    /// Pull an image into the store
+    async fn pull(&self, image: String) -> Result<()> {
+        // The pull future is not Send, so it runs on a blocking thread
+        let root = self.root.clone();
+        let runtime = tokio::runtime::Handle::current();
+        tokio::task::spawn_blocking(move || {
+            runtime.block_on(async { ImageStore::new(root)?.pull(&image, None).await })
+        })
+        .await
+        .map_err(|e| ShimError::runtime(format!("Image pull task failed: {}", e)))?
+        .map(|_| ())
+    }
+
+    fn find_manifest(&self, name: &str, reference: &str) -> Result<Option<(String, Vec<u8>)>> {
+        let store = self.store()?;
+        for info in store.list().iter().filter(|info| is_named(info, name)) {
+            if info.layers.is_empty() {
+                continue;
+            }
+            let (digest, manifest) = store.manifest(&info.id)?;
+            if info.reference.reference == reference || digest == reference {
+                return Ok(Some((digest, manifest)));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn reply(&self, method: &str, target: &str) -> Result<Reply> {
+        if method != "GET" && method != "HEAD" {
+            return Ok(Reply::error(
+                405,
+                "UNSUPPORTED",
+                "This registry is read-only",
+            ));
+        }
+        let Some(route) = route(target) else {
+            return Ok(Reply::error(404, "NAME_UNKNOWN", "Unknown endpoint"));
+        };
+        Ok(match route {
+            Route::Base => Reply::json(200, serde_json::json!({})),
+            Route::Catalog => {
+                let mut repositories: Vec<String> = self
+                    .store()?
+                    .list()
+                    .into_iter()
+                    .map(|info| info.reference.repository)
+                    .collect();
+                repositories.sort();
+                repositories.dedup();
+                Reply::json(200, serde_json::json!({ "repositories": repositories }))
+            }
+            Route::Tags(name) => {
+                let mut tags: Vec<String> = self
+                    .store()?
+                    .list()
+                    .into_iter()
+                    .filter(|info| is_named(info, name))
+                    .map(|info| info.reference.reference)
+                    .collect();
+                if tags.is_empty() {
+                    return Ok(Reply::error(
+                        404,
+                        "NAME_UNKNOWN",
+                        format!("No images named {}", name),
+                    ));
+                }
+                tags.sort();
+                tags.dedup();
+                Reply::json(200, serde_json::json!({ "name": name, "tags": tags }))
+            }
+            Route::Manifest(name, reference) => {
+                let mut found = self.find_manifest(name, reference)?;
+                // Pulling needs a tag, a manifest digest is only known locally
+                if found.is_none() && !reference.starts_with("sha256:") {
+                    if let Some(ref upstream) = self.upstream {
+                        let _pulling = self.pulls.lock().await;
+                        found = self.find_manifest(name, reference)?;
+                        if found.is_none() {
+                            let image = upstream_reference(upstream, name, reference);
+                            log::info!("Pulling {} for a registry client", image);
+                            self.pull(image).await?;
+                            found = self.find_manifest(name, reference)?;
+                        }
+                    }
+                }
+                match found {
+                    Some((digest, manifest)) => Reply {
+                        status: 200,
+                        content_type: ImageStore::MANIFEST_MEDIA_TYPE,
+                        digest: Some(digest),
+                        body: Body::Bytes(manifest),
+                    },
+                    None => Reply::error(
+                        404,
+                        "MANIFEST_UNKNOWN",
+                        format!("No manifest for {}:{}", name, reference),
+                    ),
+                }
+            }
+            Route::Blob(digest) => match self.store()?.blob_path(digest) {
+                Some(path) => {
+                    let len = std::fs::metadata(&path)?.len();
+                    Reply {
+                        status: 200,
+                        content_type: "application/octet-stream",
+                        digest: Some(digest.to_string()),
+                        body: Body::File(path, len),
+                    }
+                }
+                None => Reply::error(404, "BLOB_UNKNOWN", format!("No blob {}", digest)),
+            },
+        })
+    }
+
+    /// Answer one request and close the connection
+    async fn handle(&self, stream: TcpStream) -> std::io::Result<()> {
+        let (read, mut write) = stream.into_split();
+        let mut reader = BufReader::new(read.take(MAX_REQUEST_HEAD));
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).await?;
+        // Requests to a read-only registry have no body, so the headers are skipped
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header).await? == 0 || header.trim().is_empty() {
+                break;
+            }
+        }
+
+        let mut parts = request_line.split_whitespace();
+        let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+        let reply = match self.reply(method, target).await {
+            Ok(reply) => reply,
+            Err(e) => {
+                log::warn!("{} {} failed: {}", method, target, e);
+                Reply::error(500, "UNKNOWN", e.to_string())
+            }
+        };
+        log::debug!("{} {} -> {}", method, target, reply.status);
+
+        let mut head = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nDocker-Distribution-API-Version: registry/2.0\r\nConnection: close\r\n",
+            reply.status,
+            reason(reply.status),
+            reply.content_type,
+            reply.len()
+        );
+        if let Some(ref digest) = reply.digest {
+            head.push_str(&format!("Docker-Content-Digest: {}\r\n", digest));
+        }
+        head.push_str("\r\n");
+        write.write_all(head.as_bytes()).await?;
+        if method != "HEAD" {
+            match reply.body {
+                Body::Bytes(bytes) => write.write_all(&bytes).await?,
+                Body::File(path, _) => {
+                    let mut file = tokio::fs::File::open(&path).await?;
+                    tokio::io::copy(&mut file, &mut write).await?;
+                }
+            }
+        }
+        write.shutdown().await
+    }
+}
+
+/// Serve the image store at `root` on `addr` until `stop` returns true
+///
+/// With `upstream` (e.g. `docker.io`), manifests asked for by tag that the
+/// store lacks are pulled from that registry first.
+pub async fn serve(
+    root: PathBuf,
+    addr: &str,
+    upstream: Option<String>,
+    stop: impl Fn() -> bool,
+) -> Result<()> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(|e| ShimError::io_with_context(e, format!("Cannot listen on {}", addr)))?;
+    let local = listener.local_addr()?;
+    println!(
+        "Serving {} at http://{}{} (Ctrl+C to stop)",
+        root.display(),
+        local,
+        upstream
+            .as_ref()
+            .map(|u| format!(", pulling missing images from {}", u))
+            .unwrap_or_default()
+    );
+
+    let registry = Arc::new(Registry {
+        root,
+        upstream,
+        pulls: tokio::sync::Mutex::new(()),
+    });
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, peer)) => {
+                    let registry = registry.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = registry.handle(stream).await {
+                            log::debug!("Request from {} failed: {}", peer, e);
+                        }
+                    });
+                }
+                Err(e) => log::warn!("Failed to accept a connection: {}", e),
+            },
+            _ = tokio::time::sleep(STOP_POLL) => {
+                if stop() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libcrun_shim::ImageReference;
+
+    #[test]
+    fn test_route() {
+        assert_eq!(route("/v2/"), Some(Route::Base));
+        assert_eq!(route("/v2"), Some(Route::Base));
+        assert_eq!(route("/v2/_catalog?n=10"), Some(Route::Catalog));
+        assert_eq!(
+            route("/v2/library/alpine/tags/list"),
+            Some(Route::Tags("library/alpine"))
+        );
+        assert_eq!(
+            route("/v2/library/alpine/manifests/3.19"),
+            Some(Route::Manifest("library/alpine", "3.19"))
+        );
+        assert_eq!(
+            route("/v2/alpine/blobs/sha256:abc"),
+            Some(Route::Blob("sha256:abc"))
+        );
+        for target in [
+            "/",
+            "/v1/x",
+            "/v2/alpine",
+            "/v2//manifests/latest",
+            "/v2/a/manifests/",
+        ] {
+            assert_eq!(route(target), None, "{}", target);
+        }
+    }
+
+    #[test]
+    fn test_upstream_reference() {
+        assert_eq!(
+            upstream_reference("docker.io", "alpine", "3.19"),
+            "docker.io/library/alpine:3.19"
+        );
+        assert_eq!(
+            upstream_reference("ghcr.io", "user/app", "v1"),
+            "ghcr.io/user/app:v1"
+        );
+    }
+
+    #[test]
+    fn test_is_named() {
+        let info = |reference: &str| ImageInfo {
+            reference: ImageReference::parse(reference).unwrap(),
+            id: String::new(),
+            size: 0,
+            created: 0,
+            architecture: String::new(),
+            os: String::new(),
+            labels: Default::default(),
+            layers: Vec::new(),
+        };
+        let alpine = info("alpine");
+        assert!(is_named(&alpine, "alpine"));
+        assert!(is_named(&alpine, "library/alpine"));
+        assert!(is_named(&alpine, "docker.io/library/alpine"));
+        assert!(!is_named(&alpine, "alpine/git"));
+
+        let ghcr = info("ghcr.io/user/app:v1");
+        assert!(is_named(&ghcr, "user/app"));
+        assert!(!is_named(&ghcr, "app"));
+    }
+
+    #[tokio::test]
+    async fn test_reply() {
+        let root = std::env::temp_dir().join(format!("registry-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        // The layer's digest is made up: the registry serves what the store recorded
+        let layer = format!("sha256:{}", "cd".repeat(32));
+        // Image IDs are the start of the config's digest
+        let image_dir = root.join("44136fa355b3");
+        std::fs::create_dir_all(&image_dir).unwrap();
+        std::fs::write(image_dir.join("config.json"), "{}").unwrap();
+        std::fs::write(image_dir.join("cdcdcdcdcdcd.tar.gz"), "layer").unwrap();
+        let info = ImageInfo {
+            reference: ImageReference::parse("alpine:3.19").unwrap(),
+            id: "44136fa355b3".to_string(),
+            size: 0,
+            created: 0,
+            architecture: "amd64".to_string(),
+            os: "linux".to_string(),
+            labels: Default::default(),
+            layers: vec![layer.clone()],
+        };
+        std::fs::write(
+            image_dir.join("image_info.json"),
+            serde_json::to_string(&info).unwrap(),
+        )
+        .unwrap();
+        let registry = Registry {
+            root: root.clone(),
+            upstream: None,
+            pulls: tokio::sync::Mutex::new(()),
+        };
+
+        let reply = registry
+            .reply("GET", "/v2/alpine/manifests/3.19")
+            .await
+            .unwrap();
+        assert_eq!(reply.status, 200);
+        let digest = reply.digest.clone().unwrap();
+        let Body::Bytes(manifest) = reply.body else {
+            panic!("manifest is not inline");
+        };
+        let manifest: serde_json::Value = serde_json::from_slice(&manifest).unwrap();
+        assert_eq!(manifest["layers"][0]["digest"], layer.as_str());
+        assert_eq!(manifest["layers"][0]["size"], 5);
+
+        // By digest, as clients do after resolving a tag
+        let reply = registry
+            .reply("HEAD", &format!("/v2/library/alpine/manifests/{}", digest))
+            .await
+            .unwrap();
+        assert_eq!(reply.status, 200);
+
+        let reply = registry
+            .reply("GET", &format!("/v2/alpine/blobs/{}", layer))
+            .await
+            .unwrap();
+        assert!(matches!(reply.body, Body::File(_, 5)));
+        let config = manifest["config"]["digest"].as_str().unwrap();
+        let reply = registry
+            .reply("GET", &format!("/v2/alpine/blobs/{}", config))
+            .await
+            .unwrap();
+        assert!(matches!(reply.body, Body::File(_, 2)));
+
+        for (method, target, status) in [
+            ("GET", "/v2/alpine/manifests/latest", 404),
+            ("GET", "/v2/alpine/blobs/sha256:00", 404),
+            ("GET", "/v2/busybox/tags/list", 404),
+            ("PUT", "/v2/alpine/manifests/3.19", 405),
+            ("GET", "/v2/", 200),
+            ("GET", "/v2/_catalog", 200),
+            ("GET", "/v2/alpine/tags/list", 200),
+        ] {
+            let reply = registry.reply(method, target).await.unwrap();
+            assert_eq!(reply.status, status, "{} {}", method, target);
+        }
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
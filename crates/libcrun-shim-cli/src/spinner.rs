@@ -0,0 +1,121 @@
+//! A spinner with the phase a long operation is in, e.g. a create or a
+//! checkpoint export
+//!
+//! The phases come from the library's progress reports. The spinner is drawn
+//! on stderr, and only when it is a terminal, so output that is piped or
+//! parsed stays as it was; it is cleared when the operation is done.
+
+use colored::Colorize;
+use libcrun_shim::{subscribe_progress, OperationProgress};
+use std::io::{IsTerminal, Write};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::error::RecvError;
+
+const FRAMES: &[char] = &['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+const TICK: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// Whether the spinner was drawn, and whether it is done
+#[derive(Default)]
+struct Line {
+    drawn: bool,
+    done: bool,
+}
+
+/// Shows the phases reported until it is dropped
+pub struct Spinner {
+    task: tokio::task::JoinHandle<()>,
+    line: Arc<Mutex<Line>>,
+}
+
+impl Spinner {
+    /// Start showing the phases reported from now on, if stderr is a terminal
+    pub fn start() -> Option<Self> {
+        if !std::io::stderr().is_terminal() {
+            return None;
+        }
+        let mut progress = subscribe_progress();
+        let line = Arc::new(Mutex::new(Line::default()));
+        let drawing = line.clone();
+        let task = tokio::spawn(async move {
+            let mut latest = None;
+            let mut ticks = tokio::time::interval(TICK);
+            for frame in FRAMES.iter().cycle() {
+                loop {
+                    tokio::select! {
+                        received = progress.recv() => match received {
+                            Ok(phase) => latest = Some(phase),
+                            Err(RecvError::Lagged(_)) => {}
+                            Err(RecvError::Closed) => return,
+                        },
+                        _ = ticks.tick() => break,
+                    }
+                }
+                let Some(phase) = &latest else {
+                    continue;
+                };
+                let mut line = drawing.lock().unwrap();
+                if line.done {
+                    return;
+                }
+                let mut stderr = std::io::stderr();
+                let _ = write!(stderr, "\r\x1b[2K{} {}", frame, describe(phase));
+                let _ = stderr.flush();
+                line.drawn = true;
+            }
+        });
+        Some(Self { task, line })
+    }
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        self.task.abort();
+        // Under the lock, so the task can't draw it again once cleared
+        let mut line = self.line.lock().unwrap();
+        line.done = true;
+        if line.drawn {
+            let mut stderr = std::io::stderr();
+            let _ = write!(stderr, "\r\x1b[2K");
+            let _ = stderr.flush();
+        }
+    }
+}
+
+/// The spinner's text for a phase, e.g. `create web: Creating the container`
+fn describe(phase: &OperationProgress) -> String {
+    let mut text = format!(
+        "{} {}: {}",
+        phase.operation.to_string().bold(),
+        phase.target,
+        phase.message
+    );
+    if let Some(percent) = phase.percent {
+        text.push_str(&format!(" ({}%)", percent));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libcrun_shim::Operation;
+
+    #[test]
+    fn test_describe() {
+        colored::control::set_override(false);
+        let mut phase = OperationProgress {
+            operation: Operation::ExportCheckpoint,
+            target: "web/before-upgrade".to_string(),
+            phase: "copy".to_string(),
+            percent: Some(42),
+            message: "Copying images/pages-1.img".to_string(),
+        };
+        assert_eq!(
+            describe(&phase),
+            "export checkpoint web/before-upgrade: Copying images/pages-1.img (42%)"
+        );
+        phase.percent = None;
+        assert!(describe(&phase).ends_with("pages-1.img"));
+    }
+}
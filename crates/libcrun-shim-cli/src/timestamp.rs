@@ -0,0 +1,99 @@
+//! RFC 3339 timestamps for human-readable output
+//!
+//! Events, log lines and inspect output show times in the local timezone,
+//! or in UTC with `--utc`. JSON output keeps the raw Unix epoch values for
+//! machine consumers.
+
+/// Format a Unix time as RFC 3339, in UTC or the local timezone
+///
+/// Fractional seconds are shown, to the nanosecond, only when `nanos` is
+/// non-zero.
+pub fn rfc3339(secs: u64, nanos: u32, utc: bool) -> String {
+    let offset = if utc { 0 } else { local_offset(secs) };
+    let local = secs as i64 + offset;
+    let (year, month, day) = civil_from_days(local.div_euclid(86400));
+    let time = local.rem_euclid(86400);
+    let mut out = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    );
+    if nanos != 0 {
+        out.push_str(&format!(".{:09}", nanos));
+    }
+    if utc {
+        out.push('Z');
+    } else {
+        let sign = if offset < 0 { '-' } else { '+' };
+        let minutes = offset.abs() / 60;
+        out.push_str(&format!("{}{:02}:{:02}", sign, minutes / 60, minutes % 60));
+    }
+    out
+}
+
+/// Format a Unix time in nanoseconds as RFC 3339
+pub fn rfc3339_ns(ns: u64, utc: bool) -> String {
+    rfc3339(ns / 1_000_000_000, (ns % 1_000_000_000) as u32, utc)
+}
+
+/// Offset of the local timezone from UTC at `secs`, in seconds
+fn local_offset(secs: u64) -> i64 {
+    let time = secs as libc::time_t;
+    // SAFETY: localtime_r only writes to the tm it is given
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return 0;
+    }
+    tm.tm_gmtoff as i64
+}
+
+/// Year, month and day of a day count since 1970-01-01, in the proleptic
+/// Gregorian calendar
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    // Counted from 0000-03-01, so leap days fall at the end of a year
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month as u32, day as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rfc3339_utc() {
+        assert_eq!(rfc3339(0, 0, true), "1970-01-01T00:00:00Z");
+        assert_eq!(rfc3339(1_700_000_000, 0, true), "2023-11-14T22:13:20Z");
+        // Leap day, and the last second of a leap year
+        assert_eq!(rfc3339(951_782_400, 0, true), "2000-02-29T00:00:00Z");
+        assert_eq!(rfc3339(1_735_689_599, 0, true), "2024-12-31T23:59:59Z");
+        assert_eq!(
+            rfc3339_ns(1_700_000_000_000_001_500, true),
+            "2023-11-14T22:13:20.000001500Z"
+        );
+    }
+
+    #[test]
+    fn test_rfc3339_local() {
+        let local = rfc3339(1_700_000_000, 0, false);
+        let (_, offset) = local.split_at(local.len() - 6);
+        assert!(offset.starts_with(['+', '-']), "{}", local);
+        assert_eq!(&offset[3..4], ":", "{}", local);
+    }
+}
@@ -0,0 +1,14 @@
+//! What the agent does with every frame it receives from the host
+#![no_main]
+
+use libcrun_shim_proto::*;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Anything that decodes must encode back to the same message
+    if let Ok(request) = deserialize_request(data) {
+        let bytes = serialize_request(&request).expect("decoded request encodes");
+        let again = deserialize_request(&bytes).expect("re-encoded request decodes");
+        assert_eq!(serialize_request(&again).unwrap(), bytes);
+    }
+});
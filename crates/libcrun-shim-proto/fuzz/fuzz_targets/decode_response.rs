@@ -0,0 +1,14 @@
+//! What the host does with every frame it receives from the agent
+#![no_main]
+
+use libcrun_shim_proto::*;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Anything that decodes must encode back to the same message
+    if let Ok(response) = deserialize_response(data) {
+        let bytes = serialize_response(&response).expect("decoded response encodes");
+        let again = deserialize_response(&bytes).expect("re-encoded response decodes");
+        assert_eq!(serialize_response(&again).unwrap(), bytes);
+    }
+});
@@ -0,0 +1,15 @@
+//! A raw connection: frame headers, payloads and messages all untrusted
+#![no_main]
+
+use libcrun_shim_proto::*;
+use libfuzzer_sys::fuzz_target;
+use std::io::Cursor;
+
+fuzz_target!(|data: &[u8]| {
+    let mut cursor = Cursor::new(data);
+    while let Ok(Some(frame)) = read_frame(&mut cursor) {
+        assert!(frame.len() <= MAX_FRAME_SIZE);
+        let _ = deserialize_request(&frame);
+        let _ = deserialize_response(&frame);
+    }
+});
@@ -0,0 +1,715 @@
+//! Copying files in and out of containers
+//!
+//! A copy is a tar archive, packed from the source as it is sent and unpacked
+//! at the destination as it arrives, so neither end stages it in a file. The
+//! agent and the library runtime resolve container paths inside the
+//! container's rootfs: symbolic links in it point into the rootfs, never out
+//! of it, and nothing in an archive is unpacked outside its destination.
+
+use crate::{
+    deserialize_copy_frame, read_frame, serialize_copy_frame, write_frame, CopyFrame,
+    CopyOptionsProto, CopyStatsProto, COPY_CHUNK_SIZE,
+};
+use std::collections::VecDeque;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+
+/// As many as the kernel follows resolving a path
+const MAX_LINKS: usize = 40;
+
+/// What a copy leaves out, by glob patterns
+///
+/// A pattern without a `/`, e.g. `*.log` or `node_modules`, matches files and
+/// directories of that name anywhere in the copy. One with a `/`, e.g.
+/// `build/cache`, matches the path below the copied directory. Leaving out a
+/// directory leaves out everything in it.
+#[derive(Debug, Clone, Default)]
+pub struct Excludes {
+    names: Vec<glob::Pattern>,
+    paths: Vec<glob::Pattern>,
+}
+
+impl Excludes {
+    pub fn new(patterns: &[String]) -> Result<Self, glob::PatternError> {
+        let mut excludes = Self::default();
+        for pattern in patterns {
+            let pattern = pattern.trim_start_matches("./").trim_matches('/');
+            if pattern.contains('/') {
+                excludes.paths.push(glob::Pattern::new(pattern)?);
+            } else {
+                excludes.names.push(glob::Pattern::new(pattern)?);
+            }
+        }
+        Ok(excludes)
+    }
+
+    /// Whether `relative`, a path below the copied directory, is left out
+    pub fn matches(&self, relative: &Path) -> bool {
+        let options = glob::MatchOptions {
+            require_literal_separator: true,
+            ..Default::default()
+        };
+        let name = relative.file_name().map(Path::new);
+        name.is_some_and(|name| {
+            self.names
+                .iter()
+                .any(|pattern| pattern.matches_path_with(name, options))
+        }) || self
+            .paths
+            .iter()
+            .any(|pattern| pattern.matches_path_with(relative, options))
+    }
+}
+
+/// Resolve `path` inside `root` as if `root` were `/`
+///
+/// Symbolic links, absolute or not, and `..` stay inside `root`. The last
+/// component is only followed with `follow`, and whatever does not exist is
+/// taken as it is.
+pub fn resolve_in_root(root: &Path, path: &Path, follow: bool) -> io::Result<PathBuf> {
+    let mut remaining = components(path);
+    // Relative to `root`
+    let mut resolved = PathBuf::new();
+    let mut links = 0;
+    while let Some(name) = remaining.pop_front() {
+        if name == ".." {
+            resolved.pop();
+            continue;
+        }
+        let candidate = resolved.join(&name);
+        let last = remaining.is_empty();
+        match fs::symlink_metadata(root.join(&candidate)) {
+            Ok(meta) if meta.file_type().is_symlink() && (follow || !last) => {
+                links += 1;
+                if links > MAX_LINKS {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("too many symbolic links resolving {}", path.display()),
+                    ));
+                }
+                let target = fs::read_link(root.join(&candidate))?;
+                if target.is_absolute() {
+                    resolved = PathBuf::new();
+                }
+                for component in components(&target).into_iter().rev() {
+                    remaining.push_front(component);
+                }
+            }
+            _ => resolved = candidate,
+        }
+    }
+    Ok(root.join(resolved))
+}
+
+fn components(path: &Path) -> VecDeque<OsString> {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_owned()),
+            Component::ParentDir => Some("..".into()),
+            // The root, or `.`
+            _ => None,
+        })
+        .collect()
+}
+
+/// Files and directories of a copy, as they are found
+struct Walk<'a> {
+    root: &'a Path,
+    follow_links: bool,
+    excludes: Excludes,
+    /// Directories being walked, so a link to one of them isn't followed
+    /// around in circles
+    ancestors: Vec<(u64, u64)>,
+}
+
+impl Walk<'_> {
+    /// Pass `source`, a path inside the root, and what is in it to `visit`
+    /// with their real paths and their names in the archive
+    fn run(
+        root: &Path,
+        source: &Path,
+        options: &CopyOptionsProto,
+        visit: &mut impl FnMut(&Path, &Path, &fs::Metadata) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let excludes = Excludes::new(&options.exclude)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let name = source.file_name().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} does not name what to copy", source.display()),
+            )
+        })?;
+        let path = resolve_in_root(root, source, options.follow_links)?;
+        let mut walk = Walk {
+            root,
+            follow_links: options.follow_links,
+            excludes,
+            ancestors: Vec::new(),
+        };
+        walk.visit(path, Path::new(name), Path::new(""), visit)
+    }
+
+    fn visit(
+        &mut self,
+        mut path: PathBuf,
+        name: &Path,
+        relative: &Path,
+        visit: &mut impl FnMut(&Path, &Path, &fs::Metadata) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let mut meta = fs::symlink_metadata(&path)?;
+        if meta.file_type().is_symlink() && self.follow_links {
+            let inside = path.strip_prefix(self.root).unwrap_or(&path).to_path_buf();
+            let target = resolve_in_root(self.root, &inside, true)?;
+            // A link pointing nowhere is copied as it is
+            if let Ok(target_meta) = fs::symlink_metadata(&target) {
+                path = target;
+                meta = target_meta;
+            }
+        }
+        visit(&path, name, &meta)?;
+
+        let id = (meta.dev(), meta.ino());
+        if !meta.is_dir() || self.ancestors.contains(&id) {
+            return Ok(());
+        }
+        self.ancestors.push(id);
+        let mut entries = fs::read_dir(&path)?.collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let relative = relative.join(entry.file_name());
+            if self.excludes.matches(&relative) {
+                continue;
+            }
+            self.visit(
+                entry.path(),
+                &name.join(entry.file_name()),
+                &relative,
+                visit,
+            )?;
+        }
+        self.ancestors.pop();
+        Ok(())
+    }
+}
+
+/// Pack `source`, a path inside `root`, into a tar archive written to `out`
+///
+/// The archive holds `source` under its own name and, for a directory,
+/// everything in it that `options.exclude` does not leave out. `progress`
+/// gets the running totals after each entry. Returns the totals.
+pub fn pack<W: Write>(
+    root: &Path,
+    source: &Path,
+    out: &mut W,
+    options: &CopyOptionsProto,
+    mut progress: impl FnMut(&CopyStatsProto),
+) -> io::Result<CopyStatsProto> {
+    let mut builder = tar::Builder::new(out);
+    // Links are followed, inside the root, by the walk
+    builder.follow_symlinks(false);
+    let mut stats = CopyStatsProto::default();
+    Walk::run(root, source, options, &mut |path, name, meta| {
+        builder.append_path_with_name(path, name)?;
+        stats.entries += 1;
+        if meta.is_file() {
+            stats.bytes += meta.len();
+        }
+        progress(&stats);
+        Ok(())
+    })?;
+    builder.into_inner()?;
+    Ok(stats)
+}
+
+/// What [`pack`] would put in the archive, without reading any file
+pub fn measure(
+    root: &Path,
+    source: &Path,
+    options: &CopyOptionsProto,
+) -> io::Result<CopyStatsProto> {
+    let mut stats = CopyStatsProto::default();
+    Walk::run(root, source, options, &mut |_, _, meta| {
+        stats.entries += 1;
+        if meta.is_file() {
+            stats.bytes += meta.len();
+        }
+        Ok(())
+    })?;
+    Ok(stats)
+}
+
+/// Where a copy is unpacked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub dir: PathBuf,
+    /// Name the copy gets instead of its own
+    pub rename: Option<OsString>,
+}
+
+impl Target {
+    /// Where a copy to `destination`, a path inside `root`, goes
+    ///
+    /// Into `destination` when it is a directory, keeping the name of what
+    /// is copied; otherwise into its parent directory, which must exist,
+    /// under `destination`'s name.
+    pub fn resolve(root: &Path, destination: &Path) -> io::Result<Self> {
+        let resolved = resolve_in_root(root, destination, true)?;
+        if resolved.is_dir() {
+            return Ok(Self {
+                dir: resolved,
+                rename: None,
+            });
+        }
+        let (Some(parent), Some(name)) = (destination.parent(), destination.file_name()) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} does not name where to copy to", destination.display()),
+            ));
+        };
+        let dir = resolve_in_root(root, parent, true)?;
+        if !dir.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("directory {} does not exist", parent.display()),
+            ));
+        }
+        Ok(Self {
+            dir,
+            rename: Some(name.to_owned()),
+        })
+    }
+}
+
+/// Unpack the tar archive read from `input` at `target`
+///
+/// No entry ends up outside `target.dir`, neither by its path nor through a
+/// symbolic link unpacked before it. Permission bits and modification times
+/// are kept, and owners with `options.preserve_owner`. `progress` gets the
+/// running totals after each entry. Returns the totals.
+pub fn unpack<R: Read>(
+    input: R,
+    target: &Target,
+    options: &CopyOptionsProto,
+    mut progress: impl FnMut(&CopyStatsProto),
+) -> io::Result<CopyStatsProto> {
+    let mut archive = tar::Archive::new(input);
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_mtime(true);
+    archive.set_preserve_ownerships(options.preserve_owner);
+    archive.set_overwrite(true);
+
+    let base = target.dir.canonicalize()?;
+    let mut stats = CopyStatsProto::default();
+    // Directories get their permissions last, so read-only ones are filled
+    // before they are locked
+    let mut directories = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry_path(&entry.path()?, target.rename.as_deref())?;
+        let dest = base.join(&path);
+        let parent = dest.parent().unwrap_or(&base);
+        if !parent.canonicalize()?.starts_with(&base) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is outside of {}", path.display(), base.display()),
+            ));
+        }
+
+        let kind = entry.header().entry_type();
+        if kind.is_hard_link() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is a hard link, which is not copied", path.display()),
+            ));
+        } else if kind.is_dir() {
+            // Permissions would be set on where the link points
+            if fs::symlink_metadata(&dest).is_ok_and(|meta| meta.file_type().is_symlink()) {
+                fs::remove_file(&dest)?;
+            }
+            match fs::create_dir(&dest) {
+                Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+                _ => directories.push((entry, dest)),
+            }
+        } else {
+            if kind.is_file() {
+                stats.bytes += entry.header().size()?;
+            }
+            entry.unpack(&dest)?;
+        }
+        stats.entries += 1;
+        progress(&stats);
+    }
+    for (mut entry, dest) in directories.into_iter().rev() {
+        entry.unpack(&dest)?;
+    }
+    Ok(stats)
+}
+
+/// Path of an entry relative to the target, under the name the copy gets
+fn entry_path(path: &Path, rename: Option<&OsStr>) -> io::Result<PathBuf> {
+    let mut relative = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) if relative.as_os_str().is_empty() => {
+                relative.push(rename.unwrap_or(name))
+            }
+            Component::Normal(name) => relative.push(name),
+            Component::CurDir => {}
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("entry {} is outside of the copy", path.display()),
+                ))
+            }
+        }
+    }
+    if relative.as_os_str().is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "entry without a path",
+        ));
+    }
+    Ok(relative)
+}
+
+/// Send one frame of a copy
+pub fn send_frame<W: Write>(out: &mut W, frame: &CopyFrame) -> io::Result<()> {
+    let data =
+        serialize_copy_frame(frame).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    write_frame(out, &data)
+}
+
+/// Receive one frame of a copy, `None` once the peer closed the connection
+pub fn receive_frame<R: Read>(input: &mut R) -> io::Result<Option<CopyFrame>> {
+    match read_frame(input)? {
+        Some(data) => deserialize_copy_frame(&data)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        None => Ok(None),
+    }
+}
+
+/// Sends the archive written to it as [`CopyFrame::Data`]
+pub struct FrameWriter<W: Write> {
+    inner: W,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> FrameWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            buf: Vec::with_capacity(COPY_CHUNK_SIZE),
+        }
+    }
+
+    /// Send what is left of the archive and [`CopyFrame::End`], returning
+    /// the connection
+    pub fn finish(mut self) -> io::Result<W> {
+        self.flush()?;
+        send_frame(&mut self.inner, &CopyFrame::End)?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for FrameWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let n = data.len().min(COPY_CHUNK_SIZE - self.buf.len());
+        self.buf.extend_from_slice(&data[..n]);
+        if self.buf.len() == COPY_CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let data = std::mem::replace(&mut self.buf, Vec::with_capacity(COPY_CHUNK_SIZE));
+        send_frame(&mut self.inner, &CopyFrame::Data(data))
+    }
+}
+
+/// Reads the archive received as [`CopyFrame::Data`], up to
+/// [`CopyFrame::End`]
+///
+/// A [`CopyFrame::Failed`] from the peer is an error with its message.
+pub struct FrameReader<R: Read> {
+    inner: R,
+    buf: Vec<u8>,
+    pos: usize,
+    ended: bool,
+}
+
+impl<R: Read> FrameReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            buf: Vec::new(),
+            pos: 0,
+            ended: false,
+        }
+    }
+
+    /// Skip the rest of the archive, e.g. the padding after its last entry,
+    /// returning the connection
+    pub fn finish(mut self) -> io::Result<R> {
+        io::copy(&mut self, &mut io::sink())?;
+        Ok(self.inner)
+    }
+}
+
+impl<R: Read> Read for FrameReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.buf.len() && !self.ended {
+            match receive_frame(&mut self.inner)? {
+                Some(CopyFrame::Data(data)) => {
+                    self.buf = data;
+                    self.pos = 0;
+                }
+                Some(CopyFrame::End) => self.ended = true,
+                Some(CopyFrame::Failed(e)) => return Err(io::Error::other(e)),
+                Some(frame) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unexpected {:?} in the archive", frame),
+                    ))
+                }
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "connection closed during the copy",
+                    ))
+                }
+            }
+        }
+        let n = out.len().min(self.buf.len() - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("copy-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Copy `source` in `root` to `destination` in `dest_root` through frames
+    fn copy(
+        root: &Path,
+        source: &str,
+        dest_root: &Path,
+        destination: &str,
+        options: &CopyOptionsProto,
+    ) -> io::Result<CopyStatsProto> {
+        let mut writer = FrameWriter::new(Vec::new());
+        pack(root, Path::new(source), &mut writer, options, |_| {})?;
+        let frames = writer.finish()?;
+        let target = Target::resolve(dest_root, Path::new(destination))?;
+        let mut reader = FrameReader::new(io::Cursor::new(frames));
+        let stats = unpack(&mut reader, &target, options, |_| {})?;
+        reader.finish()?;
+        Ok(stats)
+    }
+
+    #[test]
+    fn test_excludes() {
+        let excludes = Excludes::new(&[
+            "*.log".to_string(),
+            "node_modules/".to_string(),
+            "./build/cache".to_string(),
+        ])
+        .unwrap();
+        assert!(excludes.matches(Path::new("app.log")));
+        assert!(excludes.matches(Path::new("logs/today.log")));
+        assert!(excludes.matches(Path::new("web/node_modules")));
+        assert!(excludes.matches(Path::new("build/cache")));
+        assert!(!excludes.matches(Path::new("web/build/cache")));
+        assert!(!excludes.matches(Path::new("build")));
+        assert!(!excludes.matches(Path::new("app.log.gz")));
+        assert!(Excludes::new(&["[".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_resolve_in_root() {
+        let root = temp_dir("resolve");
+        fs::create_dir_all(root.join("srv/data")).unwrap();
+        symlink("/srv", root.join("app")).unwrap();
+        symlink("../../..", root.join("srv/up")).unwrap();
+
+        // An absolute link target is in the root, not on the host
+        assert_eq!(
+            resolve_in_root(&root, Path::new("/app/data"), true).unwrap(),
+            root.join("srv/data")
+        );
+        assert_eq!(
+            resolve_in_root(&root, Path::new("/srv/up/etc"), true).unwrap(),
+            root.join("etc")
+        );
+        assert_eq!(
+            resolve_in_root(&root, Path::new("/app"), false).unwrap(),
+            root.join("app")
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_copy_tree() {
+        let root = temp_dir("tree-src");
+        let dest = temp_dir("tree-dest");
+        fs::create_dir_all(root.join("app/node_modules/left-pad")).unwrap();
+        fs::create_dir_all(root.join("app/bin")).unwrap();
+        fs::write(root.join("app/main.js"), "main").unwrap();
+        fs::write(root.join("app/debug.log"), "noise").unwrap();
+        fs::write(root.join("app/node_modules/left-pad/index.js"), "pad").unwrap();
+        fs::write(root.join("app/bin/run"), "#!/bin/sh\n").unwrap();
+        fs::set_permissions(root.join("app/bin/run"), fs::Permissions::from_mode(0o755)).unwrap();
+        fs::set_permissions(root.join("app/bin"), fs::Permissions::from_mode(0o555)).unwrap();
+        symlink("main.js", root.join("app/index.js")).unwrap();
+
+        let options = CopyOptionsProto {
+            exclude: vec!["*.log".to_string(), "node_modules".to_string()],
+            ..Default::default()
+        };
+        let expected = measure(&root, Path::new("/app"), &options).unwrap();
+        let stats = copy(&root, "/app", &dest, "/", &options).unwrap();
+        assert_eq!(stats, expected);
+        // app, bin, bin/run, index.js and main.js
+        assert_eq!(stats.entries, 5);
+        assert_eq!(stats.bytes, 14);
+
+        assert_eq!(
+            fs::read_to_string(dest.join("app/main.js")).unwrap(),
+            "main"
+        );
+        assert_eq!(
+            fs::read_link(dest.join("app/index.js")).unwrap(),
+            Path::new("main.js")
+        );
+        assert!(!dest.join("app/debug.log").exists());
+        assert!(!dest.join("app/node_modules").exists());
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&dest.join("app/bin/run")), 0o755);
+        assert_eq!(mode(&dest.join("app/bin")), 0o555);
+
+        // To a path that does not exist, the copy takes its name
+        copy(&root, "/app/main.js", &dest, "/renamed.js", &options).unwrap();
+        assert_eq!(fs::read_to_string(dest.join("renamed.js")).unwrap(), "main");
+        assert_eq!(
+            copy(&root, "/app", &dest, "/missing/app", &options)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+
+        for dir in [&root, &dest] {
+            fs::set_permissions(dir.join("app/bin"), fs::Permissions::from_mode(0o755)).unwrap();
+            fs::remove_dir_all(dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_follow_links() {
+        let root = temp_dir("follow-src");
+        let dest = temp_dir("follow-dest");
+        fs::create_dir_all(root.join("etc")).unwrap();
+        fs::write(root.join("etc/config"), "settings").unwrap();
+        // Points at the host's /etc outside the root, so at the root's own
+        symlink("/etc/config", root.join("config")).unwrap();
+        symlink(".", root.join("etc/loop")).unwrap();
+
+        let options = CopyOptionsProto {
+            follow_links: true,
+            ..Default::default()
+        };
+        copy(&root, "/config", &dest, "/", &options).unwrap();
+        assert!(!fs::symlink_metadata(dest.join("config"))
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read_to_string(dest.join("config")).unwrap(), "settings");
+
+        // A link to a directory being copied is not followed again
+        let stats = copy(&root, "/etc", &dest, "/", &options).unwrap();
+        assert_eq!(stats.entries, 3);
+        fs::remove_dir_all(&root).unwrap();
+        fs::remove_dir_all(&dest).unwrap();
+    }
+
+    #[test]
+    fn test_unpack_stays_inside_target() {
+        let dest = temp_dir("escape-dest");
+        let outside = temp_dir("escape-outside");
+        let mut archive = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        archive.append_link(&mut header, "link", &outside).unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_mode(0o644);
+        archive
+            .append_data(&mut header, "link/file", &b"oops"[..])
+            .unwrap();
+        let data = archive.into_inner().unwrap();
+
+        let target = Target::resolve(&dest, Path::new("/")).unwrap();
+        let err = unpack(
+            io::Cursor::new(data),
+            &target,
+            &CopyOptionsProto::default(),
+            |_| {},
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(!outside.join("file").exists());
+
+        assert!(entry_path(Path::new("../etc/passwd"), None).is_err());
+        assert_eq!(
+            entry_path(Path::new("./app/main.js"), Some(OsStr::new("web"))).unwrap(),
+            Path::new("web/main.js")
+        );
+        fs::remove_dir_all(&dest).unwrap();
+        fs::remove_dir_all(&outside).unwrap();
+    }
+
+    #[test]
+    fn test_frames() {
+        let data: Vec<u8> = (0..COPY_CHUNK_SIZE * 2 + 10).map(|i| i as u8).collect();
+        let mut writer = FrameWriter::new(Vec::new());
+        writer.write_all(&data).unwrap();
+        let mut frames = writer.finish().unwrap();
+        send_frame(&mut frames, &CopyFrame::Done(CopyStatsProto::default())).unwrap();
+
+        let mut cursor = io::Cursor::new(frames);
+        let mut reader = FrameReader::new(&mut cursor);
+        let mut received = Vec::new();
+        reader.read_to_end(&mut received).unwrap();
+        assert_eq!(received, data);
+        reader.finish().unwrap();
+        assert_eq!(
+            receive_frame(&mut cursor).unwrap(),
+            Some(CopyFrame::Done(CopyStatsProto::default()))
+        );
+        assert_eq!(receive_frame(&mut cursor).unwrap(), None);
+
+        let mut frames = Vec::new();
+        send_frame(&mut frames, &CopyFrame::Failed("disk full".to_string())).unwrap();
+        let err = FrameReader::new(io::Cursor::new(frames))
+            .read_to_end(&mut Vec::new())
+            .unwrap_err();
+        assert_eq!(err.to_string(), "disk full");
+    }
+}
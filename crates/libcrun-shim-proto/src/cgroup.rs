@@ -0,0 +1,423 @@
+//! Reading a container's cgroup
+//!
+//! Limits, usage and counters of a container live in the files of its
+//! cgroup, laid out differently on the unified v2 hierarchy and on v1. The
+//! agent and the native Linux runtime both find and read a container's
+//! cgroup through here, so they report the same metrics and check limits the
+//! same way on either hierarchy.
+
+use crate::{
+    is_cgroup_v2, BlkioMetricsProto, CpuMetricsProto, MemoryMetricsProto, PidsMetricsProto,
+};
+use std::collections::HashMap;
+
+/// Where a process's cgroup files live
+#[derive(Debug, PartialEq)]
+pub enum CgroupPaths {
+    /// cgroup v2: one directory holds every controller's files
+    Unified(String),
+    /// cgroup v1: one directory per controller, keyed by controller name
+    Legacy(HashMap<String, String>),
+}
+
+impl CgroupPaths {
+    /// The cgroup of process `pid`, on the hierarchy mounted on the host
+    pub fn find(pid: u32) -> Option<Self> {
+        let content = std::fs::read_to_string(format!("/proc/{}/cgroup", pid)).ok()?;
+        Self::parse(&content, is_cgroup_v2())
+    }
+
+    /// Parse /proc/<pid>/cgroup for the hierarchy mounted on the host.
+    ///
+    /// Hybrid hosts list a "0::" line next to the v1 controllers, so the mode
+    /// decides which lines count rather than the file itself.
+    pub fn parse(content: &str, unified: bool) -> Option<Self> {
+        if unified {
+            return content
+                .lines()
+                .find_map(|line| line.strip_prefix("0::"))
+                .map(|path| CgroupPaths::Unified(format!("/sys/fs/cgroup{}", path)));
+        }
+        // cgroup v1: "hierarchy:controller[,controller]:path"
+        let mut dirs = HashMap::new();
+        for line in content.lines() {
+            let mut parts = line.splitn(3, ':');
+            let (Some(_), Some(controllers), Some(path)) =
+                (parts.next(), parts.next(), parts.next())
+            else {
+                continue;
+            };
+            if controllers.is_empty() || controllers.starts_with("name=") {
+                continue;
+            }
+            let dir = format!("/sys/fs/cgroup/{}{}", controllers, path);
+            for controller in controllers.split(',') {
+                dirs.insert(controller.to_string(), dir.clone());
+            }
+        }
+        (!dirs.is_empty()).then_some(CgroupPaths::Legacy(dirs))
+    }
+
+    /// Read a controller's file, `None` if the controller isn't mounted
+    pub fn read(&self, controller: &str, file: &str) -> Option<String> {
+        let dir = match self {
+            CgroupPaths::Unified(dir) => dir,
+            CgroupPaths::Legacy(dirs) => dirs.get(controller)?,
+        };
+        std::fs::read_to_string(format!("{}/{}", dir, file)).ok()
+    }
+
+    /// Read a single-number controller file
+    pub fn read_u64(&self, controller: &str, file: &str) -> Option<u64> {
+        self.read(controller, file)?.trim().parse().ok()
+    }
+}
+
+/// Limits of the container's cgroup that do not hold the value asked for,
+/// e.g. when rootless crun could not write them or the controller is not
+/// enabled, as "<file> is <value>, expected <value>"
+///
+/// `limits` are as [`cgroup_limits`](crate::cgroup_limits) lists them.
+pub fn unapplied_limits(
+    cgroup: &CgroupPaths,
+    limits: &[(&'static str, &'static str, String)],
+) -> Vec<String> {
+    limits
+        .iter()
+        .filter_map(|(controller, file, expected)| {
+            let actual = cgroup.read(controller, file);
+            let actual = actual
+                .as_deref()
+                .and_then(|v| v.lines().next())
+                .map(str::trim);
+            (actual != Some(expected.as_str())).then(|| {
+                format!(
+                    "{} is {}, expected {}",
+                    file,
+                    actual.unwrap_or("missing"),
+                    expected
+                )
+            })
+        })
+        .collect()
+}
+
+/// Processes of the cgroup the memory controller has OOM-killed
+pub fn oom_kill_count(cgroup: &CgroupPaths) -> u32 {
+    let file = match cgroup {
+        CgroupPaths::Unified(_) => "memory.events",
+        CgroupPaths::Legacy(_) => "memory.oom_control",
+    };
+    stat_lines(&cgroup.read("memory", file).unwrap_or_default())
+        .find(|(key, _)| *key == "oom_kill")
+        .map_or(0, |(_, count)| count.try_into().unwrap_or(u32::MAX))
+}
+
+/// Split "key value" lines of a stat file
+fn stat_lines(content: &str) -> impl Iterator<Item = (&str, u64)> {
+    content.lines().filter_map(|line| {
+        let mut parts = line.split_whitespace();
+        let key = parts.next()?;
+        Some((key, parts.next()?.parse().unwrap_or(0)))
+    })
+}
+
+pub fn read_cpu_metrics(cgroup: &CgroupPaths) -> CpuMetricsProto {
+    let mut cpu = CpuMetricsProto::default();
+
+    match cgroup {
+        // cgroup v2: cpu.stat, in microseconds
+        CgroupPaths::Unified(_) => {
+            for (key, value) in stat_lines(&cgroup.read("cpu", "cpu.stat").unwrap_or_default()) {
+                match key {
+                    "usage_usec" => cpu.usage_total = value * 1000,
+                    "user_usec" => cpu.usage_user = value * 1000,
+                    "system_usec" => cpu.usage_system = value * 1000,
+                    "nr_throttled" => cpu.throttled_periods = value,
+                    "throttled_usec" => cpu.throttled_time = value * 1000,
+                    _ => {}
+                }
+            }
+        }
+        // cgroup v1: cpuacct for usage, cpu.stat for throttling
+        CgroupPaths::Legacy(_) => {
+            cpu.usage_total = cgroup.read_u64("cpuacct", "cpuacct.usage").unwrap_or(0);
+            // cpuacct.stat counts USER_HZ ticks
+            let tick_ns = 1_000_000_000 / clock_ticks();
+            for (key, value) in
+                stat_lines(&cgroup.read("cpuacct", "cpuacct.stat").unwrap_or_default())
+            {
+                match key {
+                    "user" => cpu.usage_user = value * tick_ns,
+                    "system" => cpu.usage_system = value * tick_ns,
+                    _ => {}
+                }
+            }
+            for (key, value) in stat_lines(&cgroup.read("cpu", "cpu.stat").unwrap_or_default()) {
+                match key {
+                    "nr_throttled" => cpu.throttled_periods = value,
+                    "throttled_time" => cpu.throttled_time = value,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    cpu
+}
+
+/// USER_HZ, the unit of cpuacct.stat
+fn clock_ticks() -> u64 {
+    match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+        ticks if ticks > 0 => ticks as u64,
+        _ => 100,
+    }
+}
+
+/// cgroup v1 reports "no limit" as the largest page-aligned i64
+const CGROUP_V1_UNLIMITED: u64 = i64::MAX as u64 & !0xfff;
+
+pub fn read_memory_metrics(cgroup: &CgroupPaths) -> MemoryMetricsProto {
+    let mut mem = MemoryMetricsProto::default();
+
+    match cgroup {
+        // cgroup v2: memory.current, memory.max, memory.stat
+        CgroupPaths::Unified(_) => {
+            mem.usage = cgroup.read_u64("memory", "memory.current").unwrap_or(0);
+            // "max" means unlimited
+            mem.limit = cgroup.read_u64("memory", "memory.max").unwrap_or(u64::MAX);
+            mem.max_usage = cgroup.read_u64("memory", "memory.peak").unwrap_or(0);
+            mem.swap = cgroup
+                .read_u64("memory", "memory.swap.current")
+                .unwrap_or(0);
+            for (key, value) in
+                stat_lines(&cgroup.read("memory", "memory.stat").unwrap_or_default())
+            {
+                match key {
+                    "file" => mem.cache = value,
+                    "anon" => mem.rss = value,
+                    _ => {}
+                }
+            }
+        }
+        // cgroup v1: memory.usage_in_bytes, memory.limit_in_bytes, memory.stat
+        CgroupPaths::Legacy(_) => {
+            mem.usage = cgroup
+                .read_u64("memory", "memory.usage_in_bytes")
+                .unwrap_or(0);
+            mem.limit = match cgroup.read_u64("memory", "memory.limit_in_bytes") {
+                Some(limit) if limit < CGROUP_V1_UNLIMITED => limit,
+                _ => u64::MAX,
+            };
+            mem.max_usage = cgroup
+                .read_u64("memory", "memory.max_usage_in_bytes")
+                .unwrap_or(0);
+            for (key, value) in
+                stat_lines(&cgroup.read("memory", "memory.stat").unwrap_or_default())
+            {
+                match key {
+                    "cache" => mem.cache = value,
+                    "rss" => mem.rss = value,
+                    // Only present with swap accounting enabled
+                    "swap" => mem.swap = value,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    if mem.limit > 0 && mem.limit != u64::MAX {
+        mem.usage_percent = (mem.usage as f64 / mem.limit as f64) * 100.0;
+    }
+
+    mem
+}
+
+pub fn read_blkio_metrics(cgroup: &CgroupPaths) -> BlkioMetricsProto {
+    let mut blkio = BlkioMetricsProto::default();
+
+    match cgroup {
+        // cgroup v2: io.stat
+        CgroupPaths::Unified(_) => {
+            let content = cgroup.read("io", "io.stat").unwrap_or_default();
+            for line in content.lines() {
+                // Format: "major:minor rbytes=X wbytes=Y rios=Z wios=W"
+                for part in line.split_whitespace() {
+                    if let Some(value) = part.strip_prefix("rbytes=") {
+                        blkio.read_bytes += value.parse::<u64>().unwrap_or(0);
+                    } else if let Some(value) = part.strip_prefix("wbytes=") {
+                        blkio.write_bytes += value.parse::<u64>().unwrap_or(0);
+                    } else if let Some(value) = part.strip_prefix("rios=") {
+                        blkio.read_ops += value.parse::<u64>().unwrap_or(0);
+                    } else if let Some(value) = part.strip_prefix("wios=") {
+                        blkio.write_ops += value.parse::<u64>().unwrap_or(0);
+                    }
+                }
+            }
+        }
+        // cgroup v1: blkio.throttle.*, "major:minor Op value" per device
+        // plus a "Total value" line, which is skipped
+        CgroupPaths::Legacy(_) => {
+            let per_op = |file: &str| {
+                let (mut read, mut write) = (0, 0);
+                for line in cgroup.read("blkio", file).unwrap_or_default().lines() {
+                    let parts: Vec<&str> = line.split_whitespace().collect();
+                    if let [_, op, value] = parts.as_slice() {
+                        let value: u64 = value.parse().unwrap_or(0);
+                        match *op {
+                            "Read" => read += value,
+                            "Write" => write += value,
+                            _ => {}
+                        }
+                    }
+                }
+                (read, write)
+            };
+            (blkio.read_bytes, blkio.write_bytes) = per_op("blkio.throttle.io_service_bytes");
+            (blkio.read_ops, blkio.write_ops) = per_op("blkio.throttle.io_serviced");
+        }
+    }
+
+    blkio
+}
+
+pub fn read_pids_metrics(cgroup: &CgroupPaths) -> PidsMetricsProto {
+    // Same files in both versions, "max" meaning no limit
+    PidsMetricsProto {
+        current: cgroup.read_u64("pids", "pids.current").unwrap_or(0),
+        limit: cgroup.read_u64("pids", "pids.max").unwrap_or(0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cgroup_limits;
+
+    #[test]
+    fn test_parse() {
+        let hybrid =
+            "12:pids:/ctr\n4:cpu,cpuacct:/ctr\n3:memory:/ctr\n1:name=systemd:/ctr\n0::/ctr\n";
+        assert_eq!(
+            CgroupPaths::parse(hybrid, true),
+            Some(CgroupPaths::Unified("/sys/fs/cgroup/ctr".to_string()))
+        );
+        let Some(CgroupPaths::Legacy(dirs)) = CgroupPaths::parse(hybrid, false) else {
+            panic!("expected per-controller paths");
+        };
+        assert_eq!(dirs["cpu"], "/sys/fs/cgroup/cpu,cpuacct/ctr");
+        assert_eq!(dirs["cpuacct"], "/sys/fs/cgroup/cpu,cpuacct/ctr");
+        assert_eq!(dirs["memory"], "/sys/fs/cgroup/memory/ctr");
+        assert_eq!(dirs["pids"], "/sys/fs/cgroup/pids/ctr");
+        assert_eq!(dirs.len(), 4);
+        assert_eq!(CgroupPaths::parse("0::/ctr\n", false), None);
+    }
+
+    #[test]
+    fn test_oom_kill_count() {
+        let root = std::env::temp_dir().join(format!("proto-oom-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let dir = root.display().to_string();
+
+        let unified = CgroupPaths::Unified(dir.clone());
+        std::fs::write(
+            root.join("memory.events"),
+            "low 0\nhigh 0\nmax 4\noom 1\noom_kill 0\n",
+        )
+        .unwrap();
+        assert_eq!(oom_kill_count(&unified), 0);
+        std::fs::write(root.join("memory.events"), "oom 3\noom_kill 2\n").unwrap();
+        assert_eq!(oom_kill_count(&unified), 2);
+
+        let legacy = CgroupPaths::Legacy(HashMap::from([("memory".to_string(), dir)]));
+        assert_eq!(oom_kill_count(&legacy), 0);
+        std::fs::write(
+            root.join("memory.oom_control"),
+            "oom_kill_disable 0\nunder_oom 0\noom_kill 1\n",
+        )
+        .unwrap();
+        assert_eq!(oom_kill_count(&legacy), 1);
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_v1_metrics() {
+        let root = std::env::temp_dir().join(format!("proto-cgroup-v1-{}", std::process::id()));
+        let mut dirs = HashMap::new();
+        let write = |controller: &str, file: &str, content: &str| {
+            let dir = root.join(controller);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join(file), content).unwrap();
+        };
+        for controller in ["cpuacct", "cpu", "memory", "blkio", "pids"] {
+            dirs.insert(
+                controller.to_string(),
+                root.join(controller).display().to_string(),
+            );
+        }
+        write("cpuacct", "cpuacct.usage", "5000000\n");
+        write(
+            "cpu",
+            "cpu.stat",
+            "nr_periods 10\nnr_throttled 2\nthrottled_time 300\n",
+        );
+        write("memory", "memory.usage_in_bytes", "4096\n");
+        write("memory", "memory.limit_in_bytes", "9223372036854771712\n");
+        write("memory", "memory.stat", "cache 1024\nrss 2048\nswap 512\n");
+        write(
+            "blkio",
+            "blkio.throttle.io_service_bytes",
+            "8:0 Read 100\n8:0 Write 200\n8:16 Read 1\n8:0 Total 301\nTotal 301\n",
+        );
+        write(
+            "blkio",
+            "blkio.throttle.io_serviced",
+            "8:0 Read 3\n8:0 Write 4\n",
+        );
+        write("pids", "pids.current", "7\n");
+        write("pids", "pids.max", "max\n");
+        let cgroup = CgroupPaths::Legacy(dirs);
+
+        let cpu = read_cpu_metrics(&cgroup);
+        assert_eq!(cpu.usage_total, 5_000_000);
+        assert_eq!((cpu.throttled_periods, cpu.throttled_time), (2, 300));
+
+        let mem = read_memory_metrics(&cgroup);
+        assert_eq!(
+            (mem.usage, mem.cache, mem.rss, mem.swap),
+            (4096, 1024, 2048, 512)
+        );
+        assert_eq!(mem.limit, u64::MAX);
+        assert_eq!(mem.usage_percent, 0.0);
+
+        let blkio = read_blkio_metrics(&cgroup);
+        assert_eq!((blkio.read_bytes, blkio.write_bytes), (101, 200));
+        assert_eq!((blkio.read_ops, blkio.write_ops), (3, 4));
+
+        let pids = read_pids_metrics(&cgroup);
+        assert_eq!((pids.current, pids.limit), (7, 0));
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_unapplied_limits() {
+        let root = std::env::temp_dir().join(format!("proto-cgroup-limits-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("memory.max"), "67108864\n").unwrap();
+        std::fs::write(root.join("pids.max"), "max\n").unwrap();
+        std::fs::write(root.join("io.weight"), "default 4950\n8:0 200\n").unwrap();
+        let cgroup = CgroupPaths::Unified(root.display().to_string());
+        let limits = cgroup_limits(true, 4096, Some(64 << 20), Some(32), Some(0.5), Some(500));
+        assert_eq!(
+            unapplied_limits(&cgroup, &limits),
+            vec![
+                "pids.max is max, expected 32".to_string(),
+                "cpu.max is missing, expected 50000 100000".to_string(),
+            ]
+        );
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
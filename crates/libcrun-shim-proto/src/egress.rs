@@ -0,0 +1,101 @@
+//! Egress firewall of a container
+//!
+//! Both the agent and the library runtime install a container's egress
+//! allow/deny lists as an nftables ruleset through an OCI hook. The hook is a
+//! `createContainer` one, which runs in the container's namespaces before its
+//! process starts, so `nft` loads the rules into the right network namespace
+//! without entering it by PID.
+
+use serde::Serialize;
+
+/// An OCI hook, serialized as it appears in `config.json`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OciHook {
+    pub path: String,
+    pub args: Vec<String>,
+    pub env: Vec<String>,
+}
+
+/// nftables ruleset for a container's egress allow/deny lists
+///
+/// Deny entries win; a non-empty allow list drops everything else. Loopback
+/// and replies on established flows stay open.
+pub fn ruleset(allow: &[String], deny: &[String]) -> String {
+    fn family_sets(cidrs: &[String]) -> (Vec<&str>, Vec<&str>) {
+        cidrs
+            .iter()
+            .map(String::as_str)
+            .partition(|c| !c.contains(':'))
+    }
+
+    let policy = if allow.is_empty() { "accept" } else { "drop" };
+    let mut rules = vec![
+        "oif \"lo\" accept".to_string(),
+        "ct state established,related accept".to_string(),
+    ];
+    for (cidrs, verdict) in [(deny, "drop"), (allow, "accept")] {
+        let (v4, v6) = family_sets(cidrs);
+        if !v4.is_empty() {
+            rules.push(format!("ip daddr {{ {} }} {}", v4.join(", "), verdict));
+        }
+        if !v6.is_empty() {
+            rules.push(format!("ip6 daddr {{ {} }} {}", v6.join(", "), verdict));
+        }
+    }
+
+    format!(
+        "table inet libcrun_shim_egress {{\n  chain output {{\n    type filter hook output priority 0; policy {};\n    {}\n  }}\n}}\n",
+        policy,
+        rules.join("\n    ")
+    )
+}
+
+/// OCI `createContainer` hook that loads the egress ruleset; if it fails,
+/// the start fails
+pub fn hook(allow: &[String], deny: &[String]) -> OciHook {
+    OciHook {
+        path: "/bin/sh".to_string(),
+        args: vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            r#"printf '%s' "$EGRESS_RULES" | nft -f -"#.to_string(),
+        ],
+        env: vec![
+            "PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin".to_string(),
+            format!("EGRESS_RULES={}", ruleset(allow, deny)),
+        ],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ruleset() {
+        let allow = vec!["10.0.0.0/8".to_string(), "fd00::/8".to_string()];
+        let deny = vec!["10.1.0.0/16".to_string()];
+        let rules = ruleset(&allow, &deny);
+        assert!(rules.contains("policy drop;"));
+        // Deny entries come first, so they win over the allow list
+        let denied = rules.find("ip daddr { 10.1.0.0/16 } drop").unwrap();
+        let allowed = rules.find("ip daddr { 10.0.0.0/8 } accept").unwrap();
+        assert!(denied < allowed);
+        assert!(rules.contains("ip6 daddr { fd00::/8 } accept"));
+
+        let rules = ruleset(&[], &deny);
+        assert!(rules.contains("policy accept;"));
+        assert!(!rules.contains("} accept"));
+    }
+
+    #[test]
+    fn test_hook() {
+        let hook = hook(&[], &["10.1.0.0/16".to_string()]);
+        assert_eq!(hook.path, "/bin/sh");
+        assert!(!hook.args.concat().contains("nsenter"));
+        assert!(hook.env.contains(&format!(
+            "EGRESS_RULES={}",
+            ruleset(&[], &["10.1.0.0/16".to_string()])
+        )));
+    }
+}
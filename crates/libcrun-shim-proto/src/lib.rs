@@ -1,5 +1,118 @@
+use bincode::Options;
 use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
 
+pub mod archive;
+pub mod cgroup;
+pub mod egress;
+pub mod lifecycle;
+pub mod logs;
+
+pub use lifecycle::{StateMachine, Transition, TransitionError};
+
+/// Wire protocol version spoken by this crate.
+///
+/// Bumped whenever the framing or the meaning of an existing message changes.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Oldest protocol version a host still talks to.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Upper bound for a single frame, large enough for an agent binary upload.
+pub const MAX_FRAME_SIZE: usize = 64 * 1024 * 1024;
+
+/// Optional agent capabilities, negotiated with [`Request::Features`].
+///
+/// Hosts must check for a feature before sending the request it guards, so
+/// that a newer host keeps working against an older agent.
+pub mod features {
+    pub const EXEC: &str = "exec";
+    pub const HEALTH: &str = "health";
+    pub const METRICS: &str = "metrics";
+    pub const AGENT_UPGRADE: &str = "agent-upgrade";
+    /// Follow-mode log streaming, see [`super::Request::LogsStream`]
+    pub const LOG_STREAMING: &str = "log-streaming";
+    /// Guest kernel feature report, see [`super::Request::KernelFeatures`]
+    pub const KERNEL_FEATURES: &str = "kernel-features";
+    /// Exec session history, see [`super::Request::ExecSessions`]
+    pub const EXEC_AUDIT: &str = "exec-audit";
+    /// Guest filesystem usage and trim, see [`super::Request::DiskUsage`]
+    pub const DISKS: &str = "disks";
+    /// Mounting host directories into running containers, see [`super::Request::Mount`]
+    pub const HOT_MOUNT: &str = "hot-mount";
+    /// Periodic metrics samples, see [`super::Request::MetricsHistory`]
+    pub const METRICS_HISTORY: &str = "metrics-history";
+    /// Content-addressed image blob cache, see [`super::Request::MissingBlobs`]
+    pub const BLOB_CACHE: &str = "blob-cache";
+    /// Connections restricted to inspection, see [`super::Request::SetRole`]
+    pub const READ_ONLY: &str = "read-only";
+    /// Named checkpoints, see [`super::Request::CreateCheckpoint`]
+    pub const CHECKPOINTS: &str = "checkpoints";
+    /// Copying files in and out of containers, see [`super::Request::Copy`]
+    pub const COPY: &str = "copy";
+    /// Guest memory totals, see [`super::Request::MemoryInfo`]
+    pub const MEMORY_INFO: &str = "memory-info";
+    /// Interactive exec, see [`super::Request::ExecStream`]
+    pub const EXEC_STREAM: &str = "exec-stream";
+    /// Agent build information, see [`super::Request::Version`]
+    pub const VERSION: &str = "version";
+    /// Freezing and thawing containers, see [`super::Request::Pause`]
+    pub const PAUSE: &str = "pause";
+    /// Restarting exited containers, see [`super::RestartPolicy`]
+    pub const RESTART_POLICY: &str = "restart-policy";
+    /// Waiting for containers to exit, see [`super::Request::Wait`]
+    pub const WAIT: &str = "wait";
+    /// Detaching from exec sessions and attaching again, see
+    /// [`super::Request::AttachExec`]
+    pub const EXEC_DETACH: &str = "exec-detach";
+    /// Ports containers listen on, see [`super::Request::ListeningPorts`]
+    pub const LISTENING_PORTS: &str = "listening-ports";
+    /// Setting the guest clock to the host's, see [`super::Request::SyncTime`]
+    pub const TIME_SYNC: &str = "time-sync";
+    /// Per-container writable layers over the rootfs, see
+    /// [`super::CreateRequest::rootfs_snapshot`]
+    pub const ROOTFS_SNAPSHOT: &str = "rootfs-snapshot";
+    /// Read-only root filesystems, see [`super::CreateRequest::read_only_rootfs`]
+    pub const READ_ONLY_ROOTFS: &str = "read-only-rootfs";
+    /// Copying checkpoints to and from the host, see
+    /// [`super::Request::CheckpointFiles`]
+    pub const CHECKPOINT_TRANSFER: &str = "checkpoint-transfer";
+    /// Container labels, see [`super::CreateRequest::labels`]
+    pub const LABELS: &str = "labels";
+    /// Removing stopped containers by age and label, see [`super::Request::Prune`]
+    pub const PRUNE: &str = "prune";
+    /// Name lookups with the VM's resolver, see [`super::Request::Resolve`]
+    pub const RESOLVE: &str = "resolve";
+    /// Agent and kernel logs for support bundles, see [`super::Request::Diagnostics`]
+    pub const DIAGNOSTICS: &str = "diagnostics";
+    /// Container events raised in the guest, see [`super::Request::SubscribeEvents`]
+    pub const EVENTS: &str = "events";
+    /// Progress of long requests, see [`super::Request::WithProgress`]
+    pub const PROGRESS: &str = "progress";
+    /// Listing the containers that match a filter, see
+    /// [`super::Request::ListFiltered`]
+    pub const LIST_FILTER: &str = "list-filter";
+    /// Finding and removing what failed creates left behind, see
+    /// [`super::Request::StaleResources`]
+    pub const STALE_RESOURCES: &str = "stale-resources";
+    /// Container names apart from IDs, see [`super::Request::Rename`]
+    pub const RENAME: &str = "rename";
+    /// How long stopped containers get before they are killed, see
+    /// [`super::Request::StopWithTimeout`] and [`super::CreateRequest::stop_timeout`]
+    pub const STOP_TIMEOUT: &str = "stop-timeout";
+    /// Propagation and recursive read-only volume mounts, see
+    /// [`super::VolumeMountProto`]
+    pub const MOUNT_OPTIONS: &str = "mount-options";
+    /// Taking changes again after a drain, see [`super::Request::Undrain`]
+    pub const UNDRAIN: &str = "undrain";
+
+    /// Features of protocol 1 agents that predate feature negotiation
+    pub const BASELINE: &[&str] = &[EXEC, HEALTH, METRICS, AGENT_UPGRADE];
+}
+
+/// Requests sent from the host to the agent.
+///
+/// Variants are encoded by index, so new ones must be appended at the end.
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Request {
     Create(CreateRequest),
@@ -17,6 +130,343 @@ pub enum Request {
     Health(String),
     /// Execute a command in a container
     Exec(ExecRequest),
+    /// Exchange version information with the agent
+    Handshake,
+    /// Stage a new agent binary in the guest
+    UploadAgent(UploadAgentRequest),
+    /// Stop accepting mutating requests and wait for in-flight ones to finish
+    Drain,
+    /// Replace the running agent with a previously staged binary
+    UpgradeAgent(UpgradeAgentRequest),
+    /// List the optional features the agent supports
+    Features,
+    /// Report which kernel features the guest provides
+    KernelFeatures,
+    /// List exec sessions recorded for a container
+    ExecSessions(String),
+    /// Report usage of the guest's filesystems
+    DiskUsage,
+    /// Discard unused blocks on the guest's disk-backed filesystems
+    Trim,
+    /// Bind mount a directory the host shares with the VM into a running container
+    Mount(MountRequest),
+    /// Metrics samples the agent recorded at or after a Unix timestamp
+    MetricsHistory(u64),
+    /// Which of the given blob digests the agent's cache lacks
+    MissingBlobs(Vec<String>),
+    /// Write part of a blob to the agent's cache
+    PutBlob(BlobChunk),
+    /// Restrict what the rest of this connection may do
+    ///
+    /// A connection can only give up permissions; once read-only it stays so.
+    SetRole(Role),
+    /// Checkpoint a running container under a name
+    CreateCheckpoint(CheckpointRequest),
+    /// List the checkpoints of a container, or of all containers
+    ListCheckpoints(Option<String>),
+    /// Delete a checkpoint
+    RemoveCheckpoint(CheckpointRef),
+    /// Restore a checkpoint as a new, running container
+    RestoreCheckpoint(RestoreRequest),
+    /// Copy files in or out of a container's rootfs as a tar archive
+    ///
+    /// The connection is dedicated to the copy: once the agent answers with
+    /// [`Response::CopyStarted`], it carries [`CopyFrame`]s as the
+    /// [`CopyDirection`] describes, and the agent closes it when done.
+    Copy(CopyRequest),
+    /// Report the guest's total and available memory
+    MemoryInfo,
+    /// Start a command in a container and stream its I/O as [`ExecFrame`]s
+    ExecStream(ExecStreamRequest),
+    /// Send a container's logs, then new output as it is written
+    ///
+    /// The connection is dedicated to the logs: the agent answers with a
+    /// [`Response::Logs`] for the request, then one for each batch of new
+    /// output, and an empty one at least every second while the container is
+    /// quiet. Once the container has stopped and its output was sent, the
+    /// agent closes the connection.
+    LogsStream(LogsRequest),
+    /// Report how the agent was built
+    Version,
+    /// Freeze all processes of a running container
+    Pause(String),
+    /// Thaw a paused container
+    Unpause(String),
+    /// Answer with [`Response::Exited`] once the container has stopped
+    ///
+    /// The agent holds the request until then, so it is sent on a connection
+    /// of its own.
+    Wait(String),
+    /// Take over the I/O of a detached exec session, by the ID it was given
+    /// in [`ExecFrame::Session`]
+    ///
+    /// Answered with [`Response::ExecStarted`], after which the connection
+    /// carries the session's frames as for [`Request::ExecStream`], starting
+    /// with the output it wrote while detached.
+    AttachExec(String),
+    /// TCP ports a running container listens on, in its network namespace
+    ListeningPorts(String),
+    /// Bring the guest clock in line with the host's, e.g. after the VM was
+    /// paused while the host slept
+    SyncTime(TimeSyncRequest),
+    /// List the files of a checkpoint, to copy it to the host
+    CheckpointFiles(CheckpointRef),
+    /// Read a file of a checkpoint, [`BLOB_CHUNK_SIZE`] bytes at a time
+    ReadCheckpointFile(CheckpointFileRequest),
+    /// Write part of a file of a checkpoint copied from the host
+    WriteCheckpointFile(CheckpointFileChunk),
+    /// Complete a checkpoint copied with [`Request::WriteCheckpointFile`]
+    ///
+    /// The agent checks the image against the digest of the given metadata,
+    /// and removes the copy if it does not match.
+    ImportCheckpoint(CheckpointProto),
+    /// Delete the stopped containers the filter matches
+    Prune(PruneFilterProto),
+    /// Look up a host name the way containers in the VM would
+    Resolve(String),
+    /// The agent's recent log, the guest kernel log and the agent's state
+    /// files, for a support bundle
+    Diagnostics,
+    /// Send the container events the agent raises on its own, e.g. exits,
+    /// OOM kills, restarts and health changes, as they happen
+    ///
+    /// The connection is dedicated to the events: the agent answers with an
+    /// empty [`Response::Events`] once subscribed, then one for each batch of
+    /// events, and an empty one at least every [`EVENT_HEARTBEAT_SECS`]
+    /// seconds while there are none. Events raised while no one is subscribed
+    /// are not kept.
+    SubscribeEvents,
+    /// Run the request, sending a [`Response::Progress`] with the given
+    /// request ID for each phase it goes through, before its response
+    WithProgress(u64, ProgressRequest),
+    /// List the containers the filter matches, answered like [`Request::List`]
+    ListFiltered(ListFilterProto),
+    /// List what containers left behind that no container is tracked for,
+    /// e.g. after the agent crashed in the middle of a create
+    StaleResources,
+    /// Remove the resources [`Request::StaleResources`] lists, answered with
+    /// those that were removed
+    RemoveStaleResources,
+    /// Give a container a new name
+    Rename(RenameRequest),
+    /// Stop a container with a grace period of its own, instead of the one
+    /// it was created with; answered with [`Response::Stopped`]
+    StopWithTimeout(StopRequest),
+    /// Accept changes again after a [`Request::Drain`], e.g. when the
+    /// upgrade it was for failed; answered with [`Response::Undrained`]
+    Undrain,
+}
+
+/// Longest the agent goes without sending on an events connection
+pub const EVENT_HEARTBEAT_SECS: u64 = 5;
+
+/// How long a stopped container's process has to exit after SIGTERM before
+/// it gets SIGKILL, unless [`CreateRequest::stop_timeout`] says otherwise
+pub const DEFAULT_STOP_TIMEOUT_SECS: u64 = 2;
+
+/// What a connection to the agent may do
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum Role {
+    /// Any request
+    #[default]
+    Admin,
+    /// Only list, inspect, logs, metrics and other requests that change
+    /// nothing and read no files out of containers
+    ReadOnly,
+}
+
+impl Role {
+    /// Whether a connection with this role may send the request
+    pub fn permits(self, request: &Request) -> bool {
+        match self {
+            Role::Admin => true,
+            Role::ReadOnly => match request {
+                Request::List
+                | Request::Metrics(_)
+                | Request::AllMetrics
+                | Request::Logs(_)
+                | Request::Health(_)
+                | Request::Handshake
+                | Request::Features
+                | Request::KernelFeatures
+                | Request::ExecSessions(_)
+                | Request::DiskUsage
+                | Request::MetricsHistory(_)
+                | Request::MissingBlobs(_)
+                | Request::SetRole(_)
+                | Request::ListCheckpoints(_)
+                | Request::MemoryInfo
+                | Request::LogsStream(_)
+                | Request::Version
+                | Request::Wait(_)
+                | Request::ListeningPorts(_)
+                | Request::Resolve(_)
+                | Request::SubscribeEvents
+                | Request::ListFiltered(_)
+                | Request::StaleResources => true,
+                Request::Create(_)
+                | Request::Start(_)
+                | Request::Stop(_)
+                | Request::Delete(_)
+                | Request::Exec(_)
+                | Request::UploadAgent(_)
+                | Request::Drain
+                | Request::Undrain
+                | Request::UpgradeAgent(_)
+                | Request::Trim
+                | Request::Mount(_)
+                | Request::PutBlob(_)
+                | Request::CreateCheckpoint(_)
+                | Request::RemoveCheckpoint(_)
+                | Request::RestoreCheckpoint(_)
+                | Request::ExecStream(_)
+                | Request::Pause(_)
+                | Request::Unpause(_)
+                | Request::AttachExec(_)
+                | Request::SyncTime(_)
+                | Request::WriteCheckpointFile(_)
+                | Request::ImportCheckpoint(_)
+                | Request::Prune(_)
+                | Request::WithProgress(..)
+                | Request::RemoveStaleResources
+                | Request::Rename(_)
+                | Request::StopWithTimeout(_)
+                | Request::Copy(_)
+                | Request::Diagnostics
+                | Request::CheckpointFiles(_)
+                | Request::ReadCheckpointFile(_) => false,
+            },
+        }
+    }
+}
+
+/// Where the agent should read a new binary from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum AgentBinarySource {
+    /// Binary contents sent inline over the RPC channel
+    Inline(Vec<u8>),
+    /// Path inside the guest, e.g. on a virtiofs share
+    Path(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadAgentRequest {
+    pub source: AgentBinarySource,
+    /// Expected SHA-256 of the binary, hex encoded
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpgradeAgentRequest {
+    /// SHA-256 of the staged binary to exec, must match the uploaded one
+    pub sha256: String,
+}
+
+/// Virtiofs tag of the device carrying directories shared at runtime
+///
+/// The host adds each directory to the device under a share name, and the
+/// guest sees it at `<mount point of the device>/<share name>`.
+pub const SHARES_TAG: &str = "libcrun-shares";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MountRequest {
+    /// Container to mount into
+    pub id: String,
+    /// Name of the directory on the [`SHARES_TAG`] device
+    pub share: String,
+    /// Absolute path inside the container
+    pub destination: String,
+    pub read_only: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckpointRequest {
+    /// Container to checkpoint
+    pub id: String,
+    /// Checkpoint name, unique per container
+    pub name: String,
+    /// Keep the container running instead of stopping it
+    pub leave_running: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckpointRef {
+    /// Container the checkpoint was taken of
+    pub container: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RestoreRequest {
+    pub checkpoint: CheckpointRef,
+    /// ID of the restored container, which must not exist yet
+    pub id: String,
+}
+
+/// A named checkpoint of a container
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct CheckpointProto {
+    pub container: String,
+    pub name: String,
+    /// Unix seconds
+    pub created_at: u64,
+    /// Bytes on disk of the image and the container's configuration
+    pub size_bytes: u64,
+    /// `sha256:<hex>` over the checkpoint image, checked before a restore
+    pub image_digest: String,
+}
+
+/// A file of a checkpoint, by its path relative to the checkpoint's
+/// directory: `config.json` or `images/<file>`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CheckpointFileProto {
+    pub path: String,
+    pub size: u64,
+}
+
+/// A checkpoint and the files to copy to move it elsewhere
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckpointFilesProto {
+    pub checkpoint: CheckpointProto,
+    pub files: Vec<CheckpointFileProto>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckpointFileRequest {
+    pub checkpoint: CheckpointRef,
+    /// Path as listed in [`CheckpointFileProto::path`]
+    pub path: String,
+    pub offset: u64,
+}
+
+/// Part of a checkpoint file, sent in order from offset 0
+///
+/// As with [`BlobChunk`], a chunk replaces everything from its offset on.
+/// Files can only be written while the checkpoint is not complete.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckpointFileChunk {
+    pub checkpoint: CheckpointRef,
+    /// Path as listed in [`CheckpointFileProto::path`]
+    pub path: String,
+    pub offset: u64,
+    pub data: Vec<u8>,
+}
+
+/// Largest [`BlobChunk::data`] a host sends, well under [`MAX_FRAME_SIZE`]
+pub const BLOB_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Part of a blob, sent in order from offset 0
+///
+/// A chunk at an offset the agent already has replaces everything from that
+/// offset on, so a retried chunk is harmless. The agent checks the digest
+/// once the last chunk arrives and only then adds the blob to its cache.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlobChunk {
+    /// Content digest, e.g. `sha256:<hex>`
+    pub digest: String,
+    pub offset: u64,
+    pub data: Vec<u8>,
+    pub last: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -25,6 +475,18 @@ pub struct LogsRequest {
     pub tail: u32,
     pub since: u64,
     pub timestamps: bool,
+    /// Cap on bytes returned per stream (0 = agent maximum)
+    #[serde(default)]
+    pub max_bytes: u64,
+    /// Read stdout forward from this byte offset instead of tailing
+    #[serde(default)]
+    pub stdout_offset: Option<u64>,
+    /// Read stderr forward from this byte offset instead of tailing
+    #[serde(default)]
+    pub stderr_offset: Option<u64>,
+    /// Only return output logged before this timestamp (0 = no limit)
+    #[serde(default)]
+    pub until: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -33,6 +495,129 @@ pub struct ExecRequest {
     pub command: Vec<String>,
     pub env: Vec<String>,
     pub working_dir: Option<String>,
+    /// Who asked for the exec, recorded in the container's exec history
+    #[serde(default)]
+    pub user: String,
+    #[serde(default)]
+    pub tty: bool,
+    /// User and group IDs to run as inside the container (root when unset)
+    #[serde(default)]
+    pub uid: Option<u32>,
+    #[serde(default)]
+    pub gid: Option<u32>,
+}
+
+/// Which way a [`Request::Copy`] goes
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum CopyDirection {
+    /// From the host into the container: the host sends the archive as
+    /// [`CopyFrame::Data`] and [`CopyFrame::End`], and the agent answers with
+    /// [`CopyFrame::Done`] once it is unpacked
+    In,
+    /// From the container to the host: the agent sends [`CopyFrame::Total`],
+    /// the archive as [`CopyFrame::Data`] and [`CopyFrame::End`], then
+    /// [`CopyFrame::Done`]
+    Out,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CopyRequest {
+    pub id: String,
+    /// Path in the container: what to copy out, or where to copy to
+    pub path: String,
+    pub direction: CopyDirection,
+    pub options: CopyOptionsProto,
+}
+
+/// How a copy packs and unpacks files
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub struct CopyOptionsProto {
+    /// Glob patterns of what is left out, see [`archive::Excludes`]
+    pub exclude: Vec<String>,
+    /// Copy what symbolic links point to instead of the links
+    pub follow_links: bool,
+    /// Keep the owners of the files, instead of giving them to whoever
+    /// unpacks them
+    pub preserve_owner: bool,
+}
+
+/// Files, directories and links in a copy, and the bytes of file content
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub struct CopyStatsProto {
+    pub entries: u64,
+    pub bytes: u64,
+}
+
+/// Frames of a copy
+///
+/// Once the agent answers [`Request::Copy`] with [`Response::CopyStarted`],
+/// the connection carries `CopyFrame`s. Either end may send
+/// [`CopyFrame::Failed`] instead of the rest of the copy.
+///
+/// Variants are encoded by index, so new ones must be appended at the end.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CopyFrame {
+    /// Up to [`COPY_CHUNK_SIZE`] bytes of the tar archive
+    Data(Vec<u8>),
+    /// The archive is complete
+    End,
+    /// What the archive is going to hold, sent before it (agent to host)
+    Total(CopyStatsProto),
+    /// What was unpacked or packed, sent last (agent to host)
+    Done(CopyStatsProto),
+    /// The copy could not be completed
+    Failed(String),
+}
+
+/// Most bytes of archive in a [`CopyFrame::Data`]
+pub const COPY_CHUNK_SIZE: usize = 256 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecStreamRequest {
+    pub exec: ExecRequest,
+    /// Rows and columns of the host's terminal, for `exec.tty`
+    pub terminal_size: Option<(u16, u16)>,
+    /// Keep the command running when the host detaches or goes away, so
+    /// that it can be attached again with [`Request::AttachExec`]
+    #[serde(default)]
+    pub detachable: bool,
+    /// End the command's input with the host's, by closing it or under a
+    /// terminal by sending end-of-file, and when the host detaches; later
+    /// hosts attached to the session get no input
+    #[serde(default)]
+    pub stdin_once: bool,
+}
+
+/// Frames of an interactive exec session
+///
+/// Once the agent answers [`Request::ExecStream`] with
+/// [`Response::ExecStarted`], the connection carries `ExecFrame`s in both
+/// directions. The agent sends [`ExecFrame::Exit`] or [`ExecFrame::Failed`]
+/// last and closes the connection, or closes it early once the host sends
+/// [`ExecFrame::Detach`] to a detachable session.
+///
+/// Variants are encoded by index, so new ones must be appended at the end.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ExecFrame {
+    /// Input for the command (host to agent)
+    Stdin(Vec<u8>),
+    /// No more input (host to agent); a terminal's input only ends with
+    /// [`ExecStreamRequest::stdin_once`]
+    CloseStdin,
+    /// The host's terminal was resized to rows and columns (host to agent)
+    Resize(u16, u16),
+    /// Output of the command, all of it under a terminal (agent to host)
+    Stdout(Vec<u8>),
+    /// Error output of the command (agent to host)
+    Stderr(Vec<u8>),
+    /// The command exited with this code (agent to host)
+    Exit(i32),
+    /// The command could not be run or waited for (agent to host)
+    Failed(String),
+    /// Leave the command running and close the connection (host to agent)
+    Detach,
+    /// ID to attach to a detachable session again, sent first (agent to host)
+    Session(String),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -52,6 +637,193 @@ pub struct CreateRequest {
     // Health check configuration
     #[serde(default)]
     pub health_check: Option<HealthCheckProto>,
+
+    // Capabilities, seccomp and rlimits, with profile defaults already applied
+    #[serde(default)]
+    pub security: SecurityProto,
+
+    // Image reference the rootfs was built from, checked by admission policy
+    #[serde(default)]
+    pub image: Option<String>,
+
+    // Kill the container after it has run this long
+    #[serde(default)]
+    pub max_runtime_secs: Option<u64>,
+
+    // Stop the container once it has been idle this long
+    #[serde(default)]
+    pub auto_stop: Option<AutoStopProto>,
+
+    // Namespaces joined from another container (pods)
+    #[serde(default)]
+    pub join_namespaces: Option<JoinNamespacesProto>,
+
+    // Started again by the agent when its process exits
+    #[serde(default)]
+    pub restart_policy: RestartPolicy,
+
+    // Write to an overlay (or copy) of the rootfs of the container's own
+    #[serde(default)]
+    pub rootfs_snapshot: bool,
+
+    // Mount the rootfs read-only
+    #[serde(default)]
+    pub read_only_rootfs: bool,
+
+    // Key-value metadata, matched by prune and list filters
+    #[serde(default)]
+    pub labels: std::collections::BTreeMap<String, String>,
+
+    // Name, unique among the agent's containers; the ID if empty
+    #[serde(default)]
+    pub name: String,
+
+    // Seconds between SIGTERM and SIGKILL when the container is stopped
+    #[serde(default)]
+    pub stop_timeout: Option<u64>,
+
+    // Create `working_dir` in the root filesystem if it is missing
+    #[serde(default)]
+    pub create_working_dir: bool,
+}
+
+/// New name for a container, see [`Request::Rename`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RenameRequest {
+    pub id: String,
+    pub name: String,
+}
+
+/// Container to stop and how long its process has to exit, see
+/// [`Request::StopWithTimeout`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StopRequest {
+    pub id: String,
+    pub timeout_secs: u64,
+}
+
+/// Which stopped containers [`Request::Prune`] deletes; all of them when no
+/// condition is set
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct PruneFilterProto {
+    /// Only containers created before this Unix time, in seconds (0 = any)
+    pub until: u64,
+    /// Conditions on the containers' labels, all of which must hold
+    pub labels: Vec<LabelFilterProto>,
+}
+
+/// A condition on labels: `key` is set, to `value` if given, or with
+/// `negate` is not
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LabelFilterProto {
+    pub key: String,
+    pub value: Option<String>,
+    pub negate: bool,
+}
+
+/// Which containers [`Request::ListFiltered`] lists; all of them when no
+/// condition is set
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct ListFilterProto {
+    /// Conditions on the containers' labels, all of which must hold
+    pub labels: Vec<LabelFilterProto>,
+    /// Only containers in one of these states (empty = any)
+    pub statuses: Vec<ContainerStatus>,
+    /// Only containers whose name starts with this
+    pub name_prefix: String,
+}
+
+/// What a container left behind, without a container being tracked for it
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StaleResourceProto {
+    pub kind: StaleResourceKind,
+    /// ID of the container it belonged to
+    pub id: String,
+    /// Where it is, e.g. a directory, or the name of a network interface
+    pub path: String,
+}
+
+/// Kinds of resources a create makes before the container is tracked
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StaleResourceKind {
+    /// libcrun's state directory for the container
+    State,
+    /// The container's cgroup
+    Cgroup,
+    /// The writable layer of a container created with a rootfs snapshot
+    Snapshot,
+    /// The host end of the veth pair of a container on the bridge
+    Interface,
+}
+
+impl StaleResourceKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StaleResourceKind::State => "state",
+            StaleResourceKind::Cgroup => "cgroup",
+            StaleResourceKind::Snapshot => "snapshot",
+            StaleResourceKind::Interface => "interface",
+        }
+    }
+}
+
+impl std::fmt::Display for StaleResourceKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What the agent reports for a support bundle, unredacted
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct DiagnosticsProto {
+    /// Lines the agent logged most recently, oldest first
+    pub agent_log: Vec<String>,
+    /// The guest kernel's message buffer, as `dmesg` prints it
+    pub kernel_log: String,
+    pub state_files: Vec<StateFileProto>,
+}
+
+/// A file the agent keeps its state in
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StateFileProto {
+    /// Path in the guest
+    pub path: String,
+    pub contents: Vec<u8>,
+}
+
+/// Namespaces to join from another container, for proto
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JoinNamespacesProto {
+    pub container: String,
+    pub network: bool,
+    pub ipc: bool,
+}
+
+/// Idle-based auto-stop policy for proto
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AutoStopProto {
+    pub idle_secs: u64,
+    /// CPU usage threshold, in percent of one core
+    pub cpu_percent: f64,
+}
+
+/// Process security settings for proto
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct SecurityProto {
+    /// `None` keeps the agent's default capability set
+    pub capabilities: Option<Vec<String>>,
+    /// OCI seccomp profile as JSON
+    pub seccomp: Option<String>,
+    pub rlimits: Vec<RlimitProto>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RlimitProto {
+    /// OCI rlimit type, e.g. "RLIMIT_NOFILE"
+    pub rlimit_type: String,
+    pub soft: u64,
+    pub hard: u64,
 }
 
 /// Health check configuration for proto
@@ -82,6 +854,12 @@ pub struct NetworkConfigProto {
     pub mode: String,
     pub port_mappings: Vec<PortMappingProto>,
     pub interfaces: Vec<NetworkInterfaceProto>,
+    /// Egress allow list (CIDRs); when non-empty all other egress is dropped
+    #[serde(default)]
+    pub allow_egress: Vec<String>,
+    /// Egress deny list (CIDRs), checked before the allow list
+    #[serde(default)]
+    pub deny_egress: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -96,7 +874,7 @@ pub struct PortMappingProto {
 pub struct NetworkInterfaceProto {
     pub name: String,
     pub interface_type: String,
-    pub config: std::collections::HashMap<String, String>,
+    pub config: std::collections::BTreeMap<String, String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -104,17 +882,178 @@ pub struct VolumeMountProto {
     pub source: String,
     pub destination: String,
     pub options: Vec<String>,
+    #[serde(default)]
+    pub propagation: Option<MountPropagation>,
+    #[serde(default)]
+    pub recursive_readonly: bool,
+}
+
+/// Whether mounts and unmounts under a volume reach its source on the host,
+/// and the other way around
+///
+/// Applies to the mounts below the volume as well, hence the `r`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MountPropagation {
+    /// Neither way, the default
+    Rprivate,
+    /// Mounts on the host show up in the container, not the other way around
+    Rslave,
+    /// Both ways, e.g. for FUSE filesystems or nested containers mounted in
+    /// the container
+    Rshared,
+}
+
+impl MountPropagation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MountPropagation::Rprivate => "rprivate",
+            MountPropagation::Rslave => "rslave",
+            MountPropagation::Rshared => "rshared",
+        }
+    }
+}
+
+impl std::fmt::Display for MountPropagation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for MountPropagation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rprivate" => Ok(MountPropagation::Rprivate),
+            "rslave" => Ok(MountPropagation::Rslave),
+            "rshared" => Ok(MountPropagation::Rshared),
+            _ => Err(format!(
+                "invalid mount propagation '{}', expected rprivate, rslave or rshared",
+                s
+            )),
+        }
+    }
+}
+
+/// OCI mount options of a bind-mounted volume with `options`
+///
+/// `propagation` replaces any propagation in `options`. A recursive
+/// read-only volume is bound recursively, so it includes the mounts below
+/// its source, and gets `rro`, which makes all of them read-only rather
+/// than only the top one as `ro` does.
+pub fn volume_mount_options(
+    options: &[String],
+    propagation: Option<MountPropagation>,
+    recursive_readonly: bool,
+) -> Vec<String> {
+    let mut options = options.to_vec();
+    if let Some(propagation) = propagation {
+        options.retain(|o| o.parse::<MountPropagation>().is_err());
+        options.push(propagation.to_string());
+    }
+    if recursive_readonly {
+        options.retain(|o| !matches!(o.as_str(), "bind" | "rbind" | "ro" | "rw"));
+        options.extend(["rbind".to_string(), "rro".to_string()]);
+    }
+    options
+}
+
+/// CPU period of the CFS bandwidth limit, in microseconds
+pub const CPU_PERIOD: u64 = 100_000;
+
+/// cgroup v2 `io.weight` (1-10000) for a block I/O weight (10-1000), scaled
+/// as runc does so a container gets the same share under either runtime
+pub fn io_weight(blkio_weight: u16) -> u16 {
+    let weight = u32::from(blkio_weight.clamp(10, 1000));
+    (1 + (weight - 10) * 9999 / 990) as u16
+}
+
+/// Whether the unified cgroup v2 hierarchy is mounted
+pub fn is_cgroup_v2() -> bool {
+    std::path::Path::new("/sys/fs/cgroup/cgroup.controllers").exists()
+}
+
+/// Resolve the memory+swap limit with Docker semantics: -1 is unlimited swap,
+/// 0 or unset leaves swap to the cgroup default
+pub fn swap_limit(memory: Option<u64>, memory_swap: Option<i64>) -> Option<i64> {
+    match memory_swap {
+        Some(-1) => Some(-1),
+        Some(swap) if swap > 0 && memory.is_some_and(|m| m > 0) => Some(swap),
+        _ => None,
+    }
+}
+
+/// Files of a container's cgroup that enforce its limits, as (controller,
+/// file, value read back once the limit is applied), on the unified
+/// hierarchy if `unified`, else on cgroup v1
+///
+/// The kernel rounds memory limits down to a multiple of `page_size`.
+pub fn cgroup_limits(
+    unified: bool,
+    page_size: u64,
+    memory: Option<u64>,
+    pids: Option<i64>,
+    cpu: Option<f64>,
+    blkio_weight: Option<u16>,
+) -> Vec<(&'static str, &'static str, String)> {
+    let mut limits = Vec::new();
+    if let Some(memory) = memory.filter(|m| *m > 0) {
+        let file = if unified {
+            "memory.max"
+        } else {
+            "memory.limit_in_bytes"
+        };
+        let applied = memory - memory % page_size.max(1);
+        limits.push(("memory", file, applied.to_string()));
+    }
+    if let Some(pids) = pids.filter(|p| *p > 0) {
+        limits.push(("pids", "pids.max", pids.to_string()));
+    }
+    if let Some(cpu) = cpu.filter(|c| *c > 0.0) {
+        let quota = (cpu * CPU_PERIOD as f64) as i64;
+        if unified {
+            limits.push(("cpu", "cpu.max", format!("{} {}", quota, CPU_PERIOD)));
+        } else {
+            limits.push(("cpu", "cpu.cfs_quota_us", quota.to_string()));
+        }
+    }
+    if let Some(weight) = blkio_weight {
+        if unified {
+            limits.push(("io", "io.weight", format!("default {}", io_weight(weight))));
+        } else {
+            limits.push(("blkio", "blkio.weight", weight.to_string()));
+        }
+    }
+    limits
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct ResourceLimitsProto {
     pub cpu: Option<f64>,
     pub memory: Option<u64>,
-    pub memory_swap: Option<u64>,
+    pub memory_swap: Option<i64>,
     pub pids: Option<i64>,
     pub blkio_weight: Option<u16>,
+    #[serde(default)]
+    pub memory_swappiness: Option<u8>,
+    #[serde(default)]
+    pub cpuset_cpus: Option<String>,
+    #[serde(default)]
+    pub cpuset_mems: Option<String>,
+    #[serde(default)]
+    pub hugepage_limits: Vec<HugepageLimitProto>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HugepageLimitProto {
+    pub page_size: String,
+    pub limit: u64,
+}
+
+/// Responses sent from the agent to the host.
+///
+/// Variants are encoded by index, so new ones must be appended at the end.
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Response {
     Created(String),
@@ -132,15 +1071,319 @@ pub enum Response {
     Health(HealthStatusProto),
     /// Exec result
     Exec(ExecResultProto),
+    /// Agent version information
+    Handshake(HandshakeProto),
+    /// New agent binary staged at the given guest path
+    AgentUploaded(String),
+    /// Agent is drained and idle
+    Drained,
+    /// Agent is about to exec the staged binary
+    Upgrading,
     Error(String),
+    /// Optional features supported by the agent, see [`features`]
+    Features(Vec<String>),
+    /// Request rejected by the agent's admission policy
+    Denied(Vec<PolicyViolationProto>),
+    /// Kernel features probed by the agent at startup
+    KernelFeatures(KernelFeaturesProto),
+    /// Exec sessions for a container, oldest first
+    ExecSessions(Vec<ExecSessionProto>),
+    /// Like [`Response::Error`], with a code the host can act on
+    Failed(ErrorProto),
+    /// Usage of each guest filesystem
+    DiskUsage(Vec<FilesystemUsageProto>),
+    /// Outcome of trimming each disk-backed filesystem
+    Trimmed(Vec<TrimResultProto>),
+    /// The directory is mounted in the container
+    Mounted,
+    /// Recorded metrics samples, oldest first
+    MetricsHistory(Vec<MetricsSampleProto>),
+    /// Digests missing from the agent's blob cache, in request order
+    MissingBlobs(Vec<String>),
+    /// The chunk was written, and with the last one the blob is cached
+    BlobStored,
+    /// The connection now has the requested role
+    RoleSet(Role),
+    /// The checkpoint that was written
+    Checkpoint(CheckpointProto),
+    /// Checkpoints, oldest first
+    Checkpoints(Vec<CheckpointProto>),
+    CheckpointRemoved,
+    /// The copy has started; the connection now carries [`CopyFrame`]s
+    CopyStarted,
+    /// The guest's memory totals
+    MemoryInfo(MemoryInfoProto),
+    /// The command is running; the connection now carries [`ExecFrame`]s
+    ExecStarted,
+    /// How the agent was built
+    Version(VersionProto),
+    Paused,
+    Unpaused,
+    /// How the process of a waited-for container exited
+    Exited(ExitStatusProto),
+    /// Listening TCP ports, ascending
+    ListeningPorts(Vec<u16>),
+    /// How far the guest clock was off and how it was corrected
+    TimeSynced(TimeSyncProto),
+    /// A checkpoint and its files
+    CheckpointFiles(CheckpointFilesProto),
+    /// Part of a checkpoint file, empty past its end
+    CheckpointData(Vec<u8>),
+    CheckpointFileWritten,
+    /// IDs of the deleted containers
+    Pruned(Vec<String>),
+    /// Addresses a host name resolved to
+    Resolved(Vec<String>),
+    Diagnostics(DiagnosticsProto),
+    /// Container events, oldest first; empty as a heartbeat
+    Events(Vec<EventProto>),
+    /// A request sent with [`Request::WithProgress`] entered a phase
+    Progress(ProgressProto),
+    /// Resources no container is tracked for
+    StaleResources(Vec<StaleResourceProto>),
+    Renamed,
+    /// Agent accepts changes again
+    Undrained,
+}
+
+/// Category of an agent error, so the host can tell "not found" or "try
+/// again" apart from other failures without parsing the message
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ErrorCode {
+    Internal,
+    /// The container or other resource does not exist
+    NotFound,
+    /// The resource is in the wrong state, e.g. it already exists or is running
+    Conflict,
+    InvalidArgument,
+    /// The agent cannot serve the request right now; retrying may succeed
+    Unavailable,
+    /// The connection's role does not allow the request
+    PermissionDenied,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorProto {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+/// Kernel features the guest provides, probed once at agent startup
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct KernelFeaturesProto {
+    /// Unified cgroup v2 hierarchy is mounted
+    pub cgroup_v2: bool,
+    /// Enabled cgroup controllers, e.g. "cpu", "memory", "pids"
+    pub cgroup_controllers: Vec<String>,
+    pub overlayfs: bool,
+    pub vsock: bool,
+    pub user_namespaces: bool,
+    pub seccomp: bool,
+}
+
+/// Space on one filesystem mounted in the guest
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct FilesystemUsageProto {
+    pub mount_point: String,
+    /// Backing device, e.g. "/dev/vda", or the filesystem name for virtual ones
+    pub device: String,
+    pub fs_type: String,
+    pub total_bytes: u64,
+    pub used_bytes: u64,
+    pub available_bytes: u64,
+}
+
+/// Memory of the guest, as the kernel reports it in /proc/meminfo
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct MemoryInfoProto {
+    pub total_bytes: u64,
+    /// Memory available to new workloads without swapping (MemAvailable)
+    pub available_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimeSyncRequest {
+    /// Host wall clock when the request was sent, in nanoseconds since the
+    /// Unix epoch
+    pub host_time_ns: i64,
+    /// Offsets at least this large are stepped, smaller ones slewed
+    pub step_threshold_ms: u64,
+}
+
+/// Guest clock offset found by a time sync
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct TimeSyncProto {
+    /// Host time minus guest time; positive when the guest clock was behind
+    pub offset_ns: i64,
+    /// The clock was set to the host time rather than slewed towards it
+    pub stepped: bool,
+}
+
+/// Result of trimming one filesystem
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrimResultProto {
+    pub mount_point: String,
+    /// Bytes the filesystem discarded
+    pub trimmed_bytes: u64,
+    /// Why the trim failed, e.g. the device does not support discard
+    pub error: Option<String>,
+}
+
+/// Usage of one container at one point in time, as recorded by the agent
+///
+/// CPU, network and block I/O are cumulative counters; rates come from the
+/// difference between consecutive samples.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct MetricsSampleProto {
+    pub id: String,
+    /// Unix timestamp in seconds
+    pub timestamp: u64,
+    pub cpu_usage_ns: u64,
+    pub memory_bytes: u64,
+    pub net_rx_bytes: u64,
+    pub net_tx_bytes: u64,
+    pub blkio_read_bytes: u64,
+    pub blkio_write_bytes: u64,
+    pub pids: u64,
+}
+
+/// A single admission policy rule that a request violated
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PolicyViolationProto {
+    pub rule: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HandshakeProto {
+    pub agent_version: String,
+    pub protocol_version: u32,
+}
+
+/// Build information of an agent
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct VersionProto {
+    /// Agent semantic version
+    pub version: String,
+    /// Commit the agent was built from, if built from a git checkout
+    pub git_sha: Option<String>,
+    /// Unix seconds
+    pub build_timestamp: u64,
+    /// Version of the libcrun linked into the agent, if any
+    pub libcrun_version: Option<String>,
+    /// Oldest and newest wire protocol versions the agent speaks
+    pub min_protocol_version: u32,
+    pub max_protocol_version: u32,
+}
+
+/// How a container's process exited; unknown when the agent could not
+/// reap it, e.g. after the agent was restarted
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ExitStatusProto {
+    pub exit_code: Option<i32>,
+    pub exit_reason: Option<ExitReason>,
+}
+
+/// The requests that report their progress
+///
+/// Not any [`Request`], so that requests can't nest.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ProgressRequest {
+    Create(Box<CreateRequest>),
+    CreateCheckpoint(CheckpointRequest),
+    RestoreCheckpoint(RestoreRequest),
+    ImportCheckpoint(CheckpointProto),
+}
+
+impl From<ProgressRequest> for Request {
+    fn from(request: ProgressRequest) -> Self {
+        match request {
+            ProgressRequest::Create(req) => Request::Create(*req),
+            ProgressRequest::CreateCheckpoint(req) => Request::CreateCheckpoint(req),
+            ProgressRequest::RestoreCheckpoint(req) => Request::RestoreCheckpoint(req),
+            ProgressRequest::ImportCheckpoint(checkpoint) => Request::ImportCheckpoint(checkpoint),
+        }
+    }
+}
+
+/// A phase of a request sent with [`Request::WithProgress`]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ProgressProto {
+    pub request_id: u64,
+    /// Short name of the phase, e.g. `snapshot`
+    pub phase: String,
+    /// How far through the phase the request is, when that is known
+    pub percent: Option<u8>,
+    /// What the agent is doing, for people
+    pub message: String,
+}
+
+/// What happened to a container in an [`EventProto`]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum EventKind {
+    /// Started again by its restart policy
+    Start,
+    /// Stopped by the agent, e.g. after being idle
+    Stop,
+    /// Killed by the agent, e.g. for exceeding its max runtime
+    Kill,
+    /// Its process exited
+    Die,
+    /// A process of the container was killed by the OOM killer; followed by
+    /// [`EventKind::Die`] when it was the container's main process. The
+    /// `oom_kills` attribute has the kills of the run so far.
+    Oom,
+    HealthOk,
+    HealthFail,
+}
+
+/// A container event raised by the agent
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventProto {
+    pub kind: EventKind,
+    pub container_id: String,
+    /// Unix seconds, by the guest clock
+    pub timestamp: u64,
+    /// Exit code, for [`EventKind::Die`]
+    pub exit_code: Option<i32>,
+    /// Signal sent, for [`EventKind::Kill`] and [`EventKind::Stop`]
+    pub signal: Option<i32>,
+    /// More about the event, e.g. the `reason` for a stop
+    #[serde(default)]
+    pub attributes: std::collections::BTreeMap<String, String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct LogsProto {
     pub id: String,
+    /// Output of raw stream files; empty when `entries` holds the output
     pub stdout: String,
     pub stderr: String,
     pub timestamp: u64,
+    /// Offset to continue reading stdout from
+    #[serde(default)]
+    pub stdout_offset: u64,
+    /// Offset to continue reading stderr from
+    #[serde(default)]
+    pub stderr_offset: u64,
+    /// Output was cut to stay within the byte limit
+    #[serde(default)]
+    pub truncated: bool,
+    /// Both streams as individual lines in capture order (structured logs
+    /// only), sent instead of `stdout` and `stderr`
+    #[serde(default)]
+    pub entries: Vec<LogEntryProto>,
+}
+
+/// One captured line of container output
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogEntryProto {
+    /// "stdout" or "stderr"
+    pub stream: String,
+    pub timestamp_ns: u64,
+    /// Position across both streams
+    pub seq: u64,
+    pub payload: Vec<u8>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -159,11 +1402,228 @@ pub struct ExecResultProto {
     pub stderr: String,
 }
 
+/// A finished exec session
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ExecSessionProto {
+    pub command: Vec<String>,
+    pub user: String,
+    /// Unix seconds
+    pub started_at: u64,
+    pub duration_ms: u64,
+    /// -1 if the command could not be run or was killed by a signal
+    pub exit_code: i32,
+}
+
+/// Lifecycle state of a container, shared by the agent, the protocol and the runtime
+///
+/// Encoded as its name, which keeps the wire format of the strings older
+/// agents and hosts exchange. The lowercase names and "orphaned" that older
+/// agents wrote to their state files are read as the matching state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(into = "String", from = "String")]
+pub enum ContainerStatus {
+    Created,
+    Running,
+    Stopped,
+    /// Running, with all its processes frozen
+    Paused,
+}
+
+impl ContainerStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContainerStatus::Created => "Created",
+            ContainerStatus::Running => "Running",
+            ContainerStatus::Stopped => "Stopped",
+            ContainerStatus::Paused => "Paused",
+        }
+    }
+}
+
+impl std::fmt::Display for ContainerStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<ContainerStatus> for String {
+    fn from(status: ContainerStatus) -> Self {
+        status.as_str().to_string()
+    }
+}
+
+impl From<String> for ContainerStatus {
+    fn from(status: String) -> Self {
+        match status.as_str() {
+            "Created" | "created" => ContainerStatus::Created,
+            "Running" | "running" => ContainerStatus::Running,
+            "Paused" | "paused" => ContainerStatus::Paused,
+            // Anything else has no process to talk to, e.g. "orphaned"
+            _ => ContainerStatus::Stopped,
+        }
+    }
+}
+
+/// How a container's last run ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExitReason {
+    /// Exited with status 0
+    Completed,
+    /// Exited with a non-zero status
+    Error,
+    /// Killed by a signal
+    Signal,
+    /// Killed by the kernel for going over its memory limit
+    Oom,
+}
+
+impl ExitReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExitReason::Completed => "completed",
+            ExitReason::Error => "error",
+            ExitReason::Signal => "signal",
+            ExitReason::Oom => "oom",
+        }
+    }
+}
+
+impl std::fmt::Display for ExitReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Exit code and reason for a wait status
+///
+/// Killed processes get 128 + the signal, as shells report them. A SIGKILL
+/// counts as an OOM kill when the memory controller killed something in the
+/// container's cgroup.
+pub fn exit_outcome(status: libc::c_int, oom_killed: bool) -> (i32, ExitReason) {
+    if libc::WIFSIGNALED(status) {
+        let signal = libc::WTERMSIG(status);
+        let reason = if signal == libc::SIGKILL && oom_killed {
+            ExitReason::Oom
+        } else {
+            ExitReason::Signal
+        };
+        (128 + signal, reason)
+    } else {
+        match libc::WEXITSTATUS(status) {
+            0 => (0, ExitReason::Completed),
+            code => (code, ExitReason::Error),
+        }
+    }
+}
+
+/// When the agent starts a container again after its process exits
+///
+/// Only exits the agent notices are restarted; a container stopped with
+/// `Stop`, or by the agent for its max runtime or idleness, stays stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// Leave it stopped
+    #[default]
+    No,
+    /// Restart it after every exit, and when the agent starts again even if
+    /// it had been stopped
+    Always,
+    /// Restart it after a non-zero exit, up to `max_retries` times in a row
+    /// (0 = no limit)
+    OnFailure { max_retries: u32 },
+    /// Restart it after every exit, and when the agent starts again unless
+    /// it had been stopped
+    UnlessStopped,
+}
+
+impl RestartPolicy {
+    /// Whether a container that exited with `exit_code` (None = unknown)
+    /// after `retries` restarts in a row is started again
+    pub fn restarts(&self, exit_code: Option<i32>, retries: u32) -> bool {
+        match *self {
+            RestartPolicy::No => false,
+            RestartPolicy::Always | RestartPolicy::UnlessStopped => true,
+            RestartPolicy::OnFailure { max_retries } => {
+                exit_code != Some(0) && (max_retries == 0 || retries < max_retries)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for RestartPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RestartPolicy::No => f.write_str("no"),
+            RestartPolicy::Always => f.write_str("always"),
+            RestartPolicy::OnFailure { max_retries: 0 } => f.write_str("on-failure"),
+            RestartPolicy::OnFailure { max_retries } => write!(f, "on-failure:{}", max_retries),
+            RestartPolicy::UnlessStopped => f.write_str("unless-stopped"),
+        }
+    }
+}
+
+impl std::str::FromStr for RestartPolicy {
+    type Err = String;
+
+    /// Parse `no`, `always`, `unless-stopped` or `on-failure[:max-retries]`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None => match s {
+                "no" => Ok(RestartPolicy::No),
+                "always" => Ok(RestartPolicy::Always),
+                "on-failure" => Ok(RestartPolicy::OnFailure { max_retries: 0 }),
+                "unless-stopped" => Ok(RestartPolicy::UnlessStopped),
+                _ => Err(format!(
+                    "invalid restart policy '{}', expected no, always, on-failure[:N] or unless-stopped",
+                    s
+                )),
+            },
+            Some(("on-failure", retries)) => retries
+                .parse()
+                .map(|max_retries| RestartPolicy::OnFailure { max_retries })
+                .map_err(|_| format!("invalid maximum retry count '{}'", retries)),
+            Some(_) => Err(format!(
+                "invalid restart policy '{}', only on-failure takes a retry count",
+                s
+            )),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ContainerInfoProto {
     pub id: String,
-    pub status: String,
+    pub status: ContainerStatus,
     pub pid: Option<u32>,
+    /// Set when the agent stopped the container itself, e.g. "timeout"
+    #[serde(default)]
+    pub exit_reason: Option<String>,
+    /// Times the container was started again after it exited
+    #[serde(default)]
+    pub restart_count: u32,
+    /// Exit code of the last run, 128 + signal when it was killed
+    #[serde(default)]
+    pub last_exit_code: Option<i32>,
+    #[serde(default)]
+    pub last_exit_reason: Option<ExitReason>,
+    #[serde(default)]
+    pub restart_policy: RestartPolicy,
+    /// Restarts in a row counted against the `on-failure` retry limit; reset
+    /// once a run lasts long enough
+    #[serde(default)]
+    pub restart_retries: u32,
+    /// Address on the agent's bridge network
+    #[serde(default)]
+    pub ip_address: Option<String>,
+    /// Processes of the current or last run killed by the OOM killer
+    #[serde(default)]
+    pub oom_kills: u32,
+    #[serde(default)]
+    pub labels: std::collections::BTreeMap<String, String>,
+    #[serde(default)]
+    pub name: String,
 }
 
 /// Container metrics for RPC
@@ -226,18 +1686,426 @@ pub struct PidsMetricsProto {
     pub limit: u64,
 }
 
-pub fn serialize_request(req: &Request) -> Vec<u8> {
-    bincode::serialize(req).unwrap()
+/// Error encoding or decoding a message
+pub type CodecError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Bincode settings of the wire format
+///
+/// Same encoding as `bincode::serialize`, with messages capped at
+/// [`MAX_FRAME_SIZE`] so an oversized message fails to encode instead of
+/// producing a frame the peer rejects, and a corrupt length can't make the
+/// decoder allocate more than a frame could hold.
+fn codec() -> impl bincode::Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(MAX_FRAME_SIZE as u64)
+}
+
+pub fn serialize_request(req: &Request) -> Result<Vec<u8>, CodecError> {
+    Ok(codec().serialize(req)?)
+}
+
+pub fn deserialize_request(data: &[u8]) -> Result<Request, CodecError> {
+    Ok(codec().deserialize(data)?)
+}
+
+pub fn serialize_response(resp: &Response) -> Result<Vec<u8>, CodecError> {
+    Ok(codec().serialize(resp)?)
+}
+
+pub fn deserialize_response(data: &[u8]) -> Result<Response, CodecError> {
+    Ok(codec().deserialize(data)?)
+}
+
+pub fn serialize_copy_frame(frame: &CopyFrame) -> Result<Vec<u8>, CodecError> {
+    Ok(codec().serialize(frame)?)
+}
+
+pub fn deserialize_copy_frame(data: &[u8]) -> Result<CopyFrame, CodecError> {
+    Ok(codec().deserialize(data)?)
+}
+
+pub fn serialize_exec_frame(frame: &ExecFrame) -> Result<Vec<u8>, CodecError> {
+    Ok(codec().serialize(frame)?)
+}
+
+pub fn deserialize_exec_frame(data: &[u8]) -> Result<ExecFrame, CodecError> {
+    Ok(codec().deserialize(data)?)
 }
 
-pub fn deserialize_request(data: &[u8]) -> Result<Request, Box<dyn std::error::Error>> {
-    Ok(bincode::deserialize(data)?)
+/// Write one length-prefixed frame (4-byte big-endian length followed by the payload)
+pub fn write_frame<W: Write>(writer: &mut W, payload: &[u8]) -> std::io::Result<()> {
+    if payload.len() > MAX_FRAME_SIZE {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("frame of {} bytes exceeds maximum size", payload.len()),
+        ));
+    }
+    writer.write_all(&(payload.len() as u32).to_be_bytes())?;
+    writer.write_all(payload)?;
+    writer.flush()
 }
 
-pub fn serialize_response(resp: &Response) -> Vec<u8> {
-    bincode::serialize(resp).unwrap()
+/// Read one length-prefixed frame, returning `None` on a clean end of stream
+///
+/// The stream only ends cleanly between frames; running out of data inside a
+/// header or payload is an `UnexpectedEof` error.
+pub fn read_frame<R: Read>(reader: &mut R) -> std::io::Result<Option<Vec<u8>>> {
+    let mut len_buf = [0u8; 4];
+    let mut filled = 0;
+    while filled < len_buf.len() {
+        match reader.read(&mut len_buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "stream ended inside a frame header",
+                ))
+            }
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    let len = u32::from_be_bytes(len_buf) as usize;
+    if len > MAX_FRAME_SIZE {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("frame of {} bytes exceeds maximum size", len),
+        ));
+    }
+
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload)?;
+    Ok(Some(payload))
 }
 
-pub fn deserialize_response(data: &[u8]) -> Result<Response, Box<dyn std::error::Error>> {
-    Ok(bincode::deserialize(data)?)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_roundtrip() {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, &serialize_request(&Request::Handshake).unwrap()).unwrap();
+        write_frame(&mut buf, &serialize_request(&Request::List).unwrap()).unwrap();
+
+        let mut cursor = std::io::Cursor::new(buf);
+        let first = read_frame(&mut cursor).unwrap().unwrap();
+        assert!(matches!(
+            deserialize_request(&first).unwrap(),
+            Request::Handshake
+        ));
+        let second = read_frame(&mut cursor).unwrap().unwrap();
+        assert!(matches!(
+            deserialize_request(&second).unwrap(),
+            Request::List
+        ));
+        assert!(read_frame(&mut cursor).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_encoding_limits() {
+        // Same bytes as plain bincode, so older peers still understand them
+        let request = Request::Start("c1".to_string());
+        assert_eq!(
+            serialize_request(&request).unwrap(),
+            bincode::serialize(&request).unwrap()
+        );
+
+        // Too large for a frame: an error instead of a panic or a rejected frame
+        let response = Response::Error("x".repeat(MAX_FRAME_SIZE));
+        assert!(serialize_response(&response).is_err());
+
+        // A corrupt length can't claim more than a frame holds
+        let mut bytes = serialize_response(&Response::Error(String::new())).unwrap();
+        bytes.truncate(4);
+        bytes.extend_from_slice(&u64::MAX.to_le_bytes());
+        assert!(deserialize_response(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_oversized_frame_rejected() {
+        let mut cursor = std::io::Cursor::new(((MAX_FRAME_SIZE + 1) as u32).to_be_bytes().to_vec());
+        assert!(read_frame(&mut cursor).is_err());
+    }
+
+    #[test]
+    fn test_container_status_encoding() {
+        // Encoded exactly like the plain strings of earlier versions
+        let info = ContainerInfoProto {
+            id: "c1".to_string(),
+            status: ContainerStatus::Running,
+            pid: None,
+            exit_reason: None,
+            restart_count: 0,
+            last_exit_code: None,
+            last_exit_reason: None,
+            restart_policy: RestartPolicy::No,
+            restart_retries: 0,
+            ip_address: None,
+            oom_kills: 0,
+            labels: Default::default(),
+            name: String::new(),
+        };
+        let bytes = bincode::serialize(&info).unwrap();
+        assert_eq!(
+            bytes,
+            bincode::serialize(&(
+                "c1",
+                "Running",
+                None::<u32>,
+                None::<String>,
+                0u32,
+                None::<i32>,
+                None::<ExitReason>,
+                RestartPolicy::No,
+                0u32,
+                None::<String>,
+                0u32,
+                0u64,
+                ""
+            ))
+            .unwrap()
+        );
+        let decoded: ContainerInfoProto = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(decoded.status, ContainerStatus::Running);
+
+        for (name, status) in [
+            ("running", ContainerStatus::Running),
+            ("created", ContainerStatus::Created),
+            ("stopped", ContainerStatus::Stopped),
+            ("paused", ContainerStatus::Paused),
+            ("orphaned", ContainerStatus::Stopped),
+        ] {
+            assert_eq!(ContainerStatus::from(name.to_string()), status);
+        }
+    }
+
+    #[test]
+    fn test_restart_policy() {
+        for (text, policy) in [
+            ("no", RestartPolicy::No),
+            ("always", RestartPolicy::Always),
+            ("on-failure", RestartPolicy::OnFailure { max_retries: 0 }),
+            ("on-failure:3", RestartPolicy::OnFailure { max_retries: 3 }),
+            ("unless-stopped", RestartPolicy::UnlessStopped),
+        ] {
+            assert_eq!(text.parse::<RestartPolicy>().unwrap(), policy);
+            assert_eq!(policy.to_string(), text);
+        }
+        assert!("sometimes".parse::<RestartPolicy>().is_err());
+        assert!("on-failure:x".parse::<RestartPolicy>().is_err());
+        assert!("always:3".parse::<RestartPolicy>().is_err());
+
+        assert!(!RestartPolicy::No.restarts(Some(1), 0));
+        assert!(RestartPolicy::Always.restarts(Some(0), 100));
+        assert!(RestartPolicy::UnlessStopped.restarts(None, 0));
+        let on_failure = RestartPolicy::OnFailure { max_retries: 2 };
+        assert!(!on_failure.restarts(Some(0), 0));
+        assert!(on_failure.restarts(Some(1), 1));
+        assert!(on_failure.restarts(None, 1));
+        assert!(!on_failure.restarts(Some(1), 2));
+        assert!(RestartPolicy::OnFailure { max_retries: 0 }.restarts(Some(137), 1000));
+    }
+
+    #[test]
+    fn test_volume_mount_options() {
+        let options = |list: &[&str]| list.iter().map(|o| o.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            volume_mount_options(&options(&["ro"]), None, false),
+            options(&["ro"])
+        );
+        assert_eq!(
+            volume_mount_options(
+                &options(&["bind", "rprivate"]),
+                Some(MountPropagation::Rshared),
+                false
+            ),
+            options(&["bind", "rshared"])
+        );
+        assert_eq!(
+            volume_mount_options(
+                &options(&["bind", "ro", "nosuid"]),
+                Some(MountPropagation::Rslave),
+                true
+            ),
+            options(&["nosuid", "rslave", "rbind", "rro"])
+        );
+        for propagation in ["rprivate", "rslave", "rshared"] {
+            let parsed = propagation.parse::<MountPropagation>().unwrap();
+            assert_eq!(parsed.to_string(), propagation);
+        }
+        assert!("shared".parse::<MountPropagation>().is_err());
+    }
+
+    #[test]
+    fn test_exit_outcome() {
+        // Wait statuses: exit code in the second byte, signal in the low bits
+        assert_eq!(exit_outcome(0, false), (0, ExitReason::Completed));
+        assert_eq!(exit_outcome(3 << 8, false), (3, ExitReason::Error));
+        assert_eq!(
+            exit_outcome(libc::SIGTERM, false),
+            (143, ExitReason::Signal)
+        );
+        assert_eq!(
+            exit_outcome(libc::SIGKILL, false),
+            (137, ExitReason::Signal)
+        );
+        assert_eq!(exit_outcome(libc::SIGKILL, true), (137, ExitReason::Oom));
+        // Only a SIGKILL can be the OOM killer
+        assert_eq!(exit_outcome(libc::SIGTERM, true), (143, ExitReason::Signal));
+        assert_eq!(exit_outcome(1 << 8, true), (1, ExitReason::Error));
+    }
+
+    #[test]
+    fn test_cgroup_limits() {
+        assert_eq!(io_weight(10), 1);
+        assert_eq!(io_weight(500), 4950);
+        assert_eq!(io_weight(1000), 10000);
+        assert_eq!(io_weight(5000), 10000);
+
+        assert_eq!(swap_limit(Some(64 << 20), Some(-1)), Some(-1));
+        assert_eq!(swap_limit(Some(64 << 20), Some(128 << 20)), Some(128 << 20));
+        // Swap is only limited on top of a memory limit
+        assert_eq!(swap_limit(None, Some(128 << 20)), None);
+        assert_eq!(swap_limit(Some(64 << 20), Some(0)), None);
+
+        let limit = |controller, file, value: &str| (controller, file, value.to_string());
+        assert_eq!(
+            cgroup_limits(true, 4096, Some(10_000), Some(32), Some(0.5), Some(500)),
+            vec![
+                limit("memory", "memory.max", "8192"),
+                limit("pids", "pids.max", "32"),
+                limit("cpu", "cpu.max", "50000 100000"),
+                limit("io", "io.weight", "default 4950"),
+            ]
+        );
+        assert_eq!(
+            cgroup_limits(false, 4096, Some(8192), Some(32), Some(2.0), Some(500)),
+            vec![
+                limit("memory", "memory.limit_in_bytes", "8192"),
+                limit("pids", "pids.max", "32"),
+                limit("cpu", "cpu.cfs_quota_us", "200000"),
+                limit("blkio", "blkio.weight", "500"),
+            ]
+        );
+        // Zero is unlimited, as in the OCI configuration
+        assert!(cgroup_limits(true, 4096, Some(0), Some(0), Some(0.0), None).is_empty());
+    }
+
+    #[test]
+    fn test_read_only_role() {
+        let role = Role::ReadOnly;
+        assert!(role.permits(&Request::List));
+        assert!(role.permits(&Request::Logs(LogsRequest {
+            id: "c1".to_string(),
+            tail: 0,
+            since: 0,
+            timestamps: false,
+            max_bytes: 0,
+            stdout_offset: None,
+            stderr_offset: None,
+            until: 0,
+        })));
+        assert!(role.permits(&Request::SetRole(Role::ReadOnly)));
+        assert!(!role.permits(&Request::Stop("c1".to_string())));
+        assert!(!role.permits(&Request::Delete("c1".to_string())));
+        assert!(!role.permits(&Request::Drain));
+        assert!(Role::Admin.permits(&Request::Delete("c1".to_string())));
+
+        assert!(role.permits(&Request::ListCheckpoints(None)));
+        assert!(role.permits(&Request::MemoryInfo));
+        assert!(role.permits(&Request::Version));
+        assert!(role.permits(&Request::ListeningPorts("c1".to_string())));
+        assert!(!role.permits(&Request::SyncTime(TimeSyncRequest {
+            host_time_ns: 0,
+            step_threshold_ms: 1000,
+        })));
+        assert!(!role.permits(&Request::Pause("c1".to_string())));
+        assert!(!role.permits(&Request::Unpause("c1".to_string())));
+        assert!(role.permits(&Request::LogsStream(LogsRequest {
+            id: "c1".to_string(),
+            tail: 10,
+            since: 0,
+            timestamps: false,
+            max_bytes: 0,
+            stdout_offset: None,
+            stderr_offset: None,
+            until: 0,
+        })));
+        assert!(!role.permits(&Request::ExecStream(ExecStreamRequest {
+            exec: ExecRequest {
+                id: "c1".to_string(),
+                command: vec!["sh".to_string()],
+                env: vec![],
+                working_dir: None,
+                user: String::new(),
+                tty: true,
+                uid: None,
+                gid: None,
+            },
+            terminal_size: Some((24, 80)),
+            detachable: false,
+            stdin_once: false,
+        })));
+        assert!(!role.permits(&Request::AttachExec("s1".to_string())));
+        assert!(role.permits(&Request::SubscribeEvents));
+        assert!(role.permits(&Request::ListFiltered(ListFilterProto::default())));
+        assert!(role.permits(&Request::StaleResources));
+        assert!(!role.permits(&Request::RemoveStaleResources));
+        assert!(!role.permits(&Request::Rename(RenameRequest {
+            id: "c1".to_string(),
+            name: "web".to_string(),
+        })));
+        assert!(!role.permits(&Request::StopWithTimeout(StopRequest {
+            id: "c1".to_string(),
+            timeout_secs: 30,
+        })));
+        assert!(!role.permits(&Request::WithProgress(
+            1,
+            ProgressRequest::CreateCheckpoint(CheckpointRequest {
+                id: "c1".to_string(),
+                name: "before-upgrade".to_string(),
+                leave_running: true,
+            })
+        )));
+        assert!(!role.permits(&Request::RemoveCheckpoint(CheckpointRef {
+            container: "c1".to_string(),
+            name: "before-upgrade".to_string(),
+        })));
+        let checkpoint = CheckpointRef {
+            container: "c1".to_string(),
+            name: "before-upgrade".to_string(),
+        };
+        assert!(!role.permits(&Request::CheckpointFiles(checkpoint.clone())));
+        assert!(
+            !role.permits(&Request::ReadCheckpointFile(CheckpointFileRequest {
+                checkpoint: checkpoint.clone(),
+                path: "config.json".to_string(),
+                offset: 0,
+            }))
+        );
+        assert!(
+            !role.permits(&Request::WriteCheckpointFile(CheckpointFileChunk {
+                checkpoint,
+                path: "config.json".to_string(),
+                offset: 0,
+                data: b"{}".to_vec(),
+            }))
+        );
+        assert!(!role.permits(&Request::Prune(PruneFilterProto::default())));
+        assert!(role.permits(&Request::Resolve("docker.io".to_string())));
+        assert!(!role.permits(&Request::Diagnostics));
+        for direction in [CopyDirection::In, CopyDirection::Out] {
+            assert!(!role.permits(&Request::Copy(CopyRequest {
+                id: "c1".to_string(),
+                path: "/etc".to_string(),
+                direction,
+                options: CopyOptionsProto::default(),
+            })));
+        }
+    }
 }
@@ -0,0 +1,369 @@
+//! The status changes a container goes through
+//!
+//! Both the agent and the library runtime move their containers between
+//! statuses through a [`StateMachine`], so that a request is refused the
+//! same way whichever of them handles it.
+
+use crate::ContainerStatus;
+
+/// A change of a container's status
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Transition {
+    /// Run the process of a created container, or of a stopped one again
+    Start,
+    /// Stop the process on request, e.g. with a signal or after a timeout
+    Stop,
+    /// The process exited on its own or was killed by the kernel
+    Exit,
+    Pause,
+    Unpause,
+    /// Run the process of a stopped container again, per its restart policy
+    Restart,
+    /// Remove the container, which keeps its last status
+    Delete,
+}
+
+impl Transition {
+    pub const ALL: [Transition; 7] = [
+        Transition::Start,
+        Transition::Stop,
+        Transition::Exit,
+        Transition::Pause,
+        Transition::Unpause,
+        Transition::Restart,
+        Transition::Delete,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Transition::Start => "start",
+            Transition::Stop => "stop",
+            Transition::Exit => "exit",
+            Transition::Pause => "pause",
+            Transition::Unpause => "unpause",
+            Transition::Restart => "restart",
+            Transition::Delete => "delete",
+        }
+    }
+
+    /// Status of a container in `from` after the transition, `None` if the
+    /// transition is not allowed from there
+    pub fn target(self, from: ContainerStatus) -> Option<ContainerStatus> {
+        use ContainerStatus::*;
+        match (self, from) {
+            (Transition::Start, Created | Stopped) => Some(Running),
+            // A paused container can still be stopped, or killed by the OOM killer
+            (Transition::Stop | Transition::Exit, Running | Paused) => Some(Stopped),
+            (Transition::Pause, Running) => Some(Paused),
+            (Transition::Unpause, Paused) => Some(Running),
+            (Transition::Restart, Stopped) => Some(Running),
+            (Transition::Delete, Created | Stopped) => Some(from),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Transition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Why a container could not change its status
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransitionError {
+    /// The transition is not allowed from the container's status
+    NotAllowed {
+        id: String,
+        transition: Transition,
+        from: ContainerStatus,
+    },
+    /// Exec sessions still run in the container
+    ExecsInFlight { id: String, count: usize },
+}
+
+impl TransitionError {
+    pub fn id(&self) -> &str {
+        match self {
+            TransitionError::NotAllowed { id, .. } | TransitionError::ExecsInFlight { id, .. } => {
+                id
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for TransitionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use ContainerStatus::*;
+        let (id, transition, from) = match self {
+            TransitionError::NotAllowed {
+                id,
+                transition,
+                from,
+            } => (id, *transition, *from),
+            TransitionError::ExecsInFlight { id, count } => {
+                return write!(
+                    f,
+                    "Container '{}' has {} exec session(s) in flight and cannot be deleted",
+                    id, count
+                )
+            }
+        };
+        let reason = match (transition, from) {
+            (Transition::Start, _) => "is already running",
+            (Transition::Pause, Paused) => "is already paused",
+            (Transition::Unpause, _) => "is not paused",
+            (Transition::Restart, _) => "is not stopped",
+            (Transition::Delete, _) => "is running and cannot be deleted",
+            _ => "is not running",
+        };
+        write!(f, "Container '{}' {}", id, reason)
+    }
+}
+
+impl std::error::Error for TransitionError {}
+
+type Hook = Box<dyn Fn(&str, Transition, ContainerStatus, ContainerStatus) + Send + Sync>;
+
+/// Validates and applies the status changes of containers
+///
+/// Hooks installed with [`StateMachine::on_transition`] run after every
+/// applied transition, e.g. to publish events.
+#[derive(Default)]
+pub struct StateMachine {
+    hooks: Vec<Hook>,
+}
+
+impl StateMachine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run `hook` with the container's ID, the transition and the statuses
+    /// before and after it, whenever a transition is applied
+    ///
+    /// Hooks run while the caller holds its containers, so they must not
+    /// take that lock themselves.
+    pub fn on_transition(
+        mut self,
+        hook: impl Fn(&str, Transition, ContainerStatus, ContainerStatus) + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Status of the container after `transition`, without applying it
+    pub fn check(
+        &self,
+        id: &str,
+        status: ContainerStatus,
+        transition: Transition,
+    ) -> Result<ContainerStatus, TransitionError> {
+        transition
+            .target(status)
+            .ok_or_else(|| TransitionError::NotAllowed {
+                id: id.to_string(),
+                transition,
+                from: status,
+            })
+    }
+
+    /// Check that the container can be deleted while `execs_in_flight` exec
+    /// sessions run in it
+    pub fn check_delete(
+        &self,
+        id: &str,
+        status: ContainerStatus,
+        execs_in_flight: usize,
+    ) -> Result<(), TransitionError> {
+        self.check(id, status, Transition::Delete)?;
+        if execs_in_flight > 0 {
+            return Err(TransitionError::ExecsInFlight {
+                id: id.to_string(),
+                count: execs_in_flight,
+            });
+        }
+        Ok(())
+    }
+
+    /// Move `status` through `transition` and run the hooks
+    pub fn apply(
+        &self,
+        id: &str,
+        status: &mut ContainerStatus,
+        transition: Transition,
+    ) -> Result<(), TransitionError> {
+        let from = *status;
+        let to = self.check(id, from, transition)?;
+        *status = to;
+        for hook in &self.hooks {
+            hook(id, transition, from, to);
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for StateMachine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StateMachine")
+            .field("hooks", &self.hooks.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    const STATUSES: [ContainerStatus; 4] = [
+        ContainerStatus::Created,
+        ContainerStatus::Running,
+        ContainerStatus::Stopped,
+        ContainerStatus::Paused,
+    ];
+
+    #[test]
+    fn test_every_edge() {
+        use ContainerStatus::*;
+        let allowed = [
+            (Transition::Start, Created, Running),
+            (Transition::Start, Stopped, Running),
+            (Transition::Stop, Running, Stopped),
+            (Transition::Stop, Paused, Stopped),
+            (Transition::Exit, Running, Stopped),
+            (Transition::Exit, Paused, Stopped),
+            (Transition::Pause, Running, Paused),
+            (Transition::Unpause, Paused, Running),
+            (Transition::Restart, Stopped, Running),
+            (Transition::Delete, Created, Created),
+            (Transition::Delete, Stopped, Stopped),
+        ];
+        let machine = StateMachine::new();
+        for transition in Transition::ALL {
+            for from in STATUSES {
+                let expected = allowed
+                    .iter()
+                    .find(|(t, f, _)| *t == transition && *f == from)
+                    .map(|(_, _, to)| *to);
+                let mut status = from;
+                let result = machine.apply("c1", &mut status, transition);
+                match expected {
+                    Some(to) => {
+                        assert_eq!(result, Ok(()), "{} from {}", transition, from);
+                        assert_eq!(status, to, "{} from {}", transition, from);
+                    }
+                    None => {
+                        assert_eq!(
+                            result,
+                            Err(TransitionError::NotAllowed {
+                                id: "c1".to_string(),
+                                transition,
+                                from,
+                            }),
+                            "{} from {}",
+                            transition,
+                            from
+                        );
+                        assert_eq!(status, from, "{} from {}", transition, from);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_error_messages() {
+        let message = |transition, from| {
+            StateMachine::new()
+                .check("c1", from, transition)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            message(Transition::Start, ContainerStatus::Paused),
+            "Container 'c1' is already running"
+        );
+        assert_eq!(
+            message(Transition::Stop, ContainerStatus::Created),
+            "Container 'c1' is not running"
+        );
+        assert_eq!(
+            message(Transition::Pause, ContainerStatus::Paused),
+            "Container 'c1' is already paused"
+        );
+        assert_eq!(
+            message(Transition::Pause, ContainerStatus::Stopped),
+            "Container 'c1' is not running"
+        );
+        assert_eq!(
+            message(Transition::Unpause, ContainerStatus::Running),
+            "Container 'c1' is not paused"
+        );
+        assert_eq!(
+            message(Transition::Delete, ContainerStatus::Running),
+            "Container 'c1' is running and cannot be deleted"
+        );
+    }
+
+    #[test]
+    fn test_delete_with_execs_in_flight() {
+        let machine = StateMachine::new();
+        assert!(machine
+            .check_delete("c1", ContainerStatus::Stopped, 0)
+            .is_ok());
+        let err = machine
+            .check_delete("c1", ContainerStatus::Stopped, 2)
+            .unwrap_err();
+        assert_eq!(
+            err,
+            TransitionError::ExecsInFlight {
+                id: "c1".to_string(),
+                count: 2
+            }
+        );
+        assert_eq!(err.id(), "c1");
+        // A running container is refused for its status first
+        assert!(matches!(
+            machine.check_delete("c1", ContainerStatus::Running, 1),
+            Err(TransitionError::NotAllowed { .. })
+        ));
+    }
+
+    #[test]
+    fn test_hooks_run_on_applied_transitions() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let machine = StateMachine::new().on_transition({
+            let seen = seen.clone();
+            move |id, transition, from, to| {
+                seen.lock()
+                    .unwrap()
+                    .push((id.to_string(), transition, from, to))
+            }
+        });
+
+        let mut status = ContainerStatus::Created;
+        machine.apply("c1", &mut status, Transition::Start).unwrap();
+        // Refused transitions do not run the hooks
+        assert!(machine.apply("c1", &mut status, Transition::Start).is_err());
+        machine.apply("c1", &mut status, Transition::Stop).unwrap();
+
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                (
+                    "c1".to_string(),
+                    Transition::Start,
+                    ContainerStatus::Created,
+                    ContainerStatus::Running
+                ),
+                (
+                    "c1".to_string(),
+                    Transition::Stop,
+                    ContainerStatus::Running,
+                    ContainerStatus::Stopped
+                ),
+            ]
+        );
+    }
+}
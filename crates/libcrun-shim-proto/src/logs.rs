@@ -0,0 +1,171 @@
+//! Bounded reads of container log files
+//!
+//! Logs can grow to many gigabytes, so nothing here reads a whole file: tails
+//! are found by scanning backwards from the end in fixed-size blocks, every
+//! response is capped, and callers page through a file with byte offsets.
+//! The agent and the native Linux runtime both read logs through here, so
+//! they honour the same `LogOptions` limits and offsets.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Upper bound on log bytes returned for one stream in one response
+pub const MAX_LOG_BYTES: u64 = 4 * 1024 * 1024;
+
+/// Block size for the backwards scan
+const BLOCK_SIZE: u64 = 64 * 1024;
+
+/// A slice of a log file
+#[derive(Debug, Default)]
+pub struct LogChunk {
+    pub data: String,
+    /// Offset to pass back to continue reading after this chunk
+    pub next_offset: u64,
+    /// Data was dropped to stay within the byte limit
+    pub truncated: bool,
+}
+
+/// Read part of a log file
+///
+/// With an `offset`, reads forward from it (pagination and follow). Otherwise
+/// returns the last `tail` lines, or the whole file for `tail == 0`, keeping
+/// only the newest `max_bytes` (0 or anything above [`MAX_LOG_BYTES`] means
+/// [`MAX_LOG_BYTES`]). Truncated reads are cut at line boundaries when possible.
+pub fn read_log(path: &str, tail: u32, offset: Option<u64>, max_bytes: u64) -> LogChunk {
+    let cap = match max_bytes {
+        0 => MAX_LOG_BYTES,
+        n => n.min(MAX_LOG_BYTES),
+    };
+    let Ok(mut file) = File::open(path) else {
+        return LogChunk::default();
+    };
+    let len = file.metadata().map(|m| m.len()).unwrap_or(0);
+
+    let result = match offset {
+        Some(offset) => read_forward(&mut file, len, offset, cap),
+        None => read_tail(&mut file, len, tail, cap),
+    };
+    result.unwrap_or_else(|e| {
+        log::warn!("Failed to read log {}: {}", path, e);
+        LogChunk::default()
+    })
+}
+
+fn read_forward(file: &mut File, len: u64, offset: u64, cap: u64) -> std::io::Result<LogChunk> {
+    let start = offset.min(len);
+    let end = len.min(start + cap);
+    let mut buf = read_range(file, start, end)?;
+
+    let truncated = end < len;
+    if truncated {
+        // Stop after the last complete line; the next page starts with the rest
+        if let Some(last_newline) = buf.iter().rposition(|b| *b == b'\n') {
+            buf.truncate(last_newline + 1);
+        }
+    }
+
+    Ok(LogChunk {
+        next_offset: start + buf.len() as u64,
+        data: String::from_utf8_lossy(&buf).into_owned(),
+        truncated,
+    })
+}
+
+fn read_tail(file: &mut File, len: u64, tail: u32, cap: u64) -> std::io::Result<LogChunk> {
+    let mut start = 0;
+    if tail > 0 && len > 0 {
+        // A trailing newline ends the last line rather than starting a new one
+        let mut pos = len;
+        if read_range(file, len - 1, len)? == b"\n" {
+            pos -= 1;
+        }
+
+        let mut newlines = 0;
+        'scan: while pos > 0 && len - pos <= cap {
+            let block_start = pos.saturating_sub(BLOCK_SIZE);
+            let block = read_range(file, block_start, pos)?;
+            for (i, b) in block.iter().enumerate().rev() {
+                if *b == b'\n' {
+                    newlines += 1;
+                    if newlines == tail {
+                        start = block_start + i as u64 + 1;
+                        break 'scan;
+                    }
+                }
+            }
+            pos = block_start;
+        }
+    }
+
+    let truncated = len - start > cap;
+    if truncated {
+        start = len - cap;
+    }
+    let mut buf = read_range(file, start, len)?;
+    if truncated {
+        // Drop the partial first line
+        if let Some(first_newline) = buf.iter().position(|b| *b == b'\n') {
+            if first_newline + 1 < buf.len() {
+                buf.drain(..=first_newline);
+            }
+        }
+    }
+
+    Ok(LogChunk {
+        data: String::from_utf8_lossy(&buf).into_owned(),
+        next_offset: len,
+        truncated,
+    })
+}
+
+fn read_range(file: &mut File, start: u64, end: u64) -> std::io::Result<Vec<u8>> {
+    let mut buf = vec![0; (end - start) as usize];
+    file.seek(SeekFrom::Start(start))?;
+    file.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_log() {
+        let path = std::env::temp_dir().join(format!("proto-log-{}.log", std::process::id()));
+        let content: String = (1..=5000).map(|i| format!("line {}\n", i)).collect();
+        std::fs::write(&path, &content).unwrap();
+        let path = path.to_str().unwrap();
+
+        // Tail spans several scan blocks
+        let chunk = read_log(path, 3, None, 0);
+        assert_eq!(chunk.data, "line 4998\nline 4999\nline 5000\n");
+        assert_eq!(chunk.next_offset, content.len() as u64);
+        assert!(!chunk.truncated);
+        let chunk = read_log(path, 4000, None, 0);
+        assert!(chunk.data.starts_with("line 1001\n"));
+
+        // The byte cap wins over the line count and cuts at a line boundary
+        let chunk = read_log(path, 100, None, 25);
+        assert!(chunk.truncated);
+        assert_eq!(chunk.data, "line 4999\nline 5000\n");
+        let chunk = read_log(path, 0, None, 15);
+        assert_eq!(chunk.data, "line 5000\n");
+
+        // Paging forward reassembles the file
+        let mut offset = 0;
+        let mut pages = String::new();
+        loop {
+            let chunk = read_log(path, 0, Some(offset), 1000);
+            if chunk.data.is_empty() {
+                break;
+            }
+            assert!(chunk.data.ends_with('\n'));
+            pages.push_str(&chunk.data);
+            offset = chunk.next_offset;
+        }
+        assert_eq!(pages, content);
+
+        let _ = std::fs::remove_file(path);
+        assert!(read_log(path, 10, None, 0).data.is_empty());
+    }
+}
@@ -0,0 +1,777 @@
+//! Property tests for the RPC codec
+//!
+//! Messages survive an encode/decode round trip, and no input, however
+//! malformed or truncated, makes decoding or frame reading panic. The
+//! `fuzz/` targets cover the same ground with coverage-guided inputs.
+
+use libcrun_shim_proto::*;
+use proptest::collection::{btree_map, vec};
+use proptest::option;
+use proptest::prelude::*;
+use proptest::strategy::LazyJust;
+use std::io::Cursor;
+
+fn id() -> impl Strategy<Value = String> {
+    "[a-z0-9][a-z0-9_.-]{0,63}"
+}
+
+fn strings() -> impl Strategy<Value = Vec<String>> {
+    vec(any::<String>(), 0..4)
+}
+
+fn create_request() -> impl Strategy<Value = CreateRequest> {
+    (
+        (id(), id()),
+        (any::<String>(), vec(volume(), 0..2)),
+        strings(),
+        strings(),
+        any::<bool>(),
+        (option::of(any::<u64>()), option::of(any::<u64>())),
+        option::of(any::<f64>()),
+        option::of(strings()),
+        option::of(any::<String>()),
+        restart_policy(),
+        (any::<bool>(), any::<bool>(), any::<bool>()),
+        btree_map(id(), any::<String>(), 0..4),
+    )
+        .prop_map(
+            |(
+                (id, name),
+                (rootfs, volumes),
+                command,
+                env,
+                tty,
+                (max_runtime_secs, stop_timeout),
+                cpu,
+                capabilities,
+                image,
+                restart_policy,
+                (rootfs_snapshot, read_only_rootfs, create_working_dir),
+                labels,
+            )| {
+                CreateRequest {
+                    id,
+                    rootfs,
+                    command,
+                    env,
+                    working_dir: "/".to_string(),
+                    stdio: StdioConfigProto {
+                        tty,
+                        ..Default::default()
+                    },
+                    network: NetworkConfigProto::default(),
+                    volumes,
+                    resources: ResourceLimitsProto {
+                        cpu,
+                        ..Default::default()
+                    },
+                    health_check: None,
+                    security: SecurityProto {
+                        capabilities,
+                        ..Default::default()
+                    },
+                    image,
+                    max_runtime_secs,
+                    auto_stop: None,
+                    join_namespaces: None,
+                    restart_policy,
+                    rootfs_snapshot,
+                    read_only_rootfs,
+                    labels,
+                    name,
+                    stop_timeout,
+                    create_working_dir,
+                }
+            },
+        )
+}
+
+fn request() -> impl Strategy<Value = Request> {
+    prop_oneof![
+        create_request().prop_map(Request::Create),
+        id().prop_map(Request::Start),
+        id().prop_map(Request::Stop),
+        id().prop_map(Request::Delete),
+        LazyJust::new(|| Request::List),
+        id().prop_map(Request::Metrics),
+        LazyJust::new(|| Request::AllMetrics),
+        (id(), any::<u32>(), option::of(any::<u64>()), any::<u64>()).prop_map(
+            |(id, tail, stdout_offset, max_bytes)| Request::Logs(LogsRequest {
+                id,
+                tail,
+                since: 0,
+                timestamps: false,
+                max_bytes,
+                stdout_offset,
+                stderr_offset: None,
+                until: 0,
+            })
+        ),
+        id().prop_map(Request::Health),
+        (id(), strings(), option::of(any::<u32>())).prop_map(|(id, command, uid)| {
+            Request::Exec(ExecRequest {
+                id,
+                command,
+                env: Vec::new(),
+                working_dir: None,
+                user: String::new(),
+                tty: false,
+                uid,
+                gid: uid,
+            })
+        }),
+        LazyJust::new(|| Request::Handshake),
+        vec(any::<u8>(), 0..256).prop_map(|binary| Request::UploadAgent(UploadAgentRequest {
+            source: AgentBinarySource::Inline(binary),
+            sha256: String::new(),
+        })),
+        LazyJust::new(|| Request::Drain),
+        LazyJust::new(|| Request::Features),
+        LazyJust::new(|| Request::KernelFeatures),
+        id().prop_map(Request::ExecSessions),
+        LazyJust::new(|| Request::DiskUsage),
+        LazyJust::new(|| Request::Trim),
+        (id(), any::<String>(), any::<String>(), any::<bool>()).prop_map(
+            |(id, share, destination, read_only)| Request::Mount(MountRequest {
+                id,
+                share,
+                destination,
+                read_only,
+            })
+        ),
+        any::<u64>().prop_map(Request::MetricsHistory),
+        vec(any::<String>(), 0..4).prop_map(Request::MissingBlobs),
+        (
+            any::<String>(),
+            any::<u64>(),
+            vec(any::<u8>(), 0..256),
+            any::<bool>()
+        )
+            .prop_map(|(digest, offset, data, last)| Request::PutBlob(BlobChunk {
+                digest,
+                offset,
+                data,
+                last,
+            })),
+        role().prop_map(Request::SetRole),
+        (id(), id(), any::<bool>()).prop_map(|(id, name, leave_running)| {
+            Request::CreateCheckpoint(CheckpointRequest {
+                id,
+                name,
+                leave_running,
+            })
+        }),
+        option::of(id()).prop_map(Request::ListCheckpoints),
+        checkpoint_ref().prop_map(Request::RemoveCheckpoint),
+        (checkpoint_ref(), id()).prop_map(|(checkpoint, id)| Request::RestoreCheckpoint(
+            RestoreRequest { checkpoint, id }
+        )),
+        (
+            id(),
+            any::<String>(),
+            prop_oneof![Just(CopyDirection::In), Just(CopyDirection::Out)],
+            (strings(), any::<bool>(), any::<bool>())
+        )
+            .prop_map(
+                |(id, path, direction, (exclude, follow_links, preserve_owner))| {
+                    Request::Copy(CopyRequest {
+                        id,
+                        path,
+                        direction,
+                        options: CopyOptionsProto {
+                            exclude,
+                            follow_links,
+                            preserve_owner,
+                        },
+                    })
+                }
+            ),
+        LazyJust::new(|| Request::MemoryInfo),
+        (id(), strings(), option::of((any::<u16>(), any::<u16>()))).prop_map(
+            |(id, command, terminal_size)| Request::ExecStream(ExecStreamRequest {
+                exec: ExecRequest {
+                    id,
+                    command,
+                    env: Vec::new(),
+                    working_dir: None,
+                    user: String::new(),
+                    tty: terminal_size.is_some(),
+                    uid: None,
+                    gid: None,
+                },
+                terminal_size,
+                detachable: terminal_size.is_some(),
+                stdin_once: terminal_size.is_none(),
+            })
+        ),
+        (id(), any::<u32>(), option::of(any::<u64>())).prop_map(|(id, tail, offset)| {
+            Request::LogsStream(LogsRequest {
+                id,
+                tail,
+                since: 0,
+                timestamps: false,
+                max_bytes: 0,
+                stdout_offset: offset,
+                stderr_offset: offset,
+                until: 0,
+            })
+        }),
+        LazyJust::new(|| Request::Version),
+        id().prop_map(Request::Pause),
+        id().prop_map(Request::Unpause),
+        id().prop_map(Request::Wait),
+        id().prop_map(Request::AttachExec),
+        id().prop_map(Request::ListeningPorts),
+        (any::<i64>(), any::<u64>()).prop_map(|(host_time_ns, step_threshold_ms)| {
+            Request::SyncTime(TimeSyncRequest {
+                host_time_ns,
+                step_threshold_ms,
+            })
+        }),
+        checkpoint_ref().prop_map(Request::CheckpointFiles),
+        (checkpoint_ref(), any::<String>(), any::<u64>()).prop_map(|(checkpoint, path, offset)| {
+            Request::ReadCheckpointFile(CheckpointFileRequest {
+                checkpoint,
+                path,
+                offset,
+            })
+        }),
+        (
+            checkpoint_ref(),
+            any::<String>(),
+            any::<u64>(),
+            vec(any::<u8>(), 0..256)
+        )
+            .prop_map(|(checkpoint, path, offset, data)| {
+                Request::WriteCheckpointFile(CheckpointFileChunk {
+                    checkpoint,
+                    path,
+                    offset,
+                    data,
+                })
+            }),
+        checkpoint().prop_map(Request::ImportCheckpoint),
+        (
+            any::<u64>(),
+            vec(
+                (id(), option::of(any::<String>()), any::<bool>())
+                    .prop_map(|(key, value, negate)| LabelFilterProto { key, value, negate }),
+                0..4
+            )
+        )
+            .prop_map(|(until, labels)| Request::Prune(PruneFilterProto { until, labels })),
+        any::<String>().prop_map(Request::Resolve),
+        LazyJust::new(|| Request::Diagnostics),
+        LazyJust::new(|| Request::SubscribeEvents),
+        (
+            any::<u64>(),
+            prop_oneof![
+                create_request().prop_map(|req| ProgressRequest::Create(Box::new(req))),
+                (id(), id(), any::<bool>()).prop_map(|(id, name, leave_running)| {
+                    ProgressRequest::CreateCheckpoint(CheckpointRequest {
+                        id,
+                        name,
+                        leave_running,
+                    })
+                }),
+                (checkpoint_ref(), id()).prop_map(|(checkpoint, id)| {
+                    ProgressRequest::RestoreCheckpoint(RestoreRequest { checkpoint, id })
+                }),
+                checkpoint().prop_map(ProgressRequest::ImportCheckpoint),
+            ]
+        )
+            .prop_map(|(id, request)| Request::WithProgress(id, request)),
+        (
+            vec(
+                (id(), option::of(any::<String>()), any::<bool>())
+                    .prop_map(|(key, value, negate)| LabelFilterProto { key, value, negate }),
+                0..4
+            ),
+            vec(status(), 0..4),
+            any::<String>()
+        )
+            .prop_map(|(labels, statuses, name_prefix)| {
+                Request::ListFiltered(ListFilterProto {
+                    labels,
+                    statuses,
+                    name_prefix,
+                })
+            }),
+        LazyJust::new(|| Request::StaleResources),
+        LazyJust::new(|| Request::RemoveStaleResources),
+        (id(), id()).prop_map(|(id, name)| Request::Rename(RenameRequest { id, name })),
+        (id(), any::<u64>()).prop_map(|(id, timeout_secs)| Request::StopWithTimeout(StopRequest {
+            id,
+            timeout_secs
+        })),
+        LazyJust::new(|| Request::Undrain),
+    ]
+}
+
+fn exec_frame() -> impl Strategy<Value = ExecFrame> {
+    prop_oneof![
+        vec(any::<u8>(), 0..256).prop_map(ExecFrame::Stdin),
+        LazyJust::new(|| ExecFrame::CloseStdin),
+        (any::<u16>(), any::<u16>()).prop_map(|(rows, cols)| ExecFrame::Resize(rows, cols)),
+        vec(any::<u8>(), 0..256).prop_map(ExecFrame::Stdout),
+        vec(any::<u8>(), 0..256).prop_map(ExecFrame::Stderr),
+        any::<i32>().prop_map(ExecFrame::Exit),
+        any::<String>().prop_map(ExecFrame::Failed),
+        LazyJust::new(|| ExecFrame::Detach),
+        id().prop_map(ExecFrame::Session),
+    ]
+}
+
+fn copy_stats() -> impl Strategy<Value = CopyStatsProto> {
+    (any::<u64>(), any::<u64>()).prop_map(|(entries, bytes)| CopyStatsProto { entries, bytes })
+}
+
+fn copy_frame() -> impl Strategy<Value = CopyFrame> {
+    prop_oneof![
+        vec(any::<u8>(), 0..256).prop_map(CopyFrame::Data),
+        LazyJust::new(|| CopyFrame::End),
+        copy_stats().prop_map(CopyFrame::Total),
+        copy_stats().prop_map(CopyFrame::Done),
+        any::<String>().prop_map(CopyFrame::Failed),
+    ]
+}
+
+fn checkpoint_ref() -> impl Strategy<Value = CheckpointRef> {
+    (id(), id()).prop_map(|(container, name)| CheckpointRef { container, name })
+}
+
+fn checkpoint() -> impl Strategy<Value = CheckpointProto> {
+    (id(), id(), any::<u64>(), any::<u64>(), any::<String>()).prop_map(
+        |(container, name, created_at, size_bytes, image_digest)| CheckpointProto {
+            container,
+            name,
+            created_at,
+            size_bytes,
+            image_digest,
+        },
+    )
+}
+
+fn status() -> impl Strategy<Value = ContainerStatus> {
+    prop_oneof![
+        Just(ContainerStatus::Created),
+        Just(ContainerStatus::Running),
+        Just(ContainerStatus::Stopped),
+        Just(ContainerStatus::Paused),
+    ]
+}
+
+fn error_code() -> impl Strategy<Value = ErrorCode> {
+    prop_oneof![
+        Just(ErrorCode::Internal),
+        Just(ErrorCode::NotFound),
+        Just(ErrorCode::Conflict),
+        Just(ErrorCode::InvalidArgument),
+        Just(ErrorCode::Unavailable),
+        Just(ErrorCode::PermissionDenied),
+    ]
+}
+
+fn exit_reason() -> impl Strategy<Value = ExitReason> {
+    prop_oneof![
+        Just(ExitReason::Completed),
+        Just(ExitReason::Error),
+        Just(ExitReason::Signal),
+        Just(ExitReason::Oom),
+    ]
+}
+
+fn volume() -> impl Strategy<Value = VolumeMountProto> {
+    (
+        any::<String>(),
+        any::<String>(),
+        strings(),
+        option::of(prop_oneof![
+            Just(MountPropagation::Rprivate),
+            Just(MountPropagation::Rslave),
+            Just(MountPropagation::Rshared),
+        ]),
+        any::<bool>(),
+    )
+        .prop_map(
+            |(source, destination, options, propagation, recursive_readonly)| VolumeMountProto {
+                source,
+                destination,
+                options,
+                propagation,
+                recursive_readonly,
+            },
+        )
+}
+
+fn restart_policy() -> impl Strategy<Value = RestartPolicy> {
+    prop_oneof![
+        Just(RestartPolicy::No),
+        Just(RestartPolicy::Always),
+        any::<u32>().prop_map(|max_retries| RestartPolicy::OnFailure { max_retries }),
+        Just(RestartPolicy::UnlessStopped),
+    ]
+}
+
+fn event() -> impl Strategy<Value = EventProto> {
+    (
+        prop_oneof![
+            Just(EventKind::Start),
+            Just(EventKind::Stop),
+            Just(EventKind::Kill),
+            Just(EventKind::Die),
+            Just(EventKind::Oom),
+            Just(EventKind::HealthOk),
+            Just(EventKind::HealthFail),
+        ],
+        id(),
+        any::<u64>(),
+        option::of(any::<i32>()),
+        option::of(any::<i32>()),
+        btree_map(id(), any::<String>(), 0..4),
+    )
+        .prop_map(
+            |(kind, container_id, timestamp, exit_code, signal, attributes)| EventProto {
+                kind,
+                container_id,
+                timestamp,
+                exit_code,
+                signal,
+                attributes,
+            },
+        )
+}
+
+fn role() -> impl Strategy<Value = Role> {
+    prop_oneof![Just(Role::Admin), Just(Role::ReadOnly)]
+}
+
+fn response() -> impl Strategy<Value = Response> {
+    prop_oneof![
+        id().prop_map(Response::Created),
+        LazyJust::new(|| Response::Started),
+        LazyJust::new(|| Response::Stopped),
+        LazyJust::new(|| Response::Deleted),
+        vec(
+            (
+                (id(), id()),
+                status(),
+                option::of(any::<u32>()),
+                any::<u32>(),
+                option::of(any::<i32>()),
+                option::of(exit_reason()),
+                restart_policy(),
+                any::<u32>(),
+                option::of(any::<u32>().prop_map(|a| std::net::Ipv4Addr::from(a).to_string())),
+                any::<u32>(),
+                btree_map(id(), any::<String>(), 0..4)
+            )
+                .prop_map(
+                    |(
+                        (id, name),
+                        status,
+                        pid,
+                        restart_count,
+                        last_exit_code,
+                        last_exit_reason,
+                        restart_policy,
+                        restart_retries,
+                        ip_address,
+                        oom_kills,
+                        labels,
+                    )| {
+                        ContainerInfoProto {
+                            id,
+                            status,
+                            pid,
+                            exit_reason: None,
+                            restart_count,
+                            last_exit_code,
+                            last_exit_reason,
+                            restart_policy,
+                            restart_retries,
+                            ip_address,
+                            oom_kills,
+                            labels,
+                            name,
+                        }
+                    }
+                ),
+            0..8
+        )
+        .prop_map(Response::List),
+        (id(), any::<u64>(), any::<u64>()).prop_map(|(id, usage, limit)| {
+            let mut metrics = ContainerMetricsProto {
+                id,
+                ..Default::default()
+            };
+            metrics.memory.usage = usage;
+            metrics.memory.limit = limit;
+            Response::Metrics(metrics)
+        }),
+        (id(), any::<String>(), any::<String>(), any::<bool>()).prop_map(
+            |(id, stdout, stderr, truncated)| Response::Logs(LogsProto {
+                id,
+                stdout,
+                stderr,
+                truncated,
+                ..Default::default()
+            })
+        ),
+        (any::<i32>(), any::<String>(), any::<String>()).prop_map(|(exit_code, stdout, stderr)| {
+            Response::Exec(ExecResultProto {
+                exit_code,
+                stdout,
+                stderr,
+            })
+        }),
+        (any::<String>(), any::<u32>()).prop_map(|(agent_version, protocol_version)| {
+            Response::Handshake(HandshakeProto {
+                agent_version,
+                protocol_version,
+            })
+        }),
+        any::<String>().prop_map(Response::Error),
+        strings().prop_map(Response::Features),
+        (error_code(), any::<String>())
+            .prop_map(|(code, message)| Response::Failed(ErrorProto { code, message })),
+        vec(
+            (any::<String>(), any::<u64>(), any::<u64>()).prop_map(
+                |(mount_point, total_bytes, used_bytes)| FilesystemUsageProto {
+                    mount_point,
+                    total_bytes,
+                    used_bytes,
+                    ..Default::default()
+                }
+            ),
+            0..4
+        )
+        .prop_map(Response::DiskUsage),
+        vec(
+            (any::<String>(), any::<u64>(), option::of(any::<String>())).prop_map(
+                |(mount_point, trimmed_bytes, error)| TrimResultProto {
+                    mount_point,
+                    trimmed_bytes,
+                    error,
+                }
+            ),
+            0..4
+        )
+        .prop_map(Response::Trimmed),
+        LazyJust::new(|| Response::Mounted),
+        vec(
+            (id(), any::<u64>(), any::<u64>(), any::<u64>()).prop_map(
+                |(id, timestamp, cpu_usage_ns, memory_bytes)| MetricsSampleProto {
+                    id,
+                    timestamp,
+                    cpu_usage_ns,
+                    memory_bytes,
+                    ..Default::default()
+                }
+            ),
+            0..8
+        )
+        .prop_map(Response::MetricsHistory),
+        vec(any::<String>(), 0..4).prop_map(Response::MissingBlobs),
+        LazyJust::new(|| Response::BlobStored),
+        role().prop_map(Response::RoleSet),
+        checkpoint().prop_map(Response::Checkpoint),
+        vec(checkpoint(), 0..4).prop_map(Response::Checkpoints),
+        LazyJust::new(|| Response::CheckpointRemoved),
+        LazyJust::new(|| Response::CopyStarted),
+        (any::<u64>(), any::<u64>()).prop_map(|(total_bytes, available_bytes)| {
+            Response::MemoryInfo(MemoryInfoProto {
+                total_bytes,
+                available_bytes,
+            })
+        }),
+        LazyJust::new(|| Response::ExecStarted),
+        (
+            any::<String>(),
+            option::of(any::<String>()),
+            any::<u64>(),
+            option::of(any::<String>())
+        )
+            .prop_map(|(version, git_sha, build_timestamp, libcrun_version)| {
+                Response::Version(VersionProto {
+                    version,
+                    git_sha,
+                    build_timestamp,
+                    libcrun_version,
+                    min_protocol_version: MIN_PROTOCOL_VERSION,
+                    max_protocol_version: PROTOCOL_VERSION,
+                })
+            }),
+        LazyJust::new(|| Response::Paused),
+        LazyJust::new(|| Response::Unpaused),
+        (option::of(any::<i32>()), option::of(exit_reason())).prop_map(
+            |(exit_code, exit_reason)| Response::Exited(ExitStatusProto {
+                exit_code,
+                exit_reason
+            })
+        ),
+        vec(any::<u16>(), 0..16).prop_map(Response::ListeningPorts),
+        (any::<i64>(), any::<bool>()).prop_map(|(offset_ns, stepped)| {
+            Response::TimeSynced(TimeSyncProto { offset_ns, stepped })
+        }),
+        (
+            checkpoint(),
+            vec(
+                (any::<String>(), any::<u64>())
+                    .prop_map(|(path, size)| CheckpointFileProto { path, size }),
+                0..4
+            )
+        )
+            .prop_map(|(checkpoint, files)| {
+                Response::CheckpointFiles(CheckpointFilesProto { checkpoint, files })
+            }),
+        vec(any::<u8>(), 0..256).prop_map(Response::CheckpointData),
+        LazyJust::new(|| Response::CheckpointFileWritten),
+        vec(id(), 0..4).prop_map(Response::Pruned),
+        vec(any::<String>(), 0..4).prop_map(Response::Resolved),
+        (
+            vec(any::<String>(), 0..4),
+            any::<String>(),
+            vec(
+                (any::<String>(), vec(any::<u8>(), 0..64))
+                    .prop_map(|(path, contents)| StateFileProto { path, contents }),
+                0..4
+            )
+        )
+            .prop_map(|(agent_log, kernel_log, state_files)| {
+                Response::Diagnostics(DiagnosticsProto {
+                    agent_log,
+                    kernel_log,
+                    state_files,
+                })
+            }),
+        vec(event(), 0..4).prop_map(Response::Events),
+        (any::<u64>(), id(), option::of(0u8..=100), any::<String>()).prop_map(
+            |(request_id, phase, percent, message)| {
+                Response::Progress(ProgressProto {
+                    request_id,
+                    phase,
+                    percent,
+                    message,
+                })
+            }
+        ),
+        vec(
+            (
+                prop_oneof![
+                    Just(StaleResourceKind::State),
+                    Just(StaleResourceKind::Cgroup),
+                    Just(StaleResourceKind::Snapshot),
+                    Just(StaleResourceKind::Interface),
+                ],
+                id(),
+                any::<String>()
+            )
+                .prop_map(|(kind, id, path)| StaleResourceProto { kind, id, path }),
+            0..4
+        )
+        .prop_map(Response::StaleResources),
+        LazyJust::new(|| Response::Renamed),
+        LazyJust::new(|| Response::Undrained),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn request_roundtrip(request in request()) {
+        let bytes = serialize_request(&request).unwrap();
+        let decoded = deserialize_request(&bytes).unwrap();
+        prop_assert_eq!(serialize_request(&decoded).unwrap(), bytes);
+    }
+
+    #[test]
+    fn response_roundtrip(response in response()) {
+        let bytes = serialize_response(&response).unwrap();
+        let decoded = deserialize_response(&bytes).unwrap();
+        prop_assert_eq!(serialize_response(&decoded).unwrap(), bytes);
+    }
+
+    #[test]
+    fn copy_frame_roundtrip(frame in copy_frame()) {
+        let bytes = serialize_copy_frame(&frame).unwrap();
+        let decoded = deserialize_copy_frame(&bytes).unwrap();
+        prop_assert_eq!(decoded, frame);
+        for len in 0..bytes.len() {
+            prop_assert!(deserialize_copy_frame(&bytes[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn exec_frame_roundtrip(frame in exec_frame()) {
+        let bytes = serialize_exec_frame(&frame).unwrap();
+        let decoded = deserialize_exec_frame(&bytes).unwrap();
+        prop_assert_eq!(decoded, frame);
+        for len in 0..bytes.len() {
+            prop_assert!(deserialize_exec_frame(&bytes[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn truncated_messages_are_rejected(request in request(), response in response()) {
+        // Every message has at least one byte, so a strict prefix is always incomplete
+        let bytes = serialize_request(&request).unwrap();
+        for len in 0..bytes.len() {
+            prop_assert!(deserialize_request(&bytes[..len]).is_err());
+        }
+        let bytes = serialize_response(&response).unwrap();
+        for len in 0..bytes.len() {
+            prop_assert!(deserialize_response(&bytes[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn arbitrary_bytes_never_panic(data in vec(any::<u8>(), 0..512)) {
+        let _ = deserialize_request(&data);
+        let _ = deserialize_response(&data);
+    }
+
+    #[test]
+    fn corrupted_messages_never_panic(
+        request in request(),
+        flips in vec((any::<prop::sample::Index>(), any::<u8>()), 1..8),
+    ) {
+        let mut bytes = serialize_request(&request).unwrap();
+        for (index, value) in flips {
+            let i = index.index(bytes.len());
+            bytes[i] ^= value;
+        }
+        let _ = deserialize_request(&bytes);
+        let _ = deserialize_response(&bytes);
+    }
+
+    #[test]
+    fn frames_roundtrip(payloads in vec(vec(any::<u8>(), 0..1024), 0..8)) {
+        let mut stream = Vec::new();
+        for payload in &payloads {
+            write_frame(&mut stream, payload).unwrap();
+        }
+        let mut cursor = Cursor::new(stream);
+        for payload in &payloads {
+            prop_assert_eq!(read_frame(&mut cursor).unwrap(), Some(payload.clone()));
+        }
+        prop_assert!(read_frame(&mut cursor).unwrap().is_none());
+    }
+
+    #[test]
+    fn truncated_frames_are_errors(payload in vec(any::<u8>(), 1..1024), cut in any::<prop::sample::Index>()) {
+        let mut stream = Vec::new();
+        write_frame(&mut stream, &payload).unwrap();
+        // Cutting inside the header or the payload never yields a short frame
+        let len = 1 + cut.index(stream.len() - 1);
+        let result = read_frame(&mut Cursor::new(&stream[..len]));
+        prop_assert!(result.is_err(), "{} of {} bytes: {:?}", len, stream.len(), result);
+    }
+
+    #[test]
+    fn arbitrary_streams_never_panic(data in vec(any::<u8>(), 0..2048)) {
+        let mut cursor = Cursor::new(data);
+        while let Ok(Some(frame)) = read_frame(&mut cursor) {
+            let _ = deserialize_request(&frame);
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! A real agent process on a private socket
+
+use libcrun_shim_proto::*;
+use std::io;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+const AGENT_NAME: &str = "libcrun-shim-agent";
+
+/// How long to wait for a new agent to listen
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Distinguishes agents started by one test process
+static NEXT_AGENT: AtomicUsize = AtomicUsize::new(0);
+
+/// Path of the agent binary to test against
+///
+/// `LIBCRUN_SHIM_AGENT` if set, otherwise the agent built into the same
+/// target directory as the running test (`cargo build -p libcrun-shim-agent`).
+pub fn agent_binary() -> io::Result<PathBuf> {
+    if let Some(path) = std::env::var_os("LIBCRUN_SHIM_AGENT") {
+        return Ok(PathBuf::from(path));
+    }
+    // Test executables live in target/<profile>/deps
+    let exe = std::env::current_exe()?;
+    exe.ancestors()
+        .skip(1)
+        .take(2)
+        .map(|dir| dir.join(AGENT_NAME))
+        .find(|path| path.is_file())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "{} not found next to {} (build it or set LIBCRUN_SHIM_AGENT)",
+                    AGENT_NAME,
+                    exe.display()
+                ),
+            )
+        })
+}
+
+/// An agent listening on a socket in its own temporary directory
+///
+/// The agent is killed and the directory removed on drop. Its stderr is kept
+/// in the directory and printed when it fails to start, see [`TestAgent::log`].
+pub struct TestAgent {
+    child: Child,
+    dir: PathBuf,
+    socket: PathBuf,
+    stream: Option<UnixStream>,
+}
+
+impl TestAgent {
+    /// Start the agent from [`agent_binary`]
+    pub fn spawn() -> io::Result<Self> {
+        Self::spawn_with(&agent_binary()?, &[])
+    }
+
+    /// Start `binary` with extra arguments after `--socket` and `--pid-file`
+    pub fn spawn_with(binary: &Path, args: &[&str]) -> io::Result<Self> {
+        let dir = std::env::temp_dir().join(format!(
+            "libcrun-shim-test-{}-{}",
+            std::process::id(),
+            NEXT_AGENT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&dir)?;
+        let socket = dir.join("agent.sock");
+        let log = std::fs::File::create(dir.join("agent.log"))?;
+
+        // Its own pid file, so agents of concurrent tests do not exclude
+        // each other
+        let child = Command::new(binary)
+            .arg("--socket")
+            .arg(&socket)
+            .arg("--pid-file")
+            .arg(dir.join("agent.pid"))
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(log.try_clone()?)
+            .stderr(log)
+            .spawn()?;
+        let mut agent = Self {
+            child,
+            dir,
+            socket,
+            stream: None,
+        };
+
+        let deadline = std::time::Instant::now() + STARTUP_TIMEOUT;
+        loop {
+            if let Some(status) = agent.child.try_wait()? {
+                return Err(io::Error::other(format!(
+                    "agent exited with {} during startup:\n{}",
+                    status,
+                    agent.log()
+                )));
+            }
+            if let Ok(stream) = UnixStream::connect(&agent.socket) {
+                agent.stream = Some(stream);
+                return Ok(agent);
+            }
+            if std::time::Instant::now() >= deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("agent did not listen in time:\n{}", agent.log()),
+                ));
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    /// Send one request and wait for its response
+    pub fn call(&mut self, request: Request) -> io::Result<Response> {
+        let stream = match self.stream.as_mut() {
+            Some(stream) => stream,
+            None => self.stream.insert(UnixStream::connect(&self.socket)?),
+        };
+        let payload = serialize_request(&request)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        let result = write_frame(stream, &payload).and_then(|_| {
+            read_frame(stream)?.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::UnexpectedEof, "agent closed the connection")
+            })
+        });
+        let frame = match result {
+            Ok(frame) => frame,
+            Err(e) => {
+                // Reconnect on the next call
+                self.stream = None;
+                return Err(e);
+            }
+        };
+        deserialize_response(&frame)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    }
+
+    /// Directory holding the socket and log, removed on drop
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn socket_path(&self) -> &Path {
+        &self.socket
+    }
+
+    /// Pid file locked by the agent, see its `--pid-file`
+    pub fn pid_file(&self) -> PathBuf {
+        self.dir.join("agent.pid")
+    }
+
+    /// What the agent has logged so far
+    pub fn log(&self) -> String {
+        std::fs::read_to_string(self.dir.join("agent.log")).unwrap_or_default()
+    }
+}
+
+impl Drop for TestAgent {
+    fn drop(&mut self) {
+        self.stream = None;
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
@@ -0,0 +1,137 @@
+//! Test support for code built on libcrun-shim
+//!
+//! Runs a real `libcrun-shim-agent` on a private Unix socket and talks to it
+//! with the same framed protocol the runtime uses, and builds throwaway
+//! busybox root filesystems to run containers from. Tests that need a real
+//! container call [`skip_reason`] first and return early when the machine
+//! can't run one, so the same suite passes on laptops and exercises the full
+//! lifecycle on CI. The [`perf`] module times the operations the
+//! benchmarks and performance budgets cover.
+//!
+//! ```no_run
+//! use libcrun_shim_test_support::*;
+//!
+//! if let Some(reason) = skip_reason() {
+//!     eprintln!("skipping: {}", reason);
+//!     return;
+//! }
+//! let mut agent = TestAgent::spawn().unwrap();
+//! let rootfs = busybox_rootfs(&agent.dir().join("rootfs")).unwrap();
+//! let request = create_request("demo", &rootfs, &["sleep", "60"]);
+//! agent.call(Request::Create(request)).unwrap();
+//! ```
+
+mod agent;
+pub mod perf;
+mod rootfs;
+
+pub use agent::{agent_binary, TestAgent};
+pub use libcrun_shim_proto::*;
+pub use rootfs::{busybox_rootfs, find_busybox, BUSYBOX_APPLETS};
+
+use std::path::Path;
+
+/// Why containers can't run here, or `None` if they can
+///
+/// Needs root, a usable libcrun, the agent binary (see [`agent_binary`]) and
+/// a static busybox (see [`find_busybox`]).
+pub fn skip_reason() -> Option<String> {
+    if unsafe { libc::geteuid() } != 0 {
+        return Some("containers need root".to_string());
+    }
+    if !libcrun_available() {
+        return Some("libcrun is not available".to_string());
+    }
+    if let Err(e) = agent_binary() {
+        return Some(e.to_string());
+    }
+    if find_busybox().is_none() {
+        return Some("no static busybox found (set LIBCRUN_TEST_BUSYBOX)".to_string());
+    }
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn libcrun_available() -> bool {
+    libcrun_sys::safe::Context::new().is_ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn libcrun_available() -> bool {
+    false
+}
+
+/// A create request for `command` in `rootfs` with everything else defaulted
+///
+/// Output is captured (no TTY) so it can be read back with [`Request::Logs`].
+pub fn create_request(id: &str, rootfs: &Path, command: &[&str]) -> CreateRequest {
+    CreateRequest {
+        id: id.to_string(),
+        name: String::new(),
+        rootfs: rootfs.to_string_lossy().into_owned(),
+        command: command.iter().map(|s| s.to_string()).collect(),
+        env: vec!["PATH=/bin".to_string()],
+        working_dir: "/".to_string(),
+        stdio: StdioConfigProto::default(),
+        network: NetworkConfigProto {
+            mode: "none".to_string(),
+            ..Default::default()
+        },
+        volumes: Vec::new(),
+        resources: ResourceLimitsProto::default(),
+        health_check: None,
+        security: SecurityProto::default(),
+        image: None,
+        max_runtime_secs: None,
+        auto_stop: None,
+        join_namespaces: None,
+        restart_policy: RestartPolicy::No,
+        rootfs_snapshot: false,
+        read_only_rootfs: false,
+        labels: Default::default(),
+        stop_timeout: None,
+        create_working_dir: false,
+    }
+}
+
+/// Call `f` until it returns `Some` or `timeout` passes
+pub fn wait_for<T>(timeout: std::time::Duration, mut f: impl FnMut() -> Option<T>) -> Option<T> {
+    let deadline = std::time::Instant::now() + timeout;
+    loop {
+        if let Some(value) = f() {
+            return Some(value);
+        }
+        if std::time::Instant::now() >= deadline {
+            return None;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_request() {
+        let request = create_request("c1", Path::new("/tmp/rootfs"), &["sh", "-c", "true"]);
+        assert_eq!(request.rootfs, "/tmp/rootfs");
+        assert_eq!(request.command, ["sh", "-c", "true"]);
+        assert!(!request.stdio.tty);
+
+        // Round-trips through the wire format the agent reads
+        let bytes = serialize_request(&Request::Create(request)).unwrap();
+        assert!(matches!(
+            deserialize_request(&bytes),
+            Ok(Request::Create(r)) if r.id == "c1"
+        ));
+
+        let mut calls = 0;
+        let found = wait_for(std::time::Duration::from_secs(1), || {
+            calls += 1;
+            (calls == 3).then_some(calls)
+        });
+        assert_eq!(found, Some(3));
+        assert_eq!(wait_for(std::time::Duration::ZERO, || None::<()>), None);
+    }
+}
@@ -0,0 +1,197 @@
+//! Timed container operations and the budgets they must stay within
+//!
+//! The Criterion benchmarks in `tests/integration/benches` and the budget
+//! tests of the integration suite time the same operations, so a slowdown a
+//! benchmark shows fails CI once it is over budget. Budgets leave room for a
+//! debug agent on a shared CI runner; `LIBCRUN_BUDGET_SCALE` multiplies them
+//! on slower machines.
+
+use crate::{create_request, wait_for, TestAgent};
+use libcrun_shim_proto::*;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Containers listed and measured at once by the fleet operations
+pub const FLEET_SIZE: usize = 100;
+
+/// Init process that idles until it gets SIGTERM
+pub const IDLE: &[&str] = &["sh", "-c", "trap 'exit 0' TERM; while :; do sleep 1; done"];
+
+/// How long an operation may take, at the median
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Budget {
+    pub operation: &'static str,
+    pub limit: Duration,
+}
+
+/// Creating a container and starting its init process
+pub const CREATE_START: Budget = Budget {
+    operation: "create and start",
+    limit: Duration::from_secs(2),
+};
+
+/// Running `true` in a running container and reading its result
+pub const EXEC: Budget = Budget {
+    operation: "exec round trip",
+    limit: Duration::from_millis(500),
+};
+
+/// Listing [`FLEET_SIZE`] running containers
+pub const LIST: Budget = Budget {
+    operation: "list",
+    limit: Duration::from_millis(100),
+};
+
+/// Metrics of [`FLEET_SIZE`] running containers in one request
+pub const ALL_METRICS: Budget = Budget {
+    operation: "all metrics",
+    limit: Duration::from_secs(1),
+};
+
+impl Budget {
+    /// The limit, multiplied by `LIBCRUN_BUDGET_SCALE` if set
+    pub fn limit(&self) -> Duration {
+        self.limit
+            .mul_f64(scale(std::env::var("LIBCRUN_BUDGET_SCALE").ok()))
+    }
+
+    /// The median of `samples`, or why it is over budget
+    pub fn check(&self, samples: &[Duration]) -> Result<Duration, String> {
+        let median = median(samples);
+        let limit = self.limit();
+        if median > limit {
+            return Err(format!(
+                "{} took {:?} at the median of {} samples, over its budget of {:?}",
+                self.operation,
+                median,
+                samples.len(),
+                limit
+            ));
+        }
+        Ok(median)
+    }
+}
+
+fn scale(value: Option<String>) -> f64 {
+    value
+        .and_then(|v| v.parse::<f64>().ok())
+        .filter(|scale| scale.is_finite() && *scale > 0.0)
+        .unwrap_or(1.0)
+}
+
+/// The middle sample, zero if there are none
+pub fn median(samples: &[Duration]) -> Duration {
+    let mut sorted = samples.to_vec();
+    sorted.sort();
+    sorted.get(sorted.len() / 2).copied().unwrap_or_default()
+}
+
+fn unexpected(operation: &str, response: Response) -> io::Error {
+    io::Error::other(format!("{}: unexpected response {:?}", operation, response))
+}
+
+/// Create container `id` running [`IDLE`] in `rootfs` and start it, timing
+/// both requests
+pub fn create_started(agent: &mut TestAgent, id: &str, rootfs: &Path) -> io::Result<Duration> {
+    let started = Instant::now();
+    match agent.call(Request::Create(create_request(id, rootfs, IDLE)))? {
+        Response::Created(_) => {}
+        other => return Err(unexpected("create", other)),
+    }
+    match agent.call(Request::Start(id.to_string()))? {
+        Response::Started => Ok(started.elapsed()),
+        other => Err(unexpected("start", other)),
+    }
+}
+
+/// Stop container `id` and delete it once its init process has exited
+pub fn remove(agent: &mut TestAgent, id: &str) -> io::Result<()> {
+    match agent.call(Request::Stop(id.to_string()))? {
+        Response::Stopped => {}
+        other => return Err(unexpected("stop", other)),
+    }
+    wait_for(Duration::from_secs(10), || {
+        matches!(
+            agent.call(Request::Delete(id.to_string())),
+            Ok(Response::Deleted)
+        )
+        .then_some(())
+    })
+    .ok_or_else(|| io::Error::new(io::ErrorKind::TimedOut, format!("{} was never deleted", id)))
+}
+
+/// Run `true` in container `id`, timing the round trip
+pub fn exec_true(agent: &mut TestAgent, id: &str) -> io::Result<Duration> {
+    let started = Instant::now();
+    let response = agent.call(Request::Exec(ExecRequest {
+        id: id.to_string(),
+        command: vec!["true".to_string()],
+        env: Vec::new(),
+        working_dir: None,
+        user: "bench".to_string(),
+        tty: false,
+        uid: None,
+        gid: None,
+    }))?;
+    match response {
+        Response::Exec(result) if result.exit_code == 0 => Ok(started.elapsed()),
+        other => Err(unexpected("exec", other)),
+    }
+}
+
+/// Start [`FLEET_SIZE`] containers named `<prefix>-<n>`, returning their IDs
+pub fn start_fleet(agent: &mut TestAgent, prefix: &str, rootfs: &Path) -> io::Result<Vec<String>> {
+    let mut ids = Vec::with_capacity(FLEET_SIZE);
+    for n in 1..=FLEET_SIZE {
+        let id = format!("{}-{}", prefix, n);
+        create_started(agent, &id, rootfs)?;
+        ids.push(id);
+    }
+    Ok(ids)
+}
+
+/// Time listing all containers, checking that there are at least `expected`
+pub fn list(agent: &mut TestAgent, expected: usize) -> io::Result<Duration> {
+    let started = Instant::now();
+    match agent.call(Request::List)? {
+        Response::List(containers) if containers.len() >= expected => Ok(started.elapsed()),
+        other => Err(unexpected("list", other)),
+    }
+}
+
+/// Time the metrics of all containers, checking that there are at least
+/// `expected`
+pub fn all_metrics(agent: &mut TestAgent, expected: usize) -> io::Result<Duration> {
+    let started = Instant::now();
+    match agent.call(Request::AllMetrics)? {
+        Response::AllMetrics(metrics) if metrics.len() >= expected => Ok(started.elapsed()),
+        other => Err(unexpected("all metrics", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget_check() {
+        let ms = Duration::from_millis;
+        assert_eq!(median(&[ms(30), ms(10), ms(20)]), ms(20));
+        assert_eq!(median(&[]), Duration::ZERO);
+
+        let budget = Budget {
+            operation: "op",
+            limit: ms(25),
+        };
+        // One slow outlier does not fail the budget
+        assert_eq!(budget.check(&[ms(10), ms(20), ms(900)]), Ok(ms(20)));
+        let err = budget.check(&[ms(30), ms(40), ms(10)]).unwrap_err();
+        assert!(err.starts_with("op took 30ms"), "{}", err);
+
+        assert_eq!(scale(None), 1.0);
+        assert_eq!(scale(Some("2.5".to_string())), 2.5);
+        assert_eq!(scale(Some("0".to_string())), 1.0);
+        assert_eq!(scale(Some("fast".to_string())), 1.0);
+    }
+}
@@ -0,0 +1,66 @@
+//! Throwaway busybox root filesystems
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Applets linked into [`busybox_rootfs`]
+pub const BUSYBOX_APPLETS: &[&str] = &[
+    "sh", "sleep", "true", "false", "echo", "cat", "ls", "ps", "kill", "env", "id",
+];
+
+/// Whether an ELF binary has no program interpreter
+fn is_static(binary: &[u8]) -> bool {
+    binary.starts_with(b"\x7fELF") && !binary.windows(8).any(|w| w == b".interp\0")
+}
+
+/// A statically linked busybox usable inside a bare rootfs
+///
+/// Uses `LIBCRUN_TEST_BUSYBOX` if set, otherwise the usual install paths.
+/// Dynamically linked builds are skipped since the rootfs has no libc.
+pub fn find_busybox() -> Option<PathBuf> {
+    let candidates = std::env::var_os("LIBCRUN_TEST_BUSYBOX")
+        .map(|p| vec![PathBuf::from(p)])
+        .unwrap_or_else(|| {
+            ["/bin/busybox", "/usr/bin/busybox", "/usr/sbin/busybox"]
+                .iter()
+                .map(PathBuf::from)
+                .collect()
+        });
+    candidates
+        .into_iter()
+        .find(|path| std::fs::read(path).is_ok_and(|binary| is_static(&binary)))
+}
+
+/// Build a minimal rootfs at `dir` with busybox and [`BUSYBOX_APPLETS`] in `/bin`
+pub fn busybox_rootfs(dir: &Path) -> io::Result<PathBuf> {
+    let busybox = find_busybox()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no static busybox found"))?;
+
+    let bin = dir.join("bin");
+    std::fs::create_dir_all(&bin)?;
+    for mountpoint in ["proc", "dev", "sys", "tmp", "etc"] {
+        std::fs::create_dir_all(dir.join(mountpoint))?;
+    }
+    std::fs::copy(&busybox, bin.join("busybox"))?;
+    for applet in BUSYBOX_APPLETS {
+        let link = bin.join(applet);
+        if !link.exists() {
+            std::os::unix::fs::symlink("busybox", link)?;
+        }
+    }
+    std::fs::write(dir.join("etc/passwd"), "root:x:0:0:root:/:/bin/sh\n")?;
+    std::fs::write(dir.join("etc/group"), "root:x:0:\n")?;
+    Ok(dir.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_static() {
+        assert!(is_static(b"\x7fELF\x02\x01 static"));
+        assert!(!is_static(b"\x7fELF\x02\x01 .interp\0 dynamic"));
+        assert!(!is_static(b"#!/bin/sh\n"));
+    }
+}
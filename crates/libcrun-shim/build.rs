@@ -3,6 +3,10 @@ use std::path::Path;
 use std::process::Command;
 
 fn main() {
+    emit_build_info();
+    #[cfg(feature = "cri")]
+    compile_cri_protos();
+
     // Only build Swift bridge on macOS
     let target_os = env::var("CARGO_CFG_TARGET_OS").unwrap_or_default();
     if target_os != "macos" {
@@ -100,3 +104,55 @@ fn main() {
 
     println!("cargo:warning=Swift VM bridge compiled successfully");
 }
+
+/// Generate the CRI v1 types, gRPC services and clients, see `cri::v1`
+///
+/// protoc comes from protoc-bin-vendored so that building with the "cri"
+/// feature does not need it installed.
+#[cfg(feature = "cri")]
+fn compile_cri_protos() {
+    let proto_dir = Path::new("proto").join("cri-api").join("v1");
+    let proto = proto_dir.join("api.proto");
+    println!("cargo:rerun-if-changed={}", proto.display());
+
+    if env::var_os("PROTOC").is_none() {
+        let protoc =
+            protoc_bin_vendored::protoc_bin_path().expect("No vendored protoc for this host");
+        env::set_var("PROTOC", protoc);
+    }
+    tonic_build::configure()
+        .compile(&[&proto], &[&proto_dir])
+        .expect("Failed to compile the CRI protos");
+}
+
+/// Pass the git commit and the build time to the crate as `GIT_SHA` and
+/// `BUILD_TIMESTAMP` (Unix seconds, `SOURCE_DATE_EPOCH` if set)
+fn emit_build_info() {
+    let git = |args: &[&str]| {
+        let output = Command::new("git").args(args).output().ok()?;
+        let stdout = String::from_utf8(output.stdout).ok()?;
+        output.status.success().then(|| stdout.trim().to_string())
+    };
+    if let Some(sha) = git(&["rev-parse", "--short=12", "HEAD"]) {
+        println!("cargo:rustc-env=GIT_SHA={}", sha);
+        // Rebuild when another commit is checked out or the branch moves
+        let branch = git(&["symbolic-ref", "-q", "HEAD"]);
+        for name in std::iter::once("HEAD").chain(branch.as_deref()) {
+            if let Some(path) = git(&["rev-parse", "--git-path", name]) {
+                println!("cargo:rerun-if-changed={}", path);
+            }
+        }
+    }
+
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    let timestamp = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+        });
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", timestamp);
+}
@@ -0,0 +1,537 @@
+//! Registry credentials
+//!
+//! Pulls use an explicit [`AuthConfig`] when given one, and otherwise the
+//! credentials in the Docker CLI's `config.json`, so a `docker login` works
+//! for this runtime as well. Both the `auths` section and credential helpers
+//! (`credsStore` and `credHelpers`, e.g. `osxkeychain` or `ecr-login`) are
+//! read; `crun-shim login` writes to the same places `docker login` would.
+
+use crate::error::{Result, ShimError};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Key Docker Hub credentials are stored under, for compatibility with the
+/// Docker CLI
+const DOCKER_HUB_SERVER: &str = "https://index.docker.io/v1/";
+
+/// User name credential helpers report for an identity token
+const TOKEN_USERNAME: &str = "<token>";
+
+/// Credentials for a registry
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct AuthConfig {
+    #[serde(default)]
+    pub username: Option<String>,
+    /// Password, or an access token the registry accepts in its place
+    #[serde(default)]
+    pub password: Option<String>,
+    /// OAuth2 refresh token, exchanged at the registry's token service for
+    /// access tokens
+    #[serde(default)]
+    pub identity_token: Option<String>,
+    /// Bearer token sent to the registry as is
+    #[serde(default)]
+    pub registry_token: Option<String>,
+}
+
+impl AuthConfig {
+    /// Credentials of `username` with `password`
+    pub fn basic(username: impl Into<String>, password: impl Into<String>) -> Self {
+        Self {
+            username: Some(username.into()),
+            password: Some(password.into()),
+            ..Default::default()
+        }
+    }
+
+    /// `username` and `password` for HTTP basic authentication, if both are set
+    pub(crate) fn user_password(&self) -> Option<(&str, &str)> {
+        Some((self.username.as_deref()?, self.password.as_deref()?))
+    }
+}
+
+/// A registry's answer to an unauthenticated request, from its
+/// `WWW-Authenticate` header
+#[cfg(feature = "image-pull")]
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Challenge {
+    /// Send the credentials with every request
+    Basic,
+    /// Get a token for the scope from the token service at `realm`
+    Bearer {
+        realm: String,
+        service: Option<String>,
+    },
+}
+
+#[cfg(feature = "image-pull")]
+impl Challenge {
+    /// Parse a `WWW-Authenticate` header value such as
+    /// `Bearer realm="https://auth.docker.io/token",service="registry.docker.io"`
+    pub(crate) fn parse(header: &str) -> Option<Self> {
+        let (scheme, params) = header.trim().split_once(' ').unwrap_or((header.trim(), ""));
+        if scheme.eq_ignore_ascii_case("basic") {
+            return Some(Self::Basic);
+        }
+        if !scheme.eq_ignore_ascii_case("bearer") {
+            return None;
+        }
+        let params = auth_params(params);
+        Some(Self::Bearer {
+            realm: params.get("realm")?.clone(),
+            service: params.get("service").cloned(),
+        })
+    }
+}
+
+/// `key=value` pairs of a challenge, whose quoted values may contain commas
+#[cfg(feature = "image-pull")]
+fn auth_params(params: &str) -> HashMap<String, String> {
+    let mut parsed = HashMap::new();
+    let mut rest = params.trim();
+    while let Some((key, after)) = rest.split_once('=') {
+        let key = key
+            .trim()
+            .trim_start_matches(',')
+            .trim()
+            .to_ascii_lowercase();
+        let (value, after) = match after.strip_prefix('"') {
+            Some(quoted) => match quoted.split_once('"') {
+                Some((value, after)) => (value, after),
+                None => (quoted, ""),
+            },
+            None => after.split_once(',').unwrap_or((after, "")),
+        };
+        parsed.insert(key, value.trim().to_string());
+        rest = after.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+    }
+    parsed
+}
+
+/// Host a registry is known by in `config.json`, with the Docker Hub
+/// aliases folded into `docker.io`
+fn registry_host(server: &str) -> String {
+    let host = server
+        .trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .split('/')
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match host.as_str() {
+        "index.docker.io" | "registry-1.docker.io" | "registry.docker.io" => {
+            "docker.io".to_string()
+        }
+        _ => host,
+    }
+}
+
+/// Key the credentials of `registry` are stored under
+fn server_key(registry: &str) -> String {
+    match registry_host(registry).as_str() {
+        "docker.io" => DOCKER_HUB_SERVER.to_string(),
+        host => host.to_string(),
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ConfigFile {
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    auths: HashMap<String, AuthEntry>,
+    #[serde(
+        rename = "credsStore",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    creds_store: Option<String>,
+    #[serde(
+        rename = "credHelpers",
+        default,
+        skip_serializing_if = "HashMap::is_empty"
+    )]
+    cred_helpers: HashMap<String, String>,
+    /// Settings of the Docker CLI this runtime does not use, kept on save
+    #[serde(flatten)]
+    other: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct AuthEntry {
+    /// Base64 of `username:password`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    auth: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    identitytoken: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    registrytoken: Option<String>,
+    #[serde(flatten)]
+    other: serde_json::Map<String, serde_json::Value>,
+}
+
+impl AuthEntry {
+    fn to_auth_config(&self) -> Result<AuthConfig> {
+        let mut auth = AuthConfig {
+            identity_token: self.identitytoken.clone(),
+            registry_token: self.registrytoken.clone(),
+            ..Default::default()
+        };
+        if let Some(ref encoded) = self.auth {
+            let decoded = BASE64
+                .decode(encoded.trim())
+                .ok()
+                .and_then(|decoded| String::from_utf8(decoded).ok())
+                .ok_or_else(|| ShimError::validation("auth", "Invalid base64 credentials"))?;
+            let (username, password) = decoded.split_once(':').ok_or_else(|| {
+                ShimError::validation("auth", "Credentials are not username:password")
+            })?;
+            auth.username = Some(username.to_string());
+            auth.password = Some(password.to_string());
+        }
+        Ok(auth)
+    }
+}
+
+/// Credentials as credential helpers read and write them
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct HelperCredentials {
+    #[serde(rename = "ServerURL", default)]
+    server_url: String,
+    username: String,
+    secret: String,
+}
+
+/// The Docker CLI's `config.json`
+#[derive(Debug)]
+pub struct DockerConfig {
+    path: PathBuf,
+    file: ConfigFile,
+}
+
+impl DockerConfig {
+    /// `$DOCKER_CONFIG/config.json`, by default `~/.docker/config.json`
+    pub fn default_path() -> PathBuf {
+        std::env::var_os("DOCKER_CONFIG")
+            .map(PathBuf::from)
+            .or_else(|| dirs::home_dir().map(|home| home.join(".docker")))
+            .unwrap_or_else(|| PathBuf::from(".docker"))
+            .join("config.json")
+    }
+
+    /// Load the configuration at [`Self::default_path`]
+    pub fn load() -> Result<Self> {
+        Self::load_from(Self::default_path())
+    }
+
+    /// Load the configuration at `path`, which is empty if the file is missing
+    pub fn load_from(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let file = match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data).map_err(|e| {
+                ShimError::serialization(format!("Failed to parse {}", path.display()), e)
+            })?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => ConfigFile::default(),
+            Err(e) => {
+                return Err(ShimError::io_with_context(
+                    e,
+                    format!("Failed to read {}", path.display()),
+                ))
+            }
+        };
+        Ok(Self { path, file })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Credential helper for `registry`, if its credentials are kept in one
+    fn helper(&self, registry: &str) -> Option<&str> {
+        let host = registry_host(registry);
+        self.file
+            .cred_helpers
+            .iter()
+            .find(|(server, _)| registry_host(server) == host)
+            .map(|(_, helper)| helper)
+            .or(self.file.creds_store.as_ref())
+            .map(String::as_str)
+            .filter(|helper| !helper.is_empty())
+    }
+
+    /// Stored credentials for `registry`, e.g. "docker.io" or "ghcr.io"
+    pub fn credentials(&self, registry: &str) -> Result<Option<AuthConfig>> {
+        if let Some(helper) = self.helper(registry) {
+            if let Some(auth) = helper_get(helper, &server_key(registry))? {
+                return Ok(Some(auth));
+            }
+        }
+        let host = registry_host(registry);
+        self.file
+            .auths
+            .iter()
+            .find(|(server, _)| registry_host(server) == host)
+            .map(|(_, entry)| entry.to_auth_config())
+            .transpose()
+    }
+
+    /// Store `auth` for `registry` and save the file
+    ///
+    /// Like `docker login`, the credentials go to the credential helper if
+    /// the configuration names one, and into the file otherwise.
+    pub fn store(&mut self, registry: &str, auth: &AuthConfig) -> Result<()> {
+        let server = server_key(registry);
+        let entry = if let Some(helper) = self.helper(registry) {
+            let (username, secret) =
+                match (auth.user_password(), &auth.identity_token) {
+                    (_, Some(token)) => (TOKEN_USERNAME, token.as_str()),
+                    (Some(credentials), None) => credentials,
+                    (None, None) => return Err(ShimError::validation(
+                        "auth",
+                        "A credential helper needs a username and password or an identity token",
+                    )),
+                };
+            run_helper(
+                helper,
+                "store",
+                &serde_json::to_vec(&HelperCredentials {
+                    server_url: server.clone(),
+                    username: username.to_string(),
+                    secret: secret.to_string(),
+                })
+                .map_err(|e| ShimError::serialization("Failed to encode credentials", e))?,
+            )?;
+            // Docker leaves an empty entry behind to list the registry
+            AuthEntry::default()
+        } else {
+            AuthEntry {
+                auth: auth
+                    .user_password()
+                    .map(|(username, password)| BASE64.encode(format!("{username}:{password}"))),
+                identitytoken: auth.identity_token.clone(),
+                registrytoken: auth.registry_token.clone(),
+                ..Default::default()
+            }
+        };
+        let host = registry_host(registry);
+        self.file
+            .auths
+            .retain(|existing, _| registry_host(existing) != host);
+        self.file.auths.insert(server, entry);
+        self.save()
+    }
+
+    /// Forget the credentials for `registry` and save the file, returning
+    /// whether there were any
+    pub fn remove(&mut self, registry: &str) -> Result<bool> {
+        let mut removed = false;
+        if let Some(helper) = self.helper(registry) {
+            match run_helper(helper, "erase", server_key(registry).as_bytes()) {
+                Ok(_) => removed = true,
+                Err(e) if e.is_not_found() => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let host = registry_host(registry);
+        let before = self.file.auths.len();
+        self.file
+            .auths
+            .retain(|existing, _| registry_host(existing) != host);
+        if self.file.auths.len() != before {
+            removed = true;
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    /// Write the file, readable by its owner only since it holds credentials
+    fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| {
+                ShimError::io_with_context(e, format!("Failed to create {}", parent.display()))
+            })?;
+        }
+        let data = serde_json::to_vec_pretty(&self.file)
+            .map_err(|e| ShimError::serialization("Failed to encode config.json", e))?;
+        let partial = self.path.with_extension("json.partial");
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let written = options
+            .open(&partial)
+            .and_then(|mut file| file.write_all(&data))
+            .and_then(|_| std::fs::rename(&partial, &self.path));
+        written.map_err(|e| {
+            let _ = std::fs::remove_file(&partial);
+            ShimError::io_with_context(e, format!("Failed to write {}", self.path.display()))
+        })
+    }
+}
+
+/// Run `docker-credential-<helper> <action>` with `input` on its stdin
+///
+/// Fails with [`ShimError::NotFound`] when the helper has no credentials
+/// for the server.
+fn run_helper(helper: &str, action: &str, input: &[u8]) -> Result<Vec<u8>> {
+    let program = format!("docker-credential-{}", helper);
+    let mut child = Command::new(&program)
+        .arg(action)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| ShimError::io_with_context(e, format!("Failed to run {}", program)))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input).map_err(|e| {
+            ShimError::io_with_context(e, format!("Failed to write to {}", program))
+        })?;
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|e| ShimError::io_with_context(e, format!("Failed to run {}", program)))?;
+    if output.status.success() {
+        return Ok(output.stdout);
+    }
+    let message = [&output.stdout, &output.stderr]
+        .iter()
+        .map(|output| String::from_utf8_lossy(output).trim().to_string())
+        .filter(|output| !output.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    if message.contains("credentials not found") {
+        return Err(ShimError::not_found(format!("Credentials in {}", program)));
+    }
+    Err(ShimError::runtime_with_context(
+        format!("{} {} failed", program, action),
+        message,
+    ))
+}
+
+/// Credentials for `server` from a credential helper, if it has any
+fn helper_get(helper: &str, server: &str) -> Result<Option<AuthConfig>> {
+    let output = match run_helper(helper, "get", server.as_bytes()) {
+        Ok(output) => output,
+        Err(e) if e.is_not_found() => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let credentials: HelperCredentials = serde_json::from_slice(&output).map_err(|e| {
+        ShimError::serialization(format!("Failed to parse docker-credential-{}", helper), e)
+    })?;
+    Ok(Some(if credentials.username == TOKEN_USERNAME {
+        AuthConfig {
+            identity_token: Some(credentials.secret),
+            ..Default::default()
+        }
+    } else {
+        AuthConfig::basic(credentials.username, credentials.secret)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "image-pull")]
+    fn test_parse_challenge() {
+        assert_eq!(
+            Challenge::parse(
+                r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/alpine:pull,push""#
+            ),
+            Some(Challenge::Bearer {
+                realm: "https://auth.docker.io/token".to_string(),
+                service: Some("registry.docker.io".to_string()),
+            })
+        );
+        assert_eq!(
+            Challenge::parse("bearer realm=https://ghcr.io/token, service=ghcr.io"),
+            Some(Challenge::Bearer {
+                realm: "https://ghcr.io/token".to_string(),
+                service: Some("ghcr.io".to_string()),
+            })
+        );
+        assert_eq!(
+            Challenge::parse(r#"Basic realm="https://123.dkr.ecr.us-east-1.amazonaws.com/""#),
+            Some(Challenge::Basic)
+        );
+        assert_eq!(Challenge::parse(r#"Bearer service="x""#), None);
+        assert_eq!(Challenge::parse("Negotiate"), None);
+    }
+
+    #[test]
+    fn test_server_key() {
+        assert_eq!(server_key("docker.io"), DOCKER_HUB_SERVER);
+        assert_eq!(server_key("https://index.docker.io/v1/"), DOCKER_HUB_SERVER);
+        assert_eq!(server_key("GHCR.io"), "ghcr.io");
+        assert_eq!(server_key("https://localhost:5000/v2/"), "localhost:5000");
+    }
+
+    #[test]
+    fn test_docker_config() {
+        let dir = std::env::temp_dir().join(format!("docker-config-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("config.json");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            &path,
+            r#"{
+                "auths": {
+                    "https://index.docker.io/v1/": {"auth": "dXNlcjpwYXNzOndvcmQ="},
+                    "ghcr.io": {"identitytoken": "refresh"}
+                },
+                "detachKeys": "ctrl-e,e"
+            }"#,
+        )
+        .unwrap();
+
+        let mut config = DockerConfig::load_from(&path).unwrap();
+        assert_eq!(
+            config.credentials("docker.io").unwrap(),
+            Some(AuthConfig::basic("user", "pass:word"))
+        );
+        assert_eq!(
+            config
+                .credentials("ghcr.io")
+                .unwrap()
+                .unwrap()
+                .identity_token
+                .as_deref(),
+            Some("refresh")
+        );
+        assert_eq!(config.credentials("quay.io").unwrap(), None);
+
+        config
+            .store("quay.io", &AuthConfig::basic("robot", "secret"))
+            .unwrap();
+        assert!(config.remove("docker.io").unwrap());
+        assert!(!config.remove("docker.io").unwrap());
+
+        let config = DockerConfig::load_from(&path).unwrap();
+        assert_eq!(
+            config.credentials("quay.io").unwrap(),
+            Some(AuthConfig::basic("robot", "secret"))
+        );
+        assert_eq!(config.credentials("docker.io").unwrap(), None);
+        assert_eq!(config.file.other["detachKeys"], "ctrl-e,e");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        assert_eq!(
+            DockerConfig::load_from(dir.join("missing.json"))
+                .unwrap()
+                .credentials("docker.io")
+                .unwrap(),
+            None
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,54 @@
+//! containerd shim v2 running tasks with libcrun-shim
+//!
+//! containerd runs `containerd-shim-crun-v2 -namespace ... -id ... start`
+//! to launch the shim of a task, which is this binary again serving the
+//! task API, and `... delete` to clean up after a shim that is gone.
+
+use libcrun_shim::shim::{self, ShimArgs};
+use std::io::Write;
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    let args = match ShimArgs::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("containerd-shim-crun-v2: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    init_logging(&args);
+
+    let result = match args.action.as_deref() {
+        Some("start") => shim::start(&args).map(|address| print!("{}", address)),
+        Some("delete") => shim::cleanup(&args).map(|response| {
+            let _ = std::io::stdout().write_all(&response);
+        }),
+        Some(action) => {
+            eprintln!("containerd-shim-crun-v2: unknown action '{}'", action);
+            return ExitCode::FAILURE;
+        }
+        None => shim::serve(&args),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            log::error!("{}", e);
+            eprintln!("containerd-shim-crun-v2: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Log to the "log" fifo containerd reads the shim's logs from, in the
+/// bundle the shim runs in, or to stderr for the other actions
+fn init_logging(args: &ShimArgs) {
+    let level = if args.debug { "debug" } else { "info" };
+    let mut builder =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(level));
+    if args.action.is_none() {
+        if let Ok(log) = std::fs::OpenOptions::new().write(true).open("log") {
+            builder.target(env_logger::Target::Pipe(Box::new(log)));
+        }
+    }
+    builder.init();
+}
@@ -0,0 +1,61 @@
+//! CRI server running kubelet's pods with libcrun-shim
+//!
+//! `crun-shim-cri --socket /run/crun-shim/cri.sock` serves the CRI v1
+//! runtime and image services until interrupted; point kubelet's
+//! `--container-runtime-endpoint` and crictl at the socket. `--state PATH`
+//! moves the file the pods' sandboxes and containers are kept in.
+
+use libcrun_shim::{CriServer, SandboxStore};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+/// Where the server listens unless told otherwise, as `crun-shim cri install`
+/// expects
+const DEFAULT_SOCKET: &str = "/run/crun-shim/cri.sock";
+
+fn main() -> ExitCode {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let mut socket = PathBuf::from(DEFAULT_SOCKET);
+    let mut state = SandboxStore::default_path();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("--socket", Some(path)) => socket = PathBuf::from(path),
+            ("--state", Some(path)) => state = PathBuf::from(path),
+            _ => {
+                eprintln!("usage: crun-shim-cri [--socket PATH] [--state PATH]");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    // Calls are served concurrently, a slow pull holding up no other
+    let runtime = match tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("crun-shim-cri: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let result = runtime.block_on(async {
+        let mut server = CriServer::new(socket).with_sandbox_store(SandboxStore::open(state)?);
+        server
+            .serve_with_shutdown(async {
+                let _ = tokio::signal::ctrl_c().await;
+                log::info!("Shutting down the CRI server");
+            })
+            .await
+    });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            log::error!("{}", e);
+            eprintln!("crun-shim-cri: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,436 @@
+//! Named checkpoint store for the native Linux runtime, and checkpoint exports
+//!
+//! Mirrors the VM agent's store, with the same layout and metadata, so
+//! checkpoints look the same on both backends: `<root>/<container>/<name>/`
+//! holds the CRIU image under `images/`, the container's OCI configuration as
+//! `config.json` and the metadata as `checkpoint.json`, written last.
+//!
+//! Exported checkpoints are directories on the host with the same layout, so
+//! they can be imported on another machine or into a new VM.
+
+use crate::{Checkpoint, Result, ShimError};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+/// Where checkpoints are kept
+#[cfg(target_os = "linux")]
+pub(crate) const CHECKPOINTS_DIR: &str = "/var/lib/libcrun-shim/checkpoints";
+
+/// Longest checkpoint name accepted
+const MAX_NAME_LEN: usize = 128;
+
+const METADATA_FILE: &str = "checkpoint.json";
+const CONFIG_FILE: &str = "config.json";
+
+#[cfg(target_os = "linux")]
+pub(crate) struct CheckpointStore {
+    root: PathBuf,
+}
+
+/// Check that a container ID is a safe path component
+pub(crate) fn validate_container(container: &str) -> Result<()> {
+    if container.is_empty()
+        || container == "."
+        || container == ".."
+        || container.contains(['/', '\0'])
+    {
+        return Err(ShimError::validation(
+            "id",
+            format!("Invalid container ID '{}'", container),
+        ));
+    }
+    Ok(())
+}
+
+/// Check that a container ID and checkpoint name are safe path components
+pub(crate) fn validate(container: &str, name: &str) -> Result<()> {
+    validate_container(container)?;
+    if name.is_empty()
+        || name.len() > MAX_NAME_LEN
+        || !name.starts_with(|c: char| c.is_ascii_alphanumeric())
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+    {
+        return Err(ShimError::validation(
+            "name",
+            format!(
+                "Invalid checkpoint name '{}': use up to {} letters, digits, '_', '.' and '-', starting with a letter or digit",
+                name, MAX_NAME_LEN
+            ),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+impl CheckpointStore {
+    pub(crate) fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub(crate) fn dir(&self, container: &str, name: &str) -> PathBuf {
+        self.root.join(container).join(name)
+    }
+
+    pub(crate) fn images_dir(&self, container: &str, name: &str) -> PathBuf {
+        self.dir(container, name).join("images")
+    }
+
+    /// Metadata of a checkpoint, if it was completed
+    pub(crate) fn get(&self, container: &str, name: &str) -> Option<Checkpoint> {
+        let content = std::fs::read(self.dir(container, name).join(METADATA_FILE)).ok()?;
+        serde_json::from_slice(&content).ok()
+    }
+
+    /// Create the directory for a new checkpoint, clearing a failed one
+    pub(crate) fn prepare(&self, container: &str, name: &str) -> Result<PathBuf> {
+        let _ = std::fs::remove_dir_all(self.dir(container, name));
+        let images = self.images_dir(container, name);
+        std::fs::create_dir_all(&images)
+            .map_err(|e| ShimError::io_with_context(e, format!("Creating {}", images.display())))?;
+        Ok(images)
+    }
+
+    /// Record a checkpoint once CRIU has written its image
+    pub(crate) fn finish(
+        &self,
+        container: &str,
+        name: &str,
+        config_json: &str,
+        created_at: u64,
+    ) -> Result<Checkpoint> {
+        let dir = self.dir(container, name);
+        std::fs::write(dir.join(CONFIG_FILE), config_json)
+            .map_err(|e| ShimError::io_with_context(e, "Writing checkpoint config"))?;
+        let checkpoint = Checkpoint {
+            container: container.to_string(),
+            name: name.to_string(),
+            created_at,
+            size_bytes: dir_size(&dir),
+            image_digest: image_digest(&self.images_dir(container, name))
+                .map_err(|e| ShimError::io_with_context(e, "Reading checkpoint image"))?,
+        };
+        write_metadata(&dir, &checkpoint)?;
+        Ok(checkpoint)
+    }
+
+    /// Completed checkpoints of a container, or of all containers, oldest first
+    pub(crate) fn list(&self, container: Option<&str>) -> Vec<Checkpoint> {
+        let containers: Vec<String> = match container {
+            Some(container) => vec![container.to_string()],
+            None => subdirs(&self.root),
+        };
+        let mut checkpoints: Vec<Checkpoint> = containers
+            .iter()
+            .flat_map(|container| {
+                subdirs(&self.root.join(container))
+                    .into_iter()
+                    .filter_map(move |name| self.get(container, &name))
+            })
+            .collect();
+        checkpoints.sort_by(|a, b| {
+            (a.created_at, &a.container, &a.name).cmp(&(b.created_at, &b.container, &b.name))
+        });
+        checkpoints
+    }
+
+    /// Delete a checkpoint, and the container's directory with its last one
+    pub(crate) fn remove(&self, container: &str, name: &str) -> Result<()> {
+        let dir = self.dir(container, name);
+        std::fs::remove_dir_all(&dir)
+            .map_err(|e| ShimError::io_with_context(e, format!("Removing {}", dir.display())))?;
+        // Fails while other checkpoints of the container remain
+        let _ = std::fs::remove_dir(self.root.join(container));
+        Ok(())
+    }
+
+    /// The OCI configuration of a checkpoint, after checking its image is intact
+    pub(crate) fn verify(&self, checkpoint: &Checkpoint) -> Result<String> {
+        check_image(
+            &self.images_dir(&checkpoint.container, &checkpoint.name),
+            checkpoint,
+        )?;
+        let config = self
+            .dir(&checkpoint.container, &checkpoint.name)
+            .join(CONFIG_FILE);
+        std::fs::read_to_string(&config)
+            .map_err(|e| ShimError::io_with_context(e, format!("Reading {}", config.display())))
+    }
+
+    /// Copy a checkpoint to the new directory `path`
+    pub(crate) fn export(&self, checkpoint: &Checkpoint, path: &Path) -> Result<()> {
+        let dir = self.dir(&checkpoint.container, &checkpoint.name);
+        let staging = start_export(path)?;
+        let copied = copy_files(&dir, &staging);
+        if copied.is_err() {
+            let _ = std::fs::remove_dir_all(&staging);
+        }
+        copied?;
+        finish_export(&staging, path, checkpoint)
+    }
+
+    /// Copy the checkpoint exported to `path` into the store, under the
+    /// container and name it was taken as
+    pub(crate) fn import(&self, path: &Path) -> Result<Checkpoint> {
+        let checkpoint = read_export(path)?;
+        let (container, name) = (&checkpoint.container, &checkpoint.name);
+        if self.get(container, name).is_some() {
+            return Err(ShimError::conflict(
+                format!("Checkpoint '{}' of '{}' already exists", name, container),
+                "Remove it first to import it again",
+            ));
+        }
+        self.prepare(container, name)?;
+        let dir = self.dir(container, name);
+        let imported = copy_files(path, &dir)
+            .and_then(|_| self.verify(&checkpoint))
+            .and_then(|_| {
+                let imported = Checkpoint {
+                    size_bytes: dir_size(&dir),
+                    ..checkpoint.clone()
+                };
+                write_metadata(&dir, &imported)?;
+                Ok(imported)
+            });
+        if imported.is_err() {
+            let _ = self.remove(container, name);
+        }
+        imported
+    }
+}
+
+/// Where an export to `path` is written until it is complete, next to it
+fn staging_path(path: &Path) -> PathBuf {
+    path.with_file_name(format!(
+        "{}.partial",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    ))
+}
+
+/// Start exporting a checkpoint to `path`, which must not exist yet
+///
+/// Returns the directory to copy the checkpoint's [`files`] to, empty but for
+/// `images/`, which [`finish_export`] moves to `path`.
+pub(crate) fn start_export(path: &Path) -> Result<PathBuf> {
+    if path.exists() {
+        return Err(ShimError::conflict(
+            format!("{} already exists", path.display()),
+            "Checkpoints are exported to a new directory",
+        ));
+    }
+    let staging = staging_path(path);
+    let _ = std::fs::remove_dir_all(&staging);
+    std::fs::create_dir_all(staging.join("images"))
+        .map_err(|e| ShimError::io_with_context(e, format!("Creating {}", staging.display())))?;
+    Ok(staging)
+}
+
+/// Complete an export once the copied image matches the checkpoint, by
+/// adding its metadata and moving it into place
+pub(crate) fn finish_export(staging: &Path, path: &Path, checkpoint: &Checkpoint) -> Result<()> {
+    let finished = check_image(&staging.join("images"), checkpoint)
+        .and_then(|_| write_metadata(staging, checkpoint))
+        .and_then(|_| {
+            std::fs::rename(staging, path).map_err(|e| {
+                ShimError::io_with_context(e, format!("Moving export to {}", path.display()))
+            })
+        });
+    if finished.is_err() {
+        let _ = std::fs::remove_dir_all(staging);
+    }
+    finished
+}
+
+/// The metadata of the checkpoint exported to `path`, after checking its
+/// image is intact
+pub(crate) fn read_export(path: &Path) -> Result<Checkpoint> {
+    let metadata = path.join(METADATA_FILE);
+    let content = std::fs::read(&metadata)
+        .map_err(|e| ShimError::io_with_context(e, format!("Reading {}", metadata.display())))?;
+    let checkpoint: Checkpoint = serde_json::from_slice(&content)
+        .map_err(|e| ShimError::serialization(format!("Parsing {}", metadata.display()), e))?;
+    validate(&checkpoint.container, &checkpoint.name)?;
+    check_image(&path.join("images"), &checkpoint)?;
+    Ok(checkpoint)
+}
+
+/// Files of a checkpoint directory besides its metadata, relative to it:
+/// `config.json` and `images/<file>`
+pub(crate) fn files(dir: &Path) -> Result<Vec<String>> {
+    let read_error = |e| ShimError::io_with_context(e, format!("Reading {}", dir.display()));
+    std::fs::metadata(dir.join(CONFIG_FILE)).map_err(read_error)?;
+    let mut images: Vec<String> = std::fs::read_dir(dir.join("images"))
+        .map_err(read_error)?
+        .flatten()
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_file()))
+        .filter_map(|e| e.file_name().into_string().ok())
+        .map(|file| format!("images/{}", file))
+        .collect();
+    images.sort();
+    Ok(std::iter::once(CONFIG_FILE.to_string())
+        .chain(images)
+        .collect())
+}
+
+/// Copy the [`files`] of a checkpoint directory to another, which has
+/// `images/` already
+#[cfg(target_os = "linux")]
+fn copy_files(from: &Path, to: &Path) -> Result<()> {
+    for file in files(from)? {
+        std::fs::copy(from.join(&file), to.join(&file)).map_err(|e| {
+            ShimError::io_with_context(e, format!("Copying {}", from.join(&file).display()))
+        })?;
+    }
+    Ok(())
+}
+
+fn write_metadata(dir: &Path, checkpoint: &Checkpoint) -> Result<()> {
+    let metadata = serde_json::to_vec_pretty(checkpoint)?;
+    std::fs::write(dir.join(METADATA_FILE), metadata)
+        .map_err(|e| ShimError::io_with_context(e, "Writing checkpoint metadata"))
+}
+
+/// Check the image in `images` against the digest of `checkpoint`
+fn check_image(images: &Path, checkpoint: &Checkpoint) -> Result<()> {
+    let digest = image_digest(images)
+        .map_err(|e| ShimError::io_with_context(e, "Reading checkpoint image"))?;
+    if digest != checkpoint.image_digest {
+        return Err(ShimError::runtime_with_context(
+            format!(
+                "Checkpoint '{}' of '{}' is corrupt",
+                checkpoint.name, checkpoint.container
+            ),
+            format!(
+                "Image digest is {}, expected {}",
+                digest, checkpoint.image_digest
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Names of the directories in `dir`
+#[cfg(target_os = "linux")]
+fn subdirs(dir: &Path) -> Vec<String> {
+    std::fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+                .filter_map(|e| e.file_name().into_string().ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Total size of the files under `dir`
+#[cfg(target_os = "linux")]
+fn dir_size(dir: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|e| match e.file_type() {
+            Ok(t) if t.is_dir() => dir_size(&e.path()),
+            Ok(t) if t.is_file() => e.metadata().map(|m| m.len()).unwrap_or(0),
+            _ => 0,
+        })
+        .sum()
+}
+
+/// Digest over the names and contents of the files in a checkpoint image,
+/// computed the same way as the agent's
+fn image_digest(images: &Path) -> std::io::Result<String> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(images)?
+        .flatten()
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_file()))
+        .map(|e| e.path())
+        .collect();
+    files.sort();
+    let mut hasher = Sha256::new();
+    for file in files {
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        hasher.update((name.len() as u64).to_le_bytes());
+        hasher.update(name.as_bytes());
+        let mut reader = std::fs::File::open(&file)?;
+        hasher.update(std::fs::metadata(&file)?.len().to_le_bytes());
+        std::io::copy(&mut reader, &mut hasher)?;
+    }
+    Ok(format!("sha256:{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        assert!(validate("web", "before-upgrade").is_ok());
+        assert!(validate("web", "").is_err());
+        assert!(validate("web", "../x").is_err());
+        assert!(validate("..", "before-upgrade").is_err());
+        assert!(validate("a/b", "before-upgrade").is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_checkpoint_store() {
+        let root = std::env::temp_dir().join(format!("shim-checkpoints-{}", std::process::id()));
+        let store = CheckpointStore::new(&root);
+
+        let images = store.prepare("web", "before-upgrade").unwrap();
+        std::fs::write(images.join("pages-1.img"), b"pages").unwrap();
+        let checkpoint = store.finish("web", "before-upgrade", "{}", 100).unwrap();
+        assert_eq!(checkpoint.size_bytes, 7);
+        assert_eq!(store.list(None).len(), 1);
+        assert_eq!(
+            store.get("web", "before-upgrade").as_ref(),
+            Some(&checkpoint)
+        );
+        assert_eq!(store.verify(&checkpoint).unwrap(), "{}");
+
+        std::fs::write(images.join("pages-1.img"), b"changed").unwrap();
+        assert!(store.verify(&checkpoint).is_err());
+
+        store.remove("web", "before-upgrade").unwrap();
+        assert!(store.list(Some("web")).is_empty());
+        assert!(!root.join("web").exists());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_export_and_import() {
+        let tmp = std::env::temp_dir().join(format!("shim-export-{}", std::process::id()));
+        let source = CheckpointStore::new(tmp.join("source"));
+        let target = CheckpointStore::new(tmp.join("target"));
+        let images = source.prepare("web", "before-upgrade").unwrap();
+        std::fs::write(images.join("pages-1.img"), b"pages").unwrap();
+        std::fs::write(images.join("core-1.img"), b"core").unwrap();
+        let checkpoint = source.finish("web", "before-upgrade", "{}", 100).unwrap();
+
+        let path = tmp.join("export");
+        source.export(&checkpoint, &path).unwrap();
+        assert_eq!(
+            files(&path).unwrap(),
+            ["config.json", "images/core-1.img", "images/pages-1.img"]
+        );
+        assert!(!staging_path(&path).exists());
+        let err = source.export(&checkpoint, &path).unwrap_err();
+        assert!(matches!(err, ShimError::Conflict { .. }));
+
+        assert_eq!(target.import(&path).unwrap(), checkpoint);
+        assert_eq!(target.verify(&checkpoint).unwrap(), "{}");
+        let err = target.import(&path).unwrap_err();
+        assert!(matches!(err, ShimError::Conflict { .. }));
+
+        // A damaged export is not imported
+        target.remove("web", "before-upgrade").unwrap();
+        std::fs::write(path.join("images/core-1.img"), b"changed").unwrap();
+        assert!(target.import(&path).is_err());
+        assert!(target.get("web", "before-upgrade").is_none());
+        let _ = std::fs::remove_dir_all(&tmp);
+    }
+}
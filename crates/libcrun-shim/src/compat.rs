@@ -0,0 +1,112 @@
+//! Host/agent version skew handling
+//!
+//! The host and the VM agent are upgraded independently, so a host may talk
+//! to an agent that is older (or newer) than itself. The handshake tells the
+//! host which protocol the agent speaks and which optional features it
+//! implements; requests guarded by a feature are only sent when the agent
+//! advertised it.
+
+use crate::error::{Result, ShimError};
+use crate::types::{AgentInfo, KernelFeatures};
+use libcrun_shim_proto::{features, Request, Response, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+
+/// Run the handshake and feature negotiation with an agent.
+///
+/// `call` sends one request and returns the agent's response. Agents that
+/// predate feature negotiation cannot parse [`Request::Features`] and answer
+/// with an error; they are assumed to support [`features::BASELINE`].
+pub fn negotiate<F>(mut call: F) -> Result<AgentInfo>
+where
+    F: FnMut(Request) -> Result<Response>,
+{
+    let handshake = match call(Request::Handshake)? {
+        Response::Handshake(h) => h,
+        Response::Error(e) => {
+            return Err(ShimError::runtime_with_context(
+                e,
+                "RPC handshake request failed",
+            ))
+        }
+        _ => {
+            return Err(ShimError::runtime(
+                "Unexpected response type from RPC handshake request",
+            ))
+        }
+    };
+
+    if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&handshake.protocol_version) {
+        return Err(ShimError::runtime_with_context(
+            format!(
+                "Agent {} speaks protocol {}, host supports {} to {}",
+                handshake.agent_version,
+                handshake.protocol_version,
+                MIN_PROTOCOL_VERSION,
+                PROTOCOL_VERSION
+            ),
+            "Upgrade the agent or the host so both speak a common protocol",
+        ));
+    }
+
+    let features = match call(Request::Features)? {
+        Response::Features(list) => list,
+        Response::Error(e) => {
+            log::debug!(
+                "Agent {} does not support feature negotiation ({}), assuming baseline",
+                handshake.agent_version,
+                e
+            );
+            features::BASELINE.iter().map(|f| f.to_string()).collect()
+        }
+        _ => {
+            return Err(ShimError::runtime(
+                "Unexpected response type from RPC features request",
+            ))
+        }
+    };
+
+    let kernel = if features.iter().any(|f| f == features::KERNEL_FEATURES) {
+        match call(Request::KernelFeatures)? {
+            Response::KernelFeatures(k) => Some(KernelFeatures {
+                cgroup_v2: k.cgroup_v2,
+                cgroup_controllers: k.cgroup_controllers,
+                overlayfs: k.overlayfs,
+                vsock: k.vsock,
+                user_namespaces: k.user_namespaces,
+                seccomp: k.seccomp,
+            }),
+            Response::Error(e) => {
+                log::debug!("Agent kernel feature report failed: {}", e);
+                None
+            }
+            _ => {
+                return Err(ShimError::runtime(
+                    "Unexpected response type from RPC kernel features request",
+                ))
+            }
+        }
+    } else {
+        None
+    };
+
+    Ok(AgentInfo {
+        version: handshake.agent_version,
+        protocol_version: handshake.protocol_version,
+        features,
+        kernel,
+        clock: None,
+    })
+}
+
+/// Fail with a descriptive error if the agent lacks a feature
+pub fn require_feature(agent: &AgentInfo, feature: &str, operation: &str) -> Result<()> {
+    if agent.supports(feature) {
+        return Ok(());
+    }
+    Err(ShimError::runtime_with_context(
+        format!(
+            "Agent {} does not support {} (missing feature '{}')",
+            agent.version, operation, feature
+        ),
+        "Upgrade the agent with `crun-shim agent upgrade`",
+    ))
+}
@@ -0,0 +1,141 @@
+//! Copying files in and out of containers
+//!
+//! Both runtimes copy with the tar pipeline of
+//! [`libcrun_shim_proto::archive`]: the Linux runtime packs and unpacks in
+//! this process, the macOS one streams the archive to or from the agent.
+//! This has what they share on the host.
+
+use crate::{CopyOptions, CopyStats, Operation};
+use libcrun_shim_proto::{CopyOptionsProto, CopyStatsProto};
+
+pub(crate) fn options_proto(options: &CopyOptions) -> CopyOptionsProto {
+    CopyOptionsProto {
+        exclude: options.exclude.clone(),
+        follow_links: options.follow_links,
+        preserve_owner: options.preserve_owner,
+    }
+}
+
+pub(crate) fn proto_to_stats(stats: CopyStatsProto) -> CopyStats {
+    CopyStats {
+        entries: stats.entries,
+        bytes: stats.bytes,
+    }
+}
+
+/// Reports how far a copy got, each time it is a percent further
+pub(crate) struct Progress {
+    target: String,
+    total: CopyStatsProto,
+    reported: Option<u8>,
+}
+
+impl Progress {
+    /// Progress of copying `total` to or from `target`, e.g. `web:/srv`
+    pub(crate) fn new(target: String, total: CopyStatsProto) -> Self {
+        Self {
+            target,
+            total,
+            reported: None,
+        }
+    }
+
+    pub(crate) fn update(&mut self, done: &CopyStatsProto) {
+        // A tree of empty files is measured in files
+        let percent = if self.total.bytes > 0 {
+            crate::progress::percent(done.bytes, self.total.bytes)
+        } else {
+            crate::progress::percent(done.entries, self.total.entries)
+        };
+        if self.reported == Some(percent) {
+            return;
+        }
+        self.reported = Some(percent);
+        crate::progress::report(
+            Operation::Copy,
+            &self.target,
+            "copy",
+            Some(percent),
+            format!("Copied {} of {} files", done.entries, self.total.entries),
+        );
+    }
+}
+
+/// Pack `source` inside `root` and unpack it at `target` as it is packed,
+/// through a pipe
+#[cfg(target_os = "linux")]
+pub(crate) fn transfer(
+    root: &std::path::Path,
+    source: &std::path::Path,
+    target: &libcrun_shim_proto::archive::Target,
+    options: &CopyOptionsProto,
+    progress: impl FnMut(&CopyStatsProto),
+) -> std::io::Result<CopyStatsProto> {
+    use libcrun_shim_proto::archive;
+
+    let (reader, mut writer) = std::io::pipe()?;
+    std::thread::scope(|scope| {
+        let packer = scope.spawn(move || {
+            // The writer is dropped when done, which ends the archive
+            archive::pack(root, source, &mut writer, options, |_| {})
+        });
+        let unpacked = archive::unpack(reader, target, options, progress);
+        let packed = packer
+            .join()
+            .unwrap_or_else(|_| Err(std::io::Error::other("packing the copy panicked")));
+        match packed {
+            // Why packing failed explains more than the truncated archive
+            Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => Err(e),
+            _ => unpacked,
+        }
+    })
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use libcrun_shim_proto::archive::Target;
+    use std::path::Path;
+
+    #[test]
+    fn test_transfer() {
+        let dir = std::env::temp_dir().join(format!("copy-transfer-{}", std::process::id()));
+        let source = dir.join("src/app");
+        let dest = dir.join("dest");
+        std::fs::create_dir_all(source.join("logs")).unwrap();
+        std::fs::create_dir_all(&dest).unwrap();
+        std::fs::write(source.join("main.js"), "main").unwrap();
+        std::fs::write(source.join("logs/today.log"), "noise").unwrap();
+
+        let options = options_proto(&CopyOptions {
+            exclude: vec!["logs".to_string()],
+            ..Default::default()
+        });
+        let target = Target::resolve(Path::new("/"), &dest.join("web")).unwrap();
+        let mut updates = 0;
+        let stats = transfer(Path::new("/"), &source, &target, &options, |_| updates += 1).unwrap();
+        assert_eq!(
+            proto_to_stats(stats),
+            CopyStats {
+                entries: 2,
+                bytes: 4
+            }
+        );
+        assert_eq!(updates, 2);
+        assert_eq!(
+            std::fs::read_to_string(dest.join("web/main.js")).unwrap(),
+            "main"
+        );
+        assert!(!dest.join("web/logs").exists());
+
+        let missing = transfer(
+            Path::new("/"),
+            &dir.join("missing"),
+            &target,
+            &options,
+            |_| {},
+        );
+        assert_eq!(missing.unwrap_err().kind(), std::io::ErrorKind::NotFound);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
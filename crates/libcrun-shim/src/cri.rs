@@ -8,29 +8,55 @@ use crate::error::{Result, ShimError};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::PathBuf;
+#[cfg(feature = "cri")]
+use std::sync::Arc;
+#[cfg(feature = "cri")]
+use tokio::sync::Mutex;
+
+#[cfg(feature = "cri")]
+mod server;
+mod store;
+
+pub use store::{ContainerRecord, SandboxRecord, SandboxStore};
+
+/// The CRI v1 types and gRPC services, generated from the kubernetes/cri-api
+/// protos
+#[cfg(feature = "cri")]
+#[allow(clippy::all)]
+pub mod v1 {
+    tonic::include_proto!("runtime.v1");
+}
 
 /// CRI Runtime Service interface
+///
+/// The futures are only `Send` where the implementation's are, as the CRI
+/// server needs them to be.
+#[allow(async_fn_in_trait)]
 pub trait RuntimeService {
     /// Version returns the runtime name, runtime version, and runtime API version.
-    fn version(&self, version: &str) -> Result<VersionResponse>;
+    async fn version(&self, version: &str) -> Result<VersionResponse>;
 
     /// RunPodSandbox creates and starts a pod-level sandbox.
-    fn run_pod_sandbox(&self, config: PodSandboxConfig) -> Result<String>;
+    async fn run_pod_sandbox(&self, config: PodSandboxConfig) -> Result<String>;
 
     /// StopPodSandbox stops any running processes that are part of the sandbox.
-    fn stop_pod_sandbox(&self, pod_sandbox_id: &str) -> Result<()>;
+    async fn stop_pod_sandbox(&self, pod_sandbox_id: &str) -> Result<()>;
 
     /// RemovePodSandbox removes the sandbox.
-    fn remove_pod_sandbox(&self, pod_sandbox_id: &str) -> Result<()>;
+    async fn remove_pod_sandbox(&self, pod_sandbox_id: &str) -> Result<()>;
 
     /// PodSandboxStatus returns the status of the PodSandbox.
-    fn pod_sandbox_status(&self, pod_sandbox_id: &str, verbose: bool) -> Result<PodSandboxStatus>;
+    async fn pod_sandbox_status(
+        &self,
+        pod_sandbox_id: &str,
+        verbose: bool,
+    ) -> Result<PodSandboxStatus>;
 
     /// ListPodSandbox returns a list of PodSandboxes.
-    fn list_pod_sandbox(&self, filter: Option<PodSandboxFilter>) -> Result<Vec<PodSandbox>>;
+    async fn list_pod_sandbox(&self, filter: Option<PodSandboxFilter>) -> Result<Vec<PodSandbox>>;
 
     /// CreateContainer creates a new container in the given PodSandbox.
-    fn create_container(
+    async fn create_container(
         &self,
         pod_sandbox_id: &str,
         config: ContainerConfig,
@@ -38,36 +64,36 @@ pub trait RuntimeService {
     ) -> Result<String>;
 
     /// StartContainer starts the container.
-    fn start_container(&self, container_id: &str) -> Result<()>;
+    async fn start_container(&self, container_id: &str) -> Result<()>;
 
     /// StopContainer stops a running container.
-    fn stop_container(&self, container_id: &str, timeout: i64) -> Result<()>;
+    async fn stop_container(&self, container_id: &str, timeout: i64) -> Result<()>;
 
     /// RemoveContainer removes the container.
-    fn remove_container(&self, container_id: &str) -> Result<()>;
+    async fn remove_container(&self, container_id: &str) -> Result<()>;
 
     /// ListContainers lists all containers.
-    fn list_containers(&self, filter: Option<ContainerFilter>) -> Result<Vec<Container>>;
+    async fn list_containers(&self, filter: Option<ContainerFilter>) -> Result<Vec<Container>>;
 
     /// ContainerStatus returns the status of the container.
-    fn container_status(
+    async fn container_status(
         &self,
         container_id: &str,
         verbose: bool,
     ) -> Result<ContainerStatusResponse>;
 
     /// UpdateContainerResources updates the resource constraints of the container.
-    fn update_container_resources(
+    async fn update_container_resources(
         &self,
         container_id: &str,
         resources: LinuxContainerResources,
     ) -> Result<()>;
 
     /// ReopenContainerLog reopens the container log file.
-    fn reopen_container_log(&self, container_id: &str) -> Result<()>;
+    async fn reopen_container_log(&self, container_id: &str) -> Result<()>;
 
     /// ExecSync runs a command in a container synchronously.
-    fn exec_sync(
+    async fn exec_sync(
         &self,
         container_id: &str,
         cmd: Vec<String>,
@@ -75,40 +101,41 @@ pub trait RuntimeService {
     ) -> Result<ExecSyncResponse>;
 
     /// Exec prepares a streaming endpoint to execute a command in the container.
-    fn exec(&self, request: ExecRequest) -> Result<ExecResponse>;
+    async fn exec(&self, request: ExecRequest) -> Result<ExecResponse>;
 
     /// Attach prepares a streaming endpoint to attach to a running container.
-    fn attach(&self, request: AttachRequest) -> Result<AttachResponse>;
+    async fn attach(&self, request: AttachRequest) -> Result<AttachResponse>;
 
     /// PortForward prepares a streaming endpoint to forward ports from a PodSandbox.
-    fn port_forward(&self, request: PortForwardRequest) -> Result<PortForwardResponse>;
+    async fn port_forward(&self, request: PortForwardRequest) -> Result<PortForwardResponse>;
 
     /// ContainerStats returns stats of the container.
-    fn container_stats(&self, container_id: &str) -> Result<ContainerStats>;
+    async fn container_stats(&self, container_id: &str) -> Result<ContainerStats>;
 
     /// ListContainerStats returns stats of all running containers.
-    fn list_container_stats(
+    async fn list_container_stats(
         &self,
         filter: Option<ContainerStatsFilter>,
     ) -> Result<Vec<ContainerStats>>;
 
     /// UpdateRuntimeConfig updates the runtime configuration.
-    fn update_runtime_config(&self, runtime_config: RuntimeConfig) -> Result<()>;
+    async fn update_runtime_config(&self, runtime_config: RuntimeConfig) -> Result<()>;
 
     /// Status returns the status of the runtime.
-    fn status(&self, verbose: bool) -> Result<RuntimeStatus>;
+    async fn status(&self, verbose: bool) -> Result<RuntimeStatus>;
 }
 
 /// CRI Image Service interface
+#[allow(async_fn_in_trait)]
 pub trait ImageService {
     /// ListImages lists existing images.
-    fn list_images(&self, filter: Option<ImageFilter>) -> Result<Vec<Image>>;
+    async fn list_images(&self, filter: Option<ImageFilter>) -> Result<Vec<Image>>;
 
     /// ImageStatus returns the status of the image.
-    fn image_status(&self, image: ImageSpec, verbose: bool) -> Result<ImageStatusResponse>;
+    async fn image_status(&self, image: ImageSpec, verbose: bool) -> Result<ImageStatusResponse>;
 
     /// PullImage pulls an image with authentication config.
-    fn pull_image(
+    async fn pull_image(
         &self,
         image: ImageSpec,
         auth: Option<AuthConfig>,
@@ -116,10 +143,10 @@ pub trait ImageService {
     ) -> Result<String>;
 
     /// RemoveImage removes the image.
-    fn remove_image(&self, image: ImageSpec) -> Result<()>;
+    async fn remove_image(&self, image: ImageSpec) -> Result<()>;
 
     /// ImageFsInfo returns information of the filesystem that is used to store images.
-    fn image_fs_info(&self) -> Result<Vec<FilesystemUsage>>;
+    async fn image_fs_info(&self) -> Result<Vec<FilesystemUsage>>;
 }
 
 /// Version response
@@ -132,7 +159,7 @@ pub struct VersionResponse {
 }
 
 /// Pod sandbox config
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct PodSandboxConfig {
     pub metadata: PodSandboxMetadata,
     pub hostname: String,
@@ -145,7 +172,7 @@ pub struct PodSandboxConfig {
 }
 
 /// Pod sandbox metadata
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct PodSandboxMetadata {
     pub name: String,
     pub uid: String,
@@ -313,7 +340,7 @@ pub struct PodSandboxStatus {
 }
 
 /// Pod sandbox state
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PodSandboxState {
     #[serde(rename = "SANDBOX_READY")]
     SandboxReady,
@@ -374,7 +401,7 @@ pub struct PodSandbox {
 }
 
 /// Container config (CRI)
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct ContainerConfig {
     pub metadata: ContainerMetadata,
     pub image: ImageSpec,
@@ -394,14 +421,14 @@ pub struct ContainerConfig {
 }
 
 /// Container metadata
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct ContainerMetadata {
     pub name: String,
     pub attempt: u32,
 }
 
 /// Image spec
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct ImageSpec {
     pub image: String,
     pub annotations: HashMap<String, String>,
@@ -422,6 +449,8 @@ pub struct Mount {
     pub readonly: bool,
     pub selinux_relabel: bool,
     pub propagation: MountPropagation,
+    #[serde(default)]
+    pub recursive_read_only: bool,
 }
 
 /// Mount propagation
@@ -492,7 +521,7 @@ pub struct ContainerStateValue {
 }
 
 /// Container state
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ContainerState {
     #[serde(rename = "CONTAINER_CREATED")]
     ContainerCreated,
@@ -730,12 +759,17 @@ pub struct FilesystemIdentifier {
 }
 
 /// CRI server implementation
+///
+/// Serves the CRI v1 `RuntimeService` and `ImageService` over gRPC on a Unix
+/// socket, the endpoint kubelet's `--container-runtime-endpoint` points at.
 pub struct CriServer {
     socket_path: PathBuf,
-    #[allow(dead_code)]
+    #[cfg_attr(not(feature = "cri"), allow(dead_code))]
     runtime: Option<crate::ContainerRuntime>,
-    #[allow(dead_code)]
+    #[cfg_attr(not(feature = "cri"), allow(dead_code))]
     image_store: Option<crate::ImageStore>,
+    #[cfg_attr(not(feature = "cri"), allow(dead_code))]
+    sandbox_store: Option<SandboxStore>,
 }
 
 impl CriServer {
@@ -745,6 +779,7 @@ impl CriServer {
             socket_path,
             runtime: None,
             image_store: None,
+            sandbox_store: None,
         }
     }
 
@@ -758,89 +793,54 @@ impl CriServer {
             socket_path,
             runtime: Some(runtime),
             image_store: Some(image_store),
+            sandbox_store: None,
         }
     }
 
-    /// Get or create runtime
-    #[allow(dead_code)]
-    async fn get_runtime(&mut self) -> Result<&mut crate::ContainerRuntime> {
-        if self.runtime.is_none() {
-            self.runtime = Some(crate::ContainerRuntime::new().await?);
-        }
-        Ok(self.runtime.as_mut().unwrap())
+    /// Keep the server's sandboxes and containers in `store`, instead of the
+    /// one at [`SandboxStore::default_path`]
+    pub fn with_sandbox_store(mut self, store: SandboxStore) -> Self {
+        self.sandbox_store = Some(store);
+        self
     }
 
-    /// Get or create image store
-    #[allow(dead_code)]
-    fn get_image_store(&mut self) -> Result<&mut crate::ImageStore> {
-        if self.image_store.is_none() {
-            self.image_store = Some(
-                crate::ImageStore::new(crate::ImageStore::default_path()).map_err(|e| {
-                    ShimError::runtime(format!("Failed to create image store: {}", e))
-                })?,
-            );
-        }
-        Ok(self.image_store.as_mut().unwrap())
+    /// Start the CRI server, serving until the process exits
+    #[cfg(feature = "cri")]
+    pub async fn serve(&mut self) -> Result<()> {
+        self.serve_with_shutdown(std::future::pending()).await
     }
 
-    /// Start the CRI server
+    /// Start the CRI server, serving until `shutdown` completes
+    ///
+    /// The runtime and stores given to [`CriServer::with_services`] and
+    /// [`CriServer::with_sandbox_store`] are handed over to the services, or
+    /// created if there are none.
     #[cfg(feature = "cri")]
-    pub async fn serve(&mut self) -> Result<()> {
+    pub async fn serve_with_shutdown(
+        &mut self,
+        shutdown: impl std::future::Future<Output = ()> + Send,
+    ) -> Result<()> {
         log::info!("Starting CRI server on {}", self.socket_path.display());
-
-        // Ensure services are initialized
-        let _ = self.get_runtime().await?;
-        let _ = self.get_image_store()?;
-
-        // gRPC server implementation
-        // Note: Full gRPC implementation requires:
-        // 1. Tonic server setup with Unix socket listener
-        // 2. RuntimeService and ImageService implementations
-        // 3. CRI protobuf definitions from kubernetes/cri-api
-        // 4. Request/response serialization/deserialization
-
-        #[cfg(feature = "cri")]
-        {
-            use std::io::prelude::*;
-            use std::os::unix::net::UnixListener;
-
-            // Remove old socket if exists
-            let _ = std::fs::remove_file(&self.socket_path);
-
-            let listener = UnixListener::bind(&self.socket_path).map_err(|e| {
-                ShimError::io_with_context(
-                    e,
-                    format!("Failed to bind CRI socket: {}", self.socket_path.display()),
-                )
-            })?;
-
-            log::info!("CRI server listening on {}", self.socket_path.display());
-
-            // Accept connections and handle requests
-            // In a full implementation, this would use tonic::Server
-            for stream in listener.incoming() {
-                match stream {
-                    Ok(mut stream) => {
-                        log::debug!("New CRI connection");
-                        // Handle gRPC requests here
-                        // For now, just acknowledge connection
-                        let _ = stream.write_all(b"OK");
-                    }
-                    Err(e) => {
-                        log::error!("CRI connection error: {}", e);
-                    }
-                }
-            }
-
-            Ok(())
-        }
-
-        #[cfg(not(feature = "cri"))]
-        {
-            Err(ShimError::runtime(
-                "CRI feature not enabled. Enable with 'cri' feature flag.",
-            ))
-        }
+        let runtime = match self.runtime.take() {
+            Some(runtime) => runtime,
+            None => crate::ContainerRuntime::new().await?,
+        };
+        let image_store = match self.image_store.take() {
+            Some(image_store) => image_store,
+            None => open_image_store()?,
+        };
+        let sandbox_store = match self.sandbox_store.take() {
+            Some(sandbox_store) => sandbox_store,
+            None => SandboxStore::open(SandboxStore::default_path())?,
+        };
+        let images = Arc::new(Mutex::new(image_store));
+        server::serve(
+            &self.socket_path,
+            RuntimeServiceImpl::with_runtime(runtime, images.clone(), sandbox_store),
+            ImageServiceImpl::with_store(images),
+            shutdown,
+        )
+        .await
     }
 
     /// Start the CRI server (fallback without gRPC)
@@ -857,288 +857,637 @@ impl CriServer {
     }
 }
 
+#[cfg(feature = "cri")]
+fn open_image_store() -> Result<crate::ImageStore> {
+    crate::ImageStore::new(crate::ImageStore::default_path())
+        .map_err(|e| ShimError::runtime("Failed to create image store").with_source(e))
+}
+
 /// CRI Runtime Service implementation that bridges to ContainerRuntime
+///
+/// Only the sandboxes and containers in the [`SandboxStore`] are CRI ones;
+/// other containers of the runtime are not listed.
+#[cfg(feature = "cri")]
 pub struct RuntimeServiceImpl {
-    #[allow(dead_code)]
-    runtime: crate::ContainerRuntime,
+    runtime: Arc<crate::ContainerRuntime>,
+    /// Images containers are created from, shared with the image service
+    images: Arc<Mutex<crate::ImageStore>>,
+    store: SandboxStore,
 }
 
+#[cfg(feature = "cri")]
 impl RuntimeServiceImpl {
     /// Create a new runtime service
     pub async fn new() -> Result<Self> {
         let runtime = crate::ContainerRuntime::new().await?;
-        Ok(Self { runtime })
+        let images = Arc::new(Mutex::new(open_image_store()?));
+        let store = SandboxStore::open(SandboxStore::default_path())?;
+        Ok(Self::with_runtime(runtime, images, store))
     }
-}
 
-#[cfg(feature = "cri")]
-impl RuntimeService for RuntimeServiceImpl {
-    fn version(&self, _version: &str) -> Result<VersionResponse> {
-        Ok(VersionResponse {
-            version: "0.1.0".to_string(),
-            runtime_name: "libcrun-shim".to_string(),
-            runtime_version: "0.1.0".to_string(),
-            runtime_api_version: "v1alpha2".to_string(),
-        })
+    /// Create a runtime service over `runtime`, creating containers from the
+    /// images in `images` and keeping track of them in `store`
+    pub fn with_runtime(
+        runtime: crate::ContainerRuntime,
+        images: Arc<Mutex<crate::ImageStore>>,
+        store: SandboxStore,
+    ) -> Self {
+        // Pods outlive the runtime service, but the runtime forgets them
+        for sandbox in store.sandboxes() {
+            let spec = crate::types::PodSpec::new(sandbox_container_config(&sandbox.id));
+            let members = store.sandbox_containers(&sandbox.id);
+            if let Err(e) = runtime.register_pod(&spec, members) {
+                log::warn!("Failed to register pod sandbox '{}': {}", sandbox.id, e);
+            }
+        }
+        Self {
+            runtime: Arc::new(runtime),
+            images,
+            store,
+        }
     }
 
-    fn run_pod_sandbox(&self, config: PodSandboxConfig) -> Result<String> {
-        // Create a pod sandbox (essentially a container with special networking)
-        let rt = tokio::runtime::Runtime::new()
-            .map_err(|e| ShimError::runtime(format!("Failed to create runtime: {}", e)))?;
-
-        let container_config = crate::types::ContainerConfig {
-            id: format!("pod-{}", config.metadata.uid),
-            rootfs: PathBuf::from("/"), // Pod sandbox uses minimal rootfs
-            command: vec!["pause".to_string()], // Pause container for pod
-            env: vec![],
-            working_dir: "/".to_string(),
-            ..Default::default()
-        };
-
-        let id = rt
-            .block_on(self.runtime.create(container_config))
-            .map_err(|e| ShimError::runtime(format!("Failed to create pod sandbox: {}", e)))?;
-
-        Ok(id)
+    /// The runtime's containers by ID
+    async fn list(&self) -> Result<HashMap<String, crate::types::ContainerInfo>> {
+        let containers = self.runtime.list().await?;
+        Ok(containers.into_iter().map(|c| (c.id.clone(), c)).collect())
     }
 
-    fn stop_pod_sandbox(&self, pod_sandbox_id: &str) -> Result<()> {
-        let rt = tokio::runtime::Runtime::new()
-            .map_err(|e| ShimError::runtime(format!("Failed to create runtime: {}", e)))?;
+    async fn container_info(&self, id: &str) -> Result<Option<crate::types::ContainerInfo>> {
+        Ok(self.list().await?.remove(id))
+    }
 
-        rt.block_on(self.runtime.stop(pod_sandbox_id))
-            .map_err(|e| ShimError::runtime(format!("Failed to stop pod sandbox: {}", e)))?;
+    fn sandbox_record(&self, id: &str) -> Result<SandboxRecord> {
+        self.store
+            .sandbox(id)
+            .ok_or_else(|| ShimError::not_found(format!("Pod sandbox '{}'", id)))
+    }
 
-        Ok(())
+    fn container_record(&self, id: &str) -> Result<ContainerRecord> {
+        self.store
+            .container(id)
+            .ok_or_else(|| ShimError::not_found(format!("Container '{}'", id)))
     }
 
-    fn remove_pod_sandbox(&self, pod_sandbox_id: &str) -> Result<()> {
-        let rt = tokio::runtime::Runtime::new()
-            .map_err(|e| ShimError::runtime(format!("Failed to create runtime: {}", e)))?;
+    /// A sandbox the runtime lost, as when the VM was recreated, is not ready
+    fn pod_sandbox(
+        &self,
+        record: SandboxRecord,
+        info: Option<&crate::types::ContainerInfo>,
+    ) -> PodSandbox {
+        PodSandbox {
+            id: record.id,
+            metadata: record.metadata,
+            state: info.map_or(PodSandboxState::SandboxNotready, |info| {
+                sandbox_state(info.status)
+            }),
+            created_at: record.created_at,
+            labels: record.labels,
+            annotations: record.annotations,
+            runtime_handler: record.runtime_handler,
+        }
+    }
 
-        rt.block_on(self.runtime.delete(pod_sandbox_id))
-            .map_err(|e| ShimError::runtime(format!("Failed to remove pod sandbox: {}", e)))?;
+    /// A container the runtime lost has exited
+    fn container(
+        &self,
+        record: ContainerRecord,
+        info: Option<&crate::types::ContainerInfo>,
+    ) -> Container {
+        Container {
+            id: record.id,
+            pod_sandbox_id: record.pod_sandbox_id,
+            metadata: record.metadata,
+            image: record.image,
+            image_ref: record.image_ref,
+            state: info.map_or(ContainerState::ContainerExited, |info| {
+                container_state(info.status)
+            }),
+            created_at: record.created_at,
+            labels: record.labels,
+            annotations: record.annotations,
+        }
+    }
 
+    /// Stop the running ones of `ids`
+    async fn stop_running(&self, ids: &[String]) -> Result<()> {
+        let containers = self.list().await?;
+        for id in ids {
+            let running = containers.get(id).is_some_and(|c| {
+                matches!(
+                    c.status,
+                    crate::types::ContainerStatus::Running | crate::types::ContainerStatus::Paused
+                )
+            });
+            if running {
+                self.runtime.stop(id).await?;
+                self.store
+                    .update_container(id, |record| record.finished_at = now_nanos())?;
+            }
+        }
         Ok(())
     }
 
-    fn pod_sandbox_status(&self, pod_sandbox_id: &str, _verbose: bool) -> Result<PodSandboxStatus> {
-        let rt = tokio::runtime::Runtime::new()
-            .map_err(|e| ShimError::runtime(format!("Failed to create runtime: {}", e)))?;
-
-        let containers = rt
-            .block_on(self.runtime.list())
-            .map_err(|e| ShimError::runtime(format!("Failed to list containers: {}", e)))?;
+    /// Remove a container of the runtime, stopping it first
+    async fn force_delete(&self, id: &str) -> Result<()> {
+        match self.runtime.force_delete(id).await {
+            // Removing is idempotent
+            Err(e) if e.is_not_found() => Ok(()),
+            result => result,
+        }
+    }
+}
 
-        let container = containers
-            .iter()
-            .find(|c| c.id == pod_sandbox_id)
-            .ok_or_else(|| ShimError::not_found(format!("Pod sandbox '{}'", pod_sandbox_id)))?;
+/// The pause container a pod sandbox runs, owning the pod's namespaces
+#[cfg(feature = "cri")]
+fn sandbox_container_config(id: &str) -> crate::types::ContainerConfig {
+    crate::types::ContainerConfig {
+        id: id.to_string(),
+        rootfs: PathBuf::from("/"), // Pod sandbox uses minimal rootfs
+        command: vec!["pause".to_string()],
+        env: vec![],
+        working_dir: "/".to_string(),
+        ..Default::default()
+    }
+}
 
-        let state = match container.status {
-            crate::types::ContainerStatus::Running => PodSandboxState::SandboxReady,
-            _ => PodSandboxState::SandboxNotready,
-        };
+#[cfg(feature = "cri")]
+fn sandbox_state(status: crate::types::ContainerStatus) -> PodSandboxState {
+    match status {
+        crate::types::ContainerStatus::Running => PodSandboxState::SandboxReady,
+        _ => PodSandboxState::SandboxNotready,
+    }
+}
 
-        Ok(PodSandboxStatus {
-            id: container.id.clone(),
-            metadata: PodSandboxMetadata {
-                name: container.id.clone(),
-                uid: container.id.clone(),
-                namespace: "default".to_string(),
-                attempt: 0,
-            },
-            state,
-            created_at: 0,
-            network: None,
-            linux: None,
-            labels: std::collections::HashMap::new(),
-            annotations: std::collections::HashMap::new(),
-            runtime_handler: "libcrun-shim".to_string(),
-        })
+#[cfg(feature = "cri")]
+fn container_state(status: crate::types::ContainerStatus) -> ContainerState {
+    match status {
+        crate::types::ContainerStatus::Created => ContainerState::ContainerCreated,
+        // CRI has no paused state, a paused container is still running
+        crate::types::ContainerStatus::Running | crate::types::ContainerStatus::Paused => {
+            ContainerState::ContainerRunning
+        }
+        crate::types::ContainerStatus::Stopped => ContainerState::ContainerExited,
     }
+}
+
+/// Whether `labels` has every label in `selector`
+#[cfg(feature = "cri")]
+fn matches_labels(labels: &HashMap<String, String>, selector: &HashMap<String, String>) -> bool {
+    selector
+        .iter()
+        .all(|(key, value)| labels.get(key) == Some(value))
+}
 
-    fn list_pod_sandbox(&self, _filter: Option<PodSandboxFilter>) -> Result<Vec<PodSandbox>> {
-        let rt = tokio::runtime::Runtime::new()
-            .map_err(|e| ShimError::runtime(format!("Failed to create runtime: {}", e)))?;
+/// Nanoseconds since the Unix epoch
+#[cfg(feature = "cri")]
+fn now_nanos() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos() as i64)
+}
+
+/// The runtime's config for a CRI container, over the settings of its image
+///
+/// As Kubernetes defines, `command` replaces the image's entrypoint and drops
+/// its command, while `args` only replaces the image's command.
+#[cfg(any(feature = "cri", test))]
+fn container_config(
+    id: String,
+    pod_sandbox_id: &str,
+    config: &ContainerConfig,
+    rootfs: PathBuf,
+    image_config: &serde_json::Value,
+) -> Result<crate::types::ContainerConfig> {
+    let strings = |value: &serde_json::Value| -> Vec<String> {
+        value
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|s| s.as_str().map(str::to_string))
+            .collect()
+    };
+    let (mut command, image_args) = if config.command.is_empty() {
+        (
+            strings(&image_config["Entrypoint"]),
+            strings(&image_config["Cmd"]),
+        )
+    } else {
+        (config.command.clone(), vec![])
+    };
+    command.extend(if config.args.is_empty() {
+        image_args
+    } else {
+        config.args.clone()
+    });
+    if command.is_empty() {
+        return Err(ShimError::validation(
+            "command",
+            format!("Container '{}' has no command to run", config.metadata.name),
+        ));
+    }
 
-        let containers = rt
-            .block_on(self.runtime.list())
-            .map_err(|e| ShimError::runtime(format!("Failed to list containers: {}", e)))?;
+    let mut env = strings(&image_config["Env"]);
+    for kv in &config.envs {
+        let prefix = format!("{}=", kv.key);
+        env.retain(|var| !var.starts_with(&prefix));
+        env.push(format!("{}{}", prefix, kv.value));
+    }
 
-        let sandboxes: Vec<PodSandbox> = containers
+    let working_dir = [
+        config.working_dir.as_str(),
+        image_config["WorkingDir"].as_str().unwrap_or_default(),
+    ]
+    .into_iter()
+    .find(|dir| !dir.is_empty())
+    .unwrap_or("/")
+    .to_string();
+
+    Ok(crate::types::ContainerConfig {
+        id,
+        rootfs,
+        command,
+        env,
+        working_dir,
+        volumes: config
+            .mounts
             .iter()
-            .filter(|c| c.id.starts_with("pod-"))
-            .map(|c| PodSandbox {
-                id: c.id.clone(),
-                metadata: PodSandboxMetadata {
-                    name: c.id.clone(),
-                    uid: c.id.clone(),
-                    namespace: "default".to_string(),
-                    attempt: 0,
-                },
-                state: match c.status {
-                    crate::types::ContainerStatus::Running => PodSandboxState::SandboxReady,
-                    _ => PodSandboxState::SandboxNotready,
-                },
-                created_at: 0,
-                labels: std::collections::HashMap::new(),
-                annotations: std::collections::HashMap::new(),
-                runtime_handler: "libcrun-shim".to_string(),
+            .map(|mount| crate::types::VolumeMount {
+                source: PathBuf::from(&mount.host_path),
+                destination: PathBuf::from(&mount.container_path),
+                options: vec![
+                    "rbind".to_string(),
+                    if mount.readonly { "ro" } else { "rw" }.to_string(),
+                ],
+                propagation: Some(match mount.propagation {
+                    MountPropagation::PropagationPrivate => {
+                        crate::types::MountPropagation::Rprivate
+                    }
+                    MountPropagation::PropagationHostToContainer => {
+                        crate::types::MountPropagation::Rslave
+                    }
+                    MountPropagation::PropagationBidirectional => {
+                        crate::types::MountPropagation::Rshared
+                    }
+                }),
+                recursive_readonly: mount.recursive_read_only,
             })
-            .collect();
+            .collect(),
+        resources: config
+            .linux
+            .as_ref()
+            .map(|linux| resources_from_cri(&linux.resources))
+            .unwrap_or_default(),
+        image: Some(config.image.image.clone()),
+        pod: Some(pod_sandbox_id.to_string()),
+        // Containers of an image share its rootfs
+        rootfs_snapshot: true,
+        read_only_rootfs: config
+            .linux
+            .as_ref()
+            .and_then(|linux| linux.security_context.as_ref())
+            .is_some_and(|security| security.readonly_rootfs),
+        labels: config.labels.clone(),
+        ..Default::default()
+    })
+}
 
-        Ok(sandboxes)
+#[cfg(feature = "cri")]
+impl RuntimeService for RuntimeServiceImpl {
+    async fn version(&self, _version: &str) -> Result<VersionResponse> {
+        Ok(VersionResponse {
+            version: "0.1.0".to_string(),
+            runtime_name: "libcrun-shim".to_string(),
+            runtime_version: env!("CARGO_PKG_VERSION").to_string(),
+            runtime_api_version: "v1".to_string(),
+        })
     }
 
-    fn create_container(
-        &self,
-        pod_sandbox_id: &str,
-        config: ContainerConfig,
-        _sandbox_config: PodSandboxConfig,
-    ) -> Result<String> {
-        let rt = tokio::runtime::Runtime::new()
-            .map_err(|e| ShimError::runtime(format!("Failed to create runtime: {}", e)))?;
-
-        // Convert CRI ContainerConfig to our ContainerConfig
-        let container_config = crate::types::ContainerConfig {
-            id: format!("{}-{}", pod_sandbox_id, config.metadata.name),
-            rootfs: PathBuf::from("/"), // Would come from image
-            command: config.command.clone(),
-            env: config
-                .envs
-                .iter()
-                .map(|kv| format!("{}={}", kv.key, kv.value))
-                .collect(),
-            working_dir: config.working_dir.clone(),
-            ..Default::default()
+    async fn run_pod_sandbox(&self, config: PodSandboxConfig) -> Result<String> {
+        if config.metadata.uid.is_empty() {
+            return Err(ShimError::validation(
+                "metadata.uid",
+                "Pod sandbox has no UID",
+            ));
+        }
+        // The attempt keeps a sandbox recreated for a pod apart from the old
+        // one, which kubelet removes later
+        let id = format!("pod-{}-{}", config.metadata.uid, config.metadata.attempt);
+        let sandbox = crate::types::ContainerConfig {
+            labels: config.labels.clone(),
+            ..sandbox_container_config(&id)
         };
 
-        let id = rt
-            .block_on(self.runtime.create(container_config))
-            .map_err(|e| ShimError::runtime(format!("Failed to create container: {}", e)))?;
-
+        self.runtime
+            .create_pod(crate::types::PodSpec::new(sandbox))
+            .await?;
+
+        // The pause process holds the network namespace the containers join
+        let netns_path = self
+            .container_info(&id)
+            .await?
+            .and_then(|info| info.pid)
+            .map(|pid| format!("/proc/{}/ns/net", pid))
+            .unwrap_or_default();
+        let namespace_options = config
+            .linux
+            .and_then(|linux| linux.security_context)
+            .and_then(|security| security.namespace_options)
+            .unwrap_or_default();
+        let record = SandboxRecord {
+            id: id.clone(),
+            metadata: config.metadata,
+            labels: config.labels,
+            annotations: config.annotations,
+            runtime_handler: String::new(),
+            created_at: now_nanos(),
+            log_directory: config.log_directory,
+            namespace_options,
+            netns_path,
+        };
+        if let Err(e) = self.store.add_sandbox(record) {
+            let _ = self.runtime.delete_pod(&id).await;
+            return Err(e);
+        }
         Ok(id)
     }
 
-    fn start_container(&self, container_id: &str) -> Result<()> {
-        let rt = tokio::runtime::Runtime::new()
-            .map_err(|e| ShimError::runtime(format!("Failed to create runtime: {}", e)))?;
-
-        rt.block_on(self.runtime.start(container_id))
-            .map_err(|e| ShimError::runtime(format!("Failed to start container: {}", e)))?;
+    async fn stop_pod_sandbox(&self, pod_sandbox_id: &str) -> Result<()> {
+        // Stopping is idempotent
+        if self.store.sandbox(pod_sandbox_id).is_none() {
+            return Ok(());
+        }
+        let mut ids = self.store.sandbox_containers(pod_sandbox_id);
+        ids.reverse();
+        ids.push(pod_sandbox_id.to_string());
+        self.stop_running(&ids).await
+    }
 
-        Ok(())
+    async fn remove_pod_sandbox(&self, pod_sandbox_id: &str) -> Result<()> {
+        // Removing is idempotent
+        if self.store.sandbox(pod_sandbox_id).is_none() {
+            return Ok(());
+        }
+        for id in self.store.sandbox_containers(pod_sandbox_id).iter().rev() {
+            self.force_delete(id).await?;
+        }
+        match self.runtime.delete_pod(pod_sandbox_id).await {
+            // The runtime lost the sandbox
+            Err(e) if e.is_not_found() => {}
+            result => result?,
+        }
+        self.store.remove_sandbox(pod_sandbox_id)
     }
 
-    fn stop_container(&self, container_id: &str, _timeout: i64) -> Result<()> {
-        let rt = tokio::runtime::Runtime::new()
-            .map_err(|e| ShimError::runtime(format!("Failed to create runtime: {}", e)))?;
+    async fn pod_sandbox_status(
+        &self,
+        pod_sandbox_id: &str,
+        _verbose: bool,
+    ) -> Result<PodSandboxStatus> {
+        let record = self.sandbox_record(pod_sandbox_id)?;
+        let info = self.container_info(pod_sandbox_id).await?;
+        let network = Namespace {
+            network: record.netns_path.clone(),
+            options: Some(record.namespace_options.clone()),
+        };
+        let sandbox = self.pod_sandbox(record, info.as_ref());
 
-        rt.block_on(self.runtime.stop(container_id))
-            .map_err(|e| ShimError::runtime(format!("Failed to stop container: {}", e)))?;
+        Ok(PodSandboxStatus {
+            id: sandbox.id,
+            metadata: sandbox.metadata,
+            state: sandbox.state,
+            created_at: sandbox.created_at,
+            network: info
+                .and_then(|info| info.ip_address)
+                .map(|ip| PodSandboxNetworkStatus {
+                    ip,
+                    additional_ips: vec![],
+                }),
+            linux: Some(LinuxPodSandboxStatus {
+                namespaces: network,
+            }),
+            labels: sandbox.labels,
+            annotations: sandbox.annotations,
+            runtime_handler: sandbox.runtime_handler,
+        })
+    }
 
-        Ok(())
+    async fn list_pod_sandbox(&self, filter: Option<PodSandboxFilter>) -> Result<Vec<PodSandbox>> {
+        let filter = filter.unwrap_or_default();
+        let containers = self.list().await?;
+        Ok(self
+            .store
+            .sandboxes()
+            .into_iter()
+            .map(|record| {
+                let info = containers.get(&record.id);
+                self.pod_sandbox(record, info)
+            })
+            .filter(|sandbox| {
+                filter
+                    .id
+                    .as_ref()
+                    .is_none_or(|id| sandbox.id.starts_with(id))
+                    && filter
+                        .state
+                        .as_ref()
+                        .is_none_or(|state| state.state == sandbox.state)
+                    && matches_labels(&sandbox.labels, &filter.label_selector)
+            })
+            .collect())
     }
 
-    fn remove_container(&self, container_id: &str) -> Result<()> {
-        let rt = tokio::runtime::Runtime::new()
-            .map_err(|e| ShimError::runtime(format!("Failed to create runtime: {}", e)))?;
+    async fn create_container(
+        &self,
+        pod_sandbox_id: &str,
+        config: ContainerConfig,
+        sandbox_config: PodSandboxConfig,
+    ) -> Result<String> {
+        if config.metadata.name.is_empty() {
+            return Err(ShimError::validation(
+                "metadata.name",
+                "Container has no name",
+            ));
+        }
+        let sandbox = self.sandbox_record(pod_sandbox_id)?;
+        let (image_ref, rootfs, image_config) = {
+            let images = self.images.lock().await;
+            let image = find_image(&images, &config.image.image)
+                .ok_or_else(|| ShimError::not_found(format!("Image '{}'", config.image.image)))?;
+            let rootfs = images.get_rootfs(&image.id).ok_or_else(|| {
+                ShimError::not_found(format!("Rootfs of image {}", config.image.image))
+            })?;
+            let image_config = images.config(&image.id)?;
+            (image.id, rootfs, image_config)
+        };
 
-        rt.block_on(self.runtime.delete(container_id))
-            .map_err(|e| ShimError::runtime(format!("Failed to remove container: {}", e)))?;
+        let id = format!(
+            "{}-{}-{}",
+            pod_sandbox_id, config.metadata.name, config.metadata.attempt
+        );
+        let container_config = container_config(
+            id.clone(),
+            pod_sandbox_id,
+            &config,
+            rootfs,
+            &image_config["config"],
+        )?;
+        self.runtime.create(container_config).await?;
+
+        // kubelet gives the log path relative to the sandbox's log directory
+        let log_directory = if sandbox_config.log_directory.is_empty() {
+            sandbox.log_directory
+        } else {
+            sandbox_config.log_directory
+        };
+        let log_path = if config.log_path.is_empty() {
+            String::new()
+        } else {
+            PathBuf::from(log_directory)
+                .join(&config.log_path)
+                .display()
+                .to_string()
+        };
+        let record = ContainerRecord {
+            id: id.clone(),
+            pod_sandbox_id: pod_sandbox_id.to_string(),
+            metadata: config.metadata,
+            image: config.image,
+            image_ref,
+            labels: config.labels,
+            annotations: config.annotations,
+            mounts: config.mounts,
+            log_path,
+            created_at: now_nanos(),
+            started_at: 0,
+            finished_at: 0,
+        };
+        if let Err(e) = self.store.add_container(record) {
+            let _ = self.force_delete(&id).await;
+            return Err(e);
+        }
+        Ok(id)
+    }
 
-        Ok(())
+    async fn start_container(&self, container_id: &str) -> Result<()> {
+        self.container_record(container_id)?;
+        self.runtime.start(container_id).await?;
+        self.store
+            .update_container(container_id, |record| record.started_at = now_nanos())
     }
 
-    fn list_containers(&self, _filter: Option<ContainerFilter>) -> Result<Vec<Container>> {
-        let rt = tokio::runtime::Runtime::new()
-            .map_err(|e| ShimError::runtime(format!("Failed to create runtime: {}", e)))?;
+    async fn stop_container(&self, container_id: &str, _timeout: i64) -> Result<()> {
+        // Stopping is idempotent
+        if self.store.container(container_id).is_none() {
+            return Ok(());
+        }
+        self.stop_running(&[container_id.to_string()]).await
+    }
 
-        let containers = rt
-            .block_on(self.runtime.list())
-            .map_err(|e| ShimError::runtime(format!("Failed to list containers: {}", e)))?;
+    async fn remove_container(&self, container_id: &str) -> Result<()> {
+        // Removing is idempotent
+        if self.store.container(container_id).is_none() {
+            return Ok(());
+        }
+        self.force_delete(container_id).await?;
+        self.store.remove_container(container_id)
+    }
 
-        let cri_containers: Vec<Container> = containers
-            .iter()
-            .filter(|c| !c.id.starts_with("pod-"))
-            .map(|c| Container {
-                id: c.id.clone(),
-                pod_sandbox_id: "unknown".to_string(), // Would track this
-                metadata: ContainerMetadata {
-                    name: c.id.clone(),
-                    attempt: 0,
-                },
-                image: ImageSpec {
-                    image: "unknown".to_string(),
-                    annotations: std::collections::HashMap::new(),
-                },
-                image_ref: "unknown".to_string(),
-                state: match c.status {
-                    crate::types::ContainerStatus::Created => ContainerState::CONTAINER_CREATED,
-                    crate::types::ContainerStatus::Running => ContainerState::CONTAINER_RUNNING,
-                    crate::types::ContainerStatus::Stopped => ContainerState::CONTAINER_EXITED,
-                },
-                created_at: 0,
-                labels: std::collections::HashMap::new(),
-                annotations: std::collections::HashMap::new(),
+    async fn list_containers(&self, filter: Option<ContainerFilter>) -> Result<Vec<Container>> {
+        let filter = filter.unwrap_or_default();
+        let containers = self.list().await?;
+        Ok(self
+            .store
+            .containers()
+            .into_iter()
+            .map(|record| {
+                let info = containers.get(&record.id);
+                self.container(record, info)
             })
-            .collect();
-
-        Ok(cri_containers)
+            .filter(|container| {
+                filter
+                    .id
+                    .as_ref()
+                    .is_none_or(|id| container.id.starts_with(id))
+                    && filter
+                        .state
+                        .as_ref()
+                        .is_none_or(|state| state.state == container.state)
+                    && filter
+                        .pod_sandbox_id
+                        .as_ref()
+                        .is_none_or(|id| container.pod_sandbox_id.starts_with(id))
+                    && matches_labels(&container.labels, &filter.label_selector)
+            })
+            .collect())
     }
 
-    fn container_status(
+    async fn container_status(
         &self,
         container_id: &str,
         _verbose: bool,
     ) -> Result<ContainerStatusResponse> {
-        let rt = tokio::runtime::Runtime::new()
-            .map_err(|e| ShimError::runtime(format!("Failed to create runtime: {}", e)))?;
-
-        let containers = rt
-            .block_on(self.runtime.list())
-            .map_err(|e| ShimError::runtime(format!("Failed to list containers: {}", e)))?;
-
-        let container = containers
-            .iter()
-            .find(|c| c.id == container_id)
-            .ok_or_else(|| ShimError::not_found(format!("Container '{}'", container_id)))?;
+        let record = self.container_record(container_id)?;
+        let info = self.container_info(container_id).await?;
+        let state = info
+            .as_ref()
+            .map_or(ContainerState::ContainerExited, |info| {
+                container_state(info.status)
+            });
+
+        let exited = matches!(state, ContainerState::ContainerExited);
+        let exit_code = match &info {
+            Some(info) if exited => info.last_exit_code.unwrap_or_default(),
+            Some(_) => 0,
+            // The runtime lost the container, as when the VM was recreated
+            None => 255,
+        };
+        // kubelet shows the reason, and needs "OOMKilled" for OOM kills
+        let reason = match (exited, info.as_ref().map(|info| info.last_exit_reason)) {
+            (false, _) => "",
+            (true, None) => "Unknown",
+            (true, Some(Some(crate::types::ExitReason::Oom))) => "OOMKilled",
+            (true, _) if exit_code == 0 => "Completed",
+            (true, _) => "Error",
+        };
+        let message = match info {
+            Some(info) => info.exit_reason.unwrap_or_default(),
+            None => "Container is gone from the runtime".to_string(),
+        };
+        let finished_at = match (exited, record.finished_at) {
+            // The process exited by itself, some time before now
+            (true, 0) => now_nanos(),
+            (true, finished_at) => finished_at,
+            (false, _) => 0,
+        };
+        if exited && record.finished_at == 0 {
+            self.store
+                .update_container(container_id, |record| record.finished_at = finished_at)?;
+        }
 
         Ok(ContainerStatusResponse {
             status: ContainerStatusInfo {
-                id: container.id.clone(),
-                metadata: ContainerMetadata {
-                    name: container.id.clone(),
-                    attempt: 0,
-                },
-                state: match container.status {
-                    crate::types::ContainerStatus::Created => ContainerState::ContainerCreated,
-                    crate::types::ContainerStatus::Running => ContainerState::ContainerRunning,
-                    crate::types::ContainerStatus::Stopped => ContainerState::ContainerExited,
-                },
-                created_at: 0,
-                started_at: 0,
-                finished_at: 0,
-                exit_code: 0,
-                image: ImageSpec {
-                    image: "unknown".to_string(),
-                    annotations: std::collections::HashMap::new(),
-                },
-                image_ref: "unknown".to_string(),
-                reason: String::new(),
-                message: String::new(),
-                labels: std::collections::HashMap::new(),
-                annotations: std::collections::HashMap::new(),
-                mounts: vec![],
-                log_path: String::new(),
+                id: record.id,
+                metadata: record.metadata,
+                state,
+                created_at: record.created_at,
+                started_at: record.started_at,
+                finished_at,
+                exit_code,
+                image: record.image,
+                image_ref: record.image_ref,
+                reason: reason.to_string(),
+                message,
+                labels: record.labels,
+                annotations: record.annotations,
+                mounts: record.mounts,
+                log_path: record.log_path,
             },
-            info: std::collections::HashMap::new(),
+            info: HashMap::new(),
         })
     }
 
-    fn update_container_resources(
+    async fn update_container_resources(
         &self,
         _container_id: &str,
         _resources: LinuxContainerResources,
@@ -1149,23 +1498,29 @@ impl RuntimeService for RuntimeServiceImpl {
         ))
     }
 
-    fn reopen_container_log(&self, _container_id: &str) -> Result<()> {
+    async fn reopen_container_log(&self, _container_id: &str) -> Result<()> {
         // Log reopening not implemented
         Ok(()) // No-op
     }
 
-    fn exec_sync(
+    async fn exec_sync(
         &self,
         container_id: &str,
         cmd: Vec<String>,
-        _timeout: i64,
+        timeout: i64,
     ) -> Result<ExecSyncResponse> {
-        let rt = tokio::runtime::Runtime::new()
-            .map_err(|e| ShimError::runtime(format!("Failed to create runtime: {}", e)))?;
-
-        let (exit_code, stdout, stderr) = rt
-            .block_on(self.runtime.exec(container_id, cmd))
-            .map_err(|e| ShimError::runtime(format!("Failed to exec: {}", e)))?;
+        let exec = self.runtime.exec(container_id, cmd);
+        let (exit_code, stdout, stderr) = if timeout > 0 {
+            let limit = std::time::Duration::from_secs(timeout as u64);
+            tokio::time::timeout(limit, exec).await.map_err(|_| {
+                ShimError::runtime(format!(
+                    "Command in container '{}' timed out after {}s",
+                    container_id, timeout
+                ))
+            })??
+        } else {
+            exec.await?
+        };
 
         Ok(ExecSyncResponse {
             stdout: stdout.into_bytes(),
@@ -1174,58 +1529,54 @@ impl RuntimeService for RuntimeServiceImpl {
         })
     }
 
-    fn exec(&self, _request: ExecRequest) -> Result<ExecResponse> {
+    async fn exec(&self, _request: ExecRequest) -> Result<ExecResponse> {
         // Streaming exec not fully implemented
         Err(ShimError::runtime("Streaming exec not implemented"))
     }
 
-    fn attach(&self, _request: AttachRequest) -> Result<AttachResponse> {
+    async fn attach(&self, _request: AttachRequest) -> Result<AttachResponse> {
         // Attach not fully implemented
         Err(ShimError::runtime("Attach not implemented"))
     }
 
-    fn port_forward(&self, _request: PortForwardRequest) -> Result<PortForwardResponse> {
+    async fn port_forward(&self, _request: PortForwardRequest) -> Result<PortForwardResponse> {
         // Port forward not fully implemented
         Err(ShimError::runtime("Port forward not implemented"))
     }
 
-    fn container_stats(&self, container_id: &str) -> Result<ContainerStats> {
-        let rt = tokio::runtime::Runtime::new()
-            .map_err(|e| ShimError::runtime(format!("Failed to create runtime: {}", e)))?;
-
-        let metrics = rt
-            .block_on(self.runtime.metrics(container_id))
-            .map_err(|e| ShimError::runtime(format!("Failed to get metrics: {}", e)))?;
+    async fn container_stats(&self, container_id: &str) -> Result<ContainerStats> {
+        let record = self.container_record(container_id)?;
+        let metrics = self.runtime.metrics(container_id).await?;
+        let timestamp = (metrics.timestamp as i64).saturating_mul(1_000_000_000);
+        let memory = &metrics.memory;
 
         Ok(ContainerStats {
             attributes: ContainerAttributes {
                 id: container_id.to_string(),
-                metadata: ContainerMetadata {
-                    name: container_id.to_string(),
-                    attempt: 0,
-                },
-                labels: std::collections::HashMap::new(),
-                annotations: std::collections::HashMap::new(),
+                metadata: record.metadata,
+                labels: record.labels,
+                annotations: record.annotations,
             },
             cpu: Some(CpuUsage {
-                timestamp: 0,
+                timestamp,
                 usage_core_nano_seconds: Some(UInt64Value {
-                    value: metrics.cpu.total_usage,
+                    value: metrics.cpu.usage_total,
                 }),
-                usage_nano_cores: Some(UInt64Value { value: 0 }),
+                usage_nano_cores: None,
             }),
             memory: Some(MemoryUsage {
-                timestamp: 0,
+                timestamp,
+                // As cAdvisor computes it, without the reclaimable page cache
                 working_set_bytes: Some(UInt64Value {
-                    value: metrics.memory.usage,
+                    value: memory.usage.saturating_sub(memory.cache),
                 }),
-                available_bytes: None,
-                usage_bytes: Some(UInt64Value {
-                    value: metrics.memory.usage,
+                available_bytes: (memory.limit > 0).then(|| UInt64Value {
+                    value: memory.limit.saturating_sub(memory.usage),
                 }),
-                rss_bytes: Some(UInt64Value {
-                    value: metrics.memory.usage,
+                usage_bytes: Some(UInt64Value {
+                    value: memory.usage,
                 }),
+                rss_bytes: Some(UInt64Value { value: memory.rss }),
                 page_faults: None,
                 major_page_faults: None,
             }),
@@ -1233,149 +1584,249 @@ impl RuntimeService for RuntimeServiceImpl {
         })
     }
 
-    fn list_container_stats(
+    async fn list_container_stats(
         &self,
-        _filter: Option<ContainerStatsFilter>,
+        filter: Option<ContainerStatsFilter>,
     ) -> Result<Vec<ContainerStats>> {
-        // List stats not fully implemented
-        Err(ShimError::runtime("List container stats not implemented"))
+        let filter = filter.unwrap_or_default();
+        let containers = self
+            .list_containers(Some(ContainerFilter {
+                id: filter.id,
+                state: Some(ContainerStateValue {
+                    state: ContainerState::ContainerRunning,
+                }),
+                pod_sandbox_id: filter.pod_sandbox_id,
+                label_selector: filter.label_selector,
+            }))
+            .await?;
+        let mut stats = Vec::with_capacity(containers.len());
+        for container in containers {
+            // A container may stop while the others are measured
+            if let Ok(container_stats) = self.container_stats(&container.id).await {
+                stats.push(container_stats);
+            }
+        }
+        Ok(stats)
     }
 
-    fn update_runtime_config(&self, _runtime_config: RuntimeConfig) -> Result<()> {
+    async fn update_runtime_config(&self, _runtime_config: RuntimeConfig) -> Result<()> {
         // Runtime config update not implemented
         Ok(()) // No-op
     }
 
-    fn status(&self, _verbose: bool) -> Result<RuntimeStatus> {
+    async fn status(&self, _verbose: bool) -> Result<RuntimeStatus> {
+        // kubelet marks the node ready once both conditions hold
+        let ready = |r#type: &str| RuntimeCondition {
+            r#type: r#type.to_string(),
+            status: true,
+            reason: String::new(),
+            message: String::new(),
+        };
         Ok(RuntimeStatus {
-            conditions: vec![RuntimeCondition {
-                r#type: "Ready".to_string(),
-                status: true,
-                reason: "Running".to_string(),
-                message: "Runtime is ready".to_string(),
-            }],
+            conditions: vec![ready("RuntimeReady"), ready("NetworkReady")],
         })
     }
 }
 
 /// CRI Image Service implementation that bridges to ImageStore
+#[cfg(feature = "cri")]
 pub struct ImageServiceImpl {
-    #[allow(dead_code)]
-    image_store: crate::ImageStore,
+    images: Arc<Mutex<crate::ImageStore>>,
 }
 
+#[cfg(feature = "cri")]
 impl ImageServiceImpl {
     /// Create a new image service
     pub fn new() -> Result<Self> {
-        let image_store = crate::ImageStore::new(crate::ImageStore::default_path())
-            .map_err(|e| ShimError::runtime(format!("Failed to create image store: {}", e)))?;
-        Ok(Self { image_store })
+        Ok(Self::with_store(Arc::new(Mutex::new(open_image_store()?))))
+    }
+
+    /// Create an image service over `images`
+    pub fn with_store(images: Arc<Mutex<crate::ImageStore>>) -> Self {
+        Self { images }
     }
 }
 
-#[cfg(feature = "cri")]
-impl ImageService for ImageServiceImpl {
-    fn list_images(&self, _filter: Option<ImageFilter>) -> Result<Vec<Image>> {
-        // List images from store
-        let images = self
-            .image_store
-            .list()
-            .map_err(|e| ShimError::runtime(format!("Failed to list images: {}", e)))?;
+/// The stored image `image`, a reference or an image ID, names
+#[cfg(any(feature = "cri", test))]
+fn find_image(store: &crate::ImageStore, image: &str) -> Option<crate::types::ImageInfo> {
+    let id = image.strip_prefix("sha256:").unwrap_or(image);
+    let reference = crate::types::ImageReference::parse(image).map(|r| r.full_name());
+    store
+        .list()
+        .into_iter()
+        .find(|img| img.id == image || img.id == id || Some(img.reference.full_name()) == reference)
+}
 
-        let cri_images: Vec<Image> = images
-            .iter()
-            .map(|img| Image {
-                id: img.id.clone(),
-                repo_tags: vec![img.reference.full_name()],
-                repo_digests: vec![],
-                size: img.size,
-                uid: None,
-                username: String::new(),
-                spec: Some(ImageSpec {
-                    image: img.reference.full_name(),
-                    annotations: std::collections::HashMap::new(),
-                }),
-            })
-            .collect();
+#[cfg(feature = "cri")]
+fn cri_image(info: &crate::types::ImageInfo) -> Image {
+    Image {
+        id: info.id.clone(),
+        repo_tags: vec![info.reference.full_name()],
+        repo_digests: vec![],
+        size: info.size,
+        uid: None,
+        username: String::new(),
+        spec: Some(ImageSpec {
+            image: info.id.clone(),
+            annotations: HashMap::new(),
+        }),
+    }
+}
 
-        Ok(cri_images)
+/// Registry credentials from what kubelet passes, where `auth` is the
+/// base64 of "username:password" as in Docker's configuration
+#[cfg(any(feature = "cri", test))]
+fn registry_auth(auth: &AuthConfig) -> crate::AuthConfig {
+    use base64::Engine;
+
+    let non_empty = |s: &str| (!s.is_empty()).then(|| s.to_string());
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(&auth.auth)
+        .ok()
+        .and_then(|auth| String::from_utf8(auth).ok());
+    let (username, password) = match decoded.as_deref().and_then(|auth| auth.split_once(':')) {
+        Some((username, password)) if auth.username.is_empty() => {
+            (non_empty(username), non_empty(password))
+        }
+        _ => (non_empty(&auth.username), non_empty(&auth.password)),
+    };
+    crate::AuthConfig {
+        username,
+        password,
+        identity_token: non_empty(&auth.identity_token),
+        registry_token: non_empty(&auth.registry_token),
     }
+}
 
-    fn image_status(&self, image: ImageSpec, _verbose: bool) -> Result<ImageStatusResponse> {
-        // Get image status from store
-        let images = self
-            .image_store
+#[cfg(feature = "cri")]
+impl ImageService for ImageServiceImpl {
+    async fn list_images(&self, filter: Option<ImageFilter>) -> Result<Vec<Image>> {
+        let images = self.images.lock().await;
+        let wanted = filter
+            .and_then(|filter| filter.image)
+            .map(|spec| find_image(&images, &spec.image).map(|image| image.id));
+        Ok(images
             .list()
-            .map_err(|e| ShimError::runtime(format!("Failed to list images: {}", e)))?;
-
-        let img = images
             .iter()
-            .find(|i| i.reference.full_name() == image.image);
-
-        if let Some(img) = img {
-            Ok(ImageStatusResponse {
-                image: Some(Image {
-                    id: img.id.clone(),
-                    repo_tags: vec![img.reference.full_name()],
-                    repo_digests: vec![],
-                    size: img.size,
-                    uid: None,
-                    username: String::new(),
-                    spec: Some(image),
-                }),
-                info: std::collections::HashMap::new(),
+            .filter(|image| {
+                wanted
+                    .as_ref()
+                    .is_none_or(|id| id.as_ref() == Some(&image.id))
             })
-        } else {
-            Ok(ImageStatusResponse {
-                image: None,
-                info: std::collections::HashMap::new(),
-            })
-        }
+            .map(cri_image)
+            .collect())
+    }
+
+    async fn image_status(&self, image: ImageSpec, _verbose: bool) -> Result<ImageStatusResponse> {
+        let images = self.images.lock().await;
+        // A missing image is not an error, kubelet pulls it
+        Ok(ImageStatusResponse {
+            image: find_image(&images, &image.image).map(|info| cri_image(&info)),
+            info: HashMap::new(),
+        })
     }
 
-    fn pull_image(
+    async fn pull_image(
         &self,
         image: ImageSpec,
-        _auth: Option<AuthConfig>,
+        auth: Option<AuthConfig>,
         _sandbox_config: Option<PodSandboxConfig>,
     ) -> Result<String> {
-        // Pull image using store
-        let rt = tokio::runtime::Runtime::new()
-            .map_err(|e| ShimError::runtime(format!("Failed to create runtime: {}", e)))?;
-
-        let info = rt
-            .block_on(self.image_store.pull(&image.image, None))
-            .map_err(|e| ShimError::runtime(format!("Failed to pull image: {}", e)))?;
-
+        let mut images = self.images.lock().await;
+        let info = match auth {
+            Some(auth) => {
+                images
+                    .pull_with_auth(&image.image, &registry_auth(&auth), None)
+                    .await?
+            }
+            None => images.pull(&image.image, None).await?,
+        };
         Ok(info.id)
     }
 
-    fn remove_image(&self, image: ImageSpec) -> Result<()> {
-        // Remove image from store
-        self.image_store
-            .remove(&image.image)
-            .map_err(|e| ShimError::runtime(format!("Failed to remove image: {}", e)))?;
-
-        Ok(())
+    async fn remove_image(&self, image: ImageSpec) -> Result<()> {
+        let mut images = self.images.lock().await;
+        // Removing is idempotent
+        match find_image(&images, &image.image) {
+            Some(info) => images.remove(&info.id),
+            None => Ok(()),
+        }
     }
 
-    fn image_fs_info(&self) -> Result<Vec<FilesystemUsage>> {
-        // Get filesystem info for images
+    async fn image_fs_info(&self) -> Result<Vec<FilesystemUsage>> {
+        let used_bytes = self
+            .images
+            .lock()
+            .await
+            .list()
+            .iter()
+            .map(|image| image.size)
+            .sum();
         Ok(vec![FilesystemUsage {
-            timestamp: 0,
+            timestamp: now_nanos(),
             fs_id: FilesystemIdentifier {
                 mountpoint: crate::ImageStore::default_path().display().to_string(),
             },
-            used_bytes: None,
-            inodes_used: None,
+            used_bytes: Some(UInt64Value { value: used_bytes }),
+            inodes_used: Some(UInt64Value { value: 0 }),
         }])
     }
 }
 
+/// Convert CRI container resources to runtime resource limits
+#[cfg(any(feature = "cri", test))]
+fn resources_from_cri(resources: &LinuxContainerResources) -> crate::types::ResourceLimits {
+    crate::types::ResourceLimits {
+        cpu: (resources.cpu_quota > 0 && resources.cpu_period > 0)
+            .then(|| resources.cpu_quota as f64 / resources.cpu_period as f64),
+        memory: (resources.memory_limit_in_bytes > 0)
+            .then_some(resources.memory_limit_in_bytes as u64),
+        memory_swap: (resources.memory_swap_limit_in_bytes != 0)
+            .then_some(resources.memory_swap_limit_in_bytes),
+        cpuset_cpus: (!resources.cpuset_cpus.is_empty()).then(|| resources.cpuset_cpus.clone()),
+        cpuset_mems: (!resources.cpuset_mems.is_empty()).then(|| resources.cpuset_mems.clone()),
+        hugepage_limits: resources
+            .hugepage_limits
+            .iter()
+            .map(|h| crate::types::HugepageLimit {
+                page_size: h.page_size.clone(),
+                limit: h.limit,
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_resources_from_cri() {
+        let resources = LinuxContainerResources {
+            cpu_period: 100000,
+            cpu_quota: 50000,
+            memory_limit_in_bytes: 256 * 1024 * 1024,
+            cpuset_cpus: "0-1".to_string(),
+            hugepage_limits: vec![HugepageLimit {
+                page_size: "2MB".to_string(),
+                limit: 64 * 1024 * 1024,
+            }],
+            ..Default::default()
+        };
+
+        let limits = resources_from_cri(&resources);
+        assert_eq!(limits.cpu, Some(0.5));
+        assert_eq!(limits.memory, Some(256 * 1024 * 1024));
+        assert_eq!(limits.memory_swap, None);
+        assert_eq!(limits.cpuset_cpus.as_deref(), Some("0-1"));
+        assert_eq!(limits.cpuset_mems, None);
+        assert_eq!(limits.hugepage_limits.len(), 1);
+        assert_eq!(limits.hugepage_limits[0].page_size, "2MB");
+        assert!(limits.validate().is_ok());
+    }
+
     #[test]
     fn test_version_response() {
         let version = VersionResponse {
@@ -1387,4 +1838,166 @@ mod tests {
 
         assert_eq!(version.runtime_name, "libcrun-shim");
     }
+
+    #[test]
+    fn test_container_config() {
+        let image_config = serde_json::json!({
+            "Entrypoint": ["/docker-entrypoint.sh"],
+            "Cmd": ["nginx", "-g", "daemon off;"],
+            "Env": ["PATH=/usr/bin", "NGINX_VERSION=1.25"],
+            "WorkingDir": "/srv",
+        });
+        let mut config = ContainerConfig {
+            metadata: ContainerMetadata {
+                name: "web".to_string(),
+                attempt: 0,
+            },
+            image: ImageSpec {
+                image: "nginx:1.25".to_string(),
+                ..Default::default()
+            },
+            envs: vec![KeyValue {
+                key: "NGINX_VERSION".to_string(),
+                value: "1.26".to_string(),
+            }],
+            mounts: vec![Mount {
+                container_path: "/etc/nginx/conf.d".to_string(),
+                host_path: "/var/lib/kubelet/pods/p/volumes/conf".to_string(),
+                readonly: true,
+                selinux_relabel: false,
+                propagation: MountPropagation::PropagationPrivate,
+                recursive_read_only: false,
+            }],
+            ..Default::default()
+        };
+        let build = |config: &ContainerConfig| {
+            container_config(
+                "pod-u-0-web-0".to_string(),
+                "pod-u-0",
+                config,
+                PathBuf::from("/images/nginx/rootfs"),
+                &image_config,
+            )
+            .unwrap()
+        };
+
+        let built = build(&config);
+        assert_eq!(
+            built.command,
+            ["/docker-entrypoint.sh", "nginx", "-g", "daemon off;"]
+        );
+        assert_eq!(built.env, ["PATH=/usr/bin", "NGINX_VERSION=1.26"]);
+        assert_eq!(built.working_dir, "/srv");
+        assert_eq!(built.pod.as_deref(), Some("pod-u-0"));
+        assert!(built.rootfs_snapshot);
+        assert_eq!(built.volumes.len(), 1);
+        assert_eq!(built.volumes[0].options, ["rbind", "ro"]);
+
+        // args only replace the image's command
+        config.args = vec!["nginx-debug".to_string()];
+        assert_eq!(
+            build(&config).command,
+            ["/docker-entrypoint.sh", "nginx-debug"]
+        );
+
+        // command replaces the entrypoint, and drops the image's command
+        config.command = vec!["/bin/sh".to_string()];
+        config.args = vec![];
+        assert_eq!(build(&config).command, ["/bin/sh"]);
+
+        assert!(container_config(
+            "id".to_string(),
+            "pod",
+            &ContainerConfig::default(),
+            PathBuf::from("/"),
+            &serde_json::Value::Null,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_find_image() {
+        let root = std::env::temp_dir().join(format!("cri-images-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let info = crate::types::ImageInfo {
+            reference: crate::types::ImageReference::parse("nginx:1.25").unwrap(),
+            id: "4f0a".to_string(),
+            size: 0,
+            created: 0,
+            architecture: "amd64".to_string(),
+            os: "linux".to_string(),
+            labels: HashMap::new(),
+            layers: vec![],
+        };
+        std::fs::create_dir_all(root.join("4f0a")).unwrap();
+        std::fs::write(
+            root.join("4f0a").join("image_info.json"),
+            serde_json::to_vec(&info).unwrap(),
+        )
+        .unwrap();
+        let store = crate::ImageStore::new(&root).unwrap();
+
+        for image in [
+            "nginx:1.25",
+            "docker.io/library/nginx:1.25",
+            "4f0a",
+            "sha256:4f0a",
+        ] {
+            assert_eq!(
+                find_image(&store, image).map(|i| i.id).as_deref(),
+                Some("4f0a")
+            );
+        }
+        assert!(find_image(&store, "nginx:1.26").is_none());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_registry_auth() {
+        // base64 of "user:secret"
+        let auth = registry_auth(&AuthConfig {
+            username: String::new(),
+            password: String::new(),
+            auth: "dXNlcjpzZWNyZXQ=".to_string(),
+            server_address: "registry.example.com".to_string(),
+            identity_token: String::new(),
+            registry_token: String::new(),
+        });
+        assert_eq!(auth.username.as_deref(), Some("user"));
+        assert_eq!(auth.password.as_deref(), Some("secret"));
+        assert_eq!(auth.identity_token, None);
+    }
+
+    // The services are awaited on the runtime they run in, even a
+    // single-threaded one
+    #[cfg(feature = "cri")]
+    #[tokio::test]
+    async fn test_services_in_current_thread_runtime() {
+        let dir = std::env::temp_dir().join(format!("cri-async-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let images = Arc::new(Mutex::new(
+            crate::ImageStore::new(dir.join("images")).unwrap(),
+        ));
+        let runtime = RuntimeServiceImpl::with_runtime(
+            crate::ContainerRuntime::new().await.unwrap(),
+            images.clone(),
+            SandboxStore::in_memory(),
+        );
+        let image_service = ImageServiceImpl::with_store(images);
+
+        assert_eq!(
+            runtime.version("v1").await.unwrap().runtime_api_version,
+            "v1"
+        );
+        assert!(runtime.list_pod_sandbox(None).await.unwrap().is_empty());
+        assert!(runtime.list_containers(None).await.unwrap().is_empty());
+        assert!(runtime
+            .container_status("missing", false)
+            .await
+            .unwrap_err()
+            .is_not_found());
+        assert!(image_service.list_images(None).await.unwrap().is_empty());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
 }
@@ -0,0 +1,933 @@
+//! The CRI v1 API over gRPC, as kubelet talks to runtimes
+//!
+//! [`RuntimeGrpc`] and [`ImageGrpc`] adapt [`RuntimeServiceImpl`] and
+//! [`ImageServiceImpl`] to the services generated from the kubernetes/cri-api
+//! protos in [`v1`], converting between the generated messages and the types
+//! of the `cri` module. Streaming (exec, attach, port forwarding, container
+//! events) and the pod-level stats are not served.
+
+use super::*;
+use std::path::Path;
+use tokio::net::UnixListener;
+use tokio_stream::wrappers::UnixListenerStream;
+use tonic::{Request, Response, Status};
+use v1::image_service_server::{ImageService as ImageGrpcService, ImageServiceServer};
+use v1::runtime_service_server::{RuntimeService as RuntimeGrpcService, RuntimeServiceServer};
+
+/// Serve `runtime` and `images` on the Unix socket at `socket_path` until
+/// `shutdown` completes, replacing a stale socket left there
+pub(super) async fn serve(
+    socket_path: &Path,
+    runtime: RuntimeServiceImpl,
+    images: ImageServiceImpl,
+    shutdown: impl std::future::Future<Output = ()> + Send,
+) -> Result<()> {
+    crate::socket::prepare_bind(socket_path, "A CRI server")?;
+    let listener = UnixListener::bind(socket_path).map_err(|e| {
+        ShimError::io_with_context(
+            e,
+            format!("Failed to bind CRI socket: {}", socket_path.display()),
+        )
+    })?;
+
+    let result = tonic::transport::Server::builder()
+        .add_service(RuntimeServiceServer::new(RuntimeGrpc(Arc::new(runtime))))
+        .add_service(ImageServiceServer::new(ImageGrpc(Arc::new(images))))
+        .serve_with_incoming_shutdown(UnixListenerStream::new(listener), shutdown)
+        .await;
+    let _ = std::fs::remove_file(socket_path);
+    result.map_err(|e| ShimError::runtime("CRI server failed").with_source(e))
+}
+
+/// The runtime service as gRPC serves it
+pub(super) struct RuntimeGrpc(Arc<RuntimeServiceImpl>);
+
+/// The image service as gRPC serves it
+pub(super) struct ImageGrpc(Arc<ImageServiceImpl>);
+
+/// The gRPC status kubelet expects for `error`
+fn rpc_status(error: ShimError) -> Status {
+    if error.is_not_found() {
+        Status::not_found(error.to_string())
+    } else if error.is_conflict() {
+        Status::already_exists(error.to_string())
+    } else if matches!(error, ShimError::Validation { .. }) {
+        Status::invalid_argument(error.to_string())
+    } else {
+        Status::unknown(error.to_string())
+    }
+}
+
+type GrpcResult<T> = std::result::Result<Response<T>, Status>;
+
+#[tonic::async_trait]
+impl RuntimeGrpcService for RuntimeGrpc {
+    async fn version(
+        &self,
+        request: Request<v1::VersionRequest>,
+    ) -> GrpcResult<v1::VersionResponse> {
+        let request = request.into_inner();
+        let version = self.0.version(&request.version).await.map_err(rpc_status)?;
+        Ok(Response::new(v1::VersionResponse {
+            version: version.version,
+            runtime_name: version.runtime_name,
+            runtime_version: version.runtime_version,
+            runtime_api_version: version.runtime_api_version,
+        }))
+    }
+
+    async fn run_pod_sandbox(
+        &self,
+        request: Request<v1::RunPodSandboxRequest>,
+    ) -> GrpcResult<v1::RunPodSandboxResponse> {
+        let config = sandbox_config(request.into_inner().config.unwrap_or_default());
+        let pod_sandbox_id = self.0.run_pod_sandbox(config).await.map_err(rpc_status)?;
+        Ok(Response::new(v1::RunPodSandboxResponse { pod_sandbox_id }))
+    }
+
+    async fn stop_pod_sandbox(
+        &self,
+        request: Request<v1::StopPodSandboxRequest>,
+    ) -> GrpcResult<v1::StopPodSandboxResponse> {
+        let id = request.into_inner().pod_sandbox_id;
+        self.0.stop_pod_sandbox(&id).await.map_err(rpc_status)?;
+        Ok(Response::new(v1::StopPodSandboxResponse {}))
+    }
+
+    async fn remove_pod_sandbox(
+        &self,
+        request: Request<v1::RemovePodSandboxRequest>,
+    ) -> GrpcResult<v1::RemovePodSandboxResponse> {
+        let id = request.into_inner().pod_sandbox_id;
+        self.0.remove_pod_sandbox(&id).await.map_err(rpc_status)?;
+        Ok(Response::new(v1::RemovePodSandboxResponse {}))
+    }
+
+    async fn pod_sandbox_status(
+        &self,
+        request: Request<v1::PodSandboxStatusRequest>,
+    ) -> GrpcResult<v1::PodSandboxStatusResponse> {
+        let request = request.into_inner();
+        let status = self
+            .0
+            .pod_sandbox_status(&request.pod_sandbox_id, request.verbose)
+            .await
+            .map_err(rpc_status)?;
+        Ok(Response::new(v1::PodSandboxStatusResponse {
+            status: Some(v1::PodSandboxStatus {
+                id: status.id,
+                metadata: Some(v1_sandbox_metadata(status.metadata)),
+                state: v1_sandbox_state(status.state) as i32,
+                created_at: status.created_at,
+                network: status.network.map(|network| v1::PodSandboxNetworkStatus {
+                    ip: network.ip,
+                    additional_ips: network
+                        .additional_ips
+                        .into_iter()
+                        .map(|ip| v1::PodIp { ip: ip.ip })
+                        .collect(),
+                }),
+                linux: status.linux.map(|linux| v1::LinuxPodSandboxStatus {
+                    namespaces: Some(v1::Namespace {
+                        options: linux.namespaces.options.map(v1_namespace_option),
+                    }),
+                }),
+                labels: status.labels,
+                annotations: status.annotations,
+                runtime_handler: status.runtime_handler,
+            }),
+            timestamp: now_nanos(),
+            ..Default::default()
+        }))
+    }
+
+    async fn list_pod_sandbox(
+        &self,
+        request: Request<v1::ListPodSandboxRequest>,
+    ) -> GrpcResult<v1::ListPodSandboxResponse> {
+        let filter = request.into_inner().filter.map(|filter| PodSandboxFilter {
+            id: non_empty(filter.id),
+            state: filter.state.map(|state| PodSandboxStateValue {
+                state: sandbox_state_from_v1(state.state()),
+            }),
+            label_selector: filter.label_selector,
+        });
+        let sandboxes = self.0.list_pod_sandbox(filter).await.map_err(rpc_status)?;
+        Ok(Response::new(v1::ListPodSandboxResponse {
+            items: sandboxes
+                .into_iter()
+                .map(|sandbox| v1::PodSandbox {
+                    id: sandbox.id,
+                    metadata: Some(v1_sandbox_metadata(sandbox.metadata)),
+                    state: v1_sandbox_state(sandbox.state) as i32,
+                    created_at: sandbox.created_at,
+                    labels: sandbox.labels,
+                    annotations: sandbox.annotations,
+                    runtime_handler: sandbox.runtime_handler,
+                })
+                .collect(),
+        }))
+    }
+
+    async fn create_container(
+        &self,
+        request: Request<v1::CreateContainerRequest>,
+    ) -> GrpcResult<v1::CreateContainerResponse> {
+        let request = request.into_inner();
+        let pod_sandbox_id = request.pod_sandbox_id;
+        let config = container_config_from_v1(request.config.unwrap_or_default());
+        let sandbox = sandbox_config(request.sandbox_config.unwrap_or_default());
+        let container_id = self
+            .0
+            .create_container(&pod_sandbox_id, config, sandbox)
+            .await
+            .map_err(rpc_status)?;
+        Ok(Response::new(v1::CreateContainerResponse { container_id }))
+    }
+
+    async fn start_container(
+        &self,
+        request: Request<v1::StartContainerRequest>,
+    ) -> GrpcResult<v1::StartContainerResponse> {
+        let id = request.into_inner().container_id;
+        self.0.start_container(&id).await.map_err(rpc_status)?;
+        Ok(Response::new(v1::StartContainerResponse {}))
+    }
+
+    async fn stop_container(
+        &self,
+        request: Request<v1::StopContainerRequest>,
+    ) -> GrpcResult<v1::StopContainerResponse> {
+        let request = request.into_inner();
+        self.0
+            .stop_container(&request.container_id, request.timeout)
+            .await
+            .map_err(rpc_status)?;
+        Ok(Response::new(v1::StopContainerResponse {}))
+    }
+
+    async fn remove_container(
+        &self,
+        request: Request<v1::RemoveContainerRequest>,
+    ) -> GrpcResult<v1::RemoveContainerResponse> {
+        let id = request.into_inner().container_id;
+        self.0.remove_container(&id).await.map_err(rpc_status)?;
+        Ok(Response::new(v1::RemoveContainerResponse {}))
+    }
+
+    async fn list_containers(
+        &self,
+        request: Request<v1::ListContainersRequest>,
+    ) -> GrpcResult<v1::ListContainersResponse> {
+        let filter = request.into_inner().filter.map(|filter| ContainerFilter {
+            id: non_empty(filter.id),
+            state: filter.state.map(|state| ContainerStateValue {
+                state: container_state_from_v1(state.state()),
+            }),
+            pod_sandbox_id: non_empty(filter.pod_sandbox_id),
+            label_selector: filter.label_selector,
+        });
+        let containers = self.0.list_containers(filter).await.map_err(rpc_status)?;
+        Ok(Response::new(v1::ListContainersResponse {
+            containers: containers
+                .into_iter()
+                .map(|container| v1::Container {
+                    id: container.id,
+                    pod_sandbox_id: container.pod_sandbox_id,
+                    metadata: Some(v1_container_metadata(container.metadata)),
+                    image: Some(v1_image_spec(container.image)),
+                    image_id: container.image_ref.clone(),
+                    image_ref: container.image_ref,
+                    state: v1_container_state(container.state) as i32,
+                    created_at: container.created_at,
+                    labels: container.labels,
+                    annotations: container.annotations,
+                })
+                .collect(),
+        }))
+    }
+
+    async fn container_status(
+        &self,
+        request: Request<v1::ContainerStatusRequest>,
+    ) -> GrpcResult<v1::ContainerStatusResponse> {
+        let request = request.into_inner();
+        let response = self
+            .0
+            .container_status(&request.container_id, request.verbose)
+            .await
+            .map_err(rpc_status)?;
+        let status = response.status;
+        Ok(Response::new(v1::ContainerStatusResponse {
+            status: Some(v1::ContainerStatus {
+                id: status.id,
+                metadata: Some(v1_container_metadata(status.metadata)),
+                state: v1_container_state(status.state) as i32,
+                created_at: status.created_at,
+                started_at: status.started_at,
+                finished_at: status.finished_at,
+                exit_code: status.exit_code,
+                image: Some(v1_image_spec(status.image)),
+                image_id: status.image_ref.clone(),
+                image_ref: status.image_ref,
+                reason: status.reason,
+                message: status.message,
+                labels: status.labels,
+                annotations: status.annotations,
+                mounts: status.mounts.into_iter().map(v1_mount).collect(),
+                log_path: status.log_path,
+                ..Default::default()
+            }),
+            info: response.info,
+        }))
+    }
+
+    async fn update_container_resources(
+        &self,
+        request: Request<v1::UpdateContainerResourcesRequest>,
+    ) -> GrpcResult<v1::UpdateContainerResourcesResponse> {
+        let request = request.into_inner();
+        let resources = resources_from_v1(request.linux.unwrap_or_default());
+        self.0
+            .update_container_resources(&request.container_id, resources)
+            .await
+            .map_err(rpc_status)?;
+        Ok(Response::new(v1::UpdateContainerResourcesResponse {}))
+    }
+
+    async fn reopen_container_log(
+        &self,
+        request: Request<v1::ReopenContainerLogRequest>,
+    ) -> GrpcResult<v1::ReopenContainerLogResponse> {
+        let id = request.into_inner().container_id;
+        self.0.reopen_container_log(&id).await.map_err(rpc_status)?;
+        Ok(Response::new(v1::ReopenContainerLogResponse {}))
+    }
+
+    async fn exec_sync(
+        &self,
+        request: Request<v1::ExecSyncRequest>,
+    ) -> GrpcResult<v1::ExecSyncResponse> {
+        let request = request.into_inner();
+        let response = self
+            .0
+            .exec_sync(&request.container_id, request.cmd, request.timeout)
+            .await
+            .map_err(rpc_status)?;
+        Ok(Response::new(v1::ExecSyncResponse {
+            stdout: response.stdout,
+            stderr: response.stderr,
+            exit_code: response.exit_code,
+        }))
+    }
+
+    async fn exec(&self, _request: Request<v1::ExecRequest>) -> GrpcResult<v1::ExecResponse> {
+        Err(Status::unimplemented("Streaming exec is not supported"))
+    }
+
+    async fn attach(&self, _request: Request<v1::AttachRequest>) -> GrpcResult<v1::AttachResponse> {
+        Err(Status::unimplemented("Attach is not supported"))
+    }
+
+    async fn port_forward(
+        &self,
+        _request: Request<v1::PortForwardRequest>,
+    ) -> GrpcResult<v1::PortForwardResponse> {
+        Err(Status::unimplemented("Port forwarding is not supported"))
+    }
+
+    async fn container_stats(
+        &self,
+        request: Request<v1::ContainerStatsRequest>,
+    ) -> GrpcResult<v1::ContainerStatsResponse> {
+        let id = request.into_inner().container_id;
+        let stats = self.0.container_stats(&id).await.map_err(rpc_status)?;
+        Ok(Response::new(v1::ContainerStatsResponse {
+            stats: Some(v1_container_stats(stats)),
+        }))
+    }
+
+    async fn list_container_stats(
+        &self,
+        request: Request<v1::ListContainerStatsRequest>,
+    ) -> GrpcResult<v1::ListContainerStatsResponse> {
+        let filter = request
+            .into_inner()
+            .filter
+            .map(|filter| ContainerStatsFilter {
+                id: non_empty(filter.id),
+                pod_sandbox_id: non_empty(filter.pod_sandbox_id),
+                label_selector: filter.label_selector,
+            });
+        let stats = self
+            .0
+            .list_container_stats(filter)
+            .await
+            .map_err(rpc_status)?;
+        Ok(Response::new(v1::ListContainerStatsResponse {
+            stats: stats.into_iter().map(v1_container_stats).collect(),
+        }))
+    }
+
+    async fn pod_sandbox_stats(
+        &self,
+        _request: Request<v1::PodSandboxStatsRequest>,
+    ) -> GrpcResult<v1::PodSandboxStatsResponse> {
+        Err(Status::unimplemented("Pod sandbox stats are not supported"))
+    }
+
+    async fn list_pod_sandbox_stats(
+        &self,
+        _request: Request<v1::ListPodSandboxStatsRequest>,
+    ) -> GrpcResult<v1::ListPodSandboxStatsResponse> {
+        Err(Status::unimplemented("Pod sandbox stats are not supported"))
+    }
+
+    async fn update_runtime_config(
+        &self,
+        request: Request<v1::UpdateRuntimeConfigRequest>,
+    ) -> GrpcResult<v1::UpdateRuntimeConfigResponse> {
+        let config = RuntimeConfig {
+            network_config: request
+                .into_inner()
+                .runtime_config
+                .and_then(|config| config.network_config)
+                .map(|network| NetworkConfig {
+                    pod_cidr: network.pod_cidr,
+                }),
+        };
+        self.0
+            .update_runtime_config(config)
+            .await
+            .map_err(rpc_status)?;
+        Ok(Response::new(v1::UpdateRuntimeConfigResponse {}))
+    }
+
+    async fn status(&self, request: Request<v1::StatusRequest>) -> GrpcResult<v1::StatusResponse> {
+        let verbose = request.into_inner().verbose;
+        let status = self.0.status(verbose).await.map_err(rpc_status)?;
+        Ok(Response::new(v1::StatusResponse {
+            status: Some(v1::RuntimeStatus {
+                conditions: status
+                    .conditions
+                    .into_iter()
+                    .map(|condition| v1::RuntimeCondition {
+                        r#type: condition.r#type,
+                        status: condition.status,
+                        reason: condition.reason,
+                        message: condition.message,
+                    })
+                    .collect(),
+            }),
+            ..Default::default()
+        }))
+    }
+
+    async fn checkpoint_container(
+        &self,
+        _request: Request<v1::CheckpointContainerRequest>,
+    ) -> GrpcResult<v1::CheckpointContainerResponse> {
+        Err(Status::unimplemented("Checkpointing is not supported"))
+    }
+
+    type GetContainerEventsStream =
+        tokio_stream::Empty<std::result::Result<v1::ContainerEventResponse, Status>>;
+
+    async fn get_container_events(
+        &self,
+        _request: Request<v1::GetEventsRequest>,
+    ) -> GrpcResult<Self::GetContainerEventsStream> {
+        // kubelet falls back to polling the containers
+        Err(Status::unimplemented("Container events are not supported"))
+    }
+
+    async fn list_metric_descriptors(
+        &self,
+        _request: Request<v1::ListMetricDescriptorsRequest>,
+    ) -> GrpcResult<v1::ListMetricDescriptorsResponse> {
+        Err(Status::unimplemented("Metrics are not supported"))
+    }
+
+    async fn list_pod_sandbox_metrics(
+        &self,
+        _request: Request<v1::ListPodSandboxMetricsRequest>,
+    ) -> GrpcResult<v1::ListPodSandboxMetricsResponse> {
+        Err(Status::unimplemented("Metrics are not supported"))
+    }
+
+    async fn runtime_config(
+        &self,
+        _request: Request<v1::RuntimeConfigRequest>,
+    ) -> GrpcResult<v1::RuntimeConfigResponse> {
+        // The driver `crun-shim cri install` configures kubelet with
+        let cgroup_driver = if Path::new("/run/systemd/system").exists() {
+            v1::CgroupDriver::Systemd
+        } else {
+            v1::CgroupDriver::Cgroupfs
+        };
+        Ok(Response::new(v1::RuntimeConfigResponse {
+            linux: Some(v1::LinuxRuntimeConfiguration {
+                cgroup_driver: cgroup_driver as i32,
+            }),
+        }))
+    }
+}
+
+#[tonic::async_trait]
+impl ImageGrpcService for ImageGrpc {
+    async fn list_images(
+        &self,
+        request: Request<v1::ListImagesRequest>,
+    ) -> GrpcResult<v1::ListImagesResponse> {
+        let filter = request.into_inner().filter.map(|filter| ImageFilter {
+            image: filter.image.map(image_spec_from_v1),
+        });
+        let images = self.0.list_images(filter).await.map_err(rpc_status)?;
+        Ok(Response::new(v1::ListImagesResponse {
+            images: images.into_iter().map(v1_image).collect(),
+        }))
+    }
+
+    async fn image_status(
+        &self,
+        request: Request<v1::ImageStatusRequest>,
+    ) -> GrpcResult<v1::ImageStatusResponse> {
+        let request = request.into_inner();
+        let image = image_spec_from_v1(request.image.unwrap_or_default());
+        let status = self
+            .0
+            .image_status(image, request.verbose)
+            .await
+            .map_err(rpc_status)?;
+        Ok(Response::new(v1::ImageStatusResponse {
+            image: status.image.map(v1_image),
+            info: status.info,
+        }))
+    }
+
+    async fn pull_image(
+        &self,
+        request: Request<v1::PullImageRequest>,
+    ) -> GrpcResult<v1::PullImageResponse> {
+        let request = request.into_inner();
+        let image = image_spec_from_v1(request.image.unwrap_or_default());
+        let auth = request.auth.map(|auth| AuthConfig {
+            username: auth.username,
+            password: auth.password,
+            auth: auth.auth,
+            server_address: auth.server_address,
+            identity_token: auth.identity_token,
+            registry_token: auth.registry_token,
+        });
+        let sandbox = request.sandbox_config.map(sandbox_config);
+        let image_ref = self
+            .0
+            .pull_image(image, auth, sandbox)
+            .await
+            .map_err(rpc_status)?;
+        Ok(Response::new(v1::PullImageResponse { image_ref }))
+    }
+
+    async fn remove_image(
+        &self,
+        request: Request<v1::RemoveImageRequest>,
+    ) -> GrpcResult<v1::RemoveImageResponse> {
+        let image = image_spec_from_v1(request.into_inner().image.unwrap_or_default());
+        self.0.remove_image(image).await.map_err(rpc_status)?;
+        Ok(Response::new(v1::RemoveImageResponse {}))
+    }
+
+    async fn image_fs_info(
+        &self,
+        _request: Request<v1::ImageFsInfoRequest>,
+    ) -> GrpcResult<v1::ImageFsInfoResponse> {
+        let filesystems = self.0.image_fs_info().await.map_err(rpc_status)?;
+        Ok(Response::new(v1::ImageFsInfoResponse {
+            image_filesystems: filesystems
+                .into_iter()
+                .map(|fs| v1::FilesystemUsage {
+                    timestamp: fs.timestamp,
+                    fs_id: Some(v1::FilesystemIdentifier {
+                        mountpoint: fs.fs_id.mountpoint,
+                    }),
+                    used_bytes: fs.used_bytes.map(v1_u64),
+                    inodes_used: fs.inodes_used.map(v1_u64),
+                })
+                .collect(),
+            container_filesystems: vec![],
+        }))
+    }
+}
+
+/// `None` for the empty string proto3 sends for an unset field
+fn non_empty(s: String) -> Option<String> {
+    (!s.is_empty()).then_some(s)
+}
+
+fn sandbox_config(config: v1::PodSandboxConfig) -> PodSandboxConfig {
+    let metadata = config.metadata.unwrap_or_default();
+    PodSandboxConfig {
+        metadata: PodSandboxMetadata {
+            name: metadata.name,
+            uid: metadata.uid,
+            namespace: metadata.namespace,
+            attempt: metadata.attempt,
+        },
+        hostname: config.hostname,
+        log_directory: config.log_directory,
+        labels: config.labels,
+        annotations: config.annotations,
+        linux: config.linux.map(|linux| LinuxPodSandboxConfig {
+            cgroup_parent: linux.cgroup_parent,
+            security_context: linux
+                .security_context
+                .map(|security| LinuxSandboxSecurityContext {
+                    namespace_options: security.namespace_options.map(namespace_option_from_v1),
+                    readonly_rootfs: security.readonly_rootfs,
+                    supplemental_groups: security.supplemental_groups,
+                    privileged: security.privileged,
+                    ..Default::default()
+                }),
+            sysctls: linux.sysctls,
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+fn namespace_option_from_v1(option: v1::NamespaceOption) -> NamespaceOption {
+    NamespaceOption {
+        network: namespace_mode_from_v1(option.network()),
+        pid: namespace_mode_from_v1(option.pid()),
+        ipc: namespace_mode_from_v1(option.ipc()),
+        target_id: option.target_id,
+        user_namespaces: None,
+    }
+}
+
+fn namespace_mode_from_v1(mode: v1::NamespaceMode) -> NamespaceMode {
+    match mode {
+        v1::NamespaceMode::Pod => NamespaceMode::POD,
+        v1::NamespaceMode::Container => NamespaceMode::CONTAINER,
+        v1::NamespaceMode::Node => NamespaceMode::NODE,
+        v1::NamespaceMode::Target => NamespaceMode::TARGET,
+    }
+}
+
+fn container_config_from_v1(config: v1::ContainerConfig) -> ContainerConfig {
+    let metadata = config.metadata.unwrap_or_default();
+    ContainerConfig {
+        metadata: ContainerMetadata {
+            name: metadata.name,
+            attempt: metadata.attempt,
+        },
+        image: image_spec_from_v1(config.image.unwrap_or_default()),
+        command: config.command,
+        args: config.args,
+        working_dir: config.working_dir,
+        envs: config
+            .envs
+            .into_iter()
+            .map(|kv| KeyValue {
+                key: kv.key,
+                value: kv.value,
+            })
+            .collect(),
+        mounts: config.mounts.into_iter().map(mount_from_v1).collect(),
+        labels: config.labels,
+        annotations: config.annotations,
+        log_path: config.log_path,
+        stdin: config.stdin,
+        stdin_once: config.stdin_once,
+        tty: config.tty,
+        linux: config.linux.map(|linux| LinuxContainerConfig {
+            resources: resources_from_v1(linux.resources.unwrap_or_default()),
+            security_context: linux.security_context.map(|security| {
+                LinuxContainerSecurityContext {
+                    privileged: security.privileged,
+                    run_as_username: security.run_as_username,
+                    readonly_rootfs: security.readonly_rootfs,
+                    supplemental_groups: security.supplemental_groups,
+                    no_new_privs: security.no_new_privs,
+                    masked_paths: security.masked_paths,
+                    readonly_paths: security.readonly_paths,
+                    ..Default::default()
+                }
+            }),
+        }),
+        ..Default::default()
+    }
+}
+
+fn resources_from_v1(resources: v1::LinuxContainerResources) -> LinuxContainerResources {
+    LinuxContainerResources {
+        cpu_period: resources.cpu_period,
+        cpu_quota: resources.cpu_quota,
+        cpu_shares: resources.cpu_shares,
+        memory_limit_in_bytes: resources.memory_limit_in_bytes,
+        oom_score_adj: resources.oom_score_adj,
+        cpuset_cpus: resources.cpuset_cpus,
+        cpuset_mems: resources.cpuset_mems,
+        unified: resources.unified,
+        memory_swap_limit_in_bytes: resources.memory_swap_limit_in_bytes,
+        ..Default::default()
+    }
+}
+
+fn image_spec_from_v1(spec: v1::ImageSpec) -> ImageSpec {
+    ImageSpec {
+        image: spec.image,
+        annotations: spec.annotations,
+    }
+}
+
+fn mount_from_v1(mount: v1::Mount) -> Mount {
+    let propagation = match mount.propagation() {
+        v1::MountPropagation::PropagationPrivate => MountPropagation::PropagationPrivate,
+        v1::MountPropagation::PropagationHostToContainer => {
+            MountPropagation::PropagationHostToContainer
+        }
+        v1::MountPropagation::PropagationBidirectional => {
+            MountPropagation::PropagationBidirectional
+        }
+    };
+    Mount {
+        container_path: mount.container_path,
+        host_path: mount.host_path,
+        readonly: mount.readonly,
+        selinux_relabel: mount.selinux_relabel,
+        propagation,
+        recursive_read_only: mount.recursive_read_only,
+    }
+}
+
+fn sandbox_state_from_v1(state: v1::PodSandboxState) -> PodSandboxState {
+    match state {
+        v1::PodSandboxState::SandboxReady => PodSandboxState::SandboxReady,
+        v1::PodSandboxState::SandboxNotready => PodSandboxState::SandboxNotready,
+    }
+}
+
+fn container_state_from_v1(state: v1::ContainerState) -> ContainerState {
+    match state {
+        v1::ContainerState::ContainerCreated => ContainerState::ContainerCreated,
+        v1::ContainerState::ContainerRunning => ContainerState::ContainerRunning,
+        v1::ContainerState::ContainerExited => ContainerState::ContainerExited,
+        v1::ContainerState::ContainerUnknown => ContainerState::ContainerUnknown,
+    }
+}
+
+fn v1_sandbox_state(state: PodSandboxState) -> v1::PodSandboxState {
+    match state {
+        PodSandboxState::SandboxReady => v1::PodSandboxState::SandboxReady,
+        PodSandboxState::SandboxNotready => v1::PodSandboxState::SandboxNotready,
+    }
+}
+
+fn v1_container_state(state: ContainerState) -> v1::ContainerState {
+    match state {
+        ContainerState::ContainerCreated => v1::ContainerState::ContainerCreated,
+        ContainerState::ContainerRunning => v1::ContainerState::ContainerRunning,
+        ContainerState::ContainerExited => v1::ContainerState::ContainerExited,
+        ContainerState::ContainerUnknown => v1::ContainerState::ContainerUnknown,
+    }
+}
+
+fn v1_namespace_option(option: NamespaceOption) -> v1::NamespaceOption {
+    v1::NamespaceOption {
+        network: v1_namespace_mode(option.network) as i32,
+        pid: v1_namespace_mode(option.pid) as i32,
+        ipc: v1_namespace_mode(option.ipc) as i32,
+        target_id: option.target_id,
+        userns_options: None,
+    }
+}
+
+fn v1_namespace_mode(mode: NamespaceMode) -> v1::NamespaceMode {
+    match mode {
+        NamespaceMode::POD => v1::NamespaceMode::Pod,
+        NamespaceMode::CONTAINER => v1::NamespaceMode::Container,
+        NamespaceMode::NODE => v1::NamespaceMode::Node,
+        NamespaceMode::TARGET => v1::NamespaceMode::Target,
+    }
+}
+
+fn v1_sandbox_metadata(metadata: PodSandboxMetadata) -> v1::PodSandboxMetadata {
+    v1::PodSandboxMetadata {
+        name: metadata.name,
+        uid: metadata.uid,
+        namespace: metadata.namespace,
+        attempt: metadata.attempt,
+    }
+}
+
+fn v1_container_metadata(metadata: ContainerMetadata) -> v1::ContainerMetadata {
+    v1::ContainerMetadata {
+        name: metadata.name,
+        attempt: metadata.attempt,
+    }
+}
+
+fn v1_image_spec(spec: ImageSpec) -> v1::ImageSpec {
+    v1::ImageSpec {
+        image: spec.image,
+        annotations: spec.annotations,
+        ..Default::default()
+    }
+}
+
+fn v1_mount(mount: Mount) -> v1::Mount {
+    let propagation = match mount.propagation {
+        MountPropagation::PropagationPrivate => v1::MountPropagation::PropagationPrivate,
+        MountPropagation::PropagationHostToContainer => {
+            v1::MountPropagation::PropagationHostToContainer
+        }
+        MountPropagation::PropagationBidirectional => {
+            v1::MountPropagation::PropagationBidirectional
+        }
+    };
+    v1::Mount {
+        container_path: mount.container_path,
+        host_path: mount.host_path,
+        readonly: mount.readonly,
+        selinux_relabel: mount.selinux_relabel,
+        propagation: propagation as i32,
+        recursive_read_only: mount.recursive_read_only,
+        ..Default::default()
+    }
+}
+
+fn v1_u64(value: UInt64Value) -> v1::UInt64Value {
+    v1::UInt64Value { value: value.value }
+}
+
+fn v1_container_stats(stats: ContainerStats) -> v1::ContainerStats {
+    v1::ContainerStats {
+        attributes: Some(v1::ContainerAttributes {
+            id: stats.attributes.id,
+            metadata: Some(v1_container_metadata(stats.attributes.metadata)),
+            labels: stats.attributes.labels,
+            annotations: stats.attributes.annotations,
+        }),
+        cpu: stats.cpu.map(|cpu| v1::CpuUsage {
+            timestamp: cpu.timestamp,
+            usage_core_nano_seconds: cpu.usage_core_nano_seconds.map(v1_u64),
+            usage_nano_cores: cpu.usage_nano_cores.map(v1_u64),
+        }),
+        memory: stats.memory.map(|memory| v1::MemoryUsage {
+            timestamp: memory.timestamp,
+            working_set_bytes: memory.working_set_bytes.map(v1_u64),
+            available_bytes: memory.available_bytes.map(v1_u64),
+            usage_bytes: memory.usage_bytes.map(v1_u64),
+            rss_bytes: memory.rss_bytes.map(v1_u64),
+            page_faults: memory.page_faults.map(v1_u64),
+            major_page_faults: memory.major_page_faults.map(v1_u64),
+        }),
+        ..Default::default()
+    }
+}
+
+fn v1_image(image: Image) -> v1::Image {
+    v1::Image {
+        id: image.id,
+        repo_tags: image.repo_tags,
+        repo_digests: image.repo_digests,
+        size: image.size,
+        uid: image.uid.map(|uid| v1::Int64Value { value: uid.value }),
+        username: image.username,
+        spec: image.spec.map(v1_image_spec),
+        pinned: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use v1::image_service_client::ImageServiceClient;
+    use v1::runtime_service_client::RuntimeServiceClient;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_serve() {
+        let dir = std::env::temp_dir().join(format!("cri-serve-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let socket = dir.join("cri.sock");
+        let runtime = crate::ContainerRuntime::new().await.unwrap();
+        let images = Arc::new(Mutex::new(
+            crate::ImageStore::new(dir.join("images")).unwrap(),
+        ));
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = {
+            let socket = socket.clone();
+            let runtime = RuntimeServiceImpl::with_runtime(
+                runtime,
+                images.clone(),
+                SandboxStore::in_memory(),
+            );
+            let images = ImageServiceImpl::with_store(images);
+            tokio::spawn(async move {
+                serve(&socket, runtime, images, async {
+                    let _ = stopped.await;
+                })
+                .await
+            })
+        };
+        while !socket.exists() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        let path = socket.clone();
+        let channel = tonic::transport::Endpoint::from_static("http://localhost")
+            .connect_with_connector(tower::service_fn(move |_| {
+                tokio::net::UnixStream::connect(path.clone())
+            }))
+            .await
+            .unwrap();
+        let mut runtime = RuntimeServiceClient::new(channel.clone());
+        let mut images = ImageServiceClient::new(channel);
+
+        let version = runtime
+            .version(v1::VersionRequest::default())
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(version.runtime_name, "libcrun-shim");
+        assert_eq!(version.runtime_api_version, "v1");
+
+        let status = runtime
+            .status(v1::StatusRequest::default())
+            .await
+            .unwrap()
+            .into_inner()
+            .status
+            .unwrap();
+        assert!(status.conditions.iter().all(|condition| condition.status));
+
+        // kubelet pulls an image it gets no status for
+        let image = images
+            .image_status(v1::ImageStatusRequest {
+                image: Some(v1::ImageSpec {
+                    image: "busybox:latest".to_string(),
+                    ..Default::default()
+                }),
+                verbose: false,
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(image.image.is_none());
+
+        let missing = runtime
+            .container_status(v1::ContainerStatusRequest {
+                container_id: "missing".to_string(),
+                verbose: false,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(missing.code(), tonic::Code::NotFound);
+
+        let _ = stop.send(());
+        server.await.unwrap().unwrap();
+        assert!(!socket.exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,345 @@
+//! What kubelet said about the sandboxes and containers it created
+//!
+//! Names, labels, annotations, images and the pod a container belongs to
+//! have no place in the runtime, so the CRI server keeps them in a JSON file
+//! that outlives it. kubelet would otherwise find containers without pods
+//! after a restart, and remove them.
+
+use super::{ContainerMetadata, ImageSpec, Mount, NamespaceOption, PodSandboxMetadata};
+use crate::error::{Result, ShimError};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// A pod sandbox created through the runtime service
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SandboxRecord {
+    pub id: String,
+    pub metadata: PodSandboxMetadata,
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+    #[serde(default)]
+    pub annotations: HashMap<String, String>,
+    #[serde(default)]
+    pub runtime_handler: String,
+    /// Nanoseconds since the Unix epoch, as all CRI times are
+    pub created_at: i64,
+    #[serde(default)]
+    pub log_directory: String,
+    /// The namespaces kubelet asked the pod to have
+    #[serde(default)]
+    pub namespace_options: NamespaceOption,
+    /// Network namespace of the sandbox, which the pod's containers join
+    #[serde(default)]
+    pub netns_path: String,
+}
+
+/// A container created through the runtime service
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContainerRecord {
+    pub id: String,
+    pub pod_sandbox_id: String,
+    pub metadata: ContainerMetadata,
+    pub image: ImageSpec,
+    /// ID of the image the container was created from
+    #[serde(default)]
+    pub image_ref: String,
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+    #[serde(default)]
+    pub annotations: HashMap<String, String>,
+    #[serde(default)]
+    pub mounts: Vec<Mount>,
+    #[serde(default)]
+    pub log_path: String,
+    pub created_at: i64,
+    #[serde(default)]
+    pub started_at: i64,
+    #[serde(default)]
+    pub finished_at: i64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StoreFile {
+    #[serde(default)]
+    sandboxes: HashMap<String, SandboxRecord>,
+    #[serde(default)]
+    containers: HashMap<String, ContainerRecord>,
+}
+
+/// The sandboxes and containers of the CRI server, and which sandbox each
+/// container belongs to
+///
+/// Every change is written through to the file, if there is one.
+#[derive(Debug)]
+pub struct SandboxStore {
+    path: Option<PathBuf>,
+    file: Mutex<StoreFile>,
+}
+
+impl SandboxStore {
+    /// Open the store at `path`, which is created with the first change
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let file = match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data).map_err(|e| {
+                ShimError::serialization(format!("Failed to decode {}", path.display()), e)
+            })?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => StoreFile::default(),
+            Err(e) => {
+                return Err(ShimError::io_with_context(
+                    e,
+                    format!("Failed to read {}", path.display()),
+                ))
+            }
+        };
+        Ok(Self {
+            path: Some(path),
+            file: Mutex::new(file),
+        })
+    }
+
+    /// A store kept in memory only
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            file: Mutex::new(StoreFile::default()),
+        }
+    }
+
+    /// Default location of the store
+    pub fn default_path() -> PathBuf {
+        dirs::data_local_dir()
+            .unwrap_or_else(|| PathBuf::from("/var/lib"))
+            .join("libcrun-shim")
+            .join("cri")
+            .join("sandboxes.json")
+    }
+
+    pub fn sandbox(&self, id: &str) -> Option<SandboxRecord> {
+        self.lock().sandboxes.get(id).cloned()
+    }
+
+    /// All sandboxes, oldest first
+    pub fn sandboxes(&self) -> Vec<SandboxRecord> {
+        let mut sandboxes: Vec<SandboxRecord> = self.lock().sandboxes.values().cloned().collect();
+        sandboxes.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+        sandboxes
+    }
+
+    pub fn container(&self, id: &str) -> Option<ContainerRecord> {
+        self.lock().containers.get(id).cloned()
+    }
+
+    /// All containers, oldest first
+    pub fn containers(&self) -> Vec<ContainerRecord> {
+        let mut containers: Vec<ContainerRecord> =
+            self.lock().containers.values().cloned().collect();
+        containers.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+        containers
+    }
+
+    /// IDs of the containers in the sandbox `pod_sandbox_id`, oldest first
+    pub fn sandbox_containers(&self, pod_sandbox_id: &str) -> Vec<String> {
+        self.containers()
+            .into_iter()
+            .filter(|container| container.pod_sandbox_id == pod_sandbox_id)
+            .map(|container| container.id)
+            .collect()
+    }
+
+    pub fn add_sandbox(&self, record: SandboxRecord) -> Result<()> {
+        self.change(|file| {
+            file.sandboxes.insert(record.id.clone(), record);
+        })
+    }
+
+    /// Add a container to its sandbox, which must be in the store
+    pub fn add_container(&self, record: ContainerRecord) -> Result<()> {
+        let mut file = self.lock();
+        if !file.sandboxes.contains_key(&record.pod_sandbox_id) {
+            return Err(ShimError::not_found(format!(
+                "Pod sandbox '{}'",
+                record.pod_sandbox_id
+            )));
+        }
+        file.containers.insert(record.id.clone(), record);
+        self.save(&file)
+    }
+
+    /// Change the container `id`, if it is in the store
+    pub fn update_container(
+        &self,
+        id: &str,
+        update: impl FnOnce(&mut ContainerRecord),
+    ) -> Result<()> {
+        self.change(|file| {
+            if let Some(record) = file.containers.get_mut(id) {
+                update(record);
+            }
+        })
+    }
+
+    /// Remove a sandbox and its containers
+    pub fn remove_sandbox(&self, id: &str) -> Result<()> {
+        self.change(|file| {
+            file.sandboxes.remove(id);
+            file.containers
+                .retain(|_, container| container.pod_sandbox_id != id);
+        })
+    }
+
+    pub fn remove_container(&self, id: &str) -> Result<()> {
+        self.change(|file| {
+            file.containers.remove(id);
+        })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, StoreFile> {
+        self.file.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn change(&self, change: impl FnOnce(&mut StoreFile)) -> Result<()> {
+        let mut file = self.lock();
+        change(&mut file);
+        self.save(&file)
+    }
+
+    fn save(&self, file: &StoreFile) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| {
+                ShimError::io_with_context(e, format!("Failed to create {}", parent.display()))
+            })?;
+        }
+        let data = serde_json::to_vec_pretty(file)
+            .map_err(|e| ShimError::serialization("Failed to encode sandboxes.json", e))?;
+        write_atomic(path, &data)
+    }
+}
+
+/// Replace `path` with `data`, so a crash leaves either the old or the new
+/// contents
+fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let partial = path.with_extension("json.partial");
+    let written = std::fs::File::create(&partial)
+        .and_then(|mut file| file.write_all(data))
+        .and_then(|_| std::fs::rename(&partial, path));
+    written.map_err(|e| {
+        let _ = std::fs::remove_file(&partial);
+        ShimError::io_with_context(e, format!("Failed to write {}", path.display()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sandbox(id: &str, created_at: i64) -> SandboxRecord {
+        SandboxRecord {
+            id: id.to_string(),
+            metadata: PodSandboxMetadata {
+                name: "web".to_string(),
+                uid: "uid".to_string(),
+                namespace: "default".to_string(),
+                attempt: 0,
+            },
+            labels: HashMap::from([("app".to_string(), "web".to_string())]),
+            annotations: HashMap::new(),
+            runtime_handler: String::new(),
+            created_at,
+            log_directory: "/var/log/pods/web".to_string(),
+            namespace_options: NamespaceOption::default(),
+            netns_path: "/proc/42/ns/net".to_string(),
+        }
+    }
+
+    fn container(id: &str, pod_sandbox_id: &str, created_at: i64) -> ContainerRecord {
+        ContainerRecord {
+            id: id.to_string(),
+            pod_sandbox_id: pod_sandbox_id.to_string(),
+            metadata: ContainerMetadata {
+                name: id.to_string(),
+                attempt: 0,
+            },
+            image: ImageSpec {
+                image: "nginx:latest".to_string(),
+                annotations: HashMap::new(),
+            },
+            image_ref: "sha256:abc".to_string(),
+            labels: HashMap::new(),
+            annotations: HashMap::new(),
+            mounts: vec![],
+            log_path: String::new(),
+            created_at,
+            started_at: 0,
+            finished_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_sandbox_store() {
+        let store = SandboxStore::in_memory();
+        store.add_sandbox(sandbox("pod-a", 1)).unwrap();
+        store.add_sandbox(sandbox("pod-b", 2)).unwrap();
+        store.add_container(container("app", "pod-a", 4)).unwrap();
+        store
+            .add_container(container("sidecar", "pod-a", 3))
+            .unwrap();
+        store.add_container(container("db", "pod-b", 5)).unwrap();
+        // A container needs its sandbox
+        assert!(store
+            .add_container(container("stray", "pod-c", 6))
+            .unwrap_err()
+            .is_not_found());
+
+        assert_eq!(store.sandbox_containers("pod-a"), ["sidecar", "app"]);
+        assert_eq!(store.container("db").unwrap().pod_sandbox_id, "pod-b");
+
+        store
+            .update_container("app", |record| record.started_at = 7)
+            .unwrap();
+        assert_eq!(store.container("app").unwrap().started_at, 7);
+
+        store.remove_sandbox("pod-a").unwrap();
+        assert!(store.sandbox("pod-a").is_none());
+        assert!(store.container("app").is_none());
+        let ids: Vec<String> = store.containers().into_iter().map(|c| c.id).collect();
+        assert_eq!(ids, ["db"]);
+    }
+
+    #[test]
+    fn test_sandbox_store_persists() {
+        let dir = std::env::temp_dir().join(format!("cri-store-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("sandboxes.json");
+
+        let store = SandboxStore::open(&path).unwrap();
+        assert!(store.sandboxes().is_empty());
+        store.add_sandbox(sandbox("pod-a", 1)).unwrap();
+        store.add_container(container("app", "pod-a", 2)).unwrap();
+        store
+            .update_container("app", |record| record.finished_at = 3)
+            .unwrap();
+        drop(store);
+
+        let store = SandboxStore::open(&path).unwrap();
+        let reopened = store.sandbox("pod-a").unwrap();
+        assert_eq!(reopened.metadata.name, "web");
+        assert_eq!(reopened.labels["app"], "web");
+        assert_eq!(reopened.netns_path, "/proc/42/ns/net");
+        let app = store.container("app").unwrap();
+        assert_eq!(app.pod_sandbox_id, "pod-a");
+        assert_eq!(app.finished_at, 3);
+        assert!(!path.with_extension("json.partial").exists());
+
+        std::fs::write(&path, "not json").unwrap();
+        assert!(SandboxStore::open(&path).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
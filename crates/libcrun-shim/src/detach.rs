@@ -0,0 +1,159 @@
+//! Detach keys of interactive exec sessions
+//!
+//! Typing the keys detaches from a session instead of sending them to the
+//! command, which keeps running in the VM's agent until it is attached
+//! again, see [`crate::ContainerRuntime::attach_exec`].
+
+use crate::{Result, ShimError};
+
+/// A sequence of keys, written as in Docker: `ctrl-p,ctrl-q`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DetachKeys(Vec<u8>);
+
+impl DetachKeys {
+    /// The bytes a terminal sends for the keys
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// A filter for input typed to a session, see [`DetachFilter::feed`]
+    pub fn filter(&self) -> DetachFilter {
+        DetachFilter {
+            keys: self.0.clone(),
+            held: 0,
+        }
+    }
+}
+
+impl std::str::FromStr for DetachKeys {
+    type Err = ShimError;
+
+    /// Parse keys separated by commas, each a single character or `ctrl-`
+    /// followed by a letter or one of `@[\]^_`
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = |key: &str| {
+            ShimError::validation(
+                "detach_keys",
+                format!(
+                    "invalid key '{}', expected a character or ctrl-<letter>",
+                    key
+                ),
+            )
+        };
+        let mut keys = Vec::new();
+        for key in s.split(',') {
+            let byte = match key.strip_prefix("ctrl-") {
+                Some(name) if name.len() == 1 => match name.as_bytes()[0].to_ascii_uppercase() {
+                    c @ (b'@'..=b'_') => c - b'@',
+                    _ => return Err(invalid(key)),
+                },
+                _ if key.len() == 1 && key.is_ascii() => key.as_bytes()[0],
+                _ => return Err(invalid(key)),
+            };
+            keys.push(byte);
+        }
+        Ok(Self(keys))
+    }
+}
+
+impl std::fmt::Display for DetachKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, &byte) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            match byte {
+                0..=0x1f => write!(f, "ctrl-{}", ((byte + b'@') as char).to_ascii_lowercase())?,
+                _ => write!(f, "{}", byte as char)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Finds detach keys in a session's input
+///
+/// Input that could be the start of the keys is held back until the next
+/// input shows whether it is.
+pub struct DetachFilter {
+    keys: Vec<u8>,
+    /// Keys typed so far
+    held: usize,
+}
+
+impl DetachFilter {
+    /// Add the input to send to the command to `out`, returning true once
+    /// the keys were typed; nothing after them is sent
+    pub fn feed(&mut self, input: &[u8], out: &mut Vec<u8>) -> bool {
+        input.iter().any(|&byte| self.push(byte, out))
+    }
+
+    /// Input held back, sent when there is no more of it
+    pub fn flush(&mut self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.keys[..self.held]);
+        self.held = 0;
+    }
+
+    fn push(&mut self, byte: u8, out: &mut Vec<u8>) -> bool {
+        if byte == self.keys[self.held] {
+            self.held += 1;
+            return self.held == self.keys.len();
+        }
+        if self.held == 0 {
+            out.push(byte);
+            return false;
+        }
+        // Not the keys after all: the first key held back is input, and
+        // the rest may still start them
+        let rest = self.keys[1..self.held].to_vec();
+        out.push(self.keys[0]);
+        self.held = 0;
+        for byte in rest.into_iter().chain(std::iter::once(byte)) {
+            self.push(byte, out);
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_detach_keys() {
+        let keys: DetachKeys = "ctrl-x,ctrl-Y,q".parse().unwrap();
+        assert_eq!(keys.as_bytes(), [0x18, 0x19, b'q']);
+        assert_eq!(keys.to_string(), "ctrl-x,ctrl-y,q");
+        let keys: DetachKeys = "ctrl-@,ctrl-]".parse().unwrap();
+        assert_eq!(keys.as_bytes(), [0x00, 0x1d]);
+
+        for invalid in ["", "ctrl-", "ctrl-1", "ctrl-xy", "ab", "ctrl-x,"] {
+            assert!(invalid.parse::<DetachKeys>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_detach_filter() {
+        let keys: DetachKeys = "ctrl-p,ctrl-q".parse().unwrap();
+        let mut filter = keys.filter();
+        let mut out = Vec::new();
+        assert!(!filter.feed(b"ls\x10", &mut out));
+        assert_eq!(out, b"ls");
+        // A held key that turns out to be input is sent after all
+        assert!(!filter.feed(b"\x10\x10", &mut out));
+        assert_eq!(out, b"ls\x10\x10");
+        assert!(filter.feed(b"\x11 -l", &mut out));
+        assert_eq!(out, b"ls\x10\x10");
+
+        let keys: DetachKeys = "a,a,b".parse().unwrap();
+        let mut filter = keys.filter();
+        let mut out = Vec::new();
+        assert!(filter.feed(b"xaaab", &mut out));
+        assert_eq!(out, b"xa");
+        let mut filter = keys.filter();
+        out.clear();
+        assert!(!filter.feed(b"aa", &mut out));
+        filter.flush(&mut out);
+        assert_eq!(out, b"aa");
+    }
+}
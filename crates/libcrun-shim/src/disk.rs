@@ -0,0 +1,190 @@
+//! VM disk images on the host
+//!
+//! Raw images are sparse files: growing one costs no host space until the
+//! guest writes to it, and blocks the guest trims are handed back. Resize
+//! while the VM is stopped; the guest sees the new size on its next boot and
+//! the filesystem on the disk still has to be grown from inside (e.g. with
+//! `resize2fs`).
+
+use crate::error::{Result, ShimError};
+use crate::types::{DiskImageInfo, VmDiskConfig};
+use std::io::Read;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+/// Magic at the start of a qcow2 image
+const QCOW2_MAGIC: &[u8; 4] = b"QFI\xfb";
+
+/// Sizes are rounded up to this, which every block size divides
+const SIZE_ALIGNMENT: u64 = 1024 * 1024;
+
+/// Describe the image behind a configured VM disk
+pub fn inspect(disk: &VmDiskConfig) -> Result<DiskImageInfo> {
+    let metadata = std::fs::metadata(&disk.path).map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => ShimError::NotFound {
+            resource: format!("VM disk {}", disk.path.display()),
+            context: Some(if disk.create_if_missing {
+                "The image is created when the VM first boots".to_string()
+            } else {
+                "Create the image or fix the path in vm_disks".to_string()
+            }),
+        },
+        _ => ShimError::io_with_context(e, format!("Failed to read {}", disk.path.display())),
+    })?;
+
+    let size_bytes = if disk.format == "qcow2" {
+        qcow2_size(disk)?
+    } else {
+        metadata.len()
+    };
+    Ok(DiskImageInfo {
+        path: disk.path.clone(),
+        format: disk.format.clone(),
+        size_bytes,
+        // st_blocks is always in 512-byte units
+        allocated_bytes: metadata.blocks() * 512,
+        read_only: disk.read_only,
+    })
+}
+
+/// Virtual size from a qcow2 header
+fn qcow2_size(disk: &VmDiskConfig) -> Result<u64> {
+    let mut header = [0u8; 32];
+    std::fs::File::open(&disk.path)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .map_err(|e| {
+            ShimError::io_with_context(e, format!("Failed to read {}", disk.path.display()))
+        })?;
+    if &header[..4] != QCOW2_MAGIC {
+        return Err(ShimError::validation(
+            "format",
+            format!("{} is not a qcow2 image", disk.path.display()),
+        ));
+    }
+    Ok(u64::from_be_bytes(header[24..32].try_into().unwrap()))
+}
+
+/// Grow a raw VM disk image to `size_bytes`, rounded up to a whole MiB
+///
+/// Shrinking is refused since it would cut off whatever the guest stored at
+/// the end of the disk.
+pub fn resize(disk: &VmDiskConfig, size_bytes: u64) -> Result<DiskImageInfo> {
+    if disk.read_only {
+        return Err(ShimError::validation(
+            "disk",
+            format!("{} is attached read-only", disk.path.display()),
+        ));
+    }
+    if disk.format != "raw" {
+        return Err(ShimError::validation(
+            "format",
+            format!(
+                "Cannot resize {} images; use `qemu-img resize` on {}",
+                disk.format,
+                disk.path.display()
+            ),
+        ));
+    }
+
+    let current = inspect(disk)?;
+    let size_bytes = size_bytes.div_ceil(SIZE_ALIGNMENT) * SIZE_ALIGNMENT;
+    if size_bytes < current.size_bytes {
+        return Err(ShimError::validation(
+            "size",
+            format!(
+                "{} is {} bytes; shrinking it to {} bytes would lose data",
+                disk.path.display(),
+                current.size_bytes,
+                size_bytes
+            ),
+        ));
+    }
+
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(&disk.path)
+        .and_then(|file| file.set_len(size_bytes))
+        .map_err(|e| {
+            ShimError::io_with_context(e, format!("Failed to resize {}", disk.path.display()))
+        })?;
+    log::info!(
+        "Resized {} from {} to {} bytes",
+        disk.path.display(),
+        current.size_bytes,
+        size_bytes
+    );
+    inspect(disk)
+}
+
+/// Space unprivileged users may still use on the filesystem holding `path`
+pub(crate) fn free_bytes(path: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    // SAFETY: statvfs only writes to the struct it is given
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn disk(name: &str) -> VmDiskConfig {
+        let dir = std::env::temp_dir().join(format!("disk-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        VmDiskConfig {
+            path: dir.join(name),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_resize_raw() {
+        let disk = disk("data.img");
+        assert!(inspect(&disk).unwrap_err().is_not_found());
+
+        std::fs::File::create(&disk.path)
+            .unwrap()
+            .set_len(64 * 1024 * 1024)
+            .unwrap();
+        let info = resize(&disk, 100 * 1024 * 1024 + 1).unwrap();
+        assert_eq!(info.size_bytes, 101 * 1024 * 1024);
+        // Growing a sparse file allocates nothing
+        assert!(info.allocated_bytes < info.size_bytes);
+
+        let err = resize(&disk, 1024 * 1024).unwrap_err();
+        assert!(matches!(err, ShimError::Validation { ref field, .. } if field == "size"));
+        assert_eq!(inspect(&disk).unwrap().size_bytes, 101 * 1024 * 1024);
+
+        let read_only = VmDiskConfig {
+            read_only: true,
+            ..disk.clone()
+        };
+        assert!(resize(&read_only, 200 * 1024 * 1024).is_err());
+        let _ = std::fs::remove_file(&disk.path);
+    }
+
+    #[test]
+    fn test_inspect_qcow2() {
+        let disk = VmDiskConfig {
+            format: "qcow2".to_string(),
+            ..disk("data.qcow2")
+        };
+        let mut header = QCOW2_MAGIC.to_vec();
+        header.extend_from_slice(&3u32.to_be_bytes());
+        header.resize(24, 0);
+        header.extend_from_slice(&(40u64 << 30).to_be_bytes());
+        std::fs::write(&disk.path, &header).unwrap();
+
+        let info = inspect(&disk).unwrap();
+        assert_eq!(info.size_bytes, 40 << 30);
+        assert_eq!(info.format, "qcow2");
+        // Only raw images can be resized here
+        assert!(resize(&disk, 80 << 30).is_err());
+        let _ = std::fs::remove_file(&disk.path);
+    }
+}
@@ -0,0 +1,562 @@
+//! Self-diagnostics behind `crun-shim doctor`
+//!
+//! Each check looks at one layer of the stack, from the host's
+//! virtualization support up to name lookups in the VM, and says how to fix
+//! what it finds broken. Checks that need a layer an earlier check found
+//! broken are skipped instead of failing with a less helpful error.
+
+use crate::image::ImageStore;
+use crate::types::RuntimeConfig;
+use serde::{Deserialize, Serialize};
+
+/// Host name looked up to check DNS, the registry Docker Hub pulls go to
+pub const DNS_PROBE_HOST: &str = "registry-1.docker.io";
+
+/// Hint for checks an older agent cannot answer
+#[cfg(target_os = "macos")]
+const UPGRADE_AGENT: &str = "Upgrade the agent with `crun-shim agent upgrade <binary>`";
+
+/// cgroup controllers the resource limits of containers are enforced with
+const REQUIRED_CONTROLLERS: &[&str] = &["cpu", "memory", "pids"];
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Pass,
+    /// Usable, but something is missing or out of date
+    Warn,
+    Fail,
+    /// Not run because a check it depends on failed
+    Skip,
+}
+
+/// Outcome of one check
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Check {
+    /// Short name, e.g. "agent" or "dns"
+    pub name: String,
+    pub status: Status,
+    /// What the check found
+    pub detail: String,
+    /// How to fix a warning or failure
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn new(name: &str, status: Status, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn pass(name: &str, detail: impl Into<String>) -> Self {
+        Self::new(name, Status::Pass, detail)
+    }
+
+    fn warn(name: &str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self::new(name, Status::Warn, detail).with_hint(hint)
+    }
+
+    fn fail(name: &str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self::new(name, Status::Fail, detail).with_hint(hint)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn skip(name: &str, detail: impl Into<String>) -> Self {
+        Self::new(name, Status::Skip, detail)
+    }
+
+    fn with_hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+}
+
+/// The checks in the order they ran
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    /// Checks with the given outcome
+    pub fn count(&self, status: Status) -> usize {
+        self.checks.iter().filter(|c| c.status == status).count()
+    }
+
+    /// Whether no check failed; warnings and skipped checks are fine
+    pub fn healthy(&self) -> bool {
+        self.count(Status::Fail) == 0
+    }
+}
+
+/// Run every check for the runtime `config` describes
+///
+/// On macOS this connects to the agent like any client, booting the VM if
+/// no other process has. The connection is read-only, so nothing in the VM
+/// is changed.
+pub async fn diagnose(config: RuntimeConfig) -> Report {
+    let mut checks = Vec::new();
+
+    #[cfg(target_os = "linux")]
+    {
+        checks.push(libcrun_check());
+        checks.push(match host_controllers() {
+            Some((cgroup_v2, controllers)) => cgroup_check(
+                cgroup_v2,
+                &controllers,
+                "Enable them in /sys/fs/cgroup/cgroup.subtree_control, or boot with systemd.unified_cgroup_hierarchy=1",
+            ),
+            None => Check::fail(
+                "cgroups",
+                "No cgroup hierarchy is mounted",
+                "Mount cgroup2 at /sys/fs/cgroup",
+            ),
+        });
+        checks.push(dns_check(lookup(DNS_PROBE_HOST), "host"));
+    }
+
+    #[cfg(target_os = "macos")]
+    vm_checks(&config, &mut checks).await;
+
+    checks.push(host_disk_check(
+        &ImageStore::default_path(),
+        config.resource_guard.min_free_disk_bytes,
+    ));
+    Report { checks }
+}
+
+#[cfg(target_os = "macos")]
+async fn vm_checks(config: &RuntimeConfig, checks: &mut Vec<Check>) {
+    use crate::macos::vm::VirtualMachine;
+    use crate::ContainerRuntime;
+    use libcrun_shim_proto::features;
+
+    checks.push(virtualization_check());
+
+    let search_paths = config.get_vm_asset_search_paths();
+    let missing: Vec<&str> = ["kernel", "initramfs.cpio.gz"]
+        .into_iter()
+        .filter(|name| VirtualMachine::find_vm_asset(name, &search_paths).is_none())
+        .collect();
+    checks.push(if missing.is_empty() {
+        Check::pass("vm-assets", "Kernel and initramfs found")
+    } else {
+        Check::warn(
+            "vm-assets",
+            format!(
+                "{} not found, so a VM started elsewhere must be listening on {}",
+                missing.join(" and "),
+                config.socket_path.display()
+            ),
+            "Build them with `make vm-image` and add vm-image/output to vm_asset_paths",
+        )
+    });
+
+    const AGENT_CHECKS: &[&str] = &["protocol", "libcrun", "cgroups", "dns", "vm-disk"];
+    let runtime = match ContainerRuntime::new_read_only_with_config(config.clone()).await {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            let hint = if e.is_permission_denied() {
+                "Check the owner and mode of the agent socket, or run as a user allowed to use it"
+            } else {
+                "Run with --verbose to see why the VM did not boot, or check that the agent listens on the socket"
+            };
+            checks.push(Check::fail("agent", e.to_string(), hint));
+            for name in AGENT_CHECKS {
+                checks.push(Check::skip(name, "The agent is not reachable"));
+            }
+            return;
+        }
+    };
+    let info = match runtime.agent_info().await {
+        Ok(info) => info,
+        Err(e) => {
+            checks.push(Check::fail(
+                "agent",
+                e.to_string(),
+                "Restart the VM with `crun-shim shutdown` and try again",
+            ));
+            for name in AGENT_CHECKS {
+                checks.push(Check::skip(name, "The agent is not reachable"));
+            }
+            return;
+        }
+    };
+    let reached_over = match runtime.runtime_status().await.transport {
+        Some(crate::types::Transport::Unix) | None => config.socket_path.display().to_string(),
+        Some(crate::types::Transport::Vsock) => format!("vsock port {}", config.vsock_port),
+    };
+    checks.push(Check::pass(
+        "agent",
+        format!("Agent {} at {}", info.version, reached_over),
+    ));
+
+    let host_protocol = libcrun_shim_proto::PROTOCOL_VERSION;
+    checks.push(if info.protocol_version >= host_protocol {
+        Check::pass("protocol", format!("Protocol {}", info.protocol_version))
+    } else {
+        Check::warn(
+            "protocol",
+            format!(
+                "The agent speaks protocol {}, the host {}; newer features are unavailable",
+                info.protocol_version, host_protocol
+            ),
+            UPGRADE_AGENT,
+        )
+    });
+
+    checks.push(match runtime.agent_build_info().await {
+        Ok(build) => match build.libcrun_version {
+            Some(version) => Check::pass("libcrun", format!("libcrun {} in the VM", version)),
+            None => Check::fail(
+                "libcrun",
+                "The agent was built without libcrun, so containers are not really run",
+                "Rebuild the agent with libcrun installed (`make agent-static`)",
+            ),
+        },
+        Err(e) => Check::warn(
+            "libcrun",
+            format!("The agent does not report its build: {}", e),
+            UPGRADE_AGENT,
+        ),
+    });
+
+    checks.push(match &info.kernel {
+        Some(kernel) => cgroup_check(
+            kernel.cgroup_v2,
+            &kernel.cgroup_controllers,
+            "Use a VM kernel built with them (see vm-image/)",
+        ),
+        None => Check::warn(
+            "cgroups",
+            "The agent does not report the VM kernel's features",
+            UPGRADE_AGENT,
+        ),
+    });
+
+    checks.push(if info.supports(features::RESOLVE) {
+        dns_check(runtime.resolve(DNS_PROBE_HOST).await, "VM")
+    } else {
+        Check::warn("dns", "The agent cannot look up names", UPGRADE_AGENT)
+    });
+
+    let min_free = config.resource_guard.min_free_disk_bytes;
+    if !info.supports(features::DISKS) {
+        checks.push(Check::warn(
+            "vm-disk",
+            "The agent does not report disk usage",
+            UPGRADE_AGENT,
+        ));
+        return;
+    }
+    checks.push(match runtime.guest_disk_usage().await {
+        Ok(filesystems) => {
+            // Only disks fill up; tmpfs and the like are sized from memory
+            let full: Vec<String> = filesystems
+                .iter()
+                .filter(|fs| fs.device.starts_with("/dev/") && fs.available_bytes < min_free)
+                .map(|fs| {
+                    format!(
+                        "{} has {} MiB free",
+                        fs.mount_point,
+                        fs.available_bytes / 1024 / 1024
+                    )
+                })
+                .collect();
+            if full.is_empty() {
+                Check::pass(
+                    "vm-disk",
+                    format!("{} filesystems with room to spare", filesystems.len()),
+                )
+            } else {
+                Check::fail(
+                    "vm-disk",
+                    full.join(", "),
+                    "Free space with `crun-shim prune`, or grow the disk with `crun-shim vm disk resize`",
+                )
+            }
+        }
+        Err(e) => Check::warn(
+            "vm-disk",
+            format!("Cannot read the VM's disk usage: {}", e),
+            "Check the free space with `crun-shim system df`",
+        ),
+    });
+}
+
+/// Whether Virtualization.framework is there and this binary may use it
+#[cfg(target_os = "macos")]
+fn virtualization_check() -> Check {
+    const NAME: &str = "virtualization";
+    if !crate::macos::vm::VirtualMachine::is_virtualization_available() {
+        return Check::fail(
+            NAME,
+            "Virtualization.framework is not available",
+            "Use macOS 11 or later on a Mac that supports virtualization",
+        );
+    }
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => {
+            return Check::warn(
+                NAME,
+                format!("Cannot find this binary to check its entitlements: {}", e),
+                "Run crun-shim by its path",
+            )
+        }
+    };
+    let sign = format!(
+        "Sign it with `codesign --force --sign - --entitlements entitlements.plist {}`",
+        exe.display()
+    );
+    let output = std::process::Command::new("codesign")
+        .args(["-d", "--entitlements", "-", "--xml"])
+        .arg(&exe)
+        .output();
+    match output {
+        Ok(output) if output.status.success() => {
+            if has_virtualization_entitlement(&String::from_utf8_lossy(&output.stdout)) {
+                Check::pass(NAME, "Signed with the virtualization entitlement")
+            } else {
+                Check::fail(
+                    NAME,
+                    format!(
+                        "{} lacks the com.apple.security.virtualization entitlement",
+                        exe.display()
+                    ),
+                    sign,
+                )
+            }
+        }
+        Ok(output) => Check::fail(
+            NAME,
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            sign,
+        ),
+        Err(e) => Check::warn(
+            NAME,
+            format!("Cannot run codesign to check the entitlement: {}", e),
+            "Install the Xcode command line tools",
+        ),
+    }
+}
+
+/// Whether `codesign -d --entitlements - --xml` output grants the
+/// virtualization entitlement
+#[cfg(target_os = "macos")]
+fn has_virtualization_entitlement(entitlements: &str) -> bool {
+    let Some((_, after)) = entitlements.split_once("com.apple.security.virtualization") else {
+        return false;
+    };
+    after
+        .trim_start()
+        .strip_prefix("</key>")
+        .is_some_and(|value| value.trim_start().starts_with("<true/>"))
+}
+
+/// Whether libcrun is linked in and can be initialized
+#[cfg(target_os = "linux")]
+fn libcrun_check() -> Check {
+    use libcrun_sys::safe as crun;
+
+    match crun::context_new() {
+        Ok(context) => {
+            crun::context_free(context);
+            Check::pass(
+                "libcrun",
+                format!(
+                    "libcrun {}",
+                    libcrun_sys::LIBCRUN_VERSION.unwrap_or("(unknown version)")
+                ),
+            )
+        }
+        Err(e) => Check::fail(
+            "libcrun",
+            format!("{}; containers are only simulated in memory", e),
+            "Install libcrun-dev (Debian, Ubuntu) or crun-devel (Fedora) and rebuild",
+        ),
+    }
+}
+
+/// Whether the cgroup v2 hierarchy is mounted and its enabled controllers,
+/// or the enabled cgroup v1 controllers
+#[cfg(target_os = "linux")]
+fn host_controllers() -> Option<(bool, Vec<String>)> {
+    if let Ok(controllers) = std::fs::read_to_string("/sys/fs/cgroup/cgroup.controllers") {
+        return Some((
+            true,
+            controllers.split_whitespace().map(str::to_string).collect(),
+        ));
+    }
+    // "subsys_name hierarchy num_cgroups enabled"
+    let cgroups = std::fs::read_to_string("/proc/cgroups").ok()?;
+    let controllers: Vec<String> = cgroups
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            (fields.get(3) == Some(&"1")).then(|| fields[0].to_string())
+        })
+        .collect();
+    (!controllers.is_empty()).then_some((false, controllers))
+}
+
+/// Whether the controllers containers need are enabled
+fn cgroup_check(cgroup_v2: bool, controllers: &[String], hint: &str) -> Check {
+    const NAME: &str = "cgroups";
+    let version = if cgroup_v2 { "v2" } else { "v1" };
+    let missing: Vec<&str> = REQUIRED_CONTROLLERS
+        .iter()
+        .copied()
+        .filter(|required| !controllers.iter().any(|c| c == required))
+        .collect();
+    if missing.is_empty() {
+        return Check::pass(
+            NAME,
+            format!("cgroup {}: {}", version, controllers.join(" ")),
+        );
+    }
+    let detail = format!(
+        "cgroup {} lacks the {} controller(s), so limits on them fail",
+        version,
+        missing.join(", ")
+    );
+    if missing.len() == REQUIRED_CONTROLLERS.len() {
+        Check::fail(NAME, detail, hint)
+    } else {
+        Check::warn(NAME, detail, hint)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn lookup(host: &str) -> crate::Result<Vec<String>> {
+    use std::net::ToSocketAddrs;
+
+    let addresses = (host, 0)
+        .to_socket_addrs()
+        .map_err(|e| crate::ShimError::io_with_context(e, format!("Failed to resolve {}", host)))?;
+    let mut unique: Vec<String> = Vec::new();
+    for address in addresses.map(|a| a.ip().to_string()) {
+        if !unique.contains(&address) {
+            unique.push(address);
+        }
+    }
+    Ok(unique)
+}
+
+/// Outcome of looking up [`DNS_PROBE_HOST`] on the host or in the VM
+fn dns_check(resolved: crate::Result<Vec<String>>, place: &str) -> Check {
+    const NAME: &str = "dns";
+    match resolved {
+        Ok(addresses) if !addresses.is_empty() => Check::pass(
+            NAME,
+            format!(
+                "{} resolves to {} in the {}",
+                DNS_PROBE_HOST, addresses[0], place
+            ),
+        ),
+        Ok(_) => Check::fail(
+            NAME,
+            format!("{} has no addresses in the {}", DNS_PROBE_HOST, place),
+            "Check the nameservers in /etc/resolv.conf",
+        ),
+        Err(e) => Check::fail(
+            NAME,
+            e.to_string(),
+            if place == "VM" {
+                "Check vm_network in the runtime configuration and the VM's /etc/resolv.conf"
+            } else {
+                "Check the nameservers in /etc/resolv.conf"
+            },
+        ),
+    }
+}
+
+/// Whether the filesystem holding `path` has `min_free` bytes left
+fn host_disk_check(path: &std::path::Path, min_free: u64) -> Check {
+    const NAME: &str = "disk";
+    // The store is created with the first pull
+    let existing = path.ancestors().find(|p| p.exists()).unwrap_or(path);
+    let Some(free) = crate::disk::free_bytes(existing) else {
+        return Check::warn(
+            NAME,
+            format!("Cannot read the free space at {}", existing.display()),
+            "Check that the directory is readable",
+        );
+    };
+    let detail = format!("{} MiB free at {}", free / 1024 / 1024, existing.display());
+    if free < min_free {
+        Check::fail(
+            NAME,
+            format!("Only {}, {} MiB required", detail, min_free / 1024 / 1024),
+            "Free space with `crun-shim prune --images` or `crun-shim rmi`",
+        )
+    } else {
+        Check::pass(NAME, detail)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cgroup_check() {
+        let all = ["cpu", "memory", "pids", "io"].map(str::to_string);
+        assert_eq!(cgroup_check(true, &all, "").status, Status::Pass);
+        let partial = cgroup_check(false, &all[..2], "Enable pids");
+        assert_eq!(partial.status, Status::Warn);
+        assert!(partial.detail.contains("lacks the pids controller"));
+        assert_eq!(partial.hint.as_deref(), Some("Enable pids"));
+        assert_eq!(cgroup_check(true, &[], "").status, Status::Fail);
+    }
+
+    #[test]
+    fn test_dns_check() {
+        let resolved = dns_check(Ok(vec!["10.0.0.1".to_string()]), "VM");
+        assert_eq!(resolved.status, Status::Pass);
+        assert!(resolved.detail.contains("10.0.0.1 in the VM"));
+        assert_eq!(dns_check(Ok(Vec::new()), "host").status, Status::Fail);
+        let failed = dns_check(
+            Err(crate::ShimError::not_found("registry-1.docker.io")),
+            "VM",
+        );
+        assert_eq!(failed.status, Status::Fail);
+        assert!(failed.hint.unwrap().contains("vm_network"));
+    }
+
+    #[test]
+    fn test_host_disk_check() {
+        let missing = std::env::temp_dir().join("doctor-test").join("images");
+        assert_eq!(host_disk_check(&missing, 0).status, Status::Pass);
+        let full = host_disk_check(&missing, u64::MAX);
+        assert_eq!(full.status, Status::Fail);
+        assert!(full.hint.is_some());
+    }
+
+    #[test]
+    fn test_report() {
+        let mut report = Report {
+            checks: vec![
+                Check::pass("libcrun", "libcrun 1.14"),
+                Check::warn("cgroups", "lacks pids", "Enable it"),
+            ],
+        };
+        assert!(report.healthy());
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["checks"][0]["status"], "pass");
+        assert!(json["checks"][0].get("hint").is_none());
+        assert_eq!(json["checks"][1]["hint"], "Enable it");
+
+        report
+            .checks
+            .push(Check::fail("dns", "timed out", "Check it"));
+        assert!(!report.healthy());
+        assert_eq!(report.count(Status::Fail), 1);
+    }
+}
@@ -1,10 +1,15 @@
+use libcrun_shim_proto::{ErrorCode, ErrorProto, TransitionError};
 use std::fmt;
 
+/// An underlying error kept as the [`source`](std::error::Error::source) of a [`ShimError`]
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
 #[derive(Debug)]
 pub enum ShimError {
     Runtime {
         message: String,
         context: Option<String>,
+        source: Option<BoxError>,
     },
     Io {
         error: std::io::Error,
@@ -13,15 +18,46 @@ pub enum ShimError {
     Serialization {
         message: String,
         context: Option<String>,
+        source: Option<BoxError>,
     },
     NotFound {
         resource: String,
         context: Option<String>,
     },
+    /// The resource is in the wrong state for the operation
+    Conflict {
+        message: String,
+        context: Option<String>,
+    },
+    /// Temporarily unable to serve the request; retrying may succeed
+    Unavailable {
+        message: String,
+        context: Option<String>,
+    },
     Validation {
         field: String,
         message: String,
     },
+    /// Rejected by the agent's admission policy
+    PolicyDenied {
+        violations: Vec<PolicyViolation>,
+    },
+    /// Not allowed for this runtime handle, e.g. a read-only one
+    PermissionDenied {
+        message: String,
+        context: Option<String>,
+    },
+    /// Stopped on the caller's request before it finished
+    Cancelled {
+        message: String,
+    },
+}
+
+/// A policy rule that denied a request
+#[derive(Debug, Clone, PartialEq)]
+pub struct PolicyViolation {
+    pub rule: String,
+    pub message: String,
 }
 
 impl ShimError {
@@ -29,6 +65,7 @@ impl ShimError {
         ShimError::Runtime {
             message: msg.into(),
             context: None,
+            source: None,
         }
     }
 
@@ -36,6 +73,30 @@ impl ShimError {
         ShimError::Runtime {
             message: msg.into(),
             context: Some(ctx.into()),
+            source: None,
+        }
+    }
+
+    pub fn serialization<S: Into<String>, E: Into<BoxError>>(ctx: S, source: E) -> Self {
+        let source = source.into();
+        ShimError::Serialization {
+            message: source.to_string(),
+            context: Some(ctx.into()),
+            source: Some(source),
+        }
+    }
+
+    pub fn io_with_context<S: Into<String>>(error: std::io::Error, ctx: S) -> Self {
+        ShimError::Io {
+            error,
+            context: Some(ctx.into()),
+        }
+    }
+
+    pub fn conflict<S1: Into<String>, S2: Into<String>>(msg: S1, ctx: S2) -> Self {
+        ShimError::Conflict {
+            message: msg.into(),
+            context: Some(ctx.into()),
         }
     }
 
@@ -46,19 +107,121 @@ impl ShimError {
         }
     }
 
+    pub fn permission_denied<S1: Into<String>, S2: Into<String>>(msg: S1, ctx: S2) -> Self {
+        ShimError::PermissionDenied {
+            message: msg.into(),
+            context: Some(ctx.into()),
+        }
+    }
+
+    pub fn cancelled<S: Into<String>>(msg: S) -> Self {
+        ShimError::Cancelled {
+            message: msg.into(),
+        }
+    }
+
     pub fn validation<S1: Into<String>, S2: Into<String>>(field: S1, msg: S2) -> Self {
         ShimError::Validation {
             field: field.into(),
             message: msg.into(),
         }
     }
+
+    /// Attach the error that caused this one
+    ///
+    /// Only runtime and serialization errors carry a source; other errors are
+    /// returned unchanged.
+    pub fn with_source<E: Into<BoxError>>(mut self, error: E) -> Self {
+        if let ShimError::Runtime { source, .. } | ShimError::Serialization { source, .. } =
+            &mut self
+        {
+            *source = Some(error.into());
+        }
+        self
+    }
+
+    /// The container, pod or file the operation needs does not exist
+    pub fn is_not_found(&self) -> bool {
+        match self {
+            ShimError::NotFound { .. } => true,
+            ShimError::Io { error, .. } => error.kind() == std::io::ErrorKind::NotFound,
+            _ => false,
+        }
+    }
+
+    /// The resource is in the wrong state, e.g. it already exists or is running
+    pub fn is_conflict(&self) -> bool {
+        match self {
+            ShimError::Conflict { .. } => true,
+            ShimError::Io { error, .. } => error.kind() == std::io::ErrorKind::AlreadyExists,
+            _ => false,
+        }
+    }
+
+    /// The handle or connection is not allowed to perform the operation
+    pub fn is_permission_denied(&self) -> bool {
+        match self {
+            ShimError::PermissionDenied { .. } => true,
+            ShimError::Io { error, .. } => error.kind() == std::io::ErrorKind::PermissionDenied,
+            _ => false,
+        }
+    }
+
+    /// The operation was cancelled, e.g. an image pull stopped with Ctrl+C
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, ShimError::Cancelled { .. })
+    }
+
+    /// Retrying the operation may succeed
+    ///
+    /// True for errors the agent reports as unavailable and for I/O errors
+    /// anywhere in the source chain that indicate a dropped or busy
+    /// connection.
+    pub fn is_transient(&self) -> bool {
+        if matches!(self, ShimError::Unavailable { .. }) {
+            return true;
+        }
+        let mut next: Option<&(dyn std::error::Error + 'static)> = Some(self);
+        while let Some(error) = next {
+            if let Some(io) = error.downcast_ref::<std::io::Error>() {
+                if is_transient_io(io.kind()) {
+                    return true;
+                }
+            }
+            next = error.source();
+        }
+        false
+    }
+}
+
+fn is_transient_io(kind: std::io::ErrorKind) -> bool {
+    use std::io::ErrorKind::*;
+    matches!(
+        kind,
+        ConnectionRefused
+            | ConnectionReset
+            | ConnectionAborted
+            | NotConnected
+            | BrokenPipe
+            | TimedOut
+            | Interrupted
+            | WouldBlock
+            | UnexpectedEof
+    )
 }
 
 impl fmt::Display for ShimError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            ShimError::Runtime { message, context } => {
+            ShimError::Runtime {
+                message,
+                context,
+                source,
+            } => {
                 write!(f, "Runtime error: {}", message)?;
+                if let Some(source) = source {
+                    write!(f, ": {}", source)?;
+                }
                 if let Some(ctx) = context {
                     write!(f, " (context: {})", ctx)?;
                 }
@@ -71,7 +234,9 @@ impl fmt::Display for ShimError {
                 }
                 Ok(())
             }
-            ShimError::Serialization { message, context } => {
+            ShimError::Serialization {
+                message, context, ..
+            } => {
                 write!(f, "Serialization error: {}", message)?;
                 if let Some(ctx) = context {
                     write!(f, " (context: {})", ctx)?;
@@ -85,14 +250,54 @@ impl fmt::Display for ShimError {
                 }
                 Ok(())
             }
+            ShimError::Conflict { message, context } => {
+                write!(f, "Conflict: {}", message)?;
+                if let Some(ctx) = context {
+                    write!(f, " (context: {})", ctx)?;
+                }
+                Ok(())
+            }
+            ShimError::Unavailable { message, context } => {
+                write!(f, "Unavailable: {}", message)?;
+                if let Some(ctx) = context {
+                    write!(f, " (context: {})", ctx)?;
+                }
+                Ok(())
+            }
+            ShimError::PermissionDenied { message, context } => {
+                write!(f, "Permission denied: {}", message)?;
+                if let Some(ctx) = context {
+                    write!(f, " (context: {})", ctx)?;
+                }
+                Ok(())
+            }
             ShimError::Validation { field, message } => {
                 write!(f, "Validation error for field '{}': {}", field, message)
             }
+            ShimError::Cancelled { message } => write!(f, "Cancelled: {}", message),
+            ShimError::PolicyDenied { violations } => {
+                write!(f, "Denied by policy")?;
+                for (i, v) in violations.iter().enumerate() {
+                    let sep = if i == 0 { ": " } else { "; " };
+                    write!(f, "{}{}: {}", sep, v.rule, v.message)?;
+                }
+                Ok(())
+            }
         }
     }
 }
 
-impl std::error::Error for ShimError {}
+impl std::error::Error for ShimError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ShimError::Runtime { source, .. } | ShimError::Serialization { source, .. } => {
+                source.as_deref().map(|e| e as _)
+            }
+            ShimError::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
 
 impl From<std::io::Error> for ShimError {
     fn from(e: std::io::Error) -> Self {
@@ -105,11 +310,112 @@ impl From<std::io::Error> for ShimError {
 
 impl From<serde_json::Error> for ShimError {
     fn from(e: serde_json::Error) -> Self {
-        ShimError::Serialization {
-            message: e.to_string(),
-            context: Some("JSON parsing error".to_string()),
+        ShimError::serialization("JSON parsing error", e)
+    }
+}
+
+impl From<ErrorProto> for ShimError {
+    fn from(e: ErrorProto) -> Self {
+        match e.code {
+            ErrorCode::NotFound => ShimError::NotFound {
+                resource: e.message,
+                context: None,
+            },
+            ErrorCode::Conflict => ShimError::Conflict {
+                message: e.message,
+                context: None,
+            },
+            ErrorCode::InvalidArgument => ShimError::validation("request", e.message),
+            ErrorCode::Unavailable => ShimError::Unavailable {
+                message: e.message,
+                context: None,
+            },
+            ErrorCode::PermissionDenied => ShimError::PermissionDenied {
+                message: e.message,
+                context: None,
+            },
+            ErrorCode::Internal => ShimError::runtime(e.message),
         }
     }
 }
 
+impl From<TransitionError> for ShimError {
+    fn from(e: TransitionError) -> Self {
+        let context = match &e {
+            TransitionError::NotAllowed { from, .. } => format!("Current status: {}", from),
+            TransitionError::ExecsInFlight { .. } => {
+                "Wait for the exec sessions to finish before deleting it".to_string()
+            }
+        };
+        ShimError::conflict(e.to_string(), context)
+    }
+}
+
 pub type Result<T> = std::result::Result<T, ShimError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+    use std::io;
+
+    #[test]
+    fn test_source_chain() {
+        let io = io::Error::new(io::ErrorKind::ConnectionReset, "reset by peer");
+        let err = ShimError::runtime_with_context("RPC failed", "Container ID: c1").with_source(io);
+        assert_eq!(
+            err.to_string(),
+            "Runtime error: RPC failed: reset by peer (context: Container ID: c1)"
+        );
+        let source = err.source().unwrap().downcast_ref::<io::Error>().unwrap();
+        assert_eq!(source.kind(), io::ErrorKind::ConnectionReset);
+        assert!(err.is_transient());
+
+        // The chain is followed through nested shim errors
+        let outer = ShimError::runtime("Failed to list containers").with_source(err);
+        assert!(outer.is_transient());
+        assert!(!outer.is_not_found());
+
+        let err = ShimError::from(serde_json::from_str::<u32>("x").unwrap_err());
+        assert!(err.source().unwrap().is::<serde_json::Error>());
+        assert!(!err.is_transient());
+    }
+
+    #[test]
+    fn test_classification() {
+        assert!(ShimError::not_found("Container 'c1'").is_not_found());
+        assert!(ShimError::from(io::Error::from(io::ErrorKind::NotFound)).is_not_found());
+        assert!(ShimError::conflict("Container 'c1' is running", "Stop it first").is_conflict());
+        assert!(ShimError::from(io::Error::from(io::ErrorKind::TimedOut)).is_transient());
+        assert!(!ShimError::from(io::Error::from(io::ErrorKind::PermissionDenied)).is_transient());
+        assert!(!ShimError::validation("id", "empty").is_conflict());
+        assert!(ShimError::permission_denied("Runtime is read-only", "stop").is_permission_denied());
+        assert!(
+            ShimError::from(io::Error::from(io::ErrorKind::PermissionDenied))
+                .is_permission_denied()
+        );
+    }
+
+    #[test]
+    fn test_agent_error_codes() {
+        let agent = |code, message: &str| {
+            ShimError::from(ErrorProto {
+                code,
+                message: message.to_string(),
+            })
+        };
+        assert!(agent(ErrorCode::NotFound, "Container 'c1' not found").is_not_found());
+        assert!(agent(ErrorCode::Conflict, "Container 'c1' already exists").is_conflict());
+        assert!(agent(ErrorCode::Unavailable, "Agent is draining").is_transient());
+        assert!(matches!(
+            agent(ErrorCode::InvalidArgument, "Command cannot be empty"),
+            ShimError::Validation { .. }
+        ));
+        assert!(
+            agent(ErrorCode::PermissionDenied, "Connection is read-only").is_permission_denied()
+        );
+        let err = agent(ErrorCode::Internal, "libcrun failed");
+        assert!(!err.is_not_found() && !err.is_conflict() && !err.is_transient());
+        assert_eq!(err.to_string(), "Runtime error: libcrun failed");
+    }
+}
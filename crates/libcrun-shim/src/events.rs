@@ -2,6 +2,7 @@
 //!
 //! This module provides event streaming for container lifecycle events.
 
+use crate::support::Journal;
 use crate::types::{ContainerEvent, ContainerEventType};
 use std::sync::Arc;
 use tokio::sync::broadcast;
@@ -10,13 +11,24 @@ use tokio::sync::broadcast;
 #[derive(Clone)]
 pub struct EventBroadcaster {
     sender: broadcast::Sender<ContainerEvent>,
+    /// Where sent events are also recorded, for support bundles
+    journal: Option<Journal>,
 }
 
 impl EventBroadcaster {
     /// Create a new event broadcaster
     pub fn new(capacity: usize) -> Self {
         let (sender, _) = broadcast::channel(capacity);
-        Self { sender }
+        Self {
+            sender,
+            journal: None,
+        }
+    }
+
+    /// Also record every event sent in `journal`
+    pub fn with_journal(mut self, journal: Journal) -> Self {
+        self.journal = Some(journal);
+        self
     }
 
     /// Subscribe to events
@@ -28,6 +40,9 @@ impl EventBroadcaster {
 
     /// Send an event
     pub fn send(&self, event: ContainerEvent) {
+        if let Some(journal) = &self.journal {
+            journal.append(&event);
+        }
         // Ignore send errors (no receivers)
         let _ = self.sender.send(event);
     }
@@ -121,7 +136,7 @@ static GLOBAL_EVENTS: std::sync::OnceLock<Arc<EventBroadcaster>> = std::sync::On
 /// Get the global event broadcaster
 pub fn global_events() -> Arc<EventBroadcaster> {
     GLOBAL_EVENTS
-        .get_or_init(|| Arc::new(EventBroadcaster::default()))
+        .get_or_init(|| Arc::new(EventBroadcaster::default().with_journal(Journal::events())))
         .clone()
 }
 
@@ -0,0 +1,54 @@
+//! Exec session history kept per container
+
+#[cfg(target_os = "linux")]
+use crate::types::ExecSession;
+
+/// Exec sessions kept per container; older ones are dropped first
+#[cfg(target_os = "linux")]
+pub(crate) const MAX_EXEC_SESSIONS: usize = 100;
+
+/// Name recorded as the user of exec sessions started by this process
+pub(crate) fn exec_user() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("LOGNAME"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+/// Append a session to a container's history, dropping the oldest past the limit
+#[cfg(target_os = "linux")]
+pub(crate) fn record(history: &mut Vec<ExecSession>, session: ExecSession) {
+    history.push(session);
+    if history.len() > MAX_EXEC_SESSIONS {
+        let excess = history.len() - MAX_EXEC_SESSIONS;
+        history.drain(..excess);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_record_caps_history() {
+        let mut history = Vec::new();
+        for i in 0..(MAX_EXEC_SESSIONS + 5) {
+            record(
+                &mut history,
+                ExecSession {
+                    command: vec!["true".to_string()],
+                    user: "root".to_string(),
+                    started_at: i as u64,
+                    duration_ms: 1,
+                    exit_code: 0,
+                },
+            );
+        }
+        assert_eq!(history.len(), MAX_EXEC_SESSIONS);
+        assert_eq!(history[0].started_at, 5);
+        assert_eq!(
+            history.last().unwrap().started_at,
+            MAX_EXEC_SESSIONS as u64 + 4
+        );
+    }
+}
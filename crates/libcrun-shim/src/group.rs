@@ -0,0 +1,62 @@
+//! Dependency-ordered startup of container groups
+//!
+//! Containers declare what they need with `ContainerConfig::depends_on`;
+//! `ContainerRuntime::start_group` starts a set of containers so that each one
+//! only starts after its dependencies reached the requested condition.
+
+use crate::error::{Result, ShimError};
+use crate::types::DependsOn;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+/// How long `start_group` waits for a single dependency condition
+pub const DEPENDENCY_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Interval between dependency status checks
+pub(crate) const DEPENDENCY_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Order `ids` so that every container comes after the group members it
+/// depends on. Dependencies outside the group don't affect the order; they
+/// are only waited on. Ties keep the order of `ids`.
+pub(crate) fn startup_order(
+    ids: &[String],
+    deps: &HashMap<String, Vec<DependsOn>>,
+) -> Result<Vec<String>> {
+    let members: HashSet<&str> = ids.iter().map(String::as_str).collect();
+    let mut placed: HashSet<&str> = HashSet::new();
+    let mut order = Vec::with_capacity(ids.len());
+
+    while order.len() < members.len() {
+        let next = ids.iter().find(|id| {
+            !placed.contains(id.as_str())
+                && deps.get(*id).into_iter().flatten().all(|dep| {
+                    !members.contains(dep.container.as_str())
+                        || placed.contains(dep.container.as_str())
+                })
+        });
+
+        match next {
+            Some(id) => {
+                placed.insert(id);
+                order.push(id.clone());
+            }
+            None => {
+                let mut remaining: Vec<&str> = members
+                    .iter()
+                    .filter(|id| !placed.contains(*id))
+                    .copied()
+                    .collect();
+                remaining.sort_unstable();
+                return Err(ShimError::validation(
+                    "depends_on",
+                    format!(
+                        "Dependency cycle between containers: {}",
+                        remaining.join(", ")
+                    ),
+                ));
+            }
+        }
+    }
+
+    Ok(order)
+}
@@ -0,0 +1,127 @@
+//! Generated container IDs
+//!
+//! A container created with an empty `ContainerConfig::id` is given one by
+//! the runtime's [`IdGenerator`]. The default generator is random; test
+//! harnesses can install a [`SequentialIdGenerator`] so every run creates
+//! the same IDs, which keeps snapshot assertions stable, and the CLI a
+//! [`NameGenerator`](crate::NameGenerator) for names people can type.
+//!
+//! A generated ID a container already has is drawn again, see [`unused_id`].
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Source of IDs for containers created without one
+pub trait IdGenerator: Send + Sync {
+    /// A new container ID, different from the ones returned before
+    fn next_id(&self) -> String;
+}
+
+/// 12 random hex digits, as in `3f9a0c71d2b4`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RandomIdGenerator;
+
+impl IdGenerator for RandomIdGenerator {
+    fn next_id(&self) -> String {
+        format!("{:012x}", random_u64() & 0xffff_ffff_ffff)
+    }
+}
+
+/// 64 random bits, good enough for IDs but not for secrets
+pub(crate) fn random_u64() -> u64 {
+    use std::hash::{BuildHasher, Hasher};
+    // Each RandomState is seeded differently; the time covers processes
+    // that happen to start with the same seed
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    if let Ok(now) = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+        hasher.write_u128(now.as_nanos());
+    }
+    hasher.finish()
+}
+
+/// `<prefix>-1`, `<prefix>-2`, ... in creation order
+#[derive(Debug)]
+pub struct SequentialIdGenerator {
+    prefix: String,
+    next: AtomicU64,
+}
+
+impl SequentialIdGenerator {
+    pub fn new(prefix: impl Into<String>) -> Self {
+        Self {
+            prefix: prefix.into(),
+            next: AtomicU64::new(1),
+        }
+    }
+}
+
+impl IdGenerator for SequentialIdGenerator {
+    fn next_id(&self) -> String {
+        let n = self.next.fetch_add(1, Ordering::Relaxed);
+        format!("{}-{}", self.prefix, n)
+    }
+}
+
+/// IDs drawn before a taken one is given a numeric suffix instead
+const ATTEMPTS: usize = 8;
+
+/// An ID from `generator` that is not `taken`
+///
+/// The generator is asked again while its IDs are taken; after [`ATTEMPTS`]
+/// tries the last ID gets the lowest free suffix, as in `brave_curie-2`.
+pub(crate) fn unused_id(generator: &dyn IdGenerator, taken: impl Fn(&str) -> bool) -> String {
+    let mut id = generator.next_id();
+    for _ in 1..ATTEMPTS {
+        if !taken(&id) {
+            return id;
+        }
+        log::debug!("Generated ID '{}' is taken, drawing another", id);
+        id = generator.next_id();
+    }
+    if !taken(&id) {
+        return id;
+    }
+    (2u64..)
+        .map(|n| format!("{}-{}", id, n))
+        .find(|candidate| !taken(candidate))
+        .expect("some suffix is free")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sequential_ids() {
+        let ids = SequentialIdGenerator::new("test");
+        assert_eq!(ids.next_id(), "test-1");
+        assert_eq!(ids.next_id(), "test-2");
+        assert_eq!(SequentialIdGenerator::new("test").next_id(), "test-1");
+    }
+
+    #[test]
+    fn test_random_ids() {
+        let ids = RandomIdGenerator;
+        let first = ids.next_id();
+        assert_eq!(first.len(), 12);
+        assert!(first.bytes().all(|b| b.is_ascii_hexdigit()));
+        assert_ne!(first, ids.next_id());
+    }
+
+    #[test]
+    fn test_unused_id() {
+        let ids = SequentialIdGenerator::new("test");
+        let taken = ["test-1", "test-2"];
+        assert_eq!(unused_id(&ids, |id| taken.contains(&id)), "test-3");
+
+        /// Always the same ID
+        struct Fixed;
+        impl IdGenerator for Fixed {
+            fn next_id(&self) -> String {
+                "web".to_string()
+            }
+        }
+        assert_eq!(unused_id(&Fixed, |_| false), "web");
+        let taken = ["web", "web-2"];
+        assert_eq!(unused_id(&Fixed, |id| taken.contains(&id)), "web-3");
+    }
+}
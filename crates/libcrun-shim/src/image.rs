@@ -2,30 +2,100 @@
 //!
 //! This module provides functionality for pulling and managing OCI images.
 
+use crate::auth::AuthConfig;
+#[cfg(feature = "image-pull")]
+use crate::auth::{Challenge, DockerConfig};
 use crate::error::{Result, ShimError};
-use crate::types::{ImageInfo, ImageReference, PullProgress};
+use crate::proxy::ProxyConfig;
+use crate::types::{ExtractLimits, ImageInfo, ImageReference, PruneFilter, PullProgress};
 use std::collections::HashMap;
+use std::future::Future;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+#[cfg(feature = "image-pull")]
+mod archive;
 
+#[cfg(feature = "image-pull")]
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 #[cfg(feature = "image-pull")]
 use futures_util::StreamExt;
 #[cfg(feature = "image-pull")]
 use sha2::{Digest, Sha256};
 
+/// Handle to cancel a pull started with [`ImageStore::pull_cancellable`]
+///
+/// Clones refer to the same pull, so one can be moved into a signal handler
+/// or another task.
+#[derive(Debug, Clone, Default)]
+pub struct PullHandle {
+    cancelled: Arc<AtomicBool>,
+    notify: Arc<tokio::sync::Notify>,
+}
+
+impl PullHandle {
+    /// Stop the pull, aborting its downloads; calling it again does nothing
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once the pull is cancelled
+    #[cfg(feature = "image-pull")]
+    async fn cancelled(&self) {
+        loop {
+            // Registered before the check so a cancel in between is not missed
+            let notified = self.notify.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Wait for `work`, giving up on it if the pull is cancelled first
+    #[cfg(feature = "image-pull")]
+    async fn run<T>(&self, work: impl Future<Output = Result<T>>) -> Result<T> {
+        tokio::select! {
+            result = work => result,
+            _ = self.cancelled() => Err(ShimError::cancelled("Image pull was cancelled")),
+        }
+    }
+}
+
 /// Image store for managing pulled images
 pub struct ImageStore {
     /// Root directory for image storage
     root: PathBuf,
     /// Cached image list
     images: HashMap<String, ImageInfo>,
+    /// Limits on what extracting a pulled image may write
+    limits: ExtractLimits,
     /// HTTP client for registry requests
     #[cfg(feature = "image-pull")]
     client: reqwest::Client,
 }
 
 impl ImageStore {
-    /// Create a new image store
+    /// Media type of the manifests built by [`Self::manifest`]
+    pub const MANIFEST_MEDIA_TYPE: &'static str = "application/vnd.oci.image.manifest.v1+json";
+
+    /// Create a new image store, pulling through the proxies in the host's
+    /// `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY` variables
     pub fn new(root: impl Into<PathBuf>) -> Result<Self> {
+        Self::with_proxy(root, &ProxyConfig::default())
+    }
+
+    /// Create a new image store that pulls through `proxy`, with the host's
+    /// proxy variables filling in what it leaves unset
+    pub fn with_proxy(root: impl Into<PathBuf>, proxy: &ProxyConfig) -> Result<Self> {
+        #[cfg(not(feature = "image-pull"))]
+        let _ = proxy;
         let root = root.into();
         std::fs::create_dir_all(&root).map_err(|e| {
             ShimError::runtime_with_context(
@@ -40,11 +110,33 @@ impl ImageStore {
         Ok(Self {
             root,
             images,
+            limits: ExtractLimits::default(),
             #[cfg(feature = "image-pull")]
             client: reqwest::Client::builder()
                 .user_agent("libcrun-shim/0.1.0")
+                .proxy(Self::proxy(proxy.clone().with_host_env()))
                 .build()
-                .map_err(|e| ShimError::runtime(format!("Failed to create HTTP client: {}", e)))?,
+                .map_err(|e| ShimError::runtime("Failed to create HTTP client").with_source(e))?,
+        })
+    }
+
+    /// Use `limits` instead of the default [`ExtractLimits`] for pulls
+    pub fn with_limits(mut self, limits: ExtractLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn limits(&self) -> &ExtractLimits {
+        &self.limits
+    }
+
+    /// Route each request by `config`, instead of reqwest reading the proxy
+    /// variables itself, which knows no per-registry proxies
+    #[cfg(feature = "image-pull")]
+    fn proxy(config: ProxyConfig) -> reqwest::Proxy {
+        reqwest::Proxy::custom(move |url| {
+            let proxy = config.proxy_for(url.scheme(), url.host_str()?)?;
+            reqwest::Url::parse(proxy).ok()
         })
     }
 
@@ -64,8 +156,12 @@ impl ImageStore {
             for entry in entries.filter_map(|e| e.ok()) {
                 let path = entry.path();
                 if path.is_dir() {
-                    let config_path = path.join("config.json");
-                    if config_path.exists() {
+                    // Pulled images keep their info next to the image config
+                    let config_path = ["image_info.json", "config.json"]
+                        .iter()
+                        .map(|name| path.join(name))
+                        .find(|p| p.exists());
+                    if let Some(config_path) = config_path {
                         if let Ok(content) = std::fs::read_to_string(&config_path) {
                             if let Ok(info) = serde_json::from_str::<ImageInfo>(&content) {
                                 images.insert(info.id.clone(), info);
@@ -79,12 +175,64 @@ impl ImageStore {
         images
     }
 
-    /// Pull an image from a registry
-    #[cfg(feature = "image-pull")]
+    /// Pull an image from a registry, with the credentials stored for it in
+    /// the Docker configuration, see [`DockerConfig`](crate::DockerConfig)
     pub async fn pull(
         &mut self,
         reference: &str,
-        progress_callback: Option<Box<dyn Fn(PullProgress) + Send>>,
+        progress_callback: Option<Box<dyn Fn(PullProgress) + Send + Sync>>,
+    ) -> Result<ImageInfo> {
+        self.pull_with(reference, None, progress_callback, &PullHandle::default())
+            .await
+    }
+
+    /// Pull an image from a registry with `auth` rather than the stored
+    /// credentials
+    pub async fn pull_with_auth(
+        &mut self,
+        reference: &str,
+        auth: &AuthConfig,
+        progress_callback: Option<Box<dyn Fn(PullProgress) + Send + Sync>>,
+    ) -> Result<ImageInfo> {
+        self.pull_with(
+            reference,
+            Some(auth),
+            progress_callback,
+            &PullHandle::default(),
+        )
+        .await
+    }
+
+    /// Pull an image, with a handle to cancel the pull while it runs
+    ///
+    /// The pull happens when the returned future is awaited. Once
+    /// [`PullHandle::cancel`] is called, downloads in flight are aborted,
+    /// everything the pull stored is removed, and the future fails with
+    /// [`ShimError::Cancelled`]. Images and layers that were already in the
+    /// store are left alone.
+    pub fn pull_cancellable<'a>(
+        &'a mut self,
+        reference: &'a str,
+        progress_callback: Option<Box<dyn Fn(PullProgress) + Send + Sync>>,
+    ) -> (PullHandle, impl Future<Output = Result<ImageInfo>> + 'a) {
+        let handle = PullHandle::default();
+        let pull = {
+            let handle = handle.clone();
+            async move {
+                self.pull_with(reference, None, progress_callback, &handle)
+                    .await
+            }
+        };
+        (handle, pull)
+    }
+
+    #[cfg(feature = "image-pull")]
+    async fn pull_with(
+        &mut self,
+        reference: &str,
+        auth: Option<&AuthConfig>,
+        progress_callback: Option<Box<dyn Fn(PullProgress) + Send + Sync>>,
+        handle: &PullHandle,
     ) -> Result<ImageInfo> {
         let image_ref = ImageReference::parse(reference).ok_or_else(|| {
             ShimError::validation(
@@ -92,6 +240,8 @@ impl ImageStore {
                 format!("Invalid image reference: {}", reference),
             )
         })?;
+        let cancelled =
+            || ShimError::cancelled(format!("Pull of {} was cancelled", image_ref.full_name()));
 
         log::info!("Pulling image: {}", image_ref.full_name());
 
@@ -107,11 +257,28 @@ impl ImageStore {
             });
         }
 
-        // Get auth token
-        let token = self.get_auth_token(&image_ref).await?;
+        let authorization = handle
+            .run(self.authorize(&image_ref, auth))
+            .await
+            .map_err(|e| {
+                if handle.is_cancelled() {
+                    cancelled()
+                } else {
+                    e
+                }
+            })?;
 
         // Fetch manifest
-        let manifest = self.fetch_manifest(&image_ref, token.as_deref()).await?;
+        let manifest = handle
+            .run(self.fetch_manifest(&image_ref, authorization.as_deref()))
+            .await
+            .map_err(|e| {
+                if handle.is_cancelled() {
+                    cancelled()
+                } else {
+                    e
+                }
+            })?;
 
         // Parse manifest
         let (config_digest, layer_digests, total_size) = self.parse_manifest(&manifest)?;
@@ -128,116 +295,120 @@ impl ImageStore {
         }
 
         // Create image directory using short ID
-        let image_id = if config_digest.starts_with("sha256:") {
-            config_digest[7..19].to_string()
-        } else {
-            config_digest[..12].to_string()
-        };
+        let image_id = image_id(&config_digest);
 
         let image_dir = self.root.join(&image_id);
+        let rootfs_path = image_dir.join("rootfs");
+        // Removed again if the pull does not finish
+        let new_dir = !image_dir.exists();
+        let new_rootfs = !rootfs_path.exists();
         std::fs::create_dir_all(&image_dir)?;
 
-        // Download config blob
-        let config_path = image_dir.join("config.json");
-        if !config_path.exists() {
-            self.download_blob(&image_ref, &config_digest, &config_path, token.as_deref())
-                .await?;
-        }
-
-        // Download layers
-        let mut downloaded_bytes: u64 = 0;
-        for (i, (layer_digest, layer_size)) in layer_digests.iter().enumerate() {
-            let layer_filename = layer_digest.replace("sha256:", "");
-            let layer_path = image_dir.join(format!("{}.tar.gz", &layer_filename[..12]));
-
-            if let Some(ref cb) = progress_callback {
-                cb(PullProgress {
-                    current_layer: layer_digest.clone(),
-                    total_layers: layer_digests.len() as u32,
-                    completed_layers: i as u32,
-                    downloaded_bytes,
-                    total_bytes: total_size,
-                    status: format!("Downloading layer {}/{}", i + 1, layer_digests.len()),
-                });
+        let stored = async {
+            // Download config blob
+            let config_path = image_dir.join("config.json");
+            if !config_path.exists() {
+                handle
+                    .run(self.download_blob(
+                        &image_ref,
+                        &config_digest,
+                        &config_path,
+                        authorization.as_deref(),
+                    ))
+                    .await?;
             }
 
-            if !layer_path.exists() {
-                self.download_blob_with_progress(
-                    &image_ref,
-                    layer_digest,
-                    &layer_path,
-                    token.as_deref(),
-                    *layer_size,
-                    &progress_callback,
-                    downloaded_bytes,
-                    total_size,
-                )
-                .await?;
-            }
+            // Download layers
+            let mut downloaded_bytes: u64 = 0;
+            for (i, (layer_digest, layer_size)) in layer_digests.iter().enumerate() {
+                let layer_path = image_dir.join(layer_file_name(layer_digest));
+
+                if let Some(ref cb) = progress_callback {
+                    cb(PullProgress {
+                        current_layer: layer_digest.clone(),
+                        total_layers: layer_digests.len() as u32,
+                        completed_layers: i as u32,
+                        downloaded_bytes,
+                        total_bytes: total_size,
+                        status: format!("Downloading layer {}/{}", i + 1, layer_digests.len()),
+                    });
+                }
 
-            downloaded_bytes += layer_size;
-        }
+                if !layer_path.exists() {
+                    self.download_blob_with_progress(
+                        &image_ref,
+                        layer_digest,
+                        &layer_path,
+                        authorization.as_deref(),
+                        &progress_callback,
+                        downloaded_bytes,
+                        total_size,
+                        handle,
+                    )
+                    .await?;
+                }
 
-        // Extract layers to rootfs
-        let rootfs_path = image_dir.join("rootfs");
-        if !rootfs_path.exists() {
-            std::fs::create_dir_all(&rootfs_path)?;
-
-            if let Some(ref cb) = progress_callback {
-                cb(PullProgress {
-                    current_layer: String::new(),
-                    total_layers: layer_digests.len() as u32,
-                    completed_layers: layer_digests.len() as u32,
-                    downloaded_bytes: total_size,
-                    total_bytes: total_size,
-                    status: "Extracting layers".to_string(),
-                });
+                downloaded_bytes += layer_size;
             }
 
-            for (layer_digest, _) in &layer_digests {
-                let layer_filename = layer_digest.replace("sha256:", "");
-                let layer_path = image_dir.join(format!("{}.tar.gz", &layer_filename[..12]));
-                self.extract_layer(&layer_path, &rootfs_path)?;
-            }
-        }
+            // Extract layers to rootfs
+            if new_rootfs {
+                std::fs::create_dir_all(&rootfs_path)?;
+
+                if let Some(ref cb) = progress_callback {
+                    cb(PullProgress {
+                        current_layer: String::new(),
+                        total_layers: layer_digests.len() as u32,
+                        completed_layers: layer_digests.len() as u32,
+                        downloaded_bytes: total_size,
+                        total_bytes: total_size,
+                        status: "Extracting layers".to_string(),
+                    });
+                }
 
-        // Parse config for image metadata
-        let config_content = std::fs::read_to_string(&config_path)?;
-        let config: serde_json::Value = serde_json::from_str(&config_content)?;
+                let mut image_size = 0;
+                for (layer_digest, _) in &layer_digests {
+                    let layer_path = image_dir.join(layer_file_name(layer_digest));
+                    self.extract_layer(&layer_path, &rootfs_path, &mut image_size, handle)?;
+                }
+            }
 
-        let architecture = config["architecture"]
-            .as_str()
-            .unwrap_or("amd64")
-            .to_string();
-        let os = config["os"].as_str().unwrap_or("linux").to_string();
-        let created = config["created"]
-            .as_str()
-            .and_then(parse_rfc3339_timestamp)
-            .unwrap_or(0);
-
-        let labels = config["config"]["Labels"]
-            .as_object()
-            .map(|obj| {
-                obj.iter()
-                    .filter_map(|(k, v)| v.as_str().map(|s| (k.clone(), s.to_string())))
-                    .collect()
-            })
-            .unwrap_or_default();
+            // Parse config for image metadata
+            let config_content = std::fs::read_to_string(&config_path)?;
+            let config: serde_json::Value = serde_json::from_str(&config_content)?;
+            let info = image_info(
+                image_ref.clone(),
+                image_id.clone(),
+                total_size,
+                layer_digests.iter().map(|(d, _)| d.clone()).collect(),
+                &config,
+            );
 
-        let info = ImageInfo {
-            reference: image_ref.clone(),
-            id: image_id.clone(),
-            size: total_size,
-            created,
-            architecture,
-            os,
-            labels,
+            // Save image info
+            let info_path = image_dir.join("image_info.json");
+            std::fs::write(&info_path, serde_json::to_string_pretty(&info)?)?;
+            Ok::<_, ShimError>(info)
+        }
+        .await;
+
+        let info = match stored {
+            Ok(info) => info,
+            Err(e) => {
+                if new_dir && handle.is_cancelled() {
+                    // Leave the store as it was before the pull
+                    let _ = std::fs::remove_dir_all(&image_dir);
+                } else if new_rootfs {
+                    // A half-extracted rootfs would be taken as complete next time
+                    let _ = std::fs::remove_dir_all(&rootfs_path);
+                }
+                if handle.is_cancelled() {
+                    log::info!("Pull of {} cancelled", image_ref.full_name());
+                    return Err(cancelled());
+                }
+                return Err(e);
+            }
         };
 
-        // Save image info
-        let info_path = image_dir.join("image_info.json");
-        std::fs::write(&info_path, serde_json::to_string_pretty(&info)?)?;
-
         self.images.insert(image_id.clone(), info.clone());
 
         if let Some(ref cb) = progress_callback {
@@ -262,10 +433,12 @@ impl ImageStore {
 
     /// Pull without image-pull feature (stub)
     #[cfg(not(feature = "image-pull"))]
-    pub async fn pull(
+    async fn pull_with(
         &mut self,
         reference: &str,
-        _progress_callback: Option<Box<dyn Fn(PullProgress) + Send>>,
+        _auth: Option<&AuthConfig>,
+        _progress_callback: Option<Box<dyn Fn(PullProgress) + Send + Sync>>,
+        _handle: &PullHandle,
     ) -> Result<ImageInfo> {
         Err(ShimError::runtime_with_context(
             "Image pull not available",
@@ -276,41 +449,219 @@ impl ImageStore {
         ))
     }
 
+    /// ID of the image `reference` currently points at in its registry
+    ///
+    /// Only the manifest is fetched. The ID is the one [`Self::pull`] would
+    /// store the image under, so comparing it with a local image tells
+    /// whether the registry has a newer one.
     #[cfg(feature = "image-pull")]
-    async fn get_auth_token(&self, image_ref: &ImageReference) -> Result<Option<String>> {
-        if image_ref.registry == "docker.io" {
-            // Docker Hub uses token-based auth
-            let url = format!(
-                "https://auth.docker.io/token?service=registry.docker.io&scope=repository:{}:pull",
-                image_ref.repository
-            );
+    pub async fn latest_id(&self, reference: &str) -> Result<String> {
+        let image_ref = ImageReference::parse(reference).ok_or_else(|| {
+            ShimError::validation(
+                "reference",
+                format!("Invalid image reference: {}", reference),
+            )
+        })?;
+        let authorization = self.authorize(&image_ref, None).await?;
+        let manifest = self
+            .fetch_manifest(&image_ref, authorization.as_deref())
+            .await?;
+        let (config_digest, _, _) = self.parse_manifest(&manifest)?;
+        Ok(image_id(&config_digest))
+    }
 
-            let response = self
-                .client
-                .get(&url)
-                .send()
-                .await
-                .map_err(|e| ShimError::runtime(format!("Auth request failed: {}", e)))?;
+    /// Registry lookup without image-pull feature (stub)
+    #[cfg(not(feature = "image-pull"))]
+    pub async fn latest_id(&self, reference: &str) -> Result<String> {
+        Err(ShimError::runtime_with_context(
+            "Image pull not available",
+            format!(
+                "Compile with 'image-pull' feature to enable. Reference: {}",
+                reference
+            ),
+        ))
+    }
 
-            if response.status().is_success() {
-                let json: serde_json::Value = response.json().await.map_err(|e| {
-                    ShimError::runtime(format!("Failed to parse auth response: {}", e))
-                })?;
+    /// Check that `registry` accepts `auth`, as `crun-shim login` does
+    /// before storing the credentials
+    #[cfg(feature = "image-pull")]
+    pub async fn authenticate(&self, registry: &str, auth: &AuthConfig) -> Result<()> {
+        let authorization = self.authorization(registry, None, Some(auth)).await?;
+        let mut request = self
+            .client
+            .get(format!("{}/v2/", get_registry_url(registry)));
+        if let Some(authorization) = authorization {
+            request = request.header("Authorization", authorization);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| ShimError::runtime("Registry request failed").with_source(e))?;
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => {
+                Err(ShimError::permission_denied(
+                    "The registry rejected the credentials",
+                    format!("Registry: {}", registry),
+                ))
+            }
+            status => Err(ShimError::runtime(format!("Login failed: HTTP {}", status))),
+        }
+    }
 
-                if let Some(token) = json["token"].as_str() {
-                    return Ok(Some(token.to_string()));
+    /// Login check without image-pull feature (stub)
+    #[cfg(not(feature = "image-pull"))]
+    pub async fn authenticate(&self, registry: &str, _auth: &AuthConfig) -> Result<()> {
+        Err(ShimError::runtime_with_context(
+            "Registry access not available",
+            format!(
+                "Compile with 'image-pull' feature to enable. Registry: {}",
+                registry
+            ),
+        ))
+    }
+
+    /// `Authorization` header for pulls from `image_ref`'s repository, with
+    /// `auth` or else the stored credentials
+    #[cfg(feature = "image-pull")]
+    async fn authorize(
+        &self,
+        image_ref: &ImageReference,
+        auth: Option<&AuthConfig>,
+    ) -> Result<Option<String>> {
+        let stored;
+        let auth = match auth {
+            Some(auth) => Some(auth),
+            None => {
+                stored = stored_credentials(&image_ref.registry);
+                stored.as_ref()
+            }
+        };
+        let scope = format!("repository:{}:pull", image_ref.repository);
+        self.authorization(&image_ref.registry, Some(&scope), auth)
+            .await
+    }
+
+    /// `Authorization` header for requests to `registry`, or `None` if it
+    /// lets anyone in
+    ///
+    /// The registry's challenge to an anonymous request decides: basic auth
+    /// sends the credentials as they are (e.g. ECR), bearer auth exchanges
+    /// them for a token for `scope` at its token service (e.g. Docker Hub
+    /// and GHCR, which hand out anonymous tokens for public images too).
+    #[cfg(feature = "image-pull")]
+    async fn authorization(
+        &self,
+        registry: &str,
+        scope: Option<&str>,
+        auth: Option<&AuthConfig>,
+    ) -> Result<Option<String>> {
+        if let Some(token) = auth.and_then(|auth| auth.registry_token.as_deref()) {
+            return Ok(Some(format!("Bearer {}", token)));
+        }
+
+        let response = self
+            .client
+            .get(format!("{}/v2/", get_registry_url(registry)))
+            .send()
+            .await
+            .map_err(|e| ShimError::runtime("Registry request failed").with_source(e))?;
+        if response.status() != reqwest::StatusCode::UNAUTHORIZED {
+            return Ok(None);
+        }
+        let challenge = response
+            .headers()
+            .get(reqwest::header::WWW_AUTHENTICATE)
+            .and_then(|header| header.to_str().ok())
+            .and_then(Challenge::parse);
+
+        match challenge {
+            Some(Challenge::Basic) => {
+                Ok(auth
+                    .and_then(AuthConfig::user_password)
+                    .map(|(username, password)| {
+                        format!("Basic {}", BASE64.encode(format!("{username}:{password}")))
+                    }))
+            }
+            Some(Challenge::Bearer { realm, service }) => {
+                let token = self
+                    .fetch_token(&realm, service.as_deref(), scope, auth)
+                    .await?;
+                Ok(Some(format!("Bearer {}", token)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Get a token from the token service at `realm`, trading in an
+    /// identity token by OAuth2 or logging in with the password
+    #[cfg(feature = "image-pull")]
+    async fn fetch_token(
+        &self,
+        realm: &str,
+        service: Option<&str>,
+        scope: Option<&str>,
+        auth: Option<&AuthConfig>,
+    ) -> Result<String> {
+        let mut params = Vec::new();
+        params.extend(service.map(|service| ("service", service)));
+        params.extend(scope.map(|scope| ("scope", scope)));
+
+        let request = match auth.and_then(|auth| auth.identity_token.as_deref()) {
+            Some(token) => {
+                params.extend([
+                    ("grant_type", "refresh_token"),
+                    ("refresh_token", token),
+                    ("client_id", "libcrun-shim"),
+                ]);
+                self.client.post(realm).form(&params)
+            }
+            None => {
+                let request = self.client.get(realm).query(&params);
+                match auth.and_then(AuthConfig::user_password) {
+                    Some((username, password)) => request.basic_auth(username, Some(password)),
+                    None => request,
                 }
             }
+        };
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| ShimError::runtime("Auth request failed").with_source(e))?;
+        match response.status() {
+            status if status.is_success() => {}
+            reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => {
+                return Err(ShimError::permission_denied(
+                    "The registry rejected the credentials",
+                    format!("Token service: {}", realm),
+                ))
+            }
+            status => {
+                return Err(ShimError::runtime(format!(
+                    "Auth request failed: HTTP {}",
+                    status
+                )))
+            }
         }
 
-        Ok(None)
+        let json: serde_json::Value = response
+            .json()
+            .await
+            .map_err(|e| ShimError::runtime("Failed to parse auth response").with_source(e))?;
+        // Docker's token services answer with "token", OAuth2 ones with "access_token"
+        json["token"]
+            .as_str()
+            .or_else(|| json["access_token"].as_str())
+            .map(str::to_string)
+            .ok_or_else(|| ShimError::runtime("Auth response has no token"))
     }
 
     #[cfg(feature = "image-pull")]
     async fn fetch_manifest(
         &self,
         image_ref: &ImageReference,
-        token: Option<&str>,
+        authorization: Option<&str>,
     ) -> Result<serde_json::Value> {
         let registry_url = get_registry_url(&image_ref.registry);
         let url = format!(
@@ -323,14 +674,14 @@ impl ImageStore {
             "application/vnd.docker.distribution.manifest.v2+json, application/vnd.oci.image.manifest.v1+json",
         );
 
-        if let Some(token) = token {
-            request = request.header("Authorization", format!("Bearer {}", token));
+        if let Some(authorization) = authorization {
+            request = request.header("Authorization", authorization);
         }
 
         let response = request
             .send()
             .await
-            .map_err(|e| ShimError::runtime(format!("Manifest request failed: {}", e)))?;
+            .map_err(|e| ShimError::runtime("Manifest request failed").with_source(e))?;
 
         if !response.status().is_success() {
             return Err(ShimError::runtime(format!(
@@ -342,7 +693,7 @@ impl ImageStore {
         response
             .json()
             .await
-            .map_err(|e| ShimError::runtime(format!("Failed to parse manifest: {}", e)))
+            .map_err(|e| ShimError::runtime("Failed to parse manifest").with_source(e))
     }
 
     #[cfg(feature = "image-pull")]
@@ -395,7 +746,7 @@ impl ImageStore {
         image_ref: &ImageReference,
         digest: &str,
         path: &Path,
-        token: Option<&str>,
+        authorization: Option<&str>,
     ) -> Result<()> {
         let registry_url = get_registry_url(&image_ref.registry);
         let url = format!(
@@ -404,14 +755,14 @@ impl ImageStore {
         );
 
         let mut request = self.client.get(&url);
-        if let Some(token) = token {
-            request = request.header("Authorization", format!("Bearer {}", token));
+        if let Some(authorization) = authorization {
+            request = request.header("Authorization", authorization);
         }
 
         let response = request
             .send()
             .await
-            .map_err(|e| ShimError::runtime(format!("Blob download failed: {}", e)))?;
+            .map_err(|e| ShimError::runtime("Blob download failed").with_source(e))?;
 
         if !response.status().is_success() {
             return Err(ShimError::runtime(format!(
@@ -423,7 +774,7 @@ impl ImageStore {
         let bytes = response
             .bytes()
             .await
-            .map_err(|e| ShimError::runtime(format!("Failed to read blob: {}", e)))?;
+            .map_err(|e| ShimError::runtime("Failed to read blob").with_source(e))?;
 
         // Verify digest
         let computed_digest = format!("sha256:{:x}", Sha256::digest(&bytes));
@@ -444,11 +795,11 @@ impl ImageStore {
         image_ref: &ImageReference,
         digest: &str,
         path: &Path,
-        token: Option<&str>,
-        _layer_size: u64,
-        progress_callback: &Option<Box<dyn Fn(PullProgress) + Send>>,
+        authorization: Option<&str>,
+        progress_callback: &Option<Box<dyn Fn(PullProgress) + Send + Sync>>,
         base_downloaded: u64,
         total_size: u64,
+        handle: &PullHandle,
     ) -> Result<()> {
         let registry_url = get_registry_url(&image_ref.registry);
         let url = format!(
@@ -457,14 +808,18 @@ impl ImageStore {
         );
 
         let mut request = self.client.get(&url);
-        if let Some(token) = token {
-            request = request.header("Authorization", format!("Bearer {}", token));
+        if let Some(authorization) = authorization {
+            request = request.header("Authorization", authorization);
         }
 
-        let response = request
-            .send()
-            .await
-            .map_err(|e| ShimError::runtime(format!("Blob download failed: {}", e)))?;
+        let response = handle
+            .run(async {
+                request
+                    .send()
+                    .await
+                    .map_err(|e| ShimError::runtime("Blob download failed").with_source(e))
+            })
+            .await?;
 
         if !response.status().is_success() {
             return Err(ShimError::runtime(format!(
@@ -473,56 +828,122 @@ impl ImageStore {
             )));
         }
 
-        let mut file = std::fs::File::create(path)?;
+        // Written next to the blob and only renamed once verified, so an
+        // interrupted download never looks like a stored layer
+        let partial = path.with_file_name(format!(
+            "{}.partial",
+            path.file_name().unwrap_or_default().to_string_lossy()
+        ));
+        let mut file = std::fs::File::create(&partial)?;
         let mut hasher = Sha256::new();
         let mut downloaded: u64 = 0;
         let mut stream = response.bytes_stream();
 
-        while let Some(chunk) = stream.next().await {
-            let chunk =
-                chunk.map_err(|e| ShimError::runtime(format!("Download stream error: {}", e)))?;
-
-            std::io::Write::write_all(&mut file, &chunk)?;
-            hasher.update(&chunk);
-            downloaded += chunk.len() as u64;
-
-            if let Some(ref cb) = progress_callback {
-                cb(PullProgress {
-                    current_layer: digest.to_string(),
-                    total_layers: 0,
-                    completed_layers: 0,
-                    downloaded_bytes: base_downloaded + downloaded,
-                    total_bytes: total_size,
-                    status: "Downloading".to_string(),
-                });
+        let streamed = async {
+            while let Some(chunk) = handle.run(async { Ok(stream.next().await) }).await? {
+                let chunk = chunk
+                    .map_err(|e| ShimError::runtime("Download stream error").with_source(e))?;
+
+                std::io::Write::write_all(&mut file, &chunk)?;
+                hasher.update(&chunk);
+                downloaded += chunk.len() as u64;
+
+                if let Some(ref cb) = progress_callback {
+                    cb(PullProgress {
+                        current_layer: digest.to_string(),
+                        total_layers: 0,
+                        completed_layers: 0,
+                        downloaded_bytes: base_downloaded + downloaded,
+                        total_bytes: total_size,
+                        status: "Downloading".to_string(),
+                    });
+                }
+            }
+
+            // Verify digest
+            let computed_digest = format!("sha256:{:x}", hasher.finalize());
+            if computed_digest != digest {
+                return Err(ShimError::runtime(format!(
+                    "Digest mismatch: expected {}, got {}",
+                    digest, computed_digest
+                )));
             }
+            Ok(())
         }
-
-        // Verify digest
-        let computed_digest = format!("sha256:{:x}", hasher.finalize());
-        if computed_digest != digest {
-            std::fs::remove_file(path)?;
-            return Err(ShimError::runtime(format!(
-                "Digest mismatch: expected {}, got {}",
-                digest, computed_digest
-            )));
+        .await;
+        drop(file);
+
+        match streamed {
+            Ok(()) => std::fs::rename(&partial, path)?,
+            Err(e) => {
+                let _ = std::fs::remove_file(&partial);
+                return Err(e);
+            }
         }
-
         Ok(())
     }
 
+    /// Extract a layer into `rootfs_path`, adding the size of its files to
+    /// `image_size`
+    ///
+    /// Entries are checked against the limits before they are written, so a
+    /// layer over them fails having written no more than the limits allow.
     #[cfg(feature = "image-pull")]
-    fn extract_layer(&self, layer_path: &Path, rootfs_path: &Path) -> Result<()> {
+    fn extract_layer(
+        &self,
+        layer_path: &Path,
+        rootfs_path: &Path,
+        image_size: &mut u64,
+        handle: &PullHandle,
+    ) -> Result<()> {
         use flate2::read::GzDecoder;
         use tar::Archive;
 
+        let limits = &self.limits;
         let file = std::fs::File::open(layer_path)?;
         let decoder = GzDecoder::new(file);
         let mut archive = Archive::new(decoder);
+        let mut layer_size: u64 = 0;
+        let mut layer_entries: u64 = 0;
 
         // Handle whiteout files (OCI layer deletion markers)
         for entry in archive.entries()? {
+            if handle.is_cancelled() {
+                return Err(ShimError::cancelled("Layer extraction was cancelled"));
+            }
             let mut entry = entry?;
+
+            layer_entries += 1;
+            layer_size = layer_size.saturating_add(entry.size());
+            *image_size = image_size.saturating_add(entry.size());
+            if limits.max_layer_entries > 0 && layer_entries > limits.max_layer_entries {
+                return Err(ShimError::runtime_with_context(
+                    format!("Layer has more than {} files", limits.max_layer_entries),
+                    format!(
+                        "Limit: ExtractLimits::max_layer_entries, layer: {}",
+                        layer_path.display()
+                    ),
+                ));
+            }
+            if limits.max_layer_size > 0 && layer_size > limits.max_layer_size {
+                return Err(ShimError::runtime_with_context(
+                    format!("Layer expands to more than {} bytes", limits.max_layer_size),
+                    format!(
+                        "Limit: ExtractLimits::max_layer_size, layer: {}",
+                        layer_path.display()
+                    ),
+                ));
+            }
+            if limits.max_image_size > 0 && *image_size > limits.max_image_size {
+                return Err(ShimError::runtime_with_context(
+                    format!("Image expands to more than {} bytes", limits.max_image_size),
+                    format!(
+                        "Limit: ExtractLimits::max_image_size, layer: {}",
+                        layer_path.display()
+                    ),
+                ));
+            }
+
             let path = entry.path()?;
             let path_str = path.to_string_lossy();
 
@@ -544,6 +965,30 @@ impl ImageStore {
         Ok(())
     }
 
+    /// Image saving without image-pull feature (stub)
+    #[cfg(not(feature = "image-pull"))]
+    pub fn save(&self, reference: &str, _path: impl AsRef<Path>) -> Result<()> {
+        Err(ShimError::runtime_with_context(
+            "Image archives not available",
+            format!(
+                "Compile with 'image-pull' feature to enable. Reference: {}",
+                reference
+            ),
+        ))
+    }
+
+    /// Image loading without image-pull feature (stub)
+    #[cfg(not(feature = "image-pull"))]
+    pub fn load(&mut self, path: impl AsRef<Path>) -> Result<Vec<ImageInfo>> {
+        Err(ShimError::runtime_with_context(
+            "Image archives not available",
+            format!(
+                "Compile with 'image-pull' feature to enable. Archive: {}",
+                path.as_ref().display()
+            ),
+        ))
+    }
+
     /// Get the rootfs path for an image
     pub fn get_rootfs(&self, image_id: &str) -> Option<PathBuf> {
         let rootfs_path = self.root.join(image_id).join("rootfs");
@@ -554,6 +999,95 @@ impl ImageStore {
         }
     }
 
+    /// OCI image config of an image, with the entrypoint, command and
+    /// environment it runs with under `config`
+    pub fn config(&self, image_id: &str) -> Result<serde_json::Value> {
+        let path = self.root.join(image_id).join("config.json");
+        let content = std::fs::read_to_string(&path).map_err(|e| {
+            ShimError::io_with_context(e, format!("Config of image '{}'", image_id))
+        })?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Digest and local file of each layer of an image, bottom layer first
+    pub fn layer_blobs(&self, image_id: &str) -> Result<Vec<(String, PathBuf)>> {
+        let info = self
+            .images
+            .get(image_id)
+            .ok_or_else(|| ShimError::not_found(format!("Image '{}'", image_id)))?;
+        if info.layers.is_empty() {
+            return Err(ShimError::runtime_with_context(
+                format!("Image {} has no recorded layers", image_id),
+                "Pull it again to record them",
+            ));
+        }
+        info.layers
+            .iter()
+            .map(|digest| {
+                let path = self.root.join(image_id).join(layer_file_name(digest));
+                if path.is_file() {
+                    Ok((digest.clone(), path))
+                } else {
+                    Err(ShimError::NotFound {
+                        resource: format!("Layer {} of image '{}'", digest, image_id),
+                        context: Some(format!("Expected at {}", path.display())),
+                    })
+                }
+            })
+            .collect()
+    }
+
+    /// OCI image manifest for an image and its digest
+    ///
+    /// The manifest is built from the stored config and layers, so it is the
+    /// same every time but differs from the one the image was pulled with.
+    pub fn manifest(&self, image_id: &str) -> Result<(String, Vec<u8>)> {
+        use sha2::{Digest, Sha256};
+
+        let blobs = self.layer_blobs(image_id)?;
+        let config = std::fs::read(self.root.join(image_id).join("config.json"))?;
+        let mut layers = Vec::with_capacity(blobs.len());
+        for (digest, path) in blobs {
+            layers.push(serde_json::json!({
+                "mediaType": "application/vnd.oci.image.layer.v1.tar+gzip",
+                "digest": digest,
+                "size": std::fs::metadata(&path)?.len(),
+            }));
+        }
+        let manifest = serde_json::to_vec(&serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": Self::MANIFEST_MEDIA_TYPE,
+            "config": {
+                "mediaType": "application/vnd.oci.image.config.v1+json",
+                "digest": format!("sha256:{:x}", Sha256::digest(&config)),
+                "size": config.len(),
+            },
+            "layers": layers,
+        }))?;
+        Ok((format!("sha256:{:x}", Sha256::digest(&manifest)), manifest))
+    }
+
+    /// Local file holding the config or layer blob with `digest`
+    pub fn blob_path(&self, digest: &str) -> Option<PathBuf> {
+        use sha2::{Digest, Sha256};
+
+        let hex = digest.strip_prefix("sha256:")?;
+        self.images.values().find_map(|info| {
+            let image_dir = self.root.join(&info.id);
+            if info.layers.iter().any(|layer| layer == digest) {
+                let path = image_dir.join(layer_file_name(digest));
+                return path.is_file().then_some(path);
+            }
+            // Image IDs are the start of the config digest
+            if hex.starts_with(&info.id) {
+                let path = image_dir.join("config.json");
+                let config = std::fs::read(&path).ok()?;
+                return (format!("{:x}", Sha256::digest(&config)) == hex).then_some(path);
+            }
+            None
+        })
+    }
+
     /// List all images
     pub fn list(&self) -> Vec<ImageInfo> {
         self.images.values().cloned().collect()
@@ -573,6 +1107,98 @@ impl ImageStore {
         self.images.remove(image_id);
         Ok(())
     }
+
+    /// Remove the images `filter` matches, except the newest
+    /// [`PruneFilter::keep_last`] of each repository, returning them
+    pub fn prune(&mut self, filter: &PruneFilter) -> Result<Vec<ImageInfo>> {
+        let mut images = self.list();
+        images.sort_by(|a, b| b.created.cmp(&a.created).then_with(|| a.id.cmp(&b.id)));
+        let mut newer: HashMap<String, usize> = HashMap::new();
+        let mut pruned = Vec::new();
+        for image in images {
+            let repository = format!(
+                "{}/{}",
+                image.reference.registry, image.reference.repository
+            );
+            let count = newer.entry(repository).or_default();
+            *count += 1;
+            if *count <= filter.keep_last || !filter.matches(image.created, &image.labels) {
+                continue;
+            }
+            self.remove(&image.id)?;
+            log::info!(
+                "Pruned image {} ({})",
+                image.reference.full_name(),
+                image.id
+            );
+            pruned.push(image);
+        }
+        Ok(pruned)
+    }
+}
+
+/// Short image ID for an image config digest
+#[cfg(feature = "image-pull")]
+fn image_id(config_digest: &str) -> String {
+    let hex = config_digest
+        .strip_prefix("sha256:")
+        .unwrap_or(config_digest);
+    hex[..hex.len().min(12)].to_string()
+}
+
+/// Info of an image with `config`, its parsed config blob
+#[cfg(feature = "image-pull")]
+fn image_info(
+    reference: ImageReference,
+    id: String,
+    size: u64,
+    layers: Vec<String>,
+    config: &serde_json::Value,
+) -> ImageInfo {
+    let labels = config["config"]["Labels"]
+        .as_object()
+        .map(|obj| {
+            obj.iter()
+                .filter_map(|(k, v)| v.as_str().map(|s| (k.clone(), s.to_string())))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    ImageInfo {
+        reference,
+        id,
+        size,
+        created: config["created"]
+            .as_str()
+            .and_then(parse_rfc3339_timestamp)
+            .unwrap_or(0),
+        architecture: config["architecture"]
+            .as_str()
+            .unwrap_or("amd64")
+            .to_string(),
+        os: config["os"].as_str().unwrap_or("linux").to_string(),
+        labels,
+        layers,
+    }
+}
+
+/// File name a layer is stored under in its image's directory
+fn layer_file_name(digest: &str) -> String {
+    let hex = digest.strip_prefix("sha256:").unwrap_or(digest);
+    format!("{}.tar.gz", &hex[..hex.len().min(12)])
+}
+
+/// Credentials stored for `registry`; pulls go on anonymously if they
+/// can't be read
+#[cfg(feature = "image-pull")]
+fn stored_credentials(registry: &str) -> Option<AuthConfig> {
+    match DockerConfig::load().and_then(|config| config.credentials(registry)) {
+        Ok(auth) => auth,
+        Err(e) => {
+            log::warn!("Failed to read the credentials for {}: {}", registry, e);
+            None
+        }
+    }
 }
 
 fn get_registry_url(registry: &str) -> String {
@@ -633,6 +1259,192 @@ mod tests {
         assert_eq!(ref4.reference, "latest");
     }
 
+    #[test]
+    #[cfg(feature = "image-pull")]
+    fn test_image_id() {
+        assert_eq!(image_id("sha256:0123456789abcdef0123"), "0123456789ab");
+        assert_eq!(image_id("0123456789abcdef"), "0123456789ab");
+        assert_eq!(image_id("sha256:abc"), "abc");
+    }
+
+    #[test]
+    fn test_layer_blobs() {
+        let root = std::env::temp_dir().join(format!("image-store-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let digest = format!("sha256:{}", "ab".repeat(32));
+        let info = ImageInfo {
+            reference: ImageReference::parse("alpine").unwrap(),
+            id: "0123456789ab".to_string(),
+            size: 0,
+            created: 0,
+            architecture: "amd64".to_string(),
+            os: "linux".to_string(),
+            labels: HashMap::new(),
+            layers: vec![digest.clone()],
+        };
+        let image_dir = root.join(&info.id);
+        std::fs::create_dir_all(&image_dir).unwrap();
+        std::fs::write(
+            image_dir.join("image_info.json"),
+            serde_json::to_string(&info).unwrap(),
+        )
+        .unwrap();
+
+        let store = ImageStore::new(&root).unwrap();
+        assert!(store.get(&info.id).is_some());
+        assert!(matches!(
+            store.layer_blobs(&info.id),
+            Err(ShimError::NotFound { .. })
+        ));
+        std::fs::write(image_dir.join("abababababab.tar.gz"), "layer").unwrap();
+        let blobs = store.layer_blobs(&info.id).unwrap();
+        assert_eq!(blobs, [(digest, image_dir.join("abababababab.tar.gz"))]);
+        assert!(store.layer_blobs("missing").is_err());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "image-pull")]
+    async fn test_pull_cancelled() {
+        let handle = PullHandle::default();
+        let waiting = {
+            let handle = handle.clone();
+            tokio::spawn(async move { handle.run(std::future::pending::<Result<()>>()).await })
+        };
+        handle.cancel();
+        assert!(waiting.await.unwrap().unwrap_err().is_cancelled());
+
+        let root = std::env::temp_dir().join(format!("image-cancel-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let mut store = ImageStore::new(&root).unwrap();
+        let (handle, pull) = store.pull_cancellable("localhost:1/test:latest", None);
+        handle.cancel();
+        let err = pull.await.unwrap_err();
+        assert!(err.is_cancelled(), "{}", err);
+        assert!(store.list().is_empty());
+        assert_eq!(std::fs::read_dir(&root).unwrap().count(), 0);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_prune() {
+        let root = std::env::temp_dir().join(format!("image-prune-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let image = |id: &str, reference: &str, created: u64, labels: &[(&str, &str)]| {
+            let info = ImageInfo {
+                reference: ImageReference::parse(reference).unwrap(),
+                id: id.to_string(),
+                size: 0,
+                created,
+                architecture: "amd64".to_string(),
+                os: "linux".to_string(),
+                labels: labels
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+                layers: Vec::new(),
+            };
+            let dir = root.join(id);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(
+                dir.join("image_info.json"),
+                serde_json::to_vec(&info).unwrap(),
+            )
+            .unwrap();
+        };
+        image("a1", "app:1", 100, &[("temporary", "true")]);
+        image("a2", "app:2", 200, &[("temporary", "true")]);
+        image("a3", "app:3", 300, &[]);
+        image("b1", "base:1", 100, &[]);
+        let ids = |images: Vec<ImageInfo>| -> Vec<String> {
+            let mut ids: Vec<String> = images.into_iter().map(|i| i.id).collect();
+            ids.sort();
+            ids
+        };
+
+        let mut store = ImageStore::new(&root).unwrap();
+        // The newest image of each repository is kept, whatever its labels
+        let temporary = PruneFilter::default()
+            .label(crate::LabelFilter::parse("temporary=true").unwrap())
+            .keep_last(1);
+        assert_eq!(ids(store.prune(&temporary).unwrap()), ["a1", "a2"]);
+        assert!(!root.join("a1").exists());
+
+        let old = PruneFilter {
+            until: Some(300),
+            ..Default::default()
+        };
+        assert_eq!(ids(store.prune(&old).unwrap()), ["b1"]);
+        assert_eq!(ids(store.list()), ["a3"]);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    #[cfg(feature = "image-pull")]
+    fn test_extract_limits() {
+        let root = std::env::temp_dir().join(format!("image-limits-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+
+        // 3 files of 1000 bytes
+        let layer = root.join("layer.tar.gz");
+        let encoder = flate2::write::GzEncoder::new(
+            std::fs::File::create(&layer).unwrap(),
+            flate2::Compression::default(),
+        );
+        let mut builder = tar::Builder::new(encoder);
+        for name in ["a", "b", "c"] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(1000);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, name, &[0u8; 1000][..])
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let extract = |limits: ExtractLimits, image_size: &mut u64| {
+            let rootfs = root.join("rootfs");
+            let _ = std::fs::remove_dir_all(&rootfs);
+            let store = ImageStore::new(root.join("store"))
+                .unwrap()
+                .with_limits(limits);
+            let result = store.extract_layer(&layer, &rootfs, image_size, &PullHandle::default());
+            let files = std::fs::read_dir(&rootfs).map_or(0, |dir| dir.count());
+            (result, files)
+        };
+
+        let mut image_size = 0;
+        let (result, files) = extract(ExtractLimits::default(), &mut image_size);
+        result.unwrap();
+        assert_eq!((files, image_size), (3, 3000));
+
+        let limits = ExtractLimits {
+            max_layer_size: 2500,
+            ..ExtractLimits::unlimited()
+        };
+        let (result, files) = extract(limits, &mut 0);
+        assert!(result.unwrap_err().to_string().contains("2500"));
+        assert_eq!(files, 2);
+
+        // Earlier layers count towards the image
+        let limits = ExtractLimits {
+            max_image_size: 4000,
+            ..ExtractLimits::unlimited()
+        };
+        let (result, files) = extract(limits, &mut 2500);
+        assert!(result.is_err());
+        assert_eq!(files, 1);
+
+        let limits = ExtractLimits {
+            max_layer_entries: 2,
+            ..ExtractLimits::unlimited()
+        };
+        assert!(extract(limits, &mut 0).0.is_err());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
     #[test]
     fn test_parse_timestamp() {
         let ts = parse_rfc3339_timestamp("2024-01-15T10:30:00Z");
@@ -0,0 +1,679 @@
+//! Image archives, for moving images between machines without a registry
+//!
+//! [`ImageStore::save`] writes an image as a tarball that is an OCI image
+//! layout and a `docker save` archive at once, the way Docker 25 and later
+//! do, so `docker load`, `podman load` and OCI tools all read it.
+//! [`ImageStore::load`] reads both, including the older `docker save`
+//! format with a directory per uncompressed layer, from a tarball (gzipped
+//! or not) or an OCI layout directory.
+
+use super::{image_id, image_info, layer_file_name, ImageStore, PullHandle};
+use crate::error::{Result, ShimError};
+use crate::progress::{percent, report};
+use crate::types::{ImageInfo, ImageReference, Operation};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+/// Annotation with an image's full name, as containerd and Docker write it
+const NAME_ANNOTATION: &str = "io.containerd.image.name";
+
+/// Annotation with an image's tag, or full name, in an OCI layout
+const REF_NAME_ANNOTATION: &str = "org.opencontainers.image.ref.name";
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// An image found in an unpacked archive
+struct ArchivedImage {
+    name: Option<String>,
+    config: PathBuf,
+    /// Layer files, bottom first, with the digest the archive gives them
+    layers: Vec<(PathBuf, Option<String>)>,
+}
+
+/// Directory removed when dropped
+struct TempDir(PathBuf);
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+impl ImageStore {
+    /// Write the image `reference`, a name or an ID, to a tarball at `path`
+    pub fn save(&self, reference: &str, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let info = self.resolve(reference)?;
+        let (manifest_digest, manifest) = self.manifest(&info.id)?;
+        let config = std::fs::read(self.root.join(&info.id).join("config.json"))?;
+        let config_digest = format!("sha256:{:x}", Sha256::digest(&config));
+        let layers = self.layer_blobs(&info.id)?;
+        let name = info.reference.full_name();
+
+        let index = serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.oci.image.index.v1+json",
+            "manifests": [{
+                "mediaType": Self::MANIFEST_MEDIA_TYPE,
+                "digest": manifest_digest,
+                "size": manifest.len(),
+                "annotations": {
+                    NAME_ANNOTATION: name,
+                    REF_NAME_ANNOTATION: info.reference.reference,
+                },
+            }],
+        });
+        let docker_manifest = serde_json::json!([{
+            "Config": blob_path(&config_digest),
+            "RepoTags": [name],
+            "Layers": layers.iter().map(|(digest, _)| blob_path(digest)).collect::<Vec<_>>(),
+        }]);
+
+        // Written next to the archive and only renamed once complete
+        let partial = path.with_file_name(format!(
+            "{}.partial",
+            path.file_name().unwrap_or_default().to_string_lossy()
+        ));
+        let written = (|| -> Result<()> {
+            let file = std::fs::File::create(&partial)?;
+            let mut builder = tar::Builder::new(std::io::BufWriter::new(file));
+            append(
+                &mut builder,
+                "oci-layout",
+                br#"{"imageLayoutVersion":"1.0.0"}"#,
+            )?;
+            append(&mut builder, "index.json", &serde_json::to_vec(&index)?)?;
+            append(
+                &mut builder,
+                "manifest.json",
+                &serde_json::to_vec(&docker_manifest)?,
+            )?;
+            append(&mut builder, &blob_path(&manifest_digest), &manifest)?;
+            append(&mut builder, &blob_path(&config_digest), &config)?;
+            let mut written = HashSet::new();
+            for (digest, file) in &layers {
+                // An image can have the same layer twice
+                if written.insert(digest) {
+                    builder.append_path_with_name(file, blob_path(digest))?;
+                }
+            }
+            builder.into_inner()?.flush()?;
+            Ok(())
+        })();
+
+        match written.and_then(|()| Ok(std::fs::rename(&partial, path)?)) {
+            Ok(()) => {
+                log::info!("Saved {} to {}", name, path.display());
+                Ok(())
+            }
+            Err(e) => {
+                let _ = std::fs::remove_file(&partial);
+                Err(ShimError::runtime_with_context(
+                    format!("Failed to save image {}: {}", name, e),
+                    format!("Archive: {}", path.display()),
+                ))
+            }
+        }
+    }
+
+    /// Add the images in the archive at `path` to the store and return them
+    ///
+    /// Images the store already has are returned as they are.
+    pub fn load(&mut self, path: impl AsRef<Path>) -> Result<Vec<ImageInfo>> {
+        let path = path.as_ref();
+        let target = path.display().to_string();
+        let staging = (!path.is_dir())
+            .then(|| TempDir(self.root.join(format!(".load-{}", std::process::id()))));
+        let dir = match staging {
+            Some(ref staging) => {
+                report(
+                    Operation::LoadImage,
+                    &target,
+                    "unpack",
+                    None,
+                    "Unpacking the archive",
+                );
+                unpack_archive(path, &staging.0)?;
+                staging.0.clone()
+            }
+            None => path.to_path_buf(),
+        };
+
+        let images = archived_images(&dir).map_err(|e| {
+            ShimError::runtime_with_context(
+                format!("Failed to read image archive: {}", e),
+                format!("Archive: {}", path.display()),
+            )
+        })?;
+        if images.is_empty() {
+            return Err(ShimError::validation(
+                "path",
+                format!("{} contains no images", path.display()),
+            ));
+        }
+        images
+            .into_iter()
+            .map(|image| self.import(image, &target))
+            .collect()
+    }
+
+    /// Image with the ID or name `reference`
+    fn resolve(&self, reference: &str) -> Result<&ImageInfo> {
+        if let Some(info) = self.images.get(reference) {
+            return Ok(info);
+        }
+        let name = ImageReference::parse(reference).map(|r| r.full_name());
+        self.images
+            .values()
+            .find(|info| Some(info.reference.full_name()) == name)
+            .ok_or_else(|| ShimError::not_found(format!("Image '{}'", reference)))
+    }
+
+    /// Store an image from an unpacked archive, extracting its rootfs
+    fn import(&mut self, image: ArchivedImage, target: &str) -> Result<ImageInfo> {
+        let config = std::fs::read(&image.config).map_err(|e| {
+            ShimError::io_with_context(e, format!("Failed to read {}", image.config.display()))
+        })?;
+        let config_digest = format!("sha256:{:x}", Sha256::digest(&config));
+        let id = image_id(&config_digest);
+        if let Some(info) = self.images.get(&id) {
+            log::info!("Image {} is already in the store", id);
+            return Ok(info.clone());
+        }
+        let reference = image
+            .name
+            .as_deref()
+            .and_then(ImageReference::parse)
+            .unwrap_or_else(|| ImageReference {
+                registry: "localhost".to_string(),
+                repository: id.clone(),
+                reference: "latest".to_string(),
+            });
+
+        let image_dir = self.root.join(&id);
+        let stored = (|| -> Result<ImageInfo> {
+            std::fs::create_dir_all(&image_dir)?;
+            std::fs::write(image_dir.join("config.json"), &config)?;
+
+            let name = reference.full_name();
+            let count = image.layers.len() as u64;
+            let mut layers = Vec::with_capacity(image.layers.len());
+            let mut size = 0;
+            for (file, digest) in &image.layers {
+                report(
+                    Operation::LoadImage,
+                    target,
+                    "store",
+                    Some(percent(layers.len() as u64, count)),
+                    format!(
+                        "Storing layer {} of {} of {}",
+                        layers.len() + 1,
+                        count,
+                        name
+                    ),
+                );
+                let (digest, stored_size) = store_layer(file, digest.as_deref(), &image_dir)?;
+                layers.push(digest);
+                size += stored_size;
+            }
+
+            let rootfs_path = image_dir.join("rootfs");
+            std::fs::create_dir_all(&rootfs_path)?;
+            let mut image_size = 0;
+            for (extracted, digest) in layers.iter().enumerate() {
+                report(
+                    Operation::LoadImage,
+                    target,
+                    "extract",
+                    Some(percent(extracted as u64, count)),
+                    format!(
+                        "Extracting layer {} of {} of {}",
+                        extracted + 1,
+                        count,
+                        name
+                    ),
+                );
+                self.extract_layer(
+                    &image_dir.join(layer_file_name(digest)),
+                    &rootfs_path,
+                    &mut image_size,
+                    &PullHandle::default(),
+                )?;
+            }
+
+            let config: serde_json::Value = serde_json::from_slice(&config)?;
+            let info = image_info(reference.clone(), id.clone(), size, layers, &config);
+            std::fs::write(
+                image_dir.join("image_info.json"),
+                serde_json::to_string_pretty(&info)?,
+            )?;
+            Ok(info)
+        })();
+
+        match stored {
+            Ok(info) => {
+                log::info!("Loaded {} ({})", info.reference.full_name(), id);
+                self.images.insert(id, info.clone());
+                Ok(info)
+            }
+            Err(e) => {
+                let _ = std::fs::remove_dir_all(&image_dir);
+                Err(ShimError::runtime_with_context(
+                    format!("Failed to load image {}: {}", reference.full_name(), e),
+                    format!("Image ID: {}", id),
+                ))
+            }
+        }
+    }
+}
+
+/// Path of the blob with `digest` in an OCI layout
+fn blob_path(digest: &str) -> String {
+    format!("blobs/{}", digest.replacen(':', "/", 1))
+}
+
+fn append(builder: &mut tar::Builder<impl Write>, path: &str, data: &[u8]) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, path, data)?;
+    Ok(())
+}
+
+/// Unpack the tarball at `path`, gzipped or not, into `dir`
+fn unpack_archive(path: &Path, dir: &Path) -> Result<()> {
+    let open = || {
+        std::fs::File::open(path).map_err(|e| {
+            ShimError::io_with_context(e, format!("Failed to open {}", path.display()))
+        })
+    };
+    let mut magic = [0u8; 2];
+    let gzipped = open()?.read_exact(&mut magic).is_ok() && magic == GZIP_MAGIC;
+    let reader: Box<dyn Read> = if gzipped {
+        Box::new(flate2::read::GzDecoder::new(open()?))
+    } else {
+        Box::new(open()?)
+    };
+    // Entries that would land outside `dir` are skipped
+    tar::Archive::new(reader)
+        .unpack(dir)
+        .map_err(|e| ShimError::io_with_context(e, format!("Failed to unpack {}", path.display())))
+}
+
+/// `relative`, a path named by the archive, within `dir`
+fn archive_path(dir: &Path, relative: &str) -> Result<PathBuf> {
+    let path = Path::new(relative);
+    if !path
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(ShimError::validation(
+            "path",
+            format!("Path '{}' leaves the archive", relative),
+        ));
+    }
+    Ok(dir.join(path))
+}
+
+/// Path in `dir`, an OCI layout, of the blob with `digest`
+fn layout_blob(dir: &Path, digest: &str) -> Result<PathBuf> {
+    let valid = digest.split_once(':').is_some_and(|(algorithm, hex)| {
+        !algorithm.is_empty()
+            && algorithm.chars().all(|c| c.is_ascii_alphanumeric())
+            && !hex.is_empty()
+            && hex.chars().all(|c| c.is_ascii_hexdigit())
+    });
+    if !valid {
+        return Err(ShimError::validation(
+            "digest",
+            format!("Invalid digest '{}'", digest),
+        ));
+    }
+    archive_path(dir, &blob_path(digest))
+}
+
+/// Digest named by a `blobs/<algorithm>/<hex>` path
+fn digest_of_blob_path(path: &str) -> Option<String> {
+    let mut parts = path.strip_prefix("blobs/")?.splitn(2, '/');
+    Some(format!("{}:{}", parts.next()?, parts.next()?))
+}
+
+fn read_json(path: &Path) -> Result<serde_json::Value> {
+    let data = std::fs::read(path)
+        .map_err(|e| ShimError::io_with_context(e, format!("Failed to read {}", path.display())))?;
+    Ok(serde_json::from_slice(&data)?)
+}
+
+/// The images in `dir`, an unpacked archive
+///
+/// A `docker save` manifest is preferred where both are present, since it
+/// names every image.
+fn archived_images(dir: &Path) -> Result<Vec<ArchivedImage>> {
+    let docker_manifest = dir.join("manifest.json");
+    if docker_manifest.is_file() {
+        let manifest = read_json(&docker_manifest)?;
+        let entries = manifest
+            .as_array()
+            .ok_or_else(|| ShimError::validation("manifest.json", "Not a list of images"))?;
+        return entries
+            .iter()
+            .map(|entry| {
+                let config = entry["Config"]
+                    .as_str()
+                    .ok_or_else(|| ShimError::validation("manifest.json", "Image has no Config"))?;
+                let layers = entry["Layers"]
+                    .as_array()
+                    .ok_or_else(|| ShimError::validation("manifest.json", "Image has no Layers"))?
+                    .iter()
+                    .map(|layer| {
+                        let layer = layer.as_str().ok_or_else(|| {
+                            ShimError::validation("manifest.json", "Layer is not a path")
+                        })?;
+                        Ok((archive_path(dir, layer)?, digest_of_blob_path(layer)))
+                    })
+                    .collect::<Result<_>>()?;
+                Ok(ArchivedImage {
+                    name: entry["RepoTags"][0].as_str().map(str::to_string),
+                    config: archive_path(dir, config)?,
+                    layers,
+                })
+            })
+            .collect();
+    }
+
+    if dir.join("index.json").is_file() {
+        let index = read_json(&dir.join("index.json"))?;
+        let manifests = index["manifests"]
+            .as_array()
+            .ok_or_else(|| ShimError::validation("index.json", "No manifests"))?;
+        return manifests
+            .iter()
+            .map(|descriptor| {
+                let annotations = &descriptor["annotations"];
+                let name = annotations[NAME_ANNOTATION].as_str().or_else(|| {
+                    // Often just a tag, which names no image
+                    annotations[REF_NAME_ANNOTATION]
+                        .as_str()
+                        .filter(|name| name.contains('/') || name.contains(':'))
+                });
+                layout_image(dir, descriptor, name.map(str::to_string))
+            })
+            .collect();
+    }
+
+    Err(ShimError::validation(
+        "path",
+        "Neither an OCI image layout nor a docker save archive",
+    ))
+}
+
+/// The image a manifest `descriptor` of an OCI layout points at, picking
+/// the platform of this machine from multi-platform images
+fn layout_image(
+    dir: &Path,
+    descriptor: &serde_json::Value,
+    name: Option<String>,
+) -> Result<ArchivedImage> {
+    let digest = descriptor["digest"]
+        .as_str()
+        .ok_or_else(|| ShimError::validation("index.json", "Manifest has no digest"))?;
+    let mut manifest = read_json(&layout_blob(dir, digest)?)?;
+    if let Some(platforms) = manifest["manifests"].as_array() {
+        let arch = match std::env::consts::ARCH {
+            "x86_64" => "amd64",
+            "aarch64" => "arm64",
+            arch => arch,
+        };
+        let chosen = platforms
+            .iter()
+            .find(|m| m["platform"]["os"] == "linux" && m["platform"]["architecture"] == arch)
+            .or_else(|| platforms.first())
+            .ok_or_else(|| ShimError::validation("index", "Image index lists no manifests"))?;
+        let digest = chosen["digest"]
+            .as_str()
+            .ok_or_else(|| ShimError::validation("index", "Manifest has no digest"))?;
+        manifest = read_json(&layout_blob(dir, digest)?)?;
+    }
+
+    let config = manifest["config"]["digest"]
+        .as_str()
+        .ok_or_else(|| ShimError::validation("manifest", "Missing config digest"))?;
+    let layers = manifest["layers"]
+        .as_array()
+        .ok_or_else(|| ShimError::validation("manifest", "Missing layers"))?
+        .iter()
+        .map(|layer| {
+            let digest = layer["digest"]
+                .as_str()
+                .ok_or_else(|| ShimError::validation("manifest", "Layer has no digest"))?;
+            Ok((layout_blob(dir, digest)?, Some(digest.to_string())))
+        })
+        .collect::<Result<_>>()?;
+    Ok(ArchivedImage {
+        name,
+        config: layout_blob(dir, config)?,
+        layers,
+    })
+}
+
+/// Writer that hashes what passes through it
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Store the layer `file` in `image_dir` gzipped, as pulled layers are,
+/// returning its digest there and its size
+///
+/// `digest` is the one the archive gives the file, which it must match.
+fn store_layer(file: &Path, digest: Option<&str>, image_dir: &Path) -> Result<(String, u64)> {
+    let open = || {
+        std::fs::File::open(file).map_err(|e| {
+            ShimError::io_with_context(e, format!("Failed to read {}", file.display()))
+        })
+    };
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut open()?, &mut hasher)?;
+    let file_digest = format!("sha256:{:x}", hasher.finalize());
+    if let Some(digest) = digest {
+        if digest != file_digest {
+            return Err(ShimError::runtime(format!(
+                "Digest mismatch for {}: expected {}, got {}",
+                file.display(),
+                digest,
+                file_digest
+            )));
+        }
+    }
+
+    let mut magic = [0u8; 4];
+    let read = open()?.read(&mut magic)?;
+    if read == 4 && magic == ZSTD_MAGIC {
+        return Err(ShimError::runtime(format!(
+            "zstd-compressed layers are not supported: {}",
+            file.display()
+        )));
+    }
+
+    let partial = image_dir.join("layer.partial");
+    let digest = if read >= 2 && magic[..2] == GZIP_MAGIC {
+        std::fs::copy(file, &partial)?;
+        file_digest
+    } else {
+        let mut encoder = flate2::write::GzEncoder::new(
+            HashingWriter {
+                inner: std::fs::File::create(&partial)?,
+                hasher: Sha256::new(),
+            },
+            flate2::Compression::default(),
+        );
+        std::io::copy(&mut open()?, &mut encoder)?;
+        let written = encoder.finish()?;
+        format!("sha256:{:x}", written.hasher.finalize())
+    };
+    let path = image_dir.join(layer_file_name(&digest));
+    std::fs::rename(&partial, &path)?;
+    Ok((digest, std::fs::metadata(&path)?.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tarball of `files`, gzipped if asked
+    fn tarball(files: &[(&str, &[u8])], gzip: bool) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, data) in files {
+            append(&mut builder, path, data).unwrap();
+        }
+        let tar = builder.into_inner().unwrap();
+        if !gzip {
+            return tar;
+        }
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&tar).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn digest(data: &[u8]) -> String {
+        format!("sha256:{:x}", Sha256::digest(data))
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let root = std::env::temp_dir().join(format!("image-archive-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+
+        // An image as a pull would have stored it
+        let layer = tarball(&[("etc/hostname", b"saved\n")], true);
+        let config = br#"{"architecture":"arm64","os":"linux","config":{"Labels":{"a":"b"}}}"#;
+        let id = image_id(&digest(config));
+        let image_dir = root.join("store").join(&id);
+        std::fs::create_dir_all(&image_dir).unwrap();
+        std::fs::write(image_dir.join("config.json"), config).unwrap();
+        std::fs::write(image_dir.join(layer_file_name(&digest(&layer))), &layer).unwrap();
+        let info = image_info(
+            ImageReference::parse("ghcr.io/team/app:1.0").unwrap(),
+            id.clone(),
+            layer.len() as u64,
+            vec![digest(&layer)],
+            &serde_json::from_slice(config).unwrap(),
+        );
+        std::fs::write(
+            image_dir.join("image_info.json"),
+            serde_json::to_string(&info).unwrap(),
+        )
+        .unwrap();
+
+        let store = ImageStore::new(root.join("store")).unwrap();
+        let archive = root.join("app.tar");
+        assert!(store.save("missing", &archive).unwrap_err().is_not_found());
+        store.save("ghcr.io/team/app:1.0", &archive).unwrap();
+        assert!(!root.join("app.tar.partial").exists());
+
+        let mut loaded = ImageStore::new(root.join("loaded")).unwrap();
+        let images = loaded.load(&archive).unwrap();
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].id, id);
+        assert_eq!(images[0].reference.full_name(), "ghcr.io/team/app:1.0");
+        assert_eq!(images[0].layers, [digest(&layer)]);
+        assert_eq!(images[0].architecture, "arm64");
+        assert_eq!(images[0].labels["a"], "b");
+        let rootfs = loaded.get_rootfs(&id).unwrap();
+        assert_eq!(
+            std::fs::read(rootfs.join("etc/hostname")).unwrap(),
+            b"saved\n"
+        );
+        assert_eq!(loaded.manifest(&id).unwrap(), store.manifest(&id).unwrap());
+        // Loading twice keeps the image
+        assert_eq!(loaded.load(&archive).unwrap()[0].id, id);
+
+        // The same archive read as an OCI layout only
+        let layout = root.join("layout");
+        unpack_archive(&archive, &layout).unwrap();
+        std::fs::remove_file(layout.join("manifest.json")).unwrap();
+        let mut from_layout = ImageStore::new(root.join("from-layout")).unwrap();
+        let images = from_layout.load(&layout).unwrap();
+        assert_eq!(images[0].reference.full_name(), "ghcr.io/team/app:1.0");
+        assert_eq!(images[0].layers, [digest(&layer)]);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_load_docker_archive() {
+        let root = std::env::temp_dir().join(format!("docker-archive-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+
+        // The layout of `docker save` before Docker 25, gzipped
+        let layer = tarball(&[("hello.txt", b"hi")], false);
+        let config = br#"{"architecture":"amd64","os":"linux"}"#;
+        let config_name = format!("{}.json", &digest(config)[7..]);
+        let manifest = format!(
+            r#"[{{"Config":"{}","RepoTags":["alpine:3.19"],"Layers":["abc/layer.tar"]}}]"#,
+            config_name
+        );
+        let archive = root.join("alpine.tar.gz");
+        std::fs::write(
+            &archive,
+            tarball(
+                &[
+                    ("manifest.json", manifest.as_bytes()),
+                    (&config_name, config),
+                    ("abc/layer.tar", &layer),
+                ],
+                true,
+            ),
+        )
+        .unwrap();
+
+        let mut store = ImageStore::new(root.join("store")).unwrap();
+        let images = store.load(&archive).unwrap();
+        assert_eq!(
+            images[0].reference.full_name(),
+            "docker.io/library/alpine:3.19"
+        );
+        // Uncompressed layers are stored gzipped like pulled ones
+        let (layer_digest, path) = store.layer_blobs(&images[0].id).unwrap().remove(0);
+        let stored = std::fs::read(path).unwrap();
+        assert_eq!(stored[..2], GZIP_MAGIC);
+        assert_eq!(layer_digest, digest(&stored));
+        let rootfs = store.get_rootfs(&images[0].id).unwrap();
+        assert_eq!(std::fs::read(rootfs.join("hello.txt")).unwrap(), b"hi");
+        // Nothing is left of the unpacked archive
+        assert_eq!(std::fs::read_dir(root.join("store")).unwrap().count(), 1);
+
+        let escaping = root.join("escaping.tar");
+        std::fs::write(
+            &escaping,
+            tarball(
+                &[(
+                    "manifest.json",
+                    br#"[{"Config":"../../etc/passwd","Layers":[]}]"#,
+                )],
+                false,
+            ),
+        )
+        .unwrap();
+        assert!(store.load(&escaping).is_err());
+        assert!(store.load(root.join("store")).is_err());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,190 @@
+//! Hooks around the calls made through a runtime
+//!
+//! Embedders that log, meter, rate-limit or vet what is done with a
+//! [`ContainerRuntime`](crate::ContainerRuntime) install an [`Interceptor`]
+//! instead of wrapping each of its methods. Every call that reaches the
+//! containers goes through the interceptors; a method built on others, like
+//! `create_pod` on `create` and `start`, goes through them once for itself
+//! and once for each of those calls.
+
+use crate::error::{Result, ShimError};
+use std::time::Duration;
+
+/// A call made through a runtime
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Call {
+    /// The runtime method, e.g. `start`
+    pub method: &'static str,
+    /// What the call acts on: a container, pod or exec session ID, or a
+    /// checkpoint as `<container>/<name>`
+    pub target: Option<String>,
+    /// Whether the call changes something, rather than only inspecting it
+    pub mutating: bool,
+}
+
+impl Call {
+    /// A call that only inspects
+    pub fn read(method: &'static str) -> Self {
+        Self {
+            method,
+            target: None,
+            mutating: false,
+        }
+    }
+
+    /// A call that changes something
+    pub fn write(method: &'static str) -> Self {
+        Self {
+            mutating: true,
+            ..Self::read(method)
+        }
+    }
+
+    /// The call, acting on `target`
+    pub fn on(mut self, target: impl Into<String>) -> Self {
+        self.target = Some(target.into());
+        self
+    }
+}
+
+/// Runs before and after each call made through a runtime
+///
+/// Interceptors run in the order they were installed before a call, and in
+/// the reverse order after it, like nested layers. Both hooks default to
+/// doing nothing.
+pub trait Interceptor: Send + Sync {
+    /// Called before `call` runs; an error fails the call without running it
+    ///
+    /// The interceptors that were called before this one are then called
+    /// after the call, with that error, so what they counted can be undone.
+    fn before(&self, call: &Call) -> Result<()> {
+        let _ = call;
+        Ok(())
+    }
+
+    /// Called once `call` is done, with how long it ran and the error it
+    /// failed with, if it did
+    fn after(&self, call: &Call, elapsed: Duration, error: Option<&ShimError>) {
+        let _ = (call, elapsed, error);
+    }
+}
+
+/// Run `run` as `call`, through `interceptors`
+pub(crate) async fn intercept<T>(
+    interceptors: &[Box<dyn Interceptor>],
+    call: Call,
+    run: impl std::future::Future<Output = Result<T>>,
+) -> Result<T> {
+    if interceptors.is_empty() {
+        return run.await;
+    }
+    for (i, interceptor) in interceptors.iter().enumerate() {
+        if let Err(e) = interceptor.before(&call) {
+            log::debug!("{} refused by an interceptor: {}", call.method, e);
+            for earlier in interceptors[..i].iter().rev() {
+                earlier.after(&call, Duration::ZERO, Some(&e));
+            }
+            return Err(e);
+        }
+    }
+    let started = std::time::Instant::now();
+    let result = run.await;
+    let elapsed = started.elapsed();
+    for interceptor in interceptors.iter().rev() {
+        interceptor.after(&call, elapsed, result.as_ref().err());
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Records the hooks called, and refuses the methods in `refuse`
+    struct Recorder {
+        name: &'static str,
+        refuse: &'static [&'static str],
+        seen: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Interceptor for Recorder {
+        fn before(&self, call: &Call) -> Result<()> {
+            self.seen
+                .lock()
+                .unwrap()
+                .push(format!("{} before {}", self.name, call.method));
+            if self.refuse.contains(&call.method) {
+                return Err(ShimError::permission_denied(
+                    format!("No {}", call.method),
+                    "Ask for it",
+                ));
+            }
+            Ok(())
+        }
+
+        fn after(&self, call: &Call, _elapsed: Duration, error: Option<&ShimError>) {
+            self.seen.lock().unwrap().push(format!(
+                "{} after {} ok={}",
+                self.name,
+                call.method,
+                error.is_none()
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_intercept() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let interceptors: Vec<Box<dyn Interceptor>> = vec![
+            Box::new(Recorder {
+                name: "outer",
+                refuse: &[],
+                seen: seen.clone(),
+            }),
+            Box::new(Recorder {
+                name: "inner",
+                refuse: &["delete"],
+                seen: seen.clone(),
+            }),
+        ];
+
+        let listed = intercept(&interceptors, Call::read("list"), async { Ok(3) }).await;
+        assert_eq!(listed.unwrap(), 3);
+        assert_eq!(
+            std::mem::take(&mut *seen.lock().unwrap()),
+            [
+                "outer before list",
+                "inner before list",
+                "inner after list ok=true",
+                "outer after list ok=true",
+            ]
+        );
+
+        // Refused, so it does not run, and the outer interceptor sees why
+        let ran = Mutex::new(false);
+        let deleted = intercept(&interceptors, Call::write("delete").on("web"), async {
+            *ran.lock().unwrap() = true;
+            Ok(())
+        })
+        .await;
+        assert!(deleted.unwrap_err().is_permission_denied());
+        assert!(!*ran.lock().unwrap());
+        assert_eq!(
+            std::mem::take(&mut *seen.lock().unwrap()),
+            [
+                "outer before delete",
+                "inner before delete",
+                "outer after delete ok=false",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_call() {
+        let call = Call::write("restore").on("web/before-upgrade");
+        assert!(call.mutating);
+        assert_eq!(call.target.as_deref(), Some("web/before-upgrade"));
+        assert!(!Call::read("list").mutating);
+    }
+}
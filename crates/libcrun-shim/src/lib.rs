@@ -1,33 +1,128 @@
+mod auth;
+pub mod compat;
 pub mod cri;
+mod detach;
+pub mod disk;
+pub mod doctor;
 mod error;
 pub mod events;
+mod execs;
+mod group;
+mod ids;
 pub mod image;
+mod intercept;
+mod mux;
+mod names;
+mod passthrough;
+mod pod;
+pub mod progress;
+mod proxy;
 #[cfg(unix)]
 pub mod pty;
+mod replicas;
+mod report;
 pub mod shim;
+#[cfg(unix)]
+pub mod socket;
+pub mod support;
+#[cfg(feature = "testcontainers")]
+pub mod testcontainers;
 mod types;
 
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod checkpoints;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod copy;
 #[cfg(target_os = "linux")]
 mod linux;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod metrics;
 
 #[cfg(target_os = "macos")]
 pub mod macos;
 
-pub use cri::{CriServer, ImageService, RuntimeService};
+pub use auth::{AuthConfig, DockerConfig};
+pub use cri::{CriServer, ImageService, RuntimeService, SandboxStore};
+pub use detach::{DetachFilter, DetachKeys};
 pub use error::*;
 pub use events::{global_events, subscribe_events, EventBroadcaster, EventReceiver};
-pub use image::ImageStore;
+pub use group::DEPENDENCY_TIMEOUT;
+pub use ids::{IdGenerator, RandomIdGenerator, SequentialIdGenerator};
+pub use image::{ImageStore, PullHandle};
+pub use intercept::{Call, Interceptor};
+pub use mux::{multiplex, read_multiplexed};
+pub use names::NameGenerator;
+pub use progress::{report_progress, subscribe_progress};
+pub use proxy::ProxyConfig;
 #[cfg(unix)]
-pub use pty::{get_terminal_size, InteractiveSession, Pty};
+pub use pty::{get_terminal_size, watch_terminal_size, InteractiveSession, Pty, RawMode};
 pub use shim::{ShimV2, TaskService};
 pub use types::*;
 
+/// How this library was built
+///
+/// The libcrun version is only known on Linux; on macOS libcrun runs in the
+/// VM, see `ContainerRuntime::agent_build_info`.
+pub fn build_info() -> BuildInfo {
+    #[cfg(target_os = "linux")]
+    let libcrun_version = libcrun_sys::LIBCRUN_VERSION.map(str::to_string);
+    #[cfg(not(target_os = "linux"))]
+    let libcrun_version = None;
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        git_sha: option_env!("GIT_SHA").map(str::to_string),
+        build_timestamp: env!("BUILD_TIMESTAMP").parse().unwrap_or(0),
+        libcrun_version,
+        min_protocol_version: libcrun_shim_proto::MIN_PROTOCOL_VERSION,
+        max_protocol_version: libcrun_shim_proto::PROTOCOL_VERSION,
+    }
+}
+
 pub struct ContainerRuntime {
     #[cfg(target_os = "linux")]
     inner: linux::LinuxRuntime,
 
     #[cfg(target_os = "macos")]
     inner: macos::MacOsRuntime,
+
+    /// Container profiles from the runtime configuration
+    profiles: std::collections::HashMap<String, ContainerProfile>,
+
+    /// Startup dependencies of containers created through this runtime
+    dependencies: std::sync::RwLock<std::collections::HashMap<String, Vec<DependsOn>>>,
+
+    /// Pods created through this runtime, keyed by pod (sandbox) ID
+    pods: std::sync::RwLock<std::collections::HashMap<String, pod::PodState>>,
+
+    /// Memory limits of containers created through this runtime, checked
+    /// against the memory available when they are started
+    memory_limits: std::sync::RwLock<std::collections::HashMap<String, u64>>,
+
+    /// Free memory and disk required to start a container
+    resource_guard: ResourceGuard,
+
+    /// Proxy variables set in containers, with the host's filled in
+    proxy: ProxyConfig,
+
+    /// Reservations and limits of containers created through this runtime,
+    /// checked against the machine's capacity when they are created
+    claims: std::sync::RwLock<std::collections::HashMap<String, (Reservation, Reservation)>>,
+
+    /// Configurations of containers created with [`PullPolicy::AutoUpdate`],
+    /// to recreate them from in [`Self::update_images`]
+    auto_updates: std::sync::RwLock<std::collections::HashMap<String, ContainerConfig>>,
+
+    /// Only inspection is allowed, see [`Self::new_read_only`]
+    read_only: bool,
+
+    /// IDs for containers created without one
+    id_generator: Box<dyn IdGenerator>,
+
+    /// Names for containers created without one
+    name_generator: Box<dyn IdGenerator>,
+
+    /// Hooks run around each call, see [`Self::with_interceptor`]
+    interceptors: Vec<Box<dyn Interceptor>>,
 }
 
 impl ContainerRuntime {
@@ -38,125 +133,1609 @@ impl ContainerRuntime {
 
     /// Create a new runtime with custom configuration
     pub async fn new_with_config(config: RuntimeConfig) -> Result<Self> {
+        Self::open(config, false).await
+    }
+
+    /// Create a runtime handle that can only inspect containers
+    ///
+    /// Listing, metrics, logs, health, exec history and events work as
+    /// usual; anything that would start, stop, delete or otherwise change a
+    /// container, or copy files or checkpoints out of one, fails with
+    /// [`ShimError::PermissionDenied`]. Meant for dashboards and exporters.
+    /// On macOS the agent connections are switched to the read-only role as
+    /// well, so the agent itself refuses changes.
+    pub async fn new_read_only() -> Result<Self> {
+        Self::new_read_only_with_config(RuntimeConfig::from_env()).await
+    }
+
+    /// Create a read-only runtime handle with custom configuration
+    pub async fn new_read_only_with_config(config: RuntimeConfig) -> Result<Self> {
+        Self::open(config, true).await
+    }
+
+    async fn open(config: RuntimeConfig, read_only: bool) -> Result<Self> {
+        let profiles = config.profiles.clone();
+        let resource_guard = config.resource_guard.clone();
+        let proxy = config.proxy.clone().with_host_env();
+
         #[cfg(target_os = "linux")]
         {
             let _ = config; // Linux doesn't use config yet
             return Ok(Self {
                 inner: linux::LinuxRuntime::new()?,
+                profiles,
+                dependencies: Default::default(),
+                pods: Default::default(),
+                auto_updates: Default::default(),
+                memory_limits: Default::default(),
+                resource_guard,
+                proxy,
+                claims: Default::default(),
+                read_only,
+                id_generator: Box::new(RandomIdGenerator),
+                name_generator: Box::new(NameGenerator),
+                interceptors: Vec::new(),
             });
         }
 
         #[cfg(target_os = "macos")]
         return Ok(Self {
-            inner: macos::MacOsRuntime::new_with_config(config).await?,
+            inner: if read_only {
+                macos::MacOsRuntime::new_read_only_with_config(config).await?
+            } else {
+                macos::MacOsRuntime::new_with_config(config).await?
+            },
+            profiles,
+            dependencies: Default::default(),
+            pods: Default::default(),
+            auto_updates: Default::default(),
+            memory_limits: Default::default(),
+            resource_guard,
+            proxy,
+            claims: Default::default(),
+            read_only,
+            id_generator: Box::new(RandomIdGenerator),
+            name_generator: Box::new(NameGenerator),
+            interceptors: Vec::new(),
         });
     }
 
+    /// Use `generator` for the IDs of containers created without one
+    ///
+    /// The default is [`RandomIdGenerator`]. Tests install a
+    /// [`SequentialIdGenerator`] to get the same IDs on every run.
+    pub fn with_id_generator(mut self, generator: impl IdGenerator + 'static) -> Self {
+        self.id_generator = Box::new(generator);
+        self
+    }
+
+    /// Use `generator` for the names of containers created without one
+    ///
+    /// The default is [`NameGenerator`], for names like `brave_hopper`.
+    pub fn with_name_generator(mut self, generator: impl IdGenerator + 'static) -> Self {
+        self.name_generator = Box::new(generator);
+        self
+    }
+
+    /// Run `interceptor` around each call made through this runtime
+    ///
+    /// Interceptors installed earlier wrap the ones installed later. One
+    /// refusing a call in [`Interceptor::before`] fails it, e.g. to enforce
+    /// a policy or a quota.
+    pub fn with_interceptor(mut self, interceptor: impl Interceptor + 'static) -> Self {
+        self.interceptors.push(Box::new(interceptor));
+        self
+    }
+
+    /// Run `run` as `call`, through the installed interceptors
+    async fn intercept<T>(
+        &self,
+        call: Call,
+        run: impl std::future::Future<Output = Result<T>>,
+    ) -> Result<T> {
+        intercept::intercept(&self.interceptors, call, run).await
+    }
+
+    /// Whether this handle was opened with [`Self::new_read_only`]
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Fail if this handle is read-only
+    fn check_writable(&self, operation: &str) -> Result<()> {
+        if !self.read_only {
+            return Ok(());
+        }
+        Err(ShimError::permission_denied(
+            format!("Cannot {} with a read-only runtime", operation),
+            "Use a runtime opened with ContainerRuntime::new to make changes",
+        ))
+    }
+
     /// Get the runtime configuration (macOS only)
     #[cfg(target_os = "macos")]
     pub fn config(&self) -> &RuntimeConfig {
         self.inner.config()
     }
 
-    pub async fn create(&self, config: ContainerConfig) -> Result<String> {
-        self.inner.create(config).await
+    /// Whether the VM is running and which transport reaches its agent
+    ///
+    /// On Linux containers run natively, so there is no VM or transport.
+    pub async fn runtime_status(&self) -> RuntimeStatus {
+        #[cfg(target_os = "macos")]
+        return self.inner.status().await;
+
+        #[cfg(not(target_os = "macos"))]
+        RuntimeStatus {
+            vm_running: false,
+            vm_managed: false,
+            transport: None,
+            read_only: self.read_only,
+        }
+    }
+
+    /// Get the VM agent's version information (macOS only)
+    #[cfg(target_os = "macos")]
+    pub async fn agent_info(&self) -> Result<AgentInfo> {
+        self.intercept(Call::read("agent_info"), async {
+            self.inner.agent_info().await
+        })
+        .await
+    }
+
+    /// How the VM agent was built (macOS only)
+    #[cfg(target_os = "macos")]
+    pub async fn agent_build_info(&self) -> Result<BuildInfo> {
+        self.intercept(Call::read("agent_build_info"), async {
+            self.inner.agent_build_info().await
+        })
+        .await
+    }
+
+    /// The VM agent's recent log, the guest kernel log and the agent's state
+    /// files, unredacted (macOS only)
+    ///
+    /// See [`support::collect`] for a redacted support bundle. The state
+    /// files hold container environments, so read-only handles cannot
+    /// collect them.
+    #[cfg(target_os = "macos")]
+    pub async fn agent_diagnostics(&self) -> Result<AgentDiagnostics> {
+        self.intercept(Call::read("agent_diagnostics"), async {
+            self.check_writable("collect agent diagnostics")?;
+            self.inner.diagnostics().await
+        })
+        .await
+    }
+
+    /// Upgrade the VM agent binary in place, without rebuilding the VM (macOS only)
+    ///
+    /// If `guest_path` is given the agent reads the binary from that path inside
+    /// the guest (e.g. a virtiofs share) instead of receiving it over RPC.
+    #[cfg(target_os = "macos")]
+    pub async fn upgrade_agent(
+        &self,
+        binary: &std::path::Path,
+        guest_path: Option<&str>,
+    ) -> Result<AgentUpgrade> {
+        self.intercept(Call::write("upgrade_agent"), async {
+            self.check_writable("upgrade the agent")?;
+            self.inner.upgrade_agent(binary, guest_path).await
+        })
+        .await
+    }
+
+    /// Bind mount a host directory at `destination` in a running container,
+    /// without recreating it (macOS only)
+    #[cfg(target_os = "macos")]
+    pub async fn mount(
+        &self,
+        id: &str,
+        host_dir: &std::path::Path,
+        destination: &str,
+        read_only: bool,
+    ) -> Result<()> {
+        self.intercept(Call::write("mount").on(id), async {
+            self.check_writable("mount into a container")?;
+            self.inner.mount(id, host_dir, destination, read_only).await
+        })
+        .await
+    }
+
+    /// Usage of the filesystems mounted in the VM (macOS only)
+    #[cfg(target_os = "macos")]
+    pub async fn guest_disk_usage(&self) -> Result<Vec<FilesystemUsage>> {
+        self.intercept(Call::read("guest_disk_usage"), async {
+            self.inner.disk_usage().await
+        })
+        .await
+    }
+
+    /// Addresses `host` resolves to inside the VM, as containers would look
+    /// it up (macOS only)
+    #[cfg(target_os = "macos")]
+    pub async fn resolve(&self, host: &str) -> Result<Vec<String>> {
+        self.intercept(Call::read("resolve"), async {
+            self.inner.resolve(host).await
+        })
+        .await
+    }
+
+    /// Trim the VM's disk-backed filesystems, returning freed blocks to the
+    /// host's disk images (macOS only)
+    ///
+    /// The agent also does this periodically, see its `--fstrim-interval`.
+    #[cfg(target_os = "macos")]
+    pub async fn trim_guest_disks(&self) -> Result<Vec<TrimResult>> {
+        self.intercept(Call::write("trim_guest_disks"), async {
+            self.check_writable("trim guest disks")?;
+            self.inner.trim().await
+        })
+        .await
+    }
+
+    /// Copy an image's layers into the VM's blob cache, sending only the
+    /// layers the VM does not already have (macOS only)
+    #[cfg(target_os = "macos")]
+    pub async fn sync_image(&self, store: &ImageStore, image_id: &str) -> Result<BlobSyncReport> {
+        self.intercept(Call::write("sync_image").on(image_id), async {
+            self.check_writable("sync images")?;
+            let blobs = store.layer_blobs(image_id)?;
+            self.inner.sync_blobs(&blobs).await
+        })
+        .await
+    }
+
+    /// Create a container, returning its ID
+    ///
+    /// A config without an ID gets one from the runtime's [`IdGenerator`],
+    /// drawn again while it is the ID of an existing container.
+    /// Fails with [`ShimError::Unavailable`] when the container's reservation
+    /// does not fit in the machine's [`Capacity`].
+    pub async fn create(&self, mut config: ContainerConfig) -> Result<String> {
+        if config.id.is_empty() {
+            config.id = self.unused_id().await;
+        }
+        self.intercept(Call::write("create").on(&config.id), async {
+            self.check_writable("create containers")?;
+            match &config.name {
+                Some(name) => names::validate(name)?,
+                None => config.name = Some(self.unused_name().await),
+            }
+            let auto_update = match config.pull_policy {
+                PullPolicy::Local => None,
+                PullPolicy::AutoUpdate if config.image.is_some() => Some(config.clone()),
+                PullPolicy::AutoUpdate => {
+                    return Err(ShimError::validation(
+                        "pull_policy",
+                        "Auto-update needs the image reference the container runs",
+                    ))
+                }
+            };
+            if let Some(name) = config.profile.clone() {
+                let profile = self.profiles.get(&name).ok_or_else(|| {
+                    ShimError::validation(
+                        "profile",
+                        format!("Unknown container profile '{}'", name),
+                    )
+                })?;
+                profile.apply_to(&mut config);
+            }
+            let forwarded = passthrough::expand(
+                &config.env_passthrough,
+                &config.env,
+                passthrough::host_vars(),
+            );
+            config.env.extend(forwarded);
+            self.proxy.apply_to_env(&mut config.env);
+            let pod_id = config.pod.clone();
+            if let Some(ref pod_id) = pod_id {
+                let pods = self.pods.read().unwrap();
+                let pod = pods
+                    .get(pod_id)
+                    .ok_or_else(|| ShimError::not_found(format!("Pod '{}'", pod_id)))?;
+                pod.apply_to(pod_id, &mut config);
+            }
+            if config.depends_on.iter().any(|d| d.container == config.id) {
+                return Err(ShimError::validation(
+                    "depends_on",
+                    "A container cannot depend on itself",
+                ));
+            }
+
+            let depends_on = config.depends_on.clone();
+            let memory_limit = config.resources.memory.filter(|&limit| limit > 0);
+            let id = config.id.clone();
+            let previous_claim = self.claim(&id, &config.resources).await?;
+            let id = match self.inner.create(config).await {
+                Ok(id) => id,
+                Err(e) => {
+                    let mut claims = self.claims.write().unwrap();
+                    match previous_claim {
+                        Some(claim) => claims.insert(id, claim),
+                        None => claims.remove(&id),
+                    };
+                    return Err(e);
+                }
+            };
+            if let Some(limit) = memory_limit {
+                self.memory_limits
+                    .write()
+                    .unwrap()
+                    .insert(id.clone(), limit);
+            }
+            if !depends_on.is_empty() {
+                self.dependencies
+                    .write()
+                    .unwrap()
+                    .insert(id.clone(), depends_on);
+            }
+            if let Some(pod_id) = pod_id {
+                if let Some(pod) = self.pods.write().unwrap().get_mut(&pod_id) {
+                    pod.members.push(id.clone());
+                }
+            }
+            if let Some(config) = auto_update {
+                self.auto_updates
+                    .write()
+                    .unwrap()
+                    .insert(id.clone(), config);
+            }
+            Ok(id)
+        })
+        .await
+    }
+
+    /// A generated ID no container has yet
+    async fn unused_id(&self) -> String {
+        let taken = self.taken(|c| c.id).await;
+        ids::unused_id(self.id_generator.as_ref(), |id| taken.contains(id))
+    }
+
+    /// A generated name no container has yet
+    async fn unused_name(&self) -> String {
+        let taken = self.taken(|c| c.name).await;
+        ids::unused_id(self.name_generator.as_ref(), |name| taken.contains(name))
+    }
+
+    /// The IDs or names of the containers, for generated ones to avoid
+    async fn taken(&self, field: fn(ContainerInfo) -> String) -> std::collections::HashSet<String> {
+        match self.inner.list().await {
+            Ok(containers) => containers.into_iter().map(field).collect(),
+            Err(e) => {
+                log::debug!("Not checking generated names against the containers: {}", e);
+                Default::default()
+            }
+        }
+    }
+
+    /// Record the reservation and limits of container `id`, if its
+    /// reservation fits in what is left of the machine, returning the claim
+    /// it replaces
+    async fn claim(
+        &self,
+        id: &str,
+        resources: &ResourceLimits,
+    ) -> Result<Option<(Reservation, Reservation)>> {
+        resources.validate()?;
+        let reservation = resources.reservation();
+        let total = if reservation.is_empty() {
+            None
+        } else {
+            match self.inner.total_resources().await {
+                Ok(total) => Some(total),
+                Err(e) => {
+                    log::warn!("Skipping the capacity check for '{}': {}", id, e);
+                    None
+                }
+            }
+        };
+
+        // Checked and recorded under one lock, so concurrent creates cannot
+        // both take the last of the capacity
+        let mut claims = self.claims.write().unwrap();
+        if let Some(total) = total {
+            let problems = tally(total, &claims).shortfall(reservation);
+            if !problems.is_empty() {
+                return Err(ShimError::Unavailable {
+                    message: format!(
+                        "Not enough capacity to place '{}': {}",
+                        id,
+                        problems.join("; ")
+                    ),
+                    context: Some(
+                        "Lower the reservation, or delete containers that reserve resources"
+                            .to_string(),
+                    ),
+                });
+            }
+        }
+        Ok(claims.insert(id.to_string(), (reservation, resources.limit())))
+    }
+
+    /// CPUs and memory of the machine containers run on (the VM on macOS),
+    /// and how much of them the containers created through this runtime
+    /// reserve and may use at most
+    ///
+    /// Containers count until they are deleted, whether running or not.
+    pub async fn capacity(&self) -> Result<Capacity> {
+        self.intercept(Call::read("capacity"), async {
+            let total = self.inner.total_resources().await?;
+            Ok(tally(total, &self.claims.read().unwrap()))
+        })
+        .await
+    }
+
+    /// Create a pod: start its sandbox, then create the members inside it
+    ///
+    /// Members are created but not started; use `start` or `start_group`.
+    /// Returns the pod ID, which is the sandbox container's ID.
+    pub async fn create_pod(&self, spec: PodSpec) -> Result<String> {
+        self.intercept(Call::write("create_pod").on(&spec.sandbox.id), async {
+            self.check_writable("create pods")?;
+            let pod_id = spec.sandbox.id.clone();
+            if self.pods.read().unwrap().contains_key(&pod_id) {
+                return Err(ShimError::conflict(
+                    format!("Pod '{}' already exists", pod_id),
+                    "Use a different pod ID or delete the existing pod first",
+                ));
+            }
+
+            // Members join the sandbox's namespaces, so it has to be running first
+            let mut sandbox = spec.sandbox.clone();
+            sandbox.pod = None;
+            self.create(sandbox).await?;
+            if let Err(e) = self.start(&pod_id).await {
+                let _ = self.delete(&pod_id).await;
+                return Err(e);
+            }
+            self.pods
+                .write()
+                .unwrap()
+                .insert(pod_id.clone(), pod::PodState::new(&spec));
+
+            for mut member in spec.members {
+                member.pod = Some(pod_id.clone());
+                if let Err(e) = self.create(member).await {
+                    log::warn!("Failed to create pod '{}' member, rolling back", pod_id);
+                    let _ = self.delete_pod(&pod_id).await;
+                    return Err(e);
+                }
+            }
+
+            log::info!("Created pod '{}'", pod_id);
+            Ok(pod_id)
+        })
+        .await
+    }
+
+    /// Stop a pod's running members, then its sandbox
+    pub async fn stop_pod(&self, pod_id: &str) -> Result<()> {
+        self.intercept(Call::write("stop_pod").on(pod_id), async {
+            self.check_writable("stop pods")?;
+            let members = self
+                .pod_members(pod_id)
+                .ok_or_else(|| ShimError::not_found(format!("Pod '{}'", pod_id)))?;
+            let running: Vec<String> = self
+                .list()
+                .await?
+                .into_iter()
+                .filter(|c| matches!(c.status, ContainerStatus::Running | ContainerStatus::Paused))
+                .map(|c| c.id)
+                .collect();
+
+            for id in members.iter().rev().filter(|id| running.contains(id)) {
+                self.stop(id).await?;
+            }
+            if running.iter().any(|id| id == pod_id) {
+                self.stop(pod_id).await?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Stop and delete a pod's members, then its sandbox
+    pub async fn delete_pod(&self, pod_id: &str) -> Result<()> {
+        self.intercept(Call::write("delete_pod").on(pod_id), async {
+            self.check_writable("delete pods")?;
+            let members = self
+                .pods
+                .write()
+                .unwrap()
+                .remove(pod_id)
+                .map(|pod| pod.members)
+                .ok_or_else(|| ShimError::not_found(format!("Pod '{}'", pod_id)))?;
+
+            for id in members.iter().rev() {
+                let _ = self.stop(id).await;
+                if let Err(e) = self.delete(id).await {
+                    log::warn!("Failed to delete pod '{}' member '{}': {}", pod_id, id, e);
+                }
+            }
+
+            let _ = self.stop(pod_id).await;
+            self.delete(pod_id).await
+        })
+        .await
+    }
+
+    /// Register a pod whose sandbox and `members` were created earlier, as by
+    /// a process that restarted since; `spec.members` are not created
+    pub fn register_pod(&self, spec: &PodSpec, members: Vec<String>) -> Result<()> {
+        let pod_id = spec.sandbox.id.clone();
+        let mut pods = self.pods.write().unwrap();
+        if pods.contains_key(&pod_id) {
+            return Err(ShimError::conflict(
+                format!("Pod '{}' already exists", pod_id),
+                "Delete the existing pod first",
+            ));
+        }
+        let mut pod = pod::PodState::new(spec);
+        pod.members = members;
+        pods.insert(pod_id, pod);
+        Ok(())
+    }
+
+    /// IDs of the member containers of a pod (excluding the sandbox)
+    pub fn pod_members(&self, pod_id: &str) -> Option<Vec<String>> {
+        self.pods
+            .read()
+            .unwrap()
+            .get(pod_id)
+            .map(|pod| pod.members.clone())
+    }
+
+    /// Reconcile the replica set named after `template.id` to `replicas` containers
+    ///
+    /// Missing replicas are created from the template and started, filling the
+    /// lowest free ordinals first; surplus replicas are stopped and deleted,
+    /// highest ordinals first. Returns the replica IDs after scaling, in
+    /// ordinal order. Replicas are named like their IDs.
+    pub async fn scale(&self, template: &ContainerConfig, replicas: u32) -> Result<Vec<String>> {
+        self.intercept(Call::write("scale").on(&template.id), async {
+            self.check_writable("scale containers")?;
+            let name = template.id.as_str();
+            if name.is_empty() {
+                return Err(ShimError::validation(
+                    "id",
+                    "The template ID names the replica set and cannot be empty",
+                ));
+            }
+
+            let mut current: Vec<u32> = self
+                .list()
+                .await?
+                .iter()
+                .filter_map(|c| replicas::replica_ordinal(name, &c.id))
+                .collect();
+            current.sort_unstable();
+
+            while current.len() > replicas as usize {
+                let ordinal = current.pop().unwrap();
+                let id = replicas::replica_id(name, ordinal);
+                log::info!("Scaling '{}' down: removing '{}'", name, id);
+                let _ = self.stop(&id).await;
+                self.delete(&id).await?;
+            }
+
+            if current.len() < replicas as usize && template.rootfs.as_os_str().is_empty() {
+                return Err(ShimError::validation(
+                    "rootfs",
+                    format!(
+                        "A template rootfs is required to add replicas of '{}'",
+                        name
+                    ),
+                ));
+            }
+            let mut ordinal = 0;
+            while current.len() < replicas as usize {
+                ordinal += 1;
+                if current.contains(&ordinal) {
+                    continue;
+                }
+                let id = replicas::replica_id(name, ordinal);
+                log::info!("Scaling '{}' up: creating '{}'", name, id);
+                let mut config = template.clone();
+                config.id = id.clone();
+                config.name = Some(id.clone());
+                self.create(config).await?;
+                self.start(&id).await?;
+                current.push(ordinal);
+            }
+
+            current.sort_unstable();
+            Ok(current
+                .into_iter()
+                .map(|ordinal| replicas::replica_id(name, ordinal))
+                .collect())
+        })
+        .await
+    }
+
+    /// Recreate running auto-update containers whose image has a new version
+    ///
+    /// For each container created with [`PullPolicy::AutoUpdate`] the
+    /// registry is asked which image its reference points at now. If the
+    /// container does not run that image's rootfs, the image is pulled into
+    /// `store` and the container is stopped, deleted and created again from
+    /// the same configuration on the new rootfs, then started, and an
+    /// `Updated` event is emitted. A container whose check or update fails is
+    /// logged and skipped, to be retried on the next call. Returns the
+    /// containers updated.
+    pub async fn update_images(&self, store: &mut ImageStore) -> Result<Vec<ImageUpdate>> {
+        self.intercept(Call::write("update_images"), async {
+            self.check_writable("update containers")?;
+            let running: Vec<String> = self
+                .list()
+                .await?
+                .into_iter()
+                .filter(|c| c.status == ContainerStatus::Running)
+                .map(|c| c.id)
+                .collect();
+            let tracked: Vec<ContainerConfig> = self
+                .auto_updates
+                .read()
+                .unwrap()
+                .values()
+                .filter(|config| running.contains(&config.id))
+                .cloned()
+                .collect();
+
+            let mut updates = Vec::new();
+            for config in tracked {
+                let id = config.id.clone();
+                let image = config.image.clone().unwrap_or_default();
+                match self.update_image(store, config, &image).await {
+                    Ok(Some(image_id)) => {
+                        log::info!("Updated '{}' to image {} ({})", id, image, image_id);
+                        global_events().send(
+                            ContainerEvent::new(ContainerEventType::Updated, id.clone())
+                                .with_attribute("image", image.clone())
+                                .with_attribute("image_id", image_id.clone()),
+                        );
+                        updates.push(ImageUpdate {
+                            container: id,
+                            image,
+                            image_id,
+                        });
+                    }
+                    Ok(None) => {}
+                    Err(e) => log::warn!("Failed to update '{}' ({}): {}", id, image, e),
+                }
+            }
+            Ok(updates)
+        })
+        .await
+    }
+
+    /// Recreate one container on the latest version of `image`, returning the
+    /// new image ID, or `None` if it is up to date
+    async fn update_image(
+        &self,
+        store: &mut ImageStore,
+        mut config: ContainerConfig,
+        image: &str,
+    ) -> Result<Option<String>> {
+        let latest = store.latest_id(image).await?;
+        if store.get_rootfs(&latest).as_deref() == Some(config.rootfs.as_path()) {
+            return Ok(None);
+        }
+        let info = store.pull(image, None).await?;
+        config.rootfs = store.get_rootfs(&info.id).ok_or_else(|| {
+            ShimError::not_found(format!("Rootfs of image {} ({})", image, info.id))
+        })?;
+
+        let id = config.id.clone();
+        self.stop(&id).await?;
+        self.delete(&id).await?;
+        self.create(config).await.map_err(|e| {
+            ShimError::runtime(format!("Failed to recreate '{}' after deleting it", id))
+                .with_source(e)
+        })?;
+        self.start(&id).await?;
+        Ok(Some(info.id))
+    }
+
+    /// Start a set of containers in dependency order
+    ///
+    /// Each container is started once the containers in its `depends_on` are
+    /// running (or healthy, for `DependencyCondition::Healthy`). Dependencies
+    /// outside the group must be brought up by someone else; they are waited
+    /// on for up to [`DEPENDENCY_TIMEOUT`].
+    pub async fn start_group(&self, ids: &[String]) -> Result<()> {
+        self.intercept(Call::write("start_group"), async {
+            self.check_writable("start containers")?;
+            let order = {
+                let deps = self.dependencies.read().unwrap();
+                group::startup_order(ids, &deps)?
+            };
+
+            for id in order {
+                let deps = self
+                    .dependencies
+                    .read()
+                    .unwrap()
+                    .get(&id)
+                    .cloned()
+                    .unwrap_or_default();
+                for dep in &deps {
+                    self.wait_for_dependency(&id, dep).await?;
+                }
+                log::info!("Starting container '{}'", id);
+                self.start(&id).await?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Wait until a dependency reaches its startup condition
+    async fn wait_for_dependency(&self, id: &str, dep: &DependsOn) -> Result<()> {
+        let deadline = std::time::Instant::now() + group::DEPENDENCY_TIMEOUT;
+        loop {
+            let status = self
+                .list()
+                .await?
+                .into_iter()
+                .find(|c| c.id == dep.container)
+                .map(|c| c.status)
+                .ok_or_else(|| {
+                    ShimError::not_found(format!(
+                        "Container '{}' (dependency of '{}')",
+                        dep.container, id
+                    ))
+                })?;
+
+            let ready = match (status, dep.condition) {
+                (ContainerStatus::Stopped, _) => {
+                    return Err(ShimError::runtime_with_context(
+                        format!("Dependency '{}' of '{}' is stopped", dep.container, id),
+                        "Start the dependency or remove it from depends_on",
+                    ))
+                }
+                // Waited for until it is unpaused
+                (ContainerStatus::Created | ContainerStatus::Paused, _) => false,
+                (ContainerStatus::Running, DependencyCondition::Started) => true,
+                (ContainerStatus::Running, DependencyCondition::Healthy) => {
+                    match self.health(&dep.container).await?.status {
+                        HealthState::Healthy => true,
+                        HealthState::Starting => false,
+                        HealthState::Unhealthy => {
+                            return Err(ShimError::runtime(format!(
+                                "Dependency '{}' of '{}' is unhealthy",
+                                dep.container, id
+                            )))
+                        }
+                        HealthState::None => {
+                            return Err(ShimError::validation(
+                                "depends_on",
+                                format!(
+                                    "'{}' waits for '{}' to be healthy, but it has no health check",
+                                    id, dep.container
+                                ),
+                            ))
+                        }
+                    }
+                }
+            };
+            if ready {
+                return Ok(());
+            }
+
+            if std::time::Instant::now() >= deadline {
+                return Err(ShimError::runtime(format!(
+                    "Timed out waiting for dependency '{}' of '{}'",
+                    dep.container, id
+                )));
+            }
+            tokio::time::sleep(group::DEPENDENCY_POLL_INTERVAL).await;
+        }
     }
 
+    /// Start a container
+    ///
+    /// Fails with [`ShimError::Unavailable`] when the container's memory limit
+    /// or the free disk space is beyond what the runtime's
+    /// [`ResourceGuard`] allows; see [`Self::check_resources`].
     pub async fn start(&self, id: &str) -> Result<()> {
-        self.inner.start(id).await
+        self.intercept(Call::write("start").on(id), async {
+            self.check_writable("start containers")?;
+            let problems = self.check_resources(id).await;
+            if !problems.is_empty() {
+                return Err(ShimError::Unavailable {
+                    message: format!(
+                        "Not enough resources to start '{}': {}",
+                        id,
+                        problems.join("; ")
+                    ),
+                    context: Some(
+                        "Free up memory or disk, relax resource_guard in the runtime config, or force the start"
+                            .to_string(),
+                    ),
+                });
+            }
+            self.inner.start(id).await
+        })
+        .await
+    }
+
+    /// Start a container even if the resource checks fail, logging why they did
+    pub async fn force_start(&self, id: &str) -> Result<()> {
+        self.intercept(Call::write("force_start").on(id), async {
+            self.check_writable("start containers")?;
+            for problem in self.check_resources(id).await {
+                log::warn!("Starting '{}' anyway: {}", id, problem);
+            }
+            self.inner.start(id).await
+        })
+        .await
+    }
+
+    /// Why starting a container would oversubscribe memory or disk, if it would
+    ///
+    /// Memory is checked for containers created through this runtime with a
+    /// memory limit. Amounts the runtime cannot find out are not checked.
+    pub async fn check_resources(&self, id: &str) -> Vec<String> {
+        let (memory, disk) = match self.inner.available_resources(id).await {
+            Ok(available) => available,
+            Err(e) => {
+                log::warn!("Skipping resource checks for '{}': {}", id, e);
+                return vec![];
+            }
+        };
+        let limit = self.memory_limits.read().unwrap().get(id).copied();
+        self.resource_guard.problems(limit, memory, disk)
+    }
+
+    /// Wait for a container to stop, returning how the run that ended exited
+    ///
+    /// Returns right away for a stopped container; one that was created but
+    /// not started yet is waited for until it has run.
+    pub async fn wait(&self, id: &str) -> Result<ExitStatus> {
+        self.intercept(Call::read("wait").on(id), async {
+            self.inner.wait(id).await
+        })
+        .await
+    }
+
+    /// Wait until a started container is ready for use: until it listens
+    /// on a port, or its health check passes
+    ///
+    /// Fails right away once the container stops or turns unhealthy, and
+    /// after `timeout` if it is not ready by then.
+    pub async fn wait_ready(
+        &self,
+        id: &str,
+        check: ReadyCheck,
+        timeout: std::time::Duration,
+    ) -> Result<()> {
+        self.intercept(Call::read("wait_ready").on(id), async {
+            let deadline = std::time::Instant::now() + timeout;
+            loop {
+                let status = self
+                    .list()
+                    .await?
+                    .into_iter()
+                    .find(|c| c.id == id)
+                    .map(|c| c.status)
+                    .ok_or_else(|| ShimError::not_found(format!("Container '{}'", id)))?;
+
+                let ready = match (status, check) {
+                    (ContainerStatus::Stopped, _) => {
+                        return Err(ShimError::runtime_with_context(
+                            format!("Container '{}' stopped before it was ready", id),
+                            "Check its logs for why it exited",
+                        ))
+                    }
+                    (ContainerStatus::Created | ContainerStatus::Paused, _) => false,
+                    (ContainerStatus::Running, ReadyCheck::Port(port)) => {
+                        self.listening_ports(id).await?.contains(&port)
+                    }
+                    (ContainerStatus::Running, ReadyCheck::Healthy) => {
+                        match self.health(id).await?.status {
+                            HealthState::Healthy => true,
+                            HealthState::Starting => false,
+                            HealthState::Unhealthy => {
+                                return Err(ShimError::runtime(format!(
+                                    "Container '{}' is unhealthy",
+                                    id
+                                )))
+                            }
+                            HealthState::None => {
+                                return Err(ShimError::validation(
+                                    "ready_check",
+                                    format!("Container '{}' has no health check", id),
+                                ))
+                            }
+                        }
+                    }
+                };
+                if ready {
+                    return Ok(());
+                }
+
+                if std::time::Instant::now() >= deadline {
+                    return Err(ShimError::runtime(format!(
+                        "Container '{}' was not {} after {:?}",
+                        id, check, timeout
+                    )));
+                }
+                tokio::time::sleep(READY_POLL_INTERVAL).await;
+            }
+        })
+        .await
+    }
+
+    /// TCP ports a running container listens on, ascending
+    pub async fn listening_ports(&self, id: &str) -> Result<Vec<u16>> {
+        self.intercept(Call::read("listening_ports").on(id), async {
+            self.inner.listening_ports(id).await
+        })
+        .await
     }
 
+    /// Stop a container with SIGTERM, and SIGKILL if it has not exited
+    /// within its [`ContainerConfig::stop_timeout`]
     pub async fn stop(&self, id: &str) -> Result<()> {
-        self.inner.stop(id).await
+        self.intercept(Call::write("stop").on(id), async {
+            self.check_writable("stop containers")?;
+            self.inner.stop(id, None).await
+        })
+        .await
+    }
+
+    /// Like [`Self::stop`], giving the container `timeout` to exit instead of
+    /// its own stop timeout
+    pub async fn stop_with_timeout(&self, id: &str, timeout: std::time::Duration) -> Result<()> {
+        self.intercept(Call::write("stop").on(id), async {
+            self.check_writable("stop containers")?;
+            self.inner.stop(id, Some(timeout)).await
+        })
+        .await
+    }
+
+    /// Stop a running container and start it again, giving it `timeout` to
+    /// exit (None = its own stop timeout); one that is not running is only
+    /// started
+    ///
+    /// Like [`Self::start`], a stopped container runs again from the
+    /// configuration it was created with.
+    pub async fn restart(&self, id: &str, timeout: Option<std::time::Duration>) -> Result<()> {
+        self.intercept(Call::write("restart").on(id), async {
+            self.check_writable("restart containers")?;
+            match self.inner.stop(id, timeout).await {
+                // Created, or stopped already
+                Err(e) if e.is_conflict() => {}
+                result => result?,
+            }
+            self.inner.start(id).await
+        })
+        .await
+    }
+
+    /// Freeze all processes of a running container
+    ///
+    /// The container keeps its memory and resources, but gets no CPU time
+    /// until [`Self::unpause`]. Its status is [`ContainerStatus::Paused`]
+    /// meanwhile; it can still be stopped.
+    pub async fn pause(&self, id: &str) -> Result<()> {
+        self.intercept(Call::write("pause").on(id), async {
+            self.check_writable("pause containers")?;
+            self.inner.set_paused(id, true).await?;
+            global_events().emit(ContainerEventType::Pause, id);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Thaw a container frozen with [`Self::pause`]
+    pub async fn unpause(&self, id: &str) -> Result<()> {
+        self.intercept(Call::write("unpause").on(id), async {
+            self.check_writable("unpause containers")?;
+            self.inner.set_paused(id, false).await?;
+            global_events().emit(ContainerEventType::Unpause, id);
+            Ok(())
+        })
+        .await
     }
 
     pub async fn delete(&self, id: &str) -> Result<()> {
-        self.inner.delete(id).await
+        self.intercept(Call::write("delete").on(id), async {
+            self.check_writable("delete containers")?;
+            self.inner.delete(id).await?;
+            self.forget(id);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Drop what the runtime keeps about a deleted container
+    fn forget(&self, id: &str) {
+        self.dependencies.write().unwrap().remove(id);
+        self.auto_updates.write().unwrap().remove(id);
+        self.memory_limits.write().unwrap().remove(id);
+        self.claims.write().unwrap().remove(id);
+        for pod in self.pods.write().unwrap().values_mut() {
+            pod.members.retain(|member| member != id);
+        }
     }
 
     pub async fn list(&self) -> Result<Vec<ContainerInfo>> {
-        self.inner.list().await
+        self.intercept(Call::read("list"), async { self.inner.list().await })
+            .await
+    }
+
+    /// ID of the container with the ID or name `reference`
+    ///
+    /// IDs are matched first, so a container can always be reached by its
+    /// ID, even when another one is named like it. The other methods take
+    /// IDs; the CLI resolves what users type with this.
+    pub async fn lookup(&self, reference: &str) -> Result<String> {
+        self.intercept(Call::read("lookup").on(reference), async {
+            let containers = self.inner.list().await?;
+            containers
+                .iter()
+                .find(|c| c.id == reference)
+                .or_else(|| containers.iter().find(|c| c.name == reference))
+                .map(|c| c.id.clone())
+                .ok_or_else(|| ShimError::not_found(format!("Container '{}'", reference)))
+        })
+        .await
+    }
+
+    /// Give container `id` a new name, which no other container may have
+    pub async fn rename(&self, id: &str, name: &str) -> Result<()> {
+        self.intercept(Call::write("rename").on(id), async {
+            self.check_writable("rename containers")?;
+            names::validate(name)?;
+            self.inner.rename(id, name).await?;
+            // An update recreates the container under its new name
+            if let Some(config) = self.auto_updates.write().unwrap().get_mut(id) {
+                config.name = Some(name.to_string());
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// The containers `filter` matches
+    pub async fn list_filtered(&self, filter: &ListFilter) -> Result<Vec<ContainerInfo>> {
+        self.intercept(Call::read("list_filtered"), async {
+            self.inner.list_filtered(filter).await
+        })
+        .await
     }
 
     /// Get metrics for a specific container
     pub async fn metrics(&self, id: &str) -> Result<ContainerMetrics> {
-        self.inner.metrics(id).await
+        self.intercept(Call::read("metrics").on(id), async {
+            self.inner.metrics(id).await
+        })
+        .await
     }
 
     /// Get metrics for all containers
     pub async fn all_metrics(&self) -> Result<Vec<ContainerMetrics>> {
-        self.inner.all_metrics().await
+        self.intercept(Call::read("all_metrics"), async {
+            self.inner.all_metrics().await
+        })
+        .await
+    }
+
+    /// Summarize each container's resource usage over the last `window`
+    ///
+    /// On macOS the agent samples running containers in the background
+    /// (see its `--metrics-interval`). On Linux only the samples this runtime
+    /// collected through [`Self::metrics`] and [`Self::all_metrics`] count.
+    pub async fn metrics_report(
+        &self,
+        window: std::time::Duration,
+    ) -> Result<Vec<ContainerUsageReport>> {
+        self.intercept(Call::read("metrics_report"), async {
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            let since = now.saturating_sub(window.as_secs());
+
+            #[cfg(target_os = "linux")]
+            let samples = self.inner.metrics_history(since);
+            #[cfg(target_os = "macos")]
+            let samples = self.inner.metrics_history(since).await?;
+
+            Ok(report::summarize(&samples))
+        })
+        .await
     }
 
     /// Get logs for a container
+    ///
+    /// This is a snapshot, whatever `options.follow` says; see
+    /// [`Self::follow_logs`] for output written later.
     pub async fn logs(&self, id: &str, options: LogOptions) -> Result<ContainerLogs> {
-        self.inner.logs(id, options).await
+        self.intercept(Call::read("logs").on(id), async {
+            self.inner.logs(id, options).await
+        })
+        .await
+    }
+
+    /// Follow a container's logs, like `tail -f`
+    ///
+    /// `on_logs` gets the logs `options` selects, then each batch of output
+    /// as it is written, until the container has stopped and all its output
+    /// was passed on, or `on_logs` returns false. While the container is quiet
+    /// `on_logs` is still called, with empty logs, at least once a second, so
+    /// it can decide to stop.
+    pub async fn follow_logs(
+        &self,
+        id: &str,
+        options: LogOptions,
+        mut on_logs: impl FnMut(ContainerLogs) -> bool + Send,
+    ) -> Result<()> {
+        self.intercept(Call::read("follow_logs").on(id), async {
+            self.inner.follow_logs(id, options, &mut on_logs).await
+        })
+        .await
+    }
+
+    /// Follow the logs of several containers at once, as `docker compose logs -f`
+    ///
+    /// `on_logs` gets each batch [`Self::follow_logs`] would pass on, with the
+    /// ID of the container it came from. The containers take turns, so a busy
+    /// one cannot hold back the output of the others. Ends once all of them
+    /// have stopped, when `on_logs` returns false, or with the error of the
+    /// first container whose logs cannot be followed.
+    pub async fn follow_merged_logs(
+        &self,
+        ids: &[String],
+        options: LogOptions,
+        on_logs: impl FnMut(&str, ContainerLogs) -> bool + Send,
+    ) -> Result<()> {
+        self.intercept(Call::read("follow_merged_logs"), async {
+            use std::future::Future;
+            use std::sync::atomic::{AtomicBool, Ordering};
+            use std::task::Poll;
+
+            let on_logs = std::sync::Mutex::new(on_logs);
+            let stopped = AtomicBool::new(false);
+            let mut followers: Vec<
+                std::pin::Pin<Box<dyn Future<Output = Result<()>> + Send + '_>>,
+            > = ids
+                .iter()
+                .map(|id| {
+                    let (on_logs, stopped) = (&on_logs, &stopped);
+                    let follower = self.follow_logs(id, options.clone(), move |logs| {
+                        let more =
+                            !stopped.load(Ordering::SeqCst) && (on_logs.lock().unwrap())(id, logs);
+                        if !more {
+                            stopped.store(true, Ordering::SeqCst);
+                        }
+                        more
+                    });
+                    Box::pin(follower) as _
+                })
+                .collect();
+
+            std::future::poll_fn(|cx| {
+                // Each round starts with the container after the one that went
+                // first last time
+                if !followers.is_empty() {
+                    followers.rotate_left(1);
+                }
+                let mut error = None;
+                followers.retain_mut(|follower| match follower.as_mut().poll(cx) {
+                    Poll::Pending => true,
+                    Poll::Ready(Ok(())) => false,
+                    Poll::Ready(Err(e)) => {
+                        error.get_or_insert(e);
+                        false
+                    }
+                });
+                match error {
+                    Some(e) => Poll::Ready(Err(e)),
+                    None if followers.is_empty() || stopped.load(Ordering::SeqCst) => {
+                        Poll::Ready(Ok(()))
+                    }
+                    None => Poll::Pending,
+                }
+            })
+            .await
+        })
+        .await
     }
 
     /// Get health status for a container
     pub async fn health(&self, id: &str) -> Result<HealthStatus> {
-        self.inner.health(id).await
+        self.intercept(Call::read("health").on(id), async {
+            self.inner.health(id).await
+        })
+        .await
     }
 
     /// Execute a command in a running container
     pub async fn exec(&self, id: &str, command: Vec<String>) -> Result<(i32, String, String)> {
-        self.inner.exec(id, command).await
+        let options = ExecOptions {
+            command,
+            ..Default::default()
+        };
+        self.exec_with_options(id, options).await
+    }
+
+    /// Execute a command in a running container with environment, user and
+    /// terminal options
+    ///
+    /// The session is kept in the container's exec history (see
+    /// [`Self::exec_sessions`]) and written to the `audit` log target.
+    pub async fn exec_with_options(
+        &self,
+        id: &str,
+        options: ExecOptions,
+    ) -> Result<(i32, String, String)> {
+        self.intercept(Call::write("exec").on(id), async {
+            self.check_writable("exec in containers")?;
+            let user = execs::exec_user();
+            let command = options.command.clone();
+            let started = std::time::Instant::now();
+            let result = self.inner.exec(id, options, &user).await;
+
+            let outcome = match &result {
+                Ok((exit_code, _, _)) => format!("exit_code={}", exit_code),
+                Err(e) => format!("error=\"{}\"", e),
+            };
+            log::info!(
+                target: "audit",
+                "exec container={} user={} command={:?} duration_ms={} {}",
+                id,
+                user,
+                command,
+                started.elapsed().as_millis(),
+                outcome
+            );
+            result
+        })
+        .await
+    }
+
+    /// Run a command in a running container with its input and output
+    /// streamed, e.g. an interactive shell, and return its exit code
+    ///
+    /// With [`ExecOptions::tty`] the command runs under a terminal of
+    /// `stdio.terminal_size`, and its output all goes to `stdio.stdout`.
+    /// With `stdio.detach_keys`, typing them returns
+    /// [`ExecOutcome::Detached`] and leaves the command running, as losing
+    /// the connection to the agent does too. Recorded in the exec history
+    /// and audit log like [`Self::exec_with_options`].
+    pub async fn exec_interactive(
+        &self,
+        id: &str,
+        options: ExecOptions,
+        stdio: ExecStdio,
+    ) -> Result<ExecOutcome> {
+        self.intercept(Call::write("exec_interactive").on(id), async {
+            self.check_writable("exec in containers")?;
+            let user = execs::exec_user();
+            let command = options.command.clone();
+            let started = std::time::Instant::now();
+            let result = self.inner.exec_stream(id, options, &user, stdio).await;
+
+            let outcome = match &result {
+                Ok(ExecOutcome::Exited(exit_code)) => format!("exit_code={}", exit_code),
+                Ok(ExecOutcome::Detached(session)) => format!("detached session={}", session),
+                Err(e) => format!("error=\"{}\"", e),
+            };
+            log::info!(
+                target: "audit",
+                "exec container={} user={} command={:?} duration_ms={} interactive=true {}",
+                id,
+                user,
+                command,
+                started.elapsed().as_millis(),
+                outcome
+            );
+            result
+        })
+        .await
+    }
+
+    /// Attach to an exec session left running by detaching from it, until
+    /// it exits or is detached from again (macOS only)
+    ///
+    /// Output the command wrote while detached comes first, as much of it
+    /// as the agent kept.
+    #[cfg(target_os = "macos")]
+    pub async fn attach_exec(&self, session: &str, stdio: ExecStdio) -> Result<ExecOutcome> {
+        self.intercept(Call::write("attach_exec").on(session), async {
+            self.check_writable("attach to exec sessions")?;
+            let result = self.inner.attach_exec(session, stdio).await;
+            log::info!(
+                target: "audit",
+                "attach exec session={} user={} ok={}",
+                session,
+                execs::exec_user(),
+                result.is_ok()
+            );
+            result
+        })
+        .await
+    }
+
+    /// Exec sessions recorded for a container, oldest first
+    pub async fn exec_sessions(&self, id: &str) -> Result<Vec<ExecSession>> {
+        self.intercept(Call::read("exec_sessions").on(id), async {
+            self.inner.exec_sessions(id).await
+        })
+        .await
+    }
+
+    /// Checkpoint a running container under `name`
+    ///
+    /// The checkpoint is kept in the state directory with the container's
+    /// configuration, so it can be restored after the container is deleted.
+    /// Unless `leave_running` is set, the container is stopped by it. Names
+    /// are unique per container; checkpointing needs libcrun built with CRIU
+    /// support.
+    pub async fn checkpoint(
+        &self,
+        id: &str,
+        name: &str,
+        leave_running: bool,
+    ) -> Result<Checkpoint> {
+        self.intercept(
+            Call::write("checkpoint").on(format!("{}/{}", id, name)),
+            async {
+                self.check_writable("checkpoint containers")?;
+                self.inner.checkpoint(id, name, leave_running).await
+            },
+        )
+        .await
+    }
+
+    /// Checkpoints of a container, or of all containers, oldest first
+    pub async fn checkpoints(&self, id: Option<&str>) -> Result<Vec<Checkpoint>> {
+        let mut call = Call::read("checkpoints");
+        if let Some(id) = id {
+            call = call.on(id);
+        }
+        self.intercept(call, async { self.inner.checkpoints(id).await })
+            .await
+    }
+
+    /// Delete a checkpoint of a container
+    pub async fn remove_checkpoint(&self, id: &str, name: &str) -> Result<()> {
+        self.intercept(
+            Call::write("remove_checkpoint").on(format!("{}/{}", id, name)),
+            async {
+                self.check_writable("remove checkpoints")?;
+                self.inner.remove_checkpoint(id, name).await
+            },
+        )
+        .await
+    }
+
+    /// Restore checkpoint `name` of container `id` as the new, running
+    /// container `new_id`
+    ///
+    /// The image is checked against its digest first. `id` no longer has to
+    /// exist, and several containers can be restored from one checkpoint.
+    pub async fn restore(&self, id: &str, name: &str, new_id: &str) -> Result<String> {
+        self.intercept(
+            Call::write("restore").on(format!("{}/{}", id, name)),
+            async {
+                self.check_writable("restore checkpoints")?;
+                self.inner.restore(id, name, new_id).await
+            },
+        )
+        .await
+    }
+
+    /// Copy checkpoint `name` of container `id` to the new host directory
+    /// `path`
+    ///
+    /// The export has the checkpoint's image, configuration and metadata, and
+    /// can be imported with [`ContainerRuntime::import_checkpoint`] on another
+    /// machine or after the VM was recreated, to restore the container there.
+    /// The image holds the container's memory, so read-only handles cannot
+    /// export it.
+    pub async fn export_checkpoint(
+        &self,
+        id: &str,
+        name: &str,
+        path: impl AsRef<std::path::Path>,
+    ) -> Result<Checkpoint> {
+        self.intercept(
+            Call::write("export_checkpoint").on(format!("{}/{}", id, name)),
+            async {
+                self.check_writable("export checkpoints")?;
+                self.inner.export_checkpoint(id, name, path.as_ref()).await
+            },
+        )
+        .await
+    }
+
+    /// Add a checkpoint exported to `path` to this runtime's checkpoints,
+    /// under the container and name it was taken as
+    ///
+    /// The image is checked against its digest on both ends of the copy.
+    pub async fn import_checkpoint(&self, path: impl AsRef<std::path::Path>) -> Result<Checkpoint> {
+        self.intercept(Call::write("import_checkpoint"), async {
+            self.check_writable("import checkpoints")?;
+            self.inner.import_checkpoint(path.as_ref()).await
+        })
+        .await
+    }
+
+    /// Copy `source` on the host into container `id` at `destination`
+    ///
+    /// As with `cp -r`, a directory at `destination` gets the copy under the
+    /// source's own name; otherwise the copy is named `destination`, in its
+    /// parent directory. The files go into the container's rootfs, streamed
+    /// as a tar archive rather than staged in a temporary file. Volumes are
+    /// only mounted in the container, so a path under one is copied to the
+    /// rootfs beneath it. Progress is reported as [`Operation::Copy`].
+    pub async fn copy_to(
+        &self,
+        id: &str,
+        source: impl AsRef<std::path::Path>,
+        destination: &str,
+        options: &CopyOptions,
+    ) -> Result<CopyStats> {
+        self.intercept(
+            Call::write("copy_to").on(format!("{}:{}", id, destination)),
+            async {
+                self.check_writable("copy files into containers")?;
+                let source = host_path(source.as_ref())?;
+                self.inner.copy_to(id, &source, destination, options).await
+            },
+        )
+        .await
+    }
+
+    /// Copy `source` in container `id` to `destination` on the host
+    ///
+    /// Where the copy goes follows the same rules as [`Self::copy_to`].
+    pub async fn copy_from(
+        &self,
+        id: &str,
+        source: &str,
+        destination: impl AsRef<std::path::Path>,
+        options: &CopyOptions,
+    ) -> Result<CopyStats> {
+        self.intercept(
+            Call::write("copy_from").on(format!("{}:{}", id, source)),
+            async {
+                self.check_writable("copy files out of containers")?;
+                let destination = host_path(destination.as_ref())?;
+                self.inner
+                    .copy_from(id, source, &destination, options)
+                    .await
+            },
+        )
+        .await
     }
 
     /// Gracefully shutdown all running containers
     pub async fn shutdown(&self) -> Result<()> {
-        log::info!("Initiating graceful shutdown of all containers");
-        let containers = self.list().await?;
-
-        for container in containers {
-            if container.status == ContainerStatus::Running {
-                log::info!("Stopping container '{}' during shutdown", container.id);
-                if let Err(e) = self.stop(&container.id).await {
-                    log::warn!("Failed to stop container '{}': {}", container.id, e);
+        self.intercept(Call::write("shutdown"), async {
+            self.check_writable("stop containers")?;
+            log::info!("Initiating graceful shutdown of all containers");
+            let containers = self.list().await?;
+
+            for container in containers {
+                if matches!(
+                    container.status,
+                    ContainerStatus::Running | ContainerStatus::Paused
+                ) {
+                    log::info!("Stopping container '{}' during shutdown", container.id);
+                    if let Err(e) = self.stop(&container.id).await {
+                        log::warn!("Failed to stop container '{}': {}", container.id, e);
+                    }
                 }
             }
-        }
 
-        log::info!("Graceful shutdown complete");
-        Ok(())
+            log::info!("Graceful shutdown complete");
+            Ok(())
+        })
+        .await
     }
 
     /// List containers that may be orphaned (crashed/not properly cleaned up)
     pub async fn list_orphaned(&self) -> Result<Vec<ContainerInfo>> {
-        let containers = self.list().await?;
-        Ok(containers
-            .into_iter()
-            .filter(|c| c.status == ContainerStatus::Stopped)
-            .collect())
+        self.intercept(Call::read("list_orphaned"), async {
+            let containers = self.list().await?;
+            Ok(containers
+                .into_iter()
+                .filter(|c| c.status == ContainerStatus::Stopped)
+                .collect())
+        })
+        .await
     }
 
     /// Force cleanup of a container (even if it's still running)
     pub async fn force_delete(&self, id: &str) -> Result<()> {
-        // Try to stop first, ignore errors
-        let _ = self.stop(id).await;
+        self.intercept(Call::write("force_delete").on(id), async {
+            self.check_writable("delete containers")?;
+            // Try to stop first, ignore errors
+            let _ = self.stop(id).await;
+
+            // Give container time to stop
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+
+            // Delete regardless
+            self.delete(id).await
+        })
+        .await
+    }
+
+    /// Delete the stopped containers `filter` matches, returning their IDs
+    ///
+    /// In the VM the agent picks and deletes them, so a scheduled cleanup
+    /// takes a single request.
+    pub async fn prune(&self, filter: &PruneFilter) -> Result<Vec<String>> {
+        self.intercept(Call::write("prune"), async {
+            self.check_writable("delete containers")?;
+            let pruned = self.inner.prune(filter).await?;
+            for id in &pruned {
+                log::info!("Pruned stopped container '{}'", id);
+                self.forget(id);
+            }
+            Ok(pruned)
+        })
+        .await
+    }
 
-        // Give container time to stop
-        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    /// What containers left behind without the runtime tracking them, e.g.
+    /// after a crash in the middle of a create: libcrun state, cgroups,
+    /// rootfs snapshots and, in the VM, bridge interfaces
+    ///
+    /// Containers whose process still runs are left out, as are those of
+    /// creates in progress here. Another runtime on the same machine tracks
+    /// containers of its own, whose leftovers are listed too once they
+    /// stopped.
+    pub async fn stale_resources(&self) -> Result<Vec<StaleResource>> {
+        self.intercept(Call::read("stale_resources"), async {
+            self.inner.stale_resources(false).await
+        })
+        .await
+    }
 
-        // Delete regardless
-        self.delete(id).await
+    /// Remove the resources [`Self::stale_resources`] lists, returning those
+    /// that were removed
+    pub async fn remove_stale_resources(&self) -> Result<Vec<StaleResource>> {
+        self.intercept(Call::write("remove_stale_resources"), async {
+            self.check_writable("remove stale resources")?;
+            let removed = self.inner.stale_resources(true).await?;
+            for resource in &removed {
+                log::info!(
+                    "Removed stale {} of '{}': {}",
+                    resource.kind,
+                    resource.id,
+                    resource.path
+                );
+            }
+            Ok(removed)
+        })
+        .await
     }
 
     /// Cleanup all stopped/orphaned containers
     pub async fn cleanup_stopped(&self) -> Result<usize> {
-        let containers = self.list().await?;
-        let mut cleaned = 0;
-
-        for container in containers {
-            if container.status == ContainerStatus::Stopped {
-                log::info!("Cleaning up stopped container '{}'", container.id);
-                if self.delete(&container.id).await.is_ok() {
-                    cleaned += 1;
+        self.intercept(Call::write("cleanup_stopped"), async {
+            self.check_writable("delete containers")?;
+            let containers = self.list().await?;
+            let mut cleaned = 0;
+
+            for container in containers {
+                if container.status == ContainerStatus::Stopped {
+                    log::info!("Cleaning up stopped container '{}'", container.id);
+                    if self.delete(&container.id).await.is_ok() {
+                        cleaned += 1;
+                    }
                 }
             }
-        }
 
-        Ok(cleaned)
+            Ok(cleaned)
+        })
+        .await
     }
 }
 
@@ -164,32 +1743,207 @@ impl ContainerRuntime {
 trait RuntimeImpl {
     async fn create(&self, config: ContainerConfig) -> Result<String>;
     async fn start(&self, id: &str) -> Result<()>;
-    async fn stop(&self, id: &str) -> Result<()>;
+    async fn stop(&self, id: &str, timeout: Option<std::time::Duration>) -> Result<()>;
+    /// Freeze (`pause`) a running container, or thaw a paused one
+    async fn set_paused(&self, id: &str, pause: bool) -> Result<()>;
     async fn delete(&self, id: &str) -> Result<()>;
     async fn list(&self) -> Result<Vec<ContainerInfo>>;
+    async fn list_filtered(&self, filter: &ListFilter) -> Result<Vec<ContainerInfo>>;
     async fn metrics(&self, id: &str) -> Result<ContainerMetrics>;
     async fn all_metrics(&self) -> Result<Vec<ContainerMetrics>>;
     async fn logs(&self, id: &str, options: LogOptions) -> Result<ContainerLogs>;
+    async fn follow_logs(
+        &self,
+        id: &str,
+        options: LogOptions,
+        on_logs: &mut (dyn FnMut(ContainerLogs) -> bool + Send),
+    ) -> Result<()>;
     async fn health(&self, id: &str) -> Result<HealthStatus>;
-    async fn exec(&self, id: &str, command: Vec<String>) -> Result<(i32, String, String)>;
+    async fn exec(
+        &self,
+        id: &str,
+        options: ExecOptions,
+        user: &str,
+    ) -> Result<(i32, String, String)>;
+    async fn exec_stream(
+        &self,
+        id: &str,
+        options: ExecOptions,
+        user: &str,
+        stdio: ExecStdio,
+    ) -> Result<ExecOutcome>;
+    async fn exec_sessions(&self, id: &str) -> Result<Vec<ExecSession>>;
+    async fn checkpoint(&self, id: &str, name: &str, leave_running: bool) -> Result<Checkpoint>;
+    async fn checkpoints(&self, id: Option<&str>) -> Result<Vec<Checkpoint>>;
+    async fn remove_checkpoint(&self, id: &str, name: &str) -> Result<()>;
+    async fn restore(&self, id: &str, name: &str, new_id: &str) -> Result<String>;
+    async fn export_checkpoint(
+        &self,
+        id: &str,
+        name: &str,
+        path: &std::path::Path,
+    ) -> Result<Checkpoint>;
+    async fn import_checkpoint(&self, path: &std::path::Path) -> Result<Checkpoint>;
+    async fn prune(&self, filter: &PruneFilter) -> Result<Vec<String>>;
+    async fn stale_resources(&self, remove: bool) -> Result<Vec<StaleResource>>;
+    async fn rename(&self, id: &str, name: &str) -> Result<()>;
+    /// Memory and disk space available to container `id`, where known
+    async fn available_resources(&self, id: &str) -> Result<(Option<u64>, Option<u64>)>;
+    /// CPUs and memory of the machine containers run on
+    async fn total_resources(&self) -> Result<Reservation>;
+    /// Block until a container has stopped
+    async fn wait(&self, id: &str) -> Result<ExitStatus>;
+    /// TCP ports a running container listens on, ascending
+    async fn listening_ports(&self, id: &str) -> Result<Vec<u16>>;
+    async fn copy_to(
+        &self,
+        id: &str,
+        source: &std::path::Path,
+        destination: &str,
+        options: &CopyOptions,
+    ) -> Result<CopyStats>;
+    async fn copy_from(
+        &self,
+        id: &str,
+        source: &str,
+        destination: &std::path::Path,
+        options: &CopyOptions,
+    ) -> Result<CopyStats>;
 }
 
 #[cfg(target_os = "macos")]
 trait RuntimeImpl {
     async fn create(&self, config: ContainerConfig) -> Result<String>;
     async fn start(&self, id: &str) -> Result<()>;
-    async fn stop(&self, id: &str) -> Result<()>;
+    async fn stop(&self, id: &str, timeout: Option<std::time::Duration>) -> Result<()>;
+    /// Freeze (`pause`) a running container, or thaw a paused one
+    async fn set_paused(&self, id: &str, pause: bool) -> Result<()>;
     async fn delete(&self, id: &str) -> Result<()>;
     async fn list(&self) -> Result<Vec<ContainerInfo>>;
+    async fn list_filtered(&self, filter: &ListFilter) -> Result<Vec<ContainerInfo>>;
     async fn metrics(&self, id: &str) -> Result<ContainerMetrics>;
     async fn all_metrics(&self) -> Result<Vec<ContainerMetrics>>;
     async fn logs(&self, id: &str, options: LogOptions) -> Result<ContainerLogs>;
+    async fn follow_logs(
+        &self,
+        id: &str,
+        options: LogOptions,
+        on_logs: &mut (dyn FnMut(ContainerLogs) -> bool + Send),
+    ) -> Result<()>;
     async fn health(&self, id: &str) -> Result<HealthStatus>;
-    async fn exec(&self, id: &str, command: Vec<String>) -> Result<(i32, String, String)>;
+    async fn exec(
+        &self,
+        id: &str,
+        options: ExecOptions,
+        user: &str,
+    ) -> Result<(i32, String, String)>;
+    async fn exec_stream(
+        &self,
+        id: &str,
+        options: ExecOptions,
+        user: &str,
+        stdio: ExecStdio,
+    ) -> Result<ExecOutcome>;
+    async fn exec_sessions(&self, id: &str) -> Result<Vec<ExecSession>>;
+    async fn checkpoint(&self, id: &str, name: &str, leave_running: bool) -> Result<Checkpoint>;
+    async fn checkpoints(&self, id: Option<&str>) -> Result<Vec<Checkpoint>>;
+    async fn remove_checkpoint(&self, id: &str, name: &str) -> Result<()>;
+    async fn restore(&self, id: &str, name: &str, new_id: &str) -> Result<String>;
+    async fn export_checkpoint(
+        &self,
+        id: &str,
+        name: &str,
+        path: &std::path::Path,
+    ) -> Result<Checkpoint>;
+    async fn import_checkpoint(&self, path: &std::path::Path) -> Result<Checkpoint>;
+    async fn prune(&self, filter: &PruneFilter) -> Result<Vec<String>>;
+    async fn stale_resources(&self, remove: bool) -> Result<Vec<StaleResource>>;
+    async fn rename(&self, id: &str, name: &str) -> Result<()>;
+    async fn copy_to(
+        &self,
+        id: &str,
+        source: &std::path::Path,
+        destination: &str,
+        options: &CopyOptions,
+    ) -> Result<CopyStats>;
+    async fn copy_from(
+        &self,
+        id: &str,
+        source: &str,
+        destination: &std::path::Path,
+        options: &CopyOptions,
+    ) -> Result<CopyStats>;
+    /// Memory and disk space available to container `id`, where known
+    async fn available_resources(&self, id: &str) -> Result<(Option<u64>, Option<u64>)>;
+    /// CPUs and memory of the machine containers run on
+    async fn total_resources(&self) -> Result<Reservation>;
+    /// Block until a container has stopped
+    async fn wait(&self, id: &str) -> Result<ExitStatus>;
+    /// TCP ports a running container listens on, ascending
+    async fn listening_ports(&self, id: &str) -> Result<Vec<u16>>;
 }
 
-#[cfg(test)]
-mod tests {
+/// The capacity left on a machine of size `total` by the reservations and
+/// limits in `claims`
+fn tally(
+    total: Reservation,
+    claims: &std::collections::HashMap<String, (Reservation, Reservation)>,
+) -> Capacity {
+    Capacity {
+        total,
+        reserved: claims.values().map(|claim| claim.0).sum(),
+        limits: claims.values().map(|claim| claim.1).sum(),
+    }
+}
+
+/// How often [`poll_logs`] reads new output
+const LOG_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+
+/// How often [`ContainerRuntime::wait_ready`] checks a container
+const READY_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(200);
+
+/// Follow logs by reading forward from the last offsets until the container
+/// has stopped, for backends that cannot push new output
+async fn poll_logs<R: RuntimeImpl>(
+    runtime: &R,
+    id: &str,
+    mut options: LogOptions,
+    on_logs: &mut (dyn FnMut(ContainerLogs) -> bool + Send),
+) -> Result<()> {
+    loop {
+        // Checked before reading, so output written just before the container
+        // stopped is still passed on
+        let running = runtime.list().await?.iter().any(|c| {
+            c.id == id && matches!(c.status, ContainerStatus::Running | ContainerStatus::Paused)
+        });
+        let logs = runtime.logs(id, options.clone()).await?;
+        let quiet = logs.stdout.is_empty() && logs.stderr.is_empty() && logs.entries.is_empty();
+        let more = logs.truncated && options.stdout_offset.is_some();
+        options = LogOptions {
+            max_bytes: options.max_bytes,
+            stdout_offset: Some(logs.stdout_offset),
+            stderr_offset: Some(logs.stderr_offset),
+            ..Default::default()
+        };
+        if !on_logs(logs) || (quiet && !running) {
+            return Ok(());
+        }
+        // A page cut short by the byte limit is followed by the rest right away
+        if !more {
+            tokio::time::sleep(LOG_POLL_INTERVAL).await;
+        }
+    }
+}
+
+/// `path` on the host made absolute, as copies resolve paths from `/`
+fn host_path(path: &std::path::Path) -> Result<std::path::PathBuf> {
+    std::path::absolute(path)
+        .map_err(|e| ShimError::io_with_context(e, format!("Resolving {}", path.display())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
     #[tokio::test]
     #[cfg(target_os = "linux")]
@@ -220,6 +1974,656 @@ mod tests {
         let _ = std::fs::remove_dir_all(&temp_rootfs);
     }
 
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn test_generated_ids() {
+        let runtime = ContainerRuntime::new()
+            .await
+            .unwrap()
+            .with_id_generator(SequentialIdGenerator::new("test"));
+        let config = ContainerConfig {
+            rootfs: std::env::temp_dir(),
+            command: vec!["sh".to_string()],
+            working_dir: "/".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(runtime.create(config.clone()).await.unwrap(), "test-1");
+        assert_eq!(runtime.create(config.clone()).await.unwrap(), "test-2");
+        let named = ContainerConfig {
+            id: "named".to_string(),
+            ..config
+        };
+        assert_eq!(runtime.create(named).await.unwrap(), "named");
+        let mut ids: Vec<_> = runtime
+            .list()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|c| c.id)
+            .collect();
+        ids.sort();
+        assert_eq!(ids, ["named", "test-1", "test-2"]);
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn test_prune() {
+        let runtime = ContainerRuntime::new().await.unwrap();
+        let config = ContainerConfig {
+            id: "prune-created".to_string(),
+            rootfs: std::env::temp_dir(),
+            command: vec!["sh".to_string()],
+            labels: [("temporary".to_string(), "true".to_string())].into(),
+            ..Default::default()
+        };
+        runtime.create(config.clone()).await.unwrap();
+
+        // Only stopped containers are pruned
+        assert!(runtime
+            .prune(&PruneFilter::default())
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(runtime.list().await.unwrap().len(), 1);
+
+        let temporary = LabelFilter::parse("temporary=true").unwrap();
+        assert!(temporary.matches(&config.labels));
+        assert!(!temporary.clone().negated().matches(&config.labels));
+        assert!(LabelFilter::parse("temporary")
+            .unwrap()
+            .matches(&config.labels));
+        assert!(!LabelFilter::parse("team").unwrap().matches(&config.labels));
+        assert!(LabelFilter::parse("=x").is_err());
+        let filter = PruneFilter::default().label(temporary);
+        assert!(filter.matches(0, &config.labels));
+        assert!(!filter.matches(0, &std::collections::HashMap::new()));
+        let old = PruneFilter::default().older_than(std::time::Duration::from_secs(3600));
+        assert!(old.matches(0, &std::collections::HashMap::new()));
+        assert!(!old.matches(u64::MAX, &std::collections::HashMap::new()));
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn test_failed_create() {
+        let runtime = ContainerRuntime::new().await.unwrap();
+        // Fails once the create is under way
+        let config = ContainerConfig {
+            id: "failed-create".to_string(),
+            rootfs: std::env::temp_dir(),
+            command: vec!["sh".to_string()],
+            join_namespaces: Some(JoinNamespaces {
+                container: "no-such-container".to_string(),
+                network: true,
+                ipc: false,
+            }),
+            ..Default::default()
+        };
+        runtime.create(config.clone()).await.unwrap_err();
+        assert!(runtime.list().await.unwrap().is_empty());
+
+        // The ID is free again, and what is tracked is never stale
+        let config = ContainerConfig {
+            join_namespaces: None,
+            ..config
+        };
+        runtime.create(config).await.unwrap();
+        let stale = runtime.stale_resources().await.unwrap();
+        assert!(stale.iter().all(|r| r.id != "failed-create"), "{:?}", stale);
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn test_working_dir() {
+        let rootfs = std::env::temp_dir().join(format!("shim-workdir-{}", std::process::id()));
+        std::fs::create_dir_all(&rootfs).unwrap();
+        let runtime = ContainerRuntime::new().await.unwrap();
+        let config = ContainerConfig {
+            id: "workdir".to_string(),
+            rootfs: rootfs.clone(),
+            command: vec!["sh".to_string()],
+            working_dir: "/srv/app".to_string(),
+            create_working_dir: false,
+            ..Default::default()
+        };
+        let err = runtime.create(config.clone()).await.unwrap_err();
+        assert!(matches!(err, ShimError::Validation { .. }), "{}", err);
+        assert!(
+            err.to_string().contains("'/srv/app' does not exist"),
+            "{}",
+            err
+        );
+        assert!(!rootfs.join("srv").exists());
+
+        runtime
+            .create(ContainerConfig {
+                create_working_dir: true,
+                ..config
+            })
+            .await
+            .unwrap();
+        assert!(rootfs.join("srv/app").is_dir());
+        runtime.delete("workdir").await.unwrap();
+        std::fs::remove_dir_all(&rootfs).unwrap();
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn test_names() {
+        let runtime = ContainerRuntime::new()
+            .await
+            .unwrap()
+            .with_id_generator(SequentialIdGenerator::new("names"))
+            .with_name_generator(SequentialIdGenerator::new("unnamed"));
+        let config = ContainerConfig {
+            rootfs: std::env::temp_dir(),
+            command: vec!["sh".to_string()],
+            working_dir: "/".to_string(),
+            ..Default::default()
+        };
+        let web = ContainerConfig {
+            name: Some("web".to_string()),
+            ..config.clone()
+        };
+        assert_eq!(runtime.create(web.clone()).await.unwrap(), "names-1");
+        assert_eq!(runtime.create(config.clone()).await.unwrap(), "names-2");
+        let err = runtime.create(web).await.unwrap_err();
+        assert!(matches!(err, ShimError::Conflict { .. }), "{}", err);
+        let invalid = ContainerConfig {
+            name: Some("-web".to_string()),
+            ..config
+        };
+        let err = runtime.create(invalid).await.unwrap_err();
+        assert!(matches!(err, ShimError::Validation { .. }), "{}", err);
+
+        assert_eq!(runtime.lookup("web").await.unwrap(), "names-1");
+        assert_eq!(runtime.lookup("unnamed-1").await.unwrap(), "names-2");
+        assert_eq!(runtime.lookup("names-2").await.unwrap(), "names-2");
+        assert!(runtime.lookup("db").await.is_err());
+
+        let err = runtime.rename("names-2", "web").await.unwrap_err();
+        assert!(matches!(err, ShimError::Conflict { .. }), "{}", err);
+        runtime.rename("names-1", "db").await.unwrap();
+        // The old name is free again
+        runtime.rename("names-2", "web").await.unwrap();
+        // An ID wins over a name
+        runtime.rename("names-1", "names-2").await.unwrap();
+        assert_eq!(runtime.lookup("names-2").await.unwrap(), "names-2");
+        assert_eq!(runtime.lookup("web").await.unwrap(), "names-2");
+        assert!(runtime.rename("missing", "other").await.is_err());
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn test_list_filtered() {
+        let runtime = ContainerRuntime::new().await.unwrap();
+        for (id, team) in [("web-1", "web"), ("web-2", "web"), ("db-1", "db")] {
+            let config = ContainerConfig {
+                id: id.to_string(),
+                rootfs: std::env::temp_dir(),
+                command: vec!["sh".to_string()],
+                labels: [("team".to_string(), team.to_string())].into(),
+                name: Some(id.to_string()),
+                ..Default::default()
+            };
+            runtime.create(config).await.unwrap();
+        }
+        let ids = |list: Vec<ContainerInfo>| {
+            let mut ids: Vec<_> = list.into_iter().map(|c| c.id).collect();
+            ids.sort();
+            ids
+        };
+
+        let all = runtime.list_filtered(&ListFilter::default()).await.unwrap();
+        assert_eq!(ids(all), ["db-1", "web-1", "web-2"]);
+        let web = ListFilter::default().label(LabelFilter::parse("team=web").unwrap());
+        assert_eq!(
+            ids(runtime.list_filtered(&web).await.unwrap()),
+            ["web-1", "web-2"]
+        );
+        let not_web =
+            ListFilter::default().label(LabelFilter::parse("team=web").unwrap().negated());
+        assert_eq!(
+            ids(runtime.list_filtered(&not_web).await.unwrap()),
+            ["db-1"]
+        );
+        let prefix = ListFilter::default().name_prefix("web-2");
+        assert_eq!(
+            ids(runtime.list_filtered(&prefix).await.unwrap()),
+            ["web-2"]
+        );
+
+        // None of them was started
+        let running = ListFilter::default()
+            .status(ContainerStatus::Running)
+            .status(ContainerStatus::Running);
+        assert_eq!(running.statuses, [ContainerStatus::Running]);
+        assert!(runtime.list_filtered(&running).await.unwrap().is_empty());
+        let created = web.status(ContainerStatus::Created);
+        assert_eq!(
+            ids(runtime.list_filtered(&created).await.unwrap()),
+            ["web-1", "web-2"]
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn test_follow_logs() {
+        let runtime = ContainerRuntime::new().await.unwrap();
+        let config = ContainerConfig {
+            id: "follow-logs".to_string(),
+            rootfs: std::env::temp_dir(),
+            command: vec!["sh".to_string()],
+            working_dir: "/".to_string(),
+            ..Default::default()
+        };
+        runtime.create(config).await.unwrap();
+
+        // Nothing was logged and the container is not running, so following
+        // ends after the first, empty, batch
+        let mut batches = 0;
+        runtime
+            .follow_logs("follow-logs", LogOptions::default(), |logs| {
+                assert!(logs.stdout.is_empty() && logs.stderr.is_empty());
+                batches += 1;
+                true
+            })
+            .await
+            .unwrap();
+        assert_eq!(batches, 1);
+
+        let err = runtime
+            .follow_logs("no-such-container", LogOptions::default(), |_| true)
+            .await
+            .unwrap_err();
+        assert!(err.is_not_found(), "{}", err);
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn test_follow_merged_logs() {
+        let runtime = ContainerRuntime::new().await.unwrap();
+        let ids = ["merged-a".to_string(), "merged-b".to_string()];
+        for id in &ids {
+            let config = ContainerConfig {
+                id: id.clone(),
+                rootfs: std::env::temp_dir(),
+                command: vec!["sh".to_string()],
+                ..Default::default()
+            };
+            runtime.create(config).await.unwrap();
+        }
+
+        let mut seen = Vec::new();
+        runtime
+            .follow_merged_logs(&ids, LogOptions::default(), |id, _| {
+                seen.push(id.to_string());
+                true
+            })
+            .await
+            .unwrap();
+        seen.sort();
+        assert_eq!(seen, ids);
+
+        let missing = [ids[0].clone(), "no-such-container".to_string()];
+        let err = runtime
+            .follow_merged_logs(&missing, LogOptions::default(), |_, _| true)
+            .await
+            .unwrap_err();
+        assert!(err.is_not_found(), "{}", err);
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn test_wait_ready() {
+        let runtime = ContainerRuntime::new().await.unwrap();
+        let config = ContainerConfig {
+            id: "wait-ready".to_string(),
+            rootfs: std::env::temp_dir(),
+            command: vec!["sleep".to_string(), "10".to_string()],
+            working_dir: "/".to_string(),
+            ..Default::default()
+        };
+        runtime.create(config).await.unwrap();
+        let timeout = std::time::Duration::from_millis(300);
+        let err = runtime
+            .wait_ready("wait-ready", ReadyCheck::Healthy, timeout)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("was not healthy"), "{}", err);
+
+        runtime.start("wait-ready").await.unwrap();
+        runtime
+            .wait_ready("wait-ready", ReadyCheck::Healthy, timeout)
+            .await
+            .unwrap();
+        // Without libcrun the container shares this process's network
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        runtime
+            .wait_ready("wait-ready", ReadyCheck::Port(port), timeout)
+            .await
+            .unwrap();
+        drop(listener);
+        let err = runtime
+            .wait_ready("wait-ready", ReadyCheck::Port(port), timeout)
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains(&format!("listening on port {}", port)),
+            "{}",
+            err
+        );
+
+        runtime.stop("wait-ready").await.unwrap();
+        let err = runtime
+            .wait_ready("wait-ready", ReadyCheck::Healthy, timeout)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("stopped before"), "{}", err);
+        runtime.delete("wait-ready").await.unwrap();
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn test_pause_errors() {
+        let runtime = ContainerRuntime::new().await.unwrap();
+        let config = ContainerConfig {
+            id: "pause-created".to_string(),
+            rootfs: std::env::temp_dir(),
+            command: vec!["sh".to_string()],
+            working_dir: "/".to_string(),
+            ..Default::default()
+        };
+        runtime.create(config).await.unwrap();
+
+        let err = runtime.pause("pause-created").await.unwrap_err();
+        assert!(err.is_conflict(), "{}", err);
+        assert!(err.to_string().contains("is not running"), "{}", err);
+        let err = runtime.unpause("pause-created").await.unwrap_err();
+        assert!(err.is_conflict(), "{}", err);
+        assert!(err.to_string().contains("is not paused"), "{}", err);
+        let err = runtime.pause("no-such-container").await.unwrap_err();
+        assert!(err.is_not_found(), "{}", err);
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn test_lifecycle_transitions() {
+        let mut events = subscribe_events();
+        let runtime = ContainerRuntime::new().await.unwrap();
+        let config = ContainerConfig {
+            id: "transitions".to_string(),
+            rootfs: std::env::temp_dir(),
+            command: vec!["sh".to_string()],
+            working_dir: "/".to_string(),
+            ..Default::default()
+        };
+        runtime.create(config).await.unwrap();
+        let err = runtime.stop("transitions").await.unwrap_err();
+        assert!(err.to_string().contains("is not running"), "{}", err);
+
+        runtime.start("transitions").await.unwrap();
+        let err = runtime.start("transitions").await.unwrap_err();
+        assert!(err.is_conflict(), "{}", err);
+        assert!(err.to_string().contains("is already running"), "{}", err);
+        let err = runtime.delete("transitions").await.unwrap_err();
+        assert!(err.is_conflict(), "{}", err);
+        assert!(err.to_string().contains("cannot be deleted"), "{}", err);
+
+        runtime.stop("transitions").await.unwrap();
+        // Stopped containers can be started again
+        runtime.start("transitions").await.unwrap();
+        runtime.restart("transitions", None).await.unwrap();
+        let info = runtime.list().await.unwrap();
+        let info = info.iter().find(|c| c.id == "transitions").unwrap();
+        assert_eq!(info.status, ContainerStatus::Running);
+        assert_eq!(info.restart_count, 2);
+        runtime.stop("transitions").await.unwrap();
+        let err = runtime.stop("transitions").await.unwrap_err();
+        assert!(err.to_string().contains("is not running"), "{}", err);
+        let err = runtime
+            .stop_with_timeout("transitions", std::time::Duration::from_secs(30))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("is not running"), "{}", err);
+        runtime.delete("transitions").await.unwrap();
+
+        // Only the transitions that were applied publish events
+        let mut seen = Vec::new();
+        while let Some(event) = events.try_recv() {
+            if event.container_id == "transitions" {
+                seen.push(event.event_type);
+            }
+        }
+        assert_eq!(
+            seen,
+            vec![
+                ContainerEventType::Start,
+                ContainerEventType::Stop,
+                ContainerEventType::Start,
+                ContainerEventType::Stop,
+                ContainerEventType::Start,
+                ContainerEventType::Stop,
+                ContainerEventType::Delete
+            ]
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn test_read_only_runtime() {
+        let runtime = ContainerRuntime::new_read_only().await.unwrap();
+        assert!(runtime.is_read_only());
+        assert!(runtime.list().await.unwrap().is_empty());
+
+        let config = ContainerConfig {
+            id: "read-only".to_string(),
+            rootfs: std::env::temp_dir(),
+            command: vec!["sh".to_string()],
+            working_dir: "/".to_string(),
+            ..Default::default()
+        };
+        let err = runtime.create(config).await.unwrap_err();
+        assert!(err.is_permission_denied());
+        assert!(err.to_string().contains("Cannot create containers"));
+        for err in [
+            runtime.start("read-only").await.unwrap_err(),
+            runtime.stop("read-only").await.unwrap_err(),
+            runtime.pause("read-only").await.unwrap_err(),
+            runtime.unpause("read-only").await.unwrap_err(),
+            runtime.delete("read-only").await.unwrap_err(),
+            runtime.cleanup_stopped().await.unwrap_err(),
+            runtime
+                .remove_checkpoint("read-only", "first")
+                .await
+                .unwrap_err(),
+            runtime.remove_stale_resources().await.unwrap_err(),
+            runtime
+                .export_checkpoint("read-only", "first", std::env::temp_dir())
+                .await
+                .unwrap_err(),
+            runtime
+                .copy_from(
+                    "read-only",
+                    "/etc",
+                    std::env::temp_dir(),
+                    &CopyOptions::default(),
+                )
+                .await
+                .unwrap_err(),
+        ] {
+            assert!(err.is_permission_denied(), "{}", err);
+        }
+        let err = runtime
+            .exec("read-only", vec!["true".to_string()])
+            .await
+            .unwrap_err();
+        assert!(err.is_permission_denied());
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn test_interceptors() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::{Arc, Mutex};
+
+        /// At most `limit` containers created through it
+        struct Quota {
+            limit: usize,
+            created: Arc<AtomicUsize>,
+        }
+        impl Interceptor for Quota {
+            fn before(&self, call: &Call) -> Result<()> {
+                if call.method == "create"
+                    && self.created.fetch_add(1, Ordering::SeqCst) >= self.limit
+                {
+                    self.created.fetch_sub(1, Ordering::SeqCst);
+                    return Err(ShimError::Unavailable {
+                        message: format!("Quota of {} containers reached", self.limit),
+                        context: None,
+                    });
+                }
+                Ok(())
+            }
+            fn after(&self, call: &Call, _: std::time::Duration, error: Option<&ShimError>) {
+                if call.method == "create" && error.is_some() {
+                    self.created.fetch_sub(1, Ordering::SeqCst);
+                }
+            }
+        }
+
+        /// The mutating calls that went through, and whether they succeeded
+        struct Audit(Arc<Mutex<Vec<(String, bool)>>>);
+        impl Interceptor for Audit {
+            fn after(&self, call: &Call, _: std::time::Duration, error: Option<&ShimError>) {
+                if call.mutating {
+                    let target = call.target.clone().unwrap_or_default();
+                    let entry = format!("{} {}", call.method, target);
+                    self.0.lock().unwrap().push((entry, error.is_none()));
+                }
+            }
+        }
+
+        let created = Arc::new(AtomicUsize::new(0));
+        let audit = Arc::new(Mutex::new(Vec::new()));
+        let runtime = ContainerRuntime::new()
+            .await
+            .unwrap()
+            .with_interceptor(Audit(audit.clone()))
+            .with_interceptor(Quota {
+                limit: 1,
+                created: created.clone(),
+            });
+        let config = ContainerConfig {
+            id: "quota-1".to_string(),
+            rootfs: std::env::temp_dir(),
+            command: vec!["sh".to_string()],
+            working_dir: "/".to_string(),
+            ..Default::default()
+        };
+
+        runtime.create(config.clone()).await.unwrap();
+        let err = runtime
+            .create(ContainerConfig {
+                id: "quota-2".to_string(),
+                ..config
+            })
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Quota of 1"), "{}", err);
+        assert_eq!(created.load(Ordering::SeqCst), 1);
+        assert_eq!(runtime.list().await.unwrap().len(), 1);
+        runtime.delete("quota-1").await.unwrap();
+
+        assert_eq!(
+            *audit.lock().unwrap(),
+            [
+                ("create quota-1".to_string(), true),
+                ("create quota-2".to_string(), false),
+                ("delete quota-1".to_string(), true),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn test_auto_update() {
+        let runtime = ContainerRuntime::new().await.unwrap();
+        let mut store = ImageStore::new(
+            std::env::temp_dir().join(format!("auto-update-{}", std::process::id())),
+        )
+        .unwrap();
+
+        // Without an image reference there is nothing to follow
+        let config = ContainerConfig {
+            id: "auto-update".to_string(),
+            rootfs: std::env::temp_dir(),
+            command: vec!["sh".to_string()],
+            pull_policy: PullPolicy::AutoUpdate,
+            ..Default::default()
+        };
+        let err = runtime.create(config).await.unwrap_err();
+        assert!(matches!(err, ShimError::Validation { .. }), "{}", err);
+        assert!(runtime.list().await.unwrap().is_empty());
+
+        assert!(runtime.update_images(&mut store).await.unwrap().is_empty());
+        let read_only = ContainerRuntime::new_read_only().await.unwrap();
+        let err = read_only.update_images(&mut store).await.unwrap_err();
+        assert!(err.is_permission_denied());
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn test_checkpoint_errors() {
+        let runtime = ContainerRuntime::new().await.unwrap();
+        let id = format!("no-such-container-{}", std::process::id());
+
+        let err = runtime.checkpoint(&id, "first", false).await.unwrap_err();
+        assert!(err.is_not_found(), "{}", err);
+        let err = runtime
+            .checkpoint(&id, "../first", false)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ShimError::Validation { .. }), "{}", err);
+        let err = runtime.remove_checkpoint(&id, "first").await.unwrap_err();
+        assert!(err.is_not_found(), "{}", err);
+        let err = runtime.restore(&id, "first", "restored").await.unwrap_err();
+        assert!(err.is_not_found(), "{}", err);
+        assert!(runtime.checkpoints(Some(&id)).await.unwrap().is_empty());
+        assert!(runtime.checkpoints(Some("..")).await.is_err());
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn test_metrics_report() {
+        let runtime: ContainerRuntime = ContainerRuntime::new().await.unwrap();
+        let temp_rootfs = std::env::temp_dir().join(format!("test-report-{}", std::process::id()));
+        std::fs::create_dir_all(&temp_rootfs).unwrap();
+
+        let config = ContainerConfig {
+            id: "report".to_string(),
+            rootfs: temp_rootfs.clone(),
+            command: vec!["sh".to_string()],
+            working_dir: "/".to_string(),
+            ..Default::default()
+        };
+        runtime.create(config).await.unwrap();
+        let window = std::time::Duration::from_secs(3600);
+        assert!(runtime.metrics_report(window).await.unwrap().is_empty());
+
+        // Collected metrics become the history the report is built from
+        runtime.metrics("report").await.unwrap();
+        runtime.all_metrics().await.unwrap();
+        let reports = runtime.metrics_report(window).await.unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].id, "report");
+        assert_eq!(reports[0].samples, 2);
+
+        let _ = std::fs::remove_dir_all(&temp_rootfs);
+    }
+
     #[tokio::test]
     #[cfg(target_os = "linux")]
     async fn test_container_lifecycle() {
@@ -252,6 +2656,10 @@ mod tests {
 
         // Stop
         runtime.stop("test").await.unwrap();
+        let containers = runtime.list().await.unwrap();
+        assert_eq!(containers[0].last_exit_code, Some(143));
+        assert_eq!(containers[0].last_exit_reason, Some(ExitReason::Signal));
+        assert_eq!(containers[0].restart_count, 0);
 
         // Delete
         runtime.delete("test").await.unwrap();
@@ -263,4 +2671,650 @@ mod tests {
         // Cleanup
         let _ = std::fs::remove_dir_all(&temp_rootfs);
     }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn test_max_runtime_timeout() {
+        let runtime = crate::ContainerRuntime::new().await.unwrap();
+
+        let temp_rootfs = std::env::temp_dir().join(format!("test-timeout-{}", std::process::id()));
+        std::fs::create_dir_all(&temp_rootfs).unwrap();
+
+        let config = crate::ContainerConfig {
+            id: "test-timeout".to_string(),
+            rootfs: temp_rootfs.clone(),
+            command: vec!["sleep".to_string(), "10".to_string()],
+            max_runtime_secs: Some(0),
+            ..Default::default()
+        };
+        runtime.create(config).await.unwrap();
+        runtime.start("test-timeout").await.unwrap();
+
+        let containers = runtime.list().await.unwrap();
+        assert_eq!(containers[0].status, crate::ContainerStatus::Stopped);
+        assert_eq!(containers[0].exit_reason.as_deref(), Some("timeout"));
+        let status = runtime.wait("test-timeout").await.unwrap();
+        assert_eq!(status.code, Some(137));
+
+        runtime.delete("test-timeout").await.unwrap();
+        assert!(runtime
+            .wait("test-timeout")
+            .await
+            .unwrap_err()
+            .is_not_found());
+        let _ = std::fs::remove_dir_all(&temp_rootfs);
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn test_pod_lifecycle() {
+        let runtime = crate::ContainerRuntime::new().await.unwrap();
+
+        let temp_rootfs = std::env::temp_dir().join(format!("test-pod-{}", std::process::id()));
+        std::fs::create_dir_all(&temp_rootfs).unwrap();
+        let container = |id: &str| crate::ContainerConfig {
+            id: id.to_string(),
+            rootfs: temp_rootfs.clone(),
+            command: vec!["sleep".to_string(), "10".to_string()],
+            ..Default::default()
+        };
+
+        let spec = crate::PodSpec::new(container("test-pod"))
+            .member(container("test-pod-app"))
+            .member(container("test-pod-sidecar"));
+        let pod_id = runtime.create_pod(spec).await.unwrap();
+        assert_eq!(pod_id, "test-pod");
+        assert_eq!(
+            runtime.pod_members(&pod_id).unwrap(),
+            ["test-pod-app", "test-pod-sidecar"]
+        );
+
+        // Members can also be added later, as the CRI service does
+        let mut extra = container("test-pod-extra");
+        extra.pod = Some(pod_id.clone());
+        runtime.create(extra).await.unwrap();
+        assert_eq!(runtime.pod_members(&pod_id).unwrap().len(), 3);
+
+        runtime.delete_pod(&pod_id).await.unwrap();
+        assert!(runtime.list().await.unwrap().is_empty());
+        assert!(runtime.pod_members(&pod_id).is_none());
+
+        let _ = std::fs::remove_dir_all(&temp_rootfs);
+    }
+
+    #[test]
+    fn test_pod_member_config() {
+        let spec = crate::PodSpec::new(crate::ContainerConfig {
+            id: "pod".to_string(),
+            ..Default::default()
+        })
+        .volume(crate::VolumeMount {
+            source: "/srv/shared".into(),
+            destination: "/shared".into(),
+            options: vec![],
+            propagation: None,
+            recursive_readonly: false,
+        });
+        let pod = crate::pod::PodState::new(&spec);
+
+        let mut config = crate::ContainerConfig::default();
+        pod.apply_to("pod", &mut config);
+
+        assert_eq!(config.network.mode, "container:pod");
+        let join = config.join_namespaces.unwrap();
+        assert_eq!(join.container, "pod");
+        assert!(join.network && join.ipc);
+        assert_eq!(config.volumes.len(), 1);
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn test_scale() {
+        let runtime = crate::ContainerRuntime::new().await.unwrap();
+
+        let temp_rootfs = std::env::temp_dir().join(format!("test-scale-{}", std::process::id()));
+        std::fs::create_dir_all(&temp_rootfs).unwrap();
+        let template = crate::ContainerConfig {
+            id: "test-scale".to_string(),
+            rootfs: temp_rootfs.clone(),
+            command: vec!["sleep".to_string(), "10".to_string()],
+            ..Default::default()
+        };
+
+        let ids = runtime.scale(&template, 3).await.unwrap();
+        assert_eq!(ids, ["test-scale-1", "test-scale-2", "test-scale-3"]);
+
+        // Scaling down removes the highest ordinals, scaling up refills the gaps
+        runtime.stop("test-scale-1").await.unwrap();
+        runtime.delete("test-scale-1").await.unwrap();
+        let ids = runtime.scale(&template, 1).await.unwrap();
+        assert_eq!(ids, ["test-scale-2"]);
+        let ids = runtime.scale(&template, 2).await.unwrap();
+        assert_eq!(ids, ["test-scale-1", "test-scale-2"]);
+
+        assert!(runtime.scale(&template, 0).await.unwrap().is_empty());
+        assert!(runtime.list().await.unwrap().is_empty());
+
+        let _ = std::fs::remove_dir_all(&temp_rootfs);
+    }
+
+    #[test]
+    fn test_replica_ordinal() {
+        use crate::replicas::{replica_id, replica_ordinal};
+
+        assert_eq!(replica_id("web", 3), "web-3");
+        assert_eq!(replica_ordinal("web", "web-3"), Some(3));
+        assert_eq!(replica_ordinal("web", "web-12"), Some(12));
+        assert_eq!(replica_ordinal("web", "web"), None);
+        assert_eq!(replica_ordinal("web", "web-0"), None);
+        assert_eq!(replica_ordinal("web", "web-01"), None);
+        assert_eq!(replica_ordinal("web", "web-api-1"), None);
+        assert_eq!(replica_ordinal("web", "webapp-1"), None);
+        assert_eq!(replica_ordinal("web", "web-+1"), None);
+    }
+
+    #[test]
+    fn test_startup_order() {
+        use crate::{DependencyCondition, DependsOn};
+
+        let dep = |container: &str| DependsOn {
+            container: container.to_string(),
+            condition: DependencyCondition::Started,
+        };
+        let ids: Vec<String> = ["web", "db", "cache", "worker"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let mut deps = std::collections::HashMap::new();
+        deps.insert("web".to_string(), vec![dep("db"), dep("cache")]);
+        deps.insert("worker".to_string(), vec![dep("db"), dep("external")]);
+
+        let order = crate::group::startup_order(&ids, &deps).unwrap();
+        assert_eq!(order, ["db", "cache", "web", "worker"]);
+
+        deps.insert("db".to_string(), vec![dep("worker")]);
+        let err = crate::group::startup_order(&ids, &deps).unwrap_err();
+        assert!(err.to_string().contains("db, web, worker"));
+    }
+
+    #[test]
+    fn test_cpuset_validation() {
+        let mut limits = crate::ResourceLimits {
+            cpuset_cpus: Some("0-3,6".to_string()),
+            cpuset_mems: Some("0".to_string()),
+            ..Default::default()
+        };
+        assert!(limits.validate().is_ok());
+
+        for bad in ["", "3-1", "0-", "a", "0,,1"] {
+            limits.cpuset_cpus = Some(bad.to_string());
+            assert!(limits.validate().is_err(), "{:?} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn test_memory_swap_validation() {
+        let mut limits = crate::ResourceLimits {
+            memory: Some(512 * 1024 * 1024),
+            memory_swap: Some(1024 * 1024 * 1024),
+            memory_swappiness: Some(60),
+            ..Default::default()
+        };
+        assert!(limits.validate().is_ok());
+
+        limits.memory_swap = Some(-1);
+        assert!(limits.validate().is_ok());
+
+        // Swap below the memory limit
+        limits.memory_swap = Some(256 * 1024 * 1024);
+        assert!(limits.validate().is_err());
+
+        // Swap without a memory limit
+        limits.memory = None;
+        limits.memory_swap = Some(1024 * 1024 * 1024);
+        assert!(limits.validate().is_err());
+
+        limits.memory_swap = None;
+        limits.memory_swappiness = Some(101);
+        assert!(limits.validate().is_err());
+    }
+
+    #[test]
+    fn test_blkio_weight_validation() {
+        let mut limits = crate::ResourceLimits {
+            blkio_weight: Some(500),
+            ..Default::default()
+        };
+        assert!(limits.validate().is_ok());
+        for bad in [0, 9, 1001] {
+            limits.blkio_weight = Some(bad);
+            assert!(limits.validate().is_err(), "{} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn test_profile_defaults() {
+        let profile = crate::ContainerProfile {
+            capabilities: Some(vec!["CAP_KILL".to_string()]),
+            mounts: vec![crate::VolumeMount {
+                source: "/etc/ssl/certs".into(),
+                destination: "/etc/ssl/certs".into(),
+                options: vec!["ro".to_string()],
+                propagation: None,
+                recursive_readonly: false,
+            }],
+            seccomp_profile: None,
+            ulimits: vec![
+                crate::Ulimit {
+                    name: "nofile".to_string(),
+                    soft: 4096,
+                    hard: 4096,
+                },
+                crate::Ulimit {
+                    name: "nproc".to_string(),
+                    soft: 256,
+                    hard: 256,
+                },
+            ],
+        };
+
+        let mut config = crate::ContainerConfig {
+            ulimits: vec![crate::Ulimit {
+                name: "RLIMIT_NOFILE".to_string(),
+                soft: 1024,
+                hard: 1024,
+            }],
+            ..Default::default()
+        };
+        profile.apply_to(&mut config);
+
+        assert_eq!(config.capabilities, Some(vec!["CAP_KILL".to_string()]));
+        assert_eq!(config.volumes.len(), 1);
+        // The container's own nofile limit wins over the profile
+        assert_eq!(config.ulimits.len(), 2);
+        assert_eq!(config.ulimits[0].soft, 1024);
+        assert_eq!(config.ulimits[1].rlimit_type(), "RLIMIT_NPROC");
+    }
+
+    #[test]
+    fn test_template_merging() {
+        let port = |host_port: u16, container_port: u16| crate::PortMapping {
+            host_port,
+            container_port,
+            protocol: "tcp".to_string(),
+            host_ip: None,
+        };
+        let template = crate::ContainerTemplate {
+            image: "docker.io/library/postgres:16".to_string(),
+            command: vec!["postgres".to_string()],
+            env: vec![
+                "POSTGRES_PASSWORD=postgres".to_string(),
+                "PGDATA=/var/lib/postgresql/data".to_string(),
+            ],
+            working_dir: Some("/var/lib/postgresql".to_string()),
+            ports: vec![port(5432, 5432), port(0, 9187)],
+            volumes: vec![crate::VolumeMount {
+                source: "/srv/postgres".into(),
+                destination: "/var/lib/postgresql/data".into(),
+                options: vec![],
+                propagation: None,
+                recursive_readonly: false,
+            }],
+        };
+
+        let mut config = crate::ContainerConfig {
+            env: vec!["POSTGRES_PASSWORD=secret".to_string()],
+            working_dir: String::new(),
+            ..Default::default()
+        };
+        config.network.port_mappings = vec![port(5432, 5433)];
+        template.apply_to(&mut config);
+
+        assert_eq!(config.command, ["postgres"]);
+        assert_eq!(config.working_dir, "/var/lib/postgresql");
+        assert_eq!(config.image.as_deref(), Some(template.image.as_str()));
+        // The container's own settings win over the template's
+        assert_eq!(
+            config.env,
+            [
+                "PGDATA=/var/lib/postgresql/data",
+                "POSTGRES_PASSWORD=secret"
+            ]
+        );
+        let ports: Vec<(u16, u16)> = config
+            .network
+            .port_mappings
+            .iter()
+            .map(|p| (p.host_port, p.container_port))
+            .collect();
+        assert_eq!(ports, [(0, 9187), (5432, 5433)]);
+        assert_eq!(config.volumes.len(), 1);
+
+        let mut config = crate::ContainerConfig {
+            command: vec!["psql".to_string()],
+            ..Default::default()
+        };
+        template.apply_to(&mut config);
+        assert_eq!(config.command, ["psql"]);
+        assert_eq!(config.working_dir, "/");
+    }
+
+    #[test]
+    fn test_resource_guard() {
+        const MIB: u64 = 1024 * 1024;
+        let guard = crate::ResourceGuard::default();
+        assert!(guard
+            .problems(Some(256 * MIB), Some(1024 * MIB), Some(4096 * MIB))
+            .is_empty());
+        // Unknown amounts and containers without a limit are not checked
+        assert!(guard.problems(Some(256 * MIB), None, None).is_empty());
+        assert!(guard.problems(None, Some(MIB), Some(4096 * MIB)).is_empty());
+
+        let problems = guard.problems(Some(2048 * MIB), Some(1024 * MIB), Some(100 * MIB));
+        assert_eq!(
+            problems,
+            [
+                "memory limit of 2048 MiB exceeds the 1024 MiB available",
+                "only 100 MiB of disk is free, 512 MiB is required",
+            ]
+        );
+
+        let overcommit = crate::ResourceGuard {
+            memory_factor: 2.0,
+            ..Default::default()
+        };
+        assert!(overcommit
+            .problems(Some(2048 * MIB), Some(1024 * MIB), None)
+            .is_empty());
+        assert!(crate::ResourceGuard::disabled()
+            .problems(Some(u64::MAX), Some(0), Some(0))
+            .is_empty());
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn test_start_refused_without_resources() {
+        let temp_rootfs = std::env::temp_dir().join(format!("test-guard-{}", std::process::id()));
+        std::fs::create_dir_all(&temp_rootfs).unwrap();
+        let config = crate::RuntimeConfig::builder()
+            .resource_guard(crate::ResourceGuard {
+                min_free_disk_bytes: u64::MAX,
+                ..Default::default()
+            })
+            .build();
+        let runtime = ContainerRuntime::new_with_config(config).await.unwrap();
+        runtime
+            .create(ContainerConfig {
+                id: "guarded".to_string(),
+                rootfs: temp_rootfs.clone(),
+                command: vec!["sleep".to_string(), "10".to_string()],
+                resources: crate::ResourceLimits {
+                    memory: Some(u64::MAX / 2),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(runtime.check_resources("guarded").await.len(), 2);
+        let err = runtime.start("guarded").await.unwrap_err();
+        assert!(matches!(err, ShimError::Unavailable { .. }), "{}", err);
+        assert!(err.to_string().contains("Not enough resources"));
+        let containers = runtime.list().await.unwrap();
+        assert_eq!(containers[0].status, ContainerStatus::Created);
+
+        runtime.delete("guarded").await.unwrap();
+        let _ = std::fs::remove_dir_all(&temp_rootfs);
+    }
+
+    #[test]
+    fn test_capacity() {
+        const GIB: u64 = 1024 * 1024 * 1024;
+        let capacity = crate::Capacity {
+            total: crate::Reservation {
+                cpus: 4.0,
+                memory: 8 * GIB,
+            },
+            reserved: crate::Reservation {
+                cpus: 3.5,
+                memory: 6 * GIB,
+            },
+            limits: crate::Reservation {
+                cpus: 8.0,
+                memory: 16 * GIB,
+            },
+        };
+        assert_eq!(capacity.available().cpus, 0.5);
+        assert_eq!(capacity.available().memory, 2 * GIB);
+        let fits = crate::Reservation {
+            cpus: 0.5,
+            memory: 2 * GIB,
+        };
+        assert!(capacity.shortfall(fits).is_empty());
+        let too_big = crate::Reservation {
+            cpus: 1.0,
+            memory: 3 * GIB,
+        };
+        assert_eq!(
+            capacity.shortfall(too_big),
+            [
+                "1 CPUs requested, 0.5 of 4 left",
+                "3072 MiB of memory requested, 2048 of 8192 MiB left",
+            ]
+        );
+
+        let mut limits = crate::ResourceLimits {
+            cpu: Some(2.0),
+            memory: Some(GIB),
+            cpu_reservation: Some(0.5),
+            memory_reservation: Some(GIB / 2),
+            ..Default::default()
+        };
+        assert!(limits.validate().is_ok());
+        assert_eq!(
+            limits.reservation(),
+            crate::Reservation {
+                cpus: 0.5,
+                memory: GIB / 2,
+            }
+        );
+        limits.memory_reservation = Some(2 * GIB);
+        assert!(limits.validate().is_err());
+        limits.memory_reservation = None;
+        limits.cpu_reservation = Some(-1.0);
+        assert!(limits.validate().is_err());
+    }
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn test_create_refused_beyond_capacity() {
+        let temp_rootfs =
+            std::env::temp_dir().join(format!("test-capacity-{}", std::process::id()));
+        std::fs::create_dir_all(&temp_rootfs).unwrap();
+        let runtime = ContainerRuntime::new().await.unwrap();
+        let total = runtime.capacity().await.unwrap().total;
+        let config = |id: &str| ContainerConfig {
+            id: id.to_string(),
+            rootfs: temp_rootfs.clone(),
+            command: vec!["sleep".to_string(), "10".to_string()],
+            resources: crate::ResourceLimits {
+                cpu_reservation: Some(total.cpus * 0.75),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        runtime.create(config("placed")).await.unwrap();
+        let capacity = runtime.capacity().await.unwrap();
+        assert_eq!(capacity.reserved.cpus, total.cpus * 0.75);
+
+        let err = runtime.create(config("refused")).await.unwrap_err();
+        assert!(matches!(err, ShimError::Unavailable { .. }), "{}", err);
+        assert!(err.to_string().contains("Not enough capacity"));
+        assert_eq!(runtime.list().await.unwrap().len(), 1);
+
+        // Deleting a container gives its reservation back
+        runtime.delete("placed").await.unwrap();
+        runtime.create(config("refused")).await.unwrap();
+        runtime.delete("refused").await.unwrap();
+        let _ = std::fs::remove_dir_all(&temp_rootfs);
+    }
+
+    #[test]
+    fn test_hugepage_validation() {
+        let mut limits = crate::ResourceLimits {
+            hugepage_limits: vec![crate::HugepageLimit {
+                page_size: "2MB".to_string(),
+                limit: 128 * 1024 * 1024,
+            }],
+            ..Default::default()
+        };
+        assert!(limits.validate().is_ok());
+
+        for bad in ["2M", "MB", "0MB", "2mb"] {
+            limits.hugepage_limits[0].page_size = bad.to_string();
+            assert!(limits.validate().is_err(), "{:?} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn test_egress_validation() {
+        let mut network = crate::NetworkConfig {
+            allow_egress: vec!["10.0.0.0/8".to_string(), "fd00::/8".to_string()],
+            deny_egress: vec!["169.254.169.254".to_string()],
+            ..Default::default()
+        };
+        assert!(network.validate().is_ok());
+
+        for bad in ["10.0.0.0/33", "fd00::/129", "example.com", "10.0.0/8"] {
+            network.deny_egress = vec![bad.to_string()];
+            assert!(network.validate().is_err(), "{:?} should be rejected", bad);
+        }
+
+        // Rules would land in the host's namespace
+        network.deny_egress.clear();
+        network.mode = "host".to_string();
+        assert!(network.validate().is_err());
+    }
+
+    #[test]
+    fn test_retry_policy() {
+        use std::time::Duration;
+
+        let policy = crate::RetryPolicy {
+            max_attempts: 5,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 300,
+            multiplier: 2.0,
+            max_elapsed_ms: 1000,
+        };
+        // Jitter randomizes the upper half of the capped exponential delay
+        for (retry, base) in [(1, 100), (2, 200), (3, 300), (10, 300)] {
+            for _ in 0..20 {
+                let delay = policy.backoff(retry);
+                assert!(delay >= Duration::from_millis(base / 2), "{:?}", delay);
+                assert!(delay <= Duration::from_millis(base), "{:?}", delay);
+            }
+        }
+
+        assert!(policy.next_delay(1, Duration::ZERO).is_some());
+        assert!(policy.next_delay(5, Duration::ZERO).is_none());
+        // Waiting would overrun the elapsed budget
+        assert!(policy.next_delay(1, Duration::from_millis(990)).is_none());
+        assert!(crate::RetryPolicy::disabled()
+            .next_delay(1, Duration::ZERO)
+            .is_none());
+
+        // Missing fields take the defaults
+        let config: crate::RuntimeConfig =
+            serde_json::from_str(r#"{"retry_policy": {"max_attempts": 2}}"#).unwrap();
+        assert_eq!(config.retry_policy.max_attempts, 2);
+        assert_eq!(
+            config.retry_policy.initial_backoff_ms,
+            crate::RetryPolicy::default().initial_backoff_ms
+        );
+    }
+
+    #[test]
+    fn test_idle_policy() {
+        use std::time::Duration;
+
+        let policy = crate::IdlePolicy::default();
+        assert!(!policy.is_enabled());
+        assert!(!policy.should_shut_down(0, Duration::from_secs(86400)));
+
+        let policy = crate::RuntimeConfig::builder()
+            .vm_idle_shutdown(600)
+            .build()
+            .idle_policy;
+        assert!(policy.should_shut_down(0, Duration::from_secs(600)));
+        assert!(!policy.should_shut_down(0, Duration::from_secs(599)));
+        assert!(!policy.should_shut_down(1, Duration::from_secs(3600)));
+        assert_eq!(policy.check_interval(), Duration::from_secs(30));
+        // A short delay is checked at least that often
+        assert_eq!(
+            crate::IdlePolicy::after_secs(5).check_interval(),
+            Duration::from_secs(5)
+        );
+
+        let config: crate::RuntimeConfig =
+            serde_json::from_str(r#"{"idle_policy": {"shutdown_after_secs": 900}}"#).unwrap();
+        assert_eq!(config.idle_policy.shutdown_after_secs, 900);
+        assert_eq!(config.idle_policy.check_interval_secs, 30);
+    }
+
+    #[test]
+    fn test_time_sync_policy() {
+        use std::time::Duration;
+
+        let policy = crate::TimeSyncPolicy::default();
+        assert!(policy.is_enabled());
+        assert_eq!(policy.check_interval(), Duration::from_secs(5));
+        assert!(!policy.is_due(Duration::from_secs(30), Duration::from_secs(30)));
+        assert!(policy.is_due(Duration::from_secs(60), Duration::from_secs(60)));
+        // The host slept for an hour
+        assert!(policy.is_due(Duration::from_secs(5), Duration::from_secs(3605)));
+        // Wall clock set back on the host
+        assert!(!policy.is_due(Duration::from_secs(5), Duration::ZERO));
+
+        let config: crate::RuntimeConfig =
+            serde_json::from_str(r#"{"time_sync": {"interval_secs": 2}}"#).unwrap();
+        assert_eq!(config.time_sync.check_interval(), Duration::from_secs(2));
+        assert_eq!(config.time_sync.step_threshold_ms, 1000);
+    }
+
+    #[test]
+    fn test_transport_policy() {
+        use crate::{Transport, TransportPolicy};
+        use std::time::Duration;
+
+        let policy = TransportPolicy::default();
+        assert_eq!(policy.order, [Transport::Vsock, Transport::Unix]);
+        assert_eq!(policy.boot_wait(true), Duration::from_secs(20));
+        assert_eq!(policy.boot_wait(false), Duration::from_secs(2));
+        // The transport that worked last is tried first
+        assert_eq!(
+            policy.order_from(Some(Transport::Unix)),
+            [Transport::Unix, Transport::Vsock]
+        );
+        assert_eq!(
+            TransportPolicy::only(Transport::Vsock).order_from(None),
+            [Transport::Vsock]
+        );
+
+        assert_eq!(
+            TransportPolicy::parse_order("unix, vsock").unwrap(),
+            [Transport::Unix, Transport::Vsock]
+        );
+        assert!(TransportPolicy::parse_order("tcp").is_err());
+        assert!(TransportPolicy::parse_order("").is_err());
+
+        let config: crate::RuntimeConfig =
+            serde_json::from_str(r#"{"transport": {"order": ["unix"], "boot_wait_ms": 500}}"#)
+                .unwrap();
+        assert_eq!(config.transport.order, [Transport::Unix]);
+        assert_eq!(config.transport.boot_attempts, 5);
+        assert_eq!(config.transport.boot_wait(true), Duration::from_millis(500));
+    }
 }
@@ -1,4 +1,11 @@
+use crate::checkpoints::{self, CheckpointStore};
+use crate::copy;
 use crate::*;
+use libcrun_shim_proto::cgroup::{self, oom_kill_count, unapplied_limits, CgroupPaths};
+use libcrun_shim_proto::{
+    archive, cgroup_limits, exit_outcome, io_weight, is_cgroup_v2, swap_limit,
+    ContainerMetricsProto, NetworkMetricsProto, CPU_PERIOD,
+};
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::RwLock;
@@ -8,21 +15,41 @@ use libcrun_sys::safe as crun;
 #[cfg(target_os = "linux")]
 use libcrun_sys::{LibcrunContainerPtr, LibcrunContextPtr};
 
+/// Where containers created with `rootfs_snapshot` keep their writable layer
+const SNAPSHOTS_DIR: &str = "/var/lib/libcrun-shim/snapshots";
+
 // Internal container state that includes the config
 struct ContainerState {
-    #[allow(dead_code)]
     config: ContainerConfig,
     info: ContainerInfo,
+    /// When the container was started, for `max_runtime_secs`
+    started_at: Option<std::time::Instant>,
+    /// When the container was created (Unix seconds), for prune filters
+    created_at: u64,
+    /// Finished exec sessions, oldest first
+    execs: Vec<ExecSession>,
+    /// Exec sessions running in the container, see [`ExecGuard`]
+    execs_in_flight: usize,
+    /// OCI configuration the container was created with through libcrun,
+    /// kept for checkpoints
+    oci_config: Option<String>,
     #[cfg(target_os = "linux")]
     libcrun_container: Option<LibcrunContainerPtr>,
 }
 
 pub struct LinuxRuntime {
     containers: RwLock<HashMap<String, ContainerState>>,
+    /// Metrics collected through `metrics` and `all_metrics`, oldest first
+    history: std::sync::Mutex<std::collections::VecDeque<MetricsSample>>,
+    /// Validates status changes and publishes their events
+    lifecycle: StateMachine,
     #[cfg(target_os = "linux")]
     libcrun_context: Option<LibcrunContextPtr>,
     #[cfg(target_os = "linux")]
     libcrun_available: bool,
+    /// IDs of the containers being created, see [`CreateGuard`]
+    #[cfg(target_os = "linux")]
+    creating: std::sync::Mutex<std::collections::HashSet<String>>,
 }
 
 impl Drop for LinuxRuntime {
@@ -46,6 +73,156 @@ impl Drop for LinuxRuntime {
     }
 }
 
+/// Undoes a create that does not finish
+///
+/// Whatever the create made for the container, its rootfs snapshot, libcrun
+/// state and cgroup, is removed when the guard is dropped before
+/// [`CreateGuard::commit`], whichever way the create failed. While the guard
+/// lives no other create of the ID can start, and the stale resource scan
+/// leaves the ID alone.
+#[cfg(target_os = "linux")]
+struct CreateGuard<'a> {
+    runtime: &'a LinuxRuntime,
+    id: String,
+    /// Where the rootfs snapshot is being made
+    snapshot: Option<PathBuf>,
+    /// libcrun had no state for the ID before the create
+    owns_state: bool,
+    /// The container libcrun created
+    container: Option<LibcrunContainerPtr>,
+    committed: bool,
+}
+
+#[cfg(target_os = "linux")]
+impl<'a> CreateGuard<'a> {
+    fn new(runtime: &'a LinuxRuntime, id: &str) -> Result<Self> {
+        if !runtime.creating.lock().unwrap().insert(id.to_string()) {
+            return Err(ShimError::conflict(
+                format!("Container '{}' is being created", id),
+                "Use a different container ID",
+            ));
+        }
+        Ok(Self {
+            runtime,
+            id: id.to_string(),
+            snapshot: None,
+            owns_state: false,
+            container: None,
+            committed: false,
+        })
+    }
+
+    /// The container is tracked, and keeps what was made for it
+    fn commit(mut self) {
+        self.committed = true;
+    }
+
+    fn undo(&mut self) {
+        log::info!("Removing what the failed create of '{}' made", self.id);
+        if let Some(container) = self.container.take() {
+            if let Some(ref ctx) = self.runtime.libcrun_context {
+                if let Err(e) = crun::container_delete(ctx.as_ptr(), container.as_ptr(), &self.id) {
+                    log::warn!("libcrun delete of '{}' failed: {}", self.id, e);
+                }
+            }
+            crun::container_free(container.as_ptr());
+        }
+        if self.owns_state {
+            if let Err(e) = crun::remove_state(&self.id) {
+                log::warn!("Failed to remove the libcrun state of '{}': {}", self.id, e);
+            }
+            if let Err(e) = crun::remove_cgroup(&self.id) {
+                log::warn!("Failed to remove the cgroup of '{}': {}", self.id, e);
+            }
+        }
+        if let Some(dir) = &self.snapshot {
+            if let Err(e) = crun::remove_snapshot(dir) {
+                log::warn!(
+                    "Failed to remove the rootfs snapshot of '{}': {}",
+                    self.id,
+                    e
+                );
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for CreateGuard<'_> {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.undo();
+        }
+        self.runtime.creating.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Counts an exec session running in a container, which cannot be deleted
+/// until the guard is dropped
+struct ExecGuard<'a> {
+    runtime: &'a LinuxRuntime,
+    id: String,
+}
+
+impl<'a> ExecGuard<'a> {
+    fn new(runtime: &'a LinuxRuntime, id: &str) -> Result<Self> {
+        let mut containers = runtime.containers.write().unwrap();
+        let state = containers
+            .get_mut(id)
+            .ok_or_else(|| ShimError::not_found(format!("Container '{}' not found", id)))?;
+
+        if state.info.status != ContainerStatus::Running {
+            return Err(ShimError::conflict(
+                "Container is not running",
+                format!("Container '{}' must be running to execute commands", id),
+            ));
+        }
+        state.execs_in_flight += 1;
+        Ok(Self {
+            runtime,
+            id: id.to_string(),
+        })
+    }
+}
+
+impl Drop for ExecGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(state) = self.runtime.containers.write().unwrap().get_mut(&self.id) {
+            state.execs_in_flight -= 1;
+        }
+    }
+}
+
+/// Whether a container other than `id` is named `name`
+fn name_taken(containers: &HashMap<String, ContainerState>, name: &str, id: &str) -> bool {
+    containers
+        .values()
+        .any(|c| c.info.name == name && c.info.id != id)
+}
+
+fn name_in_use(name: &str) -> ShimError {
+    ShimError::conflict(
+        format!("The name '{}' is already in use", name),
+        "Use a different name or rename the container that has it",
+    )
+}
+
+/// Status changes of the runtime's containers, which publish their events
+///
+/// Exits are published with their exit code where they are noticed, pauses
+/// by [`ContainerRuntime`] for every backend.
+fn lifecycle() -> StateMachine {
+    StateMachine::new().on_transition(|id, transition, _, _| {
+        let event = match transition {
+            Transition::Start | Transition::Restart => ContainerEventType::Start,
+            Transition::Stop => ContainerEventType::Stop,
+            Transition::Delete => ContainerEventType::Delete,
+            Transition::Exit | Transition::Pause | Transition::Unpause => return,
+        };
+        global_events().emit(event, id);
+    })
+}
+
 impl LinuxRuntime {
     pub fn new() -> Result<Self> {
         #[cfg(target_os = "linux")]
@@ -70,8 +247,11 @@ impl LinuxRuntime {
 
             Ok(Self {
                 containers: RwLock::new(HashMap::new()),
+                history: Default::default(),
+                lifecycle: lifecycle(),
                 libcrun_context: context,
                 libcrun_available: available,
+                creating: Default::default(),
             })
         }
 
@@ -79,12 +259,50 @@ impl LinuxRuntime {
         {
             Ok(Self {
                 containers: RwLock::new(HashMap::new()),
+                history: Default::default(),
+                lifecycle: lifecycle(),
             })
         }
     }
 
+    /// Metrics samples collected at or after `since` (Unix epoch seconds)
+    ///
+    /// There is no agent sampling in the background on Linux, so the history
+    /// holds whatever this process collected through `metrics` and
+    /// `all_metrics`, e.g. by polling them.
+    pub fn metrics_history(&self, since: u64) -> Vec<MetricsSample> {
+        self.history
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|s| s.timestamp >= since)
+            .cloned()
+            .collect()
+    }
+
+    /// A container's rootfs, its snapshot if it has one, and whether it is
+    /// read-only
+    fn rootfs(&self, id: &str) -> Result<(PathBuf, bool)> {
+        self.containers
+            .read()
+            .unwrap()
+            .get(id)
+            .map(|state| (state.config.rootfs.clone(), state.config.read_only_rootfs))
+            .ok_or_else(|| ShimError::not_found(format!("Container '{}'", id)))
+    }
+
+    fn record_metrics(&self, metrics: &[ContainerMetrics]) {
+        let mut history = self.history.lock().unwrap();
+        for m in metrics {
+            crate::report::record(&mut history, MetricsSample::from_metrics(m));
+        }
+    }
+
     #[cfg(target_os = "linux")]
-    fn build_oci_config_json(config: &ContainerConfig) -> Result<String> {
+    fn build_oci_config_json(
+        config: &ContainerConfig,
+        shared_namespaces: &[(&str, String)],
+    ) -> Result<String> {
         // Build a complete OCI config JSON from our ContainerConfig
         // Following OCI Runtime Specification v1.0.0
 
@@ -150,40 +368,42 @@ impl LinuxRuntime {
                 "source": volume.source.display().to_string(),
             });
 
-            if !volume.options.is_empty() {
-                mount["options"] = serde_json::json!(volume.options);
+            let options = libcrun_shim_proto::volume_mount_options(
+                &volume.options,
+                volume.propagation,
+                volume.recursive_readonly,
+            );
+            if !options.is_empty() {
+                mount["options"] = serde_json::json!(options);
             }
 
             mounts.push(mount);
         }
 
-        // Build rlimits array with defaults + resource limits
+        // Build rlimits array with defaults. Resource limits go to the
+        // cgroup below, rlimits count per process (or per user for
+        // RLIMIT_NPROC) rather than for the container as a whole.
         let mut rlimits = vec![serde_json::json!({
             "type": "RLIMIT_NOFILE",
             "hard": 1024,
             "soft": 1024
         })];
 
-        // Add resource limits
-        if let Some(memory) = config.resources.memory {
-            if memory > 0 {
-                rlimits.push(serde_json::json!({
-                    "type": "RLIMIT_AS",
-                    "hard": memory,
-                    "soft": memory
-                }));
-            }
+        // Profile or container ulimits replace the defaults of the same type
+        for ulimit in &config.ulimits {
+            let rlimit_type = ulimit.rlimit_type();
+            rlimits.retain(|r| r["type"] != rlimit_type.as_str());
+            rlimits.push(serde_json::json!({
+                "type": rlimit_type,
+                "hard": ulimit.hard,
+                "soft": ulimit.soft
+            }));
         }
 
-        if let Some(pids) = config.resources.pids {
-            if pids > 0 {
-                rlimits.push(serde_json::json!({
-                    "type": "RLIMIT_NPROC",
-                    "hard": pids,
-                    "soft": pids
-                }));
-            }
-        }
+        let capabilities: Vec<String> = match &config.capabilities {
+            Some(caps) => caps.iter().map(|c| normalize_capability(c)).collect(),
+            None => DEFAULT_CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+        };
 
         // Build resources object
         let mut resources = serde_json::json!({
@@ -196,15 +416,27 @@ impl LinuxRuntime {
         });
 
         // Add CPU and memory limits
-        if config.resources.cpu.is_some() || config.resources.memory.is_some() {
+        if config.resources.cpu.is_some()
+            || config.resources.memory.is_some()
+            || config.resources.memory_swap.is_some()
+            || config.resources.memory_swappiness.is_some()
+            || config.resources.cpuset_cpus.is_some()
+            || config.resources.cpuset_mems.is_some()
+        {
             let mut cpu_obj = serde_json::json!({});
             if let Some(cpu) = config.resources.cpu {
                 if cpu > 0.0 {
                     cpu_obj["shares"] = serde_json::json!((cpu * 1024.0) as u64);
-                    cpu_obj["quota"] = serde_json::json!((cpu * 100000.0) as i64);
-                    cpu_obj["period"] = serde_json::json!(100000);
+                    cpu_obj["quota"] = serde_json::json!((cpu * CPU_PERIOD as f64) as i64);
+                    cpu_obj["period"] = serde_json::json!(CPU_PERIOD);
                 }
             }
+            if let Some(ref cpus) = config.resources.cpuset_cpus {
+                cpu_obj["cpus"] = serde_json::json!(cpus);
+            }
+            if let Some(ref mems) = config.resources.cpuset_mems {
+                cpu_obj["mems"] = serde_json::json!(mems);
+            }
 
             let mut memory_obj = serde_json::json!({});
             if let Some(memory) = config.resources.memory {
@@ -212,9 +444,29 @@ impl LinuxRuntime {
                     memory_obj["limit"] = serde_json::json!(memory);
                 }
             }
-            if let Some(memory_swap) = config.resources.memory_swap {
-                if memory_swap > 0 {
-                    memory_obj["swap"] = serde_json::json!(memory_swap);
+
+            // The OCI `swap` key is memory+swap, which only matches cgroup v1.
+            // On v2 the swap limit is its own knob, so set it directly.
+            let cgroup_v2 = is_cgroup_v2();
+            match swap_limit(config.resources.memory, config.resources.memory_swap) {
+                Some(-1) if cgroup_v2 => {
+                    resources["unified"] = serde_json::json!({ "memory.swap.max": "max" });
+                }
+                Some(total) if cgroup_v2 => {
+                    let swap_only = total - config.resources.memory.unwrap_or(0) as i64;
+                    resources["unified"] =
+                        serde_json::json!({ "memory.swap.max": swap_only.max(0).to_string() });
+                }
+                Some(total) => {
+                    memory_obj["swap"] = serde_json::json!(total);
+                }
+                None => {}
+            }
+            if let Some(swappiness) = config.resources.memory_swappiness {
+                if cgroup_v2 {
+                    log::warn!("memory swappiness is not supported on cgroup v2, ignoring");
+                } else {
+                    memory_obj["swappiness"] = serde_json::json!(swappiness);
                 }
             }
 
@@ -226,6 +478,31 @@ impl LinuxRuntime {
             }
         }
 
+        if !config.resources.hugepage_limits.is_empty() {
+            let limits: Vec<_> = config
+                .resources
+                .hugepage_limits
+                .iter()
+                .map(|h| serde_json::json!({ "pageSize": h.page_size, "limit": h.limit }))
+                .collect();
+            resources["hugepageLimits"] = serde_json::json!(limits);
+        }
+
+        if let Some(pids) = config.resources.pids.filter(|p| *p > 0) {
+            resources["pids"] = serde_json::json!({ "limit": pids });
+        }
+        if let Some(weight) = config.resources.blkio_weight {
+            // On v2 crun writes blockIO.weight to io.bfq.weight when the BFQ
+            // scheduler has it, set io.weight itself so the weight holds
+            // whatever the scheduler
+            if is_cgroup_v2() {
+                resources["unified"]["io.weight"] =
+                    serde_json::json!(io_weight(weight).to_string());
+            } else {
+                resources["blockIO"] = serde_json::json!({ "weight": weight });
+            }
+        }
+
         // Determine network namespace based on network mode
         let network_namespace = match config.network.mode.as_str() {
             "host" => None, // No network namespace for host mode
@@ -248,7 +525,13 @@ impl LinuxRuntime {
             namespaces.push(ns);
         }
 
-        let oci_config = serde_json::json!({
+        // Namespaces joined from another container (pods)
+        for (ns_type, path) in shared_namespaces {
+            namespaces.retain(|ns| ns["type"] != *ns_type);
+            namespaces.push(serde_json::json!({ "type": ns_type, "path": path }));
+        }
+
+        let mut oci_config = serde_json::json!({
             "ociVersion": "1.0.0",
             "process": {
                 "terminal": config.stdio.tty,
@@ -260,38 +543,18 @@ impl LinuxRuntime {
                 "env": env,
                 "cwd": config.working_dir,
                 "capabilities": {
-                    "bounding": [
-                        "CAP_AUDIT_WRITE",
-                        "CAP_KILL",
-                        "CAP_NET_BIND_SERVICE"
-                    ],
-                    "effective": [
-                        "CAP_AUDIT_WRITE",
-                        "CAP_KILL",
-                        "CAP_NET_BIND_SERVICE"
-                    ],
-                    "inheritable": [
-                        "CAP_AUDIT_WRITE",
-                        "CAP_KILL",
-                        "CAP_NET_BIND_SERVICE"
-                    ],
-                    "permitted": [
-                        "CAP_AUDIT_WRITE",
-                        "CAP_KILL",
-                        "CAP_NET_BIND_SERVICE"
-                    ],
-                    "ambient": [
-                        "CAP_AUDIT_WRITE",
-                        "CAP_KILL",
-                        "CAP_NET_BIND_SERVICE"
-                    ]
+                    "bounding": capabilities,
+                    "effective": capabilities,
+                    "inheritable": capabilities,
+                    "permitted": capabilities,
+                    "ambient": capabilities
                 },
                 "rlimits": rlimits,
                 "noNewPrivileges": true
             },
             "root": {
                 "path": config.rootfs.display().to_string(),
-                "readonly": false
+                "readonly": config.read_only_rootfs
             },
             "hostname": config.id.clone(),
             "mounts": mounts,
@@ -318,10 +581,303 @@ impl LinuxRuntime {
             }
         });
 
-        serde_json::to_string_pretty(&oci_config).map_err(|e| ShimError::Serialization {
-            message: e.to_string(),
-            context: Some("Failed to serialize OCI config".to_string()),
-        })
+        if let Some(ref path) = config.seccomp_profile {
+            let content = std::fs::read_to_string(path).map_err(|e| ShimError::Io {
+                error: e,
+                context: Some(format!("Failed to read seccomp profile {}", path.display())),
+            })?;
+            let seccomp: serde_json::Value = serde_json::from_str(&content).map_err(|e| {
+                ShimError::serialization(format!("Invalid seccomp profile {}", path.display()), e)
+            })?;
+            oci_config["linux"]["seccomp"] = seccomp;
+        }
+
+        if config.network.has_egress_rules() {
+            oci_config["hooks"] = serde_json::json!({
+                "createContainer": [libcrun_shim_proto::egress::hook(
+                    &config.network.allow_egress,
+                    &config.network.deny_egress,
+                )]
+            });
+        }
+
+        serde_json::to_string_pretty(&oci_config)
+            .map_err(|e| ShimError::serialization("Failed to serialize OCI config", e))
+    }
+
+    /// Kill running containers that have exceeded their `max_runtime_secs`
+    ///
+    /// There is no supervisor process in the library runtime, so deadlines
+    /// are enforced whenever the container list is observed.
+    fn enforce_deadlines(&self) {
+        let mut containers = self.containers.write().unwrap();
+        for (id, state) in containers.iter_mut() {
+            let (Some(max_runtime), Some(started_at)) =
+                (state.config.max_runtime_secs, state.started_at)
+            else {
+                continue;
+            };
+            if state.info.status != ContainerStatus::Running
+                || started_at.elapsed().as_secs() < max_runtime
+            {
+                continue;
+            }
+
+            log::warn!(
+                "Container '{}' exceeded its max runtime of {}s, killing it",
+                id,
+                max_runtime
+            );
+            #[cfg(target_os = "linux")]
+            if let (Some(container), Some(ctx)) = (&state.libcrun_container, &self.libcrun_context)
+            {
+                if let Err(e) =
+                    crun::container_kill(ctx.as_ptr(), container.as_ptr(), id, libc::SIGKILL)
+                {
+                    log::warn!("Failed to kill container '{}': {}", id, e.message);
+                }
+            }
+
+            // Only running containers get here, which can always be stopped
+            let _ = self
+                .lifecycle
+                .apply(id, &mut state.info.status, Transition::Stop);
+            state.info.pid = None;
+            state.info.exit_reason = Some("timeout".to_string());
+            state.info.last_exit_code = Some(128 + libc::SIGKILL);
+            state.info.last_exit_reason = Some(ExitReason::Signal);
+            global_events().send(
+                ContainerEvent::new(ContainerEventType::Kill, id.clone())
+                    .with_signal(libc::SIGKILL)
+                    .with_attribute("reason", "timeout"),
+            );
+        }
+    }
+
+    /// Record the exits of running containers whose process has ended, and
+    /// the processes the OOM killer killed in them
+    ///
+    /// Like deadlines, exits are noticed whenever the container list is
+    /// observed.
+    fn reap_exited(&self) {
+        let mut containers = self.containers.write().unwrap();
+        for (id, state) in containers.iter_mut() {
+            let pid = match state.info.pid {
+                // Skip the fallback mode placeholder PID
+                // A paused container can still be killed, e.g. by the OOM killer
+                Some(pid)
+                    if matches!(
+                        state.info.status,
+                        ContainerStatus::Running | ContainerStatus::Paused
+                    ) && pid != std::process::id() =>
+                {
+                    pid
+                }
+                _ => continue,
+            };
+            // Looked up first, the process is gone once it is reaped
+            let oom_kills = CgroupPaths::find(pid).map_or(0, |cgroup| oom_kill_count(&cgroup));
+            if oom_kills > state.info.oom_kills {
+                log::warn!(
+                    "The OOM killer killed {} process(es) of '{}'",
+                    oom_kills - state.info.oom_kills,
+                    id
+                );
+                state.info.oom_kills = oom_kills;
+                global_events().send(
+                    ContainerEvent::new(ContainerEventType::Oom, id.clone())
+                        .with_attribute("oom_kills", oom_kills.to_string()),
+                );
+            }
+            let Some(status) = process_exit(pid) else {
+                continue;
+            };
+
+            let outcome = status.map(|status| exit_outcome(status, oom_kills > 0));
+            log::info!("Container '{}' exited: {:?}", id, outcome);
+            // Only running and paused containers get here, which can always exit
+            let _ = self
+                .lifecycle
+                .apply(id, &mut state.info.status, Transition::Exit);
+            state.info.pid = None;
+            state.info.last_exit_code = outcome.map(|(code, _)| code);
+            state.info.last_exit_reason = outcome.map(|(_, reason)| reason);
+
+            let mut event = ContainerEvent::new(ContainerEventType::Die, id.clone());
+            if let Some((code, _)) = outcome {
+                event = event.with_exit_code(code);
+            }
+            global_events().send(event);
+        }
+    }
+
+    /// Send SIGTERM to container `id` and mark it stopped, returning its
+    /// process and how long it has to exit, if it has one of its own
+    #[cfg(target_os = "linux")]
+    fn terminate(
+        &self,
+        id: &str,
+        timeout: Option<std::time::Duration>,
+    ) -> Result<Option<(u32, std::time::Duration)>> {
+        let mut containers = self.containers.write().unwrap();
+        let state = containers
+            .get_mut(id)
+            .ok_or_else(|| ShimError::not_found(format!("Container '{}'", id)))?;
+
+        self.lifecycle
+            .check(id, state.info.status, Transition::Stop)?;
+
+        // Try to stop container via libcrun if available
+        #[cfg(target_os = "linux")]
+        if self.libcrun_available {
+            if let Some(ref container) = state.libcrun_container {
+                if let Some(ref ctx) = self.libcrun_context {
+                    // Frozen processes only see the signal once thawed
+                    if state.info.status == ContainerStatus::Paused {
+                        if let Err(e) = crun::container_resume(ctx.as_ptr(), id) {
+                            log::warn!(
+                                "Failed to unpause '{}' before stopping it: {}",
+                                id,
+                                e.message
+                            );
+                        }
+                    }
+                    // Use SIGTERM to stop gracefully
+                    match crun::container_kill(ctx.as_ptr(), container.as_ptr(), id, libc::SIGTERM) {
+                        Ok(_) => {
+                            log::info!(
+                                "Container '{}' stopped successfully via libcrun (SIGTERM)",
+                                id
+                            );
+                        }
+                        Err(e) => {
+                            return Err(ShimError::runtime_with_context(
+                                "libcrun failed to stop container",
+                                format!("Container ID: {}, Signal: SIGTERM", id),
+                            )
+                            .with_source(e));
+                        }
+                    }
+                }
+            }
+        }
+
+        // Not the fallback mode placeholder PID
+        let pid = state.info.pid.filter(|&pid| pid != std::process::id());
+        let timeout = timeout.unwrap_or_else(|| {
+            std::time::Duration::from_secs(
+                state
+                    .config
+                    .stop_timeout
+                    .unwrap_or(libcrun_shim_proto::DEFAULT_STOP_TIMEOUT_SECS),
+            )
+        });
+
+        self.lifecycle
+            .apply(id, &mut state.info.status, Transition::Stop)?;
+        state.info.pid = None;
+        state.info.last_exit_code = Some(128 + libc::SIGTERM);
+        state.info.last_exit_reason = Some(ExitReason::Signal);
+
+        Ok(pid.map(|pid| (pid, timeout)))
+    }
+
+    /// Wait up to `timeout` for the SIGTERM'd process `pid` of container `id`
+    /// to exit, then SIGKILL it, recording how it exited
+    #[cfg(target_os = "linux")]
+    async fn await_stop(&self, id: &str, pid: u32, timeout: std::time::Duration) -> Result<()> {
+        let deadline = std::time::Instant::now() + timeout;
+        let outcome = loop {
+            if let Some(status) = process_exit(pid) {
+                break status.map(|status| exit_outcome(status, false));
+            }
+            if std::time::Instant::now() >= deadline {
+                log::warn!(
+                    "Container '{}' did not stop within {:?}, sending SIGKILL",
+                    id,
+                    timeout
+                );
+                self.kill_process(id, pid, libc::SIGKILL)?;
+                // Reaped, so it does not linger as a zombie
+                let reap_deadline = std::time::Instant::now() + std::time::Duration::from_secs(1);
+                while process_exit(pid).is_none() && std::time::Instant::now() < reap_deadline {
+                    tokio::time::sleep(STOP_POLL_INTERVAL).await;
+                }
+                break Some((128 + libc::SIGKILL, ExitReason::Signal));
+            }
+            tokio::time::sleep(STOP_POLL_INTERVAL).await;
+        };
+        if let Some((code, reason)) = outcome {
+            if let Some(state) = self.containers.write().unwrap().get_mut(id) {
+                state.info.last_exit_code = Some(code);
+                state.info.last_exit_reason = Some(reason);
+            }
+        }
+        Ok(())
+    }
+
+    /// Send `signal` to the init process `pid` of container `id`, through
+    /// libcrun when the container has one
+    #[cfg(target_os = "linux")]
+    fn kill_process(&self, id: &str, pid: u32, signal: i32) -> Result<()> {
+        let containers = self.containers.read().unwrap();
+        if let (true, Some(container), Some(ctx)) = (
+            self.libcrun_available,
+            containers
+                .get(id)
+                .and_then(|s| s.libcrun_container.as_ref()),
+            self.libcrun_context.as_ref(),
+        ) {
+            return crun::container_kill(ctx.as_ptr(), container.as_ptr(), id, signal)
+                .map(|_| ())
+                .map_err(|e| {
+                    ShimError::runtime_with_context(
+                        "libcrun failed to kill container",
+                        format!("Container ID: {}, Signal: {}", id, signal),
+                    )
+                    .with_source(e)
+                });
+        }
+        unsafe {
+            libc::kill(pid as libc::pid_t, signal);
+        }
+        Ok(())
+    }
+
+    /// Resolve the namespaces a container joins to `/proc/<pid>/ns` paths
+    fn shared_namespace_paths(
+        &self,
+        config: &ContainerConfig,
+    ) -> Result<Vec<(&'static str, String)>> {
+        let Some(ref join) = config.join_namespaces else {
+            return Ok(vec![]);
+        };
+
+        let containers = self.containers.read().unwrap();
+        let owner = containers
+            .get(&join.container)
+            .ok_or_else(|| ShimError::not_found(format!("Container '{}'", join.container)))?;
+        let pid = match owner.info.pid {
+            Some(pid) if owner.info.status == ContainerStatus::Running => pid,
+            _ => {
+                return Err(ShimError::runtime_with_context(
+                    format!(
+                        "Container '{}' must be running to share its namespaces",
+                        join.container
+                    ),
+                    format!("Container ID: {}", config.id),
+                ))
+            }
+        };
+
+        let mut paths = Vec::new();
+        if join.network {
+            paths.push(("network", format!("/proc/{}/ns/net", pid)));
+        }
+        if join.ipc {
+            paths.push(("ipc", format!("/proc/{}/ns/ipc", pid)));
+        }
+        Ok(paths)
     }
 
     fn validate_config(config: &ContainerConfig) -> Result<()> {
@@ -353,6 +909,9 @@ impl LinuxRuntime {
             ));
         }
 
+        config.resources.validate()?;
+        config.network.validate()?;
+
         Ok(())
     }
 }
@@ -362,15 +921,24 @@ impl RuntimeImpl for LinuxRuntime {
         // Validate the configuration
         Self::validate_config(&config)?;
 
+        // Held until the container is tracked, so a concurrent create of the
+        // same ID cannot undo what this one makes
+        #[cfg(target_os = "linux")]
+        let mut guard = CreateGuard::new(self, &config.id)?;
+
         // Check if container already exists
         {
             let containers = self.containers.read().unwrap();
             if containers.contains_key(&config.id) {
-                return Err(ShimError::runtime_with_context(
+                return Err(ShimError::conflict(
                     format!("Container '{}' already exists", config.id),
                     "Use a different container ID or delete the existing container first",
                 ));
             }
+            let name = config.name.as_deref().unwrap_or(&config.id);
+            if name_taken(&containers, name, &config.id) {
+                return Err(name_in_use(name));
+            }
         }
 
         log::debug!(
@@ -379,11 +947,48 @@ impl RuntimeImpl for LinuxRuntime {
             config.rootfs.display()
         );
 
+        let shared_namespaces = self.shared_namespace_paths(&config)?;
+
+        // A read-only root needs no layer to write to
+        #[cfg(target_os = "linux")]
+        let mut config = config;
+        #[cfg(target_os = "linux")]
+        if self.libcrun_available && config.rootfs_snapshot && !config.read_only_rootfs {
+            let dir = PathBuf::from(SNAPSHOTS_DIR).join(&config.id);
+            crate::progress::report(
+                Operation::Create,
+                &config.id,
+                "snapshot",
+                None,
+                "Snapshotting the root filesystem",
+            );
+            // Made part way before failing, too
+            guard.snapshot = Some(dir.clone());
+            config.rootfs = crun::snapshot_rootfs(&config.rootfs, &dir).map_err(|e| {
+                ShimError::io_with_context(
+                    e,
+                    format!("Failed to snapshot the rootfs of '{}'", config.id),
+                )
+            })?;
+        }
+
+        // libcrun only fails at start on a missing working directory, and
+        // without saying which. A shared image is never written to
+        #[cfg(target_os = "linux")]
+        {
+            let shared = config.rootfs_snapshot && guard.snapshot.is_none();
+            let create = config.create_working_dir && !config.read_only_rootfs && !shared;
+            crun::prepare_working_dir(&config.rootfs, &config.working_dir, create)
+                .map_err(|e| working_dir_error(&config, e))?;
+        }
+
+        let mut oci_config = None;
+
         // Try to use libcrun if available
         #[cfg(target_os = "linux")]
         let libcrun_container = if self.libcrun_available {
             // Build OCI config JSON
-            let oci_json = match Self::build_oci_config_json(&config) {
+            let oci_json = match Self::build_oci_config_json(&config, &shared_namespaces) {
                 Ok(json) => {
                     log::debug!("Generated OCI config for container '{}'", config.id);
                     json
@@ -398,24 +1003,31 @@ impl RuntimeImpl for LinuxRuntime {
                 Ok(container) => {
                     // Create the container using libcrun
                     if let Some(ref ctx) = self.libcrun_context {
+                        // What libcrun leaves of a failed create is ours to
+                        // remove, unless the ID had state before
+                        guard.owns_state = crun::state_dir(&config.id).is_none();
                         match crun::container_create(ctx.as_ptr(), container, &config.id) {
                             Ok(_) => {
                                 log::info!(
                                     "Container '{}' created successfully via libcrun",
                                     config.id
                                 );
+                                oci_config = Some(oci_json);
+                                warn_unapplied_limits(&config.id, &config.resources);
+                                guard.container = Some(LibcrunContainerPtr::new(container));
                                 Some(LibcrunContainerPtr::new(container))
                             }
                             Err(e) => {
                                 crun::container_free(container);
                                 return Err(ShimError::runtime_with_context(
-                                    format!("libcrun failed to create container: {}", e.message),
+                                    "libcrun failed to create container",
                                     format!(
                                         "Container ID: {}, Rootfs: {}",
                                         config.id,
                                         config.rootfs.display()
                                     ),
-                                ));
+                                )
+                                .with_source(e));
                             }
                         }
                     } else {
@@ -440,21 +1052,42 @@ impl RuntimeImpl for LinuxRuntime {
         let container_id = config.id.clone();
         let info = ContainerInfo {
             id: container_id.clone(),
+            name: config.name.clone().unwrap_or_else(|| container_id.clone()),
             status: ContainerStatus::Created,
             pid: None,
+            exit_reason: None,
+            restart_count: 0,
+            last_exit_code: None,
+            last_exit_reason: None,
+            restart_policy: config.restart_policy,
+            restart_retries: 0,
+            ip_address: None,
+            oom_kills: 0,
+            labels: config.labels.clone(),
         };
 
         let state = ContainerState {
             config,
             info,
+            started_at: None,
+            created_at: unix_now(),
+            execs: Vec::new(),
+            execs_in_flight: 0,
+            oci_config,
             #[cfg(target_os = "linux")]
             libcrun_container,
         };
 
-        self.containers
-            .write()
-            .unwrap()
-            .insert(container_id.clone(), state);
+        {
+            let mut containers = self.containers.write().unwrap();
+            // Checked again, another create may have taken the name since
+            if name_taken(&containers, &state.info.name, &container_id) {
+                return Err(name_in_use(&state.info.name));
+            }
+            containers.insert(container_id.clone(), state);
+        }
+        #[cfg(target_os = "linux")]
+        guard.commit();
         Ok(container_id)
     }
 
@@ -466,34 +1099,35 @@ impl RuntimeImpl for LinuxRuntime {
             .get_mut(id)
             .ok_or_else(|| ShimError::not_found(format!("Container '{}'", id)))?;
 
-        // Check if container is in a valid state to start
-        match state.info.status {
-            ContainerStatus::Running => {
-                return Err(ShimError::runtime_with_context(
-                    format!("Container '{}' is already running", id),
-                    "Stop the container first if you want to restart it",
-                ));
-            }
-            ContainerStatus::Stopped => {
-                return Err(ShimError::runtime_with_context(
-                    format!("Container '{}' is stopped and cannot be restarted", id),
-                    "Delete the container and create a new one to restart",
-                ));
-            }
-            ContainerStatus::Created => {
-                // Valid state to start
-                log::debug!(
-                    "Container '{}' is in Created state, proceeding with start",
-                    id
-                );
-            }
-        }
+        self.lifecycle
+            .check(id, state.info.status, Transition::Start)?;
 
         // Try to start container via libcrun if available
         #[cfg(target_os = "linux")]
         if self.libcrun_available {
             if let Some(ref container) = state.libcrun_container {
                 if let Some(ref ctx) = self.libcrun_context {
+                    // The exited instance still holds the ID, the container
+                    // is created again from the same OCI configuration
+                    if state.info.status == ContainerStatus::Stopped {
+                        if let Err(e) = crun::container_delete(ctx.as_ptr(), container.as_ptr(), id)
+                        {
+                            log::debug!(
+                                "Failed to delete exited container '{}': {}",
+                                id,
+                                e.message
+                            );
+                        }
+                        crun::container_create(ctx.as_ptr(), container.as_ptr(), id).map_err(
+                            |e| {
+                                ShimError::runtime_with_context(
+                                    "libcrun failed to create the container again",
+                                    format!("Container ID: {}", id),
+                                )
+                                .with_source(e)
+                            },
+                        )?;
+                    }
                     match crun::container_start(ctx.as_ptr(), container.as_ptr(), id) {
                         Ok(_) => {
                             log::info!("Container '{}' started successfully via libcrun", id);
@@ -514,16 +1148,23 @@ impl RuntimeImpl for LinuxRuntime {
                         }
                         Err(e) => {
                             return Err(ShimError::runtime_with_context(
-                                format!("libcrun failed to start container: {}", e.message),
+                                "libcrun failed to start container",
                                 format!("Container ID: {}", id),
-                            ));
+                            )
+                            .with_source(e));
                         }
                     }
                 }
             }
         }
 
-        state.info.status = ContainerStatus::Running;
+        self.lifecycle
+            .apply(id, &mut state.info.status, Transition::Start)?;
+        state.info.oom_kills = 0;
+        if state.started_at.is_some() {
+            state.info.restart_count += 1;
+        }
+        state.started_at = Some(std::time::Instant::now());
         // If not using libcrun, use placeholder PID
         #[cfg(target_os = "linux")]
         if !self.libcrun_available || state.libcrun_container.is_none() {
@@ -538,72 +1179,30 @@ impl RuntimeImpl for LinuxRuntime {
         Ok(())
     }
 
-    async fn stop(&self, id: &str) -> Result<()> {
+    async fn stop(&self, id: &str, timeout: Option<std::time::Duration>) -> Result<()> {
         log::debug!("Stopping container: {}", id);
 
+        // The container is stopped already, so its process is waited for
+        // without holding the containers
+        if let Some((pid, timeout)) = self.terminate(id, timeout)? {
+            self.await_stop(id, pid, timeout).await?;
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, id: &str) -> Result<()> {
+        log::debug!("Deleting container: {}", id);
+
         let mut containers = self.containers.write().unwrap();
         let state = containers
-            .get_mut(id)
+            .get(id)
             .ok_or_else(|| ShimError::not_found(format!("Container '{}'", id)))?;
 
-        // Check if container is running
-        if state.info.status != ContainerStatus::Running {
-            return Err(ShimError::runtime_with_context(
-                format!("Container '{}' is not running", id),
-                format!(
-                    "Current status: {:?}. Only running containers can be stopped.",
-                    state.info.status
-                ),
-            ));
-        }
+        self.lifecycle
+            .check_delete(id, state.info.status, state.execs_in_flight)?;
+        let mut status = state.info.status;
 
-        // Try to stop container via libcrun if available
-        #[cfg(target_os = "linux")]
-        if self.libcrun_available {
-            if let Some(ref container) = state.libcrun_container {
-                if let Some(ref ctx) = self.libcrun_context {
-                    // Use SIGTERM to stop gracefully
-                    match crun::container_kill(ctx.as_ptr(), container.as_ptr(), id, libc::SIGTERM) {
-                        Ok(_) => {
-                            log::info!(
-                                "Container '{}' stopped successfully via libcrun (SIGTERM)",
-                                id
-                            );
-                        }
-                        Err(e) => {
-                            return Err(ShimError::runtime_with_context(
-                                format!("libcrun failed to stop container: {}", e.message),
-                                format!("Container ID: {}, Signal: SIGTERM", id),
-                            ));
-                        }
-                    }
-                }
-            }
-        }
-
-        state.info.status = ContainerStatus::Stopped;
-        state.info.pid = None;
-
-        Ok(())
-    }
-
-    async fn delete(&self, id: &str) -> Result<()> {
-        log::debug!("Deleting container: {}", id);
-
-        let mut containers = self.containers.write().unwrap();
-        let state = containers
-            .get(id)
-            .ok_or_else(|| ShimError::not_found(format!("Container '{}'", id)))?;
-
-        // Check if container is stopped
-        if state.info.status == ContainerStatus::Running {
-            return Err(ShimError::runtime_with_context(
-                format!("Cannot delete running container '{}'", id),
-                "Stop the container first using stop() before deleting it",
-            ));
-        }
-
-        // Try to delete container via libcrun if available
+        // Try to delete container via libcrun if available
         #[cfg(target_os = "linux")]
         if self.libcrun_available {
             if let Some(ref container) = state.libcrun_container {
@@ -623,11 +1222,21 @@ impl RuntimeImpl for LinuxRuntime {
             }
         }
 
+        #[cfg(target_os = "linux")]
+        if state.config.rootfs_snapshot {
+            if let Err(e) = crun::remove_snapshot(&PathBuf::from(SNAPSHOTS_DIR).join(id)) {
+                log::warn!("Failed to remove the rootfs snapshot of '{}': {}", id, e);
+            }
+        }
+
         containers.remove(id);
+        self.lifecycle.apply(id, &mut status, Transition::Delete)?;
         Ok(())
     }
 
     async fn list(&self) -> Result<Vec<ContainerInfo>> {
+        self.reap_exited();
+        self.enforce_deadlines();
         let containers = self.containers.read().unwrap();
         Ok(containers
             .values()
@@ -635,21 +1244,31 @@ impl RuntimeImpl for LinuxRuntime {
             .collect())
     }
 
+    async fn list_filtered(&self, filter: &ListFilter) -> Result<Vec<ContainerInfo>> {
+        let mut list = self.list().await?;
+        list.retain(|c| filter.matches(c));
+        Ok(list)
+    }
+
     async fn metrics(&self, id: &str) -> Result<ContainerMetrics> {
         let containers = self.containers.read().unwrap();
         let state = containers
             .get(id)
             .ok_or_else(|| ShimError::not_found(format!("Container '{}' not found", id)))?;
 
-        Ok(collect_container_metrics(id, state.info.pid))
+        let metrics = collect_container_metrics(id, state.info.pid);
+        self.record_metrics(std::slice::from_ref(&metrics));
+        Ok(metrics)
     }
 
     async fn all_metrics(&self) -> Result<Vec<ContainerMetrics>> {
         let containers = self.containers.read().unwrap();
-        Ok(containers
+        let metrics: Vec<ContainerMetrics> = containers
             .iter()
             .map(|(id, state)| collect_container_metrics(id, state.info.pid))
-            .collect())
+            .collect();
+        self.record_metrics(&metrics);
+        Ok(metrics)
     }
 
     async fn logs(&self, id: &str, options: LogOptions) -> Result<ContainerLogs> {
@@ -668,17 +1287,40 @@ impl RuntimeImpl for LinuxRuntime {
             .map(|d| d.as_secs())
             .unwrap_or(0);
 
-        let stdout = read_log_file(&stdout_path, options.tail, options.since);
-        let stderr = read_log_file(&stderr_path, options.tail, options.since);
+        let stdout = libcrun_shim_proto::logs::read_log(
+            &stdout_path,
+            options.tail,
+            options.stdout_offset,
+            options.max_bytes,
+        );
+        let stderr = libcrun_shim_proto::logs::read_log(
+            &stderr_path,
+            options.tail,
+            options.stderr_offset,
+            options.max_bytes,
+        );
 
         Ok(ContainerLogs {
             id: id.to_string(),
-            stdout,
-            stderr,
+            stdout: stdout.data,
+            stderr: stderr.data,
             timestamp,
+            stdout_offset: stdout.next_offset,
+            stderr_offset: stderr.next_offset,
+            truncated: stdout.truncated || stderr.truncated,
+            entries: vec![],
         })
     }
 
+    async fn follow_logs(
+        &self,
+        id: &str,
+        options: LogOptions,
+        on_logs: &mut (dyn FnMut(ContainerLogs) -> bool + Send),
+    ) -> Result<()> {
+        crate::poll_logs(self, id, options, on_logs).await
+    }
+
     async fn health(&self, id: &str) -> Result<HealthStatus> {
         let containers = self.containers.read().unwrap();
         let state = containers
@@ -689,7 +1331,7 @@ impl RuntimeImpl for LinuxRuntime {
         let health_state = match state.info.status {
             ContainerStatus::Running => HealthState::Healthy,
             ContainerStatus::Created => HealthState::Starting,
-            ContainerStatus::Stopped => HealthState::None,
+            ContainerStatus::Stopped | ContainerStatus::Paused => HealthState::None,
         };
 
         Ok(HealthStatus {
@@ -704,241 +1346,897 @@ impl RuntimeImpl for LinuxRuntime {
         })
     }
 
-    async fn exec(&self, id: &str, command: Vec<String>) -> Result<(i32, String, String)> {
+    async fn exec(
+        &self,
+        id: &str,
+        options: ExecOptions,
+        user: &str,
+    ) -> Result<(i32, String, String)> {
+        let _exec = ExecGuard::new(self, id)?;
+
+        // libcrun joins the container's namespaces and cgroup itself
+        #[cfg(target_os = "linux")]
+        if let (true, Some(ctx)) = (self.libcrun_available, &self.libcrun_context) {
+            let process = crun::ExecProcess {
+                args: options.command.clone(),
+                env: options.env,
+                cwd: options.working_dir,
+                uid: options.uid,
+                gid: options.gid,
+                tty: options.tty,
+            };
+            let started_at = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            let started = std::time::Instant::now();
+            let output = crun::container_exec(ctx.as_ptr(), id, &process);
+
+            let session = ExecSession {
+                command: options.command,
+                user: user.to_string(),
+                started_at,
+                duration_ms: started.elapsed().as_millis() as u64,
+                exit_code: output.as_ref().map(|o| o.exit_code).unwrap_or(-1),
+            };
+            if let Some(state) = self.containers.write().unwrap().get_mut(id) {
+                crate::execs::record(&mut state.execs, session);
+            }
+
+            let output = output.map_err(|e| {
+                ShimError::runtime_with_context(
+                    "libcrun failed to exec in container",
+                    format!("Container ID: {}", id),
+                )
+                .with_source(e)
+            })?;
+            return Ok((
+                output.exit_code,
+                String::from_utf8_lossy(&output.stdout).to_string(),
+                String::from_utf8_lossy(&output.stderr).to_string(),
+            ));
+        }
+
+        Err(ShimError::runtime_with_context(
+            "Exec requires libcrun",
+            "libcrun was not found when the runtime was built",
+        ))
+    }
+
+    async fn exec_stream(
+        &self,
+        id: &str,
+        options: ExecOptions,
+        user: &str,
+        stdio: ExecStdio,
+    ) -> Result<ExecOutcome> {
+        let _exec = ExecGuard::new(self, id)?;
+        if stdio.detach_keys.is_some() {
+            // The session ends with this process, which relays its I/O
+            return Err(ShimError::validation(
+                "detach_keys",
+                "Detaching from exec sessions is only supported on macOS",
+            ));
+        }
+
+        #[cfg(target_os = "linux")]
+        if let (true, Some(ctx)) = (self.libcrun_available, &self.libcrun_context) {
+            let process = crun::ExecProcess {
+                args: options.command.clone(),
+                env: options.env,
+                cwd: options.working_dir,
+                uid: options.uid,
+                gid: options.gid,
+                tty: options.tty,
+            };
+            let started_at = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            let started = std::time::Instant::now();
+            let terminal_size = stdio.terminal_size.or(Some((24, 80)));
+            let exit_code = crun::container_exec_spawn(ctx.as_ptr(), id, &process, terminal_size)
+                .and_then(|child| relay_exec(child, stdio));
+
+            let session = ExecSession {
+                command: options.command,
+                user: user.to_string(),
+                started_at,
+                duration_ms: started.elapsed().as_millis() as u64,
+                exit_code: *exit_code.as_ref().unwrap_or(&-1),
+            };
+            if let Some(state) = self.containers.write().unwrap().get_mut(id) {
+                crate::execs::record(&mut state.execs, session);
+            }
+
+            return exit_code.map(ExecOutcome::Exited).map_err(|e| {
+                ShimError::runtime_with_context(
+                    "libcrun failed to exec in container",
+                    format!("Container ID: {}", id),
+                )
+                .with_source(e)
+            });
+        }
+
+        Err(ShimError::runtime_with_context(
+            "Exec requires libcrun",
+            "libcrun was not found when the runtime was built",
+        ))
+    }
+
+    async fn rename(&self, id: &str, name: &str) -> Result<()> {
+        let mut containers = self.containers.write().unwrap();
+        if name_taken(&containers, name, id) {
+            return Err(name_in_use(name));
+        }
+        let state = containers
+            .get_mut(id)
+            .ok_or_else(|| ShimError::not_found(format!("Container '{}'", id)))?;
+        log::info!(
+            "Renaming container '{}' from '{}' to '{}'",
+            id,
+            state.info.name,
+            name
+        );
+        state.info.name = name.to_string();
+        state.config.name = Some(name.to_string());
+        Ok(())
+    }
+
+    async fn exec_sessions(&self, id: &str) -> Result<Vec<ExecSession>> {
         let containers = self.containers.read().unwrap();
         let state = containers
             .get(id)
             .ok_or_else(|| ShimError::not_found(format!("Container '{}' not found", id)))?;
+        Ok(state.execs.clone())
+    }
 
-        if state.info.status != ContainerStatus::Running {
+    async fn set_paused(&self, id: &str, pause: bool) -> Result<()> {
+        let (verb, transition) = if pause {
+            ("pause", Transition::Pause)
+        } else {
+            ("unpause", Transition::Unpause)
+        };
+        let mut containers = self.containers.write().unwrap();
+        let state = containers
+            .get_mut(id)
+            .ok_or_else(|| ShimError::not_found(format!("Container '{}'", id)))?;
+        self.lifecycle.check(id, state.info.status, transition)?;
+        let (Some(ctx), Some(_)) = (&self.libcrun_context, &state.libcrun_container) else {
             return Err(ShimError::runtime_with_context(
-                "Container is not running",
-                format!("Container '{}' must be running to execute commands", id),
+                "Pausing containers requires libcrun",
+                format!("Container '{}' was not created through libcrun", id),
             ));
-        }
+        };
 
-        // Execute command in container namespace using nsenter
-        #[cfg(target_os = "linux")]
-        if let Some(pid) = state.info.pid {
-            let output = std::process::Command::new("nsenter")
-                .args(&["-t", &pid.to_string(), "-m", "-u", "-i", "-n", "-p", "--"])
-                .args(&command)
-                .output()
-                .map_err(|e| {
-                    ShimError::runtime_with_context(
-                        format!("Failed to execute command: {}", e),
-                        "nsenter may not be available or container namespace inaccessible",
-                    )
-                })?;
+        let result = if pause {
+            crun::container_pause(ctx.as_ptr(), id)
+        } else {
+            crun::container_resume(ctx.as_ptr(), id)
+        };
+        result.map_err(|e| {
+            ShimError::runtime_with_context(
+                format!("libcrun failed to {} container", verb),
+                format!("Container ID: {}", id),
+            )
+            .with_source(e)
+        })?;
+        self.lifecycle
+            .apply(id, &mut state.info.status, transition)?;
+        log::info!("Container '{}' is now {}", id, state.info.status);
+        Ok(())
+    }
 
-            return Ok((
-                output.status.code().unwrap_or(-1),
-                String::from_utf8_lossy(&output.stdout).to_string(),
-                String::from_utf8_lossy(&output.stderr).to_string(),
+    async fn checkpoint(&self, id: &str, name: &str, leave_running: bool) -> Result<Checkpoint> {
+        checkpoints::validate(id, name)?;
+        let store = CheckpointStore::new(checkpoints::CHECKPOINTS_DIR);
+        let mut containers = self.containers.write().unwrap();
+        let state = containers
+            .get_mut(id)
+            .ok_or_else(|| ShimError::not_found(format!("Container '{}'", id)))?;
+        if state.info.status != ContainerStatus::Running {
+            return Err(ShimError::conflict(
+                format!("Container '{}' is not running", id),
+                "Only running containers can be checkpointed",
             ));
         }
+        if store.get(id, name).is_some() {
+            return Err(ShimError::conflict(
+                format!("Checkpoint '{}' of '{}' already exists", name, id),
+                "Remove it first or use a different name",
+            ));
+        }
+        let (Some(ctx), Some(config)) = (&self.libcrun_context, &state.oci_config) else {
+            return Err(ShimError::runtime_with_context(
+                "Checkpoints require libcrun",
+                format!("Container '{}' was not created through libcrun", id),
+            ));
+        };
 
-        Err(ShimError::runtime("Container PID not available"))
+        let options = crun::CheckpointOptions {
+            image_path: store.prepare(id, name)?,
+            leave_running,
+            ..Default::default()
+        };
+        let target = format!("{}/{}", id, name);
+        crate::progress::report(
+            Operation::Checkpoint,
+            &target,
+            "dump",
+            None,
+            "Dumping the container's processes",
+        );
+        let checkpoint = crun::container_checkpoint(ctx.as_ptr(), id, &options)
+            .map_err(|e| {
+                ShimError::runtime_with_context(
+                    "libcrun failed to checkpoint container",
+                    format!("Container ID: {}", id),
+                )
+                .with_source(e)
+            })
+            .and_then(|_| {
+                crate::progress::report(
+                    Operation::Checkpoint,
+                    &target,
+                    "record",
+                    None,
+                    "Recording the checkpoint image",
+                );
+                store.finish(id, name, config, unix_now())
+            });
+        if checkpoint.is_err() {
+            let _ = store.remove(id, name);
+        }
+        let checkpoint = checkpoint?;
+        log::info!("Checkpointed container '{}' as '{}'", id, name);
+
+        if !leave_running {
+            self.lifecycle
+                .apply(id, &mut state.info.status, Transition::Stop)?;
+            state.info.pid = None;
+            state.info.exit_reason = Some("checkpoint".to_string());
+        }
+        Ok(checkpoint)
     }
-}
 
-fn read_log_file(path: &str, tail: u32, _since: u64) -> String {
-    if let Ok(content) = std::fs::read_to_string(path) {
-        if tail > 0 {
-            let lines: Vec<&str> = content.lines().collect();
-            let start = lines.len().saturating_sub(tail as usize);
-            lines[start..].join("\n")
-        } else {
-            content
+    async fn checkpoints(&self, id: Option<&str>) -> Result<Vec<Checkpoint>> {
+        if let Some(id) = id {
+            checkpoints::validate_container(id)?;
         }
-    } else {
-        String::new()
+        Ok(CheckpointStore::new(checkpoints::CHECKPOINTS_DIR).list(id))
     }
-}
 
-/// Collect metrics for a container from cgroups
-fn collect_container_metrics(id: &str, pid: Option<u32>) -> ContainerMetrics {
-    let timestamp = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .map(|d| d.as_secs())
-        .unwrap_or(0);
+    async fn remove_checkpoint(&self, id: &str, name: &str) -> Result<()> {
+        checkpoints::validate(id, name)?;
+        let store = CheckpointStore::new(checkpoints::CHECKPOINTS_DIR);
+        if store.get(id, name).is_none() {
+            return Err(ShimError::not_found(format!(
+                "Checkpoint '{}' of '{}'",
+                name, id
+            )));
+        }
+        store.remove(id, name)
+    }
 
-    let mut metrics = ContainerMetrics {
-        id: id.to_string(),
-        timestamp,
-        ..Default::default()
-    };
+    async fn restore(&self, id: &str, name: &str, new_id: &str) -> Result<String> {
+        checkpoints::validate(id, name)?;
+        if new_id.is_empty() {
+            return Err(ShimError::validation("id", "Container ID cannot be empty"));
+        }
+        let store = CheckpointStore::new(checkpoints::CHECKPOINTS_DIR);
+        let checkpoint = store
+            .get(id, name)
+            .ok_or_else(|| ShimError::not_found(format!("Checkpoint '{}' of '{}'", name, id)))?;
+        let mut containers = self.containers.write().unwrap();
+        if containers.contains_key(new_id) || name_taken(&containers, new_id, new_id) {
+            return Err(ShimError::conflict(
+                format!("Container '{}' already exists", new_id),
+                "Restore into a different container ID or delete the existing container first",
+            ));
+        }
+        crate::progress::report(
+            Operation::Restore,
+            new_id,
+            "verify",
+            None,
+            "Verifying the checkpoint image",
+        );
+        let oci_json = store.verify(&checkpoint)?;
+        let oci: serde_json::Value = serde_json::from_str(&oci_json)?;
+        let Some(ctx) = &self.libcrun_context else {
+            return Err(ShimError::runtime_with_context(
+                "Checkpoints require libcrun",
+                "libcrun was not found when the runtime was built",
+            ));
+        };
 
-    #[cfg(target_os = "linux")]
-    if let Some(pid) = pid {
-        // Try cgroup v2 first, then v1
-        if let Some(cgroup_path) = find_cgroup_path(pid) {
-            metrics.cpu = read_cpu_metrics(&cgroup_path);
-            metrics.memory = read_memory_metrics(&cgroup_path);
-            metrics.blkio = read_blkio_metrics(&cgroup_path);
-            metrics.pids = read_pids_metrics(&cgroup_path);
+        let container = crun::container_load_from_memory(&oci_json).map_err(|e| {
+            ShimError::runtime("libcrun failed to load the checkpoint's config").with_source(e)
+        })?;
+        let options = crun::CheckpointOptions {
+            image_path: store.images_dir(id, name),
+            ..Default::default()
+        };
+        crate::progress::report(
+            Operation::Restore,
+            new_id,
+            "restore",
+            None,
+            "Restoring the container's processes",
+        );
+        if let Err(e) =
+            crun::container_restore(ctx.as_ptr(), new_id, &store.dir(id, name), &options)
+        {
+            crun::container_free(container);
+            return Err(ShimError::runtime_with_context(
+                "libcrun failed to restore container",
+                format!(
+                    "Checkpoint '{}' of '{}', container ID: {}",
+                    name, id, new_id
+                ),
+            )
+            .with_source(e));
         }
-        // Network metrics from /proc/net
-        metrics.network = read_network_metrics(pid);
+        log::info!(
+            "Restored checkpoint '{}' of '{}' as container '{}'",
+            name,
+            id,
+            new_id
+        );
+
+        let strings = |value: &serde_json::Value| -> Vec<String> {
+            serde_json::from_value(value.clone()).unwrap_or_default()
+        };
+        let config = ContainerConfig {
+            id: new_id.to_string(),
+            rootfs: PathBuf::from(oci["root"]["path"].as_str().unwrap_or_default()),
+            command: strings(&oci["process"]["args"]),
+            env: strings(&oci["process"]["env"]),
+            working_dir: oci["process"]["cwd"].as_str().unwrap_or("/").to_string(),
+            ..Default::default()
+        };
+        let info = ContainerInfo {
+            id: new_id.to_string(),
+            name: new_id.to_string(),
+            status: ContainerStatus::Running,
+            pid: crun::get_container_pid(new_id),
+            exit_reason: None,
+            restart_count: 0,
+            last_exit_code: None,
+            last_exit_reason: None,
+            restart_policy: RestartPolicy::No,
+            restart_retries: 0,
+            ip_address: None,
+            oom_kills: 0,
+            labels: Default::default(),
+        };
+        containers.insert(
+            new_id.to_string(),
+            ContainerState {
+                config,
+                info,
+                started_at: Some(std::time::Instant::now()),
+                created_at: unix_now(),
+                execs: Vec::new(),
+                execs_in_flight: 0,
+                oci_config: Some(oci_json),
+                libcrun_container: Some(LibcrunContainerPtr::new(container)),
+            },
+        );
+        Ok(new_id.to_string())
     }
 
-    metrics
-}
+    async fn export_checkpoint(
+        &self,
+        id: &str,
+        name: &str,
+        path: &std::path::Path,
+    ) -> Result<Checkpoint> {
+        checkpoints::validate(id, name)?;
+        let store = CheckpointStore::new(checkpoints::CHECKPOINTS_DIR);
+        let checkpoint = store
+            .get(id, name)
+            .ok_or_else(|| ShimError::not_found(format!("Checkpoint '{}' of '{}'", name, id)))?;
+        store.export(&checkpoint, path)?;
+        log::info!(
+            "Exported checkpoint '{}' of '{}' to {}",
+            name,
+            id,
+            path.display()
+        );
+        Ok(checkpoint)
+    }
 
-#[cfg(target_os = "linux")]
-fn find_cgroup_path(pid: u32) -> Option<String> {
-    let cgroup_file = format!("/proc/{}/cgroup", pid);
-    if let Ok(content) = std::fs::read_to_string(&cgroup_file) {
-        for line in content.lines() {
-            let parts: Vec<&str> = line.split(':').collect();
-            if parts.len() >= 3 {
-                let path = parts[2];
-                // cgroup v2
-                if parts[0] == "0" && parts[1].is_empty() {
-                    return Some(format!("/sys/fs/cgroup{}", path));
-                }
+    async fn import_checkpoint(&self, path: &std::path::Path) -> Result<Checkpoint> {
+        let checkpoint = CheckpointStore::new(checkpoints::CHECKPOINTS_DIR).import(path)?;
+        log::info!(
+            "Imported checkpoint '{}' of '{}' from {}",
+            checkpoint.name,
+            checkpoint.container,
+            path.display()
+        );
+        Ok(checkpoint)
+    }
+
+    async fn prune(&self, filter: &PruneFilter) -> Result<Vec<String>> {
+        let matching: Vec<String> = self
+            .containers
+            .read()
+            .unwrap()
+            .values()
+            .filter(|state| {
+                state.info.status == ContainerStatus::Stopped
+                    && filter.matches(state.created_at, &state.config.labels)
+            })
+            .map(|state| state.info.id.clone())
+            .collect();
+        let mut pruned = Vec::new();
+        for id in matching {
+            // Skips those that were started again in the meantime
+            if self.delete(&id).await.is_ok() {
+                pruned.push(id);
             }
         }
-        // Fallback for cgroup v1
-        for line in content.lines() {
-            let parts: Vec<&str> = line.split(':').collect();
-            if parts.len() >= 3 && parts[1].contains("memory") {
-                return Some(format!("/sys/fs/cgroup/memory{}", parts[2]));
+        Ok(pruned)
+    }
+
+    async fn stale_resources(&self, remove: bool) -> Result<Vec<StaleResource>> {
+        #[cfg(target_os = "linux")]
+        {
+            let mut tracked: std::collections::HashSet<String> =
+                self.containers.read().unwrap().keys().cloned().collect();
+            tracked.extend(self.creating.lock().unwrap().iter().cloned());
+            let mut stale = find_stale_resources(&tracked);
+            if remove {
+                stale.retain(|resource| match remove_stale_resource(resource) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        log::warn!(
+                            "Failed to remove {} {}: {}",
+                            resource.kind,
+                            resource.path,
+                            e
+                        );
+                        false
+                    }
+                });
             }
+            Ok(stale)
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = remove;
+            Ok(Vec::new())
         }
     }
-    None
-}
 
-#[cfg(target_os = "linux")]
-fn read_cpu_metrics(cgroup_path: &str) -> CpuMetrics {
-    let mut cpu = CpuMetrics::default();
+    async fn copy_to(
+        &self,
+        id: &str,
+        source: &std::path::Path,
+        destination: &str,
+        options: &CopyOptions,
+    ) -> Result<CopyStats> {
+        let (rootfs, read_only) = self.rootfs(id)?;
+        if read_only {
+            return Err(ShimError::conflict(
+                format!("Container '{}' has a read-only rootfs", id),
+                "Files can only be copied out of it",
+            ));
+        }
+        let options = copy::options_proto(options);
+        let context = |e| {
+            ShimError::io_with_context(
+                e,
+                format!("Copying {} to {}:{}", source.display(), id, destination),
+            )
+        };
+        let target = archive::Target::resolve(&rootfs, std::path::Path::new(destination))
+            .map_err(context)?;
+        let host = std::path::Path::new("/");
+        let total = archive::measure(host, source, &options).map_err(context)?;
+        let mut progress = copy::Progress::new(format!("{}:{}", id, destination), total);
+        let stats = copy::transfer(host, source, &target, &options, |done| {
+            progress.update(done)
+        })
+        .map_err(context)?;
+        log::info!("Copied {} to '{}' at {}", source.display(), id, destination);
+        Ok(copy::proto_to_stats(stats))
+    }
 
-    // cgroup v2: cpu.stat
-    if let Ok(content) = std::fs::read_to_string(format!("{}/cpu.stat", cgroup_path)) {
-        for line in content.lines() {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() >= 2 {
-                let value = parts[1].parse().unwrap_or(0);
-                match parts[0] {
-                    "usage_usec" => cpu.usage_total = value * 1000,
-                    "user_usec" => cpu.usage_user = value * 1000,
-                    "system_usec" => cpu.usage_system = value * 1000,
-                    "nr_throttled" => cpu.throttled_periods = value,
-                    "throttled_usec" => cpu.throttled_time = value * 1000,
-                    _ => {}
+    async fn copy_from(
+        &self,
+        id: &str,
+        source: &str,
+        destination: &std::path::Path,
+        options: &CopyOptions,
+    ) -> Result<CopyStats> {
+        let (rootfs, _) = self.rootfs(id)?;
+        let options = copy::options_proto(options);
+        let context = |e| {
+            ShimError::io_with_context(
+                e,
+                format!("Copying {}:{} to {}", id, source, destination.display()),
+            )
+        };
+        let target =
+            archive::Target::resolve(std::path::Path::new("/"), destination).map_err(context)?;
+        let source = std::path::Path::new(source);
+        let total = archive::measure(&rootfs, source, &options).map_err(context)?;
+        let mut progress = copy::Progress::new(format!("{}:{}", id, source.display()), total);
+        let stats = copy::transfer(&rootfs, source, &target, &options, |done| {
+            progress.update(done)
+        })
+        .map_err(context)?;
+        log::info!(
+            "Copied {} of '{}' to {}",
+            source.display(),
+            id,
+            destination.display()
+        );
+        Ok(copy::proto_to_stats(stats))
+    }
+
+    async fn available_resources(&self, id: &str) -> Result<(Option<u64>, Option<u64>)> {
+        let rootfs = self
+            .containers
+            .read()
+            .unwrap()
+            .get(id)
+            .map(|state| state.config.rootfs.clone());
+        let memory = std::fs::read_to_string("/proc/meminfo")
+            .ok()
+            .and_then(|meminfo| meminfo_bytes(&meminfo, "MemAvailable"));
+        Ok((
+            memory,
+            rootfs.and_then(|rootfs| crate::disk::free_bytes(&rootfs)),
+        ))
+    }
+
+    async fn wait(&self, id: &str) -> Result<ExitStatus> {
+        loop {
+            self.reap_exited();
+            self.enforce_deadlines();
+            let pid = {
+                let containers = self.containers.read().unwrap();
+                let state = containers
+                    .get(id)
+                    .ok_or_else(|| ShimError::not_found(format!("Container '{}' not found", id)))?;
+                if state.info.status == ContainerStatus::Stopped {
+                    return Ok(ExitStatus {
+                        code: state.info.last_exit_code,
+                        reason: state.info.last_exit_reason,
+                    });
+                }
+                // Not the fallback mode placeholder PID
+                state.info.pid.filter(|&pid| pid != std::process::id())
+            };
+            // Woken as soon as the process exits, where the kernel has pidfds
+            let pidfd = pid
+                .and_then(|pid| crun::pidfd_open(pid).ok())
+                .and_then(|pidfd| tokio::io::unix::AsyncFd::new(pidfd).ok());
+            match pidfd {
+                Some(pidfd) => {
+                    let _ = tokio::time::timeout(WAIT_CHECK_INTERVAL, pidfd.readable()).await;
                 }
+                None => tokio::time::sleep(WAIT_POLL_INTERVAL).await,
             }
         }
     }
 
-    // cgroup v1 fallback
-    if cpu.usage_total == 0 {
-        if let Ok(content) = std::fs::read_to_string(format!("{}/cpuacct.usage", cgroup_path)) {
-            cpu.usage_total = content.trim().parse().unwrap_or(0);
-        }
+    async fn listening_ports(&self, id: &str) -> Result<Vec<u16>> {
+        let pid = {
+            let containers = self.containers.read().unwrap();
+            let state = containers
+                .get(id)
+                .ok_or_else(|| ShimError::not_found(format!("Container '{}'", id)))?;
+            match (state.info.status, state.info.pid) {
+                (ContainerStatus::Running, Some(pid)) => pid,
+                (status, _) => {
+                    return Err(ShimError::conflict(
+                        format!("Container '{}' is not running", id),
+                        format!("Current status: {:?}", status),
+                    ))
+                }
+            }
+        };
+        crun::listening_ports(pid).map_err(|e| {
+            ShimError::io_with_context(e, format!("Failed to read sockets of '{}'", id))
+        })
     }
 
-    cpu
+    async fn total_resources(&self) -> Result<Reservation> {
+        let cpus = std::thread::available_parallelism()
+            .map_err(|e| ShimError::io_with_context(e, "Failed to count CPUs"))?
+            .get();
+        let meminfo = std::fs::read_to_string("/proc/meminfo")
+            .map_err(|e| ShimError::io_with_context(e, "Failed to read /proc/meminfo"))?;
+        let memory = meminfo_bytes(&meminfo, "MemTotal")
+            .ok_or_else(|| ShimError::runtime("No MemTotal in /proc/meminfo"))?;
+        Ok(Reservation {
+            cpus: cpus as f64,
+            memory,
+        })
+    }
 }
 
+/// Copy an exec's I/O to and from `stdio` until it exits, and return its
+/// exit code
+///
+/// Input is copied by a thread of its own, which is left blocked on
+/// `stdio.stdin` if the command exits first.
 #[cfg(target_os = "linux")]
-fn read_memory_metrics(cgroup_path: &str) -> MemoryMetrics {
-    let mut mem = MemoryMetrics::default();
-
-    // cgroup v2
-    if let Ok(content) = std::fs::read_to_string(format!("{}/memory.current", cgroup_path)) {
-        mem.usage = content.trim().parse().unwrap_or(0);
-    }
-    if let Ok(content) = std::fs::read_to_string(format!("{}/memory.max", cgroup_path)) {
-        mem.limit = content.trim().parse().unwrap_or(u64::MAX);
+fn relay_exec(
+    mut child: crun::ExecChild,
+    stdio: ExecStdio,
+) -> std::result::Result<i32, crun::CrunError> {
+    use std::io::{Read, Write};
+
+    let ExecStdio {
+        mut stdin,
+        mut stdout,
+        mut stderr,
+        resize,
+        stdin_once,
+        ..
+    } = stdio;
+
+    // Without a separate error output, the command runs under a terminal
+    let tty = child.stderr.is_none();
+    if let Some(mut input) = child.stdin.take() {
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 16 * 1024];
+            let mut mid_line = false;
+            loop {
+                match stdin.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        if input.write_all(&buf[..n]).is_err() {
+                            return;
+                        }
+                        mid_line = buf[n - 1] != b'\n';
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                    Err(_) => break,
+                }
+            }
+            // A terminal only ends input with ^D, as for a local one
+            if tty && stdin_once {
+                let _ = crun::end_terminal_input(&input, mid_line);
+            }
+        });
     }
-    if let Ok(content) = std::fs::read_to_string(format!("{}/memory.peak", cgroup_path)) {
-        mem.max_usage = content.trim().parse().unwrap_or(0);
+    if let (None, Some(resize)) = (&child.stderr, resize) {
+        if let Ok(terminal) = child.stdout.try_clone() {
+            std::thread::spawn(move || {
+                for (rows, cols) in resize {
+                    let _ = crun::resize_terminal(&terminal, rows, cols);
+                }
+            });
+        }
     }
-    if let Ok(content) = std::fs::read_to_string(format!("{}/memory.swap.current", cgroup_path)) {
-        mem.swap = content.trim().parse().unwrap_or(0);
+    let errors = child
+        .stderr
+        .take()
+        .map(|mut output| std::thread::spawn(move || copy_output(&mut output, &mut stderr)));
+    copy_output(&mut child.stdout, &mut stdout);
+    if let Some(errors) = errors {
+        let _ = errors.join();
     }
-    if let Ok(content) = std::fs::read_to_string(format!("{}/memory.stat", cgroup_path)) {
-        for line in content.lines() {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() >= 2 {
-                match parts[0] {
-                    "file" | "cache" => mem.cache = parts[1].parse().unwrap_or(0),
-                    "anon" => mem.rss = parts[1].parse().unwrap_or(0),
-                    _ => {}
+    child.wait()
+}
+
+/// Copy output as it comes, flushing each chunk so prompts show up; a
+/// terminal reports its end as an error
+#[cfg(target_os = "linux")]
+fn copy_output(output: &mut impl std::io::Read, to: &mut impl std::io::Write) {
+    let mut buf = [0u8; 16 * 1024];
+    loop {
+        match output.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                if to.write_all(&buf[..n]).and_then(|_| to.flush()).is_err() {
+                    break;
                 }
             }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(_) => break,
         }
     }
+}
 
-    // cgroup v1 fallback
-    if mem.usage == 0 {
-        if let Ok(content) =
-            std::fs::read_to_string(format!("{}/memory.usage_in_bytes", cgroup_path))
-        {
-            mem.usage = content.trim().parse().unwrap_or(0);
+/// A size from /proc/meminfo, such as `MemAvailable` (memory available
+/// without swapping), in bytes
+fn meminfo_bytes(meminfo: &str, field: &str) -> Option<u64> {
+    meminfo.lines().find_map(|line| {
+        let kib = line.strip_prefix(field)?.strip_prefix(':')?;
+        let kib: u64 = kib.trim().trim_end_matches("kB").trim().parse().ok()?;
+        Some(kib * 1024)
+    })
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// What libcrun and snapshots hold for containers not in `tracked`, leaving
+/// out those whose process is still running
+#[cfg(target_os = "linux")]
+fn find_stale_resources(tracked: &std::collections::HashSet<String>) -> Vec<StaleResource> {
+    let stale = |id: &String| {
+        !tracked.contains(id) && !crun::read_state(id).is_some_and(|state| state.running)
+    };
+    let mut resources = Vec::new();
+    for id in crun::state_ids().into_iter().filter(stale) {
+        // The cgroup goes first, the state directory is how it is found
+        for dir in crun::cgroup_dirs(&id) {
+            resources.push(StaleResource {
+                kind: StaleResourceKind::Cgroup,
+                id: id.clone(),
+                path: dir.display().to_string(),
+            });
         }
-        if let Ok(content) =
-            std::fs::read_to_string(format!("{}/memory.limit_in_bytes", cgroup_path))
-        {
-            mem.limit = content.trim().parse().unwrap_or(u64::MAX);
+        if let Some(dir) = crun::state_dir(&id) {
+            resources.push(StaleResource {
+                kind: StaleResourceKind::State,
+                id,
+                path: dir.display().to_string(),
+            });
         }
-        if let Ok(content) =
-            std::fs::read_to_string(format!("{}/memory.max_usage_in_bytes", cgroup_path))
-        {
-            mem.max_usage = content.trim().parse().unwrap_or(0);
+    }
+    let snapshots = std::fs::read_dir(SNAPSHOTS_DIR)
+        .into_iter()
+        .flatten()
+        .flatten();
+    for entry in snapshots {
+        let Ok(id) = entry.file_name().into_string() else {
+            continue;
+        };
+        if stale(&id) {
+            resources.push(StaleResource {
+                kind: StaleResourceKind::Snapshot,
+                id,
+                path: entry.path().display().to_string(),
+            });
         }
     }
+    resources
+}
 
-    if mem.limit > 0 && mem.limit != u64::MAX {
-        mem.usage_percent = (mem.usage as f64 / mem.limit as f64) * 100.0;
+#[cfg(target_os = "linux")]
+fn remove_stale_resource(resource: &StaleResource) -> std::io::Result<()> {
+    match resource.kind {
+        StaleResourceKind::State => crun::remove_state(&resource.id),
+        StaleResourceKind::Cgroup => std::fs::remove_dir(&resource.path),
+        StaleResourceKind::Snapshot => crun::remove_snapshot(std::path::Path::new(&resource.path)),
+        // Containers on Linux get no bridge
+        StaleResourceKind::Interface => Ok(()),
     }
-
-    mem
 }
 
-#[cfg(target_os = "linux")]
-fn read_blkio_metrics(cgroup_path: &str) -> BlkioMetrics {
-    let mut blkio = BlkioMetrics::default();
-
-    // cgroup v2: io.stat
-    if let Ok(content) = std::fs::read_to_string(format!("{}/io.stat", cgroup_path)) {
-        for line in content.lines() {
-            for part in line.split_whitespace() {
-                if let Some(value) = part.strip_prefix("rbytes=") {
-                    blkio.read_bytes += value.parse::<u64>().unwrap_or(0);
-                } else if let Some(value) = part.strip_prefix("wbytes=") {
-                    blkio.write_bytes += value.parse::<u64>().unwrap_or(0);
-                } else if let Some(value) = part.strip_prefix("rios=") {
-                    blkio.read_ops += value.parse::<u64>().unwrap_or(0);
-                } else if let Some(value) = part.strip_prefix("wios=") {
-                    blkio.write_ops += value.parse::<u64>().unwrap_or(0);
-                }
-            }
+/// Collect metrics for a container from cgroups
+fn collect_container_metrics(id: &str, pid: Option<u32>) -> ContainerMetrics {
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    let mut metrics = ContainerMetricsProto {
+        id: id.to_string(),
+        timestamp,
+        ..Default::default()
+    };
+
+    #[cfg(target_os = "linux")]
+    if let Some(pid) = pid {
+        if let Some(cgroup) = CgroupPaths::find(pid) {
+            metrics.cpu = cgroup::read_cpu_metrics(&cgroup);
+            metrics.memory = cgroup::read_memory_metrics(&cgroup);
+            metrics.blkio = cgroup::read_blkio_metrics(&cgroup);
+            metrics.pids = cgroup::read_pids_metrics(&cgroup);
         }
+        // Network metrics from /proc/net
+        metrics.network = read_network_metrics(pid);
     }
 
-    blkio
+    crate::metrics::proto_to_metrics(metrics)
 }
 
+/// Longest `wait` sleeps on a process before looking at its container
+/// again, e.g. to enforce its deadline
+const WAIT_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// How often `wait` checks a container without a process to watch
+const WAIT_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(200);
+
+/// How often a stopping container's process is checked for having exited
 #[cfg(target_os = "linux")]
-fn read_pids_metrics(cgroup_path: &str) -> PidsMetrics {
-    let mut pids = PidsMetrics::default();
+const STOP_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
 
-    if let Ok(content) = std::fs::read_to_string(format!("{}/pids.current", cgroup_path)) {
-        pids.current = content.trim().parse().unwrap_or(0);
+/// Capabilities granted when neither the container nor its profile sets any
+const DEFAULT_CAPABILITIES: &[&str] = &["CAP_AUDIT_WRITE", "CAP_KILL", "CAP_NET_BIND_SERVICE"];
+
+/// Accept capability names with or without the CAP_ prefix, in any case
+fn normalize_capability(name: &str) -> String {
+    let name = name.to_uppercase();
+    if name.starts_with("CAP_") {
+        name
+    } else {
+        format!("CAP_{}", name)
+    }
+}
+
+/// Why the working directory of a container cannot be used
+#[cfg(target_os = "linux")]
+fn working_dir_error(config: &ContainerConfig, e: std::io::Error) -> ShimError {
+    let reason = match e.kind() {
+        std::io::ErrorKind::NotFound => "does not exist".to_string(),
+        _ => format!("cannot be used ({})", e),
+    };
+    ShimError::validation(
+        "working_dir",
+        format!(
+            "Working directory '{}' {} in the root filesystem {}",
+            config.working_dir,
+            reason,
+            config.rootfs.display()
+        ),
+    )
+}
+
+/// The wait status of a process that has ended, `Some(None)` if it ended
+/// but is not a child of this process, so its status is unknown
+#[cfg(target_os = "linux")]
+fn process_exit(pid: u32) -> Option<Option<libc::c_int>> {
+    let mut status = 0;
+    match unsafe { libc::waitpid(pid as libc::pid_t, &mut status, libc::WNOHANG) } {
+        0 => None,
+        reaped if reaped > 0 => Some(Some(status)),
+        _ => {
+            let alive = unsafe { libc::kill(pid as libc::pid_t, 0) == 0 };
+            (!alive).then_some(None)
+        }
     }
-    if let Ok(content) = std::fs::read_to_string(format!("{}/pids.max", cgroup_path)) {
-        pids.limit = content.trim().parse().unwrap_or(0);
+}
+
+/// Check the limits of a created container against its cgroup
+#[cfg(target_os = "linux")]
+fn warn_unapplied_limits(id: &str, resources: &ResourceLimits) {
+    let Some(cgroup) = crun::get_container_pid(id).and_then(CgroupPaths::find) else {
+        return;
+    };
+    let limits = cgroup_limits(
+        matches!(cgroup, CgroupPaths::Unified(_)),
+        unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64,
+        resources.memory,
+        resources.pids,
+        resources.cpu,
+        resources.blkio_weight,
+    );
+    for problem in unapplied_limits(&cgroup, &limits) {
+        log::warn!("Limit of container '{}' is not applied: {}", id, problem);
     }
+}
 
-    pids
+/// PIDs of the processes in the cgroup of process `pid`, i.e. in its container
+#[cfg(target_os = "linux")]
+pub(crate) fn cgroup_pids(pid: u32) -> Option<Vec<u32>> {
+    let procs = CgroupPaths::find(pid)?.read("pids", "cgroup.procs")?;
+    Some(
+        procs
+            .lines()
+            .filter_map(|line| line.trim().parse().ok())
+            .collect(),
+    )
 }
 
 #[cfg(target_os = "linux")]
-fn read_network_metrics(pid: u32) -> NetworkMetrics {
-    let mut net = NetworkMetrics::default();
+fn read_network_metrics(pid: u32) -> NetworkMetricsProto {
+    let mut net = NetworkMetricsProto::default();
 
     let net_dev = format!("/proc/{}/net/dev", pid);
     if let Ok(content) = std::fs::read_to_string(&net_dev) {
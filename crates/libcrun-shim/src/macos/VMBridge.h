@@ -44,6 +44,9 @@ bool vm_bridge_create_vm_full(
 void vm_bridge_start_vm(VMBridgeHandle handle, VMCompletionCallback callback);
 void vm_bridge_stop_vm(VMBridgeHandle handle, VMCompletionCallback callback);
 
+// Share a host directory with the running VM under `name`
+bool vm_bridge_add_share(VMBridgeHandle handle, const char* name, const char* path, bool read_only);
+
 // Network interface listing
 typedef void (*NetworkInterfaceCallback)(const char* interfaces);
 void vm_bridge_list_network_interfaces(NetworkInterfaceCallback callback);
@@ -29,6 +29,11 @@ public struct VMNetworkConfig {
     private var virtualMachine: VZVirtualMachine?
     private var completionHandler: ((Bool, String?) -> Void)?
     private var diskAttachments: [VZDiskImageStorageDeviceAttachment] = []
+    private var sharedDirectories: [String: VZSharedDirectory] = [:]
+
+    /// Virtiofs tag for directories shared at runtime; must match
+    /// `libcrun_shim_proto::SHARES_TAG`
+    static let sharesTag = "libcrun-shares"
 
     /// Create a Linux VM with the specified configuration (legacy method)
     @objc public func createVMWithKernelPath(_ kernelPath: String, initramfsPath: String, memoryBytes: UInt64, cpuCount: UInt32) -> Bool {
@@ -88,6 +93,11 @@ public struct VMNetworkConfig {
             }
             config.storageDevices = storageDevices
 
+            // Directories shared with running containers, added by addShare
+            let sharesDevice = VZVirtioFileSystemDeviceConfiguration(tag: VMBridge.sharesTag)
+            sharesDevice.share = VZMultipleDirectoryShare(directories: [:])
+            config.directorySharingDevices = [sharesDevice]
+
             // Configure network
             if let networkDevice = createNetworkDevice(mode: networkMode, bridgeInterface: bridgeInterface) {
                 config.networkDevices = [networkDevice]
@@ -340,6 +350,26 @@ public struct VMNetworkConfig {
         return vm.canStop
     }
 
+    /// Share a host directory with the running VM as `name` on the shares device
+    @objc public func addShare(_ name: String, path: String, readOnly: Bool) -> Bool {
+        guard #available(macOS 13.0, *) else {
+            print("Changing shared directories at runtime needs macOS 13")
+            return false
+        }
+        guard let vm = virtualMachine,
+              let device = vm.directorySharingDevices.first(where: {
+                  ($0 as? VZVirtioFileSystemDevice)?.tag == VMBridge.sharesTag
+              }) as? VZVirtioFileSystemDevice else {
+            print("No shares device available")
+            return false
+        }
+
+        sharedDirectories[name] = VZSharedDirectory(url: URL(fileURLWithPath: path), readOnly: readOnly)
+        device.share = VZMultipleDirectoryShare(directories: sharedDirectories)
+        print("Shared \(path) as \(name)")
+        return true
+    }
+
     /// Get vsock device for communication
     @objc public func getVsockDevice() -> VZVirtioSocketDevice? {
         guard let vm = virtualMachine else {
@@ -529,6 +559,15 @@ public func vm_bridge_create_vm_full(
     )
 }
 
+/// Share a host directory with the running VM
+@available(macOS 12.0, *)
+@_cdecl("vm_bridge_add_share")
+public func vm_bridge_add_share(_ handle: UnsafeMutableRawPointer?, _ name: UnsafePointer<CChar>, _ path: UnsafePointer<CChar>, _ readOnly: Bool) -> Bool {
+    guard let handle = handle else { return false }
+    let bridge = Unmanaged<VMBridge>.fromOpaque(handle).takeUnretainedValue()
+    return bridge.addShare(String(cString: name), path: String(cString: path), readOnly: readOnly)
+}
+
 /// Get list of available network interfaces for bridged mode
 @available(macOS 12.0, *)
 @_cdecl("vm_bridge_list_network_interfaces")
@@ -1,17 +1,77 @@
 pub mod rpc;
-mod vm;
+pub(crate) mod vm;
 mod vsock;
 
-use crate::types::RuntimeConfig;
+use crate::compat;
+use crate::types::{ClockSync, RuntimeConfig, RuntimeStatus, TimeSyncPolicy, Transport};
 use crate::*;
 use libcrun_shim_proto::*;
 
-pub struct MacOsRuntime {
-    #[allow(dead_code)]
+/// Guest directory the agent keeps image blobs and checkpoints in, whose
+/// filesystem is checked for free space before a container starts
+const GUEST_STORAGE_DIR: &str = "/var/lib/libcrun-shim";
+
+/// A VM booted by the runtime and the agent connection made at boot
+struct Guest {
     vm: vm::VirtualMachine,
     #[allow(dead_code)]
     rpc: rpc::RpcClient,
+    /// Transport the agent was last reached over
+    transport: Transport,
+}
+
+impl Guest {
+    /// Connect to the agent, over the transport that worked last if it still
+    /// does, or the next one in the configured order
+    fn connect(&mut self, config: &RuntimeConfig) -> Result<rpc::RpcClient> {
+        let order = config.transport.order_from(Some(self.transport));
+        let (rpc, transport) = connect_transport(&self.vm, config, &order)?;
+        if transport != self.transport {
+            log::info!(
+                "Agent connection switched from {} to {}",
+                self.transport,
+                transport
+            );
+            self.transport = transport;
+        }
+        Ok(rpc)
+    }
+}
+
+/// VM lifecycle state, shared with the idle, time sync and event monitors
+struct VmState {
+    /// The running VM, or `None` after an idle shutdown
+    guest: tokio::sync::Mutex<Option<Guest>>,
+    /// Last request to the agent, or last time it had containers
+    last_active: std::sync::Mutex<std::time::Instant>,
+    /// Outcome of the last guest clock sync
+    clock: std::sync::Mutex<Option<ClockSync>>,
+    /// The agent's events are being re-broadcast, so they need not be
+    /// inferred from lists and health checks
+    events_streaming: std::sync::atomic::AtomicBool,
+}
+
+impl VmState {
+    fn touch(&self) {
+        *self.last_active.lock().unwrap() = std::time::Instant::now();
+    }
+
+    fn idle_for(&self) -> std::time::Duration {
+        self.last_active.lock().unwrap().elapsed()
+    }
+}
+
+pub struct MacOsRuntime {
+    vm: std::sync::Arc<VmState>,
     config: RuntimeConfig,
+    /// Agent version and features negotiated at connect time
+    agent: std::sync::RwLock<AgentInfo>,
+    /// Containers whose agent-initiated stop has already been reported as an event
+    reported_exits: std::sync::Mutex<std::collections::HashSet<String>>,
+    /// Last health state seen per container, to report changes as events
+    reported_health: std::sync::Mutex<std::collections::HashMap<String, HealthState>>,
+    /// Switch every agent connection to [`Role::ReadOnly`]
+    read_only: bool,
 }
 
 impl MacOsRuntime {
@@ -22,108 +82,971 @@ impl MacOsRuntime {
 
     /// Create a new runtime with custom configuration
     pub async fn new_with_config(config: RuntimeConfig) -> Result<Self> {
+        Self::open(config, false).await
+    }
+
+    /// Create a runtime whose agent connections can only inspect containers
+    pub async fn new_read_only_with_config(config: RuntimeConfig) -> Result<Self> {
+        Self::open(config, true).await
+    }
+
+    async fn open(config: RuntimeConfig, read_only: bool) -> Result<Self> {
         log::info!("Starting MacOsRuntime with configuration:");
         log::info!("  Socket path: {}", config.socket_path.display());
         log::info!("  Vsock port: {}", config.vsock_port);
+        log::info!(
+            "  Transports: {}",
+            config
+                .transport
+                .order
+                .iter()
+                .map(Transport::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
         log::info!("  Connection timeout: {}s", config.connection_timeout);
         if !config.vm_asset_paths.is_empty() {
             log::info!("  Custom VM asset paths: {:?}", config.vm_asset_paths);
         }
 
-        let vm = vm::VirtualMachine::start_with_config(config.clone()).await?;
+        let (guest, agent) = boot(&config).await?;
+        let owns_vm = guest.vm.has_vm_control();
+        let vm = std::sync::Arc::new(VmState {
+            guest: tokio::sync::Mutex::new(Some(guest)),
+            last_active: std::sync::Mutex::new(std::time::Instant::now()),
+            clock: std::sync::Mutex::new(agent.clock.clone()),
+            events_streaming: std::sync::atomic::AtomicBool::new(false),
+        });
 
-        #[cfg(target_os = "macos")]
-        {
-            if vm.has_vm_control() {
-                log::info!("VM started via Swift bridge - waiting for guest to boot...");
-                // Kernel boot + initramfs + agent startup typically takes 15-20s
-                tokio::time::sleep(tokio::time::Duration::from_secs(20)).await;
-                log::info!("Boot wait complete, attempting to connect to agent");
+        if config.idle_policy.is_enabled() {
+            if read_only {
+                log::info!("Idle shutdown disabled: the runtime is read-only");
+            } else if owns_vm {
+                log::info!(
+                    "Idle shutdown after {}s without containers",
+                    config.idle_policy.shutdown_after_secs
+                );
+                tokio::spawn(idle_monitor(std::sync::Arc::downgrade(&vm), config.clone()));
             } else {
-                log::info!("Using fallback mode - assuming external VM is running");
-                tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+                log::info!("Idle shutdown disabled: the VM is managed externally");
+            }
+        }
+
+        if config.time_sync.is_enabled() && !read_only && agent.supports(features::TIME_SYNC) {
+            log::info!(
+                "Syncing the guest clock every {}s",
+                config.time_sync.interval_secs
+            );
+            tokio::spawn(time_sync_monitor(
+                std::sync::Arc::downgrade(&vm),
+                config.clone(),
+            ));
+        }
+
+        if agent.supports(features::EVENTS) {
+            tokio::spawn(event_monitor(
+                std::sync::Arc::downgrade(&vm),
+                config.clone(),
+            ));
+        }
+
+        Ok(Self {
+            vm,
+            config,
+            agent: std::sync::RwLock::new(agent),
+            reported_exits: Default::default(),
+            reported_health: Default::default(),
+            read_only,
+        })
+    }
+
+    /// Get the runtime configuration
+    pub fn config(&self) -> &RuntimeConfig {
+        &self.config
+    }
+
+    /// What the runtime is connected to
+    pub async fn status(&self) -> RuntimeStatus {
+        let guest = self.vm.guest.lock().await;
+        RuntimeStatus {
+            vm_running: guest.is_some(),
+            vm_managed: guest.as_ref().is_some_and(|g| g.vm.has_vm_control()),
+            transport: guest.as_ref().map(|g| g.transport),
+            read_only: self.read_only,
+        }
+    }
+
+    /// Send a request whose phases are reported as `operation` on `target`,
+    /// if the agent reports them
+    async fn call_reporting(
+        &self,
+        operation: Operation,
+        target: &str,
+        request: ProgressRequest,
+    ) -> Result<Response> {
+        let mut rpc = self.connect().await?;
+        if !self.agent.read().unwrap().supports(features::PROGRESS) {
+            return rpc.call(request.into());
+        }
+        rpc.call_with_progress(request, |progress| {
+            crate::progress::report(
+                operation,
+                target,
+                &progress.phase,
+                progress.percent,
+                progress.message,
+            )
+        })
+    }
+
+    /// Whether the agent's events are re-broadcast as they happen
+    fn events_streaming(&self) -> bool {
+        self.vm
+            .events_streaming
+            .load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    /// Connect to the agent, booting the VM first if it was shut down while idle
+    async fn connect(&self) -> Result<rpc::RpcClient> {
+        let mut rpc = {
+            let mut guest = self.vm.guest.lock().await;
+            if guest.is_none() {
+                log::info!("Booting VM on demand");
+                let (booted, agent) = boot(&self.config).await?;
+                *self.vm.clock.lock().unwrap() = agent.clock.clone();
+                *self.agent.write().unwrap() = agent;
+                *guest = Some(booted);
+            }
+            self.vm.touch();
+            // Connections made under the lock, vsock ones can't be made concurrently
+            guest.as_mut().unwrap().connect(&self.config)?
+        };
+        // The agent then refuses changes itself, whatever this process sends
+        if self.read_only && self.agent.read().unwrap().supports(features::READ_ONLY) {
+            match rpc.call(Request::SetRole(Role::ReadOnly))? {
+                Response::RoleSet(Role::ReadOnly) => {}
+                _ => {
+                    return Err(ShimError::runtime(
+                        "Unexpected response type from RPC set role request",
+                    ))
+                }
+            }
+        }
+        Ok(rpc)
+    }
+
+    /// Query the agent's version information
+    pub async fn agent_info(&self) -> Result<AgentInfo> {
+        let mut rpc = self.connect().await?;
+        let mut info = compat::negotiate(|req| rpc.call(req))?;
+        info.clock = self.vm.clock.lock().unwrap().clone();
+        *self.agent.write().unwrap() = info.clone();
+        Ok(info)
+    }
+
+    /// Query how the agent was built
+    pub async fn agent_build_info(&self) -> Result<BuildInfo> {
+        self.require_feature(features::VERSION, "agent build information")?;
+        match self.call_idempotent(Request::Version).await? {
+            Response::Version(v) => Ok(BuildInfo {
+                version: v.version,
+                git_sha: v.git_sha,
+                build_timestamp: v.build_timestamp,
+                libcrun_version: v.libcrun_version,
+                min_protocol_version: v.min_protocol_version,
+                max_protocol_version: v.max_protocol_version,
+            }),
+            Response::Error(e) => Err(ShimError::runtime_with_context(
+                e,
+                "RPC version request failed",
+            )),
+            _ => Err(ShimError::runtime(
+                "Unexpected response type from RPC version request",
+            )),
+        }
+    }
+
+    /// Logs and state files for support bundles
+    pub async fn diagnostics(&self) -> Result<AgentDiagnostics> {
+        self.require_feature(features::DIAGNOSTICS, "support diagnostics")?;
+        match self.call_idempotent(Request::Diagnostics).await? {
+            Response::Diagnostics(d) => Ok(AgentDiagnostics {
+                agent_log: d.agent_log,
+                kernel_log: d.kernel_log,
+                state_files: d
+                    .state_files
+                    .into_iter()
+                    .map(|file| (file.path, file.contents))
+                    .collect(),
+            }),
+            Response::Error(e) => Err(ShimError::runtime_with_context(
+                e,
+                "RPC diagnostics request failed",
+            )),
+            _ => Err(ShimError::runtime(
+                "Unexpected response type from RPC diagnostics request",
+            )),
+        }
+    }
+
+    /// Send a request that is safe to repeat, retrying transient failures
+    /// according to the configured [`RetryPolicy`]
+    async fn call_idempotent(&self, request: Request) -> Result<Response> {
+        let policy = &self.config.retry_policy;
+        let started = std::time::Instant::now();
+        let mut attempt = 1;
+        loop {
+            let result = match self.connect().await {
+                Ok(mut rpc) => rpc.call(request.clone()),
+                Err(e) => Err(e),
+            };
+            match result {
+                Err(e) if e.is_transient() => {
+                    let Some(delay) = policy.next_delay(attempt, started.elapsed()) else {
+                        return Err(e);
+                    };
+                    log::debug!(
+                        "Agent request failed (attempt {}/{}), retrying in {}ms: {}",
+                        attempt,
+                        policy.max_attempts,
+                        delay.as_millis(),
+                        e
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
             }
         }
+    }
+
+    /// Usage of the filesystems mounted in the VM
+    pub async fn disk_usage(&self) -> Result<Vec<FilesystemUsage>> {
+        self.require_feature(features::DISKS, "guest disk usage")?;
+        match self.call_idempotent(Request::DiskUsage).await? {
+            Response::DiskUsage(filesystems) => Ok(filesystems
+                .into_iter()
+                .map(|fs| FilesystemUsage {
+                    mount_point: fs.mount_point,
+                    device: fs.device,
+                    fs_type: fs.fs_type,
+                    total_bytes: fs.total_bytes,
+                    used_bytes: fs.used_bytes,
+                    available_bytes: fs.available_bytes,
+                })
+                .collect()),
+            _ => Err(ShimError::runtime(
+                "Unexpected response type from RPC disk usage request",
+            )),
+        }
+    }
 
-        #[cfg(not(target_os = "macos"))]
+    /// Addresses `host` resolves to with the VM's resolver
+    pub async fn resolve(&self, host: &str) -> Result<Vec<String>> {
+        self.require_feature(features::RESOLVE, "name lookups in the VM")?;
+        match self
+            .call_idempotent(Request::Resolve(host.to_string()))
+            .await?
         {
-            tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
-        }
-
-        // Connect to agent with retry logic
-        #[cfg(target_os = "macos")]
-        let rpc = {
-            let max_retries = 5;
-            let retry_delay = tokio::time::Duration::from_secs(3);
-            let mut connected_client: Option<rpc::RpcClient> = None;
-            let mut last_error = None;
-
-            for attempt in 1..=max_retries {
-                log::info!("Connection attempt {}/{}", attempt, max_retries);
-
-                // Try vsock first if bridge is available
-                if let Some(handle) = vm.get_bridge_handle() {
-                    log::debug!("Attempting vsock connection via Swift bridge");
-                    match rpc::RpcClient::connect_with_vm_bridge(vm.config(), handle) {
-                        Ok(client) => {
-                            log::info!("Connected to VM agent via native vsock");
-                            connected_client = Some(client);
-                            break;
-                        }
-                        Err(e) => {
-                            log::debug!("Vsock connection failed: {}", e);
-                        }
+            Response::Resolved(addresses) => Ok(addresses),
+            Response::Error(e) => Err(ShimError::runtime_with_context(
+                e,
+                "RPC resolve request failed",
+            )),
+            _ => Err(ShimError::runtime(
+                "Unexpected response type from RPC resolve request",
+            )),
+        }
+    }
+
+    /// Metrics samples the agent recorded at or after `since` (Unix epoch seconds)
+    pub async fn metrics_history(&self, since: u64) -> Result<Vec<MetricsSample>> {
+        self.require_feature(features::METRICS_HISTORY, "metrics history")?;
+        match self.call_idempotent(Request::MetricsHistory(since)).await? {
+            Response::MetricsHistory(samples) => Ok(samples
+                .into_iter()
+                .map(|s| MetricsSample {
+                    id: s.id,
+                    timestamp: s.timestamp,
+                    cpu_usage_ns: s.cpu_usage_ns,
+                    memory_bytes: s.memory_bytes,
+                    net_rx_bytes: s.net_rx_bytes,
+                    net_tx_bytes: s.net_tx_bytes,
+                    blkio_read_bytes: s.blkio_read_bytes,
+                    blkio_write_bytes: s.blkio_write_bytes,
+                    pids: s.pids,
+                })
+                .collect()),
+            _ => Err(ShimError::runtime(
+                "Unexpected response type from RPC metrics history request",
+            )),
+        }
+    }
+
+    /// Copy blobs into the VM's cache, sending only those it does not have
+    ///
+    /// Each blob is a digest and a local file with its contents. Blobs are
+    /// sent in [`BLOB_CHUNK_SIZE`] pieces and verified by the agent.
+    pub async fn sync_blobs(
+        &self,
+        blobs: &[(String, std::path::PathBuf)],
+    ) -> Result<BlobSyncReport> {
+        use std::io::Read;
+
+        self.require_feature(features::BLOB_CACHE, "syncing image blobs")?;
+        let mut unique: Vec<&(String, std::path::PathBuf)> = Vec::new();
+        for blob in blobs {
+            if !unique.iter().any(|(digest, _)| *digest == blob.0) {
+                unique.push(blob);
+            }
+        }
+        let digests = unique.iter().map(|(digest, _)| digest.clone()).collect();
+        let missing = match self.call_idempotent(Request::MissingBlobs(digests)).await? {
+            Response::MissingBlobs(missing) => missing,
+            _ => {
+                return Err(ShimError::runtime(
+                    "Unexpected response type from RPC missing blobs request",
+                ))
+            }
+        };
+
+        let mut report = BlobSyncReport {
+            cached: unique.len() - missing.len(),
+            ..Default::default()
+        };
+        for (digest, path) in unique.into_iter().filter(|(d, _)| missing.contains(d)) {
+            let read_error =
+                |e| ShimError::io_with_context(e, format!("Cannot read blob {}", path.display()));
+            let mut file = std::fs::File::open(path).map_err(read_error)?;
+            let mut offset = 0;
+            loop {
+                let mut data = Vec::with_capacity(BLOB_CHUNK_SIZE);
+                (&mut file)
+                    .take(BLOB_CHUNK_SIZE as u64)
+                    .read_to_end(&mut data)
+                    .map_err(read_error)?;
+                let len = data.len() as u64;
+                let last = data.len() < BLOB_CHUNK_SIZE;
+                let chunk = BlobChunk {
+                    digest: digest.clone(),
+                    offset,
+                    data,
+                    last,
+                };
+                match self.call_idempotent(Request::PutBlob(chunk)).await? {
+                    Response::BlobStored => {}
+                    _ => {
+                        return Err(ShimError::runtime(
+                            "Unexpected response type from RPC put blob request",
+                        ))
                     }
                 }
+                offset += len;
+                if last {
+                    break;
+                }
+            }
+            log::info!("Sent blob {} ({} bytes) to the VM", digest, offset);
+            report.sent += 1;
+            report.sent_bytes += offset;
+        }
+        Ok(report)
+    }
 
-                // Try Unix socket as fallback
-                match rpc::RpcClient::connect_with_config(vm.config()) {
-                    Ok(client) => {
-                        log::info!("Connected to VM agent via Unix socket");
-                        connected_client = Some(client);
-                        break;
-                    }
-                    Err(e) => {
-                        log::debug!("Unix socket connection failed: {}", e);
-                        last_error = Some(e);
-                    }
+    /// Discard unused blocks in the VM so the host can reclaim them
+    pub async fn trim(&self) -> Result<Vec<TrimResult>> {
+        self.require_feature(features::DISKS, "trimming guest disks")?;
+        match self.call_idempotent(Request::Trim).await? {
+            Response::Trimmed(results) => Ok(results
+                .into_iter()
+                .map(|r| TrimResult {
+                    mount_point: r.mount_point,
+                    trimmed_bytes: r.trimmed_bytes,
+                    error: r.error,
+                })
+                .collect()),
+            _ => Err(ShimError::runtime(
+                "Unexpected response type from RPC trim request",
+            )),
+        }
+    }
+
+    /// Take over the I/O of an exec session that was detached from
+    pub async fn attach_exec(&self, session: &str, stdio: ExecStdio) -> Result<ExecOutcome> {
+        self.require_feature(features::EXEC_DETACH, "attaching to exec sessions")?;
+        let rpc = self.connect().await?;
+        rpc.attach_exec(session, stdio)
+    }
+
+    /// Bind mount a host directory into a running container
+    ///
+    /// The directory is added to the VM's shares device while the VM runs,
+    /// then the agent mounts it at `destination` in the container's mount
+    /// namespace.
+    pub async fn mount(
+        &self,
+        id: &str,
+        host_dir: &std::path::Path,
+        destination: &str,
+        read_only: bool,
+    ) -> Result<()> {
+        use sha2::{Digest, Sha256};
+
+        self.require_feature(features::HOT_MOUNT, "mounting into running containers")?;
+        let host_dir = host_dir.canonicalize().map_err(|e| {
+            ShimError::io_with_context(e, format!("Cannot share {}", host_dir.display()))
+        })?;
+        if !host_dir.is_dir() {
+            return Err(ShimError::validation(
+                "source",
+                format!("{} is not a directory", host_dir.display()),
+            ));
+        }
+        // Stable per directory, so mounting it again reuses the share
+        let digest = format!(
+            "{:x}",
+            Sha256::digest(host_dir.to_string_lossy().as_bytes())
+        );
+        let share = format!("dir-{}", &digest[..16]);
+
+        let mut rpc = self.connect().await?;
+        match self.vm.guest.lock().await.as_ref() {
+            Some(guest) => guest.vm.add_share(&share, &host_dir, read_only)?,
+            None => return Err(ShimError::runtime("VM was shut down while idle")),
+        }
+
+        match rpc.call(Request::Mount(MountRequest {
+            id: id.to_string(),
+            share,
+            destination: destination.to_string(),
+            read_only,
+        }))? {
+            Response::Mounted => {
+                log::info!(
+                    "Mounted {} at {} in container {}",
+                    host_dir.display(),
+                    destination,
+                    id
+                );
+                Ok(())
+            }
+            Response::Error(e) => Err(ShimError::runtime_with_context(
+                e,
+                format!("RPC mount request failed for container: {}", id),
+            )),
+            _ => Err(ShimError::runtime(
+                "Unexpected response type from RPC mount request",
+            )),
+        }
+    }
+
+    /// Fail unless the connected agent advertised `feature`
+    fn require_feature(&self, feature: &str, operation: &str) -> Result<()> {
+        compat::require_feature(&self.agent.read().unwrap(), feature, operation)
+    }
+
+    /// Replace the agent binary inside the VM without rebuilding the VM.
+    ///
+    /// The binary is sent over the RPC channel unless `guest_path` names a
+    /// location where the guest can already read it (e.g. a virtiofs share).
+    /// The agent is drained, re-executes itself with its listening sockets
+    /// inherited, and the new agent is verified with a fresh handshake.
+    pub async fn upgrade_agent(
+        &self,
+        binary: &std::path::Path,
+        guest_path: Option<&str>,
+    ) -> Result<AgentUpgrade> {
+        use sha2::{Digest, Sha256};
+
+        let data = std::fs::read(binary).map_err(|e| ShimError::Io {
+            error: e,
+            context: Some(format!("Failed to read agent binary {}", binary.display())),
+        })?;
+        let sha256 = format!("{:x}", Sha256::digest(&data));
+
+        let mut rpc = self.connect().await?;
+        let previous = compat::negotiate(|req| rpc.call(req))?;
+        compat::require_feature(&previous, features::AGENT_UPGRADE, "in-place upgrades")?;
+        log::info!(
+            "Upgrading agent {} (protocol {}) with {}",
+            previous.version,
+            previous.protocol_version,
+            binary.display()
+        );
+
+        let source = match guest_path {
+            Some(path) => AgentBinarySource::Path(path.to_string()),
+            None => AgentBinarySource::Inline(data),
+        };
+        match rpc.call(Request::UploadAgent(UploadAgentRequest {
+            source,
+            sha256: sha256.clone(),
+        }))? {
+            Response::AgentUploaded(path) => log::info!("Agent binary staged at {}", path),
+            Response::Error(e) => {
+                return Err(ShimError::runtime_with_context(
+                    e,
+                    "RPC agent upload request failed",
+                ))
+            }
+            _ => {
+                return Err(ShimError::runtime(
+                    "Unexpected response type from RPC agent upload request",
+                ))
+            }
+        }
+
+        match rpc.call(Request::Drain)? {
+            Response::Drained => {}
+            Response::Error(e) => {
+                return Err(ShimError::runtime_with_context(
+                    e,
+                    "RPC drain request failed",
+                ))
+            }
+            _ => {
+                return Err(ShimError::runtime(
+                    "Unexpected response type from RPC drain request",
+                ))
+            }
+        }
+
+        let upgrade = match rpc.call(Request::UpgradeAgent(UpgradeAgentRequest {
+            sha256: sha256.clone(),
+        })) {
+            Ok(Response::Upgrading) => Ok(()),
+            Ok(Response::Error(e)) => Err(ShimError::runtime_with_context(
+                e,
+                "RPC agent upgrade request failed",
+            )),
+            Ok(_) => Err(ShimError::runtime(
+                "Unexpected response type from RPC agent upgrade request",
+            )),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = upgrade {
+            // The old agent keeps running, let it take changes again
+            if previous.supports(features::UNDRAIN) {
+                if let Err(undrain) = rpc.call(Request::Undrain) {
+                    log::warn!("Failed to undrain agent after failed upgrade: {}", undrain);
                 }
+            }
+            return Err(e);
+        }
+        drop(rpc);
 
-                if attempt < max_retries {
-                    log::info!("Retrying in {}s...", retry_delay.as_secs());
-                    tokio::time::sleep(retry_delay).await;
+        // The listening sockets survive the exec, so connections queue up
+        // until the new agent starts accepting them
+        let deadline = std::time::Instant::now()
+            + std::time::Duration::from_secs(self.config.connection_timeout);
+        let current = loop {
+            tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+            let attempt = self
+                .connect()
+                .await
+                .and_then(|mut rpc| compat::negotiate(|req| rpc.call(req)));
+            match attempt {
+                Ok(info) => break info,
+                Err(e) if std::time::Instant::now() < deadline => {
+                    log::debug!("Waiting for upgraded agent: {}", e);
+                }
+                Err(e) => {
+                    return Err(ShimError::runtime_with_context(
+                        format!("Upgraded agent did not respond: {}", e),
+                        "The VM may need to be restarted to recover the previous agent",
+                    ))
                 }
             }
+        };
+
+        *self.agent.write().unwrap() = current.clone();
 
-            match connected_client {
-                Some(client) => client,
-                None => {
-                    return Err(last_error.unwrap_or_else(|| {
-                        ShimError::runtime("Failed to connect to agent after all retries")
-                    }));
+        log::info!(
+            "Agent upgraded from {} to {}",
+            previous.version,
+            current.version
+        );
+        Ok(AgentUpgrade {
+            previous,
+            current,
+            sha256,
+        })
+    }
+
+    /// The containers listed in answer to `request`, [`Request::List`] or
+    /// [`Request::ListFiltered`]
+    async fn list_with(&self, request: Request) -> Result<Vec<ContainerInfo>> {
+        match self.call_idempotent(request).await? {
+            Response::List(list) => {
+                // The agent enforces max runtimes and auto-stop; surface its
+                // stops as events, unless it streams them itself
+                let streaming = self.events_streaming();
+                let mut reported = self.reported_exits.lock().unwrap();
+                for info in &list {
+                    let Some(reason) = &info.exit_reason else {
+                        continue;
+                    };
+                    if reported.insert(info.id.clone()) && !streaming {
+                        let event = match reason.as_str() {
+                            "timeout" => ContainerEvent::new(ContainerEventType::Kill, &info.id)
+                                .with_signal(libc::SIGKILL),
+                            _ => ContainerEvent::new(ContainerEventType::Stop, &info.id),
+                        };
+                        global_events().send(event.with_attribute("reason", reason));
+                    }
                 }
+
+                Ok(list
+                    .into_iter()
+                    .map(|info| ContainerInfo {
+                        // Agents from before names were added leave it empty
+                        name: if info.name.is_empty() {
+                            info.id.clone()
+                        } else {
+                            info.name
+                        },
+                        id: info.id,
+                        status: info.status,
+                        pid: info.pid,
+                        exit_reason: info.exit_reason,
+                        restart_count: info.restart_count,
+                        last_exit_code: info.last_exit_code,
+                        last_exit_reason: info.last_exit_reason,
+                        restart_policy: info.restart_policy,
+                        restart_retries: info.restart_retries,
+                        ip_address: info.ip_address,
+                        oom_kills: info.oom_kills,
+                        labels: info.labels.into_iter().collect(),
+                    })
+                    .collect())
+            }
+            Response::Error(e) => Err(ShimError::runtime_with_context(
+                e,
+                "RPC list request failed",
+            )),
+            _ => Err(ShimError::runtime(
+                "Unexpected response type from RPC list request",
+            )),
+        }
+    }
+}
+
+/// Boot the VM and connect to its agent
+async fn boot(config: &RuntimeConfig) -> Result<(Guest, AgentInfo)> {
+    let vm = vm::VirtualMachine::start_with_config(config.clone()).await?;
+
+    let policy = &config.transport;
+    let wait = policy.boot_wait(vm.has_vm_control());
+    if vm.has_vm_control() {
+        log::info!(
+            "VM started via Swift bridge - waiting {}s for guest to boot...",
+            wait.as_secs()
+        );
+    } else {
+        log::info!("Using fallback mode - assuming external VM is running");
+    }
+    tokio::time::sleep(wait).await;
+
+    let attempts = policy.boot_attempts.max(1);
+    let mut attempt = 1;
+    let (mut rpc, transport) = loop {
+        log::info!("Connection attempt {}/{}", attempt, attempts);
+        match connect_transport(&vm, config, &policy.order) {
+            Ok(connected) => break connected,
+            Err(e) if attempt < attempts => {
+                log::info!("Retrying in {}ms: {}", policy.boot_retry_delay_ms, e);
+                tokio::time::sleep(policy.boot_retry_delay()).await;
+                attempt += 1;
+            }
+            Err(e) => {
+                log::error!("Failed to connect to agent after {} attempts", attempts);
+                return Err(e);
             }
+        }
+    };
+
+    log::info!("Connected to VM agent over {}", transport);
+
+    let mut agent = compat::negotiate(|req| rpc.call(req))?;
+    log::info!(
+        "Agent {} (protocol {}), features: {}",
+        agent.version,
+        agent.protocol_version,
+        agent.features.join(", ")
+    );
+
+    if agent.supports(features::TIME_SYNC) {
+        match sync_clock(&mut rpc, &config.time_sync) {
+            Ok(sync) => {
+                log::info!("Guest clock was {}ms off the host's", sync.offset_ms);
+                agent.clock = Some(sync);
+            }
+            Err(e) => log::warn!("Failed to sync the guest clock: {}", e),
+        }
+    }
+
+    Ok((Guest { vm, rpc, transport }, agent))
+}
+
+/// Connect to the agent of `vm` over the first transport in `order` that
+/// works, returning it with the connection
+fn connect_transport(
+    vm: &vm::VirtualMachine,
+    config: &RuntimeConfig,
+    order: &[Transport],
+) -> Result<(rpc::RpcClient, Transport)> {
+    let mut last_error = None;
+    for &transport in order {
+        let result = match transport {
+            Transport::Vsock => connect_vsock(vm, config),
+            Transport::Unix => rpc::RpcClient::connect_with_config(config),
         };
+        match result {
+            Ok(rpc) => return Ok((rpc, transport)),
+            Err(e) => {
+                log::debug!("Agent connection over {} failed: {}", transport, e);
+                last_error = Some(e);
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        ShimError::validation("transport.order", "No transport to reach the agent over")
+    }))
+}
 
-        #[cfg(not(target_os = "macos"))]
-        let rpc = rpc::RpcClient::connect_with_config(&config)?;
+/// Connect over the virtio socket of a VM the runtime booted
+fn connect_vsock(vm: &vm::VirtualMachine, config: &RuntimeConfig) -> Result<rpc::RpcClient> {
+    #[cfg(target_os = "macos")]
+    if let Some(handle) = vm.get_bridge_handle() {
+        return rpc::RpcClient::connect_with_vm_bridge(config, handle);
+    }
+    let _ = (vm, config);
+    Err(ShimError::runtime_with_context(
+        "No vsock to the agent",
+        "Only a VM the runtime booted itself has one; use the unix transport for external VMs",
+    ))
+}
 
-        log::info!("Connected to VM agent via RPC");
+/// Shut the VM down once it has been idle for as long as the policy allows
+///
+/// Exits when the runtime is dropped. The next request boots the VM again.
+async fn idle_monitor(state: std::sync::Weak<VmState>, config: RuntimeConfig) {
+    let policy = &config.idle_policy;
+    loop {
+        tokio::time::sleep(policy.check_interval()).await;
+        let Some(state) = state.upgrade() else {
+            return;
+        };
+        // Holding the lock keeps requests from racing the shutdown
+        let mut guest = state.guest.lock().await;
+        if guest.is_none() {
+            continue;
+        }
+        let containers = match guest
+            .as_mut()
+            .unwrap()
+            .connect(&config)
+            .and_then(|mut rpc| rpc.call(Request::List))
+        {
+            Ok(Response::List(containers)) => containers.len(),
+            Ok(other) => {
+                log::debug!("Idle check got an unexpected response: {:?}", other);
+                continue;
+            }
+            Err(e) => {
+                log::debug!("Idle check failed: {}", e);
+                continue;
+            }
+        };
+        if containers > 0 {
+            state.touch();
+            continue;
+        }
+        let idle_for = state.idle_for();
+        if policy.should_shut_down(containers, idle_for) {
+            log::info!(
+                "Shutting down VM after {}s without containers",
+                idle_for.as_secs()
+            );
+            if let Some(mut idle) = guest.take() {
+                if let Err(e) = idle.vm.stop().await {
+                    log::warn!("Failed to stop idle VM: {}", e);
+                }
+            }
+        }
+    }
+}
 
-        Ok(Self { vm, rpc, config })
+/// Send the host time to the agent, which corrects the guest clock
+fn sync_clock(rpc: &mut rpc::RpcClient, policy: &TimeSyncPolicy) -> Result<ClockSync> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    let request = TimeSyncRequest {
+        host_time_ns: now.as_nanos() as i64,
+        step_threshold_ms: policy.step_threshold_ms,
+    };
+    match rpc.call(Request::SyncTime(request))? {
+        Response::TimeSynced(sync) => Ok(ClockSync {
+            offset_ms: sync.offset_ns / 1_000_000,
+            stepped: sync.stepped,
+            synced_at: now.as_secs(),
+        }),
+        Response::Error(e) => Err(ShimError::runtime_with_context(
+            e,
+            "RPC time sync request failed",
+        )),
+        _ => Err(ShimError::runtime(
+            "Unexpected response type from RPC time sync request",
+        )),
     }
+}
 
-    /// Get the runtime configuration
-    pub fn config(&self) -> &RuntimeConfig {
-        &self.config
+/// Sync the guest clock periodically and after the host wakes from sleep
+///
+/// Exits when the runtime is dropped. A VM shut down while idle is left
+/// alone; it is synced again when it boots.
+async fn time_sync_monitor(state: std::sync::Weak<VmState>, config: RuntimeConfig) {
+    let policy = &config.time_sync;
+    let mut last_sync = (std::time::Instant::now(), std::time::SystemTime::now());
+    loop {
+        tokio::time::sleep(policy.check_interval()).await;
+        let Some(state) = state.upgrade() else {
+            return;
+        };
+        let wall = last_sync.1.elapsed().unwrap_or_default();
+        if !policy.is_due(last_sync.0.elapsed(), wall) {
+            continue;
+        }
+        let mut guest = state.guest.lock().await;
+        let Some(guest) = guest.as_mut() else {
+            continue;
+        };
+        last_sync = (std::time::Instant::now(), std::time::SystemTime::now());
+        match guest
+            .connect(&config)
+            .and_then(|mut rpc| sync_clock(&mut rpc, policy))
+        {
+            Ok(sync) => {
+                if sync.stepped {
+                    log::info!("Stepped the guest clock by {}ms", sync.offset_ms);
+                }
+                *state.clock.lock().unwrap() = Some(sync);
+            }
+            Err(e) => log::debug!("Time sync failed: {}", e),
+        }
+    }
+}
+
+/// How long the event monitor waits before subscribing again
+const EVENT_RESUBSCRIBE_DELAY: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Re-broadcast the events the agent raises on its own, such as exits, OOM
+/// kills and health changes, on the global event bus
+///
+/// Exits when the runtime is dropped. While the VM is shut down, or the
+/// agent restarting, events are not streamed and are inferred from lists
+/// and health checks instead.
+async fn event_monitor(state: std::sync::Weak<VmState>, config: RuntimeConfig) {
+    use std::sync::atomic::Ordering;
+    loop {
+        let Some(vm) = state.upgrade() else {
+            return;
+        };
+        let rpc = match vm.guest.lock().await.as_mut() {
+            Some(guest) => guest.connect(&config),
+            None => Err(ShimError::runtime("The VM is shut down")),
+        };
+        // Not held while streaming, or the runtime would never be dropped
+        drop(vm);
+        match rpc {
+            Ok(rpc) => {
+                let streaming = state.clone();
+                let subscribed = tokio::task::spawn_blocking(move || {
+                    rpc.subscribe_events(|events| {
+                        let Some(vm) = streaming.upgrade() else {
+                            return false;
+                        };
+                        vm.events_streaming.store(true, Ordering::SeqCst);
+                        for event in events {
+                            global_events().send(proto_to_event(event));
+                        }
+                        true
+                    })
+                })
+                .await;
+                match subscribed {
+                    Ok(Ok(())) => log::debug!("Agent closed the event stream"),
+                    Ok(Err(e)) => log::debug!("Event stream failed: {}", e),
+                    Err(e) => log::warn!("Event stream reader failed: {}", e),
+                }
+            }
+            Err(e) => log::debug!("Cannot subscribe to agent events: {}", e),
+        }
+        if let Some(vm) = state.upgrade() {
+            vm.events_streaming.store(false, Ordering::SeqCst);
+        }
+        tokio::time::sleep(EVENT_RESUBSCRIBE_DELAY).await;
+    }
+}
+
+fn policy_denied(violations: Vec<libcrun_shim_proto::PolicyViolationProto>) -> ShimError {
+    ShimError::PolicyDenied {
+        violations: violations
+            .into_iter()
+            .map(|v| PolicyViolation {
+                rule: v.rule,
+                message: v.message,
+            })
+            .collect(),
     }
 }
 
 impl RuntimeImpl for MacOsRuntime {
     async fn create(&self, container_config: ContainerConfig) -> Result<String> {
         use libcrun_shim_proto::*;
-        let req = Request::Create(CreateRequest {
+        container_config.resources.validate()?;
+        container_config.network.validate()?;
+        // Catch missing kernel support here rather than halfway through the create
+        if let Some(ref kernel) = self.agent.read().unwrap().kernel {
+            kernel.check(&container_config)?;
+        }
+        if container_config.restart_policy != RestartPolicy::No {
+            self.require_feature(features::RESTART_POLICY, "restart policies")?;
+        }
+        if container_config.rootfs_snapshot {
+            self.require_feature(features::ROOTFS_SNAPSHOT, "root filesystem snapshots")?;
+        }
+        if container_config.read_only_rootfs {
+            self.require_feature(features::READ_ONLY_ROOTFS, "read-only root filesystems")?;
+        }
+        if !container_config.labels.is_empty() {
+            self.require_feature(features::LABELS, "container labels")?;
+        }
+        if container_config
+            .volumes
+            .iter()
+            .any(|v| v.propagation.is_some() || v.recursive_readonly)
+        {
+            self.require_feature(features::MOUNT_OPTIONS, "volume propagation options")?;
+        }
+        if container_config.stop_timeout.is_some() {
+            self.require_feature(features::STOP_TIMEOUT, "stop timeouts")?;
+        }
+
+        // Seccomp profiles live on the host, so ship their contents
+        let seccomp = match container_config.seccomp_profile {
+            Some(ref path) => Some(std::fs::read_to_string(path).map_err(|e| ShimError::Io {
+                error: e,
+                context: Some(format!("Failed to read seccomp profile {}", path.display())),
+            })?),
+            None => None,
+        };
+
+        let id = container_config.id.clone();
+        let req = ProgressRequest::Create(Box::new(CreateRequest {
             id: container_config.id.clone(),
+            name: container_config.name.clone().unwrap_or_default(),
             rootfs: container_config.rootfs.display().to_string(),
             command: container_config.command,
             env: container_config.env,
@@ -167,9 +1090,11 @@ impl RuntimeImpl for MacOsRuntime {
                     .map(|ni| NetworkInterfaceProto {
                         name: ni.name,
                         interface_type: ni.interface_type,
-                        config: ni.config,
+                        config: ni.config.into_iter().collect(),
                     })
                     .collect(),
+                allow_egress: container_config.network.allow_egress,
+                deny_egress: container_config.network.deny_egress,
             },
             volumes: container_config
                 .volumes
@@ -178,6 +1103,8 @@ impl RuntimeImpl for MacOsRuntime {
                     source: vm.source.display().to_string(),
                     destination: vm.destination.display().to_string(),
                     options: vm.options,
+                    propagation: vm.propagation,
+                    recursive_readonly: vm.recursive_readonly,
                 })
                 .collect(),
             resources: ResourceLimitsProto {
@@ -186,6 +1113,18 @@ impl RuntimeImpl for MacOsRuntime {
                 memory_swap: container_config.resources.memory_swap,
                 pids: container_config.resources.pids,
                 blkio_weight: container_config.resources.blkio_weight,
+                memory_swappiness: container_config.resources.memory_swappiness,
+                cpuset_cpus: container_config.resources.cpuset_cpus.clone(),
+                cpuset_mems: container_config.resources.cpuset_mems.clone(),
+                hugepage_limits: container_config
+                    .resources
+                    .hugepage_limits
+                    .iter()
+                    .map(|h| HugepageLimitProto {
+                        page_size: h.page_size.clone(),
+                        limit: h.limit,
+                    })
+                    .collect(),
             },
             health_check: container_config.health_check.map(|hc| HealthCheckProto {
                 command: hc.command,
@@ -194,11 +1133,43 @@ impl RuntimeImpl for MacOsRuntime {
                 retries: hc.retries,
                 start_period_secs: hc.start_period,
             }),
-        });
+            security: SecurityProto {
+                capabilities: container_config.capabilities,
+                seccomp,
+                rlimits: container_config
+                    .ulimits
+                    .iter()
+                    .map(|u| RlimitProto {
+                        rlimit_type: u.rlimit_type(),
+                        soft: u.soft,
+                        hard: u.hard,
+                    })
+                    .collect(),
+            },
+            image: container_config.image,
+            max_runtime_secs: container_config.max_runtime_secs,
+            stop_timeout: container_config.stop_timeout,
+            create_working_dir: container_config.create_working_dir,
+            auto_stop: container_config.auto_stop.map(|p| AutoStopProto {
+                idle_secs: p.idle_secs,
+                cpu_percent: p.cpu_percent,
+            }),
+            join_namespaces: container_config
+                .join_namespaces
+                .map(|j| JoinNamespacesProto {
+                    container: j.container,
+                    network: j.network,
+                    ipc: j.ipc,
+                }),
+            restart_policy: container_config.restart_policy,
+            rootfs_snapshot: container_config.rootfs_snapshot,
+            read_only_rootfs: container_config.read_only_rootfs,
+            labels: container_config.labels.into_iter().collect(),
+        }));
 
-        let mut rpc = rpc::RpcClient::connect_with_config(&self.config)?;
-        match rpc.call(req)? {
+        match self.call_reporting(Operation::Create, &id, req).await? {
             Response::Created(id) => Ok(id),
+            Response::Denied(violations) => Err(policy_denied(violations)),
             Response::Error(e) => Err(ShimError::runtime_with_context(
                 e,
                 "RPC create request failed",
@@ -210,9 +1181,10 @@ impl RuntimeImpl for MacOsRuntime {
     }
 
     async fn start(&self, id: &str) -> Result<()> {
-        let mut rpc = rpc::RpcClient::connect_with_config(&self.config)?;
+        let mut rpc = self.connect().await?;
         match rpc.call(Request::Start(id.to_string()))? {
             Response::Started => Ok(()),
+            Response::Denied(violations) => Err(policy_denied(violations)),
             Response::Error(e) => Err(ShimError::runtime_with_context(
                 e,
                 format!("RPC start request failed for container: {}", id),
@@ -223,9 +1195,19 @@ impl RuntimeImpl for MacOsRuntime {
         }
     }
 
-    async fn stop(&self, id: &str) -> Result<()> {
-        let mut rpc = rpc::RpcClient::connect_with_config(&self.config)?;
-        match rpc.call(Request::Stop(id.to_string()))? {
+    async fn stop(&self, id: &str, timeout: Option<std::time::Duration>) -> Result<()> {
+        let request = match timeout {
+            Some(timeout) => {
+                self.require_feature(features::STOP_TIMEOUT, "stop timeouts")?;
+                Request::StopWithTimeout(StopRequest {
+                    id: id.to_string(),
+                    timeout_secs: timeout.as_secs(),
+                })
+            }
+            None => Request::Stop(id.to_string()),
+        };
+        let mut rpc = self.connect().await?;
+        match rpc.call(request)? {
             Response::Stopped => Ok(()),
             Response::Error(e) => Err(ShimError::runtime_with_context(
                 e,
@@ -237,10 +1219,36 @@ impl RuntimeImpl for MacOsRuntime {
         }
     }
 
+    async fn set_paused(&self, id: &str, pause: bool) -> Result<()> {
+        self.require_feature(features::PAUSE, "pause")?;
+        let (request, verb) = if pause {
+            (Request::Pause(id.to_string()), "pause")
+        } else {
+            (Request::Unpause(id.to_string()), "unpause")
+        };
+        let mut rpc = self.connect().await?;
+        match rpc.call(request)? {
+            Response::Paused if pause => Ok(()),
+            Response::Unpaused if !pause => Ok(()),
+            Response::Error(e) => Err(ShimError::runtime_with_context(
+                e,
+                format!("RPC {} request failed for container: {}", verb, id),
+            )),
+            _ => Err(ShimError::runtime(format!(
+                "Unexpected response type from RPC {} request",
+                verb
+            ))),
+        }
+    }
+
     async fn delete(&self, id: &str) -> Result<()> {
-        let mut rpc = rpc::RpcClient::connect_with_config(&self.config)?;
+        let mut rpc = self.connect().await?;
         match rpc.call(Request::Delete(id.to_string()))? {
-            Response::Deleted => Ok(()),
+            Response::Deleted => {
+                self.reported_exits.lock().unwrap().remove(id);
+                self.reported_health.lock().unwrap().remove(id);
+                Ok(())
+            }
             Response::Error(e) => Err(ShimError::runtime_with_context(
                 e,
                 format!("RPC delete request failed for container: {}", id),
@@ -252,34 +1260,30 @@ impl RuntimeImpl for MacOsRuntime {
     }
 
     async fn list(&self) -> Result<Vec<ContainerInfo>> {
-        let mut rpc = rpc::RpcClient::connect_with_config(&self.config)?;
-        match rpc.call(Request::List)? {
-            Response::List(list) => Ok(list
-                .into_iter()
-                .map(|info| ContainerInfo {
-                    id: info.id,
-                    status: match info.status.as_str() {
-                        "Created" => ContainerStatus::Created,
-                        "Running" => ContainerStatus::Running,
-                        _ => ContainerStatus::Stopped,
-                    },
-                    pid: info.pid,
-                })
-                .collect()),
-            Response::Error(e) => Err(ShimError::runtime_with_context(
-                e,
-                "RPC list request failed",
-            )),
-            _ => Err(ShimError::runtime(
-                "Unexpected response type from RPC list request",
-            )),
+        self.list_with(Request::List).await
+    }
+
+    async fn list_filtered(&self, filter: &ListFilter) -> Result<Vec<ContainerInfo>> {
+        if !self.agent.read().unwrap().supports(features::LIST_FILTER) {
+            let mut list = self.list().await?;
+            list.retain(|info| filter.matches(info));
+            return Ok(list);
         }
+        self.list_with(Request::ListFiltered(ListFilterProto {
+            labels: filter.labels.iter().map(label_filter_proto).collect(),
+            statuses: filter.statuses.clone(),
+            name_prefix: filter.name_prefix.clone().unwrap_or_default(),
+        }))
+        .await
     }
 
     async fn metrics(&self, id: &str) -> Result<ContainerMetrics> {
-        let mut rpc = rpc::RpcClient::connect_with_config(&self.config)?;
-        match rpc.call(Request::Metrics(id.to_string()))? {
-            Response::Metrics(m) => Ok(proto_to_metrics(m)),
+        self.require_feature(features::METRICS, "metrics")?;
+        match self
+            .call_idempotent(Request::Metrics(id.to_string()))
+            .await?
+        {
+            Response::Metrics(m) => Ok(crate::metrics::proto_to_metrics(m)),
             Response::Error(e) => Err(ShimError::runtime_with_context(
                 e,
                 format!("RPC metrics request failed for container: {}", id),
@@ -291,9 +1295,12 @@ impl RuntimeImpl for MacOsRuntime {
     }
 
     async fn all_metrics(&self) -> Result<Vec<ContainerMetrics>> {
-        let mut rpc = rpc::RpcClient::connect_with_config(&self.config)?;
-        match rpc.call(Request::AllMetrics)? {
-            Response::AllMetrics(list) => Ok(list.into_iter().map(proto_to_metrics).collect()),
+        self.require_feature(features::METRICS, "metrics")?;
+        match self.call_idempotent(Request::AllMetrics).await? {
+            Response::AllMetrics(list) => Ok(list
+                .into_iter()
+                .map(crate::metrics::proto_to_metrics)
+                .collect()),
             Response::Error(e) => Err(ShimError::runtime_with_context(
                 e,
                 "RPC all_metrics request failed",
@@ -305,20 +1312,11 @@ impl RuntimeImpl for MacOsRuntime {
     }
 
     async fn logs(&self, id: &str, options: LogOptions) -> Result<ContainerLogs> {
-        let mut rpc = rpc::RpcClient::connect_with_config(&self.config)?;
-        let req = Request::Logs(libcrun_shim_proto::LogsRequest {
-            id: id.to_string(),
-            tail: options.tail,
-            since: options.since,
-            timestamps: options.timestamps,
-        });
-        match rpc.call(req)? {
-            Response::Logs(l) => Ok(ContainerLogs {
-                id: l.id,
-                stdout: l.stdout,
-                stderr: l.stderr,
-                timestamp: l.timestamp,
-            }),
+        match self
+            .call_idempotent(Request::Logs(logs_request(id, &options)))
+            .await?
+        {
+            Response::Logs(l) => Ok(proto_to_logs(l)),
             Response::Error(e) => Err(ShimError::runtime_with_context(
                 e,
                 format!("RPC logs request failed for container: {}", id),
@@ -329,21 +1327,78 @@ impl RuntimeImpl for MacOsRuntime {
         }
     }
 
+    async fn follow_logs(
+        &self,
+        id: &str,
+        options: LogOptions,
+        on_logs: &mut (dyn FnMut(ContainerLogs) -> bool + Send),
+    ) -> Result<()> {
+        if !self.agent.read().unwrap().supports(features::LOG_STREAMING) {
+            // Older agents only send snapshots, so ask them for new output
+            return crate::poll_logs(self, id, options, on_logs).await;
+        }
+        let rpc = self.connect().await?;
+        let request = logs_request(id, &options);
+        // The stream is read by a blocking thread, so that other futures, such
+        // as the followers of other containers, keep running meanwhile
+        let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+        let reader = tokio::task::spawn_blocking(move || {
+            rpc.follow_logs(request, |logs| tx.blocking_send(logs).is_ok())
+        });
+        while let Some(logs) = rx.recv().await {
+            if !on_logs(proto_to_logs(logs)) {
+                // Dropping the receiver ends the reader at the next batch
+                return Ok(());
+            }
+        }
+        reader
+            .await
+            .map_err(|e| ShimError::runtime(format!("Log stream reader failed: {}", e)))?
+    }
+
     async fn health(&self, id: &str) -> Result<HealthStatus> {
-        let mut rpc = rpc::RpcClient::connect_with_config(&self.config)?;
-        match rpc.call(Request::Health(id.to_string()))? {
-            Response::Health(h) => Ok(HealthStatus {
-                id: h.id,
-                status: match h.status.as_str() {
+        if !self.agent.read().unwrap().supports(features::HEALTH) {
+            // Older agents don't run health checks, report them as not configured
+            return Ok(HealthStatus {
+                id: id.to_string(),
+                status: HealthState::None,
+                failing_streak: 0,
+                last_output: String::new(),
+                last_check: 0,
+            });
+        }
+        match self
+            .call_idempotent(Request::Health(id.to_string()))
+            .await?
+        {
+            Response::Health(h) => {
+                let status = match h.status.as_str() {
                     "healthy" => HealthState::Healthy,
                     "unhealthy" => HealthState::Unhealthy,
                     "starting" => HealthState::Starting,
                     _ => HealthState::None,
-                },
-                failing_streak: h.failing_streak,
-                last_output: h.last_output,
-                last_check: h.last_check,
-            }),
+                };
+                // The agent runs the probes; surface transitions it reports as events
+                let previous = self
+                    .reported_health
+                    .lock()
+                    .unwrap()
+                    .insert(h.id.clone(), status);
+                if previous != Some(status) && !self.events_streaming() {
+                    match status {
+                        HealthState::Healthy => global_events().emit_health(&h.id, true),
+                        HealthState::Unhealthy => global_events().emit_health(&h.id, false),
+                        _ => {}
+                    }
+                }
+                Ok(HealthStatus {
+                    id: h.id,
+                    status,
+                    failing_streak: h.failing_streak,
+                    last_output: h.last_output,
+                    last_check: h.last_check,
+                })
+            }
             Response::Error(e) => Err(ShimError::runtime_with_context(
                 e,
                 format!("RPC health request failed for container: {}", id),
@@ -354,13 +1409,23 @@ impl RuntimeImpl for MacOsRuntime {
         }
     }
 
-    async fn exec(&self, id: &str, command: Vec<String>) -> Result<(i32, String, String)> {
-        let mut rpc = rpc::RpcClient::connect_with_config(&self.config)?;
+    async fn exec(
+        &self,
+        id: &str,
+        options: ExecOptions,
+        user: &str,
+    ) -> Result<(i32, String, String)> {
+        self.require_feature(features::EXEC, "exec")?;
+        let mut rpc = self.connect().await?;
         let req = Request::Exec(libcrun_shim_proto::ExecRequest {
             id: id.to_string(),
-            command,
-            env: vec![],
-            working_dir: None,
+            command: options.command,
+            env: options.env,
+            working_dir: options.working_dir,
+            user: user.to_string(),
+            tty: options.tty,
+            uid: options.uid,
+            gid: options.gid,
         });
         match rpc.call(req)? {
             Response::Exec(e) => Ok((e.exit_code, e.stdout, e.stderr)),
@@ -373,50 +1438,615 @@ impl RuntimeImpl for MacOsRuntime {
             )),
         }
     }
+
+    async fn exec_stream(
+        &self,
+        id: &str,
+        options: ExecOptions,
+        user: &str,
+        stdio: ExecStdio,
+    ) -> Result<ExecOutcome> {
+        self.require_feature(features::EXEC_STREAM, "interactive exec")?;
+        if stdio.detach_keys.is_some() {
+            self.require_feature(features::EXEC_DETACH, "detaching from exec sessions")?;
+        }
+        let rpc = self.connect().await?;
+        let req = ExecStreamRequest {
+            exec: libcrun_shim_proto::ExecRequest {
+                id: id.to_string(),
+                command: options.command,
+                env: options.env,
+                working_dir: options.working_dir,
+                user: user.to_string(),
+                tty: options.tty,
+                uid: options.uid,
+                gid: options.gid,
+            },
+            terminal_size: stdio.terminal_size,
+            detachable: stdio.detach_keys.is_some(),
+            stdin_once: stdio.stdin_once,
+        };
+        rpc.exec_stream(req, stdio)
+    }
+
+    async fn rename(&self, id: &str, name: &str) -> Result<()> {
+        self.require_feature(features::RENAME, "renaming containers")?;
+        let mut rpc = self.connect().await?;
+        match rpc.call(Request::Rename(RenameRequest {
+            id: id.to_string(),
+            name: name.to_string(),
+        }))? {
+            Response::Renamed => Ok(()),
+            _ => Err(ShimError::runtime(
+                "Unexpected response type from RPC rename request",
+            )),
+        }
+    }
+
+    async fn exec_sessions(&self, id: &str) -> Result<Vec<ExecSession>> {
+        self.require_feature(features::EXEC_AUDIT, "exec history")?;
+        match self
+            .call_idempotent(Request::ExecSessions(id.to_string()))
+            .await?
+        {
+            Response::ExecSessions(sessions) => Ok(sessions
+                .into_iter()
+                .map(|s| ExecSession {
+                    command: s.command,
+                    user: s.user,
+                    started_at: s.started_at,
+                    duration_ms: s.duration_ms,
+                    exit_code: s.exit_code,
+                })
+                .collect()),
+            Response::Error(e) => Err(ShimError::runtime_with_context(
+                e,
+                format!("RPC exec sessions request failed for container: {}", id),
+            )),
+            _ => Err(ShimError::runtime(
+                "Unexpected response type from RPC exec sessions request",
+            )),
+        }
+    }
+
+    async fn checkpoint(&self, id: &str, name: &str, leave_running: bool) -> Result<Checkpoint> {
+        self.require_feature(features::CHECKPOINTS, "checkpoints")?;
+        let request = ProgressRequest::CreateCheckpoint(CheckpointRequest {
+            id: id.to_string(),
+            name: name.to_string(),
+            leave_running,
+        });
+        match self
+            .call_reporting(Operation::Checkpoint, &format!("{}/{}", id, name), request)
+            .await?
+        {
+            Response::Checkpoint(c) => Ok(proto_to_checkpoint(c)),
+            _ => Err(ShimError::runtime(
+                "Unexpected response type from RPC checkpoint request",
+            )),
+        }
+    }
+
+    async fn checkpoints(&self, id: Option<&str>) -> Result<Vec<Checkpoint>> {
+        self.require_feature(features::CHECKPOINTS, "checkpoints")?;
+        match self
+            .call_idempotent(Request::ListCheckpoints(id.map(str::to_string)))
+            .await?
+        {
+            Response::Checkpoints(list) => Ok(list.into_iter().map(proto_to_checkpoint).collect()),
+            _ => Err(ShimError::runtime(
+                "Unexpected response type from RPC list checkpoints request",
+            )),
+        }
+    }
+
+    async fn remove_checkpoint(&self, id: &str, name: &str) -> Result<()> {
+        self.require_feature(features::CHECKPOINTS, "checkpoints")?;
+        let mut rpc = self.connect().await?;
+        match rpc.call(Request::RemoveCheckpoint(CheckpointRef {
+            container: id.to_string(),
+            name: name.to_string(),
+        }))? {
+            Response::CheckpointRemoved => Ok(()),
+            _ => Err(ShimError::runtime(
+                "Unexpected response type from RPC remove checkpoint request",
+            )),
+        }
+    }
+
+    async fn restore(&self, id: &str, name: &str, new_id: &str) -> Result<String> {
+        self.require_feature(features::CHECKPOINTS, "checkpoints")?;
+        let request = ProgressRequest::RestoreCheckpoint(RestoreRequest {
+            checkpoint: CheckpointRef {
+                container: id.to_string(),
+                name: name.to_string(),
+            },
+            id: new_id.to_string(),
+        });
+        match self
+            .call_reporting(Operation::Restore, new_id, request)
+            .await?
+        {
+            Response::Created(id) => Ok(id),
+            _ => Err(ShimError::runtime(
+                "Unexpected response type from RPC restore request",
+            )),
+        }
+    }
+
+    async fn export_checkpoint(
+        &self,
+        id: &str,
+        name: &str,
+        path: &std::path::Path,
+    ) -> Result<Checkpoint> {
+        use std::io::Write;
+
+        self.require_feature(features::CHECKPOINT_TRANSFER, "exporting checkpoints")?;
+        let checkpoint_ref = CheckpointRef {
+            container: id.to_string(),
+            name: name.to_string(),
+        };
+        let listing = match self
+            .call_idempotent(Request::CheckpointFiles(checkpoint_ref.clone()))
+            .await?
+        {
+            Response::CheckpointFiles(listing) => listing,
+            _ => {
+                return Err(ShimError::runtime(
+                    "Unexpected response type from RPC checkpoint files request",
+                ))
+            }
+        };
+        let checkpoint = proto_to_checkpoint(listing.checkpoint);
+        let target = format!("{}/{}", id, name);
+        let total: u64 = listing.files.iter().map(|file| file.size).sum();
+        let mut done = 0;
+
+        let staging = crate::checkpoints::start_export(path)?;
+        let copied: Result<()> = async {
+            for file in &listing.files {
+                let target = staging.join(&file.path);
+                let write_error =
+                    |e| ShimError::io_with_context(e, format!("Writing {}", target.display()));
+                let mut out = std::fs::File::create(&target).map_err(write_error)?;
+                let mut offset = 0;
+                loop {
+                    let request = Request::ReadCheckpointFile(CheckpointFileRequest {
+                        checkpoint: checkpoint_ref.clone(),
+                        path: file.path.clone(),
+                        offset,
+                    });
+                    let data =
+                        match self.call_idempotent(request).await? {
+                            Response::CheckpointData(data) => data,
+                            _ => return Err(ShimError::runtime(
+                                "Unexpected response type from RPC read checkpoint file request",
+                            )),
+                        };
+                    if data.is_empty() {
+                        break;
+                    }
+                    out.write_all(&data).map_err(write_error)?;
+                    offset += data.len() as u64;
+                    done += data.len() as u64;
+                    crate::progress::report(
+                        Operation::ExportCheckpoint,
+                        &target,
+                        "copy",
+                        Some(crate::progress::percent(done, total)),
+                        format!("Copying {}", file.path),
+                    );
+                }
+                if offset != file.size {
+                    return Err(ShimError::runtime_with_context(
+                        format!("Checkpoint file {} changed while exporting", file.path),
+                        format!("Read {} bytes, expected {}", offset, file.size),
+                    ));
+                }
+            }
+            Ok(())
+        }
+        .await;
+        if copied.is_err() {
+            let _ = std::fs::remove_dir_all(&staging);
+        }
+        copied?;
+        crate::checkpoints::finish_export(&staging, path, &checkpoint)?;
+        log::info!(
+            "Exported checkpoint '{}' of '{}' to {}",
+            name,
+            id,
+            path.display()
+        );
+        Ok(checkpoint)
+    }
+
+    async fn import_checkpoint(&self, path: &std::path::Path) -> Result<Checkpoint> {
+        use std::io::Read;
+
+        self.require_feature(features::CHECKPOINT_TRANSFER, "importing checkpoints")?;
+        let checkpoint = crate::checkpoints::read_export(path)?;
+        let checkpoint_ref = CheckpointRef {
+            container: checkpoint.container.clone(),
+            name: checkpoint.name.clone(),
+        };
+        let target = format!("{}/{}", checkpoint.container, checkpoint.name);
+        let files = crate::checkpoints::files(path)?;
+        let total: u64 = files
+            .iter()
+            .filter_map(|file| std::fs::metadata(path.join(file)).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        let mut done = 0;
+        for file in files {
+            let source = path.join(&file);
+            let read_error =
+                |e| ShimError::io_with_context(e, format!("Reading {}", source.display()));
+            let mut input = std::fs::File::open(&source).map_err(read_error)?;
+            let mut offset = 0;
+            loop {
+                let mut data = Vec::with_capacity(BLOB_CHUNK_SIZE);
+                (&mut input)
+                    .take(BLOB_CHUNK_SIZE as u64)
+                    .read_to_end(&mut data)
+                    .map_err(read_error)?;
+                let len = data.len() as u64;
+                let last = data.len() < BLOB_CHUNK_SIZE;
+                let chunk = CheckpointFileChunk {
+                    checkpoint: checkpoint_ref.clone(),
+                    path: file.clone(),
+                    offset,
+                    data,
+                };
+                match self
+                    .call_idempotent(Request::WriteCheckpointFile(chunk))
+                    .await?
+                {
+                    Response::CheckpointFileWritten => {}
+                    _ => {
+                        return Err(ShimError::runtime(
+                            "Unexpected response type from RPC write checkpoint file request",
+                        ))
+                    }
+                }
+                offset += len;
+                done += len;
+                crate::progress::report(
+                    Operation::ImportCheckpoint,
+                    &target,
+                    "copy",
+                    Some(crate::progress::percent(done, total)),
+                    format!("Copying {}", file),
+                );
+                if last {
+                    break;
+                }
+            }
+        }
+
+        let request = ProgressRequest::ImportCheckpoint(CheckpointProto {
+            container: checkpoint.container,
+            name: checkpoint.name,
+            created_at: checkpoint.created_at,
+            size_bytes: checkpoint.size_bytes,
+            image_digest: checkpoint.image_digest,
+        });
+        match self
+            .call_reporting(Operation::ImportCheckpoint, &target, request)
+            .await?
+        {
+            Response::Checkpoint(c) => {
+                log::info!(
+                    "Imported checkpoint '{}' of '{}' from {}",
+                    c.name,
+                    c.container,
+                    path.display()
+                );
+                Ok(proto_to_checkpoint(c))
+            }
+            _ => Err(ShimError::runtime(
+                "Unexpected response type from RPC import checkpoint request",
+            )),
+        }
+    }
+
+    async fn prune(&self, filter: &PruneFilter) -> Result<Vec<String>> {
+        self.require_feature(features::PRUNE, "pruning containers")?;
+        let mut rpc = self.connect().await?;
+        match rpc.call(Request::Prune(PruneFilterProto {
+            until: filter.until.unwrap_or(0),
+            labels: filter.labels.iter().map(label_filter_proto).collect(),
+        }))? {
+            Response::Pruned(ids) => Ok(ids),
+            Response::Error(e) => Err(ShimError::runtime_with_context(
+                e,
+                "RPC prune request failed",
+            )),
+            _ => Err(ShimError::runtime(
+                "Unexpected response type from RPC prune request",
+            )),
+        }
+    }
+
+    async fn stale_resources(&self, remove: bool) -> Result<Vec<StaleResource>> {
+        self.require_feature(features::STALE_RESOURCES, "finding stale resources")?;
+        let response = if remove {
+            self.connect().await?.call(Request::RemoveStaleResources)?
+        } else {
+            self.call_idempotent(Request::StaleResources).await?
+        };
+        match response {
+            Response::StaleResources(resources) => Ok(resources
+                .into_iter()
+                .map(|r| StaleResource {
+                    kind: r.kind,
+                    id: r.id,
+                    path: r.path,
+                })
+                .collect()),
+            Response::Error(e) => Err(ShimError::runtime_with_context(
+                e,
+                "RPC stale resources request failed",
+            )),
+            _ => Err(ShimError::runtime(
+                "Unexpected response type from RPC stale resources request",
+            )),
+        }
+    }
+
+    async fn available_resources(&self, _id: &str) -> Result<(Option<u64>, Option<u64>)> {
+        // Older agents cannot tell; their containers are not checked
+        let memory = if self.agent.read().unwrap().supports(features::MEMORY_INFO) {
+            match self.call_idempotent(Request::MemoryInfo).await? {
+                Response::MemoryInfo(info) => Some(info.available_bytes),
+                _ => {
+                    return Err(ShimError::runtime(
+                        "Unexpected response type from RPC memory info request",
+                    ))
+                }
+            }
+        } else {
+            None
+        };
+        let disk = if self.agent.read().unwrap().supports(features::DISKS) {
+            // The filesystem holding the agent's image and checkpoint storage
+            let storage = std::path::Path::new(GUEST_STORAGE_DIR);
+            self.disk_usage()
+                .await?
+                .into_iter()
+                .filter(|fs| storage.starts_with(&fs.mount_point))
+                .max_by_key(|fs| fs.mount_point.len())
+                .map(|fs| fs.available_bytes)
+        } else {
+            None
+        };
+        Ok((memory, disk))
+    }
+
+    async fn listening_ports(&self, id: &str) -> Result<Vec<u16>> {
+        self.require_feature(features::LISTENING_PORTS, "listening ports")?;
+        let mut rpc = self.connect().await?;
+        match rpc.call(Request::ListeningPorts(id.to_string()))? {
+            Response::ListeningPorts(ports) => Ok(ports),
+            Response::Error(e) => Err(ShimError::runtime_with_context(
+                e,
+                format!("RPC listening ports request failed for container: {}", id),
+            )),
+            _ => Err(ShimError::runtime(
+                "Unexpected response type from RPC listening ports request",
+            )),
+        }
+    }
+
+    async fn wait(&self, id: &str) -> Result<ExitStatus> {
+        if !self.agent.read().unwrap().supports(features::WAIT) {
+            // Older agents cannot hold a request until the exit, so poll them
+            loop {
+                let info = self
+                    .list()
+                    .await?
+                    .into_iter()
+                    .find(|c| c.id == id)
+                    .ok_or_else(|| ShimError::not_found(format!("Container '{}'", id)))?;
+                if info.status == ContainerStatus::Stopped {
+                    return Ok(ExitStatus {
+                        code: info.last_exit_code,
+                        reason: info.last_exit_reason,
+                    });
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+            }
+        }
+        // The agent answers once the container exits, so the connection is
+        // read by a blocking thread meanwhile
+        let mut rpc = self.connect().await?;
+        let request = Request::Wait(id.to_string());
+        let response = tokio::task::spawn_blocking(move || rpc.call(request))
+            .await
+            .map_err(|e| ShimError::runtime(format!("Wait for container failed: {}", e)))??;
+        match response {
+            Response::Exited(status) => Ok(ExitStatus {
+                code: status.exit_code,
+                reason: status.exit_reason,
+            }),
+            Response::Error(e) => Err(ShimError::runtime_with_context(
+                e,
+                format!("RPC wait request failed for container: {}", id),
+            )),
+            _ => Err(ShimError::runtime(
+                "Unexpected response type from RPC wait request",
+            )),
+        }
+    }
+
+    async fn total_resources(&self) -> Result<Reservation> {
+        // The kernel keeps some of the VM's memory; newer agents say how much is left
+        let memory = if self.agent.read().unwrap().supports(features::MEMORY_INFO) {
+            match self.call_idempotent(Request::MemoryInfo).await? {
+                Response::MemoryInfo(info) => info.total_bytes,
+                _ => {
+                    return Err(ShimError::runtime(
+                        "Unexpected response type from RPC memory info request",
+                    ))
+                }
+            }
+        } else {
+            self.config.vm_memory
+        };
+        Ok(Reservation {
+            cpus: self.config.vm_cpus as f64,
+            memory,
+        })
+    }
+
+    async fn copy_to(
+        &self,
+        id: &str,
+        source: &std::path::Path,
+        destination: &str,
+        options: &CopyOptions,
+    ) -> Result<CopyStats> {
+        self.require_feature(features::COPY, "copying files into containers")?;
+        let options = crate::copy::options_proto(options);
+        let context = |e| {
+            ShimError::io_with_context(
+                e,
+                format!("Copying {} to {}:{}", source.display(), id, destination),
+            )
+        };
+        let total =
+            archive::measure(std::path::Path::new("/"), source, &options).map_err(context)?;
+        let mut progress = crate::copy::Progress::new(format!("{}:{}", id, destination), total);
+        let request = CopyRequest {
+            id: id.to_string(),
+            path: destination.to_string(),
+            direction: CopyDirection::In,
+            options,
+        };
+        let stats = self
+            .connect()
+            .await?
+            .copy_in(request, source, |done| progress.update(done))?;
+        log::info!("Copied {} to '{}' at {}", source.display(), id, destination);
+        Ok(crate::copy::proto_to_stats(stats))
+    }
+
+    async fn copy_from(
+        &self,
+        id: &str,
+        source: &str,
+        destination: &std::path::Path,
+        options: &CopyOptions,
+    ) -> Result<CopyStats> {
+        self.require_feature(features::COPY, "copying files out of containers")?;
+        let target =
+            archive::Target::resolve(std::path::Path::new("/"), destination).map_err(|e| {
+                ShimError::io_with_context(
+                    e,
+                    format!("Copying {}:{} to {}", id, source, destination.display()),
+                )
+            })?;
+        let label = format!("{}:{}", id, source);
+        // The agent measures the source, so progress starts with its total
+        let mut progress = None;
+        let request = CopyRequest {
+            id: id.to_string(),
+            path: source.to_string(),
+            direction: CopyDirection::Out,
+            options: crate::copy::options_proto(options),
+        };
+        let stats = self
+            .connect()
+            .await?
+            .copy_out(request, &target, |done, total| {
+                progress
+                    .get_or_insert_with(|| crate::copy::Progress::new(label.clone(), *total))
+                    .update(done)
+            })?;
+        log::info!("Copied {} of '{}' to {}", source, id, destination.display());
+        Ok(crate::copy::proto_to_stats(stats))
+    }
+}
+
+fn logs_request(id: &str, options: &LogOptions) -> libcrun_shim_proto::LogsRequest {
+    libcrun_shim_proto::LogsRequest {
+        id: id.to_string(),
+        tail: options.tail,
+        since: options.since,
+        timestamps: options.timestamps,
+        max_bytes: options.max_bytes,
+        stdout_offset: options.stdout_offset,
+        stderr_offset: options.stderr_offset,
+        until: options.until,
+    }
+}
+
+fn label_filter_proto(l: &LabelFilter) -> LabelFilterProto {
+    LabelFilterProto {
+        key: l.key.clone(),
+        value: l.value.clone(),
+        negate: l.negate,
+    }
+}
+
+fn proto_to_logs(l: LogsProto) -> ContainerLogs {
+    let mut logs = ContainerLogs {
+        id: l.id,
+        stdout: l.stdout,
+        stderr: l.stderr,
+        timestamp: l.timestamp,
+        stdout_offset: l.stdout_offset,
+        stderr_offset: l.stderr_offset,
+        truncated: l.truncated,
+        entries: Vec::with_capacity(l.entries.len()),
+    };
+    // Structured logs only come as entries, the streams' text is theirs
+    for e in l.entries {
+        let text = String::from_utf8_lossy(&e.payload);
+        let stream = if e.stream == "stderr" {
+            logs.stderr.push_str(&text);
+            LogStream::Stderr
+        } else {
+            logs.stdout.push_str(&text);
+            LogStream::Stdout
+        };
+        logs.entries.push(LogEntry {
+            stream,
+            timestamp_ns: e.timestamp_ns,
+            seq: e.seq,
+            payload: e.payload,
+        });
+    }
+    logs
+}
+
+fn proto_to_event(e: EventProto) -> ContainerEvent {
+    let event_type = match e.kind {
+        EventKind::Start => ContainerEventType::Start,
+        EventKind::Stop => ContainerEventType::Stop,
+        EventKind::Kill => ContainerEventType::Kill,
+        EventKind::Die => ContainerEventType::Die,
+        EventKind::Oom => ContainerEventType::Oom,
+        EventKind::HealthOk => ContainerEventType::HealthOk,
+        EventKind::HealthFail => ContainerEventType::HealthFail,
+    };
+    ContainerEvent {
+        event_type,
+        container_id: e.container_id,
+        timestamp: e.timestamp,
+        exit_code: e.exit_code,
+        signal: e.signal,
+        attributes: e.attributes.into_iter().collect(),
+    }
 }
 
-/// Convert proto metrics to local types
-fn proto_to_metrics(m: libcrun_shim_proto::ContainerMetricsProto) -> ContainerMetrics {
-    ContainerMetrics {
-        id: m.id,
-        timestamp: m.timestamp,
-        cpu: CpuMetrics {
-            usage_total: m.cpu.usage_total,
-            usage_user: m.cpu.usage_user,
-            usage_system: m.cpu.usage_system,
-            per_cpu: m.cpu.per_cpu,
-            throttled_periods: m.cpu.throttled_periods,
-            throttled_time: m.cpu.throttled_time,
-            usage_percent: m.cpu.usage_percent,
-        },
-        memory: MemoryMetrics {
-            usage: m.memory.usage,
-            max_usage: m.memory.max_usage,
-            limit: m.memory.limit,
-            cache: m.memory.cache,
-            rss: m.memory.rss,
-            swap: m.memory.swap,
-            usage_percent: m.memory.usage_percent,
-        },
-        blkio: BlkioMetrics {
-            read_bytes: m.blkio.read_bytes,
-            write_bytes: m.blkio.write_bytes,
-            read_ops: m.blkio.read_ops,
-            write_ops: m.blkio.write_ops,
-        },
-        network: NetworkMetrics {
-            rx_bytes: m.network.rx_bytes,
-            tx_bytes: m.network.tx_bytes,
-            rx_packets: m.network.rx_packets,
-            tx_packets: m.network.tx_packets,
-            rx_errors: m.network.rx_errors,
-            tx_errors: m.network.tx_errors,
-            rx_dropped: m.network.rx_dropped,
-            tx_dropped: m.network.tx_dropped,
-        },
-        pids: PidsMetrics {
-            current: m.pids.current,
-            limit: m.pids.limit,
-        },
+fn proto_to_checkpoint(c: CheckpointProto) -> Checkpoint {
+    Checkpoint {
+        container: c.container,
+        name: c.name,
+        created_at: c.created_at,
+        size_bytes: c.size_bytes,
+        image_digest: c.image_digest,
     }
 }
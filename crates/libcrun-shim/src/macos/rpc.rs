@@ -3,6 +3,10 @@ use crate::types::RuntimeConfig;
 use crate::*;
 use libcrun_shim_proto::*;
 use std::io::{Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// ID of the next request sent with progress, unique in this process
+static NEXT_PROGRESS_ID: AtomicU64 = AtomicU64::new(1);
 
 pub struct RpcClient {
     stream: VsockStream,
@@ -61,17 +65,401 @@ impl RpcClient {
         Self { stream }
     }
 
+    /// Send a request and wait for its response
+    ///
+    /// Failures, including error responses, are journaled for support
+    /// bundles.
     pub fn call(&mut self, request: Request) -> Result<Response> {
-        let data = serialize_request(&request);
-        self.stream.write_all(&data)?;
-        self.stream.flush()?;
+        let result = self.exchange(&request);
+        record_failure(&request, &result);
+        result
+    }
+
+    /// Send a request and wait for its response, passing the phases the
+    /// agent reports meanwhile to `on_progress`
+    ///
+    /// Only for agents with [`features::PROGRESS`].
+    pub fn call_with_progress(
+        &mut self,
+        request: ProgressRequest,
+        mut on_progress: impl FnMut(ProgressProto),
+    ) -> Result<Response> {
+        let id = NEXT_PROGRESS_ID.fetch_add(1, Ordering::Relaxed);
+        let request = Request::WithProgress(id, request);
+        let mut result = self.exchange(&request);
+        while let Ok(Response::Progress(progress)) = result {
+            if progress.request_id == id {
+                on_progress(progress);
+            }
+            result = self.receive();
+        }
+        record_failure(&request, &result);
+        result
+    }
+
+    fn exchange(&mut self, request: &Request) -> Result<Response> {
+        let data = serialize_request(request)
+            .map_err(|e| ShimError::serialization("Failed to serialize RPC request", e))?;
+        write_frame(&mut self.stream, &data)?;
+        self.receive()
+    }
+
+    fn receive(&mut self) -> Result<Response> {
+        // The agent may be restarting, e.g. during an upgrade
+        let frame = read_frame(&mut self.stream)?.ok_or_else(|| ShimError::Unavailable {
+            message: "Agent closed the connection".to_string(),
+            context: Some("No response received for RPC request".to_string()),
+        })?;
 
-        let mut buffer = vec![0u8; 4096];
-        let n = self.stream.read(&mut buffer)?;
+        match deserialize_response(&frame) {
+            // Coded agent errors become typed errors for every request
+            Ok(Response::Failed(e)) => Err(e.into()),
+            Ok(response) => Ok(response),
+            Err(e) => Err(ShimError::serialization(
+                "Failed to deserialize RPC response",
+                e,
+            )),
+        }
+    }
+
+    /// Follow a container's logs, passing each batch the agent sends to
+    /// `on_logs` until the agent closes the connection or `on_logs` returns
+    /// false
+    ///
+    /// Following takes over the connection, so the client is consumed.
+    pub fn follow_logs(
+        mut self,
+        request: LogsRequest,
+        mut on_logs: impl FnMut(LogsProto) -> bool,
+    ) -> Result<()> {
+        let id = request.id.clone();
+        let mut response = self.call(Request::LogsStream(request))?;
+        loop {
+            match response {
+                Response::Logs(logs) => {
+                    if !on_logs(logs) {
+                        return Ok(());
+                    }
+                }
+                Response::Error(e) => {
+                    return Err(ShimError::runtime_with_context(
+                        e,
+                        format!("RPC logs request failed for container: {}", id),
+                    ))
+                }
+                _ => {
+                    return Err(ShimError::runtime(
+                        "Unexpected response type from RPC logs stream request",
+                    ))
+                }
+            }
+            // The agent closes the connection once the container has stopped
+            let Some(frame) = read_frame(&mut self.stream)? else {
+                return Ok(());
+            };
+            response = deserialize_response(&frame)
+                .map_err(|e| ShimError::serialization("Failed to deserialize RPC response", e))?;
+        }
+    }
+
+    /// Subscribe to the agent's events, passing each batch it sends to
+    /// `on_events` until the agent closes the connection or `on_events`
+    /// returns false
+    ///
+    /// The first batch, sent once subscribed, is empty, as are the
+    /// heartbeats. The subscription takes over the connection, so the client
+    /// is consumed.
+    pub fn subscribe_events(
+        mut self,
+        mut on_events: impl FnMut(Vec<EventProto>) -> bool,
+    ) -> Result<()> {
+        let mut response = self.call(Request::SubscribeEvents)?;
+        loop {
+            match response {
+                Response::Events(events) => {
+                    if !on_events(events) {
+                        return Ok(());
+                    }
+                }
+                Response::Error(e) => {
+                    return Err(ShimError::runtime_with_context(
+                        e,
+                        "RPC subscribe events request failed",
+                    ))
+                }
+                _ => {
+                    return Err(ShimError::runtime(
+                        "Unexpected response type from RPC subscribe events request",
+                    ))
+                }
+            }
+            let Some(frame) = read_frame(&mut self.stream)? else {
+                return Ok(());
+            };
+            response = deserialize_response(&frame)
+                .map_err(|e| ShimError::serialization("Failed to deserialize RPC response", e))?;
+        }
+    }
+
+    /// Start an interactive exec and relay its I/O to and from `stdio` until
+    /// it exits or is detached from
+    ///
+    /// The session takes over the connection, so the client is consumed.
+    /// Input is sent from a thread of its own, which is left blocked on
+    /// `stdio.stdin` if the command exits first.
+    pub fn exec_stream(
+        mut self,
+        request: ExecStreamRequest,
+        stdio: ExecStdio,
+    ) -> Result<ExecOutcome> {
+        let id = request.exec.id.clone();
+        match self.call(Request::ExecStream(request))? {
+            Response::ExecStarted => {}
+            Response::Error(e) => {
+                return Err(ShimError::runtime_with_context(
+                    e,
+                    format!("RPC exec request failed for container: {}", id),
+                ))
+            }
+            _ => {
+                return Err(ShimError::runtime(
+                    "Unexpected response type from RPC exec stream request",
+                ))
+            }
+        }
+        self.relay_exec(stdio, format!("Container ID: {}", id))
+    }
+
+    /// Attach to a detached exec session, then relay its I/O as
+    /// [`Self::exec_stream`] does
+    pub fn attach_exec(mut self, session: &str, stdio: ExecStdio) -> Result<ExecOutcome> {
+        match self.call(Request::AttachExec(session.to_string()))? {
+            Response::ExecStarted => {}
+            _ => {
+                return Err(ShimError::runtime(
+                    "Unexpected response type from RPC attach exec request",
+                ))
+            }
+        }
+        // The terminal may have changed size since the session was detached
+        if let Some((rows, cols)) = stdio.terminal_size {
+            let data = serialize_exec_frame(&ExecFrame::Resize(rows, cols))
+                .map_err(|e| ShimError::serialization("Failed to serialize exec frame", e))?;
+            write_frame(&mut self.stream, &data)?;
+        }
+        self.relay_exec(stdio, format!("Exec session: {}", session))
+    }
+
+    fn relay_exec(mut self, stdio: ExecStdio, context: String) -> Result<ExecOutcome> {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::{Arc, Mutex};
+
+        let ExecStdio {
+            mut stdin,
+            mut stdout,
+            mut stderr,
+            resize,
+            detach_keys,
+            ..
+        } = stdio;
+        let writer = Arc::new(Mutex::new(self.stream.try_clone()?));
+        let send = |writer: &Mutex<VsockStream>, frame: &ExecFrame| -> std::io::Result<()> {
+            let data = serialize_exec_frame(frame)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+            write_frame(&mut *writer.lock().unwrap(), &data)
+        };
+        let detached = Arc::new(AtomicBool::new(false));
+
+        let input = Arc::clone(&writer);
+        let detaching = Arc::clone(&detached);
+        std::thread::spawn(move || {
+            let mut filter = detach_keys.as_ref().map(DetachKeys::filter);
+            let mut buf = [0u8; 16 * 1024];
+            loop {
+                let n = stdin.read(&mut buf).unwrap_or(0);
+                let mut data = Vec::new();
+                let mut detach = false;
+                match filter.as_mut() {
+                    Some(filter) if n == 0 => filter.flush(&mut data),
+                    Some(filter) => detach = filter.feed(&buf[..n], &mut data),
+                    None => data.extend_from_slice(&buf[..n]),
+                }
+                if !data.is_empty() && send(&input, &ExecFrame::Stdin(data)).is_err() {
+                    break;
+                }
+                if detach {
+                    detaching.store(true, Ordering::SeqCst);
+                    let _ = send(&input, &ExecFrame::Detach);
+                    break;
+                }
+                if n == 0 {
+                    let _ = send(&input, &ExecFrame::CloseStdin);
+                    break;
+                }
+            }
+        });
+        if let Some(resize) = resize {
+            let sizes = Arc::clone(&writer);
+            std::thread::spawn(move || {
+                for (rows, cols) in resize {
+                    if send(&sizes, &ExecFrame::Resize(rows, cols)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        let mut session = None;
+        loop {
+            let frame = match read_frame(&mut self.stream)? {
+                Some(frame) => frame,
+                None => {
+                    // The agent hangs up once it has the detach request
+                    if let (true, Some(session)) = (detached.load(Ordering::SeqCst), &session) {
+                        return Ok(ExecOutcome::Detached(session.clone()));
+                    }
+                    let context = match &session {
+                        Some(session) => {
+                            format!("{}, still running as exec session {}", context, session)
+                        }
+                        None => context,
+                    };
+                    return Err(ShimError::Unavailable {
+                        message: "Agent closed the connection during exec".to_string(),
+                        context: Some(context),
+                    });
+                }
+            };
+            let frame = deserialize_exec_frame(&frame)
+                .map_err(|e| ShimError::serialization("Failed to deserialize exec frame", e))?;
+            // Output is flushed as it comes, so prompts show up
+            let written = match frame {
+                ExecFrame::Stdout(data) => stdout.write_all(&data).and_then(|_| stdout.flush()),
+                ExecFrame::Stderr(data) => stderr.write_all(&data).and_then(|_| stderr.flush()),
+                ExecFrame::Session(id) => {
+                    session = Some(id);
+                    Ok(())
+                }
+                ExecFrame::Exit(code) => return Ok(ExecOutcome::Exited(code)),
+                ExecFrame::Failed(e) => {
+                    return Err(ShimError::runtime_with_context(
+                        e,
+                        format!("RPC exec request failed: {}", context),
+                    ))
+                }
+                frame => {
+                    log::debug!("Ignoring exec frame {:?}", frame);
+                    Ok(())
+                }
+            };
+            written?;
+        }
+    }
+
+    /// Copy `source` on the host into a container, passing the running
+    /// totals to `progress` as it is packed
+    ///
+    /// The copy takes over the connection, so the client is consumed.
+    pub fn copy_in(
+        mut self,
+        request: CopyRequest,
+        source: &std::path::Path,
+        progress: impl FnMut(&CopyStatsProto),
+    ) -> Result<CopyStatsProto> {
+        let options = request.options.clone();
+        let context = format!("Container ID: {}", request.id);
+        self.start_copy(request)?;
+
+        let mut writer = archive::FrameWriter::new(&mut self.stream);
+        let packed = archive::pack(
+            std::path::Path::new("/"),
+            source,
+            &mut writer,
+            &options,
+            progress,
+        )
+        .and_then(|_| writer.finish().map(|_| ()));
+        if let Err(e) = packed {
+            // The agent answers with the failure, its own if it had one first
+            log::debug!("Copy to the agent failed: {}", e);
+            let _ = archive::send_frame(&mut self.stream, &CopyFrame::Failed(e.to_string()));
+        }
+        self.finish_copy(context)
+    }
+
+    /// Copy out of a container and unpack at `target` on the host, passing
+    /// the running totals and what the agent is sending in all to `progress`
+    ///
+    /// The copy takes over the connection, so the client is consumed.
+    pub fn copy_out(
+        mut self,
+        request: CopyRequest,
+        target: &archive::Target,
+        mut progress: impl FnMut(&CopyStatsProto, &CopyStatsProto),
+    ) -> Result<CopyStatsProto> {
+        let options = request.options.clone();
+        let context = format!("Container ID: {}", request.id);
+        self.start_copy(request)?;
+
+        let total = match archive::receive_frame(&mut self.stream)? {
+            Some(CopyFrame::Total(total)) => total,
+            Some(CopyFrame::Failed(e)) => {
+                return Err(ShimError::runtime_with_context(
+                    e,
+                    format!("RPC copy request failed: {}", context),
+                ))
+            }
+            _ => return Err(ShimError::runtime("Unexpected frame from RPC copy request")),
+        };
+        let mut reader = archive::FrameReader::new(&mut self.stream);
+        let unpacked =
+            archive::unpack(&mut reader, target, &options, |done| progress(done, &total))
+                .and_then(|_| reader.finish().map(|_| ()));
+        match unpacked {
+            Ok(()) => self.finish_copy(context),
+            // What the agent failed with, if it did, explains more
+            Err(e) => Err(self
+                .finish_copy(context)
+                .err()
+                .unwrap_or_else(|| ShimError::io_with_context(e, "Unpacking the copy"))),
+        }
+    }
+
+    fn start_copy(&mut self, request: CopyRequest) -> Result<()> {
+        let id = request.id.clone();
+        match self.call(Request::Copy(request))? {
+            Response::CopyStarted => Ok(()),
+            Response::Error(e) => Err(ShimError::runtime_with_context(
+                e,
+                format!("RPC copy request failed for container: {}", id),
+            )),
+            _ => Err(ShimError::runtime(
+                "Unexpected response type from RPC copy request",
+            )),
+        }
+    }
+
+    /// Read how the agent ended the copy
+    fn finish_copy(&mut self, context: String) -> Result<CopyStatsProto> {
+        match archive::receive_frame(&mut self.stream)? {
+            Some(CopyFrame::Done(stats)) => Ok(stats),
+            Some(CopyFrame::Failed(e)) => Err(ShimError::runtime_with_context(
+                e,
+                format!("RPC copy request failed: {}", context),
+            )),
+            Some(_) => Err(ShimError::runtime("Unexpected frame from RPC copy request")),
+            None => Err(ShimError::Unavailable {
+                message: "Agent closed the connection during copy".to_string(),
+                context: Some(context),
+            }),
+        }
+    }
+}
 
-        deserialize_response(&buffer[..n]).map_err(|e| ShimError::Serialization {
-            message: e.to_string(),
-            context: Some("Failed to deserialize RPC response".to_string()),
-        })
+fn record_failure(request: &Request, result: &Result<Response>) {
+    match result {
+        Err(e) => crate::support::record_failed_rpc(request, &e.to_string()),
+        Ok(Response::Error(e)) => crate::support::record_failed_rpc(request, e),
+        Ok(_) => {}
     }
 }
@@ -40,6 +40,12 @@ extern "C" {
     fn vm_bridge_can_start(handle: *mut c_void) -> bool;
     fn vm_bridge_can_stop(handle: *mut c_void) -> bool;
     fn vm_bridge_list_network_interfaces(callback: extern "C" fn(*const c_char));
+    fn vm_bridge_add_share(
+        handle: *mut c_void,
+        name: *const c_char,
+        path: *const c_char,
+        read_only: bool,
+    ) -> bool;
 }
 
 // Global state for async completion - used by callbacks
@@ -80,6 +86,91 @@ extern "C" fn vm_stop_callback(success: bool, error_msg: *const c_char) {
     VM_STOP_COMPLETE.store(true, Ordering::SeqCst);
 }
 
+/// How long to wait for another process to boot the VM before giving up
+#[cfg(target_os = "macos")]
+const BOOT_LOCK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(120);
+
+/// Exclusive advisory lock on `<socket path>.lock`, held by the process
+/// whose VM serves the agent socket
+///
+/// The VM lives in the process that booted it, so concurrent invocations
+/// must not each boot one. `flock` locks are released when the process
+/// exits, so a crashed owner never leaves the lock behind.
+#[cfg(target_os = "macos")]
+struct BootLock {
+    _file: std::fs::File,
+}
+
+#[cfg(target_os = "macos")]
+impl BootLock {
+    fn path(config: &RuntimeConfig) -> PathBuf {
+        let mut path = config.socket_path.clone().into_os_string();
+        path.push(".lock");
+        PathBuf::from(path)
+    }
+
+    /// Take the lock at `path`, or `None` if another process holds it
+    fn try_acquire(path: &std::path::Path) -> Result<Option<Self>> {
+        use std::os::unix::io::AsRawFd;
+
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .map_err(|e| {
+                ShimError::io_with_context(e, format!("Failed to open {}", path.display()))
+            })?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+            return Ok(Some(Self { _file: file }));
+        }
+        let error = std::io::Error::last_os_error();
+        if error.raw_os_error() == Some(libc::EWOULDBLOCK) {
+            Ok(None)
+        } else {
+            Err(ShimError::io_with_context(
+                error,
+                format!("Failed to lock {}", path.display()),
+            ))
+        }
+    }
+
+    /// Become the process that boots the VM, or `None` once the agent of
+    /// the VM another process booted accepts connections
+    ///
+    /// If the other process exits before its agent is up, this one takes
+    /// over the boot.
+    async fn elect(config: &RuntimeConfig) -> Result<Option<Self>> {
+        let path = Self::path(config);
+        let deadline = std::time::Instant::now() + BOOT_LOCK_TIMEOUT;
+        let mut waiting = false;
+        loop {
+            if let Some(lock) = Self::try_acquire(&path)? {
+                return Ok(Some(lock));
+            }
+            if crate::socket::probe_agent(&config.socket_path) == crate::socket::SocketState::Live {
+                log::info!("Reusing the VM booted by another process");
+                return Ok(None);
+            }
+            if !waiting {
+                log::info!("Waiting for another process to boot the VM");
+                waiting = true;
+            }
+            if std::time::Instant::now() >= deadline {
+                return Err(ShimError::runtime_with_context(
+                    "Timed out waiting for another process to boot the VM",
+                    format!(
+                        "Lock file: {}. Its agent socket {} never came up.",
+                        path.display(),
+                        config.socket_path.display()
+                    ),
+                ));
+            }
+            tokio::time::sleep(tokio::time::Duration::from_millis(250)).await;
+        }
+    }
+}
+
 /// Virtual Machine wrapper using Apple's Virtualization Framework via Swift bridge
 #[allow(dead_code)]
 pub struct VirtualMachine {
@@ -91,6 +182,9 @@ pub struct VirtualMachine {
     config: RuntimeConfig,
     #[cfg(target_os = "macos")]
     vm_bridge_handle: Option<*mut c_void>,
+    /// Held while this process owns the VM
+    #[cfg(target_os = "macos")]
+    boot_lock: Option<BootLock>,
 }
 
 // VirtualMachine is not Send/Sync due to raw pointer, but we only use it on main thread
@@ -135,6 +229,11 @@ impl VirtualMachine {
             log::info!("Found VM kernel: {}", kernel_path_val.display());
             log::info!("Found VM initramfs: {}", initramfs_path_val.display());
 
+            // Only one process boots the VM, the others use it
+            let Some(boot_lock) = BootLock::elect(&config).await? else {
+                return Self::start_fallback(config);
+            };
+
             // Create Swift bridge
             let bridge_handle = unsafe { vm_bridge_create() };
             if bridge_handle.is_null() {
@@ -292,6 +391,7 @@ impl VirtualMachine {
                     vsock_port: config.vsock_port,
                     config,
                     vm_bridge_handle: Some(bridge_handle),
+                    boot_lock: Some(boot_lock),
                 })
             } else {
                 log::warn!("VM start failed via Swift bridge, using fallback mode");
@@ -307,7 +407,7 @@ impl VirtualMachine {
     }
 
     #[cfg(target_os = "macos")]
-    fn is_virtualization_available() -> bool {
+    pub(crate) fn is_virtualization_available() -> bool {
         let class = Class::get("VZVirtualMachineConfiguration");
         class.is_some()
     }
@@ -325,6 +425,8 @@ impl VirtualMachine {
             config,
             #[cfg(target_os = "macos")]
             vm_bridge_handle: None,
+            #[cfg(target_os = "macos")]
+            boot_lock: None,
         })
     }
 
@@ -345,6 +447,31 @@ impl VirtualMachine {
         self.vm_bridge_handle
     }
 
+    /// Share a host directory with the running VM
+    ///
+    /// The guest sees it as `name` on the [`libcrun_shim_proto::SHARES_TAG`]
+    /// virtiofs device.
+    #[cfg(target_os = "macos")]
+    pub fn add_share(&self, name: &str, path: &std::path::Path, read_only: bool) -> Result<()> {
+        let Some(handle) = self.vm_bridge_handle else {
+            return Err(ShimError::runtime_with_context(
+                "Cannot share directories with an external VM",
+                "Share the directory with the VM yourself, or let the runtime start the VM",
+            ));
+        };
+        let name = CString::new(name).map_err(|e| ShimError::validation("share", e.to_string()))?;
+        let path = CString::new(path.to_string_lossy().as_ref())
+            .map_err(|e| ShimError::validation("source", e.to_string()))?;
+        if unsafe { vm_bridge_add_share(handle, name.as_ptr(), path.as_ptr(), read_only) } {
+            Ok(())
+        } else {
+            Err(ShimError::runtime_with_context(
+                "Failed to share the directory with the VM",
+                "Adding shares to a running VM needs macOS 13 or later",
+            ))
+        }
+    }
+
     /// Get VM state (0=starting, 1=stopped, 2=paused, 3=running, 4=error)
     #[cfg(target_os = "macos")]
     #[allow(dead_code)]
@@ -356,7 +483,7 @@ impl VirtualMachine {
         }
     }
 
-    fn find_vm_asset(name: &str, search_paths: &[PathBuf]) -> Option<PathBuf> {
+    pub(crate) fn find_vm_asset(name: &str, search_paths: &[PathBuf]) -> Option<PathBuf> {
         // First, check directly provided paths
         for base_path in search_paths {
             // Check the base path directly (if it's a file)
@@ -444,6 +571,8 @@ impl VirtualMachine {
                 }
                 self.vm_bridge_handle = None;
             }
+            // The next process to start boots the VM again
+            self.boot_lock = None;
         }
 
         self.is_running = false;
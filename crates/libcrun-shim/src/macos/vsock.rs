@@ -117,11 +117,11 @@ impl VsockClient {
 
                 while !VSOCK_CONNECT_COMPLETE.load(Ordering::SeqCst) {
                     if start_time.elapsed().as_millis() as u64 > timeout_ms {
-                        log::warn!(
-                            "Vsock connection timed out after {}s, falling back to Unix socket",
+                        // Falling back is up to the transport order
+                        return Err(ShimError::runtime(format!(
+                            "Vsock connection timed out after {}s",
                             self.connection_timeout
-                        );
-                        return self.connect_unix_socket();
+                        )));
                     }
                     std::thread::sleep(std::time::Duration::from_millis(10));
                 }
@@ -139,7 +139,7 @@ impl VsockClient {
                     // #endregion
                     return Ok(VsockStream::VsockFd(VsockStreamFd::new(fd)));
                 } else {
-                    log::warn!("Vsock connection failed, falling back to Unix socket");
+                    log::warn!("Vsock connection failed");
                     // #region host log
                     let _ = std::fs::OpenOptions::new().create(true).append(true).open("/Users/user/libcrun-shim/.cursor/debug.log").and_then(|mut f| {
                         use std::io::Write;
@@ -148,7 +148,10 @@ impl VsockClient {
                             std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis(), fd, self.port)
                     });
                     // #endregion
-                    return self.connect_unix_socket();
+                    return Err(ShimError::runtime(format!(
+                        "Vsock connection to port {} failed",
+                        self.port
+                    )));
                 }
             } else {
                 log::debug!("No VM bridge handle, using Unix socket fallback");
@@ -163,20 +166,11 @@ impl VsockClient {
     }
 
     fn connect_unix_socket(&self) -> Result<VsockStream> {
-        use std::os::unix::net::UnixStream;
         log::debug!(
             "Connecting to Unix socket at: {}",
             self.socket_path.display()
         );
-        let stream = UnixStream::connect(&self.socket_path).map_err(|e| {
-            ShimError::runtime_with_context(
-                format!("Failed to connect via Unix socket: {}", e),
-                format!(
-                    "Ensure agent is running and socket is available at: {}",
-                    self.socket_path.display()
-                ),
-            )
-        })?;
+        let stream = crate::socket::connect(&self.socket_path, "The agent")?;
         log::info!(
             "Unix socket connection established at: {}",
             self.socket_path.display()
@@ -216,6 +210,23 @@ impl Drop for VsockStreamFd {
     }
 }
 
+impl VsockStream {
+    /// A second handle to the same connection, e.g. to write from another thread
+    pub fn try_clone(&self) -> std::io::Result<Self> {
+        match self {
+            VsockStream::Unix(stream) => stream.try_clone().map(VsockStream::Unix),
+            #[cfg(target_os = "macos")]
+            VsockStream::VsockFd(stream) => {
+                let fd = unsafe { libc::dup(stream.fd) };
+                if fd < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(VsockStream::VsockFd(VsockStreamFd::new(fd)))
+            }
+        }
+    }
+}
+
 impl Read for VsockStream {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         match self {
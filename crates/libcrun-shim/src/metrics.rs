@@ -0,0 +1,56 @@
+//! Container metrics on the host
+//!
+//! Both runtimes read a container's metrics as the proto types: the macOS one
+//! gets them from the agent, the Linux one reads the cgroup through
+//! [`libcrun_shim_proto::cgroup`] as the agent does. This turns them into the
+//! public types.
+
+use crate::{
+    BlkioMetrics, ContainerMetrics, CpuMetrics, MemoryMetrics, NetworkMetrics, PidsMetrics,
+};
+use libcrun_shim_proto::ContainerMetricsProto;
+
+pub(crate) fn proto_to_metrics(m: ContainerMetricsProto) -> ContainerMetrics {
+    ContainerMetrics {
+        id: m.id,
+        timestamp: m.timestamp,
+        cpu: CpuMetrics {
+            usage_total: m.cpu.usage_total,
+            usage_user: m.cpu.usage_user,
+            usage_system: m.cpu.usage_system,
+            per_cpu: m.cpu.per_cpu,
+            throttled_periods: m.cpu.throttled_periods,
+            throttled_time: m.cpu.throttled_time,
+            usage_percent: m.cpu.usage_percent,
+        },
+        memory: MemoryMetrics {
+            usage: m.memory.usage,
+            max_usage: m.memory.max_usage,
+            limit: m.memory.limit,
+            cache: m.memory.cache,
+            rss: m.memory.rss,
+            swap: m.memory.swap,
+            usage_percent: m.memory.usage_percent,
+        },
+        blkio: BlkioMetrics {
+            read_bytes: m.blkio.read_bytes,
+            write_bytes: m.blkio.write_bytes,
+            read_ops: m.blkio.read_ops,
+            write_ops: m.blkio.write_ops,
+        },
+        network: NetworkMetrics {
+            rx_bytes: m.network.rx_bytes,
+            tx_bytes: m.network.tx_bytes,
+            rx_packets: m.network.rx_packets,
+            tx_packets: m.network.tx_packets,
+            rx_errors: m.network.rx_errors,
+            tx_errors: m.network.tx_errors,
+            rx_dropped: m.network.rx_dropped,
+            tx_dropped: m.network.tx_dropped,
+        },
+        pids: PidsMetrics {
+            current: m.pids.current,
+            limit: m.pids.limit,
+        },
+    }
+}
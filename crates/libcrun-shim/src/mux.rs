@@ -0,0 +1,123 @@
+//! Docker's multiplexed stream format, for the output of an exec without a
+//! terminal
+//!
+//! Output of both streams goes to one writer in chunks, each after an 8-byte
+//! header: the stream (1 for stdout, 2 for stderr), three zero bytes, and
+//! the length of the chunk as a big-endian `u32`. Readers can then tell the
+//! streams apart, as with `docker exec` over the Engine API.
+
+use crate::LogStream;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+
+const HEADER_SIZE: usize = 8;
+
+/// Writers for the stdout and stderr of an exec, e.g. in
+/// [`crate::ExecStdio`], that interleave their output in `output`
+pub fn multiplex<W: Write + Send + 'static>(
+    output: W,
+) -> (Box<dyn Write + Send>, Box<dyn Write + Send>) {
+    let output = Arc::new(Mutex::new(output));
+    let stdout = Multiplexed {
+        output: Arc::clone(&output),
+        stream: LogStream::Stdout,
+    };
+    let stderr = Multiplexed {
+        output,
+        stream: LogStream::Stderr,
+    };
+    (Box::new(stdout), Box::new(stderr))
+}
+
+/// The next chunk of a multiplexed stream and the stream it was written
+/// to, or `None` at its end
+pub fn read_multiplexed(input: &mut impl Read) -> std::io::Result<Option<(LogStream, Vec<u8>)>> {
+    let mut header = [0u8; HEADER_SIZE];
+    let mut filled = 0;
+    while filled < HEADER_SIZE {
+        match input.read(&mut header[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    let stream = match header[0] {
+        1 => LogStream::Stdout,
+        2 => LogStream::Stderr,
+        other => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("unknown stream {} in multiplexed output", other),
+            ))
+        }
+    };
+    let len = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    let mut data = vec![0u8; len];
+    input.read_exact(&mut data)?;
+    Ok(Some((stream, data)))
+}
+
+struct Multiplexed<W> {
+    output: Arc<Mutex<W>>,
+    stream: LogStream,
+}
+
+impl<W: Write> Write for Multiplexed<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let len = buf.len().min(u32::MAX as usize);
+        let mut header = [0u8; HEADER_SIZE];
+        header[0] = match self.stream {
+            LogStream::Stdout => 1,
+            LogStream::Stderr => 2,
+        };
+        header[4..].copy_from_slice(&(len as u32).to_be_bytes());
+        // Header and chunk under one lock, so the streams never mix
+        let mut output = self.output.lock().unwrap();
+        output.write_all(&header)?;
+        output.write_all(&buf[..len])?;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.output.lock().unwrap().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multiplex() {
+        let (mut reader, writer) = std::os::unix::net::UnixStream::pair().unwrap();
+        let (mut stdout, mut stderr) = multiplex(writer);
+        stdout.write_all(b"out\n").unwrap();
+        stderr.write_all(b"err").unwrap();
+        stdout.write_all(b"").unwrap();
+        stdout.write_all(b"more").unwrap();
+        drop((stdout, stderr));
+
+        let mut chunks = Vec::new();
+        while let Some(chunk) = read_multiplexed(&mut reader).unwrap() {
+            chunks.push(chunk);
+        }
+        assert_eq!(
+            chunks,
+            [
+                (LogStream::Stdout, b"out\n".to_vec()),
+                (LogStream::Stderr, b"err".to_vec()),
+                (LogStream::Stdout, b"more".to_vec()),
+            ]
+        );
+
+        let mut truncated: &[u8] = &[1, 0, 0, 0, 0, 0, 0, 5, b'a'];
+        assert!(read_multiplexed(&mut truncated).is_err());
+        let mut unknown: &[u8] = &[3, 0, 0, 0, 0, 0, 0, 0];
+        assert!(read_multiplexed(&mut unknown).is_err());
+    }
+}
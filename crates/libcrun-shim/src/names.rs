@@ -0,0 +1,204 @@
+//! Human-friendly container names, as in `brave_hopper`
+//!
+//! An adjective and the surname of a scientist or engineer, joined by an
+//! underscore like Docker's names. There are a few thousand of them, so the
+//! runtime draws again when one is taken, see [`crate::ContainerRuntime::create`].
+//!
+//! Names are separate from IDs: a container keeps its ID for life, while its
+//! name can be changed with [`crate::ContainerRuntime::rename`].
+
+use crate::ids::{random_u64, IdGenerator};
+use crate::{Result, ShimError};
+
+/// Longest name accepted, as long as an ID may be
+const MAX_NAME_LEN: usize = 256;
+
+const ADJECTIVES: &[&str] = &[
+    "admiring",
+    "amazing",
+    "awesome",
+    "blissful",
+    "bold",
+    "brave",
+    "busy",
+    "calm",
+    "charming",
+    "clever",
+    "cool",
+    "curious",
+    "dazzling",
+    "determined",
+    "eager",
+    "elastic",
+    "elegant",
+    "epic",
+    "exciting",
+    "fervent",
+    "focused",
+    "friendly",
+    "funny",
+    "gallant",
+    "gentle",
+    "gracious",
+    "happy",
+    "hopeful",
+    "inspiring",
+    "jolly",
+    "keen",
+    "kind",
+    "laughing",
+    "lucid",
+    "magical",
+    "modest",
+    "nervous",
+    "nice",
+    "nifty",
+    "optimistic",
+    "patient",
+    "peaceful",
+    "pensive",
+    "practical",
+    "quirky",
+    "quiet",
+    "relaxed",
+    "serene",
+    "sharp",
+    "silly",
+    "stoic",
+    "sweet",
+    "tender",
+    "trusting",
+    "upbeat",
+    "vibrant",
+    "vigilant",
+    "wizardly",
+    "wonderful",
+    "zealous",
+];
+
+const SURNAMES: &[&str] = &[
+    "agnesi",
+    "albattani",
+    "archimedes",
+    "babbage",
+    "bardeen",
+    "bartik",
+    "bell",
+    "bohr",
+    "carson",
+    "cerf",
+    "curie",
+    "darwin",
+    "dijkstra",
+    "einstein",
+    "euclid",
+    "euler",
+    "faraday",
+    "fermat",
+    "fermi",
+    "feynman",
+    "franklin",
+    "galileo",
+    "gauss",
+    "goldberg",
+    "goodall",
+    "hamilton",
+    "hawking",
+    "hopper",
+    "hypatia",
+    "johnson",
+    "kepler",
+    "knuth",
+    "lamarr",
+    "lamport",
+    "liskov",
+    "lovelace",
+    "maxwell",
+    "mccarthy",
+    "meitner",
+    "mirzakhani",
+    "moore",
+    "newton",
+    "noether",
+    "pascal",
+    "pasteur",
+    "perlman",
+    "raman",
+    "ritchie",
+    "shannon",
+    "sinoussi",
+    "tesla",
+    "thompson",
+    "torvalds",
+    "turing",
+    "villani",
+    "wilson",
+    "wing",
+    "wozniak",
+    "yalow",
+    "yonath",
+];
+
+/// `<adjective>_<surname>`, drawn at random, as in `brave_hopper`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NameGenerator;
+
+impl IdGenerator for NameGenerator {
+    fn next_id(&self) -> String {
+        let n = random_u64();
+        let adjective = ADJECTIVES[(n % ADJECTIVES.len() as u64) as usize];
+        let surname = SURNAMES[((n >> 32) % SURNAMES.len() as u64) as usize];
+        format!("{}_{}", adjective, surname)
+    }
+}
+
+/// Check that a name can be given to a container
+pub(crate) fn validate(name: &str) -> Result<()> {
+    if name.is_empty()
+        || name.len() > MAX_NAME_LEN
+        || !name.starts_with(|c: char| c.is_ascii_alphanumeric())
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+    {
+        return Err(ShimError::validation(
+            "name",
+            format!(
+                "Invalid container name '{}': use up to {} letters, digits, '_', '.' and '-', starting with a letter or digit",
+                name, MAX_NAME_LEN
+            ),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names() {
+        let name = NameGenerator.next_id();
+        let (adjective, surname) = name.split_once('_').unwrap();
+        assert!(ADJECTIVES.contains(&adjective), "{}", name);
+        assert!(SURNAMES.contains(&surname), "{}", name);
+
+        // Names are usable as IDs and on the command line
+        for word in ADJECTIVES.iter().chain(SURNAMES) {
+            assert!(word.bytes().all(|b| b.is_ascii_lowercase()), "{}", word);
+        }
+        assert!(validate(&name).is_ok(), "{}", name);
+        let drawn: std::collections::HashSet<_> =
+            (0..50).map(|_| NameGenerator.next_id()).collect();
+        assert!(drawn.len() > 40);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate("web-1.api_v2").is_ok());
+        for name in ["", ".hidden", "-x", "a/b", "a b", "caf\u{e9}"] {
+            assert!(validate(name).is_err(), "{:?}", name);
+        }
+        assert!(validate(&"a".repeat(MAX_NAME_LEN + 1)).is_err());
+    }
+}
@@ -0,0 +1,110 @@
+//! Forwarding host environment variables into containers
+//!
+//! Nothing is forwarded unless `ContainerConfig::env_passthrough` names it.
+//! Patterns are variable names or globs (`*` and `?`). A glob never matches
+//! the variables in `GLOB_DENY`, which describe the host process rather than
+//! the user's settings; they are only forwarded when named exactly.
+
+/// Variables a glob pattern never matches
+const GLOB_DENY: &[&str] = &[
+    "PATH",
+    "PWD",
+    "OLDPWD",
+    "SHLVL",
+    "_",
+    "LD_*",
+    "DYLD_*",
+    "LIBCRUN_*",
+];
+
+/// Whether `name` matches a pattern of literal characters, `*` and `?`
+fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, None) => true,
+        (Some((b'*', rest)), _) => {
+            glob_match(rest, name) || (!name.is_empty() && glob_match(pattern, &name[1..]))
+        }
+        (Some((b'?', rest)), Some((_, name_rest))) => glob_match(rest, name_rest),
+        (Some((p, rest)), Some((n, name_rest))) => p == n && glob_match(rest, name_rest),
+        _ => false,
+    }
+}
+
+/// Whether the variable `name` is forwarded by `pattern`
+fn matches(pattern: &str, name: &str) -> bool {
+    if !pattern.contains(['*', '?']) {
+        return pattern == name;
+    }
+    glob_match(pattern.as_bytes(), name.as_bytes())
+        && !GLOB_DENY
+            .iter()
+            .any(|deny| glob_match(deny.as_bytes(), name.as_bytes()))
+}
+
+/// `KEY=VALUE` entries for the host variables matching `patterns`, sorted by
+/// name and leaving out variables already set in `env`
+pub(crate) fn expand(
+    patterns: &[String],
+    env: &[String],
+    host: impl IntoIterator<Item = (String, String)>,
+) -> Vec<String> {
+    let set: Vec<&str> = env
+        .iter()
+        .map(|entry| entry.split_once('=').map_or(entry.as_str(), |(k, _)| k))
+        .collect();
+    let mut forwarded: Vec<(String, String)> = host
+        .into_iter()
+        .filter(|(name, _)| !name.is_empty() && !set.contains(&name.as_str()))
+        .filter(|(name, _)| patterns.iter().any(|p| matches(p, name)))
+        .collect();
+    forwarded.sort();
+    forwarded
+        .into_iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect()
+}
+
+/// The current process's variables that are valid UTF-8
+pub(crate) fn host_vars() -> impl Iterator<Item = (String, String)> {
+    std::env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(matches("HOME", "HOME"));
+        assert!(!matches("HOME", "HOMEDIR"));
+        assert!(matches("AWS_*", "AWS_REGION"));
+        assert!(matches("AWS_*", "AWS_"));
+        assert!(!matches("AWS_*", "XAWS_REGION"));
+        assert!(matches("LC_?", "LC_A"));
+        assert!(matches("*_TOKEN", "GITHUB_TOKEN"));
+        // Globs skip the host process's own variables, exact names do not
+        assert!(!matches("*", "PATH"));
+        assert!(!matches("L*", "LD_PRELOAD"));
+        assert!(!matches("*", "LIBCRUN_CONFIG_FILE"));
+        assert!(matches("PATH", "PATH"));
+    }
+
+    #[test]
+    fn test_expand() {
+        let host = [
+            ("HOME", "/Users/me"),
+            ("AWS_REGION", "eu-west-1"),
+            ("AWS_PROFILE", "dev"),
+            ("SECRET", "x"),
+        ]
+        .map(|(k, v)| (k.to_string(), v.to_string()));
+        let patterns = vec!["HOME".to_string(), "AWS_*".to_string()];
+        let env = vec!["AWS_REGION=us-east-1".to_string()];
+        assert_eq!(
+            expand(&patterns, &env, host.clone()),
+            ["AWS_PROFILE=dev", "HOME=/Users/me"]
+        );
+        assert!(expand(&[], &[], host).is_empty());
+    }
+}
@@ -0,0 +1,53 @@
+//! Pods: containers grouped around a sandbox that owns shared namespaces
+//!
+//! The CRI runtime service and library users both group containers through
+//! `ContainerRuntime::create_pod`; this module holds the bookkeeping.
+
+use crate::types::{ContainerConfig, JoinNamespaces, PodSpec, VolumeMount};
+
+/// A pod registered with the runtime
+pub(crate) struct PodState {
+    share_network: bool,
+    share_ipc: bool,
+    volumes: Vec<VolumeMount>,
+    /// Member container IDs, in creation order (the sandbox is not included)
+    pub(crate) members: Vec<String>,
+}
+
+impl PodState {
+    pub(crate) fn new(spec: &PodSpec) -> Self {
+        Self {
+            share_network: spec.share_network,
+            share_ipc: spec.share_ipc,
+            volumes: spec.volumes.clone(),
+            members: vec![],
+        }
+    }
+
+    /// Point a member's config at the pod's sandbox namespaces and volumes
+    pub(crate) fn apply_to(&self, pod_id: &str, config: &mut ContainerConfig) {
+        if self.share_network || self.share_ipc {
+            config.join_namespaces = Some(JoinNamespaces {
+                container: pod_id.to_string(),
+                network: self.share_network,
+                ipc: self.share_ipc,
+            });
+        }
+        if self.share_network {
+            config.network.mode = format!("container:{}", pod_id);
+        }
+
+        let volumes: Vec<VolumeMount> = self
+            .volumes
+            .iter()
+            .filter(|v| {
+                !config
+                    .volumes
+                    .iter()
+                    .any(|existing| existing.destination == v.destination)
+            })
+            .cloned()
+            .collect();
+        config.volumes.splice(0..0, volumes);
+    }
+}
@@ -0,0 +1,93 @@
+//! Progress of long operations
+//!
+//! Creating containers, checkpointing, copying checkpoints and files to and
+//! from the VM and loading image archives can take a while. The phases they go
+//! through are sent to every subscriber, so a CLI can show what is happening
+//! instead of blocking silently. Progress reported while no one is
+//! subscribed is dropped.
+
+use crate::types::{Operation, OperationProgress};
+use tokio::sync::broadcast;
+
+/// Phases a subscriber may fall behind by before it misses some
+const CAPACITY: usize = 64;
+
+static GLOBAL_PROGRESS: std::sync::OnceLock<broadcast::Sender<OperationProgress>> =
+    std::sync::OnceLock::new();
+
+fn sender() -> &'static broadcast::Sender<OperationProgress> {
+    GLOBAL_PROGRESS.get_or_init(|| broadcast::channel(CAPACITY).0)
+}
+
+/// Receive the progress reported from now on
+pub fn subscribe_progress() -> broadcast::Receiver<OperationProgress> {
+    sender().subscribe()
+}
+
+/// Send `progress` to every subscriber
+pub fn report_progress(progress: OperationProgress) {
+    // Ignore send errors (no receivers)
+    let _ = sender().send(progress);
+}
+
+/// Report that `operation` on `target` entered `phase`
+pub(crate) fn report(
+    operation: Operation,
+    target: &str,
+    phase: &str,
+    percent: Option<u8>,
+    message: impl Into<String>,
+) {
+    report_progress(OperationProgress {
+        operation,
+        target: target.to_string(),
+        phase: phase.to_string(),
+        percent,
+        message: message.into(),
+    });
+}
+
+/// `done` out of `total`, in percent; all of nothing is done
+#[cfg_attr(
+    not(any(feature = "image-pull", target_os = "linux", target_os = "macos")),
+    allow(dead_code)
+)]
+pub(crate) fn percent(done: u64, total: u64) -> u8 {
+    if total == 0 {
+        return 100;
+    }
+    (done.min(total) as u128 * 100 / total as u128) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_progress() {
+        let mut receiver = subscribe_progress();
+        report(
+            Operation::LoadImage,
+            "test-progress.tar",
+            "extract",
+            Some(50),
+            "Extracting layer 1 of 2",
+        );
+        // Other tests may report meanwhile
+        let progress = std::iter::from_fn(|| receiver.try_recv().ok())
+            .find(|p| p.target == "test-progress.tar")
+            .unwrap();
+        assert_eq!(progress.operation, Operation::LoadImage);
+        assert_eq!(progress.phase, "extract");
+        assert_eq!(progress.percent, Some(50));
+    }
+
+    #[test]
+    fn test_percent() {
+        assert_eq!(percent(0, 200), 0);
+        assert_eq!(percent(50, 200), 25);
+        assert_eq!(percent(300, 200), 100);
+        assert_eq!(percent(0, 0), 100);
+        assert_eq!(percent(u64::MAX - 1, u64::MAX), 99);
+    }
+}
@@ -0,0 +1,240 @@
+//! HTTP proxies for image pulls and containers
+//!
+//! Behind a corporate proxy, registries are only reachable through it. The
+//! settings come from the runtime configuration, falling back to the usual
+//! `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY` variables of the host. They
+//! are used for pulls on the host and set in containers, which in the VM
+//! would not see the host's environment otherwise.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Proxy settings, see the module documentation
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ProxyConfig {
+    /// Proxy for `http://` URLs, e.g. "http://proxy.corp:3128"
+    #[serde(default)]
+    pub http_proxy: Option<String>,
+    /// Proxy for `https://` URLs
+    #[serde(default)]
+    pub https_proxy: Option<String>,
+    /// Comma-separated hosts and domains reached directly, e.g.
+    /// "localhost,.corp.example"; "*" bypasses the proxy for all
+    #[serde(default)]
+    pub no_proxy: Option<String>,
+    /// Proxy per registry, e.g. "ghcr.io", used instead of the above for the
+    /// registry and its subdomains; an empty value reaches it directly
+    #[serde(default)]
+    pub registries: HashMap<String, String>,
+    /// Set the proxy variables in containers that don't set them themselves
+    #[serde(default = "default_forward_to_containers")]
+    pub forward_to_containers: bool,
+}
+
+fn default_forward_to_containers() -> bool {
+    true
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        Self {
+            http_proxy: None,
+            https_proxy: None,
+            no_proxy: None,
+            registries: HashMap::new(),
+            forward_to_containers: default_forward_to_containers(),
+        }
+    }
+}
+
+impl ProxyConfig {
+    /// Fill the settings left unset from the host's proxy variables
+    pub(crate) fn with_host_env(self) -> Self {
+        self.with_vars(|name| std::env::var(name).ok())
+    }
+
+    /// Fill the settings left unset from `var`, trying the uppercase name of
+    /// each variable before the lowercase one
+    fn with_vars(mut self, var: impl Fn(&str) -> Option<String>) -> Self {
+        let lookup = |name: &str| {
+            var(name)
+                .or_else(|| var(&name.to_lowercase()))
+                .filter(|value| !value.is_empty())
+        };
+        self.http_proxy = self.http_proxy.or_else(|| lookup("HTTP_PROXY"));
+        self.https_proxy = self.https_proxy.or_else(|| lookup("HTTPS_PROXY"));
+        self.no_proxy = self.no_proxy.or_else(|| lookup("NO_PROXY"));
+        self
+    }
+
+    /// Proxy for a request to `host` with `scheme` ("http" or "https"), or
+    /// `None` to connect directly
+    pub fn proxy_for(&self, scheme: &str, host: &str) -> Option<&str> {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        let registry = self
+            .registries
+            .iter()
+            .find(|(registry, _)| domain_matches(&host, &registry.to_ascii_lowercase()));
+        if let Some((_, proxy)) = registry {
+            return Some(proxy.as_str()).filter(|proxy| !proxy.is_empty());
+        }
+        if self.bypasses(&host) {
+            return None;
+        }
+        match scheme {
+            "https" => self.https_proxy.as_deref(),
+            _ => self.http_proxy.as_deref(),
+        }
+    }
+
+    /// Whether `no_proxy` lists `host`, given in lowercase
+    fn bypasses(&self, host: &str) -> bool {
+        let Some(ref no_proxy) = self.no_proxy else {
+            return false;
+        };
+        no_proxy
+            .split(',')
+            .map(|entry| entry.trim().to_ascii_lowercase())
+            .filter(|entry| !entry.is_empty())
+            .any(|entry| {
+                if entry == "*" {
+                    return true;
+                }
+                // "host:port" bypasses the host on any port
+                let entry = match entry.split_once(':') {
+                    Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) => name,
+                    _ => entry.as_str(),
+                };
+                domain_matches(host, entry.trim_start_matches("*.").trim_start_matches('.'))
+            })
+    }
+
+    /// Add the proxy variables, upper and lowercase, to the `KEY=VALUE`
+    /// list `env` unless it sets them in either case
+    pub fn apply_to_env(&self, env: &mut Vec<String>) {
+        if !self.forward_to_containers {
+            return;
+        }
+        let vars = [
+            ("HTTP_PROXY", &self.http_proxy),
+            ("HTTPS_PROXY", &self.https_proxy),
+            ("NO_PROXY", &self.no_proxy),
+        ];
+        for (name, value) in vars {
+            let Some(value) = value else {
+                continue;
+            };
+            let lower = name.to_lowercase();
+            let set = env.iter().any(|var| {
+                var.split_once('=')
+                    .is_some_and(|(key, _)| key == name || key == lower)
+            });
+            if !set {
+                env.push(format!("{}={}", name, value));
+                env.push(format!("{}={}", lower, value));
+            }
+        }
+    }
+}
+
+/// Whether `host` is `domain` or one of its subdomains
+fn domain_matches(host: &str, domain: &str) -> bool {
+    host == domain
+        || host
+            .strip_suffix(domain)
+            .is_some_and(|sub| sub.ends_with('.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_proxy_for() {
+        let proxy = ProxyConfig {
+            http_proxy: Some("http://proxy:3128".to_string()),
+            https_proxy: Some("http://secure-proxy:3128".to_string()),
+            no_proxy: Some("localhost, .corp.example,registry.local:5000".to_string()),
+            registries: HashMap::from([
+                ("docker.io".to_string(), "http://hub-proxy:8080".to_string()),
+                ("ghcr.io".to_string(), String::new()),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(
+            proxy.proxy_for("https", "quay.io"),
+            Some("http://secure-proxy:3128")
+        );
+        assert_eq!(
+            proxy.proxy_for("http", "example.com"),
+            Some("http://proxy:3128")
+        );
+        assert_eq!(proxy.proxy_for("https", "localhost"), None);
+        assert_eq!(proxy.proxy_for("https", "git.corp.example"), None);
+        assert_eq!(proxy.proxy_for("https", "corp.example"), None);
+        assert_eq!(
+            proxy.proxy_for("https", "notcorp.example"),
+            Some("http://secure-proxy:3128")
+        );
+        assert_eq!(proxy.proxy_for("https", "registry.local"), None);
+        // Docker Hub pulls go to registry-1.docker.io and auth.docker.io
+        assert_eq!(
+            proxy.proxy_for("https", "registry-1.docker.io"),
+            Some("http://hub-proxy:8080")
+        );
+        assert_eq!(proxy.proxy_for("https", "GHCR.io"), None);
+
+        let everything = ProxyConfig {
+            https_proxy: Some("http://proxy:3128".to_string()),
+            no_proxy: Some("*".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(everything.proxy_for("https", "quay.io"), None);
+        assert_eq!(ProxyConfig::default().proxy_for("https", "quay.io"), None);
+    }
+
+    #[test]
+    fn test_with_vars() {
+        let vars = HashMap::from([
+            ("HTTPS_PROXY", "http://upper:3128"),
+            ("https_proxy", "http://lower:3128"),
+            ("http_proxy", "http://lower:3128"),
+            ("NO_PROXY", ""),
+        ]);
+        let proxy = ProxyConfig {
+            http_proxy: Some("http://configured:3128".to_string()),
+            ..Default::default()
+        }
+        .with_vars(|name| vars.get(name).map(|v| v.to_string()));
+        assert_eq!(proxy.http_proxy.as_deref(), Some("http://configured:3128"));
+        assert_eq!(proxy.https_proxy.as_deref(), Some("http://upper:3128"));
+        assert_eq!(proxy.no_proxy, None);
+    }
+
+    #[test]
+    fn test_apply_to_env() {
+        let proxy = ProxyConfig {
+            http_proxy: Some("http://proxy:3128".to_string()),
+            https_proxy: Some("http://proxy:3128".to_string()),
+            ..Default::default()
+        };
+        let mut env = vec!["https_proxy=".to_string()];
+        proxy.apply_to_env(&mut env);
+        assert_eq!(
+            env,
+            [
+                "https_proxy=",
+                "HTTP_PROXY=http://proxy:3128",
+                "http_proxy=http://proxy:3128"
+            ]
+        );
+
+        let mut env = Vec::new();
+        ProxyConfig {
+            forward_to_containers: false,
+            ..proxy
+        }
+        .apply_to_env(&mut env);
+        assert!(env.is_empty());
+    }
+}
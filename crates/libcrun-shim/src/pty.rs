@@ -4,6 +4,9 @@
 
 use crate::error::{Result, ShimError};
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Mutex, OnceLock};
 
 /// PTY master/slave pair
 pub struct Pty {
@@ -73,31 +76,7 @@ impl Pty {
     /// Set terminal to raw mode for interactive use
     #[cfg(unix)]
     pub fn set_raw_mode(&mut self) -> Result<()> {
-        let stdin_fd = std::io::stdin().as_raw_fd();
-
-        // Save original termios
-        let mut original: libc::termios = unsafe { std::mem::zeroed() };
-        let ret = unsafe { libc::tcgetattr(stdin_fd, &mut original) };
-        if ret != 0 {
-            return Err(ShimError::runtime("Failed to get terminal attributes"));
-        }
-
-        self.original_termios = Some(original);
-
-        // Set raw mode
-        let mut raw = original;
-        raw.c_lflag &= !(libc::ECHO | libc::ICANON | libc::ISIG | libc::IEXTEN);
-        raw.c_iflag &= !(libc::BRKINT | libc::ICRNL | libc::INPCK | libc::ISTRIP | libc::IXON);
-        raw.c_oflag &= !libc::OPOST;
-        raw.c_cflag |= libc::CS8;
-        raw.c_cc[libc::VMIN] = 1;
-        raw.c_cc[libc::VTIME] = 0;
-
-        let ret = unsafe { libc::tcsetattr(stdin_fd, libc::TCSANOW, &raw) };
-        if ret != 0 {
-            return Err(ShimError::runtime("Failed to set raw mode"));
-        }
-
+        self.original_termios = Some(make_raw(std::io::stdin().as_raw_fd())?);
         Ok(())
     }
 
@@ -157,6 +136,52 @@ impl Drop for Pty {
     }
 }
 
+/// Switch a terminal to raw mode, returning its previous attributes
+fn make_raw(fd: RawFd) -> Result<libc::termios> {
+    let mut original: libc::termios = unsafe { std::mem::zeroed() };
+    let ret = unsafe { libc::tcgetattr(fd, &mut original) };
+    if ret != 0 {
+        return Err(ShimError::runtime("Failed to get terminal attributes"));
+    }
+
+    let mut raw = original;
+    raw.c_lflag &= !(libc::ECHO | libc::ICANON | libc::ISIG | libc::IEXTEN);
+    raw.c_iflag &= !(libc::BRKINT | libc::ICRNL | libc::INPCK | libc::ISTRIP | libc::IXON);
+    raw.c_oflag &= !libc::OPOST;
+    raw.c_cflag |= libc::CS8;
+    raw.c_cc[libc::VMIN] = 1;
+    raw.c_cc[libc::VTIME] = 0;
+
+    let ret = unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) };
+    if ret != 0 {
+        return Err(ShimError::runtime("Failed to set raw mode"));
+    }
+
+    Ok(original)
+}
+
+/// Keeps the terminal on stdin in raw mode until dropped
+///
+/// Keys such as Ctrl-C then reach the program at the other end, e.g. a
+/// shell in a container, instead of signalling this process.
+pub struct RawMode {
+    original: libc::termios,
+}
+
+impl RawMode {
+    /// Switch stdin to raw mode; fails when stdin is not a terminal
+    pub fn enable() -> Result<Self> {
+        let original = make_raw(std::io::stdin().as_raw_fd())?;
+        Ok(Self { original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(std::io::stdin().as_raw_fd(), libc::TCSANOW, &self.original) };
+    }
+}
+
 /// Interactive session for container exec
 pub struct InteractiveSession {
     pty: Pty,
@@ -213,6 +238,80 @@ pub fn get_terminal_size() -> Option<(u16, u16)> {
     None
 }
 
+/// Write end of the pipe the `SIGWINCH` handler wakes the resize thread with
+static WINCH_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+/// Receivers of [`watch_terminal_size`] that have not been dropped yet
+static RESIZE_WATCHERS: Mutex<Vec<Sender<(u16, u16)>>> = Mutex::new(Vec::new());
+
+extern "C" fn on_winch(_signal: libc::c_int) {
+    // Only write() here, which is async-signal-safe; when the pipe is full
+    // the thread has a wakeup pending anyway
+    let fd = WINCH_PIPE.load(Ordering::Relaxed);
+    unsafe { libc::write(fd, b"w".as_ptr().cast(), 1) };
+}
+
+/// Install the `SIGWINCH` handler and start the thread that reads the new
+/// terminal size after each signal and sends it to the watchers
+fn start_resize_thread() -> std::result::Result<(), String> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(format!(
+            "Failed to create SIGWINCH pipe: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    unsafe { libc::fcntl(fds[1], libc::F_SETFL, libc::O_NONBLOCK) };
+    WINCH_PIPE.store(fds[1], Ordering::SeqCst);
+    let mut wakeups = unsafe { std::fs::File::from_raw_fd(fds[0]) };
+
+    let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+    action.sa_sigaction = on_winch as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    action.sa_flags = libc::SA_RESTART;
+    if unsafe { libc::sigaction(libc::SIGWINCH, &action, std::ptr::null_mut()) } != 0 {
+        return Err(format!(
+            "Failed to handle SIGWINCH: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+
+    std::thread::spawn(move || {
+        use std::io::Read;
+
+        let mut buf = [0u8; 64];
+        loop {
+            match wakeups.read(&mut buf) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            }
+            if let Some(size) = get_terminal_size() {
+                RESIZE_WATCHERS
+                    .lock()
+                    .unwrap()
+                    .retain(|watcher| watcher.send(size).is_ok());
+            }
+        }
+    });
+    Ok(())
+}
+
+/// New sizes of the terminal, sent as soon as the window is resized
+///
+/// The sizes are read when the process gets `SIGWINCH`, which the first
+/// call starts handling. Meant for [`crate::ExecStdio::resize`].
+pub fn watch_terminal_size() -> Result<Receiver<(u16, u16)>> {
+    static STARTED: OnceLock<std::result::Result<(), String>> = OnceLock::new();
+    STARTED
+        .get_or_init(start_resize_thread)
+        .clone()
+        .map_err(ShimError::runtime)?;
+    let (sender, receiver) = std::sync::mpsc::channel();
+    RESIZE_WATCHERS.lock().unwrap().push(sender);
+    Ok(receiver)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -229,4 +328,17 @@ mod tests {
         // This might not work in all test environments
         let _size = get_terminal_size();
     }
+
+    #[test]
+    fn test_watch_terminal_size() {
+        let first = watch_terminal_size().unwrap();
+        let second = watch_terminal_size().unwrap();
+        drop(first);
+        // Handled rather than fatal; sizes only arrive under a terminal
+        unsafe { libc::raise(libc::SIGWINCH) };
+        if let Some(size) = get_terminal_size() {
+            let received = second.recv_timeout(std::time::Duration::from_secs(5));
+            assert_eq!(received.unwrap(), size);
+        }
+    }
 }
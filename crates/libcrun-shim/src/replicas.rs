@@ -0,0 +1,19 @@
+//! Replica sets: identical containers created from one template
+//!
+//! A replica set is named after its template's ID; replica `n` of set `web`
+//! is the container `web-n`, with ordinals starting at 1. The name is the
+//! only bookkeeping, so sets are rediscovered from `list()` after a restart.
+
+/// Container ID of replica `ordinal` of the set `name`
+pub(crate) fn replica_id(name: &str, ordinal: u32) -> String {
+    format!("{}-{}", name, ordinal)
+}
+
+/// Ordinal of `id` if it is a replica of the set `name`
+pub(crate) fn replica_ordinal(name: &str, id: &str) -> Option<u32> {
+    let ordinal = id.strip_prefix(name)?.strip_prefix('-')?;
+    if ordinal.starts_with('0') || !ordinal.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    ordinal.parse().ok().filter(|n| *n > 0)
+}
@@ -0,0 +1,166 @@
+//! Usage reports over metrics history
+//!
+//! A report turns the samples of a time window into per-container
+//! distributions, for capacity planning, and totals, for chargeback.
+
+use crate::types::{ContainerUsageReport, MetricsSample, UsageStats};
+use std::collections::BTreeMap;
+
+/// Samples kept in memory by the Linux runtime; older ones are dropped first
+#[cfg(target_os = "linux")]
+pub(crate) const MAX_SAMPLES: usize = 100_000;
+
+/// Append a sample to a history, dropping the oldest past the limit
+#[cfg(target_os = "linux")]
+pub(crate) fn record(
+    history: &mut std::collections::VecDeque<MetricsSample>,
+    sample: MetricsSample,
+) {
+    history.push_back(sample);
+    if history.len() > MAX_SAMPLES {
+        history.pop_front();
+    }
+}
+
+/// Summarize samples per container, ordered by container ID
+pub(crate) fn summarize(samples: &[MetricsSample]) -> Vec<ContainerUsageReport> {
+    let mut by_container: BTreeMap<&str, Vec<&MetricsSample>> = BTreeMap::new();
+    for sample in samples {
+        by_container.entry(&sample.id).or_default().push(sample);
+    }
+
+    by_container
+        .into_iter()
+        .map(|(id, mut samples)| {
+            samples.sort_by_key(|s| s.timestamp);
+            let mut report = ContainerUsageReport {
+                id: id.to_string(),
+                from: samples[0].timestamp,
+                to: samples[samples.len() - 1].timestamp,
+                samples: samples.len(),
+                memory_bytes: stats(samples.iter().map(|s| s.memory_bytes as f64).collect()),
+                pids: stats(samples.iter().map(|s| s.pids as f64).collect()),
+                ..Default::default()
+            };
+
+            let mut cpu_percent = Vec::new();
+            let mut cpu_ns = 0;
+            for pair in samples.windows(2) {
+                let (prev, cur) = (pair[0], pair[1]);
+                let cpu = counter_delta(prev.cpu_usage_ns, cur.cpu_usage_ns);
+                cpu_ns += cpu;
+                let elapsed = cur.timestamp.saturating_sub(prev.timestamp);
+                if elapsed > 0 {
+                    cpu_percent.push(cpu as f64 / (elapsed as f64 * 1e9) * 100.0);
+                }
+                report.net_rx_bytes += counter_delta(prev.net_rx_bytes, cur.net_rx_bytes);
+                report.net_tx_bytes += counter_delta(prev.net_tx_bytes, cur.net_tx_bytes);
+                report.blkio_read_bytes +=
+                    counter_delta(prev.blkio_read_bytes, cur.blkio_read_bytes);
+                report.blkio_write_bytes +=
+                    counter_delta(prev.blkio_write_bytes, cur.blkio_write_bytes);
+            }
+            report.cpu_percent = stats(cpu_percent);
+            report.cpu_seconds = cpu_ns as f64 / 1e9;
+            report
+        })
+        .collect()
+}
+
+/// Growth of a cumulative counter between two samples
+///
+/// A counter that went down was reset by a new container with the same ID,
+/// which has used everything it counts since.
+fn counter_delta(prev: u64, cur: u64) -> u64 {
+    if cur >= prev {
+        cur - prev
+    } else {
+        cur
+    }
+}
+
+fn stats(mut values: Vec<f64>) -> UsageStats {
+    if values.is_empty() {
+        return UsageStats::default();
+    }
+    values.sort_by(f64::total_cmp);
+    UsageStats {
+        min: values[0],
+        max: values[values.len() - 1],
+        avg: values.iter().sum::<f64>() / values.len() as f64,
+        p50: percentile(&values, 50.0),
+        p95: percentile(&values, 95.0),
+        p99: percentile(&values, 99.0),
+    }
+}
+
+/// Nearest-rank percentile of sorted values
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(id: &str, timestamp: u64, cpu_secs: u64, memory_mb: u64) -> MetricsSample {
+        MetricsSample {
+            id: id.to_string(),
+            timestamp,
+            cpu_usage_ns: cpu_secs * 1_000_000_000,
+            memory_bytes: memory_mb << 20,
+            net_rx_bytes: timestamp * 100,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_summarize() {
+        let samples = vec![
+            sample("web", 20, 5, 200),
+            sample("db", 0, 0, 500),
+            sample("web", 0, 0, 100),
+            sample("web", 10, 5, 300),
+        ];
+        let reports = summarize(&samples);
+        let ids: Vec<&str> = reports.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, ["db", "web"]);
+
+        let web = &reports[1];
+        assert_eq!((web.from, web.to, web.samples), (0, 20, 3));
+        // 5s of CPU in the first 10s, none in the next
+        assert_eq!(web.cpu_percent.max, 50.0);
+        assert_eq!(web.cpu_percent.min, 0.0);
+        assert_eq!(web.cpu_percent.avg, 25.0);
+        assert_eq!(web.cpu_seconds, 5.0);
+        assert_eq!(web.memory_bytes.min, (100u64 << 20) as f64);
+        assert_eq!(web.memory_bytes.max, (300u64 << 20) as f64);
+        assert_eq!(web.memory_bytes.p50, (200u64 << 20) as f64);
+        assert_eq!(web.net_rx_bytes, 2000);
+
+        // A single sample has no rates
+        let db = &reports[0];
+        assert_eq!(db.samples, 1);
+        assert_eq!(db.cpu_percent, UsageStats::default());
+        assert_eq!(db.memory_bytes.avg, (500u64 << 20) as f64);
+    }
+
+    #[test]
+    fn test_counter_reset() {
+        // The container was recreated between the samples
+        let samples = vec![sample("web", 0, 100, 1), sample("web", 10, 2, 1)];
+        let report = &summarize(&samples)[0];
+        assert_eq!(report.cpu_seconds, 2.0);
+    }
+
+    #[test]
+    fn test_percentile() {
+        let values: Vec<f64> = (1..=100).map(f64::from).collect();
+        assert_eq!(percentile(&values, 50.0), 50.0);
+        assert_eq!(percentile(&values, 95.0), 95.0);
+        assert_eq!(percentile(&values, 99.0), 99.0);
+        assert_eq!(percentile(&[7.0], 99.0), 7.0);
+        assert_eq!(percentile(&[1.0, 2.0], 0.0), 1.0);
+    }
+}
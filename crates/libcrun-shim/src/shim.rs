@@ -1,14 +1,30 @@
 //! Containerd Shim v2 Interface
 //!
 //! This module implements the containerd shim v2 protocol for integration
-//! with containerd as an OCI runtime.
+//! with containerd as an OCI runtime. [`TaskServiceImpl`] runs the tasks on
+//! a [`crate::ContainerRuntime`]; with the `shim-v2` feature it is served
+//! over ttrpc by the `containerd-shim-crun-v2` binary, which containerd runs
+//! for `--runtime io.containerd.crun.v2`.
 //!
 //! Reference: https://github.com/containerd/containerd/blob/main/runtime/v2/README.md
 
 use crate::error::{Result, ShimError};
-use crate::types::ContainerConfig;
+use crate::types::{
+    ContainerConfig, ContainerInfo, ContainerStatus, ExecOptions, ExecOutcome, ExecStdio,
+    LogOptions,
+};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+
+#[cfg(feature = "shim-v2")]
+mod server;
+
+#[cfg(feature = "shim-v2")]
+pub use server::{cleanup, serve, start};
 
 /// Shim v2 task service interface
 pub trait TaskService {
@@ -103,6 +119,7 @@ impl From<crate::types::ContainerStatus> for Status {
             crate::types::ContainerStatus::Created => Status::Created,
             crate::types::ContainerStatus::Running => Status::Running,
             crate::types::ContainerStatus::Stopped => Status::Stopped,
+            crate::types::ContainerStatus::Paused => Status::Paused,
         }
     }
 }
@@ -315,72 +332,28 @@ impl ShimV2 {
         &self.socket_path
     }
 
-    /// Get the runtime (creates one if not set)
-    #[allow(dead_code)]
-    async fn get_runtime(&mut self) -> Result<&mut crate::ContainerRuntime> {
-        if self.runtime.is_none() {
-            self.runtime = Some(crate::ContainerRuntime::new().await?);
-        }
-        Ok(self.runtime.as_mut().unwrap())
-    }
-
-    /// Start the shim service
+    /// Serve the task API over ttrpc on the socket until containerd shuts
+    /// the shim down
+    ///
+    /// Needs a multi-threaded Tokio runtime, which the task service blocks
+    /// on from the ttrpc threads.
     #[cfg(feature = "shim-v2")]
     pub async fn serve(&mut self) -> Result<()> {
         log::info!("Starting shim v2 service on {}", self.socket_path.display());
 
-        // Ensure runtime is initialized
-        let _ = self.get_runtime().await?;
-
-        // TTRPC server implementation
-        // Note: Full TTRPC implementation requires:
-        // 1. TTRPC server setup with Unix socket listener
-        // 2. Task service implementation using ttrpc::Service
-        // 3. Protobuf message definitions from containerd
-        // 4. Request/response serialization/deserialization
-
-        #[cfg(feature = "shim-v2")]
-        {
-            use std::io::prelude::*;
-            use std::os::unix::net::UnixListener;
-
-            // Remove old socket if exists
-            let _ = std::fs::remove_file(&self.socket_path);
-
-            let listener = UnixListener::bind(&self.socket_path).map_err(|e| {
-                ShimError::io_with_context(
-                    e,
-                    format!("Failed to bind shim socket: {}", self.socket_path.display()),
-                )
-            })?;
-
-            log::info!("Shim v2 listening on {}", self.socket_path.display());
-
-            // Accept connections and handle requests
-            // In a full implementation, this would use ttrpc::Server
-            for stream in listener.incoming() {
-                match stream {
-                    Ok(mut stream) => {
-                        log::debug!("New shim connection");
-                        // Handle TTRPC requests here
-                        // For now, just acknowledge connection
-                        let _ = stream.write_all(b"OK");
-                    }
-                    Err(e) => {
-                        log::error!("Shim connection error: {}", e);
-                    }
-                }
-            }
-
-            Ok(())
-        }
-
-        #[cfg(not(feature = "shim-v2"))]
-        {
-            Err(ShimError::runtime(
-                "Shim v2 feature not enabled. Enable with 'shim-v2' feature flag.",
-            ))
-        }
+        let runtime = match self.runtime.take() {
+            Some(runtime) => runtime,
+            None => crate::ContainerRuntime::new().await?,
+        };
+        let tasks = TaskServiceImpl::with_runtime(
+            runtime,
+            self.namespace.clone(),
+            self.bundle_path.clone(),
+        );
+        let socket_path = self.socket_path.clone();
+        tokio::task::spawn_blocking(move || server::serve_at(&socket_path, tasks))
+            .await
+            .map_err(|e| ShimError::runtime("Shim v2 server failed").with_source(e))?
     }
 
     /// Start the shim service (fallback without TTRPC)
@@ -397,25 +370,196 @@ impl ShimV2 {
     }
 }
 
+/// What containerd passes a shim on its command line
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ShimArgs {
+    pub namespace: String,
+    /// containerd's ttrpc socket, where events are published
+    pub address: String,
+    /// Binary that publishes events to containerd
+    pub publish_binary: String,
+    /// ID of the task the shim is for
+    pub id: String,
+    /// Bundle of the task; the working directory when not given
+    pub bundle: Option<PathBuf>,
+    pub debug: bool,
+    /// "start" or "delete"; `None` to serve the task API
+    pub action: Option<String>,
+}
+
+impl ShimArgs {
+    /// Parse the Go-style flags containerd passes ("-id x" or "--id=x"),
+    /// followed by the action
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self> {
+        let mut parsed = ShimArgs::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let Some(flag) = arg.strip_prefix('-') else {
+                parsed.action = Some(arg);
+                continue;
+            };
+            let flag = flag.strip_prefix('-').unwrap_or(flag);
+            let (name, inline) = match flag.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (flag, None),
+            };
+            if name == "debug" {
+                parsed.debug = inline.is_none_or(|value| value == "true");
+                continue;
+            }
+            let value = match inline {
+                Some(value) => value,
+                None => args
+                    .next()
+                    .ok_or_else(|| ShimError::validation(name, "flag needs a value"))?,
+            };
+            match name {
+                "namespace" => parsed.namespace = value,
+                "address" => parsed.address = value,
+                "publish-binary" => parsed.publish_binary = value,
+                "id" => parsed.id = value,
+                "bundle" => parsed.bundle = Some(PathBuf::from(value)),
+                _ => return Err(ShimError::validation(name, "unknown flag")),
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Socket the shim for this task serves on
+    ///
+    /// As with containerd's own shims, it is named after a hash of
+    /// containerd's address, the namespace and the task ID, in
+    /// /run/containerd/s so the path stays short enough for a socket.
+    pub fn socket_path(&self) -> PathBuf {
+        let key = format!("{}/{}/{}", self.address, self.namespace, self.id);
+        PathBuf::from(SOCKET_DIR).join(format!("{:x}", Sha256::digest(key.as_bytes())))
+    }
+}
+
+/// Directory of the shim sockets, see [`ShimArgs::socket_path`]
+const SOCKET_DIR: &str = "/run/containerd/s";
+
+/// Exit status reported when the exit code of a process is unknown
+const UNKNOWN_EXIT_STATUS: u32 = 255;
+
+/// Signals that stop a container when its init process can't be signalled
+/// directly
+const SIGKILL: u32 = 9;
+const SIGTERM: u32 = 15;
+
+/// Rows and columns of an exec's terminal until containerd resizes it
+const DEFAULT_TERMINAL_SIZE: (u16, u16) = (24, 80);
+
 /// Task service implementation that bridges to ContainerRuntime
+///
+/// The methods block, as the ttrpc handlers calling them do, so they must
+/// be called from outside the Tokio runtime the service was created in.
+/// That runtime must be a multi-threaded one.
 pub struct TaskServiceImpl {
-    #[allow(dead_code)]
-    runtime: crate::ContainerRuntime,
+    runtime: Arc<crate::ContainerRuntime>,
     #[allow(dead_code)]
     namespace: String,
-    #[allow(dead_code)]
     bundle_path: PathBuf,
+    /// Runtime the calls to `runtime` run on
+    handle: tokio::runtime::Handle,
+    tasks: Mutex<HashMap<String, Task>>,
+}
+
+/// A container created through the task service
+#[derive(Default)]
+struct Task {
+    bundle: PathBuf,
+    stdio: TaskStdio,
+    /// Where the rootfs mounts of the create request were mounted
+    rootfs: Option<PathBuf>,
+    /// Exit of the init process, once seen
+    exit: Option<TaskExit>,
+    execs: HashMap<String, Arc<ExecProcess>>,
+}
+
+/// Paths of the fifos containerd connects a process's stdio to, empty for
+/// those it doesn't
+#[derive(Debug, Clone, Default)]
+struct TaskStdio {
+    stdin: String,
+    stdout: String,
+    stderr: String,
+    terminal: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TaskExit {
+    status: u32,
+    /// Unix time
+    at: u64,
+}
+
+impl From<TaskExit> for WaitResponse {
+    fn from(exit: TaskExit) -> Self {
+        WaitResponse {
+            exit_status: exit.status,
+            exited_at: exit.at,
+        }
+    }
+}
+
+/// A process exec'd in a task's container
+struct ExecProcess {
+    options: ExecOptions,
+    stdio: TaskStdio,
+    state: Mutex<ExecState>,
+    /// Notified when the process exits
+    exited: Condvar,
+    /// Passes new terminal sizes to the running process
+    resize: Mutex<Option<mpsc::Sender<(u16, u16)>>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ExecState {
+    Created,
+    Running,
+    Exited(TaskExit),
+}
+
+impl ExecProcess {
+    fn exit(&self, exit: TaskExit) {
+        *self.state.lock().unwrap() = ExecState::Exited(exit);
+        *self.resize.lock().unwrap() = None;
+        self.exited.notify_all();
+    }
+
+    fn wait(&self) -> TaskExit {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let ExecState::Exited(exit) = *state {
+                return exit;
+            }
+            state = self.exited.wait(state).unwrap();
+        }
+    }
 }
 
 impl TaskServiceImpl {
     /// Create a new task service
     pub async fn new(namespace: String, bundle_path: PathBuf) -> Result<Self> {
         let runtime = crate::ContainerRuntime::new().await?;
-        Ok(Self {
-            runtime,
+        Ok(Self::with_runtime(runtime, namespace, bundle_path))
+    }
+
+    /// Create a task service over `runtime`, from within the Tokio runtime
+    /// it should run on
+    pub fn with_runtime(
+        runtime: crate::ContainerRuntime,
+        namespace: String,
+        bundle_path: PathBuf,
+    ) -> Self {
+        Self {
+            runtime: Arc::new(runtime),
             namespace,
             bundle_path,
-        })
+            handle: tokio::runtime::Handle::current(),
+            tasks: Mutex::new(HashMap::new()),
+        }
     }
 
     /// Get container ID from shim ID
@@ -423,136 +567,293 @@ impl TaskServiceImpl {
     fn container_id(&self, shim_id: &str) -> String {
         format!("{}.{}", self.namespace, shim_id)
     }
+
+    fn container(&self, id: &str) -> Result<ContainerInfo> {
+        self.handle
+            .block_on(self.runtime.list())?
+            .into_iter()
+            .find(|c| c.id == id)
+            .ok_or_else(|| ShimError::not_found(format!("Container '{}'", id)))
+    }
+
+    fn exec_process(&self, id: &str, exec_id: &str) -> Result<Arc<ExecProcess>> {
+        self.tasks
+            .lock()
+            .unwrap()
+            .get(id)
+            .and_then(|task| task.execs.get(exec_id).cloned())
+            .ok_or_else(|| ShimError::not_found(format!("Exec '{}' in '{}'", exec_id, id)))
+    }
+
+    /// The exit of the init process of `id`, which has stopped with `code`,
+    /// keeping the time it was first seen
+    fn record_exit(&self, id: &str, code: Option<i32>) -> TaskExit {
+        let mut tasks = self.tasks.lock().unwrap();
+        *tasks
+            .entry(id.to_string())
+            .or_default()
+            .exit
+            .get_or_insert(TaskExit {
+                status: code.map_or(UNKNOWN_EXIT_STATUS, |code| code as u32),
+                at: unix_now(),
+            })
+    }
+
+    /// Copy the output of the init process of `id` to its stdout and stderr
+    /// fifos until it stops
+    fn forward_output(&self, id: &str, stdio: TaskStdio) {
+        if stdio.stdout.is_empty() && stdio.stderr.is_empty() {
+            return;
+        }
+        let runtime = self.runtime.clone();
+        let handle = self.handle.clone();
+        let id = id.to_string();
+        std::thread::spawn(move || {
+            let mut stdout = open_output(&stdio.stdout);
+            let mut stderr = open_output(&stdio.stderr);
+            let options = LogOptions {
+                follow: true,
+                stdout_offset: Some(0),
+                stderr_offset: Some(0),
+                ..Default::default()
+            };
+            let forwarded = handle.block_on(runtime.follow_logs(&id, options, |logs| {
+                stdout.write_all(logs.stdout.as_bytes()).is_ok()
+                    && stderr.write_all(logs.stderr.as_bytes()).is_ok()
+            }));
+            if let Err(e) = forwarded {
+                log::warn!("Stopped forwarding the output of '{}': {}", id, e);
+            }
+        });
+    }
+
+    fn start_exec(&self, id: &str, exec_id: &str) -> Result<StartResponse> {
+        let process = self.exec_process(id, exec_id)?;
+        if !matches!(*process.state.lock().unwrap(), ExecState::Created) {
+            return Err(ShimError::conflict(
+                format!("Exec '{}' in '{}' was already started", exec_id, id),
+                "Exec a new process instead",
+            ));
+        }
+        let stdin: Box<dyn std::io::Read + Send> = match process.stdio.stdin.as_str() {
+            "" => Box::new(std::io::empty()),
+            path => Box::new(std::fs::File::open(path).map_err(|e| {
+                ShimError::io_with_context(e, format!("Failed to open stdin of exec '{}'", exec_id))
+            })?),
+        };
+        let (resize, resized) = mpsc::channel();
+        let stdio = ExecStdio {
+            stdin,
+            stdout: open_output(&process.stdio.stdout),
+            stderr: open_output(&process.stdio.stderr),
+            terminal_size: process.options.tty.then_some(DEFAULT_TERMINAL_SIZE),
+            resize: Some(resized),
+            detach_keys: None,
+            stdin_once: true,
+        };
+        *process.state.lock().unwrap() = ExecState::Running;
+        *process.resize.lock().unwrap() = Some(resize);
+
+        let runtime = self.runtime.clone();
+        let handle = self.handle.clone();
+        let (id, exec_id) = (id.to_string(), exec_id.to_string());
+        std::thread::spawn(move || {
+            let outcome =
+                handle.block_on(runtime.exec_interactive(&id, process.options.clone(), stdio));
+            let status = match outcome {
+                Ok(ExecOutcome::Exited(code)) => code as u32,
+                // Only sessions with detach keys are detached from
+                Ok(ExecOutcome::Detached(_)) => UNKNOWN_EXIT_STATUS,
+                Err(e) => {
+                    log::warn!("Exec '{}' in '{}' failed: {}", exec_id, id, e);
+                    UNKNOWN_EXIT_STATUS
+                }
+            };
+            process.exit(TaskExit {
+                status,
+                at: unix_now(),
+            });
+        });
+        // The exec runs through the runtime, which doesn't expose its PID
+        Ok(StartResponse { pid: 0 })
+    }
 }
 
-#[cfg(feature = "shim-v2")]
 impl TaskService for TaskServiceImpl {
-    fn state(&self, container_id: &str, _exec_id: Option<&str>) -> Result<StateResponse> {
-        // Use tokio runtime to call async methods
-        let rt = tokio::runtime::Runtime::new()
-            .map_err(|e| ShimError::runtime(format!("Failed to create runtime: {}", e)))?;
-
-        let containers = rt
-            .block_on(self.runtime.list())
-            .map_err(|e| ShimError::runtime(format!("Failed to list containers: {}", e)))?;
+    fn state(&self, container_id: &str, exec_id: Option<&str>) -> Result<StateResponse> {
+        if let Some(exec_id) = exec_id {
+            let process = self.exec_process(container_id, exec_id)?;
+            let (status, exit) = match *process.state.lock().unwrap() {
+                ExecState::Created => (Status::Created, None),
+                ExecState::Running => (Status::Running, None),
+                ExecState::Exited(exit) => (Status::Stopped, Some(exit)),
+            };
+            let bundle = self
+                .tasks
+                .lock()
+                .unwrap()
+                .get(container_id)
+                .map(|task| task.bundle.clone())
+                .unwrap_or_default();
+            return Ok(StateResponse {
+                id: exec_id.to_string(),
+                bundle: bundle.display().to_string(),
+                pid: 0,
+                status,
+                stdin: process.stdio.stdin.clone(),
+                stdout: process.stdio.stdout.clone(),
+                stderr: process.stdio.stderr.clone(),
+                terminal: process.stdio.terminal,
+                exit_status: exit.map_or(0, |exit| exit.status),
+                exited_at: exit.map_or(0, |exit| exit.at),
+            });
+        }
 
-        let container = containers
-            .iter()
-            .find(|c| c.id == container_id)
-            .ok_or_else(|| ShimError::not_found(format!("Container '{}'", container_id)))?;
+        let container = self.container(container_id)?;
+        let exit = (container.status == ContainerStatus::Stopped)
+            .then(|| self.record_exit(container_id, container.last_exit_code));
+        let tasks = self.tasks.lock().unwrap();
+        let task = tasks.get(container_id);
+        let bundle = task
+            .map(|task| task.bundle.clone())
+            .filter(|bundle| !bundle.as_os_str().is_empty())
+            .unwrap_or_else(|| self.bundle_path.clone());
+        let stdio = task.map(|task| task.stdio.clone()).unwrap_or_default();
 
         Ok(StateResponse {
-            id: container.id.clone(),
-            bundle: self.bundle_path.display().to_string(),
+            id: container.id,
+            bundle: bundle.display().to_string(),
             pid: container.pid.unwrap_or(0),
             status: Status::from(container.status),
-            stdin: String::new(),
-            stdout: String::new(),
-            stderr: String::new(),
-            terminal: false,
-            exit_status: 0,
-            exited_at: 0,
+            stdin: stdio.stdin,
+            stdout: stdio.stdout,
+            stderr: stdio.stderr,
+            terminal: stdio.terminal,
+            exit_status: exit.map_or(0, |exit| exit.status),
+            exited_at: exit.map_or(0, |exit| exit.at),
         })
     }
 
     fn create(&self, request: CreateTaskRequest) -> Result<CreateTaskResponse> {
-        let rt = tokio::runtime::Runtime::new()
-            .map_err(|e| ShimError::runtime(format!("Failed to create runtime: {}", e)))?;
+        let mut config = oci_to_container_config(&request.id, &request.bundle)?;
+        config.stdio.tty = request.terminal;
 
-        let config = oci_to_container_config(&request.id, &request.bundle)?;
-
-        let container_id = rt
-            .block_on(self.runtime.create(config))
-            .map_err(|e| ShimError::runtime(format!("Failed to create container: {}", e)))?;
-
-        // Get PID from container state
-        let containers = rt
-            .block_on(self.runtime.list())
-            .map_err(|e| ShimError::runtime(format!("Failed to list containers: {}", e)))?;
+        // containerd leaves mounting the image's layers to the shim
+        let rootfs = if request.rootfs.is_empty() {
+            None
+        } else {
+            mount_rootfs(&request.rootfs, &config.rootfs)?;
+            Some(config.rootfs.clone())
+        };
+        if let Err(e) = self.handle.block_on(self.runtime.create(config)) {
+            if let Some(ref rootfs) = rootfs {
+                unmount_rootfs(rootfs);
+            }
+            return Err(e);
+        }
 
-        let pid = containers
-            .iter()
-            .find(|c| c.id == container_id)
-            .and_then(|c| c.pid)
-            .unwrap_or(0);
+        let task = Task {
+            bundle: request.bundle,
+            stdio: TaskStdio {
+                stdin: request.stdin,
+                stdout: request.stdout,
+                stderr: request.stderr,
+                terminal: request.terminal,
+            },
+            rootfs,
+            ..Default::default()
+        };
+        self.tasks.lock().unwrap().insert(request.id.clone(), task);
 
+        let pid = self.container(&request.id)?.pid.unwrap_or(0);
         Ok(CreateTaskResponse { pid })
     }
 
-    fn start(&self, container_id: &str, _exec_id: Option<&str>) -> Result<StartResponse> {
-        let rt = tokio::runtime::Runtime::new()
-            .map_err(|e| ShimError::runtime(format!("Failed to create runtime: {}", e)))?;
-
-        rt.block_on(self.runtime.start(container_id))
-            .map_err(|e| ShimError::runtime(format!("Failed to start container: {}", e)))?;
-
-        // Get PID after start
-        let containers = rt
-            .block_on(self.runtime.list())
-            .map_err(|e| ShimError::runtime(format!("Failed to list containers: {}", e)))?;
-
-        let pid = containers
-            .iter()
-            .find(|c| c.id == container_id)
-            .and_then(|c| c.pid)
-            .unwrap_or(0);
+    fn start(&self, container_id: &str, exec_id: Option<&str>) -> Result<StartResponse> {
+        if let Some(exec_id) = exec_id {
+            return self.start_exec(container_id, exec_id);
+        }
 
+        self.handle.block_on(self.runtime.start(container_id))?;
+        let stdio = self
+            .tasks
+            .lock()
+            .unwrap()
+            .get(container_id)
+            .map(|task| task.stdio.clone())
+            .unwrap_or_default();
+        self.forward_output(container_id, stdio);
+
+        let pid = self.container(container_id)?.pid.unwrap_or(0);
         Ok(StartResponse { pid })
     }
 
-    fn delete(&self, container_id: &str, _exec_id: Option<&str>) -> Result<DeleteResponse> {
-        let rt = tokio::runtime::Runtime::new()
-            .map_err(|e| ShimError::runtime(format!("Failed to create runtime: {}", e)))?;
-
-        // Get container info before delete
-        let containers = rt
-            .block_on(self.runtime.list())
-            .map_err(|e| ShimError::runtime(format!("Failed to list containers: {}", e)))?;
-
-        let container = containers.iter().find(|c| c.id == container_id);
-
-        let pid = container.and_then(|c| c.pid).unwrap_or(0);
+    fn delete(&self, container_id: &str, exec_id: Option<&str>) -> Result<DeleteResponse> {
+        if let Some(exec_id) = exec_id {
+            let process = self.exec_process(container_id, exec_id)?;
+            let exit = match *process.state.lock().unwrap() {
+                ExecState::Running => {
+                    return Err(ShimError::conflict(
+                        format!("Exec '{}' in '{}' is running", exec_id, container_id),
+                        "Wait for it to exit first",
+                    ))
+                }
+                ExecState::Created => None,
+                ExecState::Exited(exit) => Some(exit),
+            };
+            if let Some(task) = self.tasks.lock().unwrap().get_mut(container_id) {
+                task.execs.remove(exec_id);
+            }
+            return Ok(DeleteResponse {
+                pid: 0,
+                exit_status: exit.map_or(0, |exit| exit.status),
+                exited_at: exit.map_or_else(unix_now, |exit| exit.at),
+            });
+        }
 
-        rt.block_on(self.runtime.delete(container_id))
-            .map_err(|e| ShimError::runtime(format!("Failed to delete container: {}", e)))?;
+        let container = self.container(container_id)?;
+        // Fails while the container runs, so it has stopped or never started
+        self.handle.block_on(self.runtime.delete(container_id))?;
+        let exit = self.record_exit(container_id, container.last_exit_code);
+        let task = self.tasks.lock().unwrap().remove(container_id);
+        if let Some(rootfs) = task.and_then(|task| task.rootfs) {
+            unmount_rootfs(&rootfs);
+        }
 
         Ok(DeleteResponse {
-            pid,
-            exit_status: 0,
-            exited_at: 0,
+            pid: container.pid.unwrap_or(0),
+            exit_status: exit.status,
+            exited_at: exit.at,
         })
     }
 
     fn pids(&self, container_id: &str) -> Result<PidsResponse> {
-        let rt = tokio::runtime::Runtime::new()
-            .map_err(|e| ShimError::runtime(format!("Failed to create runtime: {}", e)))?;
-
-        let containers = rt
-            .block_on(self.runtime.list())
-            .map_err(|e| ShimError::runtime(format!("Failed to list containers: {}", e)))?;
-
-        let container = containers
-            .iter()
-            .find(|c| c.id == container_id)
-            .ok_or_else(|| ShimError::not_found(format!("Container '{}'", container_id)))?;
-
-        let processes = container
-            .pid
-            .map(|pid| ProcessInfo { pid, info: None })
+        let container = self.container(container_id)?;
+        let Some(pid) = container.pid else {
+            return Ok(PidsResponse { processes: vec![] });
+        };
+
+        #[cfg(target_os = "linux")]
+        let pids = (pid != std::process::id())
+            .then(|| crate::linux::cgroup_pids(pid))
+            .flatten()
+            .unwrap_or_else(|| vec![pid]);
+        #[cfg(not(target_os = "linux"))]
+        let pids = vec![pid];
+
+        let processes = pids
             .into_iter()
+            .map(|pid| ProcessInfo { pid, info: None })
             .collect();
-
         Ok(PidsResponse { processes })
     }
 
-    fn pause(&self, _container_id: &str) -> Result<()> {
-        // Pause not implemented yet
-        Err(ShimError::runtime("Pause not implemented"))
+    fn pause(&self, container_id: &str) -> Result<()> {
+        self.handle.block_on(self.runtime.pause(container_id))
     }
 
-    fn resume(&self, _container_id: &str) -> Result<()> {
-        // Resume not implemented yet
-        Err(ShimError::runtime("Resume not implemented"))
+    fn resume(&self, container_id: &str) -> Result<()> {
+        self.handle.block_on(self.runtime.unpause(container_id))
     }
 
     fn checkpoint(&self, _container_id: &str, _options: CheckpointOptions) -> Result<()> {
@@ -563,46 +864,103 @@ impl TaskService for TaskServiceImpl {
     fn kill(
         &self,
         container_id: &str,
-        _exec_id: Option<&str>,
+        exec_id: Option<&str>,
         signal: u32,
         _all: bool,
     ) -> Result<()> {
-        let rt = tokio::runtime::Runtime::new()
-            .map_err(|e| ShimError::runtime(format!("Failed to create runtime: {}", e)))?;
-
-        // Stop is equivalent to kill with SIGTERM
-        if signal == libc::SIGTERM as u32 || signal == 15 {
-            rt.block_on(self.runtime.stop(container_id))
-                .map_err(|e| ShimError::runtime(format!("Failed to stop container: {}", e)))?;
-            Ok(())
-        } else {
-            // For other signals, we'd need to send signal to process
-            Err(ShimError::runtime(format!(
+        if let Some(exec_id) = exec_id {
+            self.exec_process(container_id, exec_id)?;
+            return Err(ShimError::runtime(format!(
+                "Signalling exec '{}' is not supported",
+                exec_id
+            )));
+        }
+
+        let container = self.container(container_id)?;
+        if container.status == ContainerStatus::Stopped {
+            return Err(ShimError::not_found(format!(
+                "Running process of '{}'",
+                container_id
+            )));
+        }
+
+        // Signalling init is enough for `all`: the rest of the container's
+        // PID namespace dies with it
+        #[cfg(target_os = "linux")]
+        if let Some(pid) = container.pid.filter(|&pid| pid != std::process::id()) {
+            if unsafe { libc::kill(pid as libc::pid_t, signal as libc::c_int) } != 0 {
+                return Err(ShimError::io_with_context(
+                    std::io::Error::last_os_error(),
+                    format!("Failed to signal '{}'", container_id),
+                ));
+            }
+            return Ok(());
+        }
+
+        // Without a process to signal, stopping is all there is
+        match signal {
+            SIGTERM | SIGKILL => self.handle.block_on(self.runtime.stop(container_id)),
+            _ => Err(ShimError::runtime(format!(
                 "Signal {} not supported",
                 signal
-            )))
+            ))),
         }
     }
 
-    fn exec(&self, _request: ExecProcessRequest) -> Result<()> {
-        // Exec not fully implemented yet
-        Err(ShimError::runtime("Exec not fully implemented"))
+    fn exec(&self, request: ExecProcessRequest) -> Result<()> {
+        let options = exec_options(&request.spec, request.terminal)?;
+        let mut tasks = self.tasks.lock().unwrap();
+        let task = tasks
+            .get_mut(&request.container_id)
+            .ok_or_else(|| ShimError::not_found(format!("Task '{}'", request.container_id)))?;
+        if task.execs.contains_key(&request.exec_id) {
+            return Err(ShimError::conflict(
+                format!(
+                    "Exec '{}' already exists in '{}'",
+                    request.exec_id, request.container_id
+                ),
+                "Use another exec ID",
+            ));
+        }
+
+        let process = ExecProcess {
+            options,
+            stdio: TaskStdio {
+                stdin: request.stdin,
+                stdout: request.stdout,
+                stderr: request.stderr,
+                terminal: request.terminal,
+            },
+            state: Mutex::new(ExecState::Created),
+            exited: Condvar::new(),
+            resize: Mutex::new(None),
+        };
+        task.execs.insert(request.exec_id, Arc::new(process));
+        Ok(())
     }
 
     fn resize_pty(
         &self,
-        _container_id: &str,
-        _exec_id: Option<&str>,
-        _width: u32,
-        _height: u32,
+        container_id: &str,
+        exec_id: Option<&str>,
+        width: u32,
+        height: u32,
     ) -> Result<()> {
-        // Resize PTY not implemented yet
-        Err(ShimError::runtime("Resize PTY not implemented"))
+        let Some(exec_id) = exec_id else {
+            return Err(ShimError::runtime(
+                "Resizing the terminal of a container's init process is not supported",
+            ));
+        };
+        let process = self.exec_process(container_id, exec_id)?;
+        if let Some(ref resize) = *process.resize.lock().unwrap() {
+            let _ = resize.send((height as u16, width as u16));
+        }
+        Ok(())
     }
 
     fn close_io(&self, _container_id: &str, _exec_id: Option<&str>, _stdin: bool) -> Result<()> {
-        // Close IO not implemented yet
-        Ok(()) // No-op for now
+        // The input ends as containerd closes its end of the stdin fifo
+        Ok(())
     }
 
     fn update(&self, _container_id: &str, _resources: Resources) -> Result<()> {
@@ -610,53 +968,37 @@ impl TaskService for TaskServiceImpl {
         Err(ShimError::runtime("Update resources not implemented"))
     }
 
-    fn wait(&self, container_id: &str, _exec_id: Option<&str>) -> Result<WaitResponse> {
-        let rt = tokio::runtime::Runtime::new()
-            .map_err(|e| ShimError::runtime(format!("Failed to create runtime: {}", e)))?;
-
-        // Wait for container to stop
-        loop {
-            let containers = rt
-                .block_on(self.runtime.list())
-                .map_err(|e| ShimError::runtime(format!("Failed to list containers: {}", e)))?;
-
-            let container = containers.iter().find(|c| c.id == container_id);
-
-            if let Some(container) = container {
-                if container.status == crate::types::ContainerStatus::Stopped {
-                    return Ok(WaitResponse {
-                        exit_status: 0,
-                        exited_at: 0,
-                    });
-                }
-            } else {
-                return Ok(WaitResponse {
-                    exit_status: 0,
-                    exited_at: 0,
-                });
-            }
+    fn wait(&self, container_id: &str, exec_id: Option<&str>) -> Result<WaitResponse> {
+        if let Some(exec_id) = exec_id {
+            return Ok(self.exec_process(container_id, exec_id)?.wait().into());
+        }
 
-            std::thread::sleep(std::time::Duration::from_millis(100));
+        let known = self
+            .tasks
+            .lock()
+            .unwrap()
+            .get(container_id)
+            .and_then(|task| task.exit);
+        if let Some(exit) = known {
+            return Ok(exit.into());
         }
+        let status = self.handle.block_on(self.runtime.wait(container_id))?;
+        Ok(self.record_exit(container_id, status.code).into())
     }
 
     fn stats(&self, container_id: &str) -> Result<StatsResponse> {
-        let rt = tokio::runtime::Runtime::new()
-            .map_err(|e| ShimError::runtime(format!("Failed to create runtime: {}", e)))?;
-
-        let metrics = rt
-            .block_on(self.runtime.metrics(container_id))
-            .map_err(|e| ShimError::runtime(format!("Failed to get metrics: {}", e)))?;
+        let metrics = self.handle.block_on(self.runtime.metrics(container_id))?;
 
         // Convert metrics to JSON
         let stats_json = serde_json::json!({
             "cpu": {
                 "usage": {
-                    "total": metrics.cpu.total_usage,
-                    "percpu": metrics.cpu.per_cpu_usage.clone(),
+                    "total": metrics.cpu.usage_total,
+                    "user": metrics.cpu.usage_user,
+                    "kernel": metrics.cpu.usage_system,
+                    "percpu": metrics.cpu.per_cpu,
                 },
                 "throttling": {
-                    "periods": metrics.cpu.throttle_periods,
                     "throttled_periods": metrics.cpu.throttled_periods,
                     "throttled_time": metrics.cpu.throttled_time,
                 },
@@ -674,23 +1016,176 @@ impl TaskService for TaskServiceImpl {
         Ok(StatsResponse { stats: stats_json })
     }
 
-    fn connect(&self, _container_id: &str) -> Result<ConnectResponse> {
+    fn connect(&self, container_id: &str) -> Result<ConnectResponse> {
         Ok(ConnectResponse {
             shim_pid: std::process::id(),
-            task_pid: 0,
+            task_pid: self.container(container_id)?.pid.unwrap_or(0),
             version: "v2".to_string(),
         })
     }
 
     fn shutdown(&self) -> Result<()> {
-        let rt = tokio::runtime::Runtime::new()
-            .map_err(|e| ShimError::runtime(format!("Failed to create runtime: {}", e)))?;
+        self.handle.block_on(self.runtime.shutdown())
+    }
+}
+
+/// Where to write a process's output: the fifo at `path`, or nowhere when
+/// containerd gave none or it can't be opened
+fn open_output(path: &str) -> Box<dyn Write + Send> {
+    if path.is_empty() {
+        return Box::new(std::io::sink());
+    }
+    match std::fs::OpenOptions::new().write(true).open(path) {
+        Ok(file) => Box::new(file),
+        Err(e) => {
+            log::warn!("Failed to open output fifo {}: {}", path, e);
+            Box::new(std::io::sink())
+        }
+    }
+}
 
-        rt.block_on(self.runtime.shutdown())
-            .map_err(|e| ShimError::runtime(format!("Failed to shutdown: {}", e)))?;
+/// Mount the rootfs mounts of a create request on `target`, in order
+#[cfg(target_os = "linux")]
+fn mount_rootfs(mounts: &[Mount], target: &Path) -> Result<()> {
+    use std::ffi::CString;
 
-        Ok(())
+    std::fs::create_dir_all(target).map_err(|e| {
+        ShimError::io_with_context(e, format!("Failed to create {}", target.display()))
+    })?;
+    let c_string = |value: &str, field: &str| {
+        CString::new(value).map_err(|_| ShimError::validation(field, "contains a NUL byte"))
+    };
+    let target_c = c_string(&target.display().to_string(), "rootfs.target")?;
+    for mount in mounts {
+        let (flags, data) = mount_options(&mount.options);
+        let source = c_string(&mount.source, "rootfs.source")?;
+        let fstype = c_string(&mount.mount_type, "rootfs.type")?;
+        let data = c_string(&data, "rootfs.options")?;
+        let result = unsafe {
+            libc::mount(
+                source.as_ptr(),
+                target_c.as_ptr(),
+                fstype.as_ptr(),
+                flags,
+                data.as_ptr() as *const libc::c_void,
+            )
+        };
+        if result != 0 {
+            let error = std::io::Error::last_os_error();
+            unmount_rootfs(target);
+            return Err(ShimError::io_with_context(
+                error,
+                format!(
+                    "Failed to mount {} ({}) on {}",
+                    mount.source,
+                    mount.mount_type,
+                    target.display()
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn mount_rootfs(_mounts: &[Mount], _target: &Path) -> Result<()> {
+    Err(ShimError::runtime(
+        "Rootfs mounts are only supported on Linux",
+    ))
+}
+
+/// Flags and filesystem data for mount(2) from mount options, e.g. "ro" and
+/// "lowerdir=..."
+#[cfg(target_os = "linux")]
+fn mount_options(options: &[String]) -> (libc::c_ulong, String) {
+    let mut flags = 0;
+    let mut data = Vec::new();
+    for option in options {
+        let (set, flag) = match option.as_str() {
+            "ro" => (true, libc::MS_RDONLY),
+            "rw" => (false, libc::MS_RDONLY),
+            "bind" => (true, libc::MS_BIND),
+            "rbind" => (true, libc::MS_BIND | libc::MS_REC),
+            "nosuid" => (true, libc::MS_NOSUID),
+            "suid" => (false, libc::MS_NOSUID),
+            "nodev" => (true, libc::MS_NODEV),
+            "dev" => (false, libc::MS_NODEV),
+            "noexec" => (true, libc::MS_NOEXEC),
+            "exec" => (false, libc::MS_NOEXEC),
+            "noatime" => (true, libc::MS_NOATIME),
+            "relatime" => (true, libc::MS_RELATIME),
+            _ => {
+                data.push(option.as_str());
+                continue;
+            }
+        };
+        if set {
+            flags |= flag;
+        } else {
+            flags &= !flag;
+        }
     }
+    (flags, data.join(","))
+}
+
+/// Undo [`mount_rootfs`], lazily so busy mounts go once unused
+fn unmount_rootfs(target: &Path) {
+    #[cfg(target_os = "linux")]
+    {
+        let Ok(target_c) = std::ffi::CString::new(target.display().to_string()) else {
+            return;
+        };
+        if unsafe { libc::umount2(target_c.as_ptr(), libc::MNT_DETACH) } != 0 {
+            log::debug!(
+                "Failed to unmount {}: {}",
+                target.display(),
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = target;
+}
+
+/// Exec options from the OCI process spec of an exec request
+fn exec_options(spec: &serde_json::Value, terminal: bool) -> Result<ExecOptions> {
+    let command = string_array(&spec["args"]);
+    if command.is_empty() {
+        return Err(ShimError::validation(
+            "spec.args",
+            "the process needs a command",
+        ));
+    }
+    Ok(ExecOptions {
+        command,
+        env: string_array(&spec["env"]),
+        working_dir: spec["cwd"]
+            .as_str()
+            .filter(|cwd| !cwd.is_empty())
+            .map(String::from),
+        uid: spec["user"]["uid"].as_u64().map(|uid| uid as u32),
+        gid: spec["user"]["gid"].as_u64().map(|gid| gid as u32),
+        tty: terminal || spec["terminal"].as_bool().unwrap_or(false),
+    })
+}
+
+/// The strings of a JSON array, none when `value` isn't one
+fn string_array(value: &serde_json::Value) -> Vec<String> {
+    value
+        .as_array()
+        .map(|arr| {
+            arr.iter()
+                .filter_map(|v| v.as_str().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
 }
 
 /// Parse OCI bundle config.json
@@ -720,23 +1215,12 @@ pub fn oci_to_container_config(
 
     let rootfs = bundle_path.join(oci_config["root"]["path"].as_str().unwrap_or("rootfs"));
 
-    let command: Vec<String> = oci_config["process"]["args"]
-        .as_array()
-        .map(|arr| {
-            arr.iter()
-                .filter_map(|v| v.as_str().map(String::from))
-                .collect()
-        })
-        .unwrap_or_else(|| vec!["/bin/sh".to_string()]);
+    let mut command = string_array(&oci_config["process"]["args"]);
+    if command.is_empty() {
+        command = vec!["/bin/sh".to_string()];
+    }
 
-    let env: Vec<String> = oci_config["process"]["env"]
-        .as_array()
-        .map(|arr| {
-            arr.iter()
-                .filter_map(|v| v.as_str().map(String::from))
-                .collect()
-        })
-        .unwrap_or_default();
+    let env = string_array(&oci_config["process"]["env"]);
 
     let working_dir = oci_config["process"]["cwd"]
         .as_str()
@@ -768,4 +1252,155 @@ mod tests {
             Status::Stopped
         );
     }
+
+    #[test]
+    fn test_shim_args() {
+        let args = ShimArgs::parse(
+            [
+                "-namespace",
+                "k8s.io",
+                "--address=/run/containerd/containerd.sock",
+                "-publish-binary",
+                "/usr/bin/containerd",
+                "-id",
+                "web",
+                "-debug",
+                "start",
+            ]
+            .map(String::from),
+        )
+        .unwrap();
+        assert_eq!(args.namespace, "k8s.io");
+        assert_eq!(args.address, "/run/containerd/containerd.sock");
+        assert_eq!(args.publish_binary, "/usr/bin/containerd");
+        assert_eq!(args.id, "web");
+        assert!(args.debug);
+        assert_eq!(args.action.as_deref(), Some("start"));
+
+        let socket_path = args.socket_path();
+        assert!(socket_path.starts_with(SOCKET_DIR));
+        assert_eq!(socket_path.file_name().unwrap().len(), 64);
+        let other = ShimArgs {
+            id: "db".to_string(),
+            ..args.clone()
+        };
+        assert_ne!(other.socket_path(), socket_path);
+
+        assert!(ShimArgs::parse(["-id".to_string()]).is_err());
+        assert!(ShimArgs::parse(["-runtime-root=/run".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_exec_options() {
+        let spec = serde_json::json!({
+            "args": ["sh", "-c", "id"],
+            "env": ["PATH=/bin"],
+            "cwd": "/srv",
+            "user": {"uid": 1000, "gid": 100},
+            "terminal": false,
+        });
+        let options = exec_options(&spec, true).unwrap();
+        assert_eq!(options.command, ["sh", "-c", "id"]);
+        assert_eq!(options.env, ["PATH=/bin"]);
+        assert_eq!(options.working_dir.as_deref(), Some("/srv"));
+        assert_eq!((options.uid, options.gid), (Some(1000), Some(100)));
+        assert!(options.tty);
+
+        let err = exec_options(&serde_json::json!({"cwd": "/"}), false).unwrap_err();
+        assert!(matches!(err, ShimError::Validation { .. }));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_mount_options() {
+        let options = ["ro", "nosuid", "lowerdir=/a:/b", "rw", "upperdir=/c"].map(String::from);
+        let (flags, data) = mount_options(&options);
+        assert_eq!(flags, libc::MS_NOSUID);
+        assert_eq!(data, "lowerdir=/a:/b,upperdir=/c");
+
+        let (flags, data) = mount_options(&["rbind".to_string()]);
+        assert_eq!(flags, libc::MS_BIND | libc::MS_REC);
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_task_lifecycle() {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let bundle = std::env::temp_dir().join(format!("shim-bundle-{}", std::process::id()));
+        std::fs::create_dir_all(bundle.join("rootfs")).unwrap();
+        std::fs::write(
+            bundle.join("config.json"),
+            r#"{"root": {"path": "rootfs"}, "process": {"args": ["sleep", "60"], "cwd": "/"}}"#,
+        )
+        .unwrap();
+        let tasks = runtime
+            .block_on(TaskServiceImpl::new("default".to_string(), bundle.clone()))
+            .unwrap();
+
+        let id = "shim-task";
+        tasks
+            .create(CreateTaskRequest {
+                id: id.to_string(),
+                bundle: bundle.clone(),
+                rootfs: vec![],
+                terminal: false,
+                stdin: String::new(),
+                stdout: String::new(),
+                stderr: String::new(),
+                checkpoint: None,
+                parent_checkpoint: None,
+                options: None,
+            })
+            .unwrap();
+        let state = tasks.state(id, None).unwrap();
+        assert_eq!(state.status, Status::Created);
+        assert_eq!(state.bundle, bundle.display().to_string());
+
+        tasks.start(id, None).unwrap();
+        assert_eq!(tasks.state(id, None).unwrap().status, Status::Running);
+        assert!(!tasks.pids(id).unwrap().processes.is_empty());
+
+        tasks
+            .exec(ExecProcessRequest {
+                container_id: id.to_string(),
+                exec_id: "probe".to_string(),
+                terminal: false,
+                stdin: String::new(),
+                stdout: String::new(),
+                stderr: String::new(),
+                spec: serde_json::json!({"args": ["true"]}),
+            })
+            .unwrap();
+        assert_eq!(
+            tasks.state(id, Some("probe")).unwrap().status,
+            Status::Created
+        );
+        tasks.delete(id, Some("probe")).unwrap();
+        assert!(tasks.state(id, Some("probe")).unwrap_err().is_not_found());
+
+        tasks.kill(id, None, SIGTERM, false).unwrap();
+        let exit = tasks.wait(id, None).unwrap();
+        let state = tasks.state(id, None).unwrap();
+        assert_eq!(state.status, Status::Stopped);
+        assert_eq!(state.exit_status, exit.exit_status);
+        assert!(tasks
+            .kill(id, None, SIGTERM, false)
+            .unwrap_err()
+            .is_not_found());
+
+        let deleted = tasks.delete(id, None).unwrap();
+        assert_eq!(
+            (deleted.exit_status, deleted.exited_at),
+            (exit.exit_status, exit.exited_at)
+        );
+        assert!(tasks.state(id, None).unwrap_err().is_not_found());
+
+        drop(tasks);
+        runtime.shutdown_background();
+        let _ = std::fs::remove_dir_all(&bundle);
+    }
 }
@@ -0,0 +1,495 @@
+//! The task API over ttrpc, as containerd talks to shims
+//!
+//! [`Service`] adapts [`TaskServiceImpl`] to the `Task` service generated
+//! in containerd-shim-protos, and publishes a `TaskExit` event through
+//! containerd's publish binary when a process exits. The entry points are
+//! the actions of the `containerd-shim-crun-v2` binary.
+
+use super::{
+    CreateTaskRequest, ExecProcessRequest, Mount, ShimArgs, Status, TaskService, TaskServiceImpl,
+    WaitResponse,
+};
+use crate::error::{Result, ShimError};
+use crate::socket;
+use containerd_shim_protos::api;
+use containerd_shim_protos::events::task::TaskExit;
+use containerd_shim_protos::protobuf::well_known_types::{Any, Timestamp};
+use containerd_shim_protos::protobuf::{Message, RepeatedField};
+use containerd_shim_protos::shim::shim_ttrpc::{create_task, Task};
+use std::io::Write;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixListener;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{mpsc, Arc};
+use ttrpc::TtrpcContext;
+
+/// File descriptor `start` hands the listening socket to the shim on
+const SOCKET_FD: RawFd = 3;
+
+/// Exit status `delete` reports for a task whose shim is gone, as if killed
+const KILLED_EXIT_STATUS: u32 = 128 + 9;
+
+/// Launch the shim for `args.id` in the background, serving on its socket,
+/// and return the address for containerd, for the `start` action
+///
+/// The shim is this binary again, in a session of its own so it outlives
+/// containerd, with the socket bound here passed on.
+pub fn start(args: &ShimArgs) -> Result<String> {
+    let socket_path = args.socket_path();
+    let address = format!("unix://{}", socket_path.display());
+    if socket::probe(&socket_path) == socket::SocketState::Live {
+        // The task already has a shim serving
+        return Ok(address);
+    }
+
+    socket::prepare_bind(&socket_path, "A shim")?;
+    let listener = UnixListener::bind(&socket_path).map_err(|e| {
+        ShimError::io_with_context(
+            e,
+            format!("Failed to bind shim socket: {}", socket_path.display()),
+        )
+    })?;
+
+    let exe = std::env::current_exe()
+        .map_err(|e| ShimError::io_with_context(e, "Failed to find the shim binary"))?;
+    let mut command = Command::new(exe);
+    command
+        .args(["-namespace", &args.namespace, "-id", &args.id])
+        .args(["-address", &args.address])
+        .args(["-publish-binary", &args.publish_binary])
+        // containerd reads the output of `start` until every copy of it is
+        // closed, so the shim must not inherit it
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    if let Some(ref bundle) = args.bundle {
+        command.arg("-bundle").arg(bundle);
+    }
+    if args.debug {
+        command.arg("-debug");
+    }
+    let fd = listener.as_raw_fd();
+    unsafe {
+        command.pre_exec(move || {
+            // The copy dup2 makes stays open across exec, but dup2 does
+            // nothing when the listener already is fd 3
+            let result = if fd == SOCKET_FD {
+                libc::fcntl(fd, libc::F_SETFD, 0)
+            } else {
+                libc::dup2(fd, SOCKET_FD)
+            };
+            if result < 0 || libc::setsid() < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    command.spawn().map_err(|e| {
+        ShimError::io_with_context(e, format!("Failed to launch the shim for '{}'", args.id))
+    })?;
+
+    // Where containerd looks for the shim of a task after restarting
+    if let Err(e) = std::fs::write("address", &address) {
+        log::warn!("Failed to write the shim address to the bundle: {}", e);
+    }
+    Ok(address)
+}
+
+/// Serve the task API on the socket `start` passed as fd 3, until containerd
+/// shuts the shim down
+pub fn serve(args: &ShimArgs) -> Result<()> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| ShimError::io_with_context(e, "Failed to create the Tokio runtime"))?;
+    let bundle = match args.bundle {
+        Some(ref bundle) => bundle.clone(),
+        None => std::env::current_dir().unwrap_or_default(),
+    };
+    let tasks = runtime.block_on(TaskServiceImpl::new(args.namespace.clone(), bundle))?;
+    let server = ttrpc::Server::new()
+        .add_listener(SOCKET_FD)
+        .map_err(|e| ShimError::runtime("Failed to listen on the shim socket").with_source(e))?;
+    let publisher = Publisher {
+        binary: args.publish_binary.clone(),
+        address: args.address.clone(),
+        namespace: args.namespace.clone(),
+    };
+    run(server, tasks, publisher)
+}
+
+/// Serve the task API on `socket_path`, without publishing events
+pub(super) fn serve_at(socket_path: &Path, tasks: TaskServiceImpl) -> Result<()> {
+    let _ = std::fs::remove_file(socket_path);
+    let server = ttrpc::Server::new()
+        .bind(&format!("unix://{}", socket_path.display()))
+        .map_err(|e| {
+            ShimError::runtime_with_context(
+                "Failed to bind shim socket",
+                socket_path.display().to_string(),
+            )
+            .with_source(e)
+        })?;
+    log::info!("Shim v2 listening on {}", socket_path.display());
+    run(server, tasks, Publisher::default())
+}
+
+fn run(server: ttrpc::Server, tasks: TaskServiceImpl, publisher: Publisher) -> Result<()> {
+    let (shutdown, shutdown_requested) = mpsc::channel();
+    let service: Box<dyn Task + Send + Sync> = Box::new(Service {
+        tasks: Arc::new(tasks),
+        publisher,
+        shutdown,
+    });
+    let mut server = server.register_service(create_task(Arc::new(service)));
+    server
+        .start()
+        .map_err(|e| ShimError::runtime("Failed to start the ttrpc server").with_source(e))?;
+
+    let _ = shutdown_requested.recv();
+    server.shutdown();
+    Ok(())
+}
+
+/// Clean up after the shim of `args.id` once it is gone, for the `delete`
+/// action, returning the encoded `DeleteResponse` to print
+pub fn cleanup(args: &ShimArgs) -> Result<Vec<u8>> {
+    let bundle = match args.bundle {
+        Some(ref bundle) => bundle.clone(),
+        None => std::env::current_dir().unwrap_or_default(),
+    };
+    super::unmount_rootfs(&bundle.join("rootfs"));
+    let _ = std::fs::remove_file(args.socket_path());
+
+    let mut response = api::DeleteResponse::new();
+    response.set_exit_status(KILLED_EXIT_STATUS);
+    response.set_exited_at(timestamp(super::unix_now()));
+    response
+        .write_to_bytes()
+        .map_err(|e| ShimError::serialization("Failed to encode the delete response", e))
+}
+
+/// Publishes events to containerd through its publish binary, when it
+/// gave one
+#[derive(Debug, Clone, Default)]
+struct Publisher {
+    binary: String,
+    address: String,
+    namespace: String,
+}
+
+impl Publisher {
+    fn task_exit(&self, container_id: &str, id: &str, pid: u32, exit: &WaitResponse) {
+        let mut event = TaskExit::new();
+        event.set_container_id(container_id.to_string());
+        event.set_id(id.to_string());
+        event.set_pid(pid);
+        event.set_exit_status(exit.exit_status);
+        event.set_exited_at(timestamp(exit.exited_at));
+        if let Err(e) = self.publish("/tasks/exit", "containerd.events.TaskExit", &event) {
+            log::warn!("Failed to publish the exit of '{}': {}", id, e);
+        }
+    }
+
+    fn publish(&self, topic: &str, type_url: &str, event: &impl Message) -> Result<()> {
+        if self.binary.is_empty() {
+            return Ok(());
+        }
+        let encode =
+            |e| ShimError::serialization(format!("Failed to encode {} event", type_url), e);
+        let mut any = Any::new();
+        any.set_type_url(type_url.to_string());
+        any.set_value(event.write_to_bytes().map_err(encode)?);
+        let data = any.write_to_bytes().map_err(encode)?;
+
+        let mut child = Command::new(&self.binary)
+            .args(["--address", &self.address, "publish"])
+            .args(["--topic", topic, "--namespace", &self.namespace])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .map_err(|e| ShimError::io_with_context(e, format!("Failed to run {}", self.binary)))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(&data)
+                .map_err(|e| ShimError::io_with_context(e, "Failed to pass on the event"))?;
+        }
+        let status = child
+            .wait()
+            .map_err(|e| ShimError::io_with_context(e, format!("Failed to run {}", self.binary)))?;
+        if !status.success() {
+            return Err(ShimError::runtime(format!(
+                "{} exited with {}",
+                self.binary, status
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// The ttrpc task service
+struct Service {
+    tasks: Arc<TaskServiceImpl>,
+    publisher: Publisher,
+    /// Told when containerd shuts the shim down
+    shutdown: mpsc::Sender<()>,
+}
+
+impl Service {
+    /// Publish the exit of the process once it exits
+    fn watch_exit(&self, container_id: &str, exec_id: Option<&str>, pid: u32) {
+        let tasks = self.tasks.clone();
+        let publisher = self.publisher.clone();
+        let container_id = container_id.to_string();
+        let exec_id = exec_id.map(String::from);
+        std::thread::spawn(move || {
+            let id = exec_id.as_deref().unwrap_or(&container_id);
+            match tasks.wait(&container_id, exec_id.as_deref()) {
+                Ok(exit) => publisher.task_exit(&container_id, id, pid, &exit),
+                Err(e) => log::warn!("Failed to wait for '{}': {}", id, e),
+            }
+        });
+    }
+}
+
+impl Task for Service {
+    fn state(
+        &self,
+        _ctx: &TtrpcContext,
+        req: api::StateRequest,
+    ) -> ttrpc::Result<api::StateResponse> {
+        let state = self
+            .tasks
+            .state(req.get_id(), exec_id(req.get_exec_id()))
+            .map_err(rpc_error)?;
+        let mut response = api::StateResponse::new();
+        response.set_id(state.id);
+        response.set_bundle(state.bundle);
+        response.set_pid(state.pid);
+        response.set_status(match state.status {
+            Status::Unknown => api::Status::UNKNOWN,
+            Status::Created => api::Status::CREATED,
+            Status::Running => api::Status::RUNNING,
+            Status::Stopped => api::Status::STOPPED,
+            Status::Paused => api::Status::PAUSED,
+            Status::Pausing => api::Status::PAUSING,
+        });
+        response.set_stdin(state.stdin);
+        response.set_stdout(state.stdout);
+        response.set_stderr(state.stderr);
+        response.set_terminal(state.terminal);
+        response.set_exit_status(state.exit_status);
+        response.set_exited_at(timestamp(state.exited_at));
+        response.set_exec_id(req.get_exec_id().to_string());
+        Ok(response)
+    }
+
+    fn create(
+        &self,
+        _ctx: &TtrpcContext,
+        req: api::CreateTaskRequest,
+    ) -> ttrpc::Result<api::CreateTaskResponse> {
+        let request = CreateTaskRequest {
+            id: req.get_id().to_string(),
+            bundle: PathBuf::from(req.get_bundle()),
+            rootfs: req
+                .get_rootfs()
+                .iter()
+                .map(|mount| Mount {
+                    mount_type: mount.get_field_type().to_string(),
+                    source: mount.get_source().to_string(),
+                    target: mount.get_target().to_string(),
+                    options: mount.get_options().to_vec(),
+                })
+                .collect(),
+            terminal: req.get_terminal(),
+            stdin: req.get_stdin().to_string(),
+            stdout: req.get_stdout().to_string(),
+            stderr: req.get_stderr().to_string(),
+            checkpoint: non_empty(req.get_checkpoint()),
+            parent_checkpoint: non_empty(req.get_parent_checkpoint()),
+            options: None,
+        };
+        let created = self.tasks.create(request).map_err(rpc_error)?;
+        let mut response = api::CreateTaskResponse::new();
+        response.set_pid(created.pid);
+        Ok(response)
+    }
+
+    fn start(
+        &self,
+        _ctx: &TtrpcContext,
+        req: api::StartRequest,
+    ) -> ttrpc::Result<api::StartResponse> {
+        let exec_id = exec_id(req.get_exec_id());
+        let started = self.tasks.start(req.get_id(), exec_id).map_err(rpc_error)?;
+        self.watch_exit(req.get_id(), exec_id, started.pid);
+        let mut response = api::StartResponse::new();
+        response.set_pid(started.pid);
+        Ok(response)
+    }
+
+    fn delete(
+        &self,
+        _ctx: &TtrpcContext,
+        req: api::DeleteRequest,
+    ) -> ttrpc::Result<api::DeleteResponse> {
+        let deleted = self
+            .tasks
+            .delete(req.get_id(), exec_id(req.get_exec_id()))
+            .map_err(rpc_error)?;
+        let mut response = api::DeleteResponse::new();
+        response.set_pid(deleted.pid);
+        response.set_exit_status(deleted.exit_status);
+        response.set_exited_at(timestamp(deleted.exited_at));
+        Ok(response)
+    }
+
+    fn pids(&self, _ctx: &TtrpcContext, req: api::PidsRequest) -> ttrpc::Result<api::PidsResponse> {
+        let pids = self.tasks.pids(req.get_id()).map_err(rpc_error)?;
+        let processes = pids
+            .processes
+            .into_iter()
+            .map(|process| {
+                let mut info = api::ProcessInfo::new();
+                info.set_pid(process.pid);
+                info
+            })
+            .collect();
+        let mut response = api::PidsResponse::new();
+        response.set_processes(RepeatedField::from_vec(processes));
+        Ok(response)
+    }
+
+    fn pause(&self, _ctx: &TtrpcContext, req: api::PauseRequest) -> ttrpc::Result<api::Empty> {
+        self.tasks.pause(req.get_id()).map_err(rpc_error)?;
+        Ok(api::Empty::new())
+    }
+
+    fn resume(&self, _ctx: &TtrpcContext, req: api::ResumeRequest) -> ttrpc::Result<api::Empty> {
+        self.tasks.resume(req.get_id()).map_err(rpc_error)?;
+        Ok(api::Empty::new())
+    }
+
+    fn kill(&self, _ctx: &TtrpcContext, req: api::KillRequest) -> ttrpc::Result<api::Empty> {
+        self.tasks
+            .kill(
+                req.get_id(),
+                exec_id(req.get_exec_id()),
+                req.get_signal(),
+                req.get_all(),
+            )
+            .map_err(rpc_error)?;
+        Ok(api::Empty::new())
+    }
+
+    fn exec(&self, _ctx: &TtrpcContext, req: api::ExecProcessRequest) -> ttrpc::Result<api::Empty> {
+        let spec = serde_json::from_slice(req.get_spec().get_value()).map_err(|e| {
+            rpc_error(ShimError::validation(
+                "spec",
+                format!("invalid process spec: {}", e),
+            ))
+        })?;
+        let request = ExecProcessRequest {
+            container_id: req.get_id().to_string(),
+            exec_id: req.get_exec_id().to_string(),
+            terminal: req.get_terminal(),
+            stdin: req.get_stdin().to_string(),
+            stdout: req.get_stdout().to_string(),
+            stderr: req.get_stderr().to_string(),
+            spec,
+        };
+        self.tasks.exec(request).map_err(rpc_error)?;
+        Ok(api::Empty::new())
+    }
+
+    fn resize_pty(
+        &self,
+        _ctx: &TtrpcContext,
+        req: api::ResizePtyRequest,
+    ) -> ttrpc::Result<api::Empty> {
+        self.tasks
+            .resize_pty(
+                req.get_id(),
+                exec_id(req.get_exec_id()),
+                req.get_width(),
+                req.get_height(),
+            )
+            .map_err(rpc_error)?;
+        Ok(api::Empty::new())
+    }
+
+    fn close_io(&self, _ctx: &TtrpcContext, req: api::CloseIORequest) -> ttrpc::Result<api::Empty> {
+        self.tasks
+            .close_io(req.get_id(), exec_id(req.get_exec_id()), req.get_stdin())
+            .map_err(rpc_error)?;
+        Ok(api::Empty::new())
+    }
+
+    fn wait(&self, _ctx: &TtrpcContext, req: api::WaitRequest) -> ttrpc::Result<api::WaitResponse> {
+        let exit = self
+            .tasks
+            .wait(req.get_id(), exec_id(req.get_exec_id()))
+            .map_err(rpc_error)?;
+        let mut response = api::WaitResponse::new();
+        response.set_exit_status(exit.exit_status);
+        response.set_exited_at(timestamp(exit.exited_at));
+        Ok(response)
+    }
+
+    fn connect(
+        &self,
+        _ctx: &TtrpcContext,
+        req: api::ConnectRequest,
+    ) -> ttrpc::Result<api::ConnectResponse> {
+        let connected = self.tasks.connect(req.get_id()).map_err(rpc_error)?;
+        let mut response = api::ConnectResponse::new();
+        response.set_shim_pid(connected.shim_pid);
+        response.set_task_pid(connected.task_pid);
+        response.set_version(connected.version);
+        Ok(response)
+    }
+
+    fn shutdown(
+        &self,
+        _ctx: &TtrpcContext,
+        _req: api::ShutdownRequest,
+    ) -> ttrpc::Result<api::Empty> {
+        self.tasks.shutdown().map_err(rpc_error)?;
+        let _ = self.shutdown.send(());
+        Ok(api::Empty::new())
+    }
+}
+
+/// The exec ID of a request, which is empty for the init process
+fn exec_id(id: &str) -> Option<&str> {
+    Some(id).filter(|id| !id.is_empty())
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    Some(value)
+        .filter(|value| !value.is_empty())
+        .map(String::from)
+}
+
+fn timestamp(unix_secs: u64) -> Timestamp {
+    let mut timestamp = Timestamp::new();
+    timestamp.set_seconds(unix_secs as i64);
+    timestamp
+}
+
+/// A ttrpc status with the code containerd maps back to the error
+fn rpc_error(error: ShimError) -> ttrpc::Error {
+    let code = if error.is_not_found() {
+        ttrpc::Code::NOT_FOUND
+    } else if error.is_conflict() {
+        ttrpc::Code::FAILED_PRECONDITION
+    } else if matches!(error, ShimError::Validation { .. }) {
+        ttrpc::Code::INVALID_ARGUMENT
+    } else {
+        ttrpc::Code::UNKNOWN
+    };
+    ttrpc::Error::RpcStatus(ttrpc::get_status(code, error.to_string()))
+}
@@ -0,0 +1,291 @@
+//! Unix sockets left behind by processes that exited
+//!
+//! A daemon that crashes leaves its socket file behind. Connecting to it is
+//! refused, which says nothing of the daemon being gone, and binding to it
+//! fails as the address is in use. These helpers tell a live socket from a
+//! stale one, removing stale ones, and say which it was when they fail.
+
+use crate::error::{Result, ShimError};
+use libcrun_shim_proto::{
+    deserialize_response, read_frame, serialize_request, write_frame, Request, Response,
+};
+use std::io::{Error, ErrorKind};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+/// How long the server on a socket may take to answer a handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What is at a socket path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketState {
+    /// Nothing
+    Missing,
+    /// A file no process listens on, e.g. the socket of one that crashed
+    Stale,
+    /// A process accepts connections, but did not answer the handshake
+    Unresponsive,
+    /// Connecting is not allowed
+    PermissionDenied,
+    /// A process accepts connections and answered the handshake
+    Live,
+}
+
+/// Whether a process listens on the socket at `path`
+pub fn probe(path: &Path) -> SocketState {
+    probe_with(path, |_| Ok(()))
+}
+
+/// Whether a process listens on the socket at `path`, and answers the
+/// `handshake` run over a connection to it
+pub fn probe_with(
+    path: &Path,
+    handshake: impl FnOnce(&mut UnixStream) -> std::io::Result<()>,
+) -> SocketState {
+    let mut stream = match UnixStream::connect(path) {
+        Ok(stream) => stream,
+        Err(e) => return state_of(&e),
+    };
+    let _ = stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT));
+    let _ = stream.set_write_timeout(Some(HANDSHAKE_TIMEOUT));
+    match handshake(&mut stream) {
+        Ok(()) => SocketState::Live,
+        Err(e) => {
+            log::debug!("No handshake over {}: {}", path.display(), e);
+            SocketState::Unresponsive
+        }
+    }
+}
+
+/// Whether an agent listens on the socket at `path`
+pub fn probe_agent(path: &Path) -> SocketState {
+    probe_with(path, agent_handshake)
+}
+
+/// Send the agent protocol's handshake over `stream`, expecting its answer
+pub fn agent_handshake(stream: &mut UnixStream) -> std::io::Result<()> {
+    let request = serialize_request(&Request::Handshake)
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e.to_string()))?;
+    write_frame(stream, &request)?;
+    let frame = read_frame(stream)?.ok_or_else(|| {
+        Error::new(
+            ErrorKind::UnexpectedEof,
+            "connection closed before the handshake",
+        )
+    })?;
+    match deserialize_response(&frame) {
+        Ok(Response::Handshake(_)) => Ok(()),
+        Ok(_) => Err(Error::new(
+            ErrorKind::InvalidData,
+            "unexpected answer to the handshake",
+        )),
+        Err(e) => Err(Error::new(ErrorKind::InvalidData, e.to_string())),
+    }
+}
+
+/// Make way for `server` to bind a socket at `path`, removing a stale socket
+/// there and creating the directory it goes in
+///
+/// Fails rather than take the path of a process still listening on it.
+pub fn prepare_bind(path: &Path, server: &str) -> Result<()> {
+    match probe(path) {
+        SocketState::Missing => {}
+        SocketState::Stale => {
+            remove_stale(path)?;
+        }
+        SocketState::Unresponsive | SocketState::Live => {
+            return Err(ShimError::conflict(
+                format!("{} is already serving on {}", server, path.display()),
+                "Stop the other server first, or use another socket path",
+            ))
+        }
+        SocketState::PermissionDenied => {
+            return Err(ShimError::permission_denied(
+                format!("Cannot replace the socket at {}", path.display()),
+                "Run as the user owning it, or use another socket path",
+            ))
+        }
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| {
+            ShimError::io_with_context(e, format!("Failed to create {}", dir.display()))
+        })?;
+    }
+    Ok(())
+}
+
+/// Connect to `server` on the socket at `path`
+///
+/// A missing or stale socket, which is removed, means the server is not
+/// running; that is [`ShimError::Unavailable`], as it may be starting. Not
+/// being allowed to connect is [`ShimError::PermissionDenied`].
+pub fn connect(path: &Path, server: &str) -> Result<UnixStream> {
+    let error = match UnixStream::connect(path) {
+        Ok(stream) => return Ok(stream),
+        Err(e) => e,
+    };
+    match state_of(&error) {
+        SocketState::Missing => Err(ShimError::Unavailable {
+            message: format!("{} is not running", server),
+            context: Some(format!("No socket at {}", path.display())),
+        }),
+        SocketState::Stale if remove_stale(path)? => Err(ShimError::Unavailable {
+            message: format!("{} is not running", server),
+            context: Some(format!(
+                "Removed the stale socket it left at {}",
+                path.display()
+            )),
+        }),
+        SocketState::PermissionDenied => Err(ShimError::permission_denied(
+            format!("Not allowed to connect to {} at {}", server, path.display()),
+            "Check the socket's owner and mode, or run as a user allowed to use it",
+        )),
+        _ => Err(ShimError::io_with_context(
+            error,
+            format!("Failed to connect to {} at {}", server, path.display()),
+        )),
+    }
+}
+
+/// Remove the stale socket at `path`, returning whether there was one
+///
+/// Anything but a socket is left alone.
+pub fn remove_stale(path: &Path) -> Result<bool> {
+    let is_socket = std::fs::symlink_metadata(path)
+        .map(|metadata| metadata.file_type().is_socket())
+        .unwrap_or(false);
+    if !is_socket {
+        return Err(ShimError::conflict(
+            format!("{} is not a socket", path.display()),
+            "Remove it, or use another socket path",
+        ));
+    }
+    match std::fs::remove_file(path) {
+        Ok(()) => {
+            log::info!("Removed stale socket {}", path.display());
+            Ok(true)
+        }
+        // Someone else removed it first
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(ShimError::io_with_context(
+            e,
+            format!("Failed to remove stale socket {}", path.display()),
+        )),
+    }
+}
+
+fn state_of(error: &Error) -> SocketState {
+    match error.kind() {
+        ErrorKind::NotFound => SocketState::Missing,
+        ErrorKind::PermissionDenied => SocketState::PermissionDenied,
+        // Nothing listens on the socket
+        ErrorKind::ConnectionRefused => SocketState::Stale,
+        _ => SocketState::Unresponsive,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libcrun_shim_proto::{deserialize_request, serialize_response, HandshakeProto};
+    use std::os::unix::net::UnixListener;
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("socket-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_probe() {
+        let dir = temp_dir("probe");
+        let path = dir.join("server.sock");
+        assert_eq!(probe(&path), SocketState::Missing);
+
+        let listener = UnixListener::bind(&path).unwrap();
+        assert_eq!(probe(&path), SocketState::Live);
+        // The listener closes, the file stays
+        drop(listener);
+        assert_eq!(probe(&path), SocketState::Stale);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_prepare_bind() {
+        let dir = temp_dir("bind");
+        let path = dir.join("run").join("server.sock");
+        prepare_bind(&path, "Server").unwrap();
+
+        let listener = UnixListener::bind(&path).unwrap();
+        let err = prepare_bind(&path, "Server").unwrap_err();
+        assert!(err.is_conflict(), "{}", err);
+        assert!(err.to_string().contains("already serving"), "{}", err);
+
+        drop(listener);
+        prepare_bind(&path, "Server").unwrap();
+        assert!(!path.exists());
+        let _listener = UnixListener::bind(&path).unwrap();
+
+        // A file that is not a socket is left alone
+        let file = dir.join("file.sock");
+        std::fs::write(&file, "data").unwrap();
+        assert!(prepare_bind(&file, "Server").is_err());
+        assert!(file.exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_connect() {
+        let dir = temp_dir("connect");
+        let path = dir.join("agent.sock");
+
+        let err = connect(&path, "Agent").unwrap_err();
+        assert!(matches!(err, ShimError::Unavailable { .. }), "{}", err);
+        assert!(err.to_string().contains("Agent is not running"), "{}", err);
+
+        drop(UnixListener::bind(&path).unwrap());
+        let err = connect(&path, "Agent").unwrap_err();
+        assert!(matches!(err, ShimError::Unavailable { .. }), "{}", err);
+        assert!(err.to_string().contains("stale socket"), "{}", err);
+        assert!(err.is_transient());
+        assert!(!path.exists());
+
+        let _listener = UnixListener::bind(&path).unwrap();
+        connect(&path, "Agent").unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_probe_agent() {
+        let dir = temp_dir("agent");
+        let path = dir.join("agent.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = std::thread::spawn(move || {
+            // An agent, then a server closing connections straight away
+            let (mut stream, _) = listener.accept().unwrap();
+            let request = read_frame(&mut stream).unwrap().unwrap();
+            assert!(matches!(
+                deserialize_request(&request).unwrap(),
+                Request::Handshake
+            ));
+            let response = Response::Handshake(HandshakeProto {
+                agent_version: "1.0.0".to_string(),
+                protocol_version: libcrun_shim_proto::PROTOCOL_VERSION,
+            });
+            write_frame(&mut stream, &serialize_response(&response).unwrap()).unwrap();
+            drop(listener.accept().unwrap());
+        });
+
+        assert_eq!(probe_agent(&path), SocketState::Live);
+        assert_eq!(probe_agent(&path), SocketState::Unresponsive);
+        server.join().unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
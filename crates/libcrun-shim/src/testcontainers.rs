@@ -0,0 +1,546 @@
+//! Run images written for [testcontainers](https://docs.rs/testcontainers)
+//! on this runtime, without Docker
+//!
+//! An image from a testcontainers module (`testcontainers_modules`) or a
+//! `GenericImage` describes what to run and when it is ready. [`Runner::start`]
+//! pulls the image, runs it with the image's entrypoint, command,
+//! environment and health check as Docker would, and waits for its ready
+//! conditions. The container is stopped and removed when the returned
+//! [`Container`] is dropped.
+//!
+//! ```ignore
+//! use testcontainers_modules::postgres::Postgres;
+//!
+//! let runtime = ContainerRuntime::new().await?;
+//! let mut runner = Runner::new(&runtime)?;
+//! let postgres = runner.start(Postgres::default()).await?;
+//! let url = format!(
+//!     "postgres://postgres:postgres@{}:{}/postgres",
+//!     postgres.get_host(),
+//!     postgres.get_host_port_ipv4(5432)?
+//! );
+//! ```
+//!
+//! Containers publish no ports: services are reached on their own ports at
+//! the container's address. Bind mounts are supported; volume and tmpfs
+//! mounts, networks, files copied into the container and commands run
+//! after it started are not, and neither is waiting for HTTP responses.
+
+use crate::{
+    ContainerConfig, ContainerRuntime, HealthCheck, ImageReference, ImageStore, LogOptions, Result,
+    ShimError, VolumeMount,
+};
+use ::testcontainers::core::wait::{ExitWaitStrategy, LogWaitStrategy};
+use ::testcontainers::core::{ContainerPort, MountType, WaitFor};
+use ::testcontainers::{ContainerRequest, Image};
+use std::time::Duration;
+
+/// How long a container may take to get ready, unless the request says
+/// otherwise (as testcontainers does)
+const DEFAULT_STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Starts testcontainers images as containers of a runtime
+pub struct Runner<'a> {
+    runtime: &'a ContainerRuntime,
+    store: ImageStore,
+}
+
+impl<'a> Runner<'a> {
+    /// A runner that keeps images in the default image store, pulling them
+    /// through the runtime's proxy
+    pub fn new(runtime: &'a ContainerRuntime) -> Result<Self> {
+        Ok(Self::with_store(
+            runtime,
+            ImageStore::with_proxy(ImageStore::default_path(), &runtime.proxy)?,
+        ))
+    }
+
+    pub fn with_store(runtime: &'a ContainerRuntime, store: ImageStore) -> Self {
+        Self { runtime, store }
+    }
+
+    /// Run an image, or a request made from one with `ImageExt`, and wait
+    /// until it is ready
+    ///
+    /// The image is pulled unless the store has it. A container that does
+    /// not get ready is removed again.
+    pub async fn start<I: Image>(
+        &mut self,
+        request: impl Into<ContainerRequest<I>>,
+    ) -> Result<Container<'a>> {
+        let request = request.into();
+        let config = self.container_config(&request).await?;
+        let id = self.runtime.create(config).await?;
+        let container = Container {
+            runtime: self.runtime,
+            id,
+            host: String::new(),
+            removed: false,
+        };
+        let timeout = request.startup_timeout().unwrap_or(DEFAULT_STARTUP_TIMEOUT);
+        let started = container.start(&request, timeout);
+        let mut container = match tokio::time::timeout(timeout, started).await {
+            Ok(result) => result?,
+            Err(_) => {
+                return Err(ShimError::runtime(format!(
+                    "Container of {} was not ready after {:?}",
+                    request.descriptor(),
+                    timeout
+                )))
+            }
+        };
+        container.host = self
+            .runtime
+            .list()
+            .await?
+            .into_iter()
+            .find(|c| c.id == container.id)
+            .and_then(|c| c.ip_address)
+            .unwrap_or_else(|| "localhost".to_string());
+        Ok(container)
+    }
+
+    /// The container to run for a request, with the settings of its image
+    async fn container_config<I: Image>(
+        &mut self,
+        request: &ContainerRequest<I>,
+    ) -> Result<ContainerConfig> {
+        let reference = request.descriptor();
+        let wanted = ImageReference::parse(&reference).map(|r| r.full_name());
+        let stored = self
+            .store
+            .list()
+            .into_iter()
+            .find(|image| Some(image.reference.full_name()) == wanted);
+        let image = match stored {
+            Some(image) => image,
+            None => self.store.pull(&reference, None).await?,
+        };
+        let rootfs = self
+            .store
+            .get_rootfs(&image.id)
+            .ok_or_else(|| ShimError::not_found(format!("Rootfs of image {}", reference)))?;
+        let image_config = self.store.config(&image.id)?;
+        let image_config = &image_config["config"];
+
+        let strings = |value: &serde_json::Value| -> Vec<String> {
+            value
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|s| s.as_str().map(str::to_string))
+                .collect()
+        };
+        // As in Docker, a new entrypoint drops the image's command too
+        let (entrypoint, image_cmd) = match request.entrypoint() {
+            Some(entrypoint) => (vec![entrypoint.to_string()], vec![]),
+            None => (
+                strings(&image_config["Entrypoint"]),
+                strings(&image_config["Cmd"]),
+            ),
+        };
+        let cmd: Vec<String> = request.cmd().map(|arg| arg.into_owned()).collect();
+        let mut command = entrypoint;
+        command.extend(if cmd.is_empty() { image_cmd } else { cmd });
+        if command.is_empty() {
+            return Err(ShimError::validation(
+                "cmd",
+                format!("Image {} has no command to run", reference),
+            ));
+        }
+
+        let mut env = strings(&image_config["Env"]);
+        for (key, value) in request.env_vars() {
+            let prefix = format!("{}=", key);
+            env.retain(|var| !var.starts_with(&prefix));
+            env.push(format!("{}{}", prefix, value));
+        }
+
+        let mut volumes = Vec::new();
+        for mount in request.mounts() {
+            match (mount.mount_type(), mount.source(), mount.target()) {
+                (MountType::Bind, Some(source), Some(target)) => volumes.push(VolumeMount {
+                    source: source.into(),
+                    destination: target.into(),
+                    options: vec![
+                        "rbind".to_string(),
+                        match mount.access_mode() {
+                            ::testcontainers::core::AccessMode::ReadOnly => "ro",
+                            ::testcontainers::core::AccessMode::ReadWrite => "rw",
+                        }
+                        .to_string(),
+                    ],
+                    propagation: None,
+                    recursive_readonly: false,
+                }),
+                _ => {
+                    return Err(ShimError::validation(
+                        "mounts",
+                        format!("{:?} mounts are not supported", mount.mount_type()),
+                    ))
+                }
+            }
+        }
+
+        let working_dir = request
+            .working_dir()
+            .or_else(|| image_config["WorkingDir"].as_str())
+            .filter(|dir| !dir.is_empty())
+            .unwrap_or("/")
+            .to_string();
+
+        Ok(ContainerConfig {
+            id: request.container_name().clone().unwrap_or_default(),
+            rootfs,
+            command,
+            env,
+            working_dir,
+            volumes,
+            health_check: health_check(&image_config["Healthcheck"]),
+            image: Some(image.reference.full_name()),
+            rootfs_snapshot: true,
+            ..Default::default()
+        })
+    }
+}
+
+/// A running container started by [`Runner::start`], removed when dropped
+pub struct Container<'a> {
+    runtime: &'a ContainerRuntime,
+    id: String,
+    host: String,
+    removed: bool,
+}
+
+impl Container<'_> {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Address the container's services are reached at
+    pub fn get_host(&self) -> &str {
+        &self.host
+    }
+
+    /// The port a container port is reached on, which is the same port
+    /// since containers publish none
+    pub fn get_host_port_ipv4(&self, port: impl Into<ContainerPort>) -> Result<u16> {
+        match port.into() {
+            ContainerPort::Tcp(port) | ContainerPort::Udp(port) | ContainerPort::Sctp(port) => {
+                Ok(port)
+            }
+        }
+    }
+
+    /// Everything the container wrote to stdout so far
+    pub async fn stdout_to_vec(&self) -> Result<Vec<u8>> {
+        let logs = self.runtime.logs(&self.id, LogOptions::default()).await?;
+        Ok(logs.stdout.into_bytes())
+    }
+
+    /// Everything the container wrote to stderr so far
+    pub async fn stderr_to_vec(&self) -> Result<Vec<u8>> {
+        let logs = self.runtime.logs(&self.id, LogOptions::default()).await?;
+        Ok(logs.stderr.into_bytes())
+    }
+
+    /// Stop and remove the container now, rather than when it is dropped
+    pub async fn rm(mut self) -> Result<()> {
+        self.removed = true;
+        remove(self.runtime, &self.id).await
+    }
+
+    /// Start the created container and wait for the request's ready
+    /// conditions, removing the container if that fails
+    async fn start<I: Image>(
+        self,
+        request: &ContainerRequest<I>,
+        timeout: Duration,
+    ) -> Result<Self> {
+        self.runtime.start(&self.id).await?;
+        for condition in request.ready_conditions() {
+            self.wait_for(condition, timeout).await?;
+        }
+        Ok(self)
+    }
+
+    async fn wait_for(&self, condition: WaitFor, timeout: Duration) -> Result<()> {
+        match condition {
+            WaitFor::Nothing => Ok(()),
+            WaitFor::Duration { length } => {
+                tokio::time::sleep(length).await;
+                Ok(())
+            }
+            WaitFor::Healthcheck(_) => {
+                self.runtime
+                    .wait_ready(&self.id, crate::ReadyCheck::Healthy, timeout)
+                    .await
+            }
+            WaitFor::Log(strategy) => self.wait_for_log(&strategy).await,
+            WaitFor::Exit(strategy) => {
+                let status = self.runtime.wait(&self.id).await?;
+                match expected_exit_code(&strategy) {
+                    Some(code) if status.code.map(i64::from) != Some(code) => {
+                        Err(ShimError::runtime(format!(
+                            "Container '{}' exited with {:?} instead of {}",
+                            self.id, status.code, code
+                        )))
+                    }
+                    _ => Ok(()),
+                }
+            }
+            #[allow(unreachable_patterns)]
+            other => Err(ShimError::validation(
+                "ready_conditions",
+                format!("{:?} is not supported", other),
+            )),
+        }
+    }
+
+    /// Wait until a message appeared in the container's output as often as
+    /// the strategy asks
+    async fn wait_for_log(&self, strategy: &LogWaitStrategy) -> Result<()> {
+        let (stderr, message, times) = log_wait(strategy).ok_or_else(|| {
+            ShimError::validation("ready_conditions", format!("Unknown {:?}", strategy))
+        })?;
+        let mut output = String::new();
+        let mut seen = false;
+        self.runtime
+            .follow_logs(&self.id, LogOptions::default(), |logs| {
+                output.push_str(if stderr { &logs.stderr } else { &logs.stdout });
+                seen = output.matches(message.as_str()).count() >= times;
+                !seen
+            })
+            .await?;
+        if seen {
+            return Ok(());
+        }
+        Err(ShimError::runtime_with_context(
+            format!(
+                "Container '{}' exited before writing {:?} to {}",
+                self.id,
+                message,
+                if stderr { "stderr" } else { "stdout" }
+            ),
+            "Check its logs for why it exited",
+        ))
+    }
+}
+
+impl Drop for Container<'_> {
+    fn drop(&mut self) {
+        if self.removed {
+            return;
+        }
+        let (runtime, id) = (self.runtime, self.id.as_str());
+        // Drop cannot await, and may run inside a runtime that must not be
+        // blocked, so the removal runs on a runtime of its own
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                let result = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .map_err(|e| ShimError::io_with_context(e, "Failed to start a runtime"))
+                    .and_then(|rt| rt.block_on(remove(runtime, id)));
+                if let Err(e) = result {
+                    log::warn!("Failed to remove container '{}': {}", id, e);
+                }
+            });
+        });
+    }
+}
+
+async fn remove(runtime: &ContainerRuntime, id: &str) -> Result<()> {
+    // Stopped already if it exited
+    if let Err(e) = runtime.stop(id).await {
+        log::debug!("Not stopping container '{}': {}", id, e);
+    }
+    runtime.delete(id).await
+}
+
+/// The health check of an image config, which gives its times in
+/// nanoseconds
+fn health_check(healthcheck: &serde_json::Value) -> Option<HealthCheck> {
+    let test: Vec<&str> = healthcheck["Test"]
+        .as_array()?
+        .iter()
+        .filter_map(|arg| arg.as_str())
+        .collect();
+    let command = match test.split_first()? {
+        (&"CMD", args) => args.iter().map(|arg| arg.to_string()).collect(),
+        (&"CMD-SHELL", [script]) => {
+            vec!["/bin/sh".to_string(), "-c".to_string(), script.to_string()]
+        }
+        _ => return None,
+    };
+    let secs = |key: &str, default: u64| match healthcheck[key].as_u64() {
+        Some(nanos) if nanos > 0 => (nanos / 1_000_000_000).max(1),
+        _ => default,
+    };
+    let defaults = HealthCheck::default();
+    Some(HealthCheck {
+        command,
+        interval: secs("Interval", defaults.interval),
+        timeout: secs("Timeout", defaults.timeout),
+        retries: healthcheck["Retries"]
+            .as_u64()
+            .filter(|&retries| retries > 0)
+            .map_or(defaults.retries, |retries| retries as u32),
+        start_period: secs("StartPeriod", defaults.start_period),
+    })
+}
+
+/// Whether a log strategy reads stderr, its message and how often the
+/// message must appear
+///
+/// testcontainers keeps these private, so they are read from the
+/// strategy's `Debug` output: `LogWaitStrategy { source: StdErr, message:
+/// b"...", times: 2 }`, the message escaped as a byte string literal.
+fn log_wait(strategy: &LogWaitStrategy) -> Option<(bool, String, usize)> {
+    let debug = format!("{:?}", strategy);
+    let stderr = match debug.split("source: ").nth(1)?.split(',').next()? {
+        "StdOut" => false,
+        "StdErr" => true,
+        _ => return None,
+    };
+    let (literal, rest) = debug
+        .split_once("message: b\"")?
+        .1
+        .split_once("\", times: ")?;
+    let times = rest.trim_end_matches(" }").parse().ok()?;
+    Some((stderr, unescape(literal)?, times))
+}
+
+/// The expected exit code of an exit strategy, read as in [`log_wait`]
+fn expected_exit_code(strategy: &ExitWaitStrategy) -> Option<i64> {
+    let debug = format!("{:?}", strategy);
+    let code = debug
+        .split("expected_code: Some(")
+        .nth(1)?
+        .split(')')
+        .next()?;
+    code.parse().ok()
+}
+
+/// The bytes of an escaped byte string literal, as UTF-8
+fn unescape(literal: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(literal.len());
+    let mut chars = literal.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            bytes.push(u8::try_from(c).ok()?);
+            continue;
+        }
+        bytes.push(match chars.next()? {
+            'n' => b'\n',
+            'r' => b'\r',
+            't' => b'\t',
+            '0' => b'\0',
+            '\\' => b'\\',
+            '"' => b'"',
+            'x' => {
+                let hex: String = chars.by_ref().take(2).collect();
+                u8::from_str_radix(&hex, 16).ok()?
+            }
+            _ => return None,
+        });
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::testcontainers::{GenericImage, ImageExt};
+
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn test_run_image() {
+        let root = std::env::temp_dir().join(format!("tc-images-{}", std::process::id()));
+        let image_dir = root.join("fake1");
+        std::fs::create_dir_all(image_dir.join("rootfs")).unwrap();
+        let config = serde_json::json!({ "config": {
+            "Entrypoint": ["docker-entrypoint.sh"],
+            "Cmd": ["postgres"],
+            "Env": ["PATH=/usr/bin", "PGDATA=/data"],
+            "WorkingDir": "/srv",
+        }});
+        std::fs::write(image_dir.join("config.json"), config.to_string()).unwrap();
+        let info = crate::ImageInfo {
+            reference: ImageReference::parse("fake:1").unwrap(),
+            id: "fake1".to_string(),
+            size: 0,
+            created: 0,
+            architecture: "amd64".to_string(),
+            os: "linux".to_string(),
+            labels: Default::default(),
+            layers: vec![],
+        };
+        std::fs::write(
+            image_dir.join("image_info.json"),
+            serde_json::to_string(&info).unwrap(),
+        )
+        .unwrap();
+
+        let runtime = ContainerRuntime::new().await.unwrap();
+        let mut runner = Runner::with_store(&runtime, ImageStore::new(&root).unwrap());
+        let request = GenericImage::new("fake", "1")
+            .with_wait_for(WaitFor::Nothing)
+            .with_env_var("PGDATA", "/tmp/data")
+            .with_container_name("tc-fake");
+        let config = runner.container_config(&request).await.unwrap();
+        assert_eq!(config.command, ["docker-entrypoint.sh", "postgres"]);
+        assert_eq!(config.env, ["PATH=/usr/bin", "PGDATA=/tmp/data"]);
+        assert_eq!(config.working_dir, "/srv");
+        assert_eq!(config.image.as_deref(), Some("docker.io/library/fake:1"));
+
+        let container = runner.start(request).await.unwrap();
+        assert_eq!(container.id(), "tc-fake");
+        assert_eq!(container.get_host_port_ipv4(5432).unwrap(), 5432);
+        let containers = runtime.list().await.unwrap();
+        assert_eq!(containers[0].status, crate::ContainerStatus::Running);
+        // Removed when dropped
+        drop(container);
+        assert!(runtime.list().await.unwrap().is_empty());
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_log_wait() {
+        let strategy = LogWaitStrategy::stderr("ready to \"accept\"\n\u{e9}").with_times(2);
+        assert_eq!(
+            log_wait(&strategy),
+            Some((true, "ready to \"accept\"\n\u{e9}".to_string(), 2))
+        );
+        let strategy = LogWaitStrategy::stdout("listening, port: 80");
+        assert_eq!(
+            log_wait(&strategy),
+            Some((false, "listening, port: 80".to_string(), 1))
+        );
+
+        assert_eq!(
+            expected_exit_code(&ExitWaitStrategy::new().with_exit_code(3)),
+            Some(3)
+        );
+        assert_eq!(expected_exit_code(&ExitWaitStrategy::new()), None);
+    }
+
+    #[test]
+    fn test_health_check() {
+        let check = health_check(&serde_json::json!({
+            "Test": ["CMD-SHELL", "pg_isready -U postgres"],
+            "Interval": 5_000_000_000u64,
+            "Retries": 5,
+        }))
+        .unwrap();
+        assert_eq!(check.command, ["/bin/sh", "-c", "pg_isready -U postgres"]);
+        assert_eq!((check.interval, check.retries), (5, 5));
+        assert_eq!(check.timeout, HealthCheck::default().timeout);
+
+        let check = health_check(&serde_json::json!({ "Test": ["CMD", "true"] })).unwrap();
+        assert_eq!(check.command, ["true"]);
+        assert!(health_check(&serde_json::json!({ "Test": ["NONE"] })).is_none());
+        assert!(health_check(&serde_json::Value::Null).is_none());
+    }
+}
@@ -1,5 +1,7 @@
+use crate::proxy::ProxyConfig;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
 /// Runtime configuration for the container shim
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -43,6 +45,548 @@ pub struct RuntimeConfig {
     /// VM network configuration
     #[serde(default)]
     pub vm_network: VmNetworkConfig,
+
+    /// Named container profiles, referenced by `ContainerConfig::profile`
+    #[serde(default)]
+    pub profiles: HashMap<String, ContainerProfile>,
+
+    /// Named container templates, run with `crun-shim run @<name>`
+    #[serde(default)]
+    pub templates: HashMap<String, ContainerTemplate>,
+
+    /// Retries for idempotent agent requests that fail transiently
+    #[serde(default)]
+    pub retry_policy: RetryPolicy,
+
+    /// When to shut down an idle VM that the runtime booted itself
+    #[serde(default)]
+    pub idle_policy: IdlePolicy,
+
+    /// Free memory and disk required to start a container
+    #[serde(default)]
+    pub resource_guard: ResourceGuard,
+
+    /// How the VM's clock is kept in line with the host's
+    #[serde(default)]
+    pub time_sync: TimeSyncPolicy,
+
+    /// HTTP proxies for image pulls and containers
+    #[serde(default)]
+    pub proxy: ProxyConfig,
+
+    /// Limits on the size of pulled images once extracted
+    #[serde(default)]
+    pub extract_limits: ExtractLimits,
+
+    /// Transports to the agent, in order of preference
+    #[serde(default)]
+    pub transport: TransportPolicy,
+}
+
+/// Retry behavior for agent requests that are safe to repeat
+///
+/// Delays grow exponentially from `initial_backoff_ms` up to
+/// `max_backoff_ms`, with jitter so that clients don't retry in lockstep.
+/// Only transient failures (see [`crate::ShimError::is_transient`]) are retried.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    /// Attempts including the first one; 1 disables retries
+    #[serde(default = "default_retry_attempts")]
+    pub max_attempts: u32,
+    /// Delay before the first retry in milliseconds
+    #[serde(default = "default_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    /// Upper bound on a single delay in milliseconds
+    #[serde(default = "default_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+    /// Factor the delay grows by after each retry
+    #[serde(default = "default_backoff_multiplier")]
+    pub multiplier: f64,
+    /// Stop retrying once this much time has passed since the first attempt (0 = no limit)
+    #[serde(default = "default_max_elapsed_ms")]
+    pub max_elapsed_ms: u64,
+}
+
+fn default_retry_attempts() -> u32 {
+    4
+}
+
+fn default_initial_backoff_ms() -> u64 {
+    100
+}
+
+fn default_max_backoff_ms() -> u64 {
+    2000
+}
+
+fn default_backoff_multiplier() -> f64 {
+    2.0
+}
+
+fn default_max_elapsed_ms() -> u64 {
+    10_000
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_retry_attempts(),
+            initial_backoff_ms: default_initial_backoff_ms(),
+            max_backoff_ms: default_max_backoff_ms(),
+            multiplier: default_backoff_multiplier(),
+            max_elapsed_ms: default_max_elapsed_ms(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Fail on the first error
+    pub fn disabled() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Delay before retry number `retry` (starting at 1)
+    ///
+    /// The exponential delay is capped at `max_backoff_ms`, then the upper
+    /// half of it is randomized.
+    pub fn backoff(&self, retry: u32) -> std::time::Duration {
+        let exponent = retry.saturating_sub(1).min(63) as i32;
+        let delay = (self.initial_backoff_ms as f64 * self.multiplier.max(1.0).powi(exponent))
+            .min(self.max_backoff_ms as f64) as u64;
+        std::time::Duration::from_millis(delay / 2 + jitter(delay - delay / 2))
+    }
+
+    /// How long to wait before the next attempt, or `None` to give up
+    ///
+    /// `attempt` is the number of attempts made so far and `elapsed` the time
+    /// since the first one started.
+    pub fn next_delay(
+        &self,
+        attempt: u32,
+        elapsed: std::time::Duration,
+    ) -> Option<std::time::Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+        let delay = self.backoff(attempt);
+        let budget = std::time::Duration::from_millis(self.max_elapsed_ms);
+        if self.max_elapsed_ms > 0 && elapsed + delay > budget {
+            return None;
+        }
+        Some(delay)
+    }
+}
+
+/// Random value in `0..=max`
+fn jitter(max: u64) -> u64 {
+    use std::hash::{BuildHasher, Hasher};
+    if max == 0 {
+        return 0;
+    }
+    // Each RandomState is seeded differently, which is random enough for jitter
+    let random = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    random % (max + 1)
+}
+
+/// Shutdown of a VM that has had no containers for a while
+///
+/// Stopping an idle VM gives its memory back to the host and saves battery,
+/// at the cost of a cold boot on the next request. Only applies when the
+/// runtime started the VM itself, not to an externally managed one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdlePolicy {
+    /// Shut down after this many seconds without containers (0 = never)
+    #[serde(default)]
+    pub shutdown_after_secs: u64,
+    /// How often to check whether the VM is idle in seconds
+    #[serde(default = "default_idle_check_interval_secs")]
+    pub check_interval_secs: u64,
+}
+
+fn default_idle_check_interval_secs() -> u64 {
+    30
+}
+
+impl Default for IdlePolicy {
+    fn default() -> Self {
+        Self::after_secs(0)
+    }
+}
+
+impl IdlePolicy {
+    /// Shut down after `secs` seconds without containers
+    pub fn after_secs(secs: u64) -> Self {
+        Self {
+            shutdown_after_secs: secs,
+            check_interval_secs: default_idle_check_interval_secs(),
+        }
+    }
+
+    /// Whether idle shutdown is on
+    pub fn is_enabled(&self) -> bool {
+        self.shutdown_after_secs > 0
+    }
+
+    /// Time between idle checks, never longer than the shutdown delay
+    pub fn check_interval(&self) -> std::time::Duration {
+        let secs = self
+            .check_interval_secs
+            .min(self.shutdown_after_secs)
+            .max(1);
+        std::time::Duration::from_secs(secs)
+    }
+
+    /// Whether a VM running `containers` containers, with no requests or
+    /// containers for `idle_for`, should be shut down
+    pub fn should_shut_down(&self, containers: usize, idle_for: std::time::Duration) -> bool {
+        self.is_enabled()
+            && containers == 0
+            && idle_for >= std::time::Duration::from_secs(self.shutdown_after_secs)
+    }
+}
+
+/// Synchronization of the guest clock with the host's
+///
+/// The VM's clock stands still while the host sleeps. The runtime sends
+/// the host time to the agent at boot, every `interval_secs` and soon after
+/// the host wakes up; the agent steps its clock when it is off by
+/// `step_threshold_ms` or more and slews it otherwise.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimeSyncPolicy {
+    /// Seconds between syncs (0 = only at boot)
+    #[serde(default = "default_time_sync_interval_secs")]
+    pub interval_secs: u64,
+    /// Offsets at least this large are corrected at once
+    #[serde(default = "default_step_threshold_ms")]
+    pub step_threshold_ms: u64,
+}
+
+fn default_time_sync_interval_secs() -> u64 {
+    60
+}
+
+fn default_step_threshold_ms() -> u64 {
+    1000
+}
+
+/// How often a wake from sleep is looked for, at most
+const WAKE_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+impl Default for TimeSyncPolicy {
+    fn default() -> Self {
+        Self {
+            interval_secs: default_time_sync_interval_secs(),
+            step_threshold_ms: default_step_threshold_ms(),
+        }
+    }
+}
+
+impl TimeSyncPolicy {
+    /// Whether the clock is synced periodically rather than only at boot
+    pub fn is_enabled(&self) -> bool {
+        self.interval_secs > 0
+    }
+
+    /// Time between checks whether a sync is due
+    pub fn check_interval(&self) -> std::time::Duration {
+        WAKE_CHECK_INTERVAL.min(std::time::Duration::from_secs(self.interval_secs.max(1)))
+    }
+
+    /// Whether to sync again, `monotonic` and `wall` after the last sync as
+    /// measured by the host's monotonic and wall clocks
+    ///
+    /// The monotonic clock does not advance while the host sleeps, so a wall
+    /// clock well ahead of it means the host (and the VM) slept since.
+    pub fn is_due(&self, monotonic: std::time::Duration, wall: std::time::Duration) -> bool {
+        let slept = wall.saturating_sub(monotonic);
+        monotonic >= std::time::Duration::from_secs(self.interval_secs)
+            || slept >= std::time::Duration::from_millis(self.step_threshold_ms)
+    }
+}
+
+/// How the host reaches the agent in the VM
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    /// Virtio socket of a VM the runtime booted itself
+    Vsock,
+    /// Unix socket at `RuntimeConfig::socket_path`, e.g. forwarded from an
+    /// external VM
+    Unix,
+}
+
+impl std::fmt::Display for Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Transport::Vsock => "vsock",
+            Transport::Unix => "unix",
+        })
+    }
+}
+
+impl std::str::FromStr for Transport {
+    type Err = crate::ShimError;
+
+    fn from_str(s: &str) -> crate::Result<Self> {
+        match s.trim() {
+            "vsock" => Ok(Transport::Vsock),
+            "unix" => Ok(Transport::Unix),
+            other => Err(crate::ShimError::validation(
+                "transport",
+                format!("Unknown transport '{}', expected vsock or unix", other),
+            )),
+        }
+    }
+}
+
+/// Which transports the host connects to the agent over, and how hard it
+/// tries at boot
+///
+/// Each connection tries the transports in `order` until one works, starting
+/// with the one that worked last. A vsock connection may take up to
+/// `RuntimeConfig::connection_timeout`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TransportPolicy {
+    /// Transports to try, first preferred
+    #[serde(default = "default_transport_order")]
+    pub order: Vec<Transport>,
+    /// Rounds through `order` before booting fails
+    #[serde(default = "default_boot_attempts")]
+    pub boot_attempts: u32,
+    /// Delay between rounds at boot in milliseconds
+    #[serde(default = "default_boot_retry_delay_ms")]
+    pub boot_retry_delay_ms: u64,
+    /// Time the guest gets to boot before the first round in milliseconds;
+    /// `None` waits 20s for a VM the runtime booted and 2s for an external one
+    #[serde(default)]
+    pub boot_wait_ms: Option<u64>,
+}
+
+fn default_transport_order() -> Vec<Transport> {
+    vec![Transport::Vsock, Transport::Unix]
+}
+
+fn default_boot_attempts() -> u32 {
+    5
+}
+
+fn default_boot_retry_delay_ms() -> u64 {
+    3000
+}
+
+impl Default for TransportPolicy {
+    fn default() -> Self {
+        Self {
+            order: default_transport_order(),
+            boot_attempts: default_boot_attempts(),
+            boot_retry_delay_ms: default_boot_retry_delay_ms(),
+            boot_wait_ms: None,
+        }
+    }
+}
+
+impl TransportPolicy {
+    /// Only connect over `transport`
+    pub fn only(transport: Transport) -> Self {
+        Self {
+            order: vec![transport],
+            ..Default::default()
+        }
+    }
+
+    /// Parse a comma-separated order, e.g. "unix,vsock"
+    pub fn parse_order(s: &str) -> crate::Result<Vec<Transport>> {
+        let order = s
+            .split(',')
+            .filter(|t| !t.trim().is_empty())
+            .map(str::parse)
+            .collect::<crate::Result<Vec<Transport>>>()?;
+        if order.is_empty() {
+            return Err(crate::ShimError::validation(
+                "transport.order",
+                "At least one transport is needed",
+            ));
+        }
+        Ok(order)
+    }
+
+    /// `order` starting with `active`, the transport that worked last
+    pub fn order_from(&self, active: Option<Transport>) -> Vec<Transport> {
+        let mut order: Vec<Transport> = active.into_iter().collect();
+        for transport in &self.order {
+            if !order.contains(transport) {
+                order.push(*transport);
+            }
+        }
+        order
+    }
+
+    /// Time the guest gets to boot before the first connection attempt
+    pub fn boot_wait(&self, owns_vm: bool) -> std::time::Duration {
+        // Kernel boot + initramfs + agent startup typically takes 15-20s
+        let default_ms = if owns_vm { 20_000 } else { 2000 };
+        std::time::Duration::from_millis(self.boot_wait_ms.unwrap_or(default_ms))
+    }
+
+    /// Delay between rounds through `order` at boot
+    pub fn boot_retry_delay(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.boot_retry_delay_ms)
+    }
+}
+
+/// What the runtime is connected to
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RuntimeStatus {
+    /// The VM is running; false after an idle shutdown
+    pub vm_running: bool,
+    /// The runtime booted the VM itself, rather than using an external one
+    pub vm_managed: bool,
+    /// Transport the agent was last reached over, `None` while the VM is down
+    pub transport: Option<Transport>,
+    /// See `ContainerRuntime::new_read_only`
+    pub read_only: bool,
+}
+
+/// Guest clock offset found by the last time sync
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct ClockSync {
+    /// Host time minus guest time in milliseconds; positive when the guest
+    /// clock was behind
+    pub offset_ms: i64,
+    /// The guest clock was set to the host time rather than slewed
+    pub stepped: bool,
+    /// When the sync happened, in seconds since the Unix epoch
+    pub synced_at: u64,
+}
+
+/// Limits on what extracting a pulled image may write
+///
+/// Layers are gzip-compressed, so a small download can expand to far more
+/// than the disk holds. Sizes are those the layer's tar headers declare,
+/// checked before anything is written; 0 means no limit.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct ExtractLimits {
+    /// Bytes of files one layer may contain
+    #[serde(default = "default_max_layer_size")]
+    pub max_layer_size: u64,
+    /// Bytes of files all layers of an image may contain together
+    #[serde(default = "default_max_image_size")]
+    pub max_image_size: u64,
+    /// Files, directories and links one layer may contain
+    #[serde(default = "default_max_layer_entries")]
+    pub max_layer_entries: u64,
+}
+
+fn default_max_layer_size() -> u64 {
+    8 * 1024 * 1024 * 1024 // 8GB
+}
+
+fn default_max_image_size() -> u64 {
+    16 * 1024 * 1024 * 1024 // 16GB
+}
+
+fn default_max_layer_entries() -> u64 {
+    1_000_000
+}
+
+impl Default for ExtractLimits {
+    fn default() -> Self {
+        Self {
+            max_layer_size: default_max_layer_size(),
+            max_image_size: default_max_image_size(),
+            max_layer_entries: default_max_layer_entries(),
+        }
+    }
+}
+
+impl ExtractLimits {
+    /// No limits at all, for trusted images
+    pub fn unlimited() -> Self {
+        Self {
+            max_layer_size: 0,
+            max_image_size: 0,
+            max_layer_entries: 0,
+        }
+    }
+}
+
+/// Checks of free memory and disk before a container is started
+///
+/// On macOS the VM's memory and disk are checked, on Linux the host's. A
+/// container that would oversubscribe them is refused, unless it is started
+/// with `ContainerRuntime::force_start`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResourceGuard {
+    /// How far a memory limit may exceed the memory currently available,
+    /// e.g. 1.5 allows limits up to 150% of it (0 = no memory check)
+    #[serde(default = "default_memory_factor")]
+    pub memory_factor: f64,
+    /// Free disk space that must remain, in bytes (0 = no disk check)
+    #[serde(default = "default_min_free_disk_bytes")]
+    pub min_free_disk_bytes: u64,
+}
+
+fn default_memory_factor() -> f64 {
+    1.0
+}
+
+fn default_min_free_disk_bytes() -> u64 {
+    512 * 1024 * 1024
+}
+
+impl Default for ResourceGuard {
+    fn default() -> Self {
+        Self {
+            memory_factor: default_memory_factor(),
+            min_free_disk_bytes: default_min_free_disk_bytes(),
+        }
+    }
+}
+
+impl ResourceGuard {
+    /// Guard that lets every container start
+    pub fn disabled() -> Self {
+        Self {
+            memory_factor: 0.0,
+            min_free_disk_bytes: 0,
+        }
+    }
+
+    /// Why a container with `memory_limit` should not start, given the memory
+    /// and disk space available (unknown amounts are not checked)
+    pub fn problems(
+        &self,
+        memory_limit: Option<u64>,
+        memory_available: Option<u64>,
+        disk_available: Option<u64>,
+    ) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let (Some(limit), Some(available)) = (memory_limit, memory_available) {
+            let allowed = available as f64 * self.memory_factor;
+            if self.memory_factor > 0.0 && limit as f64 > allowed {
+                problems.push(format!(
+                    "memory limit of {} MiB exceeds the {} MiB available",
+                    limit / (1024 * 1024),
+                    available / (1024 * 1024)
+                ));
+            }
+        }
+        if let Some(available) = disk_available {
+            if available < self.min_free_disk_bytes {
+                problems.push(format!(
+                    "only {} MiB of disk is free, {} MiB is required",
+                    available / (1024 * 1024),
+                    self.min_free_disk_bytes / (1024 * 1024)
+                ));
+            }
+        }
+        problems
+    }
 }
 
 /// Virtual disk configuration for VM
@@ -195,6 +739,15 @@ impl Default for RuntimeConfig {
             virtiofs_shares: vec![],
             rosetta: RosettaConfig::default(),
             vm_network: VmNetworkConfig::default(),
+            profiles: HashMap::new(),
+            templates: HashMap::new(),
+            retry_policy: RetryPolicy::default(),
+            idle_policy: IdlePolicy::default(),
+            resource_guard: ResourceGuard::default(),
+            time_sync: TimeSyncPolicy::default(),
+            proxy: ProxyConfig::default(),
+            extract_limits: ExtractLimits::default(),
+            transport: TransportPolicy::default(),
         }
     }
 }
@@ -225,17 +778,41 @@ impl RuntimeConfig {
         RuntimeConfigBuilder::default()
     }
 
+    /// Load configuration from a JSON file
+    pub fn from_file(path: impl AsRef<Path>) -> crate::Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path).map_err(|e| crate::ShimError::Io {
+            error: e,
+            context: Some(format!("Failed to read config file {}", path.display())),
+        })?;
+        serde_json::from_str(&content).map_err(|e| {
+            crate::ShimError::serialization(
+                format!("Failed to parse config file {}", path.display()),
+                e,
+            )
+        })
+    }
+
     /// Load configuration from environment variables
     ///
     /// Supported variables:
+    /// - `LIBCRUN_CONFIG_FILE`: JSON config file to start from (e.g., for profiles)
     /// - `LIBCRUN_SOCKET_PATH`: Unix socket path
     /// - `LIBCRUN_VSOCK_PORT`: Vsock port number
     /// - `LIBCRUN_VM_ASSET_PATHS`: Colon-separated list of paths
     /// - `LIBCRUN_VM_MEMORY`: VM memory in bytes
     /// - `LIBCRUN_VM_CPUS`: Number of VM CPUs
     /// - `LIBCRUN_CONNECTION_TIMEOUT`: Connection timeout in seconds
+    /// - `LIBCRUN_VM_IDLE_SHUTDOWN`: Seconds without containers before the VM is shut down (0 = never)
+    /// - `LIBCRUN_TIME_SYNC_INTERVAL`: Seconds between guest clock syncs (0 = only at boot)
     pub fn from_env() -> Self {
-        let mut config = Self::default();
+        let mut config = match std::env::var("LIBCRUN_CONFIG_FILE") {
+            Ok(path) => Self::from_file(&path).unwrap_or_else(|e| {
+                log::warn!("Ignoring config file {}: {}", path, e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        };
 
         if let Ok(path) = std::env::var("LIBCRUN_SOCKET_PATH") {
             config.socket_path = PathBuf::from(path);
@@ -273,6 +850,25 @@ impl RuntimeConfig {
             }
         }
 
+        if let Ok(idle) = std::env::var("LIBCRUN_VM_IDLE_SHUTDOWN") {
+            if let Ok(secs) = idle.parse() {
+                config.idle_policy.shutdown_after_secs = secs;
+            }
+        }
+
+        if let Ok(interval) = std::env::var("LIBCRUN_TIME_SYNC_INTERVAL") {
+            if let Ok(secs) = interval.parse() {
+                config.time_sync.interval_secs = secs;
+            }
+        }
+
+        if let Ok(order) = std::env::var("LIBCRUN_TRANSPORT") {
+            match TransportPolicy::parse_order(&order) {
+                Ok(order) => config.transport.order = order,
+                Err(e) => log::warn!("Ignoring LIBCRUN_TRANSPORT: {}", e),
+            }
+        }
+
         config
     }
 
@@ -317,6 +913,15 @@ pub struct RuntimeConfigBuilder {
     virtiofs_shares: Vec<VirtioFsShare>,
     rosetta: Option<RosettaConfig>,
     vm_network: Option<VmNetworkConfig>,
+    profiles: HashMap<String, ContainerProfile>,
+    templates: HashMap<String, ContainerTemplate>,
+    retry_policy: Option<RetryPolicy>,
+    idle_policy: Option<IdlePolicy>,
+    resource_guard: Option<ResourceGuard>,
+    time_sync: Option<TimeSyncPolicy>,
+    proxy: Option<ProxyConfig>,
+    extract_limits: Option<ExtractLimits>,
+    transport: Option<TransportPolicy>,
 }
 
 impl RuntimeConfigBuilder {
@@ -414,6 +1019,65 @@ impl RuntimeConfigBuilder {
         self
     }
 
+    /// Define a named container profile
+    pub fn profile(mut self, name: impl Into<String>, profile: ContainerProfile) -> Self {
+        self.profiles.insert(name.into(), profile);
+        self
+    }
+
+    /// Define a named container template
+    pub fn template(mut self, name: impl Into<String>, template: ContainerTemplate) -> Self {
+        self.templates.insert(name.into(), template);
+        self
+    }
+
+    /// Set the retry policy for idempotent agent requests
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(policy);
+        self
+    }
+
+    /// Set when an idle VM is shut down
+    pub fn idle_policy(mut self, policy: IdlePolicy) -> Self {
+        self.idle_policy = Some(policy);
+        self
+    }
+
+    /// Set the free memory and disk required to start a container
+    pub fn resource_guard(mut self, guard: ResourceGuard) -> Self {
+        self.resource_guard = Some(guard);
+        self
+    }
+
+    /// Set how the VM's clock is kept in line with the host's
+    pub fn time_sync(mut self, policy: TimeSyncPolicy) -> Self {
+        self.time_sync = Some(policy);
+        self
+    }
+
+    /// Set the HTTP proxies for image pulls and containers
+    pub fn proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Set the limits on the size of pulled images once extracted
+    pub fn extract_limits(mut self, limits: ExtractLimits) -> Self {
+        self.extract_limits = Some(limits);
+        self
+    }
+
+    /// Set which transports reach the agent, and how hard to try at boot
+    pub fn transport(mut self, policy: TransportPolicy) -> Self {
+        self.transport = Some(policy);
+        self
+    }
+
+    /// Shut the VM down after `secs` seconds without containers
+    pub fn vm_idle_shutdown(self, secs: u64) -> Self {
+        self.idle_policy(IdlePolicy::after_secs(secs))
+    }
+
     pub fn build(self) -> RuntimeConfig {
         RuntimeConfig {
             socket_path: self.socket_path.unwrap_or_else(default_socket_path),
@@ -428,6 +1092,15 @@ impl RuntimeConfigBuilder {
             virtiofs_shares: self.virtiofs_shares,
             rosetta: self.rosetta.unwrap_or_default(),
             vm_network: self.vm_network.unwrap_or_default(),
+            profiles: self.profiles,
+            templates: self.templates,
+            retry_policy: self.retry_policy.unwrap_or_default(),
+            idle_policy: self.idle_policy.unwrap_or_default(),
+            resource_guard: self.resource_guard.unwrap_or_default(),
+            time_sync: self.time_sync.unwrap_or_default(),
+            proxy: self.proxy.unwrap_or_default(),
+            extract_limits: self.extract_limits.unwrap_or_default(),
+            transport: self.transport.unwrap_or_default(),
         }
     }
 }
@@ -468,31 +1141,432 @@ pub struct ContainerConfig {
     /// Maximum log size in bytes (0 = unlimited)
     #[serde(default)]
     pub log_max_size: u64,
-}
 
-fn default_log_driver() -> String {
-    "json-file".to_string()
-}
+    /// Container profile from the runtime configuration to take defaults from
+    #[serde(default)]
+    pub profile: Option<String>,
 
-impl Default for ContainerConfig {
-    fn default() -> Self {
-        Self {
-            id: String::new(),
-            rootfs: PathBuf::new(),
-            command: vec![],
-            env: vec![],
-            working_dir: "/".to_string(),
-            stdio: StdioConfig::default(),
-            network: NetworkConfig::default(),
-            volumes: vec![],
-            resources: ResourceLimits::default(),
-            health_check: None,
-            log_driver: default_log_driver(),
-            log_max_size: 0,
+    /// Capabilities of the container process (None = runtime defaults)
+    #[serde(default)]
+    pub capabilities: Option<Vec<String>>,
+
+    /// Path to an OCI seccomp profile (JSON)
+    #[serde(default)]
+    pub seccomp_profile: Option<PathBuf>,
+
+    /// Process resource limits
+    #[serde(default)]
+    pub ulimits: Vec<Ulimit>,
+
+    /// Image reference the rootfs was created from, if any
+    #[serde(default)]
+    pub image: Option<String>,
+
+    /// Kill the container once it has been running this long (None = no limit)
+    #[serde(default)]
+    pub max_runtime_secs: Option<u64>,
+
+    /// Stop the container after a period of inactivity (enforced by the VM agent)
+    #[serde(default)]
+    pub auto_stop: Option<AutoStopPolicy>,
+
+    /// Containers that must be up before this one is started by `start_group`
+    #[serde(default)]
+    pub depends_on: Vec<DependsOn>,
+
+    /// Pod to create the container in (see `ContainerRuntime::create_pod`)
+    #[serde(default)]
+    pub pod: Option<String>,
+
+    /// Join the namespaces of another running container
+    #[serde(default)]
+    pub join_namespaces: Option<JoinNamespaces>,
+
+    /// Whether the container follows new versions of its image
+    #[serde(default)]
+    pub pull_policy: PullPolicy,
+
+    /// Host environment variables to forward, by name or glob (e.g. `AWS_*`);
+    /// variables set in `env` take precedence
+    #[serde(default)]
+    pub env_passthrough: Vec<String>,
+
+    /// Start the container again when its process exits (applied by the VM agent)
+    #[serde(default)]
+    pub restart_policy: RestartPolicy,
+
+    /// Write to a layer of the container's own over `rootfs` rather than to
+    /// `rootfs` itself, e.g. an image shared with other containers
+    ///
+    /// The layer is an overlay, or a copy of `rootfs` where overlayfs is
+    /// unavailable, and is removed with the container.
+    #[serde(default)]
+    pub rootfs_snapshot: bool,
+
+    /// Mount the root filesystem read-only
+    #[serde(default)]
+    pub read_only_rootfs: bool,
+
+    /// Key-value metadata, e.g. to prune containers by with [`PruneFilter`]
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+
+    /// Name, unique among the containers, that the container can be looked
+    /// up by like its ID; one like `brave_hopper` is generated if not set
+    #[serde(default)]
+    pub name: Option<String>,
+
+    /// Seconds a stopped container has to exit after SIGTERM before it gets
+    /// SIGKILL (None = 2 seconds)
+    #[serde(default)]
+    pub stop_timeout: Option<u64>,
+
+    /// Create `working_dir` in the root filesystem when it is missing, as
+    /// Docker does; otherwise a missing working directory fails the create
+    #[serde(default = "default_true")]
+    pub create_working_dir: bool,
+}
+
+/// Namespaces to join from another running container
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JoinNamespaces {
+    /// ID of the container owning the namespaces
+    pub container: String,
+    /// Join its network namespace
+    #[serde(default)]
+    pub network: bool,
+    /// Join its IPC namespace
+    #[serde(default)]
+    pub ipc: bool,
+}
+
+/// A group of containers sharing namespaces and volumes
+///
+/// The sandbox container owns the shared namespaces and gives the pod its ID;
+/// it should run a long-lived process (e.g. `pause`). Members join its
+/// namespaces and get the pod volumes mounted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PodSpec {
+    /// Container holding the pod's namespaces; its ID is the pod ID
+    pub sandbox: ContainerConfig,
+    /// Containers created in the pod along with the sandbox
+    #[serde(default)]
+    pub members: Vec<ContainerConfig>,
+    /// Volumes mounted into every member
+    #[serde(default)]
+    pub volumes: Vec<VolumeMount>,
+    /// Members share the sandbox's network namespace
+    #[serde(default = "default_true")]
+    pub share_network: bool,
+    /// Members share the sandbox's IPC namespace
+    #[serde(default = "default_true")]
+    pub share_ipc: bool,
+}
+
+impl PodSpec {
+    /// Pod with the given sandbox, sharing network and IPC
+    pub fn new(sandbox: ContainerConfig) -> Self {
+        Self {
+            sandbox,
+            members: vec![],
+            volumes: vec![],
+            share_network: true,
+            share_ipc: true,
+        }
+    }
+
+    /// Add a member container
+    pub fn member(mut self, config: ContainerConfig) -> Self {
+        self.members.push(config);
+        self
+    }
+
+    /// Add a volume shared by all members
+    pub fn volume(mut self, volume: VolumeMount) -> Self {
+        self.volumes.push(volume);
+        self
+    }
+}
+
+/// Startup dependency on another container
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DependsOn {
+    /// ID of the container depended on
+    pub container: String,
+    /// What the dependency must reach before this container starts
+    #[serde(default)]
+    pub condition: DependencyCondition,
+}
+
+/// Startup gate for a dependency
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DependencyCondition {
+    /// The dependency is running
+    #[default]
+    Started,
+    /// The dependency's health check passes
+    Healthy,
+}
+
+/// When a started container counts as ready, see
+/// `ContainerRuntime::wait_ready`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadyCheck {
+    /// A process in the container listens on this TCP port
+    Port(u16),
+    /// The container's health check passes
+    Healthy,
+}
+
+impl std::fmt::Display for ReadyCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReadyCheck::Port(port) => write!(f, "listening on port {}", port),
+            ReadyCheck::Healthy => f.write_str("healthy"),
+        }
+    }
+}
+
+/// How a container's image is kept up to date
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PullPolicy {
+    /// Run the image from the local store as it is
+    #[default]
+    Local,
+    /// Recreate the container when its image reference points at a new
+    /// image in the registry (see `ContainerRuntime::update_images`)
+    AutoUpdate,
+}
+
+/// Idle-based auto-stop: a container counts as idle while its CPU usage stays
+/// below `cpu_percent` and it sends or receives no network traffic
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AutoStopPolicy {
+    /// Seconds of continuous idleness before the container is stopped
+    pub idle_secs: u64,
+    /// CPU usage threshold, in percent of one core
+    #[serde(default = "default_idle_cpu_percent")]
+    pub cpu_percent: f64,
+}
+
+impl AutoStopPolicy {
+    /// Policy with the default CPU threshold
+    pub fn after(idle_secs: u64) -> Self {
+        Self {
+            idle_secs,
+            cpu_percent: default_idle_cpu_percent(),
+        }
+    }
+}
+
+fn default_idle_cpu_percent() -> f64 {
+    1.0
+}
+
+fn default_log_driver() -> String {
+    "json-file".to_string()
+}
+
+impl Default for ContainerConfig {
+    fn default() -> Self {
+        Self {
+            id: String::new(),
+            rootfs: PathBuf::new(),
+            command: vec![],
+            env: vec![],
+            working_dir: "/".to_string(),
+            stdio: StdioConfig::default(),
+            network: NetworkConfig::default(),
+            volumes: vec![],
+            resources: ResourceLimits::default(),
+            health_check: None,
+            log_driver: default_log_driver(),
+            log_max_size: 0,
+            profile: None,
+            capabilities: None,
+            seccomp_profile: None,
+            ulimits: vec![],
+            image: None,
+            max_runtime_secs: None,
+            auto_stop: None,
+            depends_on: vec![],
+            pod: None,
+            join_namespaces: None,
+            pull_policy: PullPolicy::Local,
+            env_passthrough: vec![],
+            restart_policy: RestartPolicy::No,
+            rootfs_snapshot: false,
+            read_only_rootfs: false,
+            labels: HashMap::new(),
+            name: None,
+            stop_timeout: None,
+            create_working_dir: true,
         }
     }
 }
 
+/// Process resource limit, as set with `ulimit`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Ulimit {
+    /// Limit name, e.g. "nofile" or "RLIMIT_NOFILE"
+    pub name: String,
+    pub soft: u64,
+    pub hard: u64,
+}
+
+impl Ulimit {
+    /// OCI rlimit type for this limit (e.g., "RLIMIT_NOFILE")
+    pub fn rlimit_type(&self) -> String {
+        let name = self.name.to_uppercase();
+        if name.starts_with("RLIMIT_") {
+            name
+        } else {
+            format!("RLIMIT_{}", name)
+        }
+    }
+}
+
+/// Named set of defaults applied to containers that reference it
+///
+/// Settings given explicitly on a container take precedence over the profile.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ContainerProfile {
+    /// Capabilities granted to the container process
+    #[serde(default)]
+    pub capabilities: Option<Vec<String>>,
+    /// Mounts added to every container
+    #[serde(default)]
+    pub mounts: Vec<VolumeMount>,
+    /// Path to an OCI seccomp profile (JSON)
+    #[serde(default)]
+    pub seccomp_profile: Option<PathBuf>,
+    /// Process resource limits
+    #[serde(default)]
+    pub ulimits: Vec<Ulimit>,
+}
+
+impl ContainerProfile {
+    /// Fill in settings the container doesn't set itself
+    pub fn apply_to(&self, config: &mut ContainerConfig) {
+        if config.capabilities.is_none() {
+            config.capabilities = self.capabilities.clone();
+        }
+        if config.seccomp_profile.is_none() {
+            config.seccomp_profile = self.seccomp_profile.clone();
+        }
+
+        for ulimit in &self.ulimits {
+            let rlimit_type = ulimit.rlimit_type();
+            if !config
+                .ulimits
+                .iter()
+                .any(|u| u.rlimit_type() == rlimit_type)
+            {
+                config.ulimits.push(ulimit.clone());
+            }
+        }
+
+        let mounts: Vec<VolumeMount> = self
+            .mounts
+            .iter()
+            .filter(|m| {
+                !config
+                    .volumes
+                    .iter()
+                    .any(|v| v.destination == m.destination)
+            })
+            .cloned()
+            .collect();
+        config.volumes.splice(0..0, mounts);
+    }
+}
+
+/// Named preset for a common service: an image and the settings it is
+/// usually run with
+///
+/// Settings given explicitly on a container take precedence over the
+/// template; env vars are merged by name, volumes by destination and port
+/// mappings by host port and protocol.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ContainerTemplate {
+    /// Image reference
+    pub image: String,
+    /// Command, used when the container gives none
+    #[serde(default)]
+    pub command: Vec<String>,
+    /// Environment variables (KEY=VALUE)
+    #[serde(default)]
+    pub env: Vec<String>,
+    /// Working directory, used when the container gives none
+    #[serde(default)]
+    pub working_dir: Option<String>,
+    /// Port mappings
+    #[serde(default)]
+    pub ports: Vec<PortMapping>,
+    /// Volume mounts
+    #[serde(default)]
+    pub volumes: Vec<VolumeMount>,
+}
+
+impl ContainerTemplate {
+    /// Fill in settings the container doesn't set itself
+    ///
+    /// An empty command or working directory counts as not set.
+    pub fn apply_to(&self, config: &mut ContainerConfig) {
+        if config.command.is_empty() {
+            config.command = self.command.clone();
+        }
+        if config.working_dir.is_empty() {
+            if let Some(dir) = &self.working_dir {
+                config.working_dir = dir.clone();
+            }
+        }
+        if config.image.is_none() {
+            config.image = Some(self.image.clone());
+        }
+
+        let env_name = |var: &str| var.split('=').next().unwrap_or_default().to_string();
+        let env: Vec<String> = self
+            .env
+            .iter()
+            .filter(|var| !config.env.iter().any(|v| env_name(v) == env_name(var)))
+            .cloned()
+            .collect();
+        config.env.splice(0..0, env);
+
+        let volumes: Vec<VolumeMount> = self
+            .volumes
+            .iter()
+            .filter(|m| {
+                !config
+                    .volumes
+                    .iter()
+                    .any(|v| v.destination == m.destination)
+            })
+            .cloned()
+            .collect();
+        config.volumes.splice(0..0, volumes);
+
+        let ports: Vec<PortMapping> = self
+            .ports
+            .iter()
+            .filter(|p| {
+                // Random host ports never clash
+                p.host_port == 0
+                    || !config
+                        .network
+                        .port_mappings
+                        .iter()
+                        .any(|m| m.host_port == p.host_port && m.protocol == p.protocol)
+            })
+            .cloned()
+            .collect();
+        config.network.port_mappings.splice(0..0, ports);
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct StdioConfig {
     /// Whether to allocate a pseudo-TTY
@@ -518,6 +1592,12 @@ pub struct NetworkConfig {
     /// Additional network interfaces
     #[serde(default)]
     pub interfaces: Vec<NetworkInterface>,
+    /// CIDRs the container may reach; when non-empty all other egress is dropped
+    #[serde(default)]
+    pub allow_egress: Vec<String>,
+    /// CIDRs the container may never reach (takes precedence over `allow_egress`)
+    #[serde(default)]
+    pub deny_egress: Vec<String>,
 }
 
 impl Default for NetworkConfig {
@@ -526,6 +1606,8 @@ impl Default for NetworkConfig {
             mode: default_network_mode(),
             port_mappings: vec![],
             interfaces: vec![],
+            allow_egress: vec![],
+            deny_egress: vec![],
         }
     }
 }
@@ -534,6 +1616,52 @@ fn default_network_mode() -> String {
     "bridge".to_string()
 }
 
+impl NetworkConfig {
+    /// Whether egress firewall rules need to be installed
+    pub fn has_egress_rules(&self) -> bool {
+        !self.allow_egress.is_empty() || !self.deny_egress.is_empty()
+    }
+
+    /// Validate the network settings before they are handed to the runtime
+    pub fn validate(&self) -> crate::Result<()> {
+        if self.has_egress_rules() && self.mode == "host" {
+            return Err(crate::ShimError::validation(
+                "network",
+                "Egress rules require a private network namespace, not host networking",
+            ));
+        }
+        for (field, cidrs) in [
+            ("allow_egress", &self.allow_egress),
+            ("deny_egress", &self.deny_egress),
+        ] {
+            if let Some(bad) = cidrs.iter().find(|c| !is_valid_cidr(c)) {
+                return Err(crate::ShimError::validation(
+                    field,
+                    format!("Invalid CIDR '{}', expected e.g. 10.0.0.0/8", bad),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check an IPv4/IPv6 address with an optional prefix length
+fn is_valid_cidr(cidr: &str) -> bool {
+    let (addr, prefix) = match cidr.split_once('/') {
+        Some((addr, prefix)) => (addr, Some(prefix)),
+        None => (cidr, None),
+    };
+    let max_prefix = match addr.parse::<std::net::IpAddr>() {
+        Ok(std::net::IpAddr::V4(_)) => 32,
+        Ok(std::net::IpAddr::V6(_)) => 128,
+        Err(_) => return false,
+    };
+    match prefix {
+        Some(prefix) => prefix.parse::<u8>().is_ok_and(|p| p <= max_prefix),
+        None => true,
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PortMapping {
     /// Host port (0 for random)
@@ -564,6 +1692,14 @@ pub struct VolumeMount {
     pub destination: PathBuf,
     /// Mount options (e.g., "ro", "rw", "bind")
     pub options: Vec<String>,
+    /// Whether mounts under the volume propagate between the host and the
+    /// container (None = rprivate, neither way)
+    #[serde(default)]
+    pub propagation: Option<MountPropagation>,
+    /// Make the mounts under the source read-only in the container as well,
+    /// where `ro` only covers the volume itself (needs Linux 5.12)
+    #[serde(default)]
+    pub recursive_readonly: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -572,12 +1708,265 @@ pub struct ResourceLimits {
     pub cpu: Option<f64>,
     /// Memory limit (in bytes, 0 = unlimited)
     pub memory: Option<u64>,
-    /// Memory swap limit (in bytes, 0 = unlimited)
-    pub memory_swap: Option<u64>,
+    /// Memory plus swap limit (in bytes, -1 = unlimited swap), as Docker's
+    /// `--memory-swap`. Requires a memory limit.
+    pub memory_swap: Option<i64>,
+    /// Kernel swappiness for the container (0-100, cgroup v1 only)
+    #[serde(default)]
+    pub memory_swappiness: Option<u8>,
     /// PIDs limit (0 = unlimited)
     pub pids: Option<i64>,
     /// Block IO weight (10-1000)
     pub blkio_weight: Option<u16>,
+    /// CPUs the container may run on, in cpuset list format (e.g., "0-3,6")
+    #[serde(default)]
+    pub cpuset_cpus: Option<String>,
+    /// NUMA memory nodes the container may allocate from (e.g., "0")
+    #[serde(default)]
+    pub cpuset_mems: Option<String>,
+    /// Hugepage usage limits, one per page size
+    #[serde(default)]
+    pub hugepage_limits: Vec<HugepageLimit>,
+    /// CPU cores set aside for the container when it is placed, see
+    /// [`Capacity`]. Not enforced by cgroups; at most `cpu` when both are set.
+    #[serde(default)]
+    pub cpu_reservation: Option<f64>,
+    /// Memory (in bytes) set aside for the container when it is placed, see
+    /// [`Capacity`]. Not enforced by cgroups; at most `memory` when both are set.
+    #[serde(default)]
+    pub memory_reservation: Option<u64>,
+}
+
+/// Limit on hugepage usage for one page size
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct HugepageLimit {
+    /// Page size with unit, as in the kernel's hugetlb controller (e.g., "2MB", "1GB")
+    pub page_size: String,
+    /// Limit in bytes
+    pub limit: u64,
+}
+
+impl ResourceLimits {
+    /// Validate the limits before they are handed to the runtime
+    pub fn validate(&self) -> crate::Result<()> {
+        if let Some(swap) = self.memory_swap {
+            if swap < -1 {
+                return Err(crate::ShimError::validation(
+                    "memory_swap",
+                    "Swap limit must be -1 (unlimited) or a positive size",
+                ));
+            }
+            if swap > 0 {
+                match self.memory {
+                    Some(memory) if memory > 0 && swap as u64 >= memory => {}
+                    Some(memory) if memory > 0 => {
+                        return Err(crate::ShimError::validation(
+                            "memory_swap",
+                            "Memory plus swap limit must be at least the memory limit",
+                        ));
+                    }
+                    _ => {
+                        return Err(crate::ShimError::validation(
+                            "memory_swap",
+                            "A memory limit is required when setting a swap limit",
+                        ));
+                    }
+                }
+            }
+        }
+        for hugepage in &self.hugepage_limits {
+            if !is_valid_hugepage_size(&hugepage.page_size) {
+                return Err(crate::ShimError::validation(
+                    "hugepage_limits",
+                    format!(
+                        "Invalid hugepage size '{}', expected e.g. 2MB or 1GB",
+                        hugepage.page_size
+                    ),
+                ));
+            }
+        }
+        if let Some(swappiness) = self.memory_swappiness {
+            if swappiness > 100 {
+                return Err(crate::ShimError::validation(
+                    "memory_swappiness",
+                    format!("Swappiness must be between 0 and 100, got {}", swappiness),
+                ));
+            }
+        }
+        if let Some(weight) = self.blkio_weight {
+            if !(10..=1000).contains(&weight) {
+                return Err(crate::ShimError::validation(
+                    "blkio_weight",
+                    format!(
+                        "Block I/O weight must be between 10 and 1000, got {}",
+                        weight
+                    ),
+                ));
+            }
+        }
+        if let Some(cpus) = &self.cpuset_cpus {
+            if !is_valid_cpuset(cpus) {
+                return Err(crate::ShimError::validation(
+                    "cpuset_cpus",
+                    format!("Invalid CPU list '{}', expected e.g. 0-3,6", cpus),
+                ));
+            }
+        }
+        if let Some(mems) = &self.cpuset_mems {
+            if !is_valid_cpuset(mems) {
+                return Err(crate::ShimError::validation(
+                    "cpuset_mems",
+                    format!("Invalid memory node list '{}', expected e.g. 0-1", mems),
+                ));
+            }
+        }
+        if let Some(reserved) = self.cpu_reservation {
+            if !(reserved >= 0.0 && reserved.is_finite()) {
+                return Err(crate::ShimError::validation(
+                    "cpu_reservation",
+                    "CPU reservation must be a positive number of cores",
+                ));
+            }
+            if self
+                .cpu
+                .is_some_and(|limit| limit > 0.0 && reserved > limit)
+            {
+                return Err(crate::ShimError::validation(
+                    "cpu_reservation",
+                    "CPU reservation must not exceed the CPU limit",
+                ));
+            }
+        }
+        if let Some(reserved) = self.memory_reservation {
+            if self
+                .memory
+                .is_some_and(|limit| limit > 0 && reserved > limit)
+            {
+                return Err(crate::ShimError::validation(
+                    "memory_reservation",
+                    "Memory reservation must not exceed the memory limit",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// CPUs and memory set aside for the container (unset = nothing)
+    pub fn reservation(&self) -> Reservation {
+        Reservation {
+            cpus: self.cpu_reservation.unwrap_or(0.0),
+            memory: self.memory_reservation.unwrap_or(0),
+        }
+    }
+
+    /// CPUs and memory the container may use at most (unlimited = nothing)
+    pub fn limit(&self) -> Reservation {
+        Reservation {
+            cpus: self.cpu.filter(|&cpus| cpus > 0.0).unwrap_or(0.0),
+            memory: self.memory.unwrap_or(0),
+        }
+    }
+}
+
+/// An amount of CPU and memory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Reservation {
+    /// CPU cores
+    pub cpus: f64,
+    /// Memory in bytes
+    pub memory: u64,
+}
+
+impl Reservation {
+    /// Whether nothing is set aside
+    pub fn is_empty(&self) -> bool {
+        self.cpus <= 0.0 && self.memory == 0
+    }
+}
+
+impl std::ops::Add for Reservation {
+    type Output = Reservation;
+
+    fn add(self, other: Reservation) -> Reservation {
+        Reservation {
+            cpus: self.cpus + other.cpus,
+            memory: self.memory.saturating_add(other.memory),
+        }
+    }
+}
+
+impl std::iter::Sum for Reservation {
+    fn sum<I: Iterator<Item = Reservation>>(iter: I) -> Reservation {
+        iter.fold(Reservation::default(), |total, r| total + r)
+    }
+}
+
+/// CPUs and memory of the machine containers run on (the VM on macOS),
+/// and how much of them containers created through the runtime claim
+///
+/// Reservations are what placement is checked against: a container whose
+/// reservation does not fit in what is left is refused at create. Limits
+/// may add up to more than the machine has; their sum only shows how far
+/// it is overcommitted.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Capacity {
+    /// CPUs and memory of the machine
+    pub total: Reservation,
+    /// Sum of the reservations of the containers
+    pub reserved: Reservation,
+    /// Sum of the limits of the containers; unlimited ones are not counted
+    pub limits: Reservation,
+}
+
+impl Capacity {
+    /// What is left to reserve
+    pub fn available(&self) -> Reservation {
+        Reservation {
+            cpus: (self.total.cpus - self.reserved.cpus).max(0.0),
+            memory: self.total.memory.saturating_sub(self.reserved.memory),
+        }
+    }
+
+    /// Why `reservation` does not fit in what is left, if it does not
+    pub fn shortfall(&self, reservation: Reservation) -> Vec<String> {
+        let available = self.available();
+        let mut problems = Vec::new();
+        // Sums of fractional cores are compared with a little slack
+        if reservation.cpus > available.cpus + 1e-9 {
+            problems.push(format!(
+                "{} CPUs requested, {} of {} left",
+                reservation.cpus, available.cpus, self.total.cpus
+            ));
+        }
+        if reservation.memory > available.memory {
+            problems.push(format!(
+                "{} MiB of memory requested, {} of {} MiB left",
+                reservation.memory / (1024 * 1024),
+                available.memory / (1024 * 1024),
+                self.total.memory / (1024 * 1024)
+            ));
+        }
+        problems
+    }
+}
+
+/// Check a hugepage size such as "2MB": a number followed by KB, MB, GB or TB
+fn is_valid_hugepage_size(size: &str) -> bool {
+    let digits = size.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let unit = &size[digits.len()..];
+    matches!(unit, "KB" | "MB" | "GB" | "TB") && digits.parse::<u64>().is_ok_and(|n| n > 0)
+}
+
+/// Check a cpuset list: comma-separated indices or ascending ranges
+fn is_valid_cpuset(list: &str) -> bool {
+    !list.is_empty()
+        && list.split(',').all(|item| match item.split_once('-') {
+            Some((start, end)) => match (start.parse::<u32>(), end.parse::<u32>()) {
+                (Ok(start), Ok(end)) => start <= end,
+                _ => false,
+            },
+            None => item.parse::<u32>().is_ok(),
+        })
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -585,13 +1974,336 @@ pub struct ContainerInfo {
     pub id: String,
     pub status: ContainerStatus,
     pub pid: Option<u32>,
+    /// Why a stopped container stopped, when the runtime stopped it (e.g. "timeout")
+    #[serde(default)]
+    pub exit_reason: Option<String>,
+    /// Times the container was started again after it exited
+    #[serde(default)]
+    pub restart_count: u32,
+    /// Exit code of the last run, 128 + signal when it was killed
+    #[serde(default)]
+    pub last_exit_code: Option<i32>,
+    /// How the last run ended
+    #[serde(default)]
+    pub last_exit_reason: Option<ExitReason>,
+    #[serde(default)]
+    pub restart_policy: RestartPolicy,
+    /// Restarts in a row counted against the `on-failure` retry limit
+    #[serde(default)]
+    pub restart_retries: u32,
+    /// Address of the container on the VM's bridge network
+    #[serde(default)]
+    pub ip_address: Option<String>,
+    /// Processes of the current or last run killed by the OOM killer; the
+    /// container keeps running unless its main process was one of them
+    #[serde(default)]
+    pub oom_kills: u32,
+    /// Key-value metadata it was created with, see [`ContainerConfig::labels`]
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+    /// See [`ContainerConfig::name`]
+    #[serde(default)]
+    pub name: String,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
-pub enum ContainerStatus {
-    Created,
-    Running,
-    Stopped,
+impl ContainerInfo {
+    /// Whether the container stopped because the OOM killer killed its main
+    /// process
+    pub fn oom_killed(&self) -> bool {
+        self.status == ContainerStatus::Stopped && self.last_exit_reason == Some(ExitReason::Oom)
+    }
+}
+
+pub use libcrun_shim_proto::{
+    ContainerStatus, ExitReason, MountPropagation, RestartPolicy, StaleResourceKind, StateMachine,
+    Transition, TransitionError,
+};
+
+/// How a container's process exited, see `ContainerRuntime::wait`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExitStatus {
+    /// Exit code, 128 + signal when it was killed; unknown when the runtime
+    /// could not reap the process, e.g. after the VM agent was restarted
+    pub code: Option<i32>,
+    pub reason: Option<ExitReason>,
+}
+
+/// Options for running a command in a container
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ExecOptions {
+    pub command: Vec<String>,
+    /// Environment variables (KEY=VALUE); a default PATH is used when empty
+    #[serde(default)]
+    pub env: Vec<String>,
+    /// Working directory inside the container ("/" when unset)
+    #[serde(default)]
+    pub working_dir: Option<String>,
+    /// User ID to run as (root when unset)
+    #[serde(default)]
+    pub uid: Option<u32>,
+    /// Group ID to run as (root when unset)
+    #[serde(default)]
+    pub gid: Option<u32>,
+    /// Allocate a pseudo-terminal; its output is returned as stdout
+    #[serde(default)]
+    pub tty: bool,
+}
+
+/// Input and output of an interactive exec, see
+/// [`crate::ContainerRuntime::exec_interactive`]
+pub struct ExecStdio {
+    pub stdin: Box<dyn std::io::Read + Send>,
+    /// Output of the command; all of it when it runs under a terminal
+    pub stdout: Box<dyn std::io::Write + Send>,
+    pub stderr: Box<dyn std::io::Write + Send>,
+    /// Rows and columns of the terminal, with [`ExecOptions::tty`]
+    pub terminal_size: Option<(u16, u16)>,
+    /// New terminal sizes as the user resizes the window
+    pub resize: Option<std::sync::mpsc::Receiver<(u16, u16)>>,
+    /// Keys that detach from the session, leaving the command running
+    /// (macOS only)
+    pub detach_keys: Option<crate::DetachKeys>,
+    /// `stdin` is all the input the command gets: its end also ends the
+    /// input of a terminal, as typing ^D does, and detaching closes it
+    pub stdin_once: bool,
+}
+
+impl ExecStdio {
+    /// The calling process's stdin, stdout and stderr
+    pub fn inherit() -> Self {
+        Self {
+            stdin: Box::new(std::io::stdin()),
+            stdout: Box::new(std::io::stdout()),
+            stderr: Box::new(std::io::stderr()),
+            terminal_size: crate::get_terminal_size(),
+            resize: None,
+            detach_keys: None,
+            stdin_once: false,
+        }
+    }
+}
+
+/// How an interactive exec ended for the caller
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExecOutcome {
+    /// The command exited with this code
+    Exited(i32),
+    /// The caller detached; the command runs on in the session with this
+    /// ID, see [`crate::ContainerRuntime::attach_exec`]
+    Detached(String),
+}
+
+/// A finished exec session, kept in the container's exec history
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ExecSession {
+    pub command: Vec<String>,
+    /// Who ran the command
+    pub user: String,
+    /// Unix epoch seconds
+    pub started_at: u64,
+    pub duration_ms: u64,
+    /// -1 if the command could not be run or was killed by a signal
+    pub exit_code: i32,
+}
+
+/// A named checkpoint of a container, see [`crate::ContainerRuntime::checkpoint`]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Checkpoint {
+    /// Container the checkpoint was taken of
+    pub container: String,
+    pub name: String,
+    /// Unix epoch seconds
+    pub created_at: u64,
+    /// Bytes on disk of the image and the container's configuration
+    pub size_bytes: u64,
+    /// `sha256:<hex>` over the checkpoint image, checked before a restore
+    pub image_digest: String,
+}
+
+/// Which stopped containers or images a prune removes, see
+/// [`crate::ContainerRuntime::prune`] and [`crate::ImageStore::prune`]
+///
+/// Without conditions, all of them are removed.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PruneFilter {
+    /// Only those created before this time (Unix seconds)
+    #[serde(default)]
+    pub until: Option<u64>,
+    /// Conditions on labels, all of which must hold
+    #[serde(default)]
+    pub labels: Vec<LabelFilter>,
+    /// Images only: keep this many of the newest images of each repository,
+    /// whether or not they match
+    #[serde(default)]
+    pub keep_last: usize,
+}
+
+impl PruneFilter {
+    /// Only prune what was created longer than `age` ago
+    pub fn older_than(mut self, age: std::time::Duration) -> Self {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        self.until = Some(now.saturating_sub(age.as_secs()));
+        self
+    }
+
+    /// Only prune what `filter` holds for
+    pub fn label(mut self, filter: LabelFilter) -> Self {
+        self.labels.push(filter);
+        self
+    }
+
+    /// Keep the `count` newest images of each repository
+    pub fn keep_last(mut self, count: usize) -> Self {
+        self.keep_last = count;
+        self
+    }
+
+    /// Whether something created at `created` (Unix seconds) with `labels`
+    /// is pruned
+    pub fn matches(&self, created: u64, labels: &HashMap<String, String>) -> bool {
+        self.until.is_none_or(|until| created < until)
+            && self.labels.iter().all(|filter| filter.matches(labels))
+    }
+}
+
+/// Which containers [`crate::ContainerRuntime::list_filtered`] lists
+///
+/// Without conditions, all of them are listed.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ListFilter {
+    /// Conditions on labels, all of which must hold
+    #[serde(default)]
+    pub labels: Vec<LabelFilter>,
+    /// Only containers in one of these states (empty = any)
+    #[serde(default)]
+    pub statuses: Vec<ContainerStatus>,
+    /// Only containers whose name starts with this
+    #[serde(default)]
+    pub name_prefix: Option<String>,
+}
+
+impl ListFilter {
+    /// Only list containers `filter` holds for
+    pub fn label(mut self, filter: LabelFilter) -> Self {
+        self.labels.push(filter);
+        self
+    }
+
+    /// Also list containers in `status`
+    pub fn status(mut self, status: ContainerStatus) -> Self {
+        if !self.statuses.contains(&status) {
+            self.statuses.push(status);
+        }
+        self
+    }
+
+    /// Only list containers whose name starts with `prefix`
+    pub fn name_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.name_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Whether `container` is listed
+    pub fn matches(&self, container: &ContainerInfo) -> bool {
+        (self.statuses.is_empty() || self.statuses.contains(&container.status))
+            && self
+                .name_prefix
+                .as_deref()
+                .is_none_or(|prefix| container.name.starts_with(prefix))
+            && self
+                .labels
+                .iter()
+                .all(|filter| filter.matches(&container.labels))
+    }
+}
+
+/// A condition on labels, see [`PruneFilter`] and [`ListFilter`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LabelFilter {
+    pub key: String,
+    /// Value the label must have (None = any)
+    #[serde(default)]
+    pub value: Option<String>,
+    /// Hold where the label is not set (to `value`) instead
+    #[serde(default)]
+    pub negate: bool,
+}
+
+impl LabelFilter {
+    /// Parse `KEY` or `KEY=VALUE`, as in `--filter label=KEY=VALUE`
+    pub fn parse(s: &str) -> crate::Result<Self> {
+        let (key, value) = match s.split_once('=') {
+            Some((key, value)) => (key, Some(value.to_string())),
+            None => (s, None),
+        };
+        if key.is_empty() {
+            return Err(crate::ShimError::validation(
+                "label",
+                format!("Invalid label filter '{}', expected KEY or KEY=VALUE", s),
+            ));
+        }
+        Ok(Self {
+            key: key.to_string(),
+            value,
+            negate: false,
+        })
+    }
+
+    /// The opposite condition, as in `--filter label!=KEY=VALUE`
+    pub fn negated(mut self) -> Self {
+        self.negate = !self.negate;
+        self
+    }
+
+    /// Whether the condition holds for `labels`
+    pub fn matches(&self, labels: &HashMap<String, String>) -> bool {
+        let set = match &self.value {
+            Some(value) => labels.get(&self.key) == Some(value),
+            None => labels.contains_key(&self.key),
+        };
+        set != self.negate
+    }
+}
+
+/// What a container left behind without the runtime tracking a container
+/// for it, see [`crate::ContainerRuntime::stale_resources`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StaleResource {
+    pub kind: StaleResourceKind,
+    /// ID of the container it belonged to
+    pub id: String,
+    /// Where it is, e.g. a directory, or the name of a network interface
+    pub path: String,
+}
+
+/// How a copy in or out of a container treats what it copies, see
+/// [`crate::ContainerRuntime::copy_to`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CopyOptions {
+    /// Glob patterns of what is left out: one without a `/`, e.g. `*.log`,
+    /// matches by name anywhere, one with a `/` the path below the copied
+    /// directory, e.g. `build/cache`
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Copy what symbolic links point to instead of the links
+    #[serde(default)]
+    pub follow_links: bool,
+    /// Keep the owners of the files; otherwise they belong to whoever
+    /// unpacks them, root in the container and the current user on the host
+    #[serde(default)]
+    pub preserve_owner: bool,
+}
+
+/// What a copy copied
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CopyStats {
+    /// Files, directories and links
+    pub entries: u64,
+    /// Bytes of file content
+    pub bytes: u64,
 }
 
 /// Container resource metrics
@@ -711,6 +2423,252 @@ pub struct VmMetrics {
     pub container_count: u32,
 }
 
+/// Version information reported by the VM agent during the handshake
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct AgentInfo {
+    /// Agent semantic version
+    pub version: String,
+    /// Wire protocol version spoken by the agent
+    pub protocol_version: u32,
+    /// Optional features supported by the agent
+    #[serde(default)]
+    pub features: Vec<String>,
+    /// Guest kernel features (None for agents that don't report them)
+    #[serde(default)]
+    pub kernel: Option<KernelFeatures>,
+    /// Guest clock offset at the last time sync (None before the first one,
+    /// or for agents that can't sync)
+    #[serde(default)]
+    pub clock: Option<ClockSync>,
+}
+
+impl AgentInfo {
+    /// Whether the agent advertised the given feature
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+}
+
+/// How a component was built, for bug reports
+///
+/// See [`crate::build_info`] for the host library and
+/// `ContainerRuntime::agent_build_info` for the VM agent.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct BuildInfo {
+    /// Semantic version
+    pub version: String,
+    /// Commit it was built from, if built from a git checkout
+    pub git_sha: Option<String>,
+    /// Unix seconds
+    pub build_timestamp: u64,
+    /// Version of the libcrun linked in, if any
+    pub libcrun_version: Option<String>,
+    /// Oldest and newest agent wire protocol versions spoken
+    pub min_protocol_version: u32,
+    pub max_protocol_version: u32,
+}
+
+/// What the VM agent reports for a support bundle
+///
+/// See `ContainerRuntime::agent_diagnostics`.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct AgentDiagnostics {
+    /// Lines the agent logged most recently, oldest first
+    pub agent_log: Vec<String>,
+    /// The guest kernel's message buffer, as `dmesg` prints it
+    pub kernel_log: String,
+    /// The agent's state files by their path in the guest
+    pub state_files: Vec<(String, Vec<u8>)>,
+}
+
+/// Kernel features available in the guest VM
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct KernelFeatures {
+    /// Unified cgroup v2 hierarchy is mounted
+    pub cgroup_v2: bool,
+    /// Enabled cgroup controllers, e.g. "cpu", "memory", "pids"
+    pub cgroup_controllers: Vec<String>,
+    pub overlayfs: bool,
+    pub vsock: bool,
+    pub user_namespaces: bool,
+    pub seccomp: bool,
+}
+
+impl KernelFeatures {
+    /// Whether a cgroup controller is enabled
+    pub fn has_controller(&self, controller: &str) -> bool {
+        self.cgroup_controllers.iter().any(|c| c == controller)
+    }
+
+    /// Check that the kernel supports everything a container config asks for
+    pub fn check(&self, config: &ContainerConfig) -> crate::Result<()> {
+        let resources = &config.resources;
+        // cgroup v1 names the block I/O controller "blkio"
+        let io = if self.cgroup_v2 { "io" } else { "blkio" };
+        let required = [
+            (
+                "memory",
+                resources.memory.is_some()
+                    || resources.memory_swap.is_some()
+                    || resources.memory_swappiness.is_some(),
+                "a memory limit",
+            ),
+            ("cpu", resources.cpu.is_some(), "a CPU limit"),
+            (
+                "cpuset",
+                resources.cpuset_cpus.is_some() || resources.cpuset_mems.is_some(),
+                "a cpuset",
+            ),
+            ("pids", resources.pids.is_some(), "a pids limit"),
+            (io, resources.blkio_weight.is_some(), "a block I/O weight"),
+            (
+                "hugetlb",
+                !resources.hugepage_limits.is_empty(),
+                "hugepage limits",
+            ),
+        ];
+        for (controller, needed, what) in required {
+            if needed && !self.has_controller(controller) {
+                return Err(crate::ShimError::runtime_with_context(
+                    format!("{} controller not enabled in the guest kernel", controller),
+                    format!(
+                        "Container '{}' sets {}; use a VM kernel with the {} cgroup controller enabled, or remove the limit",
+                        config.id, what, controller
+                    ),
+                ));
+            }
+        }
+
+        if config.seccomp_profile.is_some() && !self.seccomp {
+            return Err(crate::ShimError::runtime_with_context(
+                "seccomp not supported by the guest kernel",
+                format!(
+                    "Container '{}' sets a seccomp profile; use a VM kernel built with CONFIG_SECCOMP_FILTER",
+                    config.id
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Result of an in-place agent upgrade
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentUpgrade {
+    /// Agent running before the upgrade
+    pub previous: AgentInfo,
+    /// Agent running after the upgrade
+    pub current: AgentInfo,
+    /// SHA-256 of the installed binary
+    pub sha256: String,
+}
+
+/// Space on a filesystem mounted in the VM
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FilesystemUsage {
+    pub mount_point: String,
+    /// Backing device, e.g. "/dev/vda", or the filesystem name for virtual ones
+    pub device: String,
+    pub fs_type: String,
+    pub total_bytes: u64,
+    pub used_bytes: u64,
+    pub available_bytes: u64,
+}
+
+/// Result of trimming a filesystem in the VM
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TrimResult {
+    pub mount_point: String,
+    /// Bytes discarded, which the host can reclaim from the disk image
+    pub trimmed_bytes: u64,
+    /// Why the trim failed, e.g. the device does not support discard
+    pub error: Option<String>,
+}
+
+/// A VM disk image as stored on the host
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DiskImageInfo {
+    pub path: PathBuf,
+    /// "raw" or "qcow2"
+    pub format: String,
+    /// Size of the disk as seen by the guest
+    pub size_bytes: u64,
+    /// Host disk space actually used by the (sparse) image file
+    pub allocated_bytes: u64,
+    pub read_only: bool,
+}
+
+/// Usage of one container at one point in time
+///
+/// CPU, network and block I/O are cumulative counters; rates come from the
+/// difference between consecutive samples.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct MetricsSample {
+    pub id: String,
+    /// Unix timestamp in seconds
+    pub timestamp: u64,
+    pub cpu_usage_ns: u64,
+    pub memory_bytes: u64,
+    pub net_rx_bytes: u64,
+    pub net_tx_bytes: u64,
+    pub blkio_read_bytes: u64,
+    pub blkio_write_bytes: u64,
+    pub pids: u64,
+}
+
+impl MetricsSample {
+    /// The counters of a metrics snapshot, stamped with its collection time
+    pub fn from_metrics(metrics: &ContainerMetrics) -> Self {
+        Self {
+            id: metrics.id.clone(),
+            timestamp: metrics.timestamp,
+            cpu_usage_ns: metrics.cpu.usage_total,
+            memory_bytes: metrics.memory.usage,
+            net_rx_bytes: metrics.network.rx_bytes,
+            net_tx_bytes: metrics.network.tx_bytes,
+            blkio_read_bytes: metrics.blkio.read_bytes,
+            blkio_write_bytes: metrics.blkio.write_bytes,
+            pids: metrics.pids.current,
+        }
+    }
+}
+
+/// Distribution of a value over a report window
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+pub struct UsageStats {
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+    pub p50: f64,
+    pub p95: f64,
+    pub p99: f64,
+}
+
+/// Resource usage of one container over a report window
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct ContainerUsageReport {
+    pub id: String,
+    /// Time of the first sample in the window (Unix epoch seconds)
+    pub from: u64,
+    /// Time of the last sample in the window (Unix epoch seconds)
+    pub to: u64,
+    pub samples: usize,
+    /// CPU usage in percent of one core, between consecutive samples
+    pub cpu_percent: UsageStats,
+    /// CPU time consumed in the window
+    pub cpu_seconds: f64,
+    pub memory_bytes: UsageStats,
+    pub pids: UsageStats,
+    /// Bytes received in the window
+    pub net_rx_bytes: u64,
+    /// Bytes transmitted in the window
+    pub net_tx_bytes: u64,
+    /// Bytes read from block devices in the window
+    pub blkio_read_bytes: u64,
+    /// Bytes written to block devices in the window
+    pub blkio_write_bytes: u64,
+}
+
 /// Container log output
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct ContainerLogs {
@@ -722,6 +2680,38 @@ pub struct ContainerLogs {
     pub stderr: String,
     /// Timestamp of log retrieval
     pub timestamp: u64,
+    /// Offset to pass as `LogOptions::stdout_offset` to read newer stdout
+    #[serde(default)]
+    pub stdout_offset: u64,
+    /// Offset to pass as `LogOptions::stderr_offset` to read newer stderr
+    #[serde(default)]
+    pub stderr_offset: u64,
+    /// Output was cut to stay within `LogOptions::max_bytes`
+    #[serde(default)]
+    pub truncated: bool,
+    /// Both streams line by line in the order they were written, when the
+    /// runtime keeps structured logs (the VM agent does)
+    #[serde(default)]
+    pub entries: Vec<LogEntry>,
+}
+
+/// Output stream of a log entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+}
+
+/// One captured line of container output
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub stream: LogStream,
+    /// Capture time (Unix epoch nanoseconds)
+    pub timestamp_ns: u64,
+    /// Position across both streams
+    pub seq: u64,
+    pub payload: Vec<u8>,
 }
 
 /// Log retrieval options
@@ -733,8 +2723,21 @@ pub struct LogOptions {
     pub since: u64,
     /// Include timestamps in output
     pub timestamps: bool,
-    /// Follow log output (streaming)
+    /// Follow log output; only [`crate::ContainerRuntime::follow_logs`]
+    /// streams it, other calls return a snapshot
     pub follow: bool,
+    /// Maximum bytes returned per stream (0 = runtime maximum, 4 MiB)
+    #[serde(default)]
+    pub max_bytes: u64,
+    /// Read stdout forward from this byte offset instead of tailing
+    #[serde(default)]
+    pub stdout_offset: Option<u64>,
+    /// Read stderr forward from this byte offset instead of tailing
+    #[serde(default)]
+    pub stderr_offset: Option<u64>,
+    /// Return logs before this timestamp (0 = no limit)
+    #[serde(default)]
+    pub until: u64,
 }
 
 /// Health check configuration
@@ -894,6 +2897,31 @@ pub struct ImageInfo {
     pub os: String,
     /// Labels
     pub labels: std::collections::HashMap<String, String>,
+    /// Layer digests, bottom layer first (empty for images pulled by older versions)
+    #[serde(default)]
+    pub layers: Vec<String>,
+}
+
+/// Outcome of copying image blobs into the VM's cache
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BlobSyncReport {
+    /// Blobs the VM already had
+    pub cached: usize,
+    /// Blobs sent to the VM
+    pub sent: usize,
+    /// Bytes sent to the VM
+    pub sent_bytes: u64,
+}
+
+/// A container recreated on a new version of its image
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageUpdate {
+    /// Container ID
+    pub container: String,
+    /// Image reference the container follows
+    pub image: String,
+    /// ID of the image the container now runs
+    pub image_id: String,
 }
 
 /// Image pull progress
@@ -913,6 +2941,52 @@ pub struct PullProgress {
     pub status: String,
 }
 
+/// A long operation whose progress is reported, see
+/// [`crate::subscribe_progress`]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Operation {
+    /// Creating a container
+    Create,
+    Checkpoint,
+    /// Restoring a checkpoint as a new container
+    Restore,
+    ExportCheckpoint,
+    ImportCheckpoint,
+    /// Loading an image archive
+    LoadImage,
+    /// Copying files in or out of a container
+    Copy,
+}
+
+impl std::fmt::Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Operation::Create => "create",
+            Operation::Checkpoint => "checkpoint",
+            Operation::Restore => "restore",
+            Operation::ExportCheckpoint => "export checkpoint",
+            Operation::ImportCheckpoint => "import checkpoint",
+            Operation::LoadImage => "load image",
+            Operation::Copy => "copy",
+        })
+    }
+}
+
+/// A phase of a long operation
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct OperationProgress {
+    pub operation: Operation,
+    /// What it works on: a container, a checkpoint or an archive
+    pub target: String,
+    /// Short name of the phase, e.g. `snapshot`
+    pub phase: String,
+    /// How far through the phase the operation is, when that is known
+    pub percent: Option<u8>,
+    /// What the operation is doing, for people
+    pub message: String,
+}
+
 /// Container event types
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum ContainerEventType {
@@ -942,6 +3016,8 @@ pub enum ContainerEventType {
     ExecStart,
     /// Container exec died
     ExecDie,
+    /// Container recreated on a new version of its image
+    Updated,
 }
 
 /// Container event
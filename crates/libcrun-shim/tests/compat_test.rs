@@ -0,0 +1,228 @@
+//! Host/agent version skew tests
+//!
+//! Each simulated agent generation answers the host's requests through the
+//! real wire codec, so these tests catch both negotiation mistakes and
+//! changes to the encoding of existing messages.
+
+use libcrun_shim::compat::{negotiate, require_feature};
+use libcrun_shim::{Result, ShimError};
+use libcrun_shim_proto::*;
+
+/// A simulated agent release
+struct FakeAgent {
+    version: &'static str,
+    protocol_version: u32,
+    /// `None` for agents that predate feature negotiation
+    features: Option<Vec<&'static str>>,
+}
+
+impl FakeAgent {
+    /// Protocol 1 agent shipped before `Request::Features` existed
+    fn legacy() -> Self {
+        Self {
+            version: "0.1.0",
+            protocol_version: 1,
+            features: None,
+        }
+    }
+
+    /// Agent built from this tree
+    fn current() -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            protocol_version: PROTOCOL_VERSION,
+            features: Some(vec![
+                features::EXEC,
+                features::HEALTH,
+                features::METRICS,
+                features::AGENT_UPGRADE,
+                features::KERNEL_FEATURES,
+            ]),
+        }
+    }
+
+    /// Agent that speaks the current protocol but was built without exec
+    fn without_exec() -> Self {
+        Self {
+            version: "0.1.0-minimal",
+            protocol_version: PROTOCOL_VERSION,
+            features: Some(vec![features::METRICS]),
+        }
+    }
+
+    /// Agent from a future release with an incompatible protocol
+    fn future() -> Self {
+        Self {
+            version: "9.0.0",
+            protocol_version: PROTOCOL_VERSION + 1,
+            features: Some(vec![]),
+        }
+    }
+
+    fn respond(&self, request: Request) -> Response {
+        match request {
+            Request::Handshake => Response::Handshake(HandshakeProto {
+                agent_version: self.version.to_string(),
+                protocol_version: self.protocol_version,
+            }),
+            Request::Features => match &self.features {
+                Some(list) => Response::Features(list.iter().map(|f| f.to_string()).collect()),
+                // What an old agent sends back for a variant it can't decode
+                None => Response::Error("Parse error: unknown variant".to_string()),
+            },
+            // A guest kernel built without the memory controller
+            Request::KernelFeatures => Response::KernelFeatures(KernelFeaturesProto {
+                cgroup_v2: true,
+                cgroup_controllers: vec!["cpu".to_string(), "pids".to_string()],
+                overlayfs: true,
+                vsock: true,
+                user_namespaces: true,
+                seccomp: true,
+            }),
+            other => Response::Error(format!("unexpected request {:?}", other)),
+        }
+    }
+
+    /// Send a request through the framed codec, as the host does over the socket
+    fn call(&self, request: Request) -> Result<Response> {
+        let mut wire = Vec::new();
+        write_frame(&mut wire, &serialize_request(&request).unwrap()).unwrap();
+        let frame = read_frame(&mut wire.as_slice()).unwrap().unwrap();
+        let request = deserialize_request(&frame)
+            .map_err(|e| ShimError::runtime(format!("agent failed to decode: {}", e)))?;
+
+        let mut wire = Vec::new();
+        write_frame(
+            &mut wire,
+            &serialize_response(&self.respond(request)).unwrap(),
+        )
+        .unwrap();
+        let frame = read_frame(&mut wire.as_slice()).unwrap().unwrap();
+        deserialize_response(&frame)
+            .map_err(|e| ShimError::runtime(format!("host failed to decode: {}", e)))
+    }
+}
+
+#[test]
+fn test_current_agent_negotiation() {
+    let agent = FakeAgent::current();
+    let info = negotiate(|req| agent.call(req)).unwrap();
+
+    assert_eq!(info.protocol_version, PROTOCOL_VERSION);
+    assert!(info.supports(features::EXEC));
+    assert!(!info.supports(features::LOG_STREAMING));
+}
+
+#[test]
+fn test_legacy_agent_gets_baseline_features() {
+    let agent = FakeAgent::legacy();
+    let info = negotiate(|req| agent.call(req)).unwrap();
+
+    assert_eq!(info.version, "0.1.0");
+    for feature in features::BASELINE {
+        assert!(
+            info.supports(feature),
+            "legacy agent should support {}",
+            feature
+        );
+    }
+    assert!(!info.supports(features::LOG_STREAMING));
+}
+
+#[test]
+fn test_future_protocol_rejected() {
+    let agent = FakeAgent::future();
+    let err = negotiate(|req| agent.call(req)).unwrap_err();
+    assert!(err.to_string().contains("speaks protocol"));
+}
+
+#[test]
+fn test_missing_feature_is_gated() {
+    let agent = FakeAgent::without_exec();
+    let info = negotiate(|req| agent.call(req)).unwrap();
+
+    assert!(require_feature(&info, features::METRICS, "metrics").is_ok());
+    let err = require_feature(&info, features::EXEC, "exec").unwrap_err();
+    assert!(err.to_string().contains("missing feature 'exec'"));
+}
+
+#[test]
+fn test_kernel_features_reported() {
+    let info = negotiate(|req| FakeAgent::legacy().call(req)).unwrap();
+    assert!(info.kernel.is_none());
+
+    let info = negotiate(|req| FakeAgent::current().call(req)).unwrap();
+    let kernel = info.kernel.unwrap();
+    assert!(kernel.has_controller("cpu"));
+
+    let mut config = libcrun_shim::ContainerConfig {
+        id: "limited".to_string(),
+        ..Default::default()
+    };
+    config.resources.cpu = Some(1.0);
+    assert!(kernel.check(&config).is_ok());
+
+    config.resources.memory = Some(64 * 1024 * 1024);
+    let err = kernel.check(&config).unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("memory controller not enabled in the guest kernel"));
+}
+
+#[test]
+fn test_kernel_check_cgroup_v1_blkio() {
+    let mut config = libcrun_shim::ContainerConfig {
+        id: "weighted".to_string(),
+        ..Default::default()
+    };
+    config.resources.blkio_weight = Some(500);
+
+    // cgroup v1 names the controller "blkio", v2 "io"
+    let v1 = libcrun_shim::KernelFeatures {
+        cgroup_v2: false,
+        cgroup_controllers: vec!["cpu".to_string(), "blkio".to_string()],
+        ..Default::default()
+    };
+    assert!(v1.check(&config).is_ok());
+
+    let v2 = libcrun_shim::KernelFeatures {
+        cgroup_v2: true,
+        ..v1
+    };
+    let err = v2.check(&config).unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("io controller not enabled in the guest kernel"));
+}
+
+#[test]
+fn test_compatibility_matrix() {
+    // (agent, negotiates, exec allowed, streams logs)
+    let matrix = [
+        (FakeAgent::legacy(), true, true, false),
+        (FakeAgent::current(), true, true, false),
+        (FakeAgent::without_exec(), true, false, false),
+        (FakeAgent::future(), false, false, false),
+    ];
+
+    for (agent, negotiates, exec, streams) in matrix {
+        match negotiate(|req| agent.call(req)) {
+            Ok(info) => {
+                assert!(negotiates, "agent {} should be rejected", agent.version);
+                assert_eq!(
+                    require_feature(&info, features::EXEC, "exec").is_ok(),
+                    exec,
+                    "exec gating for agent {}",
+                    agent.version
+                );
+                assert_eq!(
+                    info.supports(features::LOG_STREAMING),
+                    streams,
+                    "log streaming for agent {}",
+                    agent.version
+                );
+            }
+            Err(e) => assert!(!negotiates, "agent {} failed: {}", agent.version, e),
+        }
+    }
+}
@@ -2,7 +2,8 @@
 use libcrun_shim::{ContainerConfig, ContainerRuntime, ContainerStatus};
 #[cfg(target_os = "macos")]
 use libcrun_shim_proto::{
-    CreateRequest, NetworkConfigProto, Request, ResourceLimitsProto, Response, StdioConfigProto,
+    CreateRequest, NetworkConfigProto, Request, ResourceLimitsProto, Response, RestartPolicy,
+    StdioConfigProto,
 };
 use std::os::unix::net::UnixStream;
 use std::process::{Child, Command};
@@ -68,6 +69,7 @@ async fn test_macos_rpc_communication() {
     // Test Create
     let create_req = Request::Create(CreateRequest {
         id: "test-rpc".to_string(),
+        name: String::new(),
         rootfs: "/tmp/rootfs".to_string(),
         command: vec!["sh".to_string()],
         env: vec![],
@@ -77,6 +79,14 @@ async fn test_macos_rpc_communication() {
         volumes: vec![],
         resources: ResourceLimitsProto::default(),
         health_check: None,
+        security: Default::default(),
+        image: None,
+        max_runtime_secs: None,
+        auto_stop: None,
+        join_namespaces: None,
+        restart_policy: RestartPolicy::No,
+        rootfs_snapshot: false,
+        read_only_rootfs: false,
     });
 
     match client.call(create_req).unwrap() {
@@ -119,6 +129,7 @@ async fn test_linux_runtime_integration() {
         health_check: None,
         log_driver: "json-file".to_string(),
         log_max_size: 10 * 1024 * 1024,
+        ..Default::default()
     };
 
     // Create container
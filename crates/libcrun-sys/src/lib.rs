@@ -8,6 +8,9 @@
 
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
 
+/// Version of the libcrun linked in, `None` with the stub bindings
+pub const LIBCRUN_VERSION: Option<&str> = option_env!("LIBCRUN_VERSION");
+
 /// Thread-safe wrapper for libcrun context pointer
 /// 
 /// # Safety
@@ -59,10 +62,14 @@ impl LibcrunContainerPtr {
 }
 
 // Safe wrappers around the FFI functions
+//
+// Every wrapper validates its string arguments before crossing the FFI
+// boundary, and releases libcrun's error object on both success and failure.
 pub mod safe {
     use super::*;
     use std::ffi::CString;
     use std::os::raw::c_int;
+    use std::path::{Path, PathBuf};
     use std::ptr;
 
     /// Error type for libcrun operations
@@ -84,35 +91,68 @@ pub mod safe {
                 message: "libcrun error".to_string(),
             })
         }
+
+        fn invalid(message: &str) -> Self {
+            CrunError {
+                code: -1,
+                message: message.to_string(),
+            }
+        }
     }
 
-    /// Create a new libcrun context
-    pub fn context_new() -> Result<*mut libcrun_context_t, CrunError> {
-            let mut err: *mut libcrun_error_t = ptr::null_mut();
-            let context = libcrun_context_new(&mut err);
+    impl std::fmt::Display for CrunError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{} (code {})", self.message, self.code)
+        }
+    }
 
-            if context.is_null() {
-                if let Some(e) = CrunError::from_libcrun_error(err) {
-                    libcrun_error_release(&mut err);
-                    return Err(e);
-                }
-                return Err(CrunError {
-                    code: -1,
-                    message: "Failed to create libcrun context".to_string(),
-                });
-            }
+    impl std::error::Error for CrunError {}
+
+    fn c_string(value: &str, what: &str) -> Result<CString, CrunError> {
+        CString::new(value).map_err(|_| CrunError::invalid(what))
+    }
 
-            if !err.is_null() {
+    /// Turn a libcrun return code and error object into a `Result`, releasing
+    /// the error object either way
+    fn check(
+        result: c_int,
+        mut err: *mut libcrun_error_t,
+        failure: impl FnOnce() -> String,
+    ) -> Result<(), CrunError> {
+        if result != 0 {
+            if let Some(e) = CrunError::from_libcrun_error(err) {
                 libcrun_error_release(&mut err);
+                return Err(e);
             }
+            return Err(CrunError {
+                code: result,
+                message: failure(),
+            });
+        }
+
+        if !err.is_null() {
+            libcrun_error_release(&mut err);
+        }
 
-            Ok(context)
+        Ok(())
+    }
+
+    /// Create a new libcrun context
+    pub fn context_new() -> Result<*mut libcrun_context_t, CrunError> {
+        let mut err: *mut libcrun_error_t = ptr::null_mut();
+        let context = libcrun_context_new(&mut err);
+
+        if context.is_null() {
+            check(-1, err, || "Failed to create libcrun context".to_string())?;
+        }
+        check(0, err, String::new)?;
+        Ok(context)
     }
 
     /// Free a libcrun context
     pub fn context_free(context: *mut libcrun_context_t) {
         if !context.is_null() {
-                libcrun_context_free(context);
+            libcrun_context_free(context);
         }
     }
 
@@ -120,36 +160,63 @@ pub mod safe {
     pub fn container_load_from_memory(
         config_json: &str,
     ) -> Result<*mut libcrun_container_t, CrunError> {
-        let config_cstr = CString::new(config_json).map_err(|_| CrunError {
-            code: -1,
-            message: "Invalid JSON string".to_string(),
-        })?;
+        let config_cstr = c_string(config_json, "Invalid JSON string")?;
 
-            let mut err: *mut libcrun_error_t = ptr::null_mut();
-            let container = libcrun_container_load_from_memory(config_cstr.as_ptr(), &mut err);
-
-            if container.is_null() {
-                if let Some(e) = CrunError::from_libcrun_error(err) {
-                    libcrun_error_release(&mut err);
-                    return Err(e);
-                }
-                return Err(CrunError {
-                    code: -1,
-                    message: "Failed to load container from JSON".to_string(),
-                });
-            }
+        let mut err: *mut libcrun_error_t = ptr::null_mut();
+        let container = libcrun_container_load_from_memory(config_cstr.as_ptr(), &mut err);
 
-            if !err.is_null() {
-                libcrun_error_release(&mut err);
-            }
-
-            Ok(container)
+        if container.is_null() {
+            check(-1, err, || "Failed to load container from JSON".to_string())?;
+        }
+        check(0, err, String::new)?;
+        Ok(container)
     }
 
     /// Free a container
     pub fn container_free(container: *mut libcrun_container_t) {
         if !container.is_null() {
-                libcrun_container_free(container);
+            libcrun_container_free(container);
+        }
+    }
+
+    /// An owned libcrun context, freed when dropped
+    #[derive(Debug)]
+    pub struct Context(LibcrunContextPtr);
+
+    impl Context {
+        pub fn new() -> Result<Self, CrunError> {
+            context_new().map(|ptr| Self(LibcrunContextPtr::new(ptr)))
+        }
+
+        pub fn as_ptr(&self) -> *mut libcrun_context_t {
+            self.0.as_ptr()
+        }
+    }
+
+    impl Drop for Context {
+        fn drop(&mut self) {
+            context_free(self.0.as_ptr());
+        }
+    }
+
+    /// An owned container definition loaded from an OCI config, freed when
+    /// dropped. Dropping it does not touch a created container's processes.
+    #[derive(Debug)]
+    pub struct Container(LibcrunContainerPtr);
+
+    impl Container {
+        pub fn load(config_json: &str) -> Result<Self, CrunError> {
+            container_load_from_memory(config_json).map(|ptr| Self(LibcrunContainerPtr::new(ptr)))
+        }
+
+        pub fn as_ptr(&self) -> *mut libcrun_container_t {
+            self.0.as_ptr()
+        }
+    }
+
+    impl Drop for Container {
+        fn drop(&mut self) {
+            container_free(self.0.as_ptr());
         }
     }
 
@@ -159,214 +226,873 @@ pub mod safe {
         container: *mut libcrun_container_t,
         id: &str,
     ) -> Result<(), CrunError> {
-        let id_cstr = CString::new(id).map_err(|_| CrunError {
-            code: -1,
-            message: "Invalid container ID".to_string(),
-        })?;
-
-            let mut err: *mut libcrun_error_t = ptr::null_mut();
-            let result = libcrun_container_create(context, container, id_cstr.as_ptr(), &mut err);
+        let id_cstr = c_string(id, "Invalid container ID")?;
+        let mut err: *mut libcrun_error_t = ptr::null_mut();
+        let result = libcrun_container_create(context, container, id_cstr.as_ptr(), &mut err);
+        check(result, err, || {
+            format!("Failed to create container: {}", id)
+        })
+    }
 
-            if result != 0 {
-                if let Some(e) = CrunError::from_libcrun_error(err) {
-                    libcrun_error_release(&mut err);
-                    return Err(e);
-                }
-                return Err(CrunError {
-                    code: result,
-                    message: format!("Failed to create container: {}", id),
-                });
-            }
+    /// Start a container
+    pub fn container_start(
+        context: *mut libcrun_context_t,
+        container: *mut libcrun_container_t,
+        id: &str,
+    ) -> Result<(), CrunError> {
+        let id_cstr = c_string(id, "Invalid container ID")?;
+        let mut err: *mut libcrun_error_t = ptr::null_mut();
+        let result = libcrun_container_start(context, container, id_cstr.as_ptr(), &mut err);
+        check(result, err, || format!("Failed to start container: {}", id))
+    }
 
-            if !err.is_null() {
-                libcrun_error_release(&mut err);
-            }
+    /// Kill (stop) a container
+    pub fn container_kill(
+        context: *mut libcrun_context_t,
+        container: *mut libcrun_container_t,
+        id: &str,
+        signal: c_int,
+    ) -> Result<(), CrunError> {
+        let id_cstr = c_string(id, "Invalid container ID")?;
+        let mut err: *mut libcrun_error_t = ptr::null_mut();
+        let result = libcrun_container_kill(context, container, id_cstr.as_ptr(), signal, &mut err);
+        check(result, err, || format!("Failed to kill container: {}", id))
+    }
 
-            Ok(())
+    /// Delete a container
+    pub fn container_delete(
+        context: *mut libcrun_context_t,
+        container: *mut libcrun_container_t,
+        id: &str,
+    ) -> Result<(), CrunError> {
+        let id_cstr = c_string(id, "Invalid container ID")?;
+        let mut err: *mut libcrun_error_t = ptr::null_mut();
+        let result = libcrun_container_delete(context, container, id_cstr.as_ptr(), &mut err);
+        check(result, err, || {
+            format!("Failed to delete container: {}", id)
+        })
     }
 
-    /// Start a container
-    pub fn container_start(
+    /// Get container state
+    pub fn container_state(
         context: *mut libcrun_context_t,
         container: *mut libcrun_container_t,
         id: &str,
     ) -> Result<(), CrunError> {
-        let id_cstr = CString::new(id).map_err(|_| CrunError {
-            code: -1,
-            message: "Invalid container ID".to_string(),
-        })?;
+        let id_cstr = c_string(id, "Invalid container ID")?;
+        let mut err: *mut libcrun_error_t = ptr::null_mut();
+        let result = libcrun_container_state(context, container, id_cstr.as_ptr(), &mut err);
+        check(result, err, || {
+            format!("Failed to get container state: {}", id)
+        })
+    }
 
-            let mut err: *mut libcrun_error_t = ptr::null_mut();
-            let result = libcrun_container_start(context, container, id_cstr.as_ptr(), &mut err);
+    /// Freeze all processes of a running container
+    pub fn container_pause(context: *mut libcrun_context_t, id: &str) -> Result<(), CrunError> {
+        let id_cstr = c_string(id, "Invalid container ID")?;
+        let mut err: *mut libcrun_error_t = ptr::null_mut();
+        let result = libcrun_container_pause(context, id_cstr.as_ptr(), &mut err);
+        check(result, err, || format!("Failed to pause container: {}", id))
+    }
 
-            if result != 0 {
-                if let Some(e) = CrunError::from_libcrun_error(err) {
-                    libcrun_error_release(&mut err);
-                    return Err(e);
-                }
-                return Err(CrunError {
-                    code: result,
-                    message: format!("Failed to start container: {}", id),
-                });
-            }
+    /// Thaw a container frozen with [`container_pause`]
+    pub fn container_resume(context: *mut libcrun_context_t, id: &str) -> Result<(), CrunError> {
+        let id_cstr = c_string(id, "Invalid container ID")?;
+        let mut err: *mut libcrun_error_t = ptr::null_mut();
+        let result = libcrun_container_unpause(context, id_cstr.as_ptr(), &mut err);
+        check(result, err, || {
+            format!("Failed to resume container: {}", id)
+        })
+    }
 
-            if !err.is_null() {
-                libcrun_error_release(&mut err);
-            }
+    /// Change the resource limits of a running container
+    ///
+    /// `resources_json` is an OCI `linux.resources` object, e.g.
+    /// `{"memory": {"limit": 268435456}, "pids": {"limit": 64}}`.
+    pub fn container_update(
+        context: *mut libcrun_context_t,
+        id: &str,
+        resources_json: &str,
+    ) -> Result<(), CrunError> {
+        let id_cstr = c_string(id, "Invalid container ID")?;
+        let content = c_string(resources_json, "Invalid resources JSON")?;
+        let mut err: *mut libcrun_error_t = ptr::null_mut();
+        let result = libcrun_container_update(
+            context,
+            id_cstr.as_ptr(),
+            content.as_ptr(),
+            resources_json.len() as _,
+            &mut err,
+        );
+        check(result, err, || {
+            format!("Failed to update container: {}", id)
+        })
+    }
 
-            Ok(())
+    /// Options for [`container_checkpoint`]
+    #[derive(Debug, Clone, Default)]
+    pub struct CheckpointOptions {
+        /// Directory the checkpoint images are written to
+        pub image_path: PathBuf,
+        /// Directory for CRIU logs and work files (the image directory when unset)
+        pub work_path: Option<PathBuf>,
+        /// Keep the container running after the checkpoint
+        pub leave_running: bool,
+        pub tcp_established: bool,
+        pub shell_job: bool,
+        pub ext_unix_sk: bool,
+        pub file_locks: bool,
     }
 
-    /// Kill (stop) a container
-    pub fn container_kill(
+    /// libcrun's form of `options`, with the strings its pointers refer to
+    fn cr_options(
+        options: &CheckpointOptions,
+    ) -> Result<(libcrun_checkpoint_restore_t, Vec<CString>), CrunError> {
+        let image_path = c_string(&options.image_path.to_string_lossy(), "Invalid image path")?;
+        let work_path = options
+            .work_path
+            .as_ref()
+            .map(|p| c_string(&p.to_string_lossy(), "Invalid work path"))
+            .transpose()?;
+
+        // SAFETY: all-zero is the "unset" value for every checkpoint option
+        let mut cr_options: libcrun_checkpoint_restore_t = unsafe { std::mem::zeroed() };
+        cr_options.image_path = image_path.as_ptr() as _;
+        if let Some(work_path) = &work_path {
+            cr_options.work_path = work_path.as_ptr() as _;
+        }
+        cr_options.leave_running = options.leave_running;
+        cr_options.tcp_established = options.tcp_established;
+        cr_options.shell_job = options.shell_job;
+        cr_options.ext_unix_sk = options.ext_unix_sk;
+        cr_options.file_locks = options.file_locks;
+
+        // Moving a CString does not move its buffer, so the pointers stay valid
+        Ok((
+            cr_options,
+            std::iter::once(image_path).chain(work_path).collect(),
+        ))
+    }
+
+    /// Checkpoint a running container with CRIU
+    pub fn container_checkpoint(
         context: *mut libcrun_context_t,
-        container: *mut libcrun_container_t,
         id: &str,
-        signal: c_int,
+        options: &CheckpointOptions,
     ) -> Result<(), CrunError> {
-        let id_cstr = CString::new(id).map_err(|_| CrunError {
-            code: -1,
-            message: "Invalid container ID".to_string(),
-        })?;
-
-            let mut err: *mut libcrun_error_t = ptr::null_mut();
-            let result =
-                libcrun_container_kill(context, container, id_cstr.as_ptr(), signal, &mut err);
-
-            if result != 0 {
-                if let Some(e) = CrunError::from_libcrun_error(err) {
-                    libcrun_error_release(&mut err);
-                    return Err(e);
+        let id_cstr = c_string(id, "Invalid container ID")?;
+        let (mut cr_options, _paths) = cr_options(options)?;
+
+        let mut err: *mut libcrun_error_t = ptr::null_mut();
+        let result =
+            libcrun_container_checkpoint(context, id_cstr.as_ptr(), &mut cr_options, &mut err);
+        check(result, err, || {
+            format!("Failed to checkpoint container: {}", id)
+        })
+    }
+
+    /// Restore a checkpoint written by [`container_checkpoint`] as container `id`
+    ///
+    /// `options.image_path` is the checkpoint; `leave_running` does not apply.
+    /// The ID may differ from the checkpointed container's. libcrun reads the
+    /// container's configuration from `config.json` in the working directory,
+    /// so the restore runs in a forked child that changes into `bundle` first
+    /// and leaves the caller's working directory alone.
+    pub fn container_restore(
+        context: *mut libcrun_context_t,
+        id: &str,
+        bundle: &Path,
+        options: &CheckpointOptions,
+    ) -> Result<(), CrunError> {
+        let id_cstr = c_string(id, "Invalid container ID")?;
+        let bundle_cstr = c_string(&bundle.to_string_lossy(), "Invalid bundle path")?;
+        let (mut cr_options, _paths) = cr_options(options)?;
+
+        let pid = unsafe { libc::fork() };
+        if pid == 0 {
+            // Child: restore from the bundle and report the result as exit status
+            unsafe {
+                if libc::chdir(bundle_cstr.as_ptr()) != 0 {
+                    libc::_exit(2);
                 }
-                return Err(CrunError {
-                    code: result,
-                    message: format!("Failed to kill container: {}", id),
-                });
+                let mut err: *mut libcrun_error_t = ptr::null_mut();
+                let ret =
+                    libcrun_container_restore(context, id_cstr.as_ptr(), &mut cr_options, &mut err);
+                libc::_exit(if ret < 0 { 1 } else { 0 });
             }
+        }
+        if pid < 0 {
+            return Err(CrunError {
+                code: -1,
+                message: format!(
+                    "Failed to fork for restore: {}",
+                    std::io::Error::last_os_error()
+                ),
+            });
+        }
 
-            if !err.is_null() {
-                libcrun_error_release(&mut err);
-            }
+        let mut status: c_int = 0;
+        unsafe { libc::waitpid(pid, &mut status, 0) };
+        match libc::WIFEXITED(status).then(|| libc::WEXITSTATUS(status)) {
+            Some(0) => Ok(()),
+            Some(2) => Err(CrunError {
+                code: -1,
+                message: format!("Bundle directory {} is not accessible", bundle.display()),
+            }),
+            _ => Err(CrunError {
+                code: -1,
+                message: format!("Failed to restore container: {}", id),
+            }),
+        }
+    }
 
-            Ok(())
+    /// Process to run in a container with [`container_exec`]
+    #[derive(Debug, Clone, Default)]
+    pub struct ExecProcess {
+        pub args: Vec<String>,
+        /// `KEY=VALUE` pairs; a default `PATH` is used when empty
+        pub env: Vec<String>,
+        /// Working directory, "/" when unset
+        pub cwd: Option<String>,
+        /// User ID inside the container, root when unset
+        pub uid: Option<u32>,
+        /// Group ID inside the container, root when unset
+        pub gid: Option<u32>,
+        /// Run under a pseudo-terminal; its output is returned as stdout
+        pub tty: bool,
     }
 
-    /// Delete a container
-    pub fn container_delete(
+    /// Output of a process run with [`container_exec`]
+    #[derive(Debug, Default)]
+    pub struct ExecOutput {
+        pub exit_code: i32,
+        pub stdout: Vec<u8>,
+        pub stderr: Vec<u8>,
+    }
+
+    /// Run a process in a running container and wait for it
+    ///
+    /// The process gets no input; its stdout and stderr are collected.
+    pub fn container_exec(
         context: *mut libcrun_context_t,
-        container: *mut libcrun_container_t,
         id: &str,
-    ) -> Result<(), CrunError> {
-        let id_cstr = CString::new(id).map_err(|_| CrunError {
-            code: -1,
-            message: "Invalid container ID".to_string(),
-        })?;
+        process: &ExecProcess,
+    ) -> Result<ExecOutput, CrunError> {
+        use std::io::Read;
 
-            let mut err: *mut libcrun_error_t = ptr::null_mut();
-            let result = libcrun_container_delete(context, container, id_cstr.as_ptr(), &mut err);
+        let mut child = container_exec_spawn(context, id, process, None)?;
+        drop(child.stdin.take());
+        let stderr_reader = child.stderr.take().map(|mut pipe| {
+            std::thread::spawn(move || {
+                let mut buf = Vec::new();
+                let _ = pipe.read_to_end(&mut buf);
+                buf
+            })
+        });
+        let mut stdout = Vec::new();
+        let _ = child.stdout.read_to_end(&mut stdout);
+        let stderr = stderr_reader
+            .and_then(|reader| reader.join().ok())
+            .unwrap_or_default();
 
-            if result != 0 {
-                if let Some(e) = CrunError::from_libcrun_error(err) {
-                    libcrun_error_release(&mut err);
-                    return Err(e);
+        child
+            .wait()
+            .map(|exit_code| ExecOutput {
+                exit_code,
+                stdout,
+                stderr: stderr.clone(),
+            })
+            .map_err(|mut e| {
+                let detail = String::from_utf8_lossy(&stderr);
+                if !detail.trim().is_empty() {
+                    e.message = format!("{}: {}", e.message, detail.trim());
                 }
-                return Err(CrunError {
-                    code: result,
-                    message: format!("Failed to delete container: {}", id),
-                });
+                e
+            })
+    }
+
+    /// A process started in a container with [`container_exec_spawn`]
+    pub struct ExecChild {
+        id: String,
+        pid: libc::pid_t,
+        spec_path: PathBuf,
+        status: std::fs::File,
+        /// Input of the process; dropping it closes the process's stdin,
+        /// except under a terminal, where it is the terminal's master end
+        /// (see [`resize_terminal`])
+        pub stdin: Option<std::fs::File>,
+        /// Output of the process, all of it when it runs under a terminal
+        pub stdout: std::fs::File,
+        /// Error output of the process, `None` under a terminal
+        pub stderr: Option<std::fs::File>,
+    }
+
+    impl ExecChild {
+        /// PID of the forked process running libcrun, which the command is
+        /// a child of
+        pub fn pid(&self) -> libc::pid_t {
+            self.pid
+        }
+
+        /// Wait for the process and return its exit code
+        ///
+        /// The output should be read to its end first, or a process writing
+        /// more than a pipe holds never exits.
+        pub fn wait(self) -> Result<i32, CrunError> {
+            use std::io::Read;
+
+            let mut status_pipe = self.status;
+            let mut status = [0u8; 4];
+            let status_read = status_pipe.read_exact(&mut status);
+            let mut wait_status: c_int = 0;
+            unsafe { libc::waitpid(self.pid, &mut wait_status, 0) };
+            let _ = std::fs::remove_file(&self.spec_path);
+
+            match status_read.map(|_| c_int::from_ne_bytes(status)) {
+                Ok(ret) if ret >= 0 => Ok(ret),
+                Ok(ret) => Err(CrunError {
+                    code: ret,
+                    message: format!("Failed to exec in container {}", self.id),
+                }),
+                Err(_) => Err(CrunError {
+                    code: -1,
+                    message: format!(
+                        "Exec in container {} ended without a result from libcrun",
+                        self.id
+                    ),
+                }),
             }
+        }
+    }
 
-            if !err.is_null() {
-                libcrun_error_release(&mut err);
+    /// Set the size of the terminal of a process started with
+    /// [`container_exec_spawn`], which libcrun copies to the container's
+    pub fn resize_terminal(terminal: &std::fs::File, rows: u16, cols: u16) -> std::io::Result<()> {
+        use std::os::unix::io::AsRawFd;
+
+        let size = libc::winsize {
+            ws_row: rows,
+            ws_col: cols,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        if unsafe { libc::ioctl(terminal.as_raw_fd(), libc::TIOCSWINSZ, &size) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// End the input of a terminal of [`container_exec_spawn`], as typing
+    /// its end-of-file character (^D) does
+    ///
+    /// The character only hands over a line being typed, so it is sent
+    /// twice when the input ended `mid_line`.
+    pub fn end_terminal_input(terminal: &std::fs::File, mid_line: bool) -> std::io::Result<()> {
+        use std::io::Write;
+        use std::os::unix::io::AsRawFd;
+
+        let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+        let eof = if unsafe { libc::tcgetattr(terminal.as_raw_fd(), &mut termios) } == 0 {
+            termios.c_cc[libc::VEOF]
+        } else {
+            0x04
+        };
+        let eofs = [eof; 2];
+        let mut terminal = terminal;
+        terminal.write_all(&eofs[..if mid_line { 2 } else { 1 }])
+    }
+
+    /// A pidfd for process `pid`, which becomes readable once it has exited
+    ///
+    /// Unlike `waitpid` this works for processes that are not children of
+    /// the caller, and does not reap them. Fails on kernels before 5.3.
+    pub fn pidfd_open(pid: u32) -> std::io::Result<std::os::fd::OwnedFd> {
+        use std::os::fd::FromRawFd;
+
+        let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(unsafe { std::os::fd::OwnedFd::from_raw_fd(fd as c_int) })
+    }
+
+    /// TCP ports listened on in the network namespace of process `pid`,
+    /// over IPv4 or IPv6, ascending
+    ///
+    /// Read from the kernel's socket tables in `/proc`, so the namespace
+    /// needs no tools of its own.
+    pub fn listening_ports(pid: u32) -> std::io::Result<Vec<u16>> {
+        /// `st` of a socket in the LISTEN state
+        const TCP_LISTEN: &str = "0A";
+
+        let mut ports = std::collections::BTreeSet::new();
+        for table in ["tcp", "tcp6"] {
+            let content = match std::fs::read_to_string(format!("/proc/{}/net/{}", pid, table)) {
+                Ok(content) => content,
+                // No IPv6 in the kernel
+                Err(e) if table == "tcp6" && e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            // sl local_address rem_address st ..., addresses as ADDR:PORT in hex
+            for line in content.lines().skip(1) {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                if fields.get(3) != Some(&TCP_LISTEN) {
+                    continue;
+                }
+                let port = fields[1]
+                    .rsplit_once(':')
+                    .and_then(|(_, port)| u16::from_str_radix(port, 16).ok());
+                ports.extend(port);
             }
+        }
+        Ok(ports.into_iter().collect())
+    }
+
+    /// Give a container a writable layer of its own over the image root
+    /// `lower`, kept in `dir`, and return the path to use as its root
+    ///
+    /// The layer is an overlay mounted at `dir/merged`, with its upper and
+    /// work directories next to it. Where overlayfs is missing or cannot
+    /// stack on the filesystem of `lower`, `lower` is copied to `dir/rootfs`
+    /// instead.
+    pub fn snapshot_rootfs(lower: &Path, dir: &Path) -> std::io::Result<PathBuf> {
+        use std::os::unix::ffi::OsStrExt;
 
-            Ok(())
+        let path = |p: &Path| {
+            CString::new(p.as_os_str().as_bytes())
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+        };
+        let upper = dir.join("upper");
+        let work = dir.join("work");
+        let merged = dir.join("merged");
+        for dir in [&upper, &work, &merged] {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        // Options are separated by commas and lower layers by colons
+        let layers = [lower, &upper, &work].map(|p| p.display().to_string());
+        let mount_error = if layers.iter().any(|p| p.contains([',', ':'])) {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "path not usable in overlay options",
+            )
+        } else {
+            let options = format!(
+                "lowerdir={},upperdir={},workdir={}",
+                layers[0], layers[1], layers[2]
+            );
+            let overlay = CString::new("overlay").unwrap();
+            let options = CString::new(options)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+            let target = path(&merged)?;
+            let result = unsafe {
+                libc::mount(
+                    overlay.as_ptr(),
+                    target.as_ptr(),
+                    overlay.as_ptr(),
+                    0,
+                    options.as_ptr() as *const libc::c_void,
+                )
+            };
+            if result == 0 {
+                return Ok(merged);
+            }
+            std::io::Error::last_os_error()
+        };
+
+        for dir in [&upper, &work, &merged] {
+            std::fs::remove_dir_all(dir)?;
+        }
+        let copy = dir.join("rootfs");
+        std::fs::create_dir_all(&copy)?;
+        let status = std::process::Command::new("cp")
+            .arg("-a")
+            .arg(lower.join("."))
+            .arg(&copy)
+            .status()?;
+        if !status.success() {
+            return Err(std::io::Error::other(format!(
+                "cannot mount an overlay ({}), and copying {} failed ({})",
+                mount_error,
+                lower.display(),
+                status
+            )));
+        }
+        Ok(copy)
     }
 
-    /// Get container state
-    pub fn container_state(
+    /// Unmount and remove a layer made by [`snapshot_rootfs`]
+    pub fn remove_snapshot(dir: &Path) -> std::io::Result<()> {
+        use std::os::unix::ffi::OsStrExt;
+
+        let merged = dir.join("merged");
+        if merged.exists() {
+            let target = CString::new(merged.as_os_str().as_bytes())
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+            if unsafe { libc::umount2(target.as_ptr(), libc::MNT_DETACH) } != 0 {
+                let e = std::io::Error::last_os_error();
+                // Not mounted, e.g. after a reboot
+                if e.raw_os_error() != Some(libc::EINVAL) {
+                    return Err(e);
+                }
+            }
+        }
+        match std::fs::remove_dir_all(dir) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Check that the working directory `dir` of a container is a directory
+    /// in its root `rootfs`, creating what is missing of it if `create`
+    ///
+    /// Symbolic links are followed as they would be inside the container,
+    /// so an absolute target is taken relative to `rootfs`. Fails with
+    /// `NotFound` if part of `dir` is missing and `create` is false, and with
+    /// `NotADirectory` if part of it is a file.
+    pub fn prepare_working_dir(rootfs: &Path, dir: &str, create: bool) -> std::io::Result<()> {
+        use std::collections::VecDeque;
+        use std::path::Component;
+
+        /// As many as the kernel follows resolving a path
+        const MAX_LINKS: usize = 40;
+
+        let mut remaining: VecDeque<PathBuf> = Path::new(dir)
+            .components()
+            .map(|c| PathBuf::from(c.as_os_str()))
+            .collect();
+        // Relative to `rootfs`
+        let mut resolved = PathBuf::new();
+        let mut links = 0;
+        while let Some(component) = remaining.pop_front() {
+            let name = match component.components().next() {
+                Some(Component::Normal(name)) => name.to_owned(),
+                Some(Component::ParentDir) => {
+                    resolved.pop();
+                    continue;
+                }
+                // The root, or `.`
+                _ => continue,
+            };
+            let candidate = resolved.join(&name);
+            let path = rootfs.join(&candidate);
+            match std::fs::symlink_metadata(&path) {
+                Ok(meta) if meta.file_type().is_symlink() => {
+                    links += 1;
+                    if links > MAX_LINKS {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidInput,
+                            format!("too many symbolic links resolving {}", dir),
+                        ));
+                    }
+                    let target = std::fs::read_link(&path)?;
+                    if target.is_absolute() {
+                        resolved = PathBuf::new();
+                    }
+                    for c in target.components().rev() {
+                        remaining.push_front(PathBuf::from(c.as_os_str()));
+                    }
+                }
+                Ok(meta) if meta.is_dir() => resolved = candidate,
+                Ok(_) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::NotADirectory,
+                        format!("/{} is not a directory", candidate.display()),
+                    ))
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound && create => {
+                    std::fs::create_dir(&path)?;
+                    resolved = candidate;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Start a process in a running container without waiting for it
+    ///
+    /// libcrun joins the container's namespaces and cgroup itself and returns
+    /// the exit status of the process. The process inherits the caller's
+    /// stdio, so the call is made from a forked child whose stdio are pipes,
+    /// or with `terminal` (rows, columns) and `process.tty` a pseudo-terminal
+    /// of that size which libcrun relays the container's terminal to.
+    /// libcrun's return value comes back over a separate pipe, so its
+    /// failures are not confused with exit codes of the command.
+    pub fn container_exec_spawn(
         context: *mut libcrun_context_t,
-        container: *mut libcrun_container_t,
         id: &str,
-    ) -> Result<(), CrunError> {
-        let id_cstr = CString::new(id).map_err(|_| CrunError {
-            code: -1,
-            message: "Invalid container ID".to_string(),
-        })?;
+        process: &ExecProcess,
+        terminal: Option<(u16, u16)>,
+    ) -> Result<ExecChild, CrunError> {
+        use std::os::unix::io::FromRawFd;
+        use std::sync::atomic::{AtomicU64, Ordering};
 
-            let mut err: *mut libcrun_error_t = ptr::null_mut();
-            let result = libcrun_container_state(context, container, id_cstr.as_ptr(), &mut err);
+        static NEXT_SPEC: AtomicU64 = AtomicU64::new(0);
 
-            if result != 0 {
-                if let Some(e) = CrunError::from_libcrun_error(err) {
-                    libcrun_error_release(&mut err);
-                    return Err(e);
+        let error = |message: String| CrunError { code: -1, message };
+        if process.args.is_empty() {
+            return Err(error("No command given".to_string()));
+        }
+        let id_cstr = CString::new(id).map_err(|_| error("Invalid container ID".to_string()))?;
+        let terminal = terminal.filter(|_| process.tty);
+
+        let env = if process.env.is_empty() {
+            vec!["PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin".to_string()]
+        } else {
+            process.env.clone()
+        };
+        let spec = serde_json::json!({
+            "terminal": process.tty,
+            "user": {
+                "uid": process.uid.unwrap_or(0),
+                "gid": process.gid.unwrap_or(0),
+            },
+            "args": process.args,
+            "env": env,
+            "cwd": process.cwd.as_deref().unwrap_or("/"),
+        });
+        let spec_path = std::env::temp_dir().join(format!(
+            "crun-exec-{}-{}.json",
+            std::process::id(),
+            NEXT_SPEC.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&spec_path, spec.to_string())
+            .map_err(|e| error(format!("Failed to write exec process spec: {}", e)))?;
+        let spec_cstr = CString::new(spec_path.to_string_lossy().into_owned())
+            .map_err(|_| error("Invalid exec process spec path".to_string()))?;
+
+        // (child end, parent end) of stdin, stdout and stderr, or of the
+        // terminal standing in for all three, then of the pipe libcrun's
+        // result comes back on
+        let mut ends: Vec<(c_int, c_int)> = Vec::with_capacity(4);
+        let close_all = |ends: &[(c_int, c_int)]| {
+            for &(child, parent) in ends {
+                unsafe {
+                    libc::close(child);
+                    libc::close(parent);
                 }
-                return Err(CrunError {
-                    code: result,
-                    message: format!("Failed to get container state: {}", id),
-                });
             }
+        };
+        if let Some((rows, cols)) = terminal {
+            let (mut master, mut slave) = (0, 0);
+            let size = libc::winsize {
+                ws_row: rows,
+                ws_col: cols,
+                ws_xpixel: 0,
+                ws_ypixel: 0,
+            };
+            let ret = unsafe {
+                libc::openpty(&mut master, &mut slave, ptr::null_mut(), ptr::null(), &size)
+            };
+            if ret != 0 {
+                let e = std::io::Error::last_os_error();
+                let _ = std::fs::remove_file(&spec_path);
+                return Err(error(format!("Failed to open a terminal for exec: {}", e)));
+            }
+            unsafe {
+                libc::fcntl(master, libc::F_SETFD, libc::FD_CLOEXEC);
+                libc::fcntl(slave, libc::F_SETFD, libc::FD_CLOEXEC);
+            }
+            ends.push((slave, master));
+        }
+        let pipes = if terminal.is_some() { 1 } else { 4 };
+        for i in 0..pipes {
+            let mut pipe = [0 as c_int; 2];
+            if unsafe { libc::pipe2(pipe.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+                let e = std::io::Error::last_os_error();
+                close_all(&ends);
+                let _ = std::fs::remove_file(&spec_path);
+                return Err(error(format!("Failed to create exec pipes: {}", e)));
+            }
+            // The child reads its stdin and writes everything else
+            let [read, write] = pipe;
+            ends.push(if terminal.is_none() && i == 0 {
+                (read, write)
+            } else {
+                (write, read)
+            });
+        }
+        let (stdin, stdout, stderr, status) = match ends[..] {
+            [tty, status] => (tty, tty, tty, status),
+            [stdin, stdout, stderr, status] => (stdin, stdout, stderr, status),
+            _ => unreachable!(),
+        };
 
-            if !err.is_null() {
-                libcrun_error_release(&mut err);
+        let pid = unsafe { libc::fork() };
+        if pid == 0 {
+            // Child: set up stdio and hand over to libcrun, then report its result
+            unsafe {
+                if terminal.is_some() {
+                    // Make the terminal the controlling one, so that resizing
+                    // it signals libcrun
+                    libc::setsid();
+                    libc::ioctl(stdin.0, libc::TIOCSCTTY, 0);
+                }
+                libc::dup2(stdin.0, 0);
+                libc::dup2(stdout.0, 1);
+                libc::dup2(stderr.0, 2);
+
+                let mut opts: libcrun_container_exec_options_s = std::mem::zeroed();
+                opts.struct_size = std::mem::size_of::<libcrun_container_exec_options_s>() as _;
+                opts.path = spec_cstr.as_ptr();
+                let mut err: *mut libcrun_error_t = ptr::null_mut();
+                let ret = libcrun_container_exec_with_options(
+                    context,
+                    id_cstr.as_ptr(),
+                    &mut opts,
+                    &mut err,
+                );
+
+                let result = ret.to_ne_bytes();
+                libc::write(status.0, result.as_ptr() as *const _, result.len());
+                libc::_exit(0);
             }
+        }
+
+        for &(child, _) in &ends {
+            unsafe { libc::close(child) };
+        }
+        if pid < 0 {
+            let e = std::io::Error::last_os_error();
+            for &(_, parent) in &ends {
+                unsafe { libc::close(parent) };
+            }
+            let _ = std::fs::remove_file(&spec_path);
+            return Err(error(format!("Failed to fork for exec: {}", e)));
+        }
+
+        let file = |fd: c_int| unsafe { std::fs::File::from_raw_fd(fd) };
+        let stdout_file = file(stdout.1);
+        let (stdin_file, stderr_file) = if terminal.is_some() {
+            (stdout_file.try_clone().ok(), None)
+        } else {
+            (Some(file(stdin.1)), Some(file(stderr.1)))
+        };
+        Ok(ExecChild {
+            id: id.to_string(),
+            pid,
+            spec_path,
+            status: file(status.1),
+            stdin: stdin_file,
+            stdout: stdout_file,
+            stderr: stderr_file,
+        })
+    }
+
+    /// Runtime state of a container, read from libcrun's state directory
+    #[derive(Debug, Clone, Default)]
+    pub struct State {
+        pub pid: Option<u32>,
+        /// The container's init process is alive
+        pub running: bool,
+        pub bundle: Option<String>,
+    }
+
+    /// Directories libcrun keeps the state of containers in, most likely first
+    const STATE_ROOTS: [&str; 2] = ["/run/crun", "/var/run/crun"];
+
+    /// State files libcrun may have written for `id`, most likely first
+    fn state_files(id: &str) -> Vec<PathBuf> {
+        STATE_ROOTS
+            .iter()
+            .flat_map(|root| {
+                let dir = PathBuf::from(root).join(id);
+                [dir.join("status"), dir.join("state.json"), dir]
+            })
+            .collect()
+    }
+
+    /// Read the state of a created container without a libcrun context
+    pub fn read_state(id: &str) -> Option<State> {
+        let json = state_files(id)
+            .into_iter()
+            .filter(|path| path.is_file())
+            .filter_map(|path| std::fs::read_to_string(path).ok())
+            .find_map(|content| serde_json::from_str::<serde_json::Value>(&content).ok())?;
+
+        // Try different possible JSON structures
+        let pid = json
+            .get("pid")
+            .or_else(|| json.get("init_process_pid"))
+            .or_else(|| json.get("state").and_then(|s| s.get("pid")))
+            .and_then(|pid| pid.as_u64())
+            .map(|pid| pid as u32);
+        let running = pid.is_some_and(|pid| unsafe { libc::kill(pid as libc::pid_t, 0) } == 0);
+        let bundle = json
+            .get("bundle")
+            .and_then(|b| b.as_str())
+            .map(str::to_string);
 
-            Ok(())
+        Some(State {
+            pid,
+            running,
+            bundle,
+        })
     }
 
     /// Get container PID by reading from state file
     /// This is a fallback method when container_state doesn't provide PID directly
     pub fn get_container_pid(id: &str) -> Option<u32> {
-        use std::fs;
-        use std::path::PathBuf;
-
-        // Try common locations for crun state files
-        let state_paths = vec![
-            PathBuf::from(format!("/run/crun/{}", id)),
-            PathBuf::from(format!("/run/crun/{}/state.json", id)),
-            PathBuf::from(format!("/var/run/crun/{}", id)),
-            PathBuf::from(format!("/var/run/crun/{}/state.json", id)),
-        ];
-
-        for path in state_paths {
-            if path.is_file() {
-                // Try to read and parse JSON state file
-                if let Ok(content) = fs::read_to_string(&path) {
-                    if let Ok(json) = serde_json::from_str::<serde_json::Value>(&content) {
-                        // Try different possible JSON structures
-                        if let Some(pid) = json
-                            .get("pid")
-                            .or_else(|| json.get("init_process_pid"))
-                            .or_else(|| json.get("state").and_then(|s| s.get("pid")))
-                        {
-                            if let Some(pid_num) = pid.as_u64() {
-                                return Some(pid_num as u32);
-                            }
-                        }
-                    }
-                }
-            } else if path.is_dir() {
-                // Try state.json in the directory
-                let state_file = path.join("state.json");
-                if state_file.exists() {
-                    if let Ok(content) = fs::read_to_string(&state_file) {
-                        if let Ok(json) = serde_json::from_str::<serde_json::Value>(&content) {
-                            if let Some(pid) = json
-                                .get("pid")
-                                .or_else(|| json.get("init_process_pid"))
-                                .or_else(|| json.get("state").and_then(|s| s.get("pid")))
-                            {
-                                if let Some(pid_num) = pid.as_u64() {
-                                    return Some(pid_num as u32);
-                                }
-                            }
-                        }
-                    }
-                }
+        read_state(id)?.pid
+    }
+
+    /// IDs of the containers libcrun has a state directory for
+    pub fn state_ids() -> Vec<String> {
+        let mut ids: Vec<String> = STATE_ROOTS
+            .iter()
+            .filter_map(|root| std::fs::read_dir(root).ok())
+            .flatten()
+            .flatten()
+            .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .collect();
+        // /var/run is usually a link to /run
+        ids.sort();
+        ids.dedup();
+        ids
+    }
+
+    /// libcrun's state directory for `id`, if there is one
+    pub fn state_dir(id: &str) -> Option<PathBuf> {
+        STATE_ROOTS
+            .iter()
+            .map(|root| Path::new(root).join(id))
+            .find(|dir| dir.is_dir())
+    }
+
+    /// Remove libcrun's state directory for `id`, for a container libcrun
+    /// cannot delete, e.g. one whose create failed part way
+    pub fn remove_state(id: &str) -> std::io::Result<()> {
+        for root in STATE_ROOTS {
+            match std::fs::remove_dir_all(Path::new(root).join(id)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                _ => {}
             }
         }
+        Ok(())
+    }
 
-        None
+    /// The cgroup directories of `id` that exist, where libcrun's default
+    /// cgroupfs manager puts them: one on cgroup v2, one per controller on v1
+    pub fn cgroup_dirs(id: &str) -> Vec<PathBuf> {
+        let root = Path::new("/sys/fs/cgroup");
+        let dirs = if root.join("cgroup.controllers").exists() {
+            vec![root.join(id)]
+        } else {
+            std::fs::read_dir(root)
+                .map(|entries| entries.flatten().map(|e| e.path().join(id)).collect())
+                .unwrap_or_default()
+        };
+        dirs.into_iter().filter(|dir| dir.is_dir()).collect()
+    }
+
+    /// Remove the cgroup directories of `id`, which fails while processes
+    /// are left in them
+    pub fn remove_cgroup(id: &str) -> std::io::Result<()> {
+        for dir in cgroup_dirs(id) {
+            std::fs::remove_dir(&dir)?;
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,231 @@
+//! Tests for the safe libcrun wrappers
+//!
+//! Argument validation and state parsing run everywhere. The container
+//! lifecycle test needs root, a real libcrun and a static busybox to build the
+//! container's rootfs from (`LIBCRUN_TEST_BUSYBOX`, or busybox on the PATH);
+//! without them it is skipped. Set `LIBCRUN_TEST_CHECKPOINT=1` to also
+//! checkpoint the container, which needs libcrun built with CRIU support.
+
+use libcrun_sys::safe::*;
+use std::path::{Path, PathBuf};
+
+/// Whether an ELF binary has no program interpreter
+fn is_static(binary: &[u8]) -> bool {
+    binary.starts_with(b"\x7fELF") && !binary.windows(8).any(|w| w == b".interp\0")
+}
+
+fn find_busybox() -> Option<PathBuf> {
+    let candidates = std::env::var_os("LIBCRUN_TEST_BUSYBOX")
+        .map(|p| vec![PathBuf::from(p)])
+        .unwrap_or_else(|| {
+            ["/bin/busybox", "/usr/bin/busybox", "/usr/sbin/busybox"]
+                .iter()
+                .map(PathBuf::from)
+                .collect()
+        });
+    candidates
+        .into_iter()
+        .find(|path| std::fs::read(path).is_ok_and(|binary| is_static(&binary)))
+}
+
+/// Minimal OCI config running `sleep` as the container's init process
+fn oci_config(rootfs: &Path) -> String {
+    serde_json::json!({
+        "ociVersion": "1.0.2",
+        "process": {
+            "terminal": false,
+            "user": {"uid": 0, "gid": 0},
+            "args": ["/bin/sleep", "300"],
+            "env": ["PATH=/bin"],
+            "cwd": "/",
+        },
+        "root": {"path": rootfs, "readonly": false},
+        "hostname": "safe-test",
+        "mounts": [
+            {"destination": "/proc", "type": "proc", "source": "proc"},
+            {"destination": "/dev", "type": "tmpfs", "source": "tmpfs",
+             "options": ["nosuid", "strictatime", "mode=755", "size=65536k"]},
+        ],
+        "linux": {
+            "resources": {"pids": {"limit": 128}},
+            "namespaces": [
+                {"type": "pid"}, {"type": "mount"}, {"type": "ipc"}, {"type": "uts"},
+            ],
+        },
+    })
+    .to_string()
+}
+
+/// A container with a throwaway busybox rootfs, killed and deleted on drop
+struct Harness {
+    context: Context,
+    container: Container,
+    id: String,
+    dir: PathBuf,
+}
+
+impl Harness {
+    /// `None` when the environment can't run containers
+    fn new(name: &str) -> Option<Self> {
+        if unsafe { libc::geteuid() } != 0 {
+            eprintln!("skipping: containers need root");
+            return None;
+        }
+        let Ok(context) = Context::new() else {
+            eprintln!("skipping: libcrun is not available");
+            return None;
+        };
+        let Some(busybox) = find_busybox() else {
+            eprintln!("skipping: no static busybox found");
+            return None;
+        };
+
+        let id = format!("{}-{}", name, std::process::id());
+        let dir = std::env::temp_dir().join(format!("libcrun-sys-{}", id));
+        let bin = dir.join("rootfs/bin");
+        std::fs::create_dir_all(&bin).ok()?;
+        for mountpoint in ["proc", "dev", "tmp"] {
+            std::fs::create_dir_all(dir.join("rootfs").join(mountpoint)).ok()?;
+        }
+        std::fs::copy(&busybox, bin.join("busybox")).ok()?;
+        for applet in ["sh", "sleep", "true", "echo", "cat"] {
+            std::os::unix::fs::symlink("busybox", bin.join(applet)).ok()?;
+        }
+
+        let container = Container::load(&oci_config(&dir.join("rootfs"))).ok()?;
+        Some(Self {
+            context,
+            container,
+            id,
+            dir,
+        })
+    }
+
+    fn exec(&self, args: &[&str]) -> Result<ExecOutput, CrunError> {
+        let process = ExecProcess {
+            args: args.iter().map(|a| a.to_string()).collect(),
+            ..Default::default()
+        };
+        container_exec(self.context.as_ptr(), &self.id, &process)
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        let (ctx, container) = (self.context.as_ptr(), self.container.as_ptr());
+        let _ = container_kill(ctx, container, &self.id, libc::SIGKILL);
+        let _ = container_delete(ctx, container, &self.id);
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[test]
+fn test_rejects_invalid_arguments() {
+    let ctx = std::ptr::null_mut();
+    let container = std::ptr::null_mut();
+
+    let err = container_create(ctx, container, "bad\0id").unwrap_err();
+    assert_eq!(err.message, "Invalid container ID");
+    assert!(container_start(ctx, container, "bad\0id").is_err());
+    assert!(container_kill(ctx, container, "bad\0id", libc::SIGTERM).is_err());
+    assert!(container_delete(ctx, container, "bad\0id").is_err());
+    assert!(container_pause(ctx, "bad\0id").is_err());
+    assert!(container_resume(ctx, "bad\0id").is_err());
+
+    let err = container_update(ctx, "c1", "{\"pids\":\0}").unwrap_err();
+    assert_eq!(err.message, "Invalid resources JSON");
+    assert!(container_load_from_memory("{\0}").is_err());
+    assert!(Container::load("{\0}").is_err());
+
+    let err = container_exec(ctx, "c1", &ExecProcess::default()).unwrap_err();
+    assert_eq!(err.message, "No command given");
+
+    let options = CheckpointOptions {
+        image_path: PathBuf::from("/tmp/bad\0path"),
+        ..Default::default()
+    };
+    let err = container_checkpoint(ctx, "c1", &options).unwrap_err();
+    assert_eq!(err.message, "Invalid image path");
+    let err = container_restore(ctx, "c1", Path::new("/tmp"), &options).unwrap_err();
+    assert_eq!(err.message, "Invalid image path");
+    let err = container_restore(
+        ctx,
+        "c1",
+        Path::new("/tmp/bad\0bundle"),
+        &CheckpointOptions::default(),
+    )
+    .unwrap_err();
+    assert_eq!(err.message, "Invalid bundle path");
+}
+
+#[test]
+fn test_read_state() {
+    assert!(read_state("libcrun-sys-test-no-such-container").is_none());
+    assert_eq!(
+        get_container_pid("libcrun-sys-test-no-such-container"),
+        None
+    );
+}
+
+#[test]
+fn test_container_lifecycle() {
+    let Some(harness) = Harness::new("lifecycle") else {
+        return;
+    };
+    let (ctx, container, id) = (
+        harness.context.as_ptr(),
+        harness.container.as_ptr(),
+        harness.id.as_str(),
+    );
+
+    container_create(ctx, container, id).unwrap();
+    container_start(ctx, container, id).unwrap();
+    let state = read_state(id).expect("state after start");
+    assert!(state.running);
+    assert!(state.pid.is_some());
+
+    // Exit codes and output come back separately from libcrun failures
+    let output = harness.exec(&["echo", "hello"]).unwrap();
+    assert_eq!(
+        (output.exit_code, output.stdout.as_slice()),
+        (0, &b"hello\n"[..])
+    );
+    let output = harness
+        .exec(&["sh", "-c", "echo oops >&2; exit 3"])
+        .unwrap();
+    assert_eq!(
+        (output.exit_code, output.stderr.as_slice()),
+        (3, &b"oops\n"[..])
+    );
+    // Depending on the libcrun version a failed execve is an error or exit code 127
+    let missing = harness.exec(&["/no/such/binary"]);
+    assert!(!missing.is_ok_and(|o| o.exit_code == 0));
+
+    container_pause(ctx, id).unwrap();
+    container_resume(ctx, id).unwrap();
+    assert_eq!(harness.exec(&["true"]).unwrap().exit_code, 0);
+
+    container_update(ctx, id, r#"{"pids": {"limit": 64}}"#).unwrap();
+
+    if std::env::var_os("LIBCRUN_TEST_CHECKPOINT").is_some() {
+        let options = CheckpointOptions {
+            image_path: harness.dir.join("checkpoint"),
+            leave_running: true,
+            ..Default::default()
+        };
+        container_checkpoint(ctx, id, &options).unwrap();
+        assert!(read_state(id).unwrap().running);
+    }
+
+    container_kill(ctx, container, id, libc::SIGKILL).unwrap();
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+    while read_state(id).is_some_and(|s| s.running) {
+        assert!(
+            std::time::Instant::now() < deadline,
+            "container did not exit"
+        );
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+    container_delete(ctx, container, id).unwrap();
+    assert!(read_state(id).is_none());
+}
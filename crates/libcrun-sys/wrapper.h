@@ -11,6 +11,9 @@ extern "C" {
 #include <libcrun/context.h>
 #include <libcrun/error.h>
 #else
+#include <stdbool.h>
+#include <stddef.h>
+
 // Fallback: forward declarations when headers not available
 // These match the actual libcrun API structure
 
@@ -65,6 +68,68 @@ int libcrun_container_state(
 
 void libcrun_container_free(libcrun_container_t *container);
 
+int libcrun_container_pause(
+    libcrun_context_t *context,
+    const char *id,
+    libcrun_error_t **err
+);
+
+int libcrun_container_unpause(
+    libcrun_context_t *context,
+    const char *id,
+    libcrun_error_t **err
+);
+
+// Apply new resource limits, `content` is a JSON `linux.resources` object
+int libcrun_container_update(
+    libcrun_context_t *context,
+    const char *id,
+    const char *content,
+    size_t len,
+    libcrun_error_t **err
+);
+
+typedef struct libcrun_checkpoint_restore_s {
+    const char *image_path;
+    const char *work_path;
+    bool leave_running;
+    bool tcp_established;
+    bool shell_job;
+    bool ext_unix_sk;
+    bool file_locks;
+} libcrun_checkpoint_restore_t;
+
+int libcrun_container_checkpoint(
+    libcrun_context_t *context,
+    const char *id,
+    libcrun_checkpoint_restore_t *cr_options,
+    libcrun_error_t **err
+);
+
+// Restore a checkpoint as container `id`, reading its config.json from the
+// working directory
+int libcrun_container_restore(
+    libcrun_context_t *context,
+    const char *id,
+    libcrun_checkpoint_restore_t *cr_options,
+    libcrun_error_t **err
+);
+
+// Run an additional process in a running container
+struct libcrun_container_exec_options_s {
+    size_t struct_size;
+    void *process;
+    const char *path;
+    const char *cgroup;
+};
+
+int libcrun_container_exec_with_options(
+    libcrun_context_t *context,
+    const char *id,
+    struct libcrun_container_exec_options_s *opts,
+    libcrun_error_t **err
+);
+
 // Context operations
 libcrun_context_t* libcrun_context_new(libcrun_error_t **err);
 void libcrun_context_free(libcrun_context_t *context);
@@ -0,0 +1,39 @@
+#!/bin/bash
+# End-to-end latency of crun-shim commands, measured with hyperfine
+# Usage: ./scripts/bench-cli.sh [image]   (default: docker.io/library/busybox:latest)
+#
+# Complements the Criterion benchmarks (make bench) with what users see,
+# including process startup and, on macOS, the connection to the VM.
+# Results are written to target/bench-cli.json.
+
+set -e
+
+SCRIPT_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")" && pwd)"
+PROJECT_ROOT="$(cd "$SCRIPT_DIR/.." && pwd)"
+IMAGE="${1:-docker.io/library/busybox:latest}"
+CRUN_SHIM="${CRUN_SHIM:-$PROJECT_ROOT/target/release/crun-shim}"
+
+if ! command -v hyperfine >/dev/null; then
+    echo "hyperfine not found, see https://github.com/sharkdp/hyperfine#installation" >&2
+    exit 1
+fi
+if [ ! -x "$CRUN_SHIM" ]; then
+    cargo build --release --package libcrun-shim-cli --manifest-path "$PROJECT_ROOT/Cargo.toml"
+fi
+
+"$CRUN_SHIM" pull --quiet "$IMAGE"
+cleanup() {
+    "$CRUN_SHIM" rm --force bench-run >/dev/null 2>&1 || true
+    "$CRUN_SHIM" rm --force bench-exec >/dev/null 2>&1 || true
+}
+trap cleanup EXIT
+cleanup
+"$CRUN_SHIM" run "$IMAGE" --name bench-exec -- sleep 3600 >/dev/null
+
+hyperfine --warmup 3 --export-json "$PROJECT_ROOT/target/bench-cli.json" \
+    --prepare "$CRUN_SHIM rm --force bench-run >/dev/null 2>&1 || true" \
+    --command-name "run (create and start)" "$CRUN_SHIM run $IMAGE --name bench-run -- sleep 3600" \
+    --prepare "true" \
+    --command-name "exec round trip" "$CRUN_SHIM exec bench-exec true" \
+    --prepare "true" \
+    --command-name "ps" "$CRUN_SHIM ps -a"
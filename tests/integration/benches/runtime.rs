@@ -0,0 +1,206 @@
+//! Latency of the common container operations, on both backends
+//!
+//! `agent` talks to a real agent over its socket, as the macOS runtime does
+//! inside the VM; `runtime` goes through `ContainerRuntime`, which is libcrun
+//! itself on Linux and the VM on macOS. Both need what the lifecycle suite
+//! needs and are skipped without it. `pull` measures image pull throughput
+//! when `LIBCRUN_BENCH_PULL` names an image (e.g.
+//! `docker.io/library/busybox:latest`).
+//!
+//! Run with `make bench`; the budgets CI enforces on the same operations are
+//! in `libcrun_shim_test_support::perf`.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use libcrun_shim::{ContainerConfig, ContainerRuntime, ImageStore};
+use libcrun_shim_test_support::perf::{self, FLEET_SIZE, IDLE};
+use libcrun_shim_test_support::*;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+fn skipped(group: &str) -> bool {
+    match skip_reason() {
+        Some(reason) => {
+            eprintln!("skipping {} benchmarks: {}", group, reason);
+            true
+        }
+        None => false,
+    }
+}
+
+fn bench_agent(c: &mut Criterion) {
+    if skipped("agent") {
+        return;
+    }
+    let mut agent = TestAgent::spawn().unwrap();
+    let rootfs = busybox_rootfs(&agent.dir().join("rootfs")).unwrap();
+    let mut group = c.benchmark_group("agent");
+
+    let mut n = 0;
+    group.bench_function("create_start", |b| {
+        b.iter_custom(|iters| {
+            let mut total = Duration::ZERO;
+            for _ in 0..iters {
+                n += 1;
+                let id = format!("bench-start-{}", n);
+                total += perf::create_started(&mut agent, &id, &rootfs).unwrap();
+                perf::remove(&mut agent, &id).unwrap();
+            }
+            total
+        })
+    });
+
+    let fleet = perf::start_fleet(&mut agent, "bench", &rootfs).unwrap();
+    group.bench_function("exec", |b| {
+        b.iter_custom(|iters| {
+            (0..iters)
+                .map(|_| perf::exec_true(&mut agent, &fleet[0]).unwrap())
+                .sum()
+        })
+    });
+    group.bench_function(format!("list_{}", FLEET_SIZE), |b| {
+        b.iter_custom(|iters| {
+            (0..iters)
+                .map(|_| perf::list(&mut agent, FLEET_SIZE).unwrap())
+                .sum()
+        })
+    });
+    group.bench_function(format!("all_metrics_{}", FLEET_SIZE), |b| {
+        b.iter_custom(|iters| {
+            (0..iters)
+                .map(|_| perf::all_metrics(&mut agent, FLEET_SIZE).unwrap())
+                .sum()
+        })
+    });
+    group.finish();
+
+    for id in &fleet {
+        perf::remove(&mut agent, id).unwrap();
+    }
+}
+
+/// Rootfs for the runtime's containers: the image `LIBCRUN_TEST_PULL` names,
+/// or a busybox one
+fn runtime_rootfs(dir: &Path, tokio: &tokio::runtime::Runtime) -> Option<PathBuf> {
+    let Some(reference) = std::env::var("LIBCRUN_TEST_PULL").ok() else {
+        return busybox_rootfs(&dir.join("rootfs")).ok();
+    };
+    let mut store = ImageStore::new(dir.join("images")).ok()?;
+    let image = tokio.block_on(store.pull(&reference, None)).ok()?;
+    store.get_rootfs(&image.id)
+}
+
+fn config(rootfs: &Path) -> ContainerConfig {
+    ContainerConfig {
+        rootfs: rootfs.to_path_buf(),
+        command: IDLE.iter().map(|s| s.to_string()).collect(),
+        working_dir: "/".to_string(),
+        ..Default::default()
+    }
+}
+
+async fn remove(runtime: &ContainerRuntime, id: &str) {
+    runtime.stop(id).await.unwrap();
+    runtime.delete(id).await.unwrap();
+}
+
+fn bench_runtime(c: &mut Criterion) {
+    // On macOS the runtime boots its VM instead
+    if cfg!(target_os = "linux") && skipped("runtime") {
+        return;
+    }
+    let tokio = tokio::runtime::Runtime::new().unwrap();
+    let runtime = match tokio.block_on(ContainerRuntime::new()) {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("skipping runtime benchmarks: {}", e);
+            return;
+        }
+    };
+    let dir = std::env::temp_dir().join(format!("libcrun-shim-bench-{}", std::process::id()));
+    let Some(rootfs) = runtime_rootfs(&dir, &tokio) else {
+        eprintln!("skipping runtime benchmarks: no rootfs (set LIBCRUN_TEST_PULL)");
+        return;
+    };
+    let mut group = c.benchmark_group("runtime");
+
+    group.bench_function("create_start", |b| {
+        b.iter_custom(|iters| {
+            tokio.block_on(async {
+                let mut total = Duration::ZERO;
+                for _ in 0..iters {
+                    let started = Instant::now();
+                    let id = runtime.create(config(&rootfs)).await.unwrap();
+                    runtime.start(&id).await.unwrap();
+                    total += started.elapsed();
+                    remove(&runtime, &id).await;
+                }
+                total
+            })
+        })
+    });
+
+    let fleet = tokio.block_on(async {
+        let mut fleet = Vec::with_capacity(FLEET_SIZE);
+        for _ in 0..FLEET_SIZE {
+            let id = runtime.create(config(&rootfs)).await.unwrap();
+            runtime.start(&id).await.unwrap();
+            fleet.push(id);
+        }
+        fleet
+    });
+    group.bench_function("exec", |b| {
+        b.iter(|| {
+            tokio
+                .block_on(runtime.exec(&fleet[0], vec!["true".to_string()]))
+                .unwrap()
+        })
+    });
+    group.bench_function(format!("list_{}", FLEET_SIZE), |b| {
+        b.iter(|| tokio.block_on(runtime.list()).unwrap())
+    });
+    group.bench_function(format!("all_metrics_{}", FLEET_SIZE), |b| {
+        b.iter(|| tokio.block_on(runtime.all_metrics()).unwrap())
+    });
+    group.finish();
+
+    tokio.block_on(async {
+        for id in &fleet {
+            remove(&runtime, id).await;
+        }
+    });
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+fn bench_pull(c: &mut Criterion) {
+    let Some(reference) = std::env::var("LIBCRUN_BENCH_PULL").ok() else {
+        eprintln!("skipping pull benchmarks: LIBCRUN_BENCH_PULL is not set");
+        return;
+    };
+    let tokio = tokio::runtime::Runtime::new().unwrap();
+    let dir = std::env::temp_dir().join(format!("libcrun-shim-bench-pull-{}", std::process::id()));
+    let store = |n: usize| ImageStore::new(dir.join(n.to_string())).unwrap();
+    let size = tokio
+        .block_on(store(0).pull(&reference, None))
+        .unwrap()
+        .size;
+
+    let mut group = c.benchmark_group("pull");
+    group.sample_size(10).throughput(Throughput::Bytes(size));
+    let mut n = 0;
+    group.bench_function("image", |b| {
+        // Every pull goes to an empty store, so nothing is cached
+        b.iter_batched(
+            || {
+                n += 1;
+                store(n)
+            },
+            |mut store| tokio.block_on(store.pull(&reference, None)).unwrap(),
+            BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+criterion_group!(benches, bench_agent, bench_runtime, bench_pull);
+criterion_main!(benches);
@@ -0,0 +1,64 @@
+//! Performance budgets of the agent's common operations
+//!
+//! Times what `benches/runtime.rs` benchmarks, over a few samples, and fails
+//! when a median is over its budget in `libcrun_shim_test_support::perf`.
+//! Needs the same environment as the lifecycle suite and skips without it.
+
+use libcrun_shim_test_support::perf::{self, Budget};
+use libcrun_shim_test_support::*;
+
+const SAMPLES: usize = 5;
+
+fn check(budget: Budget, samples: &[std::time::Duration], agent: &TestAgent) {
+    match budget.check(samples) {
+        Ok(median) => eprintln!(
+            "{}: {:?} (budget {:?})",
+            budget.operation,
+            median,
+            budget.limit()
+        ),
+        Err(e) => panic!("{}\n{}", e, agent.log()),
+    }
+}
+
+#[test]
+fn test_performance_budgets() {
+    if let Some(reason) = skip_reason() {
+        assert!(
+            std::env::var_os("LIBCRUN_TEST_REQUIRE").is_none(),
+            "cannot run containers: {}",
+            reason
+        );
+        eprintln!("skipping: {}", reason);
+        return;
+    }
+    let mut agent = TestAgent::spawn().unwrap();
+    let rootfs = busybox_rootfs(&agent.dir().join("rootfs")).unwrap();
+    let prefix = format!("budget-{}", std::process::id());
+
+    let mut samples = Vec::new();
+    for n in 0..SAMPLES {
+        let id = format!("{}-start-{}", prefix, n);
+        samples.push(perf::create_started(&mut agent, &id, &rootfs).unwrap());
+        perf::remove(&mut agent, &id).unwrap();
+    }
+    check(perf::CREATE_START, &samples, &agent);
+
+    let fleet = perf::start_fleet(&mut agent, &prefix, &rootfs).unwrap();
+    let samples: Vec<_> = (0..SAMPLES)
+        .map(|_| perf::exec_true(&mut agent, &fleet[0]).unwrap())
+        .collect();
+    check(perf::EXEC, &samples, &agent);
+    let samples: Vec<_> = (0..SAMPLES)
+        .map(|_| perf::list(&mut agent, fleet.len()).unwrap())
+        .collect();
+    check(perf::LIST, &samples, &agent);
+    let samples: Vec<_> = (0..SAMPLES)
+        .map(|_| perf::all_metrics(&mut agent, fleet.len()).unwrap())
+        .collect();
+    check(perf::ALL_METRICS, &samples, &agent);
+
+    for id in &fleet {
+        perf::remove(&mut agent, id).unwrap();
+    }
+}
@@ -0,0 +1,43 @@
+//! One agent per pid file
+//!
+//! Only needs the agent binary, not a working container runtime.
+
+use libcrun_shim_test_support::*;
+
+#[test]
+fn test_second_agent() {
+    let binary = match agent_binary() {
+        Ok(binary) => binary,
+        Err(e) => {
+            eprintln!("skipping: {}", e);
+            return;
+        }
+    };
+    let mut first = TestAgent::spawn().unwrap();
+    let pid_file = first.pid_file().to_string_lossy().into_owned();
+
+    // A second agent on the same pid file exits and says why
+    let err = match TestAgent::spawn_with(&binary, &["--pid-file", &pid_file]) {
+        Ok(_) => panic!("second agent started next to the first"),
+        Err(e) => e.to_string(),
+    };
+    assert!(err.contains("is already running"), "{}", err);
+    assert!(err.contains("--replace"), "{}", err);
+    assert!(matches!(first.call(Request::List), Ok(Response::List(_))));
+
+    // With --replace it drains the first one and takes over
+    let mut second =
+        TestAgent::spawn_with(&binary, &["--pid-file", &pid_file, "--replace"]).unwrap();
+    assert!(
+        matches!(second.call(Request::List), Ok(Response::List(_))),
+        "{}",
+        second.log()
+    );
+    assert!(first.call(Request::List).is_err());
+    let owner = std::fs::read_to_string(&pid_file).unwrap();
+    assert!(
+        owner.ends_with(&format!("{}\n", second.socket_path().display())),
+        "{}",
+        owner
+    );
+}
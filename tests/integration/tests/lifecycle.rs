@@ -0,0 +1,260 @@
+//! Full container lifecycle against a real agent
+//!
+//! Needs root, libcrun, a built agent and a static busybox; see
+//! `libcrun_shim_test_support::skip_reason`. CI runs these in a privileged
+//! container with `make test-integration`. Set `LIBCRUN_TEST_PULL` to an image
+//! reference (e.g. `docker.io/library/busybox:latest`) to pull the rootfs from
+//! a registry instead of building it from the local busybox, and
+//! `LIBCRUN_TEST_REQUIRE=1` to fail rather than skip when the environment is
+//! missing something.
+
+use libcrun_shim_test_support::*;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Init process that logs once and exits cleanly on SIGTERM
+const INIT: &[&str] = &[
+    "sh",
+    "-c",
+    "trap 'exit 0' TERM; echo ready; while :; do sleep 1; done",
+];
+
+fn call(agent: &mut TestAgent, request: Request) -> Response {
+    match agent.call(request) {
+        Ok(response) => response,
+        Err(e) => panic!("agent call failed: {}\n{}", e, agent.log()),
+    }
+}
+
+fn rootfs(agent: &TestAgent) -> PathBuf {
+    let Some(reference) = std::env::var("LIBCRUN_TEST_PULL").ok() else {
+        return busybox_rootfs(&agent.dir().join("rootfs")).unwrap();
+    };
+    let mut store = libcrun_shim::ImageStore::new(agent.dir().join("images")).unwrap();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let image = runtime.block_on(store.pull(&reference, None)).unwrap();
+    store
+        .get_rootfs(&image.id)
+        .expect("pulled image has a rootfs")
+}
+
+/// Run a copy on a connection of its own, `transfer` sending or receiving
+/// the archive, and return the frame the agent ends it with
+fn copy(
+    agent: &TestAgent,
+    id: &str,
+    path: &str,
+    direction: CopyDirection,
+    transfer: impl FnOnce(&mut UnixStream),
+) -> CopyFrame {
+    let mut stream = UnixStream::connect(agent.socket_path()).unwrap();
+    let request = Request::Copy(CopyRequest {
+        id: id.to_string(),
+        path: path.to_string(),
+        direction,
+        options: CopyOptionsProto::default(),
+    });
+    write_frame(&mut stream, &serialize_request(&request).unwrap()).unwrap();
+    let frame = read_frame(&mut stream).unwrap().expect("copy response");
+    match deserialize_response(&frame).unwrap() {
+        Response::CopyStarted => {}
+        other => panic!("copy: {:?}\n{}", other, agent.log()),
+    }
+    transfer(&mut stream);
+    archive::receive_frame(&mut stream)
+        .unwrap()
+        .expect("copy result")
+}
+
+fn exec_request(id: &str, command: &[&str]) -> Request {
+    Request::Exec(ExecRequest {
+        id: id.to_string(),
+        command: command.iter().map(|arg| arg.to_string()).collect(),
+        env: Vec::new(),
+        working_dir: None,
+        user: "integration".to_string(),
+        tty: false,
+        uid: None,
+        gid: None,
+    })
+}
+
+fn logs_request(id: &str) -> Request {
+    Request::Logs(LogsRequest {
+        id: id.to_string(),
+        tail: 0,
+        since: 0,
+        timestamps: false,
+        max_bytes: 0,
+        stdout_offset: None,
+        stderr_offset: None,
+        until: 0,
+    })
+}
+
+#[test]
+fn test_container_lifecycle() {
+    if let Some(reason) = skip_reason() {
+        assert!(
+            std::env::var_os("LIBCRUN_TEST_REQUIRE").is_none(),
+            "cannot run containers: {}",
+            reason
+        );
+        eprintln!("skipping: {}", reason);
+        return;
+    }
+    let mut agent = TestAgent::spawn().unwrap();
+    let rootfs = rootfs(&agent);
+    let id = format!("lifecycle-{}", std::process::id());
+
+    let mut request = create_request(&id, &rootfs, INIT);
+    request.resources = ResourceLimitsProto {
+        memory: Some(64 << 20),
+        pids: Some(64),
+        ..Default::default()
+    };
+    match call(&mut agent, Request::Create(request)) {
+        Response::Created(created) => assert_eq!(created, id),
+        other => panic!("create: {:?}\n{}", other, agent.log()),
+    }
+    match call(&mut agent, Request::Start(id.clone())) {
+        Response::Started => {}
+        other => panic!("start: {:?}\n{}", other, agent.log()),
+    }
+    match call(&mut agent, Request::List) {
+        Response::List(containers) => {
+            let info = containers.iter().find(|c| c.id == id).expect("listed");
+            assert_eq!(info.status, ContainerStatus::Running);
+            assert!(info.pid.is_some());
+        }
+        other => panic!("list: {:?}", other),
+    }
+
+    match call(&mut agent, exec_request(&id, &["echo", "hello"])) {
+        Response::Exec(result) => {
+            assert_eq!(result.exit_code, 0, "stderr: {}", result.stderr);
+            assert_eq!(result.stdout, "hello\n");
+        }
+        other => panic!("exec: {:?}\n{}", other, agent.log()),
+    }
+    match call(&mut agent, Request::ExecSessions(id.clone())) {
+        Response::ExecSessions(sessions) => {
+            assert_eq!(sessions.len(), 1);
+            assert_eq!(sessions[0].user, "integration");
+        }
+        other => panic!("exec sessions: {:?}", other),
+    }
+
+    // Files copied in are seen by the container, and come back out the same
+    let site = agent.dir().join("copy-in/site");
+    std::fs::create_dir_all(&site).unwrap();
+    std::fs::write(site.join("index.html"), "welcome\n").unwrap();
+    let options = CopyOptionsProto::default();
+    let done = copy(&agent, &id, "/tmp", CopyDirection::In, |stream| {
+        let mut writer = archive::FrameWriter::new(stream);
+        archive::pack(Path::new("/"), &site, &mut writer, &options, |_| {}).unwrap();
+        writer.finish().unwrap();
+    });
+    assert!(
+        matches!(done, CopyFrame::Done(stats) if stats.entries == 2),
+        "copy in: {:?}\n{}",
+        done,
+        agent.log()
+    );
+    match call(
+        &mut agent,
+        exec_request(&id, &["cat", "/tmp/site/index.html"]),
+    ) {
+        Response::Exec(result) => assert_eq!(result.stdout, "welcome\n"),
+        other => panic!("exec: {:?}\n{}", other, agent.log()),
+    }
+    let copied = agent.dir().join("copy-out");
+    std::fs::create_dir_all(&copied).unwrap();
+    let done = copy(&agent, &id, "/tmp/site", CopyDirection::Out, |stream| {
+        let Some(CopyFrame::Total(total)) = archive::receive_frame(stream).unwrap() else {
+            panic!("copy out sent no total");
+        };
+        let target = archive::Target::resolve(Path::new("/"), &copied).unwrap();
+        let mut reader = archive::FrameReader::new(stream);
+        let stats = archive::unpack(&mut reader, &target, &options, |_| {}).unwrap();
+        reader.finish().unwrap();
+        assert_eq!(stats, total);
+    });
+    assert!(matches!(done, CopyFrame::Done(_)), "copy out: {:?}", done);
+    assert_eq!(
+        std::fs::read_to_string(copied.join("site/index.html")).unwrap(),
+        "welcome\n"
+    );
+
+    let logs = wait_for(Duration::from_secs(10), || {
+        match call(&mut agent, logs_request(&id)) {
+            Response::Logs(logs)
+                if logs
+                    .entries
+                    .iter()
+                    .any(|e| String::from_utf8_lossy(&e.payload).contains("ready")) =>
+            {
+                Some(logs)
+            }
+            _ => None,
+        }
+    });
+    assert!(logs.is_some(), "init output never logged\n{}", agent.log());
+
+    match call(&mut agent, Request::Metrics(id.clone())) {
+        Response::Metrics(metrics) => {
+            assert_eq!(metrics.id, id);
+            assert!(metrics.timestamp > 0);
+            // Limits are enforced by the container's cgroup
+            assert_eq!(metrics.memory.limit, 64 << 20);
+            assert_eq!(metrics.pids.limit, 64);
+        }
+        other => panic!("metrics: {:?}", other),
+    }
+
+    match call(&mut agent, Request::Stop(id.clone())) {
+        Response::Stopped => {}
+        other => panic!("stop: {:?}\n{}", other, agent.log()),
+    }
+    // Delete succeeds once the init process has handled SIGTERM and exited
+    let deleted = wait_for(Duration::from_secs(10), || {
+        matches!(
+            call(&mut agent, Request::Delete(id.clone())),
+            Response::Deleted
+        )
+        .then_some(())
+    });
+    assert!(deleted.is_some(), "delete never succeeded\n{}", agent.log());
+    match call(&mut agent, Request::List) {
+        Response::List(containers) => assert!(containers.iter().all(|c| c.id != id)),
+        other => panic!("list: {:?}", other),
+    }
+}
+
+#[test]
+fn test_unknown_container() {
+    // Only needs the agent, not a working container runtime
+    if let Err(e) = agent_binary() {
+        eprintln!("skipping: {}", e);
+        return;
+    }
+    let mut agent = TestAgent::spawn().unwrap();
+
+    for request in [
+        Request::Start("no-such-container".to_string()),
+        Request::Metrics("no-such-container".to_string()),
+        logs_request("no-such-container"),
+        Request::Copy(CopyRequest {
+            id: "no-such-container".to_string(),
+            path: "/etc".to_string(),
+            direction: CopyDirection::Out,
+            options: CopyOptionsProto::default(),
+        }),
+    ] {
+        match call(&mut agent, request) {
+            Response::Failed(error) => assert_eq!(error.code, ErrorCode::NotFound),
+            other => panic!("expected not found, got {:?}", other),
+        }
+    }
+}
@@ -112,7 +112,6 @@ fn send_rpc_request(
     socket_path: &PathBuf,
     request: &libcrun_shim_proto::Request,
 ) -> Result<libcrun_shim_proto::Response, String> {
-    use std::io::{Read, Write};
     use std::os::unix::net::UnixStream;
 
     let mut stream = UnixStream::connect(socket_path)
@@ -121,17 +120,16 @@ fn send_rpc_request(
     stream.set_read_timeout(Some(Duration::from_secs(5))).ok();
     stream.set_write_timeout(Some(Duration::from_secs(5))).ok();
 
-    let data = libcrun_shim_proto::serialize_request(request);
-    stream
-        .write_all(&data)
+    let data = libcrun_shim_proto::serialize_request(request)
+        .map_err(|e| format!("Failed to encode request: {}", e))?;
+    libcrun_shim_proto::write_frame(&mut stream, &data)
         .map_err(|e| format!("Failed to send request: {}", e))?;
 
-    let mut buffer = vec![0u8; 4096];
-    let n = stream
-        .read(&mut buffer)
-        .map_err(|e| format!("Failed to read response: {}", e))?;
+    let frame = libcrun_shim_proto::read_frame(&mut stream)
+        .map_err(|e| format!("Failed to read response: {}", e))?
+        .ok_or_else(|| "Agent closed the connection".to_string())?;
 
-    libcrun_shim_proto::deserialize_response(&buffer[..n])
+    libcrun_shim_proto::deserialize_response(&frame)
         .map_err(|e| format!("Failed to parse response: {}", e))
 }
 
@@ -186,6 +184,7 @@ fn test_container_lifecycle() {
     // Test: Create container
     let create_request = libcrun_shim_proto::Request::Create(libcrun_shim_proto::CreateRequest {
         id: "test-container".to_string(),
+        name: String::new(),
         rootfs: config.test_rootfs.display().to_string(),
         command: vec!["/bin/sh".to_string(), "-c".to_string(), "echo hello".to_string()],
         env: vec!["PATH=/bin".to_string()],
@@ -247,6 +246,7 @@ fn test_metrics() {
 
     let create_request = libcrun_shim_proto::Request::Create(libcrun_shim_proto::CreateRequest {
         id: "metrics-test".to_string(),
+        name: String::new(),
         rootfs: config.test_rootfs.display().to_string(),
         command: vec!["/bin/sh".to_string()],
         env: vec![],
@@ -300,6 +300,7 @@ fn test_logs() {
 
     let create_request = libcrun_shim_proto::Request::Create(libcrun_shim_proto::CreateRequest {
         id: "logs-test".to_string(),
+        name: String::new(),
         rootfs: config.test_rootfs.display().to_string(),
         command: vec!["/bin/sh".to_string()],
         env: vec![],
@@ -375,6 +376,7 @@ fn test_e2e_real_container() {
     // Create container with real rootfs
     let create_request = libcrun_shim_proto::Request::Create(libcrun_shim_proto::CreateRequest {
         id: "e2e-test".to_string(),
+        name: String::new(),
         rootfs: alpine_rootfs.display().to_string(),
         command: vec!["echo".to_string(), "Hello from container!".to_string()],
         env: vec!["PATH=/usr/bin:/bin".to_string()],